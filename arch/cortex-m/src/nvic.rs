@@ -19,6 +19,10 @@ struct NvicRegisters {
     icpr: [VolatileCell<u32>; 8],
 }
 
+// How many times `next_pending()` returned each interrupt, if the board
+// created an `InterruptCounter`.
+static mut INTERRUPT_COUNTS: Option<&'static mut [u32]> = None;
+
 // NVIC base address
 const NVIC_BASE_ADDRESS: StaticRef<NvicRegisters> =
     unsafe { StaticRef::new(0xe000e100 as *const NvicRegisters) };
@@ -59,7 +63,13 @@ pub unsafe fn next_pending() -> Option<u32> {
         if ispr != 0 {
             // trailing_zeros == index of first high bit
             let bit = ispr.trailing_zeros();
-            return Some(block as u32 * 32 + bit);
+            let interrupt = block as u32 * 32 + bit;
+            if let Some(counts) = INTERRUPT_COUNTS.as_mut() {
+                if let Some(count) = counts.get_mut(interrupt as usize) {
+                    *count = count.wrapping_add(1);
+                }
+            }
+            return Some(interrupt);
         }
     }
     None
@@ -71,6 +81,36 @@ pub unsafe fn has_pending() -> bool {
     nvic.ispr.iter().fold(0, |i, ispr| ispr.get() | i) != 0
 }
 
+/// Counts how many times the chip serviced each interrupt, assuming the chip
+/// services every interrupt that `next_pending()` returns.
+///
+/// Only interrupts with a number below the length of the counts buffer are
+/// counted, so boards can size the buffer for the interrupts of their chip.
+pub struct InterruptCounter;
+
+impl InterruptCounter {
+    /// Start counting interrupts into `counts`. There should only be one
+    /// counter.
+    pub unsafe fn new(counts: &'static mut [u32]) -> InterruptCounter {
+        INTERRUPT_COUNTS = Some(counts);
+        InterruptCounter
+    }
+}
+
+impl kernel::InterruptCounter for InterruptCounter {
+    fn number_of_interrupts(&self) -> usize {
+        unsafe { INTERRUPT_COUNTS.as_ref().map_or(0, |counts| counts.len()) }
+    }
+
+    fn interrupt_count(&self, interrupt: usize) -> Option<u32> {
+        unsafe {
+            INTERRUPT_COUNTS
+                .as_ref()
+                .and_then(|counts| counts.get(interrupt).cloned())
+        }
+    }
+}
+
 /// An opaque wrapper for a single NVIC interrupt.
 ///
 /// Hand these out to low-level driver to let them control their own interrupts
@@ -54,3 +54,11 @@ pub unsafe fn reset() {
     let reset = (0x5FA << 16) | (aircr & (0x7 << 8)) | (1 << 2);
     SCB.aircr.set(reset);
 }
+
+/// Clear the sticky flags recording that a breakpoint instruction escalated
+/// to a hard fault.
+pub unsafe fn clear_breakpoint_debug_event() {
+    // Both registers are write-one-to-clear.
+    SCB.hfsr.set(1 << 31); // DEBUGEVT
+    SCB.dfsr.set(1 << 1); // BKPT
+}
@@ -4,6 +4,8 @@
 use core::fmt::Write;
 use core::ptr::{read_volatile, write_volatile};
 
+use crate::scb;
+
 /// This is used in the syscall handler. When set to 1 this means the
 /// svc_handler was called. Marked `pub` because it is used in the cortex-m*
 /// specific handler.
@@ -465,4 +467,244 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
             },
         ));
     }
+
+    fn debug_target_description(&self) -> &'static str {
+        TARGET_DESCRIPTION
+    }
+
+    unsafe fn debug_read_register(
+        &self,
+        stack_pointer: *const usize,
+        state: &CortexMStoredState,
+        register: usize,
+    ) -> Option<usize> {
+        match register {
+            4..=11 => Some(state.regs[register - 4]),
+            13 => {
+                // The process stack pointer before the hardware stacked the
+                // frame, which includes a padding word if bit 9 of the stacked
+                // xPSR is set.
+                let xpsr = read_volatile(stack_pointer.offset(7));
+                let padding = (xpsr >> 9) & 0x1;
+                Some(stack_pointer.offset(8 + padding as isize) as usize)
+            }
+            _ => stacked_register_offset(register)
+                .map(|offset| read_volatile(stack_pointer.offset(offset))),
+        }
+    }
+
+    unsafe fn debug_write_register(
+        &self,
+        stack_pointer: *const usize,
+        state: &mut CortexMStoredState,
+        register: usize,
+        value: usize,
+    ) -> Result<(), ()> {
+        match register {
+            4..=11 => {
+                state.regs[register - 4] = value;
+                Ok(())
+            }
+            // Moving the stack pointer would also move the stacked frame.
+            13 => Err(()),
+            _ => stacked_register_offset(register).map_or(Err(()), |offset| {
+                write_volatile((stack_pointer as *mut usize).offset(offset), value);
+                Ok(())
+            }),
+        }
+    }
+
+    fn debug_breakpoint_instruction(&self, kind: usize) -> Option<&'static [u8]> {
+        match kind {
+            // A 16-bit `bkpt #0` also replaces the first half of a 32-bit
+            // Thumb-2 instruction.
+            2..=4 => Some(&[0x00, 0xbe]),
+            _ => None,
+        }
+    }
+
+    unsafe fn debug_step_addresses(
+        &self,
+        stack_pointer: *const usize,
+        state: &CortexMStoredState,
+        read_halfword: &dyn Fn(usize) -> Option<u16>,
+    ) -> [Option<(usize, usize)>; 2] {
+        let pc = read_volatile(stack_pointer.offset(6)) & !0x1;
+        let read_register = |register| self.debug_read_register(stack_pointer, state, register);
+        let read_word = |address: usize| {
+            let low = read_halfword(address)?;
+            let high = read_halfword(address + 2)?;
+            Some((high as usize) << 16 | low as usize)
+        };
+
+        // A 16-bit `bkpt` works for both instruction sizes.
+        let next = match read_halfword(pc) {
+            Some(first) if first & 0xf800 < 0xe800 => [
+                Some(pc + 2),
+                thumb16_branch_target(pc, first, &read_register, &read_word),
+            ],
+            Some(first) => [
+                Some(pc + 4),
+                read_halfword(pc + 2).and_then(|second| {
+                    thumb32_branch_target(pc, first, second, &read_register, &read_word)
+                }),
+            ],
+            None => [None, None],
+        };
+        [
+            next[0].map(|address| (address, 2)),
+            next[1].map(|address| (address, 2)),
+        ]
+    }
+
+    unsafe fn debug_breakpoint_hit(
+        &self,
+        _stack_pointer: *const usize,
+        _state: &CortexMStoredState,
+    ) -> bool {
+        // Without a hardware debugger a `bkpt` escalates to a hard fault and
+        // sets the DEBUGEVT flag of the HFSR.
+        let hfsr = SCB_REGISTERS[2];
+        if hfsr & (1 << 31) != 0 {
+            SCB_REGISTERS[2] = hfsr & !(1 << 31);
+            scb::clear_breakpoint_debug_event();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Registers of the `org.gnu.gdb.arm.m-profile` GDB feature: r0-r12, sp, lr,
+/// pc and xpsr.
+const TARGET_DESCRIPTION: &'static str = concat!(
+    "<?xml version=\"1.0\"?>",
+    "<!DOCTYPE target SYSTEM \"gdb-target.dtd\">",
+    "<target version=\"1.0\">",
+    "<architecture>arm</architecture>",
+    "<feature name=\"org.gnu.gdb.arm.m-profile\">",
+    "<reg name=\"r0\" bitsize=\"32\"/>",
+    "<reg name=\"r1\" bitsize=\"32\"/>",
+    "<reg name=\"r2\" bitsize=\"32\"/>",
+    "<reg name=\"r3\" bitsize=\"32\"/>",
+    "<reg name=\"r4\" bitsize=\"32\"/>",
+    "<reg name=\"r5\" bitsize=\"32\"/>",
+    "<reg name=\"r6\" bitsize=\"32\"/>",
+    "<reg name=\"r7\" bitsize=\"32\"/>",
+    "<reg name=\"r8\" bitsize=\"32\"/>",
+    "<reg name=\"r9\" bitsize=\"32\"/>",
+    "<reg name=\"r10\" bitsize=\"32\"/>",
+    "<reg name=\"r11\" bitsize=\"32\"/>",
+    "<reg name=\"r12\" bitsize=\"32\"/>",
+    "<reg name=\"sp\" bitsize=\"32\" type=\"data_ptr\"/>",
+    "<reg name=\"lr\" bitsize=\"32\"/>",
+    "<reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\"/>",
+    "<reg name=\"xpsr\" bitsize=\"32\"/>",
+    "</feature>",
+    "</target>",
+);
+
+/// Offset in words from the process stack pointer of a register the hardware
+/// stacked when the process entered the kernel, or `None` for r4-r11, which
+/// are in `CortexMStoredState`.
+fn stacked_register_offset(register: usize) -> Option<isize> {
+    match register {
+        0..=3 => Some(register as isize), // r0-r3
+        12 => Some(4),                    // r12
+        14 => Some(5),                    // lr
+        15 => Some(6),                    // pc
+        16 => Some(7),                    // xpsr
+        _ => None,
+    }
+}
+
+/// Where the 16-bit Thumb instruction `first` at `pc` may branch to, or
+/// `None` if it does not branch.
+fn thumb16_branch_target(
+    pc: usize,
+    first: u16,
+    read_register: &dyn Fn(usize) -> Option<usize>,
+    read_word: &dyn Fn(usize) -> Option<usize>,
+) -> Option<usize> {
+    let first = first as u32;
+    if first & 0xf000 == 0xd000 && first & 0x0e00 != 0x0e00 {
+        // B<c> (encoding T1). Conditions 0b1110 and 0b1111 are UDF and SVC.
+        Some(relative_target(pc, sign_extend((first & 0xff) << 1, 9)))
+    } else if first & 0xf800 == 0xe000 {
+        // B (encoding T2)
+        Some(relative_target(pc, sign_extend((first & 0x7ff) << 1, 12)))
+    } else if first & 0xf500 == 0xb100 {
+        // CBZ, CBNZ
+        let imm = (first >> 9 & 0x1) << 6 | (first >> 3 & 0x1f) << 1;
+        Some(relative_target(pc, imm))
+    } else if first & 0xff00 == 0x4700 || first & 0xff87 == 0x4687 {
+        // BX, BLX and MOV pc, Rm
+        read_register((first >> 3 & 0xf) as usize).map(|target| target & !0x1)
+    } else if first & 0xff00 == 0xbd00 {
+        // POP {..., pc}: pc is loaded last
+        let sp = read_register(13)?;
+        let count = (first & 0xff).count_ones() as usize;
+        read_word(sp + 4 * count).map(|target| target & !0x1)
+    } else {
+        None
+    }
+}
+
+/// Where the 32-bit Thumb instruction made of the halfwords `first` and
+/// `second` at `pc` may branch to, or `None` if it does not branch.
+fn thumb32_branch_target(
+    pc: usize,
+    first: u16,
+    second: u16,
+    read_register: &dyn Fn(usize) -> Option<usize>,
+    read_word: &dyn Fn(usize) -> Option<usize>,
+) -> Option<usize> {
+    let (first, second) = (first as u32, second as u32);
+    if first & 0xf800 == 0xf000 && second & 0x8000 == 0x8000 {
+        let s = first >> 10 & 0x1;
+        let j1 = second >> 13 & 0x1;
+        let j2 = second >> 11 & 0x1;
+        let imm11 = second & 0x7ff;
+        if second & 0x5000 == 0x0000 {
+            // B<c>.W (encoding T3). Conditions 0b111x are other instructions.
+            if first & 0x0380 == 0x0380 {
+                return None;
+            }
+            let imm6 = first & 0x3f;
+            let imm = s << 20 | j2 << 19 | j1 << 18 | imm6 << 12 | imm11 << 1;
+            Some(relative_target(pc, sign_extend(imm, 21)))
+        } else if second & 0x1000 == 0x1000 {
+            // B.W (encoding T4) and BL
+            let i1 = !(j1 ^ s) & 0x1;
+            let i2 = !(j2 ^ s) & 0x1;
+            let imm10 = first & 0x3ff;
+            let imm = s << 24 | i1 << 23 | i2 << 22 | imm10 << 12 | imm11 << 1;
+            Some(relative_target(pc, sign_extend(imm, 25)))
+        } else {
+            None
+        }
+    } else if first == 0xe8bd && second & 0x8000 == 0x8000 {
+        // POP.W {..., pc}: pc is loaded last
+        let sp = read_register(13)?;
+        let count = second.count_ones() as usize;
+        read_word(sp + 4 * (count - 1)).map(|target| target & !0x1)
+    } else if first == 0xf85d && second == 0xfb04 {
+        // POP.W {pc}
+        let sp = read_register(13)?;
+        read_word(sp).map(|target| target & !0x1)
+    } else {
+        None
+    }
+}
+
+/// The target of a branch at `pc` with the offset `imm`, which is relative to
+/// the address of the instruction plus 4.
+fn relative_target(pc: usize, imm: u32) -> usize {
+    (pc as u32).wrapping_add(4).wrapping_add(imm) as usize
+}
+
+/// Sign-extend the `bits` lowest bits of `value`.
+fn sign_extend(value: u32, bits: u32) -> u32 {
+    let shift = 32 - bits;
+    ((value << shift) as i32 >> shift) as u32
 }
@@ -144,6 +144,15 @@ impl kernel::SysTick for SysTick {
         SYSTICK_BASE.syst_csr.is_set(ControlAndStatus::COUNTFLAG)
     }
 
+    fn remaining_us(&self) -> Option<u32> {
+        let hertz = self.hertz() as u64;
+        if hertz == 0 {
+            return None;
+        }
+        let value = SYSTICK_BASE.syst_cvr.read(CurrentValue::CURRENT) as u64;
+        Some((value * 1_000_000 / hertz) as u32)
+    }
+
     fn reset(&self) {
         SYSTICK_BASE.syst_csr.set(0);
         SYSTICK_BASE.syst_rvr.set(0);
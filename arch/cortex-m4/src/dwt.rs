@@ -31,6 +31,27 @@ pub unsafe fn get_time() -> u32 {
     DWT.cycnt.get()
 }
 
+/// The DWT cycle counter, which the kernel can use to account CPU time.
+///
+/// `bench()` resets and stops the same counter, so boards that pass the
+/// counter to the kernel should not use `bench()`.
+pub struct CycleCounter;
+
+impl CycleCounter {
+    /// Enable the DWT and start the cycle counter.
+    pub unsafe fn new() -> CycleCounter {
+        DEMCR.demcr.set(DEMCR.demcr.get() | 0x01000000);
+        DWT.ctrl.set(DWT.ctrl.get() | 1);
+        CycleCounter
+    }
+}
+
+impl kernel::CycleCounter for CycleCounter {
+    fn cycles(&self) -> u32 {
+        DWT.cycnt.get()
+    }
+}
+
 pub unsafe fn bench<F: FnOnce()>(f: F) -> u32 {
     reset_timer();
     start_timer();
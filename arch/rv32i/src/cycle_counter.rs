@@ -0,0 +1,19 @@
+//! CPU time accounting with the `mcycle` CSR.
+
+use crate::csr::{mcycle, CSR};
+
+/// Reads the lower 32 bits of the `mcycle` CSR, which counts the clock cycles
+/// the hart has executed.
+pub struct CycleCounter;
+
+impl CycleCounter {
+    pub const fn new() -> CycleCounter {
+        CycleCounter
+    }
+}
+
+impl kernel::CycleCounter for CycleCounter {
+    fn cycles(&self) -> u32 {
+        CSR.mcycle.read(mcycle::mcycle::mcycle)
+    }
+}
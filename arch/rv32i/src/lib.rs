@@ -15,6 +15,7 @@
 
 pub mod clic;
 pub mod csr;
+pub mod cycle_counter;
 pub mod machine_timer;
 pub mod pmp;
 pub mod support;
@@ -1,5 +1,7 @@
 //! Create a timer using the Machine Timer registers.
 
+use core::marker::PhantomData;
+
 use crate::csr;
 use kernel::common::cells::OptionalCell;
 use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite};
@@ -23,16 +25,20 @@ register_bitfields![u64,
     ]
 ];
 
-pub struct MachineTimer<'a> {
+/// The machine timer. `F` is the frequency `mtime` counts at, which is set by
+/// the platform.
+pub struct MachineTimer<'a, F = hil::time::Freq32KHz> {
     registers: StaticRef<MachineTimerRegisters>,
     client: OptionalCell<&'a dyn hil::time::AlarmClient>,
+    frequency: PhantomData<F>,
 }
 
-impl MachineTimer<'a> {
-    pub const fn new(base: StaticRef<MachineTimerRegisters>) -> MachineTimer<'a> {
+impl<F> MachineTimer<'a, F> {
+    pub const fn new(base: StaticRef<MachineTimerRegisters>) -> MachineTimer<'a, F> {
         MachineTimer {
             registers: base,
             client: OptionalCell::empty(),
+            frequency: PhantomData,
         }
     }
 
@@ -53,8 +59,8 @@ impl MachineTimer<'a> {
     }
 }
 
-impl hil::time::Time for MachineTimer<'a> {
-    type Frequency = hil::time::Freq32KHz;
+impl<F: hil::time::Frequency> hil::time::Time for MachineTimer<'a, F> {
+    type Frequency = F;
 
     fn now(&self) -> u32 {
         self.registers.mtime.get() as u32
@@ -65,15 +71,19 @@ impl hil::time::Time for MachineTimer<'a> {
     }
 }
 
-impl hil::time::Alarm<'a> for MachineTimer<'a> {
+impl<F: hil::time::Frequency> hil::time::Alarm<'a> for MachineTimer<'a, F> {
     fn set_client(&self, client: &'a dyn hil::time::AlarmClient) {
         self.client.set(client);
     }
 
     fn set_alarm(&self, tics: u32) {
+        // Alarms are in terms of the low 32 bits of `mtime`. Expire at the
+        // next time those bits equal `tics`, as a 32 bit compare would.
+        let now = self.registers.mtime.get();
+        let expiration = now + (tics.wrapping_sub(now as u32) as u64);
         self.registers
             .mtimecmp
-            .write(MTimeCmp::MTIMECMP.val(tics as u64));
+            .write(MTimeCmp::MTIMECMP.val(expiration));
         csr::CSR.mie.modify(csr::mie::mie::mtimer::SET);
     }
 
@@ -1,227 +1,313 @@
 //! Implementation of the physical memory protection unit (PMP).
+//!
+//! The PMP checks user mode memory accesses against its entries, and denies
+//! any access that no entry allows. Machine mode accesses are only checked
+//! against locked entries. This driver never locks an entry, so the kernel
+//! always has access to all memory.
+//!
+//! Each region uses a pair of entries in top-of-range (TOR) mode: the first
+//! entry holds the start address and is itself turned off, the second holds
+//! the end address and the permissions. TOR regions only need to be four byte
+//! aligned, so the app memory region can grow with the app break exactly.
+//!
+//! See section 3.6 of the RISC-V privileged ISA specification, version 1.10:
+//! <https://content.riscv.org/wp-content/uploads/2017/05/riscv-privileged-v1.10.pdf>
 
+use core::cmp;
 use core::fmt;
 
 use crate::csr;
 use kernel;
 use kernel::mpu;
 
-/// Struct storing configuration for a RISCV PMP region.
-/// In accordance with the priviliged ISA 1.10
-/// https://content.riscv.org/wp-content/uploads/2017/05/riscv-privileged-v1.10.pdf
+/// Maximum number of regions. The privileged specification allows for 16 PMP
+/// entries, and each region uses two of them.
+const MAX_REGIONS: usize = 8;
 
-/// Struct storing region configuration for RISCV PMP.
+/// The region number used for app-owned memory.
+const APP_MEMORY_REGION_NUM: usize = 0;
+
+// Bits of the `pmpcfg` byte of an entry.
+const PMPCFG_R: u8 = 1 << 0;
+const PMPCFG_W: u8 = 1 << 1;
+const PMPCFG_X: u8 = 1 << 2;
+const PMPCFG_A_TOR: u8 = 1 << 3;
+
+/// Round `address` up to the four byte granularity of the PMP.
+fn align4(address: usize) -> usize {
+    (address + 3) & !3
+}
+
+/// Struct storing configuration for a RISC-V PMP region.
 #[derive(Copy, Clone)]
+pub struct PMPRegion {
+    location: Option<(*const u8, usize)>,
+    /// The `pmpcfg` byte of the entry holding the end of the region.
+    cfg: u8,
+}
+
+impl PMPRegion {
+    fn new(start: *const u8, size: usize, permissions: mpu::Permissions) -> PMPRegion {
+        let access = match permissions {
+            mpu::Permissions::ReadWriteExecute => PMPCFG_R | PMPCFG_W | PMPCFG_X,
+            mpu::Permissions::ReadWriteOnly => PMPCFG_R | PMPCFG_W,
+            mpu::Permissions::ReadExecuteOnly => PMPCFG_R | PMPCFG_X,
+            mpu::Permissions::ReadOnly => PMPCFG_R,
+            mpu::Permissions::ExecuteOnly => PMPCFG_X,
+        };
+
+        PMPRegion {
+            location: Some((start, size)),
+            cfg: access | PMPCFG_A_TOR,
+        }
+    }
+
+    const fn empty() -> PMPRegion {
+        PMPRegion {
+            location: None,
+            cfg: 0,
+        }
+    }
+
+    fn location(&self) -> Option<(*const u8, usize)> {
+        self.location
+    }
+
+    fn overlaps(&self, start: usize, end: usize) -> bool {
+        match self.location {
+            Some((region_start, region_size)) => {
+                let region_start = region_start as usize;
+                start < region_start + region_size && region_start < end
+            }
+            None => false,
+        }
+    }
+}
+
+/// Struct storing the PMP configuration of a process.
 pub struct PMPConfig {
-    regions: usize,
+    regions: [PMPRegion; MAX_REGIONS],
 }
 
 impl Default for PMPConfig {
-    /// number of regions on the arty chip
     fn default() -> PMPConfig {
-        PMPConfig { regions: 4 }
+        PMPConfig {
+            regions: [PMPRegion::empty(); MAX_REGIONS],
+        }
     }
 }
 
 impl fmt::Display for PMPConfig {
-    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        Ok(())
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\r\n RISC-V PMP")?;
+        for (i, region) in self.regions.iter().enumerate() {
+            if let Some(location) = region.location() {
+                write!(
+                    f,
+                    "\r\n  Region {}: base:{:>width$x}, length: {} bytes; {}{}{}",
+                    i,
+                    location.0 as usize,
+                    location.1,
+                    if region.cfg & PMPCFG_R != 0 { "R" } else { "-" },
+                    if region.cfg & PMPCFG_W != 0 { "W" } else { "-" },
+                    if region.cfg & PMPCFG_X != 0 { "X" } else { "-" },
+                    width = 10,
+                )?;
+            } else {
+                write!(f, "\r\n  Region {}: Unused", i)?;
+            }
+        }
+        write!(f, "\r\n")
     }
 }
 
 impl PMPConfig {
-    pub const fn new(num_regions: usize) -> PMPConfig {
-        PMPConfig {
-            regions: num_regions,
+    fn unused_region_number(&self, num_regions: usize) -> Option<usize> {
+        for (number, region) in self.regions.iter().enumerate().take(num_regions) {
+            if number == APP_MEMORY_REGION_NUM {
+                continue;
+            }
+            if let None = region.location() {
+                return Some(number);
+            }
         }
+        None
+    }
+
+    fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.regions
+            .iter()
+            .any(|region| region.overlaps(start, end))
     }
 }
 
-impl kernel::mpu::MPU for PMPConfig {
-    fn enable_mpu(&self) {}
+/// The PMP of a hart.
+pub struct PMP {
+    /// Number of regions, which is half the number of PMP entries the hart
+    /// implements.
+    num_regions: usize,
+}
 
-    fn disable_mpu(&self) {
-        for x in 0..self.regions {
-            // disable everything
-            match x {
-                0 => {
-                    csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::r0::CLEAR);
-                    csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::w0::CLEAR);
-                    csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::x0::CLEAR);
-                    csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::a0::OFF);
-                    csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::l0::CLEAR);
-                    csr::CSR.pmpaddr0.set(0x0);
-                }
-                1 => {
-                    csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::r1::CLEAR);
-                    csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::w1::CLEAR);
-                    csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::x1::CLEAR);
-                    csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::a1::OFF);
-                    csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::l1::CLEAR);
-                    csr::CSR.pmpaddr1.set(0x0);
-                }
-                2 => {
-                    csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::r2::CLEAR);
-                    csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::w2::CLEAR);
-                    csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::x2::CLEAR);
-                    csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::a2::OFF);
-                    csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::l2::CLEAR);
-                    csr::CSR.pmpaddr2.set(0x0);
-                }
-                3 => {
-                    csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::r3::CLEAR);
-                    csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::w3::CLEAR);
-                    csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::x3::CLEAR);
-                    csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::a3::OFF);
-                    csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::l3::CLEAR);
-                    csr::CSR.pmpaddr3.set(0x0);
-                }
-                4 => {
-                    csr::CSR.pmpcfg1.modify(csr::pmpconfig::pmpcfg::r0::CLEAR);
-                    csr::CSR.pmpcfg1.modify(csr::pmpconfig::pmpcfg::w0::CLEAR);
-                    csr::CSR.pmpcfg1.modify(csr::pmpconfig::pmpcfg::x0::CLEAR);
-                    csr::CSR.pmpcfg1.modify(csr::pmpconfig::pmpcfg::a0::OFF);
-                    csr::CSR.pmpcfg1.modify(csr::pmpconfig::pmpcfg::l0::CLEAR);
-                    csr::CSR.pmpaddr4.set(0x0);
-                }
-                5 => {
-                    csr::CSR.pmpcfg1.modify(csr::pmpconfig::pmpcfg::r1::CLEAR);
-                    csr::CSR.pmpcfg1.modify(csr::pmpconfig::pmpcfg::w1::CLEAR);
-                    csr::CSR.pmpcfg1.modify(csr::pmpconfig::pmpcfg::x1::CLEAR);
-                    csr::CSR.pmpcfg1.modify(csr::pmpconfig::pmpcfg::a1::OFF);
-                    csr::CSR.pmpcfg1.modify(csr::pmpconfig::pmpcfg::l1::CLEAR);
-                    csr::CSR.pmpaddr5.set(0x0);
-                }
-                6 => {
-                    csr::CSR.pmpcfg1.modify(csr::pmpconfig::pmpcfg::r2::CLEAR);
-                    csr::CSR.pmpcfg1.modify(csr::pmpconfig::pmpcfg::w2::CLEAR);
-                    csr::CSR.pmpcfg1.modify(csr::pmpconfig::pmpcfg::x2::CLEAR);
-                    csr::CSR.pmpcfg1.modify(csr::pmpconfig::pmpcfg::a2::OFF);
-                    csr::CSR.pmpcfg1.modify(csr::pmpconfig::pmpcfg::l2::CLEAR);
-                    csr::CSR.pmpaddr6.set(0x0);
-                }
-                7 => {
-                    csr::CSR.pmpcfg1.modify(csr::pmpconfig::pmpcfg::r3::CLEAR);
-                    csr::CSR.pmpcfg1.modify(csr::pmpconfig::pmpcfg::w3::CLEAR);
-                    csr::CSR.pmpcfg1.modify(csr::pmpconfig::pmpcfg::x3::CLEAR);
-                    csr::CSR.pmpcfg1.modify(csr::pmpconfig::pmpcfg::a3::OFF);
-                    csr::CSR.pmpcfg1.modify(csr::pmpconfig::pmpcfg::l3::CLEAR);
-                    csr::CSR.pmpaddr7.set(0x0);
-                }
-                8 => {
-                    csr::CSR.pmpcfg2.modify(csr::pmpconfig::pmpcfg::r0::CLEAR);
-                    csr::CSR.pmpcfg2.modify(csr::pmpconfig::pmpcfg::w0::CLEAR);
-                    csr::CSR.pmpcfg2.modify(csr::pmpconfig::pmpcfg::x0::CLEAR);
-                    csr::CSR.pmpcfg2.modify(csr::pmpconfig::pmpcfg::a0::OFF);
-                    csr::CSR.pmpcfg2.modify(csr::pmpconfig::pmpcfg::l0::CLEAR);
-                    csr::CSR.pmpaddr8.set(0x0);
-                }
-                9 => {
-                    csr::CSR.pmpcfg2.modify(csr::pmpconfig::pmpcfg::r1::CLEAR);
-                    csr::CSR.pmpcfg2.modify(csr::pmpconfig::pmpcfg::w1::CLEAR);
-                    csr::CSR.pmpcfg2.modify(csr::pmpconfig::pmpcfg::x1::CLEAR);
-                    csr::CSR.pmpcfg2.modify(csr::pmpconfig::pmpcfg::a1::OFF);
-                    csr::CSR.pmpcfg2.modify(csr::pmpconfig::pmpcfg::l1::CLEAR);
-                    csr::CSR.pmpaddr9.set(0x0);
-                }
-                10 => {
-                    csr::CSR.pmpcfg2.modify(csr::pmpconfig::pmpcfg::r2::CLEAR);
-                    csr::CSR.pmpcfg2.modify(csr::pmpconfig::pmpcfg::w2::CLEAR);
-                    csr::CSR.pmpcfg2.modify(csr::pmpconfig::pmpcfg::x2::CLEAR);
-                    csr::CSR.pmpcfg2.modify(csr::pmpconfig::pmpcfg::a2::OFF);
-                    csr::CSR.pmpcfg2.modify(csr::pmpconfig::pmpcfg::l2::CLEAR);
-                    csr::CSR.pmpaddr10.set(0x0);
-                }
-                11 => {
-                    csr::CSR.pmpcfg2.modify(csr::pmpconfig::pmpcfg::r3::CLEAR);
-                    csr::CSR.pmpcfg2.modify(csr::pmpconfig::pmpcfg::w3::CLEAR);
-                    csr::CSR.pmpcfg2.modify(csr::pmpconfig::pmpcfg::x3::CLEAR);
-                    csr::CSR.pmpcfg2.modify(csr::pmpconfig::pmpcfg::a3::OFF);
-                    csr::CSR.pmpcfg2.modify(csr::pmpconfig::pmpcfg::l3::CLEAR);
-                    csr::CSR.pmpaddr11.set(0x0);
-                }
-                12 => {
-                    csr::CSR.pmpcfg3.modify(csr::pmpconfig::pmpcfg::r0::CLEAR);
-                    csr::CSR.pmpcfg3.modify(csr::pmpconfig::pmpcfg::w0::CLEAR);
-                    csr::CSR.pmpcfg3.modify(csr::pmpconfig::pmpcfg::x0::CLEAR);
-                    csr::CSR.pmpcfg3.modify(csr::pmpconfig::pmpcfg::a0::OFF);
-                    csr::CSR.pmpcfg3.modify(csr::pmpconfig::pmpcfg::l0::CLEAR);
-                    csr::CSR.pmpaddr12.set(0x0);
-                }
-                13 => {
-                    csr::CSR.pmpcfg3.modify(csr::pmpconfig::pmpcfg::r1::CLEAR);
-                    csr::CSR.pmpcfg3.modify(csr::pmpconfig::pmpcfg::w1::CLEAR);
-                    csr::CSR.pmpcfg3.modify(csr::pmpconfig::pmpcfg::x1::CLEAR);
-                    csr::CSR.pmpcfg3.modify(csr::pmpconfig::pmpcfg::a1::OFF);
-                    csr::CSR.pmpcfg3.modify(csr::pmpconfig::pmpcfg::l1::CLEAR);
-                    csr::CSR.pmpaddr13.set(0x0);
-                }
-                14 => {
-                    csr::CSR.pmpcfg3.modify(csr::pmpconfig::pmpcfg::r2::CLEAR);
-                    csr::CSR.pmpcfg3.modify(csr::pmpconfig::pmpcfg::w2::CLEAR);
-                    csr::CSR.pmpcfg3.modify(csr::pmpconfig::pmpcfg::x2::CLEAR);
-                    csr::CSR.pmpcfg3.modify(csr::pmpconfig::pmpcfg::a2::OFF);
-                    csr::CSR.pmpcfg3.modify(csr::pmpconfig::pmpcfg::l2::CLEAR);
-                    csr::CSR.pmpaddr14.set(0x0);
-                }
-                15 => {
-                    csr::CSR.pmpcfg3.modify(csr::pmpconfig::pmpcfg::r3::CLEAR);
-                    csr::CSR.pmpcfg3.modify(csr::pmpconfig::pmpcfg::w3::CLEAR);
-                    csr::CSR.pmpcfg3.modify(csr::pmpconfig::pmpcfg::x3::CLEAR);
-                    csr::CSR.pmpcfg3.modify(csr::pmpconfig::pmpcfg::a3::OFF);
-                    csr::CSR.pmpcfg3.modify(csr::pmpconfig::pmpcfg::l3::CLEAR);
-                    csr::CSR.pmpaddr15.set(0x0);
-                }
-                // spec 1.10 only goes to 15
-                _ => break,
-            }
-            //set first PMP to have permissions to entire space
-            csr::CSR.pmpaddr0.set(0xFFFF_FFFF);
-            //enable R W X fields
-            csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::r0::SET);
-            csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::w0::SET);
-            csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::x0::SET);
-            csr::CSR.pmpcfg0.modify(csr::pmpconfig::pmpcfg::a0::OFF);
+impl PMP {
+    /// Create the PMP driver for a hart that implements `num_entries` PMP
+    /// entries.
+    pub fn new(num_entries: usize) -> PMP {
+        PMP {
+            num_regions: cmp::min(num_entries / 2, MAX_REGIONS),
         }
     }
+}
+
+fn write_pmpaddr(entry: usize, value: u32) {
+    match entry {
+        0 => csr::CSR.pmpaddr0.set(value),
+        1 => csr::CSR.pmpaddr1.set(value),
+        2 => csr::CSR.pmpaddr2.set(value),
+        3 => csr::CSR.pmpaddr3.set(value),
+        4 => csr::CSR.pmpaddr4.set(value),
+        5 => csr::CSR.pmpaddr5.set(value),
+        6 => csr::CSR.pmpaddr6.set(value),
+        7 => csr::CSR.pmpaddr7.set(value),
+        8 => csr::CSR.pmpaddr8.set(value),
+        9 => csr::CSR.pmpaddr9.set(value),
+        10 => csr::CSR.pmpaddr10.set(value),
+        11 => csr::CSR.pmpaddr11.set(value),
+        12 => csr::CSR.pmpaddr12.set(value),
+        13 => csr::CSR.pmpaddr13.set(value),
+        14 => csr::CSR.pmpaddr14.set(value),
+        15 => csr::CSR.pmpaddr15.set(value),
+        // spec 1.10 only goes to 15
+        _ => {}
+    }
+}
+
+fn write_pmpcfg(index: usize, value: u32) {
+    match index {
+        0 => csr::CSR.pmpcfg0.set(value),
+        1 => csr::CSR.pmpcfg1.set(value),
+        2 => csr::CSR.pmpcfg2.set(value),
+        3 => csr::CSR.pmpcfg3.set(value),
+        _ => {}
+    }
+}
+
+impl kernel::mpu::MPU for PMP {
+    type MpuConfig = PMPConfig;
+
+    // The PMP has no global enable: its entries always apply to user mode, and
+    // never to machine mode since they are not locked. So there is nothing to
+    // do when switching between the kernel and a process.
+    fn enable_mpu(&self) {}
+
+    fn disable_mpu(&self) {}
 
     fn number_total_regions(&self) -> usize {
-        self.regions
+        self.num_regions
     }
 
     fn allocate_region(
         &self,
-        _unallocated_memory_start: *const u8,
-        _unallocated_memory_size: usize,
-        _min_region_size: usize,
-        _permissions: mpu::Permissions,
-        _config: &mut Self::MpuConfig,
+        unallocated_memory_start: *const u8,
+        unallocated_memory_size: usize,
+        min_region_size: usize,
+        permissions: mpu::Permissions,
+        config: &mut Self::MpuConfig,
     ) -> Option<mpu::Region> {
-        None
+        let region_num = config.unused_region_number(self.num_regions)?;
+
+        let start = align4(unallocated_memory_start as usize);
+        let size = align4(min_region_size);
+        if start + size > (unallocated_memory_start as usize) + unallocated_memory_size
+            || config.overlaps(start, start + size)
+        {
+            return None;
+        }
+
+        config.regions[region_num] = PMPRegion::new(start as *const u8, size, permissions);
+
+        Some(mpu::Region::new(start as *const u8, size))
     }
 
     fn allocate_app_memory_region(
         &self,
-        _unallocated_memory_start: *const u8,
-        _unallocated_memory_size: usize,
-        _min_memory_size: usize,
-        _initial_app_memory_size: usize,
-        _initial_kernel_memory_size: usize,
-        _permissions: mpu::Permissions,
-        _config: &mut Self::MpuConfig,
+        unallocated_memory_start: *const u8,
+        unallocated_memory_size: usize,
+        min_memory_size: usize,
+        initial_app_memory_size: usize,
+        initial_kernel_memory_size: usize,
+        permissions: mpu::Permissions,
+        config: &mut Self::MpuConfig,
     ) -> Option<(*const u8, usize)> {
-        None
+        // The app memory region can only be allocated once.
+        if config.regions[APP_MEMORY_REGION_NUM].location().is_some() {
+            return None;
+        }
+
+        let start = align4(unallocated_memory_start as usize);
+        let memory_size = align4(cmp::max(
+            min_memory_size,
+            initial_app_memory_size + initial_kernel_memory_size,
+        ));
+        if start + memory_size > (unallocated_memory_start as usize) + unallocated_memory_size
+            || config.overlaps(start, start + memory_size)
+        {
+            return None;
+        }
+
+        let app_memory_size = align4(initial_app_memory_size);
+        if app_memory_size + initial_kernel_memory_size > memory_size {
+            return None;
+        }
+
+        config.regions[APP_MEMORY_REGION_NUM] =
+            PMPRegion::new(start as *const u8, app_memory_size, permissions);
+
+        Some((start as *const u8, memory_size))
     }
 
     fn update_app_memory_region(
         &self,
-        _app_memory_break: *const u8,
-        _kernel_memory_break: *const u8,
-        _permissions: mpu::Permissions,
-        _config: &mut Self::MpuConfig,
+        app_memory_break: *const u8,
+        kernel_memory_break: *const u8,
+        permissions: mpu::Permissions,
+        config: &mut Self::MpuConfig,
     ) -> Result<(), ()> {
-        Err(())
+        let (start, _) = config.regions[APP_MEMORY_REGION_NUM].location().ok_or(())?;
+
+        let end = align4(app_memory_break as usize);
+        if end > kernel_memory_break as usize {
+            return Err(());
+        }
+
+        config.regions[APP_MEMORY_REGION_NUM] =
+            PMPRegion::new(start, end - (start as usize), permissions);
+
+        Ok(())
     }
 
-    fn configure_mpu(&self, _config: &Self::MpuConfig) {}
+    fn configure_mpu(&self, config: &Self::MpuConfig) {
+        let mut pmpcfg = [0u32; MAX_REGIONS / 2];
+
+        for (i, region) in config.regions.iter().enumerate().take(self.num_regions) {
+            let (start, end) = match region.location() {
+                Some((start, size)) => (start as usize, start as usize + size),
+                None => (0, 0),
+            };
+
+            // `pmpaddr` holds bits 33:2 of the address.
+            write_pmpaddr(2 * i, (start >> 2) as u32);
+            write_pmpaddr(2 * i + 1, (end >> 2) as u32);
+
+            // The entry holding the start address stays off. Each `pmpcfg`
+            // register holds the configuration of four entries.
+            let entry = 2 * i + 1;
+            pmpcfg[entry / 4] |= (region.cfg as u32) << ((entry % 4) * 8);
+        }
+
+        for (index, value) in pmpcfg.iter().enumerate() {
+            if index * 2 < self.num_regions {
+                write_pmpcfg(index, *value);
+            }
+        }
+    }
 }
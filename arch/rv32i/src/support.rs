@@ -2,6 +2,8 @@
 
 use core::ops::FnOnce;
 
+use crate::csr;
+
 #[inline(always)]
 /// NOP instruction
 pub fn nop() {
@@ -16,12 +18,23 @@ pub unsafe fn wfi() {
     asm!("wfi" :::: "volatile");
 }
 
-/// TODO: implement
+/// Run `f` with interrupts disabled.
+///
+/// Interrupts that occur in the meantime stay pending and are taken once `f`
+/// returns. `wfi` still wakes up for them, so `f` may put the chip to sleep.
 pub unsafe fn atomic<F, R>(f: F) -> R
 where
     F: FnOnce() -> R,
 {
-    f()
+    let interrupts_enabled = csr::CSR.mstatus.is_set(csr::mstatus::mstatus::mie);
+    csr::CSR.mstatus.modify(csr::mstatus::mstatus::mie::CLEAR);
+
+    let res = f();
+
+    if interrupts_enabled {
+        csr::CSR.mstatus.modify(csr::mstatus::mstatus::mie::SET);
+    }
+    res
 }
 
 #[cfg(target_os = "none")]
@@ -408,4 +408,221 @@ impl kernel::syscall::UserspaceKernelBoundary for SysCall {
         _writer: &mut dyn Write,
     ) {
     }
+
+    fn debug_target_description(&self) -> &'static str {
+        TARGET_DESCRIPTION
+    }
+
+    unsafe fn debug_read_register(
+        &self,
+        _stack_pointer: *const usize,
+        state: &RiscvimacStoredState,
+        register: usize,
+    ) -> Option<usize> {
+        match register {
+            0 => Some(0),                             // x0 is hardwired to zero
+            1..=31 => Some(state.regs[register - 1]), // x1 = regs[0]
+            32 => Some(state.pc),
+            _ => None,
+        }
+    }
+
+    unsafe fn debug_write_register(
+        &self,
+        _stack_pointer: *const usize,
+        state: &mut RiscvimacStoredState,
+        register: usize,
+        value: usize,
+    ) -> Result<(), ()> {
+        match register {
+            0 => {}
+            1..=31 => state.regs[register - 1] = value,
+            32 => state.pc = value,
+            _ => return Err(()),
+        }
+        Ok(())
+    }
+
+    fn debug_breakpoint_instruction(&self, kind: usize) -> Option<&'static [u8]> {
+        match kind {
+            2 => Some(&[0x02, 0x90]),             // c.ebreak
+            4 => Some(&[0x73, 0x00, 0x10, 0x00]), // ebreak
+            _ => None,
+        }
+    }
+
+    unsafe fn debug_step_addresses(
+        &self,
+        stack_pointer: *const usize,
+        state: &RiscvimacStoredState,
+        read_halfword: &dyn Fn(usize) -> Option<u16>,
+    ) -> [Option<(usize, usize)>; 2] {
+        let pc = state.pc;
+        let read_register = |register| self.debug_read_register(stack_pointer, state, register);
+        // The breakpoint replaces an instruction of the same length, so that
+        // `c.ebreak` is only used where the core supports compressed
+        // instructions.
+        let with_kind = |address: Option<usize>| {
+            address.and_then(|address| {
+                read_halfword(address).map(|instruction| match instruction & 0x3 {
+                    0x3 => (address, 4),
+                    _ => (address, 2),
+                })
+            })
+        };
+
+        let next = match read_halfword(pc) {
+            // The two lowest bits of 32-bit instructions are set.
+            Some(first) if first & 0x3 == 0x3 => [
+                Some(pc + 4),
+                read_halfword(pc + 2).and_then(|second| {
+                    let instruction = (second as u32) << 16 | first as u32;
+                    branch_target(pc, instruction, &read_register)
+                }),
+            ],
+            Some(first) => [
+                Some(pc + 2),
+                compressed_branch_target(pc, first, &read_register),
+            ],
+            None => [None, None],
+        };
+        [with_kind(next[0]), with_kind(next[1])]
+    }
+
+    unsafe fn debug_breakpoint_hit(
+        &self,
+        _stack_pointer: *const usize,
+        state: &RiscvimacStoredState,
+    ) -> bool {
+        // Exception code 3 is a breakpoint. Interrupts set the top bit.
+        (state.mcause as isize) >= 0 && state.mcause & 0x1ff == 3
+    }
+}
+
+/// Where the 32-bit instruction at `pc` may jump or branch to, or `None` if
+/// it does not.
+fn branch_target(
+    pc: usize,
+    instruction: u32,
+    read_register: &dyn Fn(usize) -> Option<usize>,
+) -> Option<usize> {
+    let i = instruction;
+    match i & 0x7f {
+        // JAL
+        0x6f => {
+            let imm = (i >> 31 & 0x1) << 20
+                | (i >> 21 & 0x3ff) << 1
+                | (i >> 20 & 0x1) << 11
+                | (i >> 12 & 0xff) << 12;
+            Some(relative_target(pc, sign_extend(imm, 21)))
+        }
+        // JALR
+        0x67 => {
+            let imm = sign_extend(i >> 20, 12);
+            read_register((i >> 15 & 0x1f) as usize)
+                .map(|base| (base as u32).wrapping_add(imm) as usize & !0x1)
+        }
+        // BEQ, BNE, BLT, BGE, BLTU and BGEU
+        0x63 => {
+            let imm = (i >> 31 & 0x1) << 12
+                | (i >> 25 & 0x3f) << 5
+                | (i >> 8 & 0xf) << 1
+                | (i >> 7 & 0x1) << 11;
+            Some(relative_target(pc, sign_extend(imm, 13)))
+        }
+        _ => None,
+    }
 }
+
+/// Where the 16-bit compressed instruction at `pc` may jump or branch to, or
+/// `None` if it does not.
+fn compressed_branch_target(
+    pc: usize,
+    instruction: u16,
+    read_register: &dyn Fn(usize) -> Option<usize>,
+) -> Option<usize> {
+    let i = instruction as u32;
+    match (i & 0x3, i >> 13) {
+        // C.JAL and C.J
+        (1, 1) | (1, 5) => {
+            let imm = (i >> 12 & 0x1) << 11
+                | (i >> 11 & 0x1) << 4
+                | (i >> 9 & 0x3) << 8
+                | (i >> 8 & 0x1) << 10
+                | (i >> 7 & 0x1) << 6
+                | (i >> 6 & 0x1) << 7
+                | (i >> 3 & 0x7) << 1
+                | (i >> 2 & 0x1) << 5;
+            Some(relative_target(pc, sign_extend(imm, 12)))
+        }
+        // C.BEQZ and C.BNEZ
+        (1, 6) | (1, 7) => {
+            let imm = (i >> 12 & 0x1) << 8
+                | (i >> 10 & 0x3) << 3
+                | (i >> 5 & 0x3) << 6
+                | (i >> 3 & 0x3) << 1
+                | (i >> 2 & 0x1) << 5;
+            Some(relative_target(pc, sign_extend(imm, 9)))
+        }
+        // C.JR and C.JALR. With rs1 zero these are reserved and C.EBREAK.
+        (2, 4) if i >> 2 & 0x1f == 0 && i >> 7 & 0x1f != 0 => {
+            read_register((i >> 7 & 0x1f) as usize).map(|target| target & !0x1)
+        }
+        _ => None,
+    }
+}
+
+/// The target of a jump or branch at `pc` with the offset `imm`.
+fn relative_target(pc: usize, imm: u32) -> usize {
+    (pc as u32).wrapping_add(imm) as usize
+}
+
+/// Sign-extend the `bits` lowest bits of `value`.
+fn sign_extend(value: u32, bits: u32) -> u32 {
+    let shift = 32 - bits;
+    ((value << shift) as i32 >> shift) as u32
+}
+
+/// Registers of the `org.gnu.gdb.riscv.cpu` GDB feature: x0-x31 and pc.
+const TARGET_DESCRIPTION: &'static str = concat!(
+    "<?xml version=\"1.0\"?>",
+    "<!DOCTYPE target SYSTEM \"gdb-target.dtd\">",
+    "<target version=\"1.0\">",
+    "<architecture>riscv:rv32</architecture>",
+    "<feature name=\"org.gnu.gdb.riscv.cpu\">",
+    "<reg name=\"x0\" bitsize=\"32\"/>",
+    "<reg name=\"x1\" bitsize=\"32\" type=\"code_ptr\"/>",
+    "<reg name=\"x2\" bitsize=\"32\" type=\"data_ptr\"/>",
+    "<reg name=\"x3\" bitsize=\"32\"/>",
+    "<reg name=\"x4\" bitsize=\"32\"/>",
+    "<reg name=\"x5\" bitsize=\"32\"/>",
+    "<reg name=\"x6\" bitsize=\"32\"/>",
+    "<reg name=\"x7\" bitsize=\"32\"/>",
+    "<reg name=\"x8\" bitsize=\"32\"/>",
+    "<reg name=\"x9\" bitsize=\"32\"/>",
+    "<reg name=\"x10\" bitsize=\"32\"/>",
+    "<reg name=\"x11\" bitsize=\"32\"/>",
+    "<reg name=\"x12\" bitsize=\"32\"/>",
+    "<reg name=\"x13\" bitsize=\"32\"/>",
+    "<reg name=\"x14\" bitsize=\"32\"/>",
+    "<reg name=\"x15\" bitsize=\"32\"/>",
+    "<reg name=\"x16\" bitsize=\"32\"/>",
+    "<reg name=\"x17\" bitsize=\"32\"/>",
+    "<reg name=\"x18\" bitsize=\"32\"/>",
+    "<reg name=\"x19\" bitsize=\"32\"/>",
+    "<reg name=\"x20\" bitsize=\"32\"/>",
+    "<reg name=\"x21\" bitsize=\"32\"/>",
+    "<reg name=\"x22\" bitsize=\"32\"/>",
+    "<reg name=\"x23\" bitsize=\"32\"/>",
+    "<reg name=\"x24\" bitsize=\"32\"/>",
+    "<reg name=\"x25\" bitsize=\"32\"/>",
+    "<reg name=\"x26\" bitsize=\"32\"/>",
+    "<reg name=\"x27\" bitsize=\"32\"/>",
+    "<reg name=\"x28\" bitsize=\"32\"/>",
+    "<reg name=\"x29\" bitsize=\"32\"/>",
+    "<reg name=\"x30\" bitsize=\"32\"/>",
+    "<reg name=\"x31\" bitsize=\"32\"/>",
+    "<reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\"/>",
+    "</feature>",
+    "</target>",
+);
@@ -0,0 +1,70 @@
+//! Component for GdbStub, which debugs a process with GDB over a UART.
+//!
+//! This provides one Component, GdbStubComponent, which creates a GDB remote
+//! serial protocol stub on a virtual UART and lets the kernel report the
+//! breakpoints of processes to it. GDB needs a UART of its own, so the mux
+//! should not also carry the console or the process console.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let gdb_stub = GdbStubComponent::new(board_kernel, uart_mux).finalize(());
+//! gdb_stub.start();
+//! ```
+
+#![allow(dead_code)] // Components are intended to be conditionally included
+
+use capsules::gdb_stub;
+use capsules::virtual_uart::{MuxUart, UartDevice};
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil;
+use kernel::static_init;
+
+pub struct GdbStubComponent {
+    board_kernel: &'static kernel::Kernel,
+    uart_mux: &'static MuxUart<'static>,
+}
+
+impl GdbStubComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        uart_mux: &'static MuxUart,
+    ) -> GdbStubComponent {
+        GdbStubComponent {
+            board_kernel: board_kernel,
+            uart_mux: uart_mux,
+        }
+    }
+}
+
+pub struct Capability;
+unsafe impl capabilities::ProcessManagementCapability for Capability {}
+
+impl Component for GdbStubComponent {
+    type StaticInput = ();
+    type Output = &'static gdb_stub::GdbStub<'static, Capability>;
+
+    unsafe fn finalize(&mut self, _s: Self::StaticInput) -> Self::Output {
+        let gdb_uart = static_init!(UartDevice, UartDevice::new(self.uart_mux, true));
+        gdb_uart.setup();
+
+        let gdb_stub = static_init!(
+            gdb_stub::GdbStub<'static, Capability>,
+            gdb_stub::GdbStub::new(
+                gdb_uart,
+                &mut gdb_stub::TX_BUF,
+                &mut gdb_stub::RX_BUF,
+                &mut gdb_stub::PACKET_BUF,
+                &mut gdb_stub::FLASH_BUF,
+                self.board_kernel,
+                Capability,
+            )
+        );
+        hil::uart::Transmit::set_transmit_client(gdb_uart, gdb_stub);
+        hil::uart::Receive::set_receive_client(gdb_uart, gdb_stub);
+        self.board_kernel.set_debugger(gdb_stub, &Capability);
+
+        gdb_stub
+    }
+}
@@ -5,9 +5,11 @@ pub mod alarm;
 pub mod console;
 pub mod crc;
 pub mod debug_writer;
+pub mod gdb_stub;
 pub mod isl29035;
 pub mod nrf51822;
 pub mod process_console;
+pub mod process_info;
 pub mod rng;
 pub mod si7021;
 pub mod spi;
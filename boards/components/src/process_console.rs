@@ -9,6 +9,9 @@
 //! ```rust
 //! let pconsole = ProcessConsoleComponent::new(board_kernel, uart_mux).finalize(());
 //! ```
+//!
+//! Boards that can reset themselves should also let the `reboot` command do
+//! so with `pconsole.set_reset_function()`.
 
 // Author: Philip Levis <pal@cs.stanford.edu>
 // Last modified: 6/20/2018
@@ -58,6 +61,7 @@ impl Component for ProcessConsoleComponent {
                 &mut process_console::WRITE_BUF,
                 &mut process_console::READ_BUF,
                 &mut process_console::COMMAND_BUF,
+                &mut process_console::HISTORY_BUF,
                 self.board_kernel,
                 Capability,
             )
@@ -0,0 +1,57 @@
+//! Component for the process info driver.
+//!
+//! This provides one Component, ProcessInfoComponent, which lets the listed
+//! privileged apps inspect processes and kernel statistics.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let process_info = ProcessInfoComponent::new(board_kernel, &["monitor"]).finalize(());
+//! ```
+
+#![allow(dead_code)] // Components are intended to be conditionally included
+
+use capsules::process_info;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::static_init;
+
+pub struct ProcessInfoComponent {
+    board_kernel: &'static kernel::Kernel,
+    privileged_apps: &'static [&'static str],
+}
+
+impl ProcessInfoComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        privileged_apps: &'static [&'static str],
+    ) -> ProcessInfoComponent {
+        ProcessInfoComponent {
+            board_kernel: board_kernel,
+            privileged_apps: privileged_apps,
+        }
+    }
+}
+
+pub struct Capability;
+unsafe impl capabilities::ProcessManagementCapability for Capability {}
+
+impl Component for ProcessInfoComponent {
+    type StaticInput = ();
+    type Output = &'static process_info::ProcessInfo<Capability>;
+
+    unsafe fn finalize(&mut self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        static_init!(
+            process_info::ProcessInfo<Capability>,
+            process_info::ProcessInfo::new(
+                self.board_kernel,
+                self.board_kernel.create_grant(&grant_cap),
+                self.privileged_apps,
+                Capability,
+            )
+        )
+    }
+}
@@ -0,0 +1,64 @@
+//! Sample processes linked into the host kernel.
+//!
+//! These use the system call interface directly, the way libtock-c does under
+//! the hood.
+
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use host::image::App;
+use host::userspace::{allow, command, subscribe, yieldk_for};
+
+pub static APPS: [App; 2] = [
+    App {
+        name: "hello",
+        main: hello,
+        minimum_ram_size: 4096,
+    },
+    App {
+        name: "blink",
+        main: blink,
+        minimum_ram_size: 4096,
+    },
+];
+
+/// Print a message on the console.
+extern "C" fn hello(_flash_start: usize, mem_start: usize, _mem_len: usize, _app_break: usize) {
+    static WRITTEN: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn write_done(_: usize, _: usize, _: usize, _: usize) {
+        WRITTEN.store(true, Ordering::SeqCst);
+    }
+
+    // Shared buffers must be in process memory, so copy the message there.
+    let message = b"Hello from a host process!\r\n";
+    let buffer = mem_start as *mut u8;
+    unsafe {
+        ptr::copy_nonoverlapping(message.as_ptr(), buffer, message.len());
+    }
+
+    allow(capsules::console::DRIVER_NUM, 1, buffer, message.len());
+    subscribe(capsules::console::DRIVER_NUM, 1, Some(write_done), 0);
+    command(capsules::console::DRIVER_NUM, 1, message.len(), 0);
+    yieldk_for(|| WRITTEN.load(Ordering::SeqCst));
+}
+
+/// Toggle the first LED every half second.
+extern "C" fn blink(_flash_start: usize, _mem_start: usize, _mem_len: usize, _app_break: usize) {
+    static FIRED: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn alarm_fired(_: usize, _: usize, _: usize, _: usize) {
+        FIRED.store(true, Ordering::SeqCst);
+    }
+
+    subscribe(capsules::alarm::DRIVER_NUM, 0, Some(alarm_fired), 0);
+    loop {
+        command(capsules::led::DRIVER_NUM, 3, 0, 0);
+
+        // The alarm ticks at 1 kHz.
+        let now = command(capsules::alarm::DRIVER_NUM, 2, 0, 0) as usize;
+        FIRED.store(false, Ordering::SeqCst);
+        command(capsules::alarm::DRIVER_NUM, 4, now.wrapping_add(500), 0);
+        yieldk_for(|| FIRED.load(Ordering::SeqCst));
+    }
+}
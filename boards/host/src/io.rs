@@ -0,0 +1,42 @@
+use core::fmt::Write;
+use std::io::{self, Write as IoWrite};
+use std::panic;
+use std::thread;
+
+use kernel::debug;
+
+use crate::PROCESSES;
+
+/// Writes straight to the terminal, like the UART's synchronous transmit.
+struct Writer;
+
+impl Write for Writer {
+    fn write_str(&mut self, s: &str) -> ::core::fmt::Result {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        let _ = stdout.write_all(s.as_bytes());
+        let _ = stdout.flush();
+        Ok(())
+    }
+}
+
+/// Print the usual kernel panic report when the kernel thread panics.
+///
+/// Process threads are left to the default hook: their panics are process
+/// faults, which the chip reports to the kernel.
+pub fn set_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |pi| {
+        if thread::current().name() != Some("main") {
+            default_hook(pi);
+            return;
+        }
+
+        let writer = &mut Writer;
+        unsafe {
+            debug::panic_banner(writer, pi);
+            debug::flush(writer);
+            debug::panic_process_info(&PROCESSES, writer);
+        }
+    }));
+}
@@ -0,0 +1,255 @@
+//! Board file for running Tock as a Linux host process.
+//!
+//! This runs the same kernel loop, capsules and system call paths as the
+//! hardware boards, on the machine that builds it. Processes are host threads
+//! (see the `host` chip crate), and the sample processes in `apps` are linked
+//! into the kernel binary.
+//!
+//! The console is the terminal. LEDs, the button and the GPIO driver pins are
+//! simulated GPIO pins, which can be mirrored to files with `--gpio <dir>`:
+//!
+//! | Pin | Use               |
+//! |-----|-------------------|
+//! | 0-1 | LEDs (active high)|
+//! | 2   | Button            |
+//! | 3-6 | GPIO driver       |
+
+use std::env;
+use std::path::PathBuf;
+
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil;
+use kernel::Platform;
+use kernel::{create_capability, debug, static_init};
+
+mod apps;
+mod io;
+
+/// Number of simulated GPIO pins.
+const NUM_PINS: usize = 7;
+
+// Actual memory for holding the active process structures.
+static mut PROCESSES: [Option<&'static dyn kernel::procs::ProcessType>; 4] =
+    [None, None, None, None];
+
+// How should the kernel respond when a process faults.
+const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
+
+/// RAM to be shared by all application processes. The kernel places process
+/// structures in it, so it must be aligned like RAM on a microcontroller.
+#[repr(align(8))]
+struct AppMemory([u8; 0x10000]);
+
+static mut APP_MEMORY: AppMemory = AppMemory([0; 0x10000]);
+
+/// A structure representing this platform that holds references to all
+/// capsules for this platform.
+struct Host {
+    console: &'static capsules::console::Console<'static>,
+    alarm: &'static capsules::alarm::AlarmDriver<
+        'static,
+        VirtualMuxAlarm<'static, host::alarm::HostAlarm<'static>>,
+    >,
+    led: &'static capsules::led::LED<'static>,
+    button: &'static capsules::button::Button<'static>,
+    gpio: &'static capsules::gpio::GPIO<'static>,
+    ipc: kernel::ipc::IPC,
+}
+
+/// Mapping of integer syscalls to objects that implement syscalls.
+impl Platform for Host {
+    fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn kernel::Driver>) -> R,
+    {
+        match driver_num {
+            capsules::console::DRIVER_NUM => f(Some(self.console)),
+            capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
+            capsules::led::DRIVER_NUM => f(Some(self.led)),
+            capsules::button::DRIVER_NUM => f(Some(self.button)),
+            capsules::gpio::DRIVER_NUM => f(Some(self.gpio)),
+            kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
+            _ => f(None),
+        }
+    }
+}
+
+/// Command line options.
+struct Options {
+    /// Directory to mirror the GPIO pins to.
+    gpio_dir: Option<PathBuf>,
+}
+
+fn parse_options() -> Options {
+    let mut options = Options { gpio_dir: None };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--gpio" => options.gpio_dir = args.next().map(PathBuf::from),
+            _ => {
+                eprintln!("usage: tock-host [--gpio <dir>]");
+                std::process::exit(1);
+            }
+        }
+    }
+    options
+}
+
+fn main() {
+    let options = parse_options();
+    unsafe {
+        reset_handler(options);
+    }
+}
+
+/// Set up the board and run the kernel. Never returns.
+unsafe fn reset_handler(options: Options) {
+    let process_mgmt_cap = create_capability!(capabilities::ProcessManagementCapability);
+    let memory_allocation_cap = create_capability!(capabilities::MemoryAllocationCapability);
+    let main_loop_cap = create_capability!(capabilities::MainLoopCapability);
+
+    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
+
+    // Simulated peripherals.
+    let uart = static_init!(host::uart::Uart<'static>, host::uart::Uart::new());
+    let alarm = static_init!(
+        host::alarm::HostAlarm<'static>,
+        host::alarm::HostAlarm::new()
+    );
+    kernel::debug::set_log_clock(alarm);
+    let gpio_port = static_init!(
+        host::gpio::Port,
+        host::gpio::Port::new(NUM_PINS, options.gpio_dir.as_ref().map(|dir| dir.as_path()))
+    );
+
+    let chip = static_init!(
+        host::chip::Host,
+        host::chip::Host::new(uart, alarm, gpio_port, None)
+    );
+    io::set_panic_hook();
+
+    // Create a shared UART channel for the console and for kernel debug.
+    uart.initialize();
+    let uart_mux = components::console::UartMuxComponent::new(uart, 115200).finalize(());
+
+    // Setup the console.
+    let console = components::console::ConsoleComponent::new(board_kernel, uart_mux).finalize(());
+    // Create the debugger object that handles calls to `debug!()`.
+    components::debug_writer::DebugWriterComponent::new(uart_mux).finalize(());
+    // Create the process console, which reads commands from the terminal.
+    let process_console =
+        components::process_console::ProcessConsoleComponent::new(board_kernel, uart_mux)
+            .finalize(());
+
+    // Create a shared virtualization mux layer on top of the host alarm.
+    let mux_alarm = static_init!(
+        MuxAlarm<'static, host::alarm::HostAlarm>,
+        MuxAlarm::new(alarm)
+    );
+    hil::time::Alarm::set_client(alarm, mux_alarm);
+
+    let alarm = components::alarm::AlarmDriverComponent::new(board_kernel, mux_alarm)
+        .finalize(components::alarm_component_helper!(host::alarm::HostAlarm));
+
+    // LEDs
+    let led_pins = static_init!(
+        [(
+            &'static dyn kernel::hil::gpio::Pin,
+            capsules::led::ActivationMode
+        ); 2],
+        [
+            (&gpio_port[0], capsules::led::ActivationMode::ActiveHigh),
+            (&gpio_port[1], capsules::led::ActivationMode::ActiveHigh),
+        ]
+    );
+    let led = static_init!(
+        capsules::led::LED<'static>,
+        capsules::led::LED::new(led_pins)
+    );
+
+    // Button
+    let button_pins = static_init!(
+        [(
+            &'static dyn kernel::hil::gpio::InterruptValuePin,
+            capsules::button::GpioMode
+        ); 1],
+        [(
+            static_init!(
+                hil::gpio::InterruptValueWrapper,
+                hil::gpio::InterruptValueWrapper::new(&gpio_port[2])
+            )
+            .finalize(),
+            capsules::button::GpioMode::HighWhenPressed
+        )]
+    );
+    let button = static_init!(
+        capsules::button::Button<'static>,
+        capsules::button::Button::new(
+            button_pins,
+            board_kernel.create_grant(&memory_allocation_cap)
+        )
+    );
+    for (pin, _) in button_pins.iter() {
+        pin.set_client(button);
+    }
+
+    // GPIO driver for the remaining pins.
+    let gpio_pins = static_init!(
+        [&'static dyn kernel::hil::gpio::InterruptValuePin; 4],
+        [
+            static_init!(
+                hil::gpio::InterruptValueWrapper,
+                hil::gpio::InterruptValueWrapper::new(&gpio_port[3])
+            )
+            .finalize(),
+            static_init!(
+                hil::gpio::InterruptValueWrapper,
+                hil::gpio::InterruptValueWrapper::new(&gpio_port[4])
+            )
+            .finalize(),
+            static_init!(
+                hil::gpio::InterruptValueWrapper,
+                hil::gpio::InterruptValueWrapper::new(&gpio_port[5])
+            )
+            .finalize(),
+            static_init!(
+                hil::gpio::InterruptValueWrapper,
+                hil::gpio::InterruptValueWrapper::new(&gpio_port[6])
+            )
+            .finalize(),
+        ]
+    );
+    let gpio = static_init!(
+        capsules::gpio::GPIO<'static>,
+        capsules::gpio::GPIO::new(gpio_pins, board_kernel.create_grant(&memory_allocation_cap))
+    );
+    for pin in gpio_pins.iter() {
+        pin.set_client(gpio);
+    }
+
+    let platform = Host {
+        console: console,
+        alarm: alarm,
+        led: led,
+        button: button,
+        gpio: gpio,
+        ipc: kernel::ipc::IPC::new(board_kernel, &memory_allocation_cap),
+    };
+
+    process_console.start();
+    debug!("Host initialization complete. Entering main loop");
+
+    kernel::procs::load_processes(
+        board_kernel,
+        chip,
+        host::image::build(&apps::APPS).as_ptr(),
+        &mut APP_MEMORY.0,
+        &mut PROCESSES,
+        FAULT_RESPONSE,
+        &process_mgmt_cap,
+    );
+
+    board_kernel.kernel_loop(&platform, chip, Some(&platform.ipc), &main_loop_cap, chip);
+}
@@ -79,8 +79,16 @@ pub unsafe fn initialize_all(
         capsules::ieee802154::virtual_mac::MacUser::new(mux_mac)
     );
     mux_mac.add_user(radio_mac);
+    let sixlowpan_alarm = static_init!(
+        VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
     let sixlowpan = static_init!(
-        Sixlowpan<'static, sam4l::ast::Ast<'static>, sixlowpan_compression::Context>,
+        Sixlowpan<
+            'static,
+            VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+            sixlowpan_compression::Context,
+        >,
         Sixlowpan::new(
             sixlowpan_compression::Context {
                 prefix: DEFAULT_CTX_PREFIX,
@@ -88,9 +96,10 @@ pub unsafe fn initialize_all(
                 id: 0,
                 compress: false,
             },
-            &sam4l::ast::AST
+            sixlowpan_alarm
         )
     );
+    sixlowpan_alarm.set_client(sixlowpan);
 
     let sixlowpan_state = sixlowpan as &dyn SixlowpanState;
     let sixlowpan_tx = TxState::new(sixlowpan_state);
@@ -0,0 +1,218 @@
+//! Component for the CoAP syscall interface on imix board.
+//!
+//! This provides one Component, CoapComponent, which lets apps serve CoAP
+//! resources and send CoAP requests. CoAP sends its messages through a UDP
+//! sender of its own, and receives them through the UDP receive path of the
+//! UDPComponent, on the CoAP port. It draws the tokens of its requests from
+//! the shared TRNG.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let (udp_driver, ip_recv_mux, sixlowpan_state, ip_receive, udp_recv) =
+//!     UDPComponent::new(...).finalize(());
+//! let coap_driver = CoapComponent::new(board_kernel,
+//!                                      mux_mac,
+//!                                      sixlowpan_state,
+//!                                      udp_recv,
+//!                                      mux_entropy,
+//!                                      DST_MAC_ADDR,
+//!                                      src_mac_from_serial_num,
+//!                                      eui64_from_serial_num,
+//!                                      interface,
+//!                                      mux_alarm).finalize(());
+//! ```
+
+#![allow(dead_code)] // Components are intended to be conditionally included
+
+use capsules::ieee802154::device::MacDevice;
+use capsules::net::coap::coap::{Coap, COAP_PORT, MAX_MESSAGE_LEN};
+use capsules::net::coap::CoapDriver;
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::ipv6::ipv6_interface::IP6Interface;
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules::net::sixlowpan::sixlowpan_state;
+use capsules::net::udp::udp::UDPHeader;
+use capsules::net::udp::udp_recv::{UDPKernelClient, UDPReceiver};
+use capsules::net::udp::udp_send::{UDPSendStruct, UDPSender};
+use capsules::rng::Entropy32ToRandom;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules::virtual_entropy::{MuxEntropy32, VirtualEntropy32};
+
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::radio;
+use kernel::hil::rng::Rng;
+use kernel::hil::time::Alarm;
+use kernel::static_init;
+
+// The largest request that apps can send, and response they can receive
+const CLIENT_BUF_LEN: usize = 512;
+// The largest request that the server can receive in blocks, and response it
+// can send in blocks
+const BODY_BUF_LEN: usize = 512;
+
+// CoAP requires several buffers:
+//
+//   1. COAP_RF233_BUF: buffer the IP6_Sender uses to pass frames to the radio after fragmentation
+//   2. COAP_PAYLOAD: The payload of the IP6_Packet, which holds a message before it is tx'd
+//   3. COAP_CLIENT_TX_BUF and COAP_SERVER_TX_BUF: buffers the messages of the client and of the
+//      server are kept in until they are acknowledged
+//   4. COAP_BODY_BUF and COAP_CLIENT_BUF: buffers large requests and responses are assembled in
+
+static mut COAP_RF233_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut COAP_PAYLOAD: [u8; MAX_MESSAGE_LEN] = [0x00; MAX_MESSAGE_LEN];
+static mut COAP_CLIENT_TX_BUF: [u8; MAX_MESSAGE_LEN] = [0x00; MAX_MESSAGE_LEN];
+static mut COAP_SERVER_TX_BUF: [u8; MAX_MESSAGE_LEN] = [0x00; MAX_MESSAGE_LEN];
+static mut COAP_BODY_BUF: [u8; BODY_BUF_LEN] = [0x00; BODY_BUF_LEN];
+static mut COAP_CLIENT_BUF: [u8; CLIENT_BUF_LEN] = [0x00; CLIENT_BUF_LEN];
+
+type CoapIP6Sender = IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>;
+type CoapUDPSender = UDPSendStruct<'static, CoapIP6Sender>;
+type CoapDevice = Coap<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>, CoapUDPSender>;
+pub type CoapDriverDevice =
+    CoapDriver<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>, CoapUDPSender>;
+
+pub struct CoapComponent {
+    board_kernel: &'static kernel::Kernel,
+    mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+    sixlowpan_state: &'static dyn sixlowpan_state::SixlowpanState<'static>,
+    udp_recv: &'static UDPReceiver<'static>,
+    mux_entropy: &'static MuxEntropy32<'static>,
+    dst_mac_addr: MacAddress,
+    src_mac_addr: MacAddress,
+    eui64: [u8; 8],
+    interface: &'static IP6Interface,
+    alarm_mux: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+}
+
+impl CoapComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        sixlowpan_state: &'static dyn sixlowpan_state::SixlowpanState<'static>,
+        udp_recv: &'static UDPReceiver<'static>,
+        mux_entropy: &'static MuxEntropy32<'static>,
+        dst_mac_addr: MacAddress,
+        src_mac_addr: MacAddress,
+        eui64: [u8; 8],
+        interface: &'static IP6Interface,
+        alarm: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    ) -> CoapComponent {
+        CoapComponent {
+            board_kernel,
+            mux_mac,
+            sixlowpan_state,
+            udp_recv,
+            mux_entropy,
+            dst_mac_addr,
+            src_mac_addr,
+            eui64,
+            interface,
+            alarm_mux: alarm,
+        }
+    }
+}
+
+impl Component for CoapComponent {
+    type StaticInput = ();
+    type Output = &'static CoapDriverDevice;
+
+    unsafe fn finalize(&mut self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let ipsender_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+
+        // Only used to transmit: the UDPComponent receives the frames of
+        // all protocols.
+        let coap_mac = static_init!(
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(coap_mac);
+
+        let sixlowpan_tx = sixlowpan_state::TxState::new(self.sixlowpan_state);
+
+        let tr_hdr = TransportHeader::UDP(UDPHeader::new());
+        let ip_pyld: IPPayload = IPPayload {
+            header: tr_hdr,
+            payload: &mut COAP_PAYLOAD,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        let ip_send = static_init!(
+            CoapIP6Sender,
+            IP6SendStruct::new(
+                ip6_dg,
+                ipsender_virtual_alarm,
+                &mut COAP_RF233_BUF,
+                sixlowpan_tx,
+                coap_mac,
+                self.dst_mac_addr,
+                self.src_mac_addr
+            )
+        );
+        ipsender_virtual_alarm.set_client(ip_send);
+        ip_send.set_interface(self.interface);
+        coap_mac.set_transmit_client(ip_send);
+
+        let udp_send = static_init!(CoapUDPSender, UDPSendStruct::new(ip_send));
+        ip_send.set_client(udp_send);
+
+        let coap_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let coap_entropy = static_init!(
+            VirtualEntropy32<'static>,
+            VirtualEntropy32::new(self.mux_entropy)
+        );
+        let coap_rng = static_init!(
+            Entropy32ToRandom<'static>,
+            Entropy32ToRandom::new(coap_entropy)
+        );
+        // Message IDs start from a value that differs between boards
+        let seed = self
+            .eui64
+            .iter()
+            .fold(0x2545_f491, |seed: u32, b| seed.rotate_left(5) ^ *b as u32);
+        let coap = static_init!(
+            CoapDevice,
+            Coap::new(
+                udp_send,
+                coap_virtual_alarm,
+                coap_rng,
+                seed,
+                &mut COAP_CLIENT_TX_BUF,
+                &mut COAP_SERVER_TX_BUF,
+                &mut COAP_BODY_BUF
+            )
+        );
+        udp_send.set_client(coap);
+        coap_virtual_alarm.set_client(coap);
+        coap_rng.set_client(coap);
+
+        let coap_udp_client = static_init!(
+            UDPKernelClient<'static>,
+            UDPKernelClient::new(COAP_PORT, coap)
+        );
+        self.udp_recv.add_kernel_client(coap_udp_client);
+
+        let coap_driver = static_init!(
+            CoapDriverDevice,
+            CoapDriver::new(
+                coap,
+                self.board_kernel.create_grant(&grant_cap),
+                &mut COAP_CLIENT_BUF
+            )
+        );
+        coap.set_client(coap_driver);
+        coap.set_server(coap_driver);
+        coap_driver
+    }
+}
@@ -0,0 +1,224 @@
+//! Component to initialize the icmpv6/6lowpan interface on imix board.
+//!
+//! This provides one Component, ICMP6Component, which answers Echo Requests,
+//! sends ICMPv6 error messages for the packets the stack cannot deliver, and
+//! implements a userspace syscall interface to send Echo Requests (ping). It
+//! also runs Neighbor Discovery, which configures the addresses and the
+//! default router of the interface from the extended address of the radio and
+//! the Router Advertisements it receives, as well as the 6LoWPAN contexts of
+//! `contexts`, and RPL, which joins the DODAGs
+//! advertised by neighbors and adds the routes through the mesh to the
+//! interface. It shares the 6lowpan state and the receive path of the
+//! UDPComponent.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let (udp_driver, ip_recv_mux, sixlowpan_state, _ip_receive) =
+//!     UDPComponent::new(...).finalize(());
+//! let icmp6_driver = ICMP6Component::new(board_kernel,
+//!                                        mux_mac,
+//!                                        sixlowpan_state,
+//!                                        ip_recv_mux,
+//!                                        DST_MAC_ADDR,
+//!                                        src_mac_from_serial_num,
+//!                                        interface,
+//!                                        contexts,
+//!                                        mux_alarm).finalize(());
+//! ```
+
+#![allow(dead_code)] // Components are intended to be conditionally included
+
+use capsules::ieee802154::device::MacDevice;
+use capsules::net::icmpv6::icmpv6::{ICMP6Header, ICMP6Type};
+use capsules::net::icmpv6::icmpv6_recv::ICMP6RecvStruct;
+use capsules::net::icmpv6::icmpv6_send::{ICMP6SendStruct, ICMP6Sender};
+use capsules::net::icmpv6::nd::NeighborDiscovery;
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::ip6_nh;
+use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::ipv6::ipv6_interface::IP6Interface;
+use capsules::net::ipv6::ipv6_recv::{IP6RecvMux, IP6RecvMuxClient};
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules::net::rpl::rpl::RPLNode;
+use capsules::net::sixlowpan::sixlowpan_context::ContextTable;
+use capsules::net::sixlowpan::sixlowpan_state;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::radio;
+use kernel::hil::time::Alarm;
+use kernel::static_init;
+
+// The largest ICMPv6 message body sent, the size of the largest UDP payload
+// of the UDPComponent.
+const PAYLOAD_LEN: usize = 192;
+
+// The ICMPv6 stack requires several buffers:
+//
+//   1. ICMP_RF233_BUF: buffer the IP6_Sender uses to pass frames to the radio after fragmentation
+//   2. ICMP_PAYLOAD: The payload of the IP6_Packet, which holds the body of a message before it is tx'd
+//   3. ICMP_ERROR_BUF: Buffer in which the ICMP6RecvStruct assembles the body of error messages
+//
+// Received packets are decompressed in the receive buffer of the UDPComponent.
+
+static mut ICMP_RF233_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut ICMP_PAYLOAD: [u8; PAYLOAD_LEN] = [0; PAYLOAD_LEN];
+static mut ICMP_ERROR_BUF: [u8; PAYLOAD_LEN] = [0; PAYLOAD_LEN];
+
+type ICMPIP6Sender = IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>;
+
+pub struct ICMP6Component {
+    board_kernel: &'static kernel::Kernel,
+    mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+    sixlowpan_state: &'static dyn sixlowpan_state::SixlowpanState<'static>,
+    ip_recv_mux: &'static IP6RecvMux<'static>,
+    dst_mac_addr: MacAddress,
+    src_mac_addr: MacAddress,
+    interface: &'static IP6Interface,
+    contexts: &'static ContextTable<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
+    alarm_mux: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+}
+
+impl ICMP6Component {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        sixlowpan_state: &'static dyn sixlowpan_state::SixlowpanState<'static>,
+        ip_recv_mux: &'static IP6RecvMux<'static>,
+        dst_mac_addr: MacAddress,
+        src_mac_addr: MacAddress,
+        interface: &'static IP6Interface,
+        contexts: &'static ContextTable<
+            'static,
+            VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+        >,
+        alarm: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    ) -> ICMP6Component {
+        ICMP6Component {
+            board_kernel,
+            mux_mac,
+            sixlowpan_state,
+            ip_recv_mux,
+            dst_mac_addr,
+            src_mac_addr,
+            interface,
+            contexts,
+            alarm_mux: alarm,
+        }
+    }
+}
+
+impl Component for ICMP6Component {
+    type StaticInput = ();
+    type Output = &'static capsules::net::icmpv6::ICMP6Driver<'static>;
+
+    unsafe fn finalize(&mut self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let ipsender_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let nd_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let rpl_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+
+        // Only used to transmit: the UDPComponent receives the frames of
+        // all protocols.
+        let icmp_mac = static_init!(
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(icmp_mac);
+
+        let sixlowpan_tx = sixlowpan_state::TxState::new(self.sixlowpan_state);
+
+        let tr_hdr = TransportHeader::ICMP(ICMP6Header::new(ICMP6Type::Type128));
+        let ip_pyld: IPPayload = IPPayload {
+            header: tr_hdr,
+            payload: &mut ICMP_PAYLOAD,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        let ip_send = static_init!(
+            ICMPIP6Sender,
+            IP6SendStruct::new(
+                ip6_dg,
+                ipsender_virtual_alarm,
+                &mut ICMP_RF233_BUF,
+                sixlowpan_tx,
+                icmp_mac,
+                self.dst_mac_addr,
+                self.src_mac_addr
+            )
+        );
+        ipsender_virtual_alarm.set_client(ip_send);
+        ip_send.set_interface(self.interface);
+        icmp_mac.set_transmit_client(ip_send);
+
+        let icmp_send = static_init!(
+            ICMP6SendStruct<'static, ICMPIP6Sender>,
+            ICMP6SendStruct::new(ip_send)
+        );
+        ip_send.set_client(icmp_send);
+
+        let icmp_recv = static_init!(
+            ICMP6RecvStruct<'static>,
+            ICMP6RecvStruct::new(icmp_send, &mut ICMP_ERROR_BUF)
+        );
+        let icmp_recv_mux_client = static_init!(
+            IP6RecvMuxClient<'static>,
+            IP6RecvMuxClient::new(ip6_nh::ICMP, icmp_recv)
+        );
+        self.ip_recv_mux.add_client(icmp_recv_mux_client);
+        self.ip_recv_mux.set_error_reporter(icmp_recv);
+
+        let icmp6_driver = static_init!(
+            capsules::net::icmpv6::ICMP6Driver<'static>,
+            capsules::net::icmpv6::ICMP6Driver::new(
+                icmp_send,
+                self.board_kernel.create_grant(&grant_cap),
+                PAYLOAD_LEN
+            )
+        );
+        icmp_send.set_client(icmp6_driver);
+        icmp_recv.set_client(icmp6_driver);
+
+        let nd = static_init!(
+            NeighborDiscovery<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+            NeighborDiscovery::new(
+                icmp_send,
+                self.interface,
+                nd_virtual_alarm,
+                icmp_mac.get_address_long()
+            )
+        );
+        nd_virtual_alarm.set_client(nd);
+        nd.set_context_table(self.contexts);
+        icmp_recv.set_nd_client(nd);
+        nd.start();
+
+        let rpl = static_init!(
+            RPLNode<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+            RPLNode::new(
+                icmp_send,
+                self.interface,
+                rpl_virtual_alarm,
+                icmp_mac.get_address_long()
+            )
+        );
+        rpl_virtual_alarm.set_client(rpl);
+        icmp_recv.set_rpl_client(rpl);
+        ip_send.set_link_client(rpl);
+        rpl.start();
+
+        icmp6_driver
+    }
+}
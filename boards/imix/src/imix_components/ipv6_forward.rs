@@ -0,0 +1,150 @@
+//! Component to initialize IPv6 forwarding on imix board.
+//!
+//! This provides one Component, IP6ForwardComponent, which makes the board a
+//! router: the packets the UDPComponent receives for other destinations are
+//! sent on to the next hop towards them, using the routes of the interface,
+//! and the packets that cannot be forwarded are answered with ICMPv6 error
+//! messages through the receive path of the UDPComponent.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let (udp_driver, ip_recv_mux, sixlowpan_state, ip_receive) =
+//!     UDPComponent::new(...).finalize(());
+//! let forwarder = IP6ForwardComponent::new(mux_mac,
+//!                                          sixlowpan_state,
+//!                                          ip_receive,
+//!                                          ip_recv_mux,
+//!                                          DST_MAC_ADDR,
+//!                                          src_mac_from_serial_num,
+//!                                          interface,
+//!                                          mux_alarm).finalize(());
+//! ```
+
+#![allow(dead_code)] // Components are intended to be conditionally included
+
+use capsules::ieee802154::device::MacDevice;
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::ipv6::ipv6_forward::{IP6Forwarder, FORWARD_QUEUE_LEN};
+use capsules::net::ipv6::ipv6_interface::IP6Interface;
+use capsules::net::ipv6::ipv6_recv::{IP6RecvMux, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules::net::sixlowpan::sixlowpan_state;
+use capsules::net::udp::udp::UDPHeader;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+
+use kernel::component::Component;
+use kernel::hil::radio;
+use kernel::hil::time::Alarm;
+use kernel::static_init;
+
+// The largest packet forwarded, with its IPv6 header.
+const FORWARD_SLOT_LEN: usize = 256;
+const IP6_HDR_LEN: usize = 40;
+
+// Forwarding requires several buffers:
+//
+//   1. FORWARD_RF233_BUF: buffer the IP6_Sender uses to pass frames to the radio after fragmentation
+//   2. FORWARD_PAYLOAD: The payload of the IP6_Packet, which holds a forwarded packet before it is tx'd
+//   3. FORWARD_QUEUE_BUF: The slots of the queue of packets waiting to be forwarded
+//
+// Received packets are decompressed in the receive buffer of the UDPComponent.
+
+static mut FORWARD_RF233_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut FORWARD_PAYLOAD: [u8; FORWARD_SLOT_LEN - IP6_HDR_LEN] =
+    [0; FORWARD_SLOT_LEN - IP6_HDR_LEN];
+static mut FORWARD_QUEUE_BUF: [u8; FORWARD_QUEUE_LEN * FORWARD_SLOT_LEN] =
+    [0; FORWARD_QUEUE_LEN * FORWARD_SLOT_LEN];
+
+type ForwardIP6Sender = IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>;
+
+pub struct IP6ForwardComponent {
+    mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+    sixlowpan_state: &'static dyn sixlowpan_state::SixlowpanState<'static>,
+    ip_receive: &'static IP6RecvStruct<'static>,
+    ip_recv_mux: &'static IP6RecvMux<'static>,
+    dst_mac_addr: MacAddress,
+    src_mac_addr: MacAddress,
+    interface: &'static IP6Interface,
+    alarm_mux: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+}
+
+impl IP6ForwardComponent {
+    pub fn new(
+        mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        sixlowpan_state: &'static dyn sixlowpan_state::SixlowpanState<'static>,
+        ip_receive: &'static IP6RecvStruct<'static>,
+        ip_recv_mux: &'static IP6RecvMux<'static>,
+        dst_mac_addr: MacAddress,
+        src_mac_addr: MacAddress,
+        interface: &'static IP6Interface,
+        alarm: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    ) -> IP6ForwardComponent {
+        IP6ForwardComponent {
+            mux_mac,
+            sixlowpan_state,
+            ip_receive,
+            ip_recv_mux,
+            dst_mac_addr,
+            src_mac_addr,
+            interface,
+            alarm_mux: alarm,
+        }
+    }
+}
+
+impl Component for IP6ForwardComponent {
+    type StaticInput = ();
+    type Output = &'static IP6Forwarder<'static, ForwardIP6Sender>;
+
+    unsafe fn finalize(&mut self, _s: Self::StaticInput) -> Self::Output {
+        let ipsender_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+
+        // Only used to transmit: the UDPComponent receives the frames of
+        // all protocols.
+        let forward_mac = static_init!(
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(forward_mac);
+
+        let sixlowpan_tx = sixlowpan_state::TxState::new(self.sixlowpan_state);
+
+        // The transport header is replaced by the one of each forwarded packet
+        let tr_hdr = TransportHeader::UDP(UDPHeader::new());
+        let ip_pyld: IPPayload = IPPayload {
+            header: tr_hdr,
+            payload: &mut FORWARD_PAYLOAD,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        let ip_send = static_init!(
+            ForwardIP6Sender,
+            IP6SendStruct::new(
+                ip6_dg,
+                ipsender_virtual_alarm,
+                &mut FORWARD_RF233_BUF,
+                sixlowpan_tx,
+                forward_mac,
+                self.dst_mac_addr,
+                self.src_mac_addr
+            )
+        );
+        ipsender_virtual_alarm.set_client(ip_send);
+        ip_send.set_interface(self.interface);
+        forward_mac.set_transmit_client(ip_send);
+
+        let forwarder = static_init!(
+            IP6Forwarder<'static, ForwardIP6Sender>,
+            IP6Forwarder::new(ip_send, self.interface, &mut FORWARD_QUEUE_BUF)
+        );
+        ip_send.set_client(forwarder);
+        forwarder.set_error_reporter(self.ip_recv_mux);
+        self.ip_receive.set_forward_client(forwarder);
+        forwarder
+    }
+}
@@ -2,24 +2,38 @@ pub mod adc;
 pub mod analog_comparator;
 pub mod button;
 pub mod clock_pm;
+pub mod coap;
 pub mod fxos8700;
 pub mod gpio;
+pub mod icmp_6lowpan;
+pub mod ipv6_forward;
 pub mod led;
 pub mod nonvolatile_storage;
 pub mod radio;
 pub mod rf233;
+pub mod sixlowpan_context;
+pub mod tcp_6lowpan;
+pub mod thread_mle;
 pub mod udp_6lowpan;
 pub mod usb;
+pub mod watchdog;
 
 pub use self::adc::AdcComponent;
 pub use self::analog_comparator::AcComponent;
 pub use self::button::ButtonComponent;
 pub use self::clock_pm::ClockManagerComponent;
+pub use self::coap::CoapComponent;
 pub use self::fxos8700::NineDofComponent;
 pub use self::gpio::GpioComponent;
+pub use self::icmp_6lowpan::ICMP6Component;
+pub use self::ipv6_forward::IP6ForwardComponent;
 pub use self::led::LedComponent;
 pub use self::nonvolatile_storage::NonvolatileStorageComponent;
 pub use self::radio::RadioComponent;
 pub use self::rf233::RF233Component;
+pub use self::sixlowpan_context::SixlowpanContextComponent;
+pub use self::tcp_6lowpan::TCPComponent;
+pub use self::thread_mle::ThreadComponent;
 pub use self::udp_6lowpan::UDPComponent;
 pub use self::usb::UsbComponent;
+pub use self::watchdog::WatchdogComponent;
@@ -7,7 +7,13 @@
 //! Usage
 //! -----
 //! ```rust
-//! let (radio_driver, mux_mac) = RadioComponent::new(rf233, PAN_ID, 0x1008).finalize(());
+//! let (radio_driver, mux_mac, mux_aes_ccm) = RadioComponent::new(rf233, PAN_ID, 0x1008).finalize(());
+//! ```
+//!
+//! The AES-CCM* engine is shared through `mux_aes_ccm` with the users of
+//! MLE security, like the ThreadComponent.
+//!
+//! ```rust
 //! ```
 
 // Author: Philip Levis <pal@cs.stanford.edu>
@@ -30,7 +36,10 @@ use kernel::hil::symmetric_encryption::{AES128, AES128CCM};
 use kernel::hil::time::Alarm;
 use kernel::hil::rng::Rng;
 use kernel::static_init;
+use capsules::net::thread::mle::MLE_BUF_LEN;
+use capsules::virtual_aes_ccm::{MuxAES128CCM, VirtualAES128CCM};
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules::virtual_entropy::{MuxEntropy32, VirtualEntropy32};
 
 // Save some deep nesting
 type RF233Device =
@@ -38,13 +47,16 @@ type RF233Device =
 type AlarmDriverDevice = capsules::virtual_alarm::VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>;
 type RngDevice = capsules::rng::Entropy32ToRandom<'static>;
 type XMacDevice = capsules::ieee802154::xmac::XMac<'static, RF233Device, AlarmDriverDevice>;
+type AESCCMDevice = capsules::aes_ccm::AES128CCM<'static, sam4l::aes::Aes<'static>>;
 
 pub struct RadioComponent {
     board_kernel: &'static kernel::Kernel,
     rf233: &'static RF233Device,
     alarm: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    mux_entropy: &'static MuxEntropy32<'static>,
     pan_id: capsules::net::ieee802154::PanID,
     short_addr: u16,
+    long_addr: [u8; 8],
 }
 
 impl RadioComponent {
@@ -52,15 +64,19 @@ impl RadioComponent {
         board_kernel: &'static kernel::Kernel,
         rf233: &'static RF233Device,
         alarm: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+        mux_entropy: &'static MuxEntropy32<'static>,
         pan_id: capsules::net::ieee802154::PanID,
         addr: u16,
+        long_addr: [u8; 8],
     ) -> RadioComponent {
         RadioComponent {
             board_kernel: board_kernel,
             rf233: rf233,
             alarm: alarm,
+            mux_entropy: mux_entropy,
             pan_id: pan_id,
             short_addr: addr,
+            long_addr: long_addr,
         }
     }
 }
@@ -73,8 +89,9 @@ static mut RADIO_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
 static mut RF233_RX_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
 
 // This buffer is used as an intermediate buffer for AES CCM encryption
-// An upper bound on the required size is 3 * BLOCK_SIZE + radio::MAX_BUF_SIZE
-const CRYPT_SIZE: usize = 3 * symmetric_encryption::AES128_BLOCK_SIZE + radio::MAX_BUF_SIZE;
+// An upper bound on the required size is 3 * BLOCK_SIZE + the size of the
+// buffers encrypted, which is larger for MLE messages than for radio frames
+const CRYPT_SIZE: usize = 3 * symmetric_encryption::AES128_BLOCK_SIZE + MLE_BUF_LEN;
 static mut CRYPT_BUF: [u8; CRYPT_SIZE] = [0x00; CRYPT_SIZE];
 
 impl Component for RadioComponent {
@@ -82,6 +99,7 @@ impl Component for RadioComponent {
     type Output = (
         &'static capsules::ieee802154::RadioDriver<'static>,
         &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        &'static MuxAES128CCM<'static, AESCCMDevice>,
     );
 
     unsafe fn finalize(&mut self, _s: Self::StaticInput) -> Self::Output {
@@ -93,19 +111,32 @@ impl Component for RadioComponent {
             VirtualMuxAlarm<'static, sam4l::ast::Ast>,
             VirtualMuxAlarm::new(self.alarm)
         );
+        let xmac_entropy = static_init!(
+            VirtualEntropy32<'static>,
+            VirtualEntropy32::new(self.mux_entropy)
+        );
         let entropy_to_random = static_init!(
             capsules::rng::Entropy32ToRandom<'static>,
-            capsules::rng::Entropy32ToRandom::new(&sam4l::trng::TRNG)
+            capsules::rng::Entropy32ToRandom::new(xmac_entropy)
         );
         let xmac: &XMacDevice = static_init!(XMacDevice, xmac::XMac::new(self.rf233, virtual_alarm, entropy_to_random));
         entropy_to_random.set_client(xmac);
         virtual_alarm.set_client(xmac);
         
         let aes_ccm = static_init!(
-            capsules::aes_ccm::AES128CCM<'static, sam4l::aes::Aes<'static>>,
+            AESCCMDevice,
             capsules::aes_ccm::AES128CCM::new(&sam4l::aes::AES, &mut CRYPT_BUF)
         );
         sam4l::aes::AES.set_client(aes_ccm);
+        let mux_aes_ccm = static_init!(
+            MuxAES128CCM<'static, AESCCMDevice>,
+            MuxAES128CCM::new(aes_ccm)
+        );
+        aes_ccm.set_client(mux_aes_ccm);
+        let framer_aes_ccm = static_init!(
+            VirtualAES128CCM<'static, AESCCMDevice>,
+            VirtualAES128CCM::new(mux_aes_ccm)
+        );
         //sam4l::aes::AES.enable();
 
         // Hook up the radio to the XMAC implementation.
@@ -118,9 +149,10 @@ impl Component for RadioComponent {
         // We can now use the XMac driver to instantiate a MacDevice like a Framer
         let mac_device = static_init!(
             capsules::ieee802154::framer::Framer<'static, XMacDevice,
-            capsules::aes_ccm::AES128CCM<'static, sam4l::aes::Aes<'static>>,
+            VirtualAES128CCM<'static, AESCCMDevice>,
             >,
-            capsules::ieee802154::framer::Framer::new(xmac, aes_ccm));
+            capsules::ieee802154::framer::Framer::new(xmac, framer_aes_ccm));
+        framer_aes_ccm.set_client(mac_device);
         xmac.set_transmit_client(mac_device);
         xmac.set_receive_client(mac_device);
         xmac.set_config_client(mac_device);
@@ -171,7 +203,8 @@ impl Component for RadioComponent {
         radio_mac.set_receive_client(radio_driver);
         radio_mac.set_pan(self.pan_id);
         radio_mac.set_address(self.short_addr);
+        radio_mac.set_address_long(self.long_addr);
 
-        (radio_driver, mux_mac)
+        (radio_driver, mux_mac, mux_aes_ccm)
     }
 }
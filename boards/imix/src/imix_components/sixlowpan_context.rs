@@ -0,0 +1,81 @@
+//! Component for the 6LoWPAN context driver on imix board.
+//!
+//! This provides one Component, SixlowpanContextComponent, which creates the
+//! table of the 6LoWPAN compression contexts that the UDPComponent
+//! compresses packets against, and lets the listed privileged apps read and
+//! set them. The table ages the contexts on an alarm of its own.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let (contexts, context_driver) =
+//!     SixlowpanContextComponent::new(board_kernel, mux_alarm, &["netconfig"]).finalize(());
+//! ```
+
+#![allow(dead_code)] // Components are intended to be conditionally included
+
+use capsules::net::sixlowpan::sixlowpan_context::ContextTable;
+use capsules::net::sixlowpan::ContextDriver;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::time::Alarm;
+use kernel::static_init;
+
+type ContextAlarm = VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>;
+
+pub struct SixlowpanContextComponent {
+    board_kernel: &'static kernel::Kernel,
+    alarm_mux: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    privileged_apps: &'static [&'static str],
+}
+
+impl SixlowpanContextComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        alarm: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+        privileged_apps: &'static [&'static str],
+    ) -> SixlowpanContextComponent {
+        SixlowpanContextComponent {
+            board_kernel,
+            alarm_mux: alarm,
+            privileged_apps,
+        }
+    }
+}
+
+pub struct Capability;
+unsafe impl capabilities::ProcessManagementCapability for Capability {}
+
+impl Component for SixlowpanContextComponent {
+    type StaticInput = ();
+    type Output = (
+        &'static ContextTable<'static, ContextAlarm>,
+        &'static ContextDriver<'static, ContextAlarm, Capability>,
+    );
+
+    unsafe fn finalize(&mut self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let context_virtual_alarm =
+            static_init!(ContextAlarm, VirtualMuxAlarm::new(self.alarm_mux));
+        let contexts = static_init!(
+            ContextTable<'static, ContextAlarm>,
+            ContextTable::new(context_virtual_alarm)
+        );
+        context_virtual_alarm.set_client(contexts);
+
+        let context_driver = static_init!(
+            ContextDriver<'static, ContextAlarm, Capability>,
+            ContextDriver::new(
+                contexts,
+                self.board_kernel,
+                self.board_kernel.create_grant(&grant_cap),
+                self.privileged_apps,
+                Capability
+            )
+        );
+        (contexts, context_driver)
+    }
+}
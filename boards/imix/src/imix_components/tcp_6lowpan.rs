@@ -0,0 +1,179 @@
+//! Component to initialize the tcp/6lowpan interface on imix board.
+//!
+//! This provides one Component, TCPComponent, which implements a
+//! userspace syscall interface to a TCP stack on top of 6lowpan. It shares
+//! the 6lowpan state and the receive path of the UDPComponent.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let (udp_driver, ip_recv_mux, sixlowpan_state, _ip_receive) =
+//!     UDPComponent::new(...).finalize(());
+//! let tcp_driver = TCPComponent::new(board_kernel,
+//!                                    mux_mac,
+//!                                    sixlowpan_state,
+//!                                    ip_recv_mux,
+//!                                    DST_MAC_ADDR,
+//!                                    src_mac_from_serial_num,
+//!                                    interface,
+//!                                    mux_alarm).finalize(());
+//! ```
+
+#![allow(dead_code)] // Components are intended to be conditionally included
+
+use capsules::ieee802154::device::MacDevice;
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::ip6_nh;
+use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::ipv6::ipv6_interface::IP6Interface;
+use capsules::net::ipv6::ipv6_recv::{IP6RecvMux, IP6RecvMuxClient};
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules::net::sixlowpan::sixlowpan_state;
+use capsules::net::tcp::tcp::TCPHeader;
+use capsules::net::tcp::tcp_stack::{TCPConnection, TCPStack, TCPStackStruct};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::radio;
+use kernel::hil::time::Alarm;
+use kernel::static_init;
+
+// The largest segment sent or received, the size of the largest UDP payload of
+// the UDPComponent.
+const TCP_MSS: usize = 192;
+
+// The TCP stack requires several buffers:
+//
+//   1. TCP_RF233_BUF: buffer the IP6_Sender uses to pass frames to the radio after fragmentation
+//   2. TCP_SEGMENT: The payload of the IP6_Packet, which holds the data of a segment before it is tx'd
+//   3. TCP_SEND_BUF_*, TCP_RECV_BUF_*: The send and receive buffers of each connection,
+//      which bound the windows
+//
+// Received packets are decompressed in the receive buffer of the UDPComponent.
+
+const TCP_BUF_LEN: usize = 256;
+static mut TCP_RF233_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut TCP_SEGMENT: [u8; TCP_MSS] = [0; TCP_MSS];
+static mut TCP_SEND_BUF_0: [u8; TCP_BUF_LEN] = [0; TCP_BUF_LEN];
+static mut TCP_RECV_BUF_0: [u8; TCP_BUF_LEN] = [0; TCP_BUF_LEN];
+static mut TCP_SEND_BUF_1: [u8; TCP_BUF_LEN] = [0; TCP_BUF_LEN];
+static mut TCP_RECV_BUF_1: [u8; TCP_BUF_LEN] = [0; TCP_BUF_LEN];
+
+type TCPIP6Sender = IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>;
+
+pub struct TCPComponent {
+    board_kernel: &'static kernel::Kernel,
+    mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+    sixlowpan_state: &'static dyn sixlowpan_state::SixlowpanState<'static>,
+    ip_recv_mux: &'static IP6RecvMux<'static>,
+    dst_mac_addr: MacAddress,
+    src_mac_addr: MacAddress,
+    interface: &'static IP6Interface,
+    alarm_mux: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+}
+
+impl TCPComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        sixlowpan_state: &'static dyn sixlowpan_state::SixlowpanState<'static>,
+        ip_recv_mux: &'static IP6RecvMux<'static>,
+        dst_mac_addr: MacAddress,
+        src_mac_addr: MacAddress,
+        interface: &'static IP6Interface,
+        alarm: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    ) -> TCPComponent {
+        TCPComponent {
+            board_kernel,
+            mux_mac,
+            sixlowpan_state,
+            ip_recv_mux,
+            dst_mac_addr,
+            src_mac_addr,
+            interface,
+            alarm_mux: alarm,
+        }
+    }
+}
+
+impl Component for TCPComponent {
+    type StaticInput = ();
+    type Output = &'static capsules::net::tcp::TCPDriver<'static>;
+
+    unsafe fn finalize(&mut self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let ipsender_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let tcp_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+
+        // Only used to transmit: the UDPComponent receives the frames of
+        // all protocols.
+        let tcp_mac = static_init!(
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(tcp_mac);
+
+        let sixlowpan_tx = sixlowpan_state::TxState::new(self.sixlowpan_state);
+
+        let tr_hdr = TransportHeader::TCP(TCPHeader::new());
+        let ip_pyld: IPPayload = IPPayload {
+            header: tr_hdr,
+            payload: &mut TCP_SEGMENT,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        let ip_send = static_init!(
+            TCPIP6Sender,
+            IP6SendStruct::new(
+                ip6_dg,
+                ipsender_virtual_alarm,
+                &mut TCP_RF233_BUF,
+                sixlowpan_tx,
+                tcp_mac,
+                self.dst_mac_addr,
+                self.src_mac_addr
+            )
+        );
+        ipsender_virtual_alarm.set_client(ip_send);
+        ip_send.set_interface(self.interface);
+        tcp_mac.set_transmit_client(ip_send);
+
+        let tcp_connections = static_init!(
+            [TCPConnection; 2],
+            [
+                TCPConnection::new(&mut TCP_SEND_BUF_0, &mut TCP_RECV_BUF_0),
+                TCPConnection::new(&mut TCP_SEND_BUF_1, &mut TCP_RECV_BUF_1),
+            ]
+        );
+        let tcp_stack = static_init!(
+            TCPStackStruct<'static, TCPIP6Sender, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+            TCPStackStruct::new(ip_send, tcp_virtual_alarm, tcp_connections, TCP_MSS as u16)
+        );
+        ip_send.set_client(tcp_stack);
+        tcp_virtual_alarm.set_client(tcp_stack);
+
+        let tcp_recv_mux_client = static_init!(
+            IP6RecvMuxClient<'static>,
+            IP6RecvMuxClient::new(ip6_nh::TCP, tcp_stack)
+        );
+        self.ip_recv_mux.add_client(tcp_recv_mux_client);
+
+        let tcp_driver = static_init!(
+            capsules::net::tcp::TCPDriver<'static>,
+            capsules::net::tcp::TCPDriver::new(
+                tcp_stack,
+                self.board_kernel.create_grant(&grant_cap)
+            )
+        );
+        tcp_stack.set_client(tcp_driver);
+        tcp_driver
+    }
+}
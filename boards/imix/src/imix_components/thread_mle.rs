@@ -0,0 +1,244 @@
+//! Component for the Thread syscall interface on imix board.
+//!
+//! This provides one Component, ThreadComponent, which lets apps attach the
+//! board to a Thread network as a Sleepy End Device with MLE. MLE sends its
+//! messages through an IPv6 sender of its own, from the extended address of
+//! the board, and receives them through the UDP receive path of the
+//! UDPComponent, on the MLE port. It draws its challenges from the shared
+//! TRNG, and reserves its frame counters in the kernel region of the
+//! nonvolatile storage, at `storage_address`.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let (radio_driver, mux_mac, mux_aes_ccm) = RadioComponent::new(...).finalize(());
+//! let (udp_driver, ip_recv_mux, sixlowpan_state, ip_receive, udp_recv) =
+//!     UDPComponent::new(...).finalize(());
+//! let thread_driver = ThreadComponent::new(board_kernel,
+//!                                          mux_mac,
+//!                                          sixlowpan_state,
+//!                                          udp_recv,
+//!                                          mux_aes_ccm,
+//!                                          mux_entropy,
+//!                                          nonvolatile_storage,
+//!                                          storage_address,
+//!                                          DST_MAC_ADDR,
+//!                                          eui64_from_serial_num,
+//!                                          interface,
+//!                                          mux_alarm,
+//!                                          THREAD_KEY_SEQUENCE,
+//!                                          THREAD_MLE_KEY).finalize(());
+//! ```
+
+#![allow(dead_code)] // Components are intended to be conditionally included
+
+use capsules::ieee802154::device::MacDevice;
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::ipv6::ipv6_interface::IP6Interface;
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules::net::sixlowpan::sixlowpan_state;
+use capsules::net::thread::mle::{Mle, MLE_BUF_LEN, MLE_PORT, MLE_STORAGE_LEN};
+use capsules::net::thread::ThreadDriver;
+use capsules::net::udp::udp::UDPHeader;
+use capsules::net::udp::udp_recv::{UDPKernelClient, UDPReceiver};
+use capsules::nonvolatile_storage_driver::NonvolatileStorage;
+use capsules::rng::Entropy32ToRandom;
+use capsules::virtual_aes_ccm::{MuxAES128CCM, VirtualAES128CCM};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules::virtual_entropy::{MuxEntropy32, VirtualEntropy32};
+
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::nonvolatile_storage::NonvolatileStorage as _;
+use kernel::hil::radio;
+use kernel::hil::rng::Rng;
+use kernel::hil::symmetric_encryption::{AES128CCM, AES128_KEY_SIZE};
+use kernel::hil::time::Alarm;
+use kernel::static_init;
+
+// MLE requires several buffers:
+//
+//   1. MLE_RF233_BUF: buffer the IP6_Sender uses to pass frames to the radio after fragmentation
+//   2. MLE_PAYLOAD: The payload of the IP6_Packet, which holds a message before it is tx'd
+//   3. MLE_TX_BUF and MLE_RX_BUF: buffers messages are secured in before they are sent, and
+//      after they are received
+//   4. MLE_STORAGE_BUF: buffer the reserved frame counters are read and written through
+
+static mut MLE_RF233_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+static mut MLE_PAYLOAD: [u8; MLE_BUF_LEN] = [0x00; MLE_BUF_LEN];
+static mut MLE_TX_BUF: [u8; MLE_BUF_LEN] = [0x00; MLE_BUF_LEN];
+static mut MLE_RX_BUF: [u8; MLE_BUF_LEN] = [0x00; MLE_BUF_LEN];
+static mut MLE_STORAGE_BUF: [u8; MLE_STORAGE_LEN] = [0x00; MLE_STORAGE_LEN];
+
+type AESCCMDevice = capsules::aes_ccm::AES128CCM<'static, sam4l::aes::Aes<'static>>;
+type MleIP6Sender = IP6SendStruct<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>;
+type MleDevice = Mle<
+    'static,
+    VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    MleIP6Sender,
+    VirtualAES128CCM<'static, AESCCMDevice>,
+>;
+pub type ThreadDriverDevice = ThreadDriver<
+    'static,
+    VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    MleIP6Sender,
+    VirtualAES128CCM<'static, AESCCMDevice>,
+>;
+
+pub struct ThreadComponent {
+    board_kernel: &'static kernel::Kernel,
+    mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+    sixlowpan_state: &'static dyn sixlowpan_state::SixlowpanState<'static>,
+    udp_recv: &'static UDPReceiver<'static>,
+    mux_aes_ccm: &'static MuxAES128CCM<'static, AESCCMDevice>,
+    mux_entropy: &'static MuxEntropy32<'static>,
+    storage: &'static NonvolatileStorage<'static>,
+    storage_address: usize,
+    dst_mac_addr: MacAddress,
+    eui64: [u8; 8],
+    interface: &'static IP6Interface,
+    alarm_mux: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    key_sequence: u32,
+    key: [u8; AES128_KEY_SIZE],
+}
+
+impl ThreadComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        sixlowpan_state: &'static dyn sixlowpan_state::SixlowpanState<'static>,
+        udp_recv: &'static UDPReceiver<'static>,
+        mux_aes_ccm: &'static MuxAES128CCM<'static, AESCCMDevice>,
+        mux_entropy: &'static MuxEntropy32<'static>,
+        storage: &'static NonvolatileStorage<'static>,
+        storage_address: usize,
+        dst_mac_addr: MacAddress,
+        eui64: [u8; 8],
+        interface: &'static IP6Interface,
+        alarm: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+        key_sequence: u32,
+        key: [u8; AES128_KEY_SIZE],
+    ) -> ThreadComponent {
+        ThreadComponent {
+            board_kernel,
+            mux_mac,
+            sixlowpan_state,
+            udp_recv,
+            mux_aes_ccm,
+            mux_entropy,
+            storage,
+            storage_address,
+            dst_mac_addr,
+            eui64,
+            interface,
+            alarm_mux: alarm,
+            key_sequence,
+            key,
+        }
+    }
+}
+
+impl Component for ThreadComponent {
+    type StaticInput = ();
+    type Output = &'static ThreadDriverDevice;
+
+    unsafe fn finalize(&mut self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let ipsender_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+
+        // Only used to transmit: the UDPComponent receives the frames of
+        // all protocols.
+        let mle_mac = static_init!(
+            capsules::ieee802154::virtual_mac::MacUser<'static>,
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
+        );
+        self.mux_mac.add_user(mle_mac);
+
+        let sixlowpan_tx = sixlowpan_state::TxState::new(self.sixlowpan_state);
+
+        let tr_hdr = TransportHeader::UDP(UDPHeader::new());
+        let ip_pyld: IPPayload = IPPayload {
+            header: tr_hdr,
+            payload: &mut MLE_PAYLOAD,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        // MLE messages are sent from the link-local address derived from the
+        // extended address, so that neighbors can derive it back
+        let ip_send = static_init!(
+            MleIP6Sender,
+            IP6SendStruct::new(
+                ip6_dg,
+                ipsender_virtual_alarm,
+                &mut MLE_RF233_BUF,
+                sixlowpan_tx,
+                mle_mac,
+                self.dst_mac_addr,
+                MacAddress::Long(self.eui64)
+            )
+        );
+        ipsender_virtual_alarm.set_client(ip_send);
+        ip_send.set_interface(self.interface);
+        mle_mac.set_transmit_client(ip_send);
+
+        let mle_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let mle_aes_ccm = static_init!(
+            VirtualAES128CCM<'static, AESCCMDevice>,
+            VirtualAES128CCM::new(self.mux_aes_ccm)
+        );
+
+        let mle_entropy = static_init!(
+            VirtualEntropy32<'static>,
+            VirtualEntropy32::new(self.mux_entropy)
+        );
+        let mle_rng = static_init!(
+            Entropy32ToRandom<'static>,
+            Entropy32ToRandom::new(mle_entropy)
+        );
+
+        let mle = static_init!(
+            MleDevice,
+            Mle::new(
+                ip_send,
+                self.interface,
+                mle_virtual_alarm,
+                mle_aes_ccm,
+                mle_rng,
+                self.storage,
+                self.storage_address,
+                self.eui64,
+                &mut MLE_TX_BUF,
+                &mut MLE_RX_BUF,
+                &mut MLE_STORAGE_BUF
+            )
+        );
+        mle.set_key(self.key_sequence, self.key);
+        ip_send.set_client(mle);
+        mle_virtual_alarm.set_client(mle);
+        mle_aes_ccm.set_client(mle);
+        mle_rng.set_client(mle);
+        self.storage.set_client(mle);
+
+        let mle_udp_client = static_init!(
+            UDPKernelClient<'static>,
+            UDPKernelClient::new(MLE_PORT, mle)
+        );
+        self.udp_recv.add_kernel_client(mle_udp_client);
+
+        let thread_driver = static_init!(
+            ThreadDriverDevice,
+            ThreadDriver::new(mle, self.board_kernel.create_grant(&grant_cap))
+        );
+        mle.set_client(thread_driver);
+        thread_driver
+    }
+}
@@ -6,12 +6,24 @@
 //! Usage
 //! -----
 //! ```rust
-//! let udp_driver = UDPComponent::new(mux_mac,
-//!                                    DEFAULT_CTX_PREFIX_LEN,
-//!                                    DEFAULT_CTX_PREFIX,
-//!                                    DST_MAC_ADDR,
-//!                                    &LOCAL_IP_IFACES).finalize(());
+//! let (udp_driver, ip_recv_mux, sixlowpan_state, ip_receive, udp_recv) =
+//!     UDPComponent::new(mux_mac,
+//!                       contexts,
+//!                       DST_MAC_ADDR,
+//!                       src_mac_from_serial_num,
+//!                       interface,
+//!                       mux_alarm).finalize(());
 //! ```
+//!
+//! The 6LoWPAN layer compresses packets against the contexts of `contexts`,
+//! which may change at runtime.
+//!
+//! The `IP6RecvMux` and the 6LoWPAN state are returned so that other
+//! transport protocols, like TCP, can share the receive path. The receive path
+//! only passes up the packets sent to the addresses of the interface, and the
+//! `IP6RecvStruct` is returned so that a router can forward the others. The
+//! `UDPReceiver` is returned so that in-kernel protocols, like MLE, can bind
+//! to their ports.
 
 // Author: Hudson Ayers <hayers@stanford.edu>
 // Last Modified: 8/26/2018
@@ -20,11 +32,13 @@
 
 use capsules::ieee802154::device::MacDevice;
 use capsules::net::ieee802154::MacAddress;
-use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ip_utils::ip6_nh;
 use capsules::net::ipv6::ipv6::{IP6Packet, IPPayload, TransportHeader};
-use capsules::net::ipv6::ipv6_recv::IP6Receiver;
+use capsules::net::ipv6::ipv6_interface::IP6Interface;
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvMux, IP6RecvMuxClient, IP6RecvStruct};
 use capsules::net::ipv6::ipv6_send::IP6Sender;
-use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
+use capsules::net::sixlowpan::sixlowpan_context::ContextTable;
+use capsules::net::sixlowpan::sixlowpan_state;
 use capsules::net::udp::udp::UDPHeader;
 use capsules::net::udp::udp_recv::UDPReceiver;
 use capsules::net::udp::udp_send::{UDPSendStruct, UDPSender};
@@ -42,22 +56,23 @@ const PAYLOAD_LEN: usize = 200; //The max size UDP message that can be sent by u
 // The UDP stack requires several packet buffers:
 //
 //   1. RF233_BUF: buffer the IP6_Sender uses to pass frames to the radio after fragmentation
-//   2. SIXLOWPAN_RX_BUF: Buffer to hold full IP packets after they are decompressed by 6LoWPAN
+//   2. SIXLOWPAN_RX_BUF_*: Buffers to hold full IP packets after they are decompressed by 6LoWPAN,
+//      one for each packet that can be reassembled at the same time
 //   3. UDP_DGRAM: The payload of the IP6_Packet, which holds full IP Packets before they are tx'd
 
 const UDP_HDR_SIZE: usize = 8;
 static mut RF233_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
-static mut SIXLOWPAN_RX_BUF: [u8; 1280] = [0x00; 1280];
+static mut SIXLOWPAN_RX_BUF_0: [u8; 1280] = [0x00; 1280];
+static mut SIXLOWPAN_RX_BUF_1: [u8; 1280] = [0x00; 1280];
 static mut UDP_DGRAM: [u8; PAYLOAD_LEN - UDP_HDR_SIZE] = [0; PAYLOAD_LEN - UDP_HDR_SIZE];
 
 pub struct UDPComponent {
     board_kernel: &'static kernel::Kernel,
     mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
-    ctx_pfix_len: u8,
-    ctx_pfix: [u8; 16],
+    contexts: &'static ContextTable<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
     dst_mac_addr: MacAddress,
     src_mac_addr: MacAddress,
-    interface_list: &'static [IPAddr],
+    interface: &'static IP6Interface,
     alarm_mux: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
 }
 
@@ -65,21 +80,22 @@ impl UDPComponent {
     pub fn new(
         board_kernel: &'static kernel::Kernel,
         mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
-        ctx_pfix_len: u8,
-        ctx_pfix: [u8; 16],
+        contexts: &'static ContextTable<
+            'static,
+            VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+        >,
         dst_mac_addr: MacAddress,
         src_mac_addr: MacAddress,
-        interface_list: &'static [IPAddr],
+        interface: &'static IP6Interface,
         alarm: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
     ) -> UDPComponent {
         UDPComponent {
             board_kernel: board_kernel,
             mux_mac: mux_mac,
-            ctx_pfix_len: ctx_pfix_len,
-            ctx_pfix: ctx_pfix,
+            contexts: contexts,
             dst_mac_addr: dst_mac_addr,
             src_mac_addr: src_mac_addr,
-            interface_list: interface_list,
+            interface: interface,
             alarm_mux: alarm,
         }
     }
@@ -87,7 +103,13 @@ impl UDPComponent {
 
 impl Component for UDPComponent {
     type StaticInput = ();
-    type Output = &'static capsules::net::udp::UDPDriver<'static>;
+    type Output = (
+        &'static capsules::net::udp::UDPDriver<'static>,
+        &'static IP6RecvMux<'static>,
+        &'static dyn sixlowpan_state::SixlowpanState<'static>,
+        &'static IP6RecvStruct<'static>,
+        &'static UDPReceiver<'static>,
+    );
 
     unsafe fn finalize(&mut self, _s: Self::StaticInput) -> Self::Output {
         let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
@@ -102,30 +124,34 @@ impl Component for UDPComponent {
         );
         self.mux_mac.add_user(udp_mac);
 
+        let sixlowpan_virtual_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+
         let sixlowpan = static_init!(
             sixlowpan_state::Sixlowpan<
                 'static,
-                sam4l::ast::Ast<'static>,
-                sixlowpan_compression::Context,
+                VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+                &'static ContextTable<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>,
             >,
-            sixlowpan_state::Sixlowpan::new(
-                sixlowpan_compression::Context {
-                    prefix: self.ctx_pfix,
-                    prefix_len: self.ctx_pfix_len,
-                    id: 0,
-                    compress: false,
-                },
-                &sam4l::ast::AST
-            )
+            sixlowpan_state::Sixlowpan::new(self.contexts, sixlowpan_virtual_alarm)
         );
+        // Times out the reassembly of packets whose fragments were lost
+        sixlowpan_virtual_alarm.set_client(sixlowpan);
 
         let sixlowpan_state = sixlowpan as &dyn sixlowpan_state::SixlowpanState;
         let sixlowpan_tx = sixlowpan_state::TxState::new(sixlowpan_state);
-        let default_rx_state = static_init!(
-            sixlowpan_state::RxState<'static>,
-            sixlowpan_state::RxState::new(&mut SIXLOWPAN_RX_BUF)
+        let rx_states = static_init!(
+            [sixlowpan_state::RxState<'static>; 2],
+            [
+                sixlowpan_state::RxState::new(&mut SIXLOWPAN_RX_BUF_0),
+                sixlowpan_state::RxState::new(&mut SIXLOWPAN_RX_BUF_1),
+            ]
         );
-        sixlowpan_state.add_rx_state(default_rx_state);
+        for rx_state in rx_states.iter() {
+            sixlowpan_state.add_rx_state(rx_state);
+        }
         udp_mac.set_receive_client(sixlowpan);
 
         let tr_hdr = TransportHeader::UDP(UDPHeader::new());
@@ -152,9 +178,10 @@ impl Component for UDPComponent {
         );
         ipsender_virtual_alarm.set_client(ip_send);
 
-        // Set src IP of the sender to be the address configured via the sam4l.
-        // Userland apps can change this if they so choose.
-        ip_send.set_addr(self.interface_list[2]);
+        // The source address and the next hop of each packet are picked from
+        // the addresses and routers of the interface, which change as
+        // Neighbor Discovery configures them.
+        ip_send.set_interface(self.interface);
         udp_mac.set_transmit_client(ip_send);
 
         let udp_send = static_init!(
@@ -169,14 +196,20 @@ impl Component for UDPComponent {
         );
         ip_send.set_client(udp_send);
 
-        let ip_receive = static_init!(
-            capsules::net::ipv6::ipv6_recv::IP6RecvStruct<'static>,
-            capsules::net::ipv6::ipv6_recv::IP6RecvStruct::new()
-        );
+        let ip_receive = static_init!(IP6RecvStruct<'static>, IP6RecvStruct::new());
+        ip_receive.set_interface(self.interface);
         sixlowpan_state.set_rx_client(ip_receive);
 
+        let ip_recv_mux = static_init!(IP6RecvMux<'static>, IP6RecvMux::new());
+        ip_receive.set_client(ip_recv_mux);
+
         let udp_recv = static_init!(UDPReceiver<'static>, UDPReceiver::new());
-        ip_receive.set_client(udp_recv);
+        let udp_recv_mux_client = static_init!(
+            IP6RecvMuxClient<'static>,
+            IP6RecvMuxClient::new(ip6_nh::UDP, udp_recv)
+        );
+        ip_recv_mux.add_client(udp_recv_mux_client);
+        udp_recv.set_error_reporter(ip_recv_mux);
 
         let udp_driver = static_init!(
             capsules::net::udp::UDPDriver<'static>,
@@ -184,12 +217,20 @@ impl Component for UDPComponent {
                 udp_send,
                 udp_recv,
                 self.board_kernel.create_grant(&grant_cap),
-                self.interface_list,
+                self.interface,
                 PAYLOAD_LEN
             )
         );
         udp_send.set_client(udp_driver);
         udp_recv.set_client(udp_driver);
-        udp_driver
+        udp_recv.port_table().set_user(udp_driver);
+        udp_driver.set_sixlowpan_state(sixlowpan_state);
+        (
+            udp_driver,
+            ip_recv_mux,
+            sixlowpan_state,
+            ip_receive,
+            udp_recv,
+        )
     }
 }
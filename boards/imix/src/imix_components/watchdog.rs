@@ -0,0 +1,68 @@
+//! Component for the watchdog service on imix board.
+//!
+//! This provides one Component, WatchdogComponent, which makes the kernel
+//! loop tickle the SAM4L watchdog, and lets apps register as supervised.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let watchdog = WatchdogComponent::new(board_kernel, mux_alarm, 1000).finalize(());
+//! ```
+
+#![allow(dead_code)] // Components are intended to be conditionally included
+
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules::watchdog::WatchdogService;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::time::Alarm;
+use kernel::static_init;
+
+pub type WatchdogDevice =
+    WatchdogService<'static, sam4l::wdt::Wdt, VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>>;
+
+pub struct WatchdogComponent {
+    board_kernel: &'static kernel::Kernel,
+    alarm_mux: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    period_ms: usize,
+}
+
+impl WatchdogComponent {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        alarm: &'static MuxAlarm<'static, sam4l::ast::Ast<'static>>,
+        period_ms: usize,
+    ) -> WatchdogComponent {
+        WatchdogComponent {
+            board_kernel,
+            alarm_mux: alarm,
+            period_ms,
+        }
+    }
+}
+
+impl Component for WatchdogComponent {
+    type StaticInput = ();
+    type Output = &'static WatchdogDevice;
+
+    unsafe fn finalize(&mut self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let watchdog_alarm = static_init!(
+            VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        let watchdog = static_init!(
+            WatchdogDevice,
+            WatchdogService::new(
+                &sam4l::wdt::WDT,
+                watchdog_alarm,
+                self.period_ms,
+                self.board_kernel.create_grant(&grant_cap)
+            )
+        );
+        watchdog_alarm.set_client(watchdog);
+        watchdog
+    }
+}
@@ -12,6 +12,8 @@ mod imix_components;
 use capsules::alarm::AlarmDriver;
 use capsules::net::ieee802154::MacAddress;
 use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_interface::IP6Interface;
+use capsules::net::sixlowpan::sixlowpan_compression::Context;
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use capsules::virtual_i2c::MuxI2C;
 use capsules::virtual_spi::{MuxSpiMaster, VirtualSpiMasterDevice};
@@ -41,14 +43,21 @@ use imix_components::adc::AdcComponent;
 use imix_components::analog_comparator::AcComponent;
 use imix_components::button::ButtonComponent;
 use imix_components::clock_pm::ClockManagerComponent;
+use imix_components::coap::CoapComponent;
 use imix_components::fxos8700::NineDofComponent;
 use imix_components::gpio::GpioComponent;
 use imix_components::led::LedComponent;
 use imix_components::nonvolatile_storage::NonvolatileStorageComponent;
 use imix_components::radio::RadioComponent;
 use imix_components::rf233::RF233Component;
+use imix_components::sixlowpan_context::SixlowpanContextComponent;
+use imix_components::icmp_6lowpan::ICMP6Component;
+use imix_components::ipv6_forward::IP6ForwardComponent;
+use imix_components::tcp_6lowpan::TCPComponent;
+use imix_components::thread_mle::ThreadComponent;
 use imix_components::udp_6lowpan::UDPComponent;
 use imix_components::usb::UsbComponent;
+use imix_components::watchdog::WatchdogComponent;
 
 /// Support routines for debugging I/O.
 ///
@@ -99,6 +108,17 @@ const DST_MAC_ADDR: MacAddress = MacAddress::Short(49138);
 const DEFAULT_CTX_PREFIX_LEN: u8 = 8; //Length of context for 6LoWPAN compression
 const DEFAULT_CTX_PREFIX: [u8; 16] = [0x0 as u8; 16]; //Context for 6LoWPAN Compression
 const PAN_ID: u16 = 0xABCD;
+// The MLE key of the Thread network, derived for key sequence 0 from the
+// master key 00112233445566778899aabbccddeeff
+const THREAD_KEY_SEQUENCE: u32 = 0;
+const THREAD_MLE_KEY: [u8; 16] = [
+    0x54, 0x45, 0xf4, 0x15, 0x8f, 0xd7, 0x59, 0x12, 0x17, 0x58, 0x09, 0xf8, 0xb5, 0x7a, 0x66, 0xa4,
+];
+// Holds the frame counters MLE reserved, in the kernel region of the
+// nonvolatile storage
+mod storage {
+    kernel::storage_volume!(THREAD_MLE, 1);
+}
 
 // how should the kernel respond when a process faults
 const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
@@ -138,6 +158,16 @@ struct Imix {
     ninedof: &'static capsules::ninedof::NineDof<'static>,
     radio_driver: &'static capsules::ieee802154::RadioDriver<'static>,
     udp_driver: &'static capsules::net::udp::UDPDriver<'static>,
+    tcp_driver: &'static capsules::net::tcp::TCPDriver<'static>,
+    icmp6_driver: &'static capsules::net::icmpv6::ICMP6Driver<'static>,
+    sixlowpan_context_driver: &'static capsules::net::sixlowpan::ContextDriver<
+        'static,
+        VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+        imix_components::sixlowpan_context::Capability,
+    >,
+    thread_driver: &'static imix_components::thread_mle::ThreadDriverDevice,
+    coap_driver: &'static imix_components::coap::CoapDriverDevice,
+    watchdog: &'static imix_components::watchdog::WatchdogDevice,
     //crc: &'static capsules::crc::Crc<'static, sam4l::crccu::Crccu<'static>>,
     //usb_driver: &'static capsules::usb::usb_user::UsbSyscallDriver<
     //    'static,
@@ -182,13 +212,23 @@ impl kernel::Platform for Imix {
             //capsules::usb::usb_user::DRIVER_NUM => f(Some(self.usb_driver)),
             capsules::ieee802154::DRIVER_NUM => f(Some(self.radio_driver)),
             capsules::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
+            capsules::net::tcp::DRIVER_NUM => f(Some(self.tcp_driver)),
+            capsules::net::icmpv6::DRIVER_NUM => f(Some(self.icmp6_driver)),
+            capsules::net::sixlowpan::DRIVER_NUM => f(Some(self.sixlowpan_context_driver)),
+            capsules::net::thread::DRIVER_NUM => f(Some(self.thread_driver)),
+            capsules::net::coap::DRIVER_NUM => f(Some(self.coap_driver)),
             //capsules::nrf51822_serialization::DRIVER_NUM => f(Some(self.nrf51822)),
             capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
+            capsules::watchdog::DRIVER_NUM => f(Some(self.watchdog)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             _ => f(None),
         }
     }
+
+    fn watchdog(&self) -> &dyn kernel::watchdog::WatchDog {
+        self.watchdog
+    }
 }
 
 unsafe fn set_pin_primary_functions() {
@@ -343,6 +383,9 @@ pub unsafe fn reset_handler() {
     let alarm = AlarmDriverComponent::new(board_kernel, mux_alarm)
         .finalize(components::alarm_component_helper!(sam4l::ast::Ast));
 
+    // Reset if the kernel does not loop for one second
+    let watchdog = WatchdogComponent::new(board_kernel, mux_alarm, 1000).finalize(());
+
     // # I2C and I2C Sensors
     let mux_i2c = static_init!(MuxI2C<'static>, MuxI2C::new(&sam4l::i2c::I2C2));
     sam4l::i2c::I2C2.set_master_client(mux_i2c);
@@ -381,7 +424,17 @@ pub unsafe fn reset_handler() {
     //let crc = CrcComponent::new(board_kernel, &sam4l::crccu::CRCCU)
     //    .finalize(components::crc_component_helper!(sam4l::crccu::Crccu));
     //let analog_comparator = AcComponent::new().finalize(());
-    let rng = RngComponent::new(board_kernel, &sam4l::trng::TRNG).finalize(());
+    // The TRNG is shared by the RNG driver, the XMAC, MLE and CoAP
+    let mux_entropy = static_init!(
+        capsules::virtual_entropy::MuxEntropy32<'static>,
+        capsules::virtual_entropy::MuxEntropy32::new(&sam4l::trng::TRNG)
+    );
+    kernel::hil::entropy::Entropy32::set_client(&sam4l::trng::TRNG, mux_entropy);
+    let rng_entropy = static_init!(
+        capsules::virtual_entropy::VirtualEntropy32<'static>,
+        capsules::virtual_entropy::VirtualEntropy32::new(mux_entropy)
+    );
+    let rng = RngComponent::new(board_kernel, rng_entropy).finalize(());
 
     // For now, assign the 802.15.4 MAC address on the device as
     // simply a 16-bit short address which represents the last 16 bits
@@ -391,38 +444,117 @@ pub unsafe fn reset_handler() {
     let serial_num: sam4l::serial_num::SerialNum = sam4l::serial_num::SerialNum::new();
     let serial_num_bottom_16 = (serial_num.get_lower_64() & 0x0000_0000_0000_ffff) as u16;
     let src_mac_from_serial_num: MacAddress = MacAddress::Short(serial_num_bottom_16);
+    // The extended address, from which Neighbor Discovery derives the
+    // addresses it configures, is the lower 64 bits of the serial number.
+    let eui64_from_serial_num: [u8; 8] = serial_num.get_lower_64().to_be_bytes();
 
     // Can this initialize be pushed earlier, or into component? -pal
     rf233.initialize(&mut RF233_BUF, &mut RF233_REG_WRITE, &mut RF233_REG_READ);
-    let (radio_driver, mux_mac) =
-        RadioComponent::new(board_kernel, rf233, mux_alarm, PAN_ID, serial_num_bottom_16).finalize(());
+    let (radio_driver, mux_mac, mux_aes_ccm) =
+        RadioComponent::new(board_kernel, rf233, mux_alarm, mux_entropy, PAN_ID, serial_num_bottom_16, eui64_from_serial_num).finalize(());
 
     //let usb_driver = UsbComponent::new(board_kernel).finalize(());
     let nonvolatile_storage = NonvolatileStorageComponent::new(board_kernel).finalize(());
 
-    let local_ip_ifaces = static_init!(
-        [IPAddr; 3],
-        [
-            IPAddr([
-                0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
-                0x0e, 0x0f,
-            ]),
-            IPAddr([
-                0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d,
-                0x1e, 0x1f,
-            ]),
-            IPAddr::generate_from_mac(src_mac_from_serial_num),
-        ]
+    // Neighbor Discovery adds the addresses it configures, and the default
+    // router, to the interface.
+    let interface = static_init!(IP6Interface, IP6Interface::new());
+    interface.add_addr(IPAddr::generate_from_mac(src_mac_from_serial_num), None);
+
+    // The default context, to which Neighbor Discovery adds the contexts
+    // routers advertise. Only the "netconfig" app may change them.
+    let (contexts, sixlowpan_context_driver) =
+        SixlowpanContextComponent::new(board_kernel, mux_alarm, &["netconfig"]).finalize(());
+    contexts.set_context(
+        Context {
+            prefix: DEFAULT_CTX_PREFIX,
+            prefix_len: DEFAULT_CTX_PREFIX_LEN,
+            id: 0,
+            compress: false,
+        },
+        None,
     );
 
-    let udp_driver = UDPComponent::new(
+    let (udp_driver, ip_recv_mux, sixlowpan_state, ip_receive, udp_recv) = UDPComponent::new(
+        board_kernel,
+        mux_mac,
+        contexts,
+        DST_MAC_ADDR,
+        src_mac_from_serial_num,
+        interface,
+        mux_alarm,
+    )
+    .finalize(());
+
+    let icmp6_driver = ICMP6Component::new(
+        board_kernel,
+        mux_mac,
+        sixlowpan_state,
+        ip_recv_mux,
+        DST_MAC_ADDR,
+        src_mac_from_serial_num,
+        interface,
+        contexts,
+        mux_alarm,
+    )
+    .finalize(());
+
+    let tcp_driver = TCPComponent::new(
+        board_kernel,
+        mux_mac,
+        sixlowpan_state,
+        ip_recv_mux,
+        DST_MAC_ADDR,
+        src_mac_from_serial_num,
+        interface,
+        mux_alarm,
+    )
+    .finalize(());
+
+    // Forwards the packets of the other nodes of the mesh along the routes
+    // RPL adds to the interface.
+    IP6ForwardComponent::new(
+        mux_mac,
+        sixlowpan_state,
+        ip_receive,
+        ip_recv_mux,
+        DST_MAC_ADDR,
+        src_mac_from_serial_num,
+        interface,
+        mux_alarm,
+    )
+    .finalize(());
+
+    // Attaches to a Thread network as a sleepy end device, when an app asks
+    let thread_driver = ThreadComponent::new(
+        board_kernel,
+        mux_mac,
+        sixlowpan_state,
+        udp_recv,
+        mux_aes_ccm,
+        mux_entropy,
+        nonvolatile_storage,
+        &storage::THREAD_MLE as *const [u8] as *const u8 as usize,
+        DST_MAC_ADDR,
+        eui64_from_serial_num,
+        interface,
+        mux_alarm,
+        THREAD_KEY_SEQUENCE,
+        THREAD_MLE_KEY,
+    )
+    .finalize(());
+
+    // Serves the CoAP resources of apps, and sends their CoAP requests
+    let coap_driver = CoapComponent::new(
         board_kernel,
         mux_mac,
-        DEFAULT_CTX_PREFIX_LEN,
-        DEFAULT_CTX_PREFIX,
+        sixlowpan_state,
+        udp_recv,
+        mux_entropy,
         DST_MAC_ADDR,
         src_mac_from_serial_num,
-        local_ip_ifaces,
+        eui64_from_serial_num,
+        interface,
         mux_alarm,
     )
     .finalize(());
@@ -455,6 +587,12 @@ pub unsafe fn reset_handler() {
         ninedof,
         radio_driver,
         udp_driver,
+        tcp_driver,
+        icmp6_driver,
+        sixlowpan_context_driver,
+        thread_driver,
+        coap_driver,
+        watchdog,
         //usb_driver,
         //nrf51822: nrf_serialization,
         nonvolatile_storage: nonvolatile_storage,
@@ -233,6 +233,7 @@ pub unsafe fn reset_handler() {
     //         &mut capsules::process_console::WRITE_BUF,
     //         &mut capsules::process_console::READ_BUF,
     //         &mut capsules::process_console::COMMAND_BUF,
+    //         &mut capsules::process_console::HISTORY_BUF,
     //         board_kernel,
     //         ProcessConsoleCapability,
     //     )
@@ -0,0 +1,38 @@
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use cortexm3;
+use kernel::debug;
+use qemu_mps2_an385;
+
+use crate::PROCESSES;
+
+struct Writer {}
+
+static mut WRITER: Writer = Writer {};
+
+impl Write for Writer {
+    fn write_str(&mut self, s: &str) -> ::core::fmt::Result {
+        unsafe {
+            qemu_mps2_an385::uart::UART0.transmit_sync(s.as_bytes());
+        }
+        Ok(())
+    }
+}
+
+/// Panic handler.
+///
+/// There are no LEDs to blink, so after printing the panic this exits QEMU
+/// with a failure status instead.
+#[cfg(not(test))]
+#[no_mangle]
+#[panic_handler]
+pub unsafe extern "C" fn panic_fmt(pi: &PanicInfo) -> ! {
+    let writer = &mut WRITER;
+
+    debug::panic_begin(&cortexm3::support::nop);
+    debug::panic_banner(writer, pi);
+    debug::flush(writer);
+    debug::panic_process_info(&PROCESSES, writer);
+
+    qemu_mps2_an385::exit::failure()
+}
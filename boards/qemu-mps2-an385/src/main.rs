@@ -0,0 +1,168 @@
+//! Board file for the QEMU `mps2-an385` machine.
+//!
+//! - <https://developer.arm.com/tools-and-software/development-boards/fpga-prototyping-boards/mps2>
+//!
+//! This board runs Tock and its apps in QEMU on an emulated Cortex-M3. It is
+//! meant for testing the kernel without hardware, see `tests/`.
+
+#![no_std]
+#![no_main]
+#![feature(asm)]
+
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil;
+use kernel::Platform;
+use kernel::{create_capability, debug, static_init};
+use qemu_mps2_an385::chip::QemuMps2An385;
+use qemu_mps2_an385::timer::CmsdkAlarm;
+
+pub mod io;
+
+// Actual memory for holding the active process structures.
+static mut PROCESSES: [Option<&'static dyn kernel::procs::ProcessType>; 4] =
+    [None, None, None, None];
+
+// How should the kernel respond when a process faults. Restarting the process
+// lets tests check that the kernel recovers from a faulting app.
+const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Restart;
+
+// RAM to be shared by all application processes.
+#[link_section = ".app_memory"]
+static mut APP_MEMORY: [u8; 0x10000] = [0; 0x10000];
+
+// How many times each of the 32 interrupts of the chip was serviced, for the
+// process console.
+static mut INTERRUPT_COUNTS: [u32; 32] = [0; 32];
+
+/// Dummy buffer that causes the linker to reserve enough space for the stack.
+#[no_mangle]
+#[link_section = ".stack_buffer"]
+pub static mut STACK_MEMORY: [u8; 0x1000] = [0; 0x1000];
+
+/// A structure representing this platform that holds references to all
+/// capsules for this platform.
+struct QemuMps2An385Platform {
+    console: &'static capsules::console::Console<'static>,
+    alarm: &'static capsules::alarm::AlarmDriver<
+        'static,
+        VirtualMuxAlarm<'static, CmsdkAlarm<'static>>,
+    >,
+    ipc: kernel::ipc::IPC,
+}
+
+/// Mapping of integer syscalls to objects that implement syscalls.
+impl Platform for QemuMps2An385Platform {
+    fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn kernel::Driver>) -> R,
+    {
+        match driver_num {
+            capsules::console::DRIVER_NUM => f(Some(self.console)),
+            capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
+            kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
+            _ => f(None),
+        }
+    }
+}
+
+/// Reset the board, for the `reboot` command of the process console.
+fn reboot() -> ! {
+    unsafe {
+        cortexm3::scb::reset();
+    }
+    loop {}
+}
+
+/// Reset Handler.
+///
+/// This symbol is loaded into vector table by the chip crate. When the chip
+/// first powers on or later does a hard reset, after the core initializes all
+/// the hardware, the address of this function is loaded and execution begins
+/// here.
+#[no_mangle]
+pub unsafe fn reset_handler() {
+    qemu_mps2_an385::init();
+
+    let process_mgmt_cap = create_capability!(capabilities::ProcessManagementCapability);
+    let memory_allocation_cap = create_capability!(capabilities::MemoryAllocationCapability);
+    let main_loop_cap = create_capability!(capabilities::MainLoopCapability);
+
+    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
+
+    let chip = static_init!(QemuMps2An385, QemuMps2An385::new());
+    chip.enable_interrupts();
+
+    let interrupt_counter = static_init!(
+        cortexm3::nvic::InterruptCounter,
+        cortexm3::nvic::InterruptCounter::new(&mut INTERRUPT_COUNTS)
+    );
+    board_kernel.set_interrupt_counter(interrupt_counter);
+
+    // Create a shared UART channel for the console and for kernel debug.
+    let uart_mux =
+        components::console::UartMuxComponent::new(&qemu_mps2_an385::uart::UART0, 115200)
+            .finalize(());
+
+    // Setup the console.
+    let console = components::console::ConsoleComponent::new(board_kernel, uart_mux).finalize(());
+    // Create the debugger object that handles calls to `debug!()`.
+    components::debug_writer::DebugWriterComponent::new(uart_mux).finalize(());
+    // Create the process console, which reads commands from the terminal.
+    let process_console =
+        components::process_console::ProcessConsoleComponent::new(board_kernel, uart_mux)
+            .finalize(());
+    process_console.set_reset_function(reboot);
+
+    // Create a shared virtualization mux layer on top of a single hardware
+    // alarm.
+    let alarm = &qemu_mps2_an385::timer::ALARM;
+    alarm.start();
+    let mux_alarm = static_init!(MuxAlarm<'static, CmsdkAlarm>, MuxAlarm::new(alarm));
+    hil::time::Alarm::set_client(alarm, mux_alarm);
+    kernel::debug::set_log_clock(alarm);
+
+    let alarm = components::alarm::AlarmDriverComponent::new(board_kernel, mux_alarm)
+        .finalize(components::alarm_component_helper!(CmsdkAlarm));
+
+    // Let IPC messages time out.
+    let ipc_alarm = static_init!(
+        VirtualMuxAlarm<'static, CmsdkAlarm>,
+        VirtualMuxAlarm::new(mux_alarm)
+    );
+    let ipc_timer = static_init!(
+        capsules::ipc_timer::IpcTimer<'static, VirtualMuxAlarm<'static, CmsdkAlarm>>,
+        capsules::ipc_timer::IpcTimer::new(ipc_alarm)
+    );
+    hil::time::Alarm::set_client(ipc_alarm, ipc_timer);
+
+    let platform = QemuMps2An385Platform {
+        console: console,
+        alarm: alarm,
+        ipc: kernel::ipc::IPC::new(board_kernel, &memory_allocation_cap),
+    };
+    platform.ipc.set_timer(ipc_timer);
+
+    debug!("QEMU MPS2 AN385 initialization complete. Entering main loop");
+    process_console.start();
+
+    extern "C" {
+        /// Beginning of the ROM region containing app images.
+        ///
+        /// This symbol is defined in the linker script.
+        static _sapps: u8;
+    }
+
+    kernel::procs::load_processes(
+        board_kernel,
+        chip,
+        &_sapps as *const u8,
+        &mut APP_MEMORY,
+        &mut PROCESSES,
+        FAULT_RESPONSE,
+        &process_mgmt_cap,
+    );
+
+    board_kernel.kernel_loop(&platform, chip, Some(&platform.ipc), &main_loop_cap, chip);
+}
@@ -0,0 +1,8 @@
+// Print a line and exit. Checks that processes load and can use the console.
+
+#include <stdio.h>
+
+int main(void) {
+  printf("console: hello\n");
+  return 0;
+}
@@ -0,0 +1,11 @@
+// Print a counter every 100 ms.
+
+#include <stdio.h>
+#include <timer.h>
+
+int main(void) {
+  for (int i = 1; ; i++) {
+    delay_ms(100);
+    printf("counter: %d\n", i);
+  }
+}
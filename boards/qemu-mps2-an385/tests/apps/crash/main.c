@@ -0,0 +1,14 @@
+// Write to kernel memory, which the MPU does not allow. The board restarts
+// faulting processes, so this prints "started" again after each fault.
+
+#include <stdio.h>
+
+// Start of RAM, which holds the kernel stack.
+#define KERNEL_RAM ((volatile int*) 0x20000000)
+
+int main(void) {
+  printf("crash: started\n");
+  *KERNEL_RAM = 0;
+  printf("crash: not stopped\n");
+  return 0;
+}
@@ -0,0 +1,50 @@
+// IPC client of `ipc_service`. Shares a buffer with the service, asks it to
+// increment each byte and checks the result.
+
+#include <ipc.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <timer.h>
+
+#define BUFFER_LEN 64
+
+// The MPU requires shared buffers to be aligned to their size.
+static uint8_t buffer[BUFFER_LEN] __attribute__ ((aligned(BUFFER_LEN)));
+static bool done = false;
+
+static void ipc_callback(__attribute__ ((unused)) int pid,
+                         __attribute__ ((unused)) int len,
+                         __attribute__ ((unused)) int arg2,
+                         __attribute__ ((unused)) void* ud) {
+  done = true;
+}
+
+int main(void) {
+  // Give the service time to register its callback.
+  delay_ms(100);
+
+  int service = ipc_discover("ipc_service");
+  if (service < 0) {
+    printf("ipc_client: discover failed (%d)\n", service);
+    return -1;
+  }
+
+  for (int i = 0; i < BUFFER_LEN; i++) {
+    buffer[i] = i;
+  }
+
+  ipc_register_client_cb(service, ipc_callback, NULL);
+  ipc_share(service, buffer, BUFFER_LEN);
+  ipc_notify_svc(service);
+  yield_for(&done);
+
+  for (int i = 0; i < BUFFER_LEN; i++) {
+    if (buffer[i] != i + 1) {
+      printf("ipc_client: byte %d is %d, expected %d\n", i, buffer[i], i + 1);
+      return -1;
+    }
+  }
+  printf("ipc_client: ok\n");
+  return 0;
+}
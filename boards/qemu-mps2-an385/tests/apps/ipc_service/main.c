@@ -0,0 +1,20 @@
+// IPC service that adds one to each byte of the buffer a client shares with
+// it, then notifies the client.
+
+#include <ipc.h>
+#include <stdint.h>
+#include <stdio.h>
+
+static void ipc_callback(int pid, int len, int buf, __attribute__ ((unused)) void* ud) {
+  uint8_t* buffer = (uint8_t*) buf;
+  for (int i = 0; i < len; i++) {
+    buffer[i]++;
+  }
+  ipc_notify_client(pid);
+}
+
+int main(void) {
+  ipc_register_svc_callback(ipc_callback, NULL);
+  printf("ipc_service: ready\n");
+  return 0;
+}
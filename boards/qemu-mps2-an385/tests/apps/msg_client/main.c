@@ -0,0 +1,70 @@
+// Client of `msg_service`. Sends a message and checks the reply, then sends a
+// message the service ignores and checks that it times out.
+
+#include <ipc.h>
+#include <stdbool.h>
+#include <stdio.h>
+#include <string.h>
+#include <timer.h>
+#include <tock.h>
+
+#define IPC_DRIVER     0x10000
+#define REPLY_CALLBACK 0xfffffffe
+#define MESSAGE_BUFFER 0xffffffff
+#define CALL           2
+#define SET_TIMEOUT    4
+
+static char buffer[32];
+static bool done;
+static int reply_len;
+static int reply_result;
+
+static void reply_callback(__attribute__ ((unused)) int service, int len, int result,
+                           __attribute__ ((unused)) void* ud) {
+  reply_len    = len;
+  reply_result = result;
+  done         = true;
+}
+
+// Send `message` to `service` and wait for the reply.
+static int call(int service, const char* message) {
+  int len = strlen(message);
+  memcpy(buffer, message, len);
+  done = false;
+  int ret = command(IPC_DRIVER, service, CALL, len);
+  if (ret < 0) {
+    return ret;
+  }
+  yield_for(&done);
+  return reply_result;
+}
+
+int main(void) {
+  // Give the service time to register its callback.
+  delay_ms(100);
+
+  int service = ipc_discover("msg_service");
+  if (service < 0) {
+    printf("msg_client: discover failed (%d)\n", service);
+    return -1;
+  }
+
+  allow(IPC_DRIVER, MESSAGE_BUFFER, buffer, sizeof(buffer));
+  subscribe(IPC_DRIVER, REPLY_CALLBACK, reply_callback, NULL);
+
+  int ret = call(service, "hello");
+  if (ret != TOCK_SUCCESS || reply_len != 5 || memcmp(buffer, "HELLO", 5) != 0) {
+    printf("msg_client: bad reply (%d)\n", ret);
+    return -1;
+  }
+  printf("msg_client: reply ok\n");
+
+  command(IPC_DRIVER, service, SET_TIMEOUT, 100);
+  ret = call(service, "ignore");
+  if (ret != TOCK_ENOACK) {
+    printf("msg_client: expected a timeout (%d)\n", ret);
+    return -1;
+  }
+  printf("msg_client: timeout ok\n");
+  return 0;
+}
@@ -0,0 +1,34 @@
+// IPC service that replies to each message with the message in upper case.
+// It never replies to "ignore", so clients can test timeouts.
+
+#include <ctype.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+#include <tock.h>
+
+#define IPC_DRIVER       0x10000
+#define MESSAGE_CALLBACK 0xffffffff
+#define MESSAGE_BUFFER   0xffffffff
+#define REPLY            3
+
+static char buffer[32];
+
+static void message_callback(int client, int len,
+                             __attribute__ ((unused)) int arg2,
+                             __attribute__ ((unused)) void* ud) {
+  if (len == 6 && memcmp(buffer, "ignore", 6) == 0) {
+    return;
+  }
+  for (int i = 0; i < len; i++) {
+    buffer[i] = toupper((unsigned char) buffer[i]);
+  }
+  command(IPC_DRIVER, client, REPLY, len);
+}
+
+int main(void) {
+  allow(IPC_DRIVER, MESSAGE_BUFFER, buffer, sizeof(buffer));
+  subscribe(IPC_DRIVER, MESSAGE_CALLBACK, message_callback, NULL);
+  printf("msg_service: ready\n");
+  return 0;
+}
@@ -0,0 +1,9 @@
+// Never yield. Other processes only run if the kernel preempts this one when
+// its timeslice expires.
+
+#include <stdio.h>
+
+int main(void) {
+  printf("spin: started\n");
+  while (1) {}
+}
@@ -0,0 +1,214 @@
+#!/usr/bin/env python3
+"""Regression tests that boot the kernel in QEMU and check its console output.
+
+Each test builds a set of apps from `apps/` with libtock-c, appends them to
+the kernel image and runs it with `make run-app`. The test passes when the
+console output contains the expected lines, in order, before the timeout.
+
+Usage:
+
+    ./tests/run.py [--libtock-c PATH] [--verbose] [TEST ...]
+
+Without arguments, all tests are run. The kernel must already be built, which
+`make test` takes care of.
+"""
+
+import argparse
+import os
+import select
+import signal
+import subprocess
+import sys
+import time
+
+BOARD_DIR = os.path.dirname(os.path.dirname(os.path.abspath(__file__)))
+APPS_DIR = os.path.join(BOARD_DIR, 'tests', 'apps')
+
+# The architecture the apps are built for.
+ARCH = 'cortex-m3'
+
+# Seconds to wait for the expected output of a test.
+DEFAULT_TIMEOUT = 30
+
+
+class Expect:
+    """Wait until the console prints `text`."""
+
+    def __init__(self, text):
+        self.text = text.encode()
+
+    def __str__(self):
+        return 'expect {!r}'.format(self.text.decode())
+
+
+class Send:
+    """Type `text` on the console."""
+
+    def __init__(self, text):
+        self.text = text.encode()
+
+    def __str__(self):
+        return 'send {!r}'.format(self.text.decode())
+
+
+class Test:
+    def __init__(self, name, description, apps, steps, timeout=DEFAULT_TIMEOUT):
+        self.name = name
+        self.description = description
+        self.apps = apps
+        self.steps = steps
+        self.timeout = timeout
+
+
+TESTS = [
+    Test('boot', 'the kernel boots without apps',
+         apps=[],
+         steps=[Expect('initialization complete')]),
+    Test('console', 'a process is loaded and writes to the console',
+         apps=['console'],
+         steps=[Expect('console: hello')]),
+    Test('preemption', 'a process that never yields is preempted',
+         apps=['spin', 'counter'],
+         steps=[Expect('spin: started'), Expect('counter: 3')]),
+    Test('restart', 'a process that faults is restarted',
+         apps=['crash'],
+         steps=[Expect('crash: started'), Expect('crash: started')]),
+    Test('ipc', 'a client shares a buffer with a service over IPC',
+         apps=['ipc_service', 'ipc_client'],
+         steps=[Expect('ipc_service: ready'), Expect('ipc_client: ok')]),
+    Test('ipc_message', 'a client sends a message to a service and gets the reply',
+         apps=['msg_service', 'msg_client'],
+         steps=[Expect('msg_service: ready'), Expect('msg_client: reply ok'),
+                Expect('msg_client: timeout ok')]),
+    Test('process_console', 'the process console lists processes',
+         apps=['console'],
+         steps=[Expect('console: hello'), Send('list\n'), Expect('PID'),
+                Expect('console')]),
+    Test('top', 'the process console shows the CPU time of processes',
+         apps=['spin'],
+         steps=[Expect('spin: started'), Send('top\n'), Expect('CPU'),
+                Expect('spin')]),
+]
+
+# Output that means the test failed, without waiting for the timeout.
+FAILURES = [b'Kernel panic']
+
+
+def build_app(app, libtock_c):
+    """Build an app and return the path of its TBF."""
+    app_dir = os.path.join(APPS_DIR, app)
+    subprocess.run(['make', '-C', app_dir,
+                    'TOCK_TARGETS=' + ARCH,
+                    'TOCK_USERLAND_BASE_DIR=' + libtock_c],
+                   check=True, stdout=subprocess.DEVNULL)
+    return os.path.join(app_dir, 'build', ARCH, ARCH + '.tbf')
+
+
+def run_test(test, tbfs, verbose):
+    """Boot the kernel with `tbfs` and run the steps of `test`.
+
+    Returns the console output and the step that did not complete, or `None`
+    if all of them did.
+    """
+    command = ['make', '--no-print-directory', '-s', '-C', BOARD_DIR]
+    if tbfs:
+        command += ['run-app', 'APP=' + ' '.join(tbfs)]
+    else:
+        command += ['run']
+
+    # Run QEMU in its own process group, so it can be stopped along with make.
+    qemu = subprocess.Popen(command, stdin=subprocess.PIPE,
+                            stdout=subprocess.PIPE, stderr=subprocess.STDOUT,
+                            start_new_session=True)
+    output = b''
+    # Where in `output` to look for the next expected text.
+    position = 0
+    steps = list(test.steps)
+    deadline = time.monotonic() + test.timeout
+    try:
+        while steps:
+            step = steps[0]
+            if isinstance(step, Send):
+                qemu.stdin.write(step.text)
+                qemu.stdin.flush()
+                steps.pop(0)
+                continue
+
+            found = output.find(step.text, position)
+            if found >= 0:
+                position = found + len(step.text)
+                steps.pop(0)
+                continue
+            if any(failure in output for failure in FAILURES):
+                break
+
+            remaining = deadline - time.monotonic()
+            if remaining <= 0:
+                break
+            ready, _, _ = select.select([qemu.stdout], [], [], remaining)
+            if not ready:
+                break
+            data = os.read(qemu.stdout.fileno(), 4096)
+            if not data:
+                # QEMU exited.
+                break
+            if verbose:
+                sys.stdout.buffer.write(data)
+                sys.stdout.flush()
+            output += data
+    finally:
+        try:
+            os.killpg(qemu.pid, signal.SIGTERM)
+        except ProcessLookupError:
+            pass
+        qemu.wait()
+
+    return output, steps[0] if steps else None
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument('tests', nargs='*', metavar='TEST',
+                        help='tests to run: ' + ', '.join(t.name for t in TESTS))
+    parser.add_argument('--libtock-c', default=os.environ.get(
+        'LIBTOCK_C', os.path.join(BOARD_DIR, '..', '..', '..', 'libtock-c')),
+        help='path of the libtock-c checkout used to build the apps '
+        '(default: $LIBTOCK_C, or libtock-c next to the Tock checkout)')
+    parser.add_argument('-v', '--verbose', action='store_true',
+                        help='print the console output while tests run')
+    args = parser.parse_args()
+
+    tests = TESTS
+    if args.tests:
+        unknown = set(args.tests) - set(t.name for t in TESTS)
+        if unknown:
+            parser.error('unknown tests: ' + ', '.join(sorted(unknown)))
+        tests = [t for t in TESTS if t.name in args.tests]
+
+    libtock_c = os.path.abspath(args.libtock_c)
+    apps = sorted(set(app for test in tests for app in test.apps))
+    if apps and not os.path.isdir(libtock_c):
+        sys.exit('libtock-c not found at {}, use --libtock-c'.format(libtock_c))
+    tbfs = {app: build_app(app, libtock_c) for app in apps}
+
+    failed = []
+    for test in tests:
+        output, step = run_test(test, [tbfs[app] for app in test.apps],
+                                args.verbose)
+        if step is None:
+            print('[ OK ] {}: {}'.format(test.name, test.description))
+        else:
+            print('[FAIL] {}: {}'.format(test.name, test.description))
+            print('       did not {}. Console output:'.format(step))
+            sys.stdout.flush()
+            sys.stdout.buffer.write(output)
+            print()
+            failed.append(test.name)
+
+    print('{} of {} tests passed'.format(len(tests) - len(failed), len(tests)))
+    if failed:
+        sys.exit(1)
+
+
+if __name__ == '__main__':
+    main()
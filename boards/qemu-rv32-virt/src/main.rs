@@ -0,0 +1,144 @@
+//! Board file for the QEMU RISC-V `virt` machine.
+//!
+//! - <https://github.com/qemu/qemu/blob/master/hw/riscv/virt.c>
+//!
+//! This board runs Tock and its apps in QEMU, with apps isolated by the PMP.
+//! It is meant for testing the kernel without hardware.
+
+#![no_std]
+#![no_main]
+#![feature(asm)]
+
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::hil;
+use kernel::hil::time::Freq10MHz;
+use kernel::Platform;
+use kernel::{create_capability, debug, static_init};
+use qemu_rv32_virt::chip::QemuRv32Virt;
+use rv32i::csr;
+use rv32i::machine_timer::MachineTimer;
+
+pub mod io;
+
+// Actual memory for holding the active process structures.
+static mut PROCESSES: [Option<&'static dyn kernel::procs::ProcessType>; 4] =
+    [None, None, None, None];
+
+// How should the kernel respond when a process faults.
+const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
+
+// RAM to be shared by all application processes.
+#[link_section = ".app_memory"]
+static mut APP_MEMORY: [u8; 0x8000] = [0; 0x8000];
+
+/// Dummy buffer that causes the linker to reserve enough space for the stack.
+#[no_mangle]
+#[link_section = ".stack_buffer"]
+pub static mut STACK_MEMORY: [u8; 0x1000] = [0; 0x1000];
+
+/// A structure representing this platform that holds references to all
+/// capsules for this platform. We've included an alarm and console.
+struct QemuRv32VirtPlatform {
+    console: &'static capsules::console::Console<'static>,
+    alarm: &'static capsules::alarm::AlarmDriver<
+        'static,
+        VirtualMuxAlarm<'static, MachineTimer<'static, Freq10MHz>>,
+    >,
+}
+
+/// Mapping of integer syscalls to objects that implement syscalls.
+impl Platform for QemuRv32VirtPlatform {
+    fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
+    where
+        F: FnOnce(Option<&dyn kernel::Driver>) -> R,
+    {
+        match driver_num {
+            capsules::console::DRIVER_NUM => f(Some(self.console)),
+            capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
+            _ => f(None),
+        }
+    }
+}
+
+/// Reset Handler.
+///
+/// This function is called from the arch crate after some very basic RISC-V
+/// setup.
+#[no_mangle]
+pub unsafe fn reset_handler() {
+    // Basic setup of the platform.
+    rv32i::init_memory();
+    // Apps run in user mode, traps are handled in machine mode.
+    rv32i::configure_trap_handler(rv32i::PermissionMode::Machine);
+
+    // initialize capabilities
+    let process_mgmt_cap = create_capability!(capabilities::ProcessManagementCapability);
+    let main_loop_cap = create_capability!(capabilities::MainLoopCapability);
+
+    let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
+    // Account the CPU time of processes with the cycle counter of the hart.
+    let cycle_counter = static_init!(
+        rv32i::cycle_counter::CycleCounter,
+        rv32i::cycle_counter::CycleCounter::new()
+    );
+    board_kernel.set_cycle_counter(cycle_counter);
+
+    let chip = static_init!(QemuRv32Virt, QemuRv32Virt::new());
+
+    chip.enable_interrupts();
+    // enable interrupts globally
+    csr::CSR.mstatus.modify(csr::mstatus::mstatus::mie::SET);
+
+    // Create a shared UART channel for the console and for kernel debug.
+    let uart_mux = components::console::UartMuxComponent::new(&qemu_rv32_virt::uart::UART0, 115200)
+        .finalize(());
+
+    // Setup the console.
+    let console = components::console::ConsoleComponent::new(board_kernel, uart_mux).finalize(());
+    // Create the debugger object that handles calls to `debug!()`.
+    components::debug_writer::DebugWriterComponent::new(uart_mux).finalize(());
+    // Create the process console, which reads commands from the terminal.
+    let process_console =
+        components::process_console::ProcessConsoleComponent::new(board_kernel, uart_mux)
+            .finalize(());
+
+    // Create a shared virtualization mux layer on top of a single hardware
+    // alarm.
+    let mux_alarm = static_init!(
+        MuxAlarm<'static, MachineTimer<Freq10MHz>>,
+        MuxAlarm::new(&qemu_rv32_virt::timer::MACHINETIMER)
+    );
+    hil::time::Alarm::set_client(&qemu_rv32_virt::timer::MACHINETIMER, mux_alarm);
+
+    let alarm = components::alarm::AlarmDriverComponent::new(board_kernel, mux_alarm)
+        .finalize(components::alarm_component_helper!(MachineTimer<Freq10MHz>));
+
+    debug!("QEMU RISC-V virt initialization complete. Entering main loop");
+    process_console.start();
+
+    extern "C" {
+        /// Beginning of the ROM region containing app images.
+        ///
+        /// This symbol is defined in the linker script.
+        static _sapps: u8;
+    }
+
+    let platform = QemuRv32VirtPlatform {
+        console: console,
+        alarm: alarm,
+    };
+
+    kernel::procs::load_processes(
+        board_kernel,
+        chip,
+        &_sapps as *const u8,
+        &mut APP_MEMORY,
+        &mut PROCESSES,
+        FAULT_RESPONSE,
+        &process_mgmt_cap,
+    );
+
+    board_kernel.kernel_loop(&platform, chip, None, &main_loop_cap, chip);
+}
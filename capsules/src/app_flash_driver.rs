@@ -88,7 +88,7 @@ impl AppFlash<'a> {
                                     *c = d[i];
                                 }
 
-                                self.driver.write(buffer, flash_address, length)
+                                self.write_buffer(buffer, flash_address, length)
                             })
                         })
                 } else {
@@ -104,6 +104,22 @@ impl AppFlash<'a> {
             })
             .unwrap_or_else(|err| err.into())
     }
+
+    // Start writing the internal buffer to flash, keeping it for the next
+    // command if the write does not start.
+    fn write_buffer(
+        &self,
+        buffer: &'static mut [u8],
+        flash_address: usize,
+        length: usize,
+    ) -> ReturnCode {
+        let (res, buffer) = self.driver.write(buffer, flash_address, length);
+        if res != ReturnCode::SUCCESS {
+            buffer.map(|buffer| self.buffer.replace(buffer));
+            self.current_app.clear();
+        }
+        res
+    }
 }
 
 impl hil::nonvolatile_storage::NonvolatileStorageClient<'static> for AppFlash<'a> {
@@ -142,7 +158,7 @@ impl hil::nonvolatile_storage::NonvolatileStorageClient<'static> for AppFlash<'a
                                     *c = d[i];
                                 }
 
-                                self.driver.write(buffer, flash_address, length)
+                                self.write_buffer(buffer, flash_address, length)
                                     == ReturnCode::SUCCESS
                             }
                         })
@@ -24,8 +24,9 @@
 //! ```c
 //! // (Optional) Set a callback to be invoked when the buffer has been written
 //! subscribe(CONSOLE_DRIVER_NUM, 1, my_callback);
-//! // Share the buffer from userspace with the driver
-//! allow(CONSOLE_DRIVER_NUM, buffer, buffer_len_in_bytes);
+//! // Share the buffer from userspace with the driver. The buffer can be in
+//! // flash, e.g. a string constant.
+//! allow_readonly(CONSOLE_DRIVER_NUM, 1, buffer, buffer_len_in_bytes);
 //! // Initiate the transaction
 //! command(CONSOLE_DRIVER_NUM, 1, len_to_write_in_bytes)
 //! ```
@@ -37,7 +38,7 @@
 use core::cmp;
 use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::hil::uart;
-use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReadOnly, ReturnCode, Shared};
 
 /// Syscall driver number.
 use crate::driver;
@@ -46,7 +47,7 @@ pub const DRIVER_NUM: usize = driver::NUM::Console as usize;
 #[derive(Default)]
 pub struct App {
     write_callback: Option<Callback>,
-    write_buffer: Option<AppSlice<Shared, u8>>,
+    write_buffer: Option<AppSlice<ReadOnly, u8>>,
     write_len: usize,
     write_remaining: usize, // How many bytes didn't fit in the buffer and still need to be printed.
     pending_write: bool,
@@ -115,7 +116,7 @@ impl Console<'a> {
 
     /// Internal helper function for sending data for an existing transaction.
     /// Cannot fail. If can't send now, it will schedule for sending later.
-    fn send(&self, app_id: AppId, app: &mut App, slice: AppSlice<Shared, u8>) {
+    fn send(&self, app_id: AppId, app: &mut App, slice: AppSlice<ReadOnly, u8>) {
         if self.tx_in_progress.is_none() {
             self.tx_in_progress.set(app_id);
             self.tx_buffer.take().map(|buffer| {
@@ -186,7 +187,7 @@ impl Driver for Console<'a> {
     ///
     /// ### `allow_num`
     ///
-    /// - `1`: Writeable buffer for write buffer
+    /// - `1`: Write buffer. Prefer `allow_readonly`.
     /// - `2`: Writeable buffer for read buffer
     fn allow(
         &self,
@@ -195,17 +196,34 @@ impl Driver for Console<'a> {
         slice: Option<AppSlice<Shared, u8>>,
     ) -> ReturnCode {
         match allow_num {
-            1 => self
+            1 => self.allow_readonly(appid, allow_num, slice.map(AppSlice::from)),
+            2 => self
                 .apps
                 .enter(appid, |app, _| {
-                    app.write_buffer = slice;
+                    app.read_buffer = slice;
                     ReturnCode::SUCCESS
                 })
                 .unwrap_or_else(|err| err.into()),
-            2 => self
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup read-only shared buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `1`: Buffer for write buffer
+    fn allow_readonly(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<ReadOnly, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            1 => self
                 .apps
                 .enter(appid, |app, _| {
-                    app.read_buffer = slice;
+                    app.write_buffer = slice;
                     ReturnCode::SUCCESS
                 })
                 .unwrap_or_else(|err| err.into()),
@@ -68,7 +68,7 @@
 use kernel::common::cells::OptionalCell;
 use kernel::hil;
 use kernel::hil::crc::CrcAlg;
-use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReadOnly, ReturnCode, Shared};
 
 /// Syscall driver number.
 use crate::driver;
@@ -78,7 +78,7 @@ pub const DRIVER_NUM: usize = driver::NUM::Crc as usize;
 #[derive(Default)]
 pub struct App {
     callback: Option<Callback>,
-    buffer: Option<AppSlice<Shared, u8>>,
+    buffer: Option<AppSlice<ReadOnly, u8>>,
 
     // if Some, the application is awaiting the result of a CRC
     //   using the given algorithm
@@ -162,19 +162,31 @@ impl<C: hil::crc::CRC> Crc<'a, C> {
 /// memory.
 ///
 /// At a high level, the client first provides a callback for the result of computations through
-/// the `subscribe` system call and `allow`s the driver access to the buffer over-which to compute.
+/// the `subscribe` system call and `allow_readonly`s the driver access to the buffer over-which to
+/// compute, which may be in flash.
 /// Then, it initiates a CRC computation using the `command` system call. See function-specific
 /// comments for details.
 impl<C: hil::crc::CRC> Driver for Crc<'a, C> {
-    /// The `allow` syscall for this driver supports the single
+    /// The `allow` syscall for this driver supports the same `allow_num`
+    /// as `allow_readonly`, for processes that do not use the latter.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        self.allow_readonly(appid, allow_num, slice.map(AppSlice::from))
+    }
+
+    /// The `allow_readonly` syscall for this driver supports the single
     /// `allow_num` zero, which is used to provide a buffer over which
     /// to compute a CRC computation.
     ///
-    fn allow(
+    fn allow_readonly(
         &self,
         appid: AppId,
         allow_num: usize,
-        slice: Option<AppSlice<Shared, u8>>,
+        slice: Option<AppSlice<ReadOnly, u8>>,
     ) -> ReturnCode {
         match allow_num {
             // Provide user buffer to compute CRC over
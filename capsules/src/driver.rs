@@ -19,6 +19,8 @@ pub enum NUM {
 
     // Kernel
     Ipc                   = 0x10000,
+    ProcessInfo           = 0x10001,
+    Watchdog              = 0x10002,
 
     // HW Buses
     Spi                   = 0x20001,
@@ -29,6 +31,11 @@ pub enum NUM {
     BleAdvertising        = 0x30000,
     Ieee802154            = 0x30001,
     Udp                   = 0x30002,
+    Tcp                   = 0x30003,
+    Icmp6                 = 0x30004,
+    SixlowpanContext      = 0x30005,
+    Thread                = 0x30006,
+    Coap                  = 0x30007,
 
     // Cryptography
     Rng                   = 0x40001,
@@ -296,11 +296,33 @@ impl<S: hil::spi::SpiMasterDevice> hil::nonvolatile_storage::NonvolatileStorage<
         self.client.set(client);
     }
 
-    fn read(&self, buffer: &'static mut [u8], address: usize, length: usize) -> ReturnCode {
-        self.read(address as u16, buffer, length as u16)
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.txbuffer.is_none() || self.rxbuffer.is_none() {
+            return (ReturnCode::ERESERVE, Some(buffer));
+        }
+        match self.read(address as u16, buffer, length as u16) {
+            ReturnCode::SUCCESS => (ReturnCode::SUCCESS, None),
+            error => (error, self.client_buffer.take()),
+        }
     }
 
-    fn write(&self, buffer: &'static mut [u8], address: usize, length: usize) -> ReturnCode {
-        self.write(address as u16, buffer, length as u16)
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.txbuffer.is_none() {
+            return (ReturnCode::ERESERVE, Some(buffer));
+        }
+        match self.write(address as u16, buffer, length as u16) {
+            ReturnCode::SUCCESS => (ReturnCode::SUCCESS, None),
+            error => (error, self.client_buffer.take()),
+        }
     }
 }
@@ -0,0 +1,962 @@
+//! Stub of the GDB remote serial protocol for debugging a process over a
+//! UART.
+//!
+//! The stub lets a stock `gdb` debug one process without a hardware debugger
+//! and without stopping the kernel or the other processes. It halts the
+//! process at its next context switch, reads and writes its registers, its
+//! RAM and its flash, and resumes it.
+//!
+//! Breakpoints replace an instruction of the process with one that traps into
+//! the kernel. The kernel reports the trap to the stub, which stops the process
+//! instead of letting it fault. Single-stepping sets temporary breakpoints at
+//! the next instruction and at the target of a branch. Writing to flash,
+//! including breakpoints in flash, needs a `NonvolatileStorage` driver for the
+//! flash of the processes.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! pub struct Capability;
+//! unsafe impl capabilities::ProcessManagementCapability for Capability {}
+//!
+//! let gdb_uart = static_init!(UartDevice, UartDevice::new(uart_mux, true));
+//! gdb_uart.setup();
+//! let gdb_stub = static_init!(
+//!     capsules::gdb_stub::GdbStub<'static, Capability>,
+//!     capsules::gdb_stub::GdbStub::new(
+//!         gdb_uart,
+//!         &mut capsules::gdb_stub::TX_BUF,
+//!         &mut capsules::gdb_stub::RX_BUF,
+//!         &mut capsules::gdb_stub::PACKET_BUF,
+//!         &mut capsules::gdb_stub::FLASH_BUF,
+//!         board_kernel,
+//!         Capability,
+//!     )
+//! );
+//! hil::uart::Transmit::set_transmit_client(gdb_uart, gdb_stub);
+//! hil::uart::Receive::set_receive_client(gdb_uart, gdb_stub);
+//! gdb_stub.set_flash(nv_to_page);
+//! board_kernel.set_debugger(gdb_stub, &Capability);
+//! gdb_stub.start();
+//! ```
+//!
+//! Using the stub
+//! --------------
+//!
+//! Connect `gdb`, with the ELF file of the app, to the serial port of the
+//! stub:
+//!
+//! ```text
+//! $ arm-none-eabi-gdb blink.elf
+//! (gdb) target remote /dev/ttyUSB0
+//! (gdb) monitor app blink
+//! ```
+//!
+//! The stub debugs the first process until `monitor app <name>` selects
+//! another one. Apps are relocated when they are loaded, so the symbols of the
+//! ELF file have to be loaded at the address of the app in flash, e.g. with
+//! `add-symbol-file`.
+
+use core::cell::Cell;
+use core::cmp;
+use core::str;
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil;
+use kernel::hil::uart;
+use kernel::procs::{ProcessDebugger, ProcessType};
+use kernel::{AppId, Kernel, ReturnCode};
+
+pub static mut TX_BUF: [u8; 300] = [0; 300];
+// Bytes are received one at a time.
+pub static mut RX_BUF: [u8; 1] = [0; 1];
+// Holds the packet being received. A memory write packet carries at most
+// `FLASH_BUF.len()` bytes of data in this space.
+pub static mut PACKET_BUF: [u8; 256] = [0; 256];
+pub static mut FLASH_BUF: [u8; 128] = [0; 128];
+
+/// How many breakpoints can be set, including the two single-stepping uses.
+const MAX_BREAKPOINTS: usize = 10;
+
+/// Signal numbers GDB uses to report why the process stopped.
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+#[derive(Copy, Clone, PartialEq)]
+enum ReceiveState {
+    /// Waiting for the start of a packet.
+    Idle,
+    /// Receiving the data of a packet.
+    Packet,
+    /// Receiving the first digit of the checksum.
+    Checksum,
+    /// Receiving the second digit of the checksum, after the first one.
+    Checksum2(u8),
+}
+
+/// Replies without data, which the stub may have to send later.
+#[derive(Copy, Clone, PartialEq)]
+enum Reply {
+    Ok,
+    Error,
+    Stop(u8),
+}
+
+/// What the stub does once all breakpoints are in memory as wanted.
+#[derive(Copy, Clone, PartialEq)]
+enum Then {
+    Nothing,
+    /// Reply `OK`, or an error if a write failed.
+    Reply,
+    /// Resume the process.
+    Resume,
+    /// Report that the process stopped with the signal.
+    Stop(u8),
+    /// Resume the process and stop debugging it.
+    Detach,
+}
+
+/// How a write to the memory of the process completes.
+enum Completion {
+    Done,
+    /// The write continues in the flash driver.
+    InFlash,
+}
+
+#[derive(Copy, Clone)]
+struct Breakpoint {
+    address: usize,
+    /// GDB breakpoint kind, which selects the breakpoint instruction.
+    kind: usize,
+    /// The memory the breakpoint instruction replaces.
+    original: [u8; 4],
+    length: usize,
+    /// Whether the breakpoint instruction should be in memory.
+    wanted: bool,
+    /// Whether the breakpoint instruction is in memory.
+    inserted: bool,
+    /// Whether the stub set the breakpoint to single-step.
+    step: bool,
+}
+
+pub struct GdbStub<'a, C: ProcessManagementCapability> {
+    uart: &'a dyn uart::UartData<'a>,
+    flash: OptionalCell<&'a dyn hil::nonvolatile_storage::NonvolatileStorage<'static>>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    packet_buffer: TakeCell<'static, [u8]>,
+    flash_buffer: TakeCell<'static, [u8]>,
+    receive_state: Cell<ReceiveState>,
+    packet_len: Cell<usize>,
+    packet_overflow: Cell<bool>,
+    checksum: Cell<u8>,
+    /// Whether the stub still has to acknowledge the last packet.
+    ack_pending: Cell<bool>,
+    /// Reply to send once the UART finishes transmitting.
+    pending_reply: Cell<Option<Reply>>,
+    /// Index of the process being debugged.
+    app: Cell<usize>,
+    /// Whether GDB is connected and debugging the process.
+    attached: Cell<bool>,
+    /// Whether the process runs, as far as the debugger is concerned.
+    running: Cell<bool>,
+    breakpoints: [Cell<Option<Breakpoint>>; MAX_BREAKPOINTS],
+    /// Whether the flash driver is writing for the stub.
+    flash_writing: Cell<bool>,
+    /// Breakpoint the flash driver is writing.
+    flash_breakpoint: Cell<Option<usize>>,
+    /// Whether writing a breakpoint failed since the last command.
+    write_failed: Cell<bool>,
+    then: Cell<Then>,
+    kernel: &'static Kernel,
+    capability: C,
+}
+
+impl<'a, C: ProcessManagementCapability> GdbStub<'a, C> {
+    pub fn new(
+        uart: &'a dyn uart::UartData<'a>,
+        tx_buffer: &'static mut [u8],
+        rx_buffer: &'static mut [u8],
+        packet_buffer: &'static mut [u8],
+        flash_buffer: &'static mut [u8],
+        kernel: &'static Kernel,
+        capability: C,
+    ) -> GdbStub<'a, C> {
+        GdbStub {
+            uart: uart,
+            flash: OptionalCell::empty(),
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            packet_buffer: TakeCell::new(packet_buffer),
+            flash_buffer: TakeCell::new(flash_buffer),
+            receive_state: Cell::new(ReceiveState::Idle),
+            packet_len: Cell::new(0),
+            packet_overflow: Cell::new(false),
+            checksum: Cell::new(0),
+            ack_pending: Cell::new(false),
+            pending_reply: Cell::new(None),
+            app: Cell::new(0),
+            attached: Cell::new(false),
+            running: Cell::new(true),
+            breakpoints: Default::default(),
+            flash_writing: Cell::new(false),
+            flash_breakpoint: Cell::new(None),
+            write_failed: Cell::new(false),
+            then: Cell::new(Then::Nothing),
+            kernel: kernel,
+            capability: capability,
+        }
+    }
+
+    /// Use `flash` to write to the flash of processes. The flash driver must
+    /// use the addresses the flash is mapped at, and its client must be set to
+    /// this stub.
+    pub fn set_flash(&self, flash: &'a dyn hil::nonvolatile_storage::NonvolatileStorage<'static>) {
+        self.flash.set(flash);
+    }
+
+    pub fn start(&self) -> ReturnCode {
+        self.rx_buffer.take().map_or(ReturnCode::EBUSY, |buffer| {
+            self.uart.receive_buffer(buffer, 1);
+            ReturnCode::SUCCESS
+        })
+    }
+
+    fn process_map_or<R: Copy>(&self, default: R, closure: impl Fn(&dyn ProcessType) -> R) -> R {
+        let result = Cell::new(default);
+        let app = self.app.get();
+        self.kernel
+            .process_each_capability(&self.capability, |i, process| {
+                if i == app {
+                    result.set(closure(process));
+                }
+            });
+        result.get()
+    }
+
+    // Handle one byte received from GDB.
+    fn receive_byte(&self, byte: u8) {
+        match self.receive_state.get() {
+            ReceiveState::Idle => match byte {
+                b'$' => {
+                    self.packet_len.set(0);
+                    self.packet_overflow.set(false);
+                    self.checksum.set(0);
+                    self.receive_state.set(ReceiveState::Packet);
+                }
+                // Ctrl-C interrupts the running process.
+                0x03 => self.interrupt(),
+                // Acknowledgements. The stub does not retransmit.
+                _ => {}
+            },
+            ReceiveState::Packet => {
+                if byte == b'#' {
+                    self.receive_state.set(ReceiveState::Checksum);
+                } else {
+                    self.checksum.set(self.checksum.get().wrapping_add(byte));
+                    let len = self.packet_len.get();
+                    self.packet_buffer.map(|packet| {
+                        if len < packet.len() {
+                            packet[len] = byte;
+                            self.packet_len.set(len + 1);
+                        } else {
+                            self.packet_overflow.set(true);
+                        }
+                    });
+                }
+            }
+            ReceiveState::Checksum => {
+                let digit = hex_digit(byte).unwrap_or(0);
+                self.receive_state.set(ReceiveState::Checksum2(digit));
+            }
+            ReceiveState::Checksum2(high) => {
+                self.receive_state.set(ReceiveState::Idle);
+                let checksum = high << 4 | hex_digit(byte).unwrap_or(0);
+                if checksum != self.checksum.get() || self.packet_overflow.get() {
+                    self.send_raw(b"-");
+                } else {
+                    self.ack_pending.set(true);
+                    self.handle_packet();
+                    if self.ack_pending.get() {
+                        self.send_raw(b"+");
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_packet(&self) {
+        self.attached.set(true);
+        let len = self.packet_len.get();
+        self.packet_buffer.map(|buffer| {
+            let packet_size = buffer.len();
+            let packet = &buffer[..len];
+            let (command, arguments) = match packet.split_first() {
+                Some((command, arguments)) => (*command, arguments),
+                None => return self.send_packet(b""),
+            };
+            match command {
+                b'?' => {
+                    self.halt();
+                    self.send_reply(Reply::Stop(SIGTRAP));
+                }
+                b'g' => self.read_registers(),
+                b'G' => self.write_registers(arguments),
+                b'p' => match parse_hex(arguments) {
+                    Some(register) => self.read_register(register),
+                    None => self.send_reply(Reply::Error),
+                },
+                b'P' => {
+                    let mut fields = arguments.splitn(2, |&b| b == b'=');
+                    let register = fields.next().and_then(parse_hex);
+                    let value = fields.next().and_then(parse_register_value);
+                    match (register, value) {
+                        (Some(register), Some(value)) => self.write_register(register, value),
+                        _ => self.send_reply(Reply::Error),
+                    }
+                }
+                b'm' => match parse_address_length(arguments) {
+                    Some((address, length)) => self.read_memory(address, length),
+                    None => self.send_reply(Reply::Error),
+                },
+                b'M' => self.write_memory_command(arguments),
+                b'Z' | b'z' => self.breakpoint_command(command == b'Z', arguments),
+                b'c' => self.resume(),
+                b's' => self.step(),
+                b'D' => self.detach(),
+                b'k' => {
+                    self.detach();
+                    // GDB does not expect a reply to kill.
+                    self.pending_reply.set(None);
+                }
+                b'H' | b'T' => self.send_reply(Reply::Ok),
+                b'q' => self.query(arguments, packet_size),
+                // Unsupported commands get an empty reply.
+                _ => self.send_packet(b""),
+            }
+        });
+    }
+
+    fn query(&self, query: &[u8], packet_size: usize) {
+        if query.starts_with(b"Supported") {
+            let mut reply = [0; 40];
+            let prefix = b"PacketSize=";
+            let suffix = b";qXfer:features:read+";
+            reply[..prefix.len()].copy_from_slice(prefix);
+            let mut len = prefix.len() + write_hex_number(&mut reply[prefix.len()..], packet_size);
+            reply[len..len + suffix.len()].copy_from_slice(suffix);
+            len += suffix.len();
+            self.send_packet(&reply[..len]);
+        } else if query.starts_with(b"Xfer:features:read:target.xml:") {
+            let arguments = &query[b"Xfer:features:read:target.xml:".len()..];
+            match parse_address_length(arguments) {
+                Some((offset, length)) => self.read_target_description(offset, length),
+                None => self.send_reply(Reply::Error),
+            }
+        } else if query.starts_with(b"Attached") {
+            self.send_packet(b"1");
+        } else if query.starts_with(b"Rcmd,") {
+            self.monitor_command(&query[b"Rcmd,".len()..]);
+        } else {
+            self.send_packet(b"");
+        }
+    }
+
+    // Handle `monitor` commands. `monitor app <name>` selects the process to
+    // debug.
+    fn monitor_command(&self, hex: &[u8]) {
+        let mut command = [0; 32];
+        let len = hex.len() / 2;
+        if len > command.len() || decode_hex(hex, &mut command[..len]).is_none() {
+            return self.send_reply(Reply::Error);
+        }
+        let name = str::from_utf8(&command[..len]).ok().and_then(|command| {
+            let mut words = command.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("app"), Some(name)) => Some(name),
+                _ => None,
+            }
+        });
+        let index = Cell::new(None);
+        name.map(|name| {
+            self.kernel
+                .process_each_capability(&self.capability, |i, process| {
+                    if process.get_process_name() == name {
+                        index.set(Some(i));
+                    }
+                });
+        });
+        let has_breakpoints = self.breakpoints.iter().any(|slot| slot.get().is_some());
+        match index.get() {
+            // Breakpoints belong to the process being debugged, so GDB has to
+            // remove them before switching.
+            Some(index) if !has_breakpoints => {
+                self.process_map_or((), |process| process.resume());
+                self.app.set(index);
+                self.halt();
+                self.send_reply(Reply::Ok);
+            }
+            _ => self.send_reply(Reply::Error),
+        }
+    }
+
+    fn read_target_description(&self, offset: usize, length: usize) {
+        let description = self.process_map_or("", |process| process.debugger_target_description());
+        if description.is_empty() {
+            return self.send_reply(Reply::Error);
+        }
+        let start = cmp::min(offset, description.len());
+        let room = self.tx_buffer.map_or(0, |buffer| buffer.len() - 6);
+        let end = cmp::min(start + cmp::min(length, room), description.len());
+        let more = if end < description.len() { b'm' } else { b'l' };
+        self.send_packet_with(|payload| {
+            payload[0] = more;
+            payload[1..1 + end - start].copy_from_slice(&description.as_bytes()[start..end]);
+            1 + end - start
+        });
+    }
+
+    fn read_registers(&self) {
+        if self
+            .process_map_or(None, |process| process.debugger_read_register(0))
+            .is_none()
+        {
+            return self.send_reply(Reply::Error);
+        }
+        self.send_packet_with(|payload| {
+            let mut register = 0;
+            while payload.len() >= (register + 1) * 8 {
+                match self.process_map_or(None, |process| process.debugger_read_register(register))
+                {
+                    Some(value) => {
+                        let bytes = (value as u32).to_le_bytes();
+                        write_hex_bytes(&mut payload[register * 8..], &bytes);
+                    }
+                    None => break,
+                }
+                register += 1;
+            }
+            register * 8
+        });
+    }
+
+    fn write_registers(&self, hex: &[u8]) {
+        for (register, value) in hex.chunks(8).enumerate() {
+            let value = match parse_register_value(value) {
+                Some(value) => value,
+                None => return self.send_reply(Reply::Error),
+            };
+            // GDB writes all registers, including those that cannot be
+            // changed, so only write those whose value changes.
+            let current =
+                self.process_map_or(None, |process| process.debugger_read_register(register));
+            if current != Some(value) && !self.set_register(register, value) {
+                return self.send_reply(Reply::Error);
+            }
+        }
+        self.send_reply(Reply::Ok);
+    }
+
+    fn read_register(&self, register: usize) {
+        match self.process_map_or(None, |process| process.debugger_read_register(register)) {
+            Some(value) => self.send_packet_with(|payload| {
+                write_hex_bytes(payload, &(value as u32).to_le_bytes());
+                8
+            }),
+            None => self.send_reply(Reply::Error),
+        }
+    }
+
+    fn write_register(&self, register: usize, value: usize) {
+        if self.set_register(register, value) {
+            self.send_reply(Reply::Ok);
+        } else {
+            self.send_reply(Reply::Error);
+        }
+    }
+
+    fn set_register(&self, register: usize, value: usize) -> bool {
+        self.process_map_or(ReturnCode::EINVAL, |process| {
+            process.debugger_write_register(register, value)
+        }) == ReturnCode::SUCCESS
+    }
+
+    fn read_memory(&self, address: usize, length: usize) {
+        let readable = self.process_map_or(ReturnCode::EINVAL, |process| {
+            process.debugger_read_memory(address, &mut [0; 1])
+        });
+        if length > 0 && readable != ReturnCode::SUCCESS {
+            return self.send_reply(Reply::Error);
+        }
+        self.send_packet_with(|payload| {
+            let length = cmp::min(length, payload.len() / 2);
+            let mut read = 0;
+            // Read in chunks, and reply with fewer bytes than requested if the
+            // memory of the process ends in between.
+            while read < length {
+                let len = cmp::min(16, length - read);
+                let (result, data) =
+                    self.process_map_or((ReturnCode::EINVAL, [0; 16]), |process| {
+                        let mut data = [0; 16];
+                        let result = match address.checked_add(read) {
+                            Some(address) => {
+                                process.debugger_read_memory(address, &mut data[..len])
+                            }
+                            None => ReturnCode::EINVAL,
+                        };
+                        (result, data)
+                    });
+                if result != ReturnCode::SUCCESS {
+                    break;
+                }
+                write_hex_bytes(&mut payload[read * 2..], &data[..len]);
+                read += len;
+            }
+            read * 2
+        });
+    }
+
+    fn write_memory_command(&self, arguments: &[u8]) {
+        let mut fields = arguments.splitn(2, |&b| b == b':');
+        let address_length = fields.next().and_then(parse_address_length);
+        let hex = fields.next().unwrap_or(b"");
+        let mut data = [0; 128];
+        match address_length {
+            Some((address, length))
+                if length <= data.len()
+                    && hex.len() == length * 2
+                    && decode_hex(hex, &mut data[..length]).is_some() =>
+            {
+                match self.write_memory(address, &data[..length]) {
+                    Ok(Completion::Done) => self.send_reply(Reply::Ok),
+                    Ok(Completion::InFlash) => self.then.set(Then::Reply),
+                    Err(_) => self.send_reply(Reply::Error),
+                }
+            }
+            _ => self.send_reply(Reply::Error),
+        }
+    }
+
+    // Write to the RAM of the process, or through the flash driver to the part
+    // of its flash after the protected region.
+    fn write_memory(&self, address: usize, data: &[u8]) -> Result<Completion, ReturnCode> {
+        let (flash_start, flash_end) = self.process_map_or((0, 0), |process| {
+            (
+                process.flash_non_protected_start() as usize,
+                process.flash_end() as usize,
+            )
+        });
+        let end = address.checked_add(data.len()).ok_or(ReturnCode::EINVAL)?;
+        if address >= flash_start && end <= flash_end {
+            let flash = self
+                .flash
+                .map_or(Err(ReturnCode::ENOSUPPORT), |flash| Ok(*flash))?;
+            if self.flash_writing.get() {
+                return Err(ReturnCode::EBUSY);
+            }
+            let buffer = self.flash_buffer.take().ok_or(ReturnCode::FAIL)?;
+            if data.len() > buffer.len() {
+                self.flash_buffer.replace(buffer);
+                return Err(ReturnCode::ESIZE);
+            }
+            buffer[..data.len()].copy_from_slice(data);
+            match flash.write(buffer, address, data.len()) {
+                (ReturnCode::SUCCESS, _) => {
+                    self.flash_writing.set(true);
+                    Ok(Completion::InFlash)
+                }
+                // The flash driver gives the buffer back if the write does
+                // not start, for the next write.
+                (error, buffer) => {
+                    buffer.map(|buffer| self.flash_buffer.replace(buffer));
+                    Err(error)
+                }
+            }
+        } else {
+            match self.process_map_or(ReturnCode::EINVAL, |process| {
+                process.debugger_write_memory(address, data)
+            }) {
+                ReturnCode::SUCCESS => Ok(Completion::Done),
+                error => Err(error),
+            }
+        }
+    }
+
+    fn breakpoint_command(&self, insert: bool, arguments: &[u8]) {
+        let mut fields = arguments.split(|&b| b == b',');
+        let kind_of_point = fields.next();
+        let address = fields.next().and_then(parse_hex);
+        let kind = fields.next().and_then(parse_hex);
+        match (kind_of_point, address, kind) {
+            // Only software breakpoints are supported.
+            (Some(b"0"), Some(address), Some(kind)) => {
+                if insert && self.insert_breakpoint(address, kind, false).is_none() {
+                    return self.send_reply(Reply::Error);
+                } else if !insert {
+                    self.remove_breakpoint(address);
+                }
+                self.then.set(Then::Reply);
+                self.flush_breakpoints();
+            }
+            _ => self.send_packet(b""),
+        }
+    }
+
+    fn insert_breakpoint(&self, address: usize, kind: usize, step: bool) -> Option<()> {
+        if self.breakpoints.iter().any(|slot| {
+            slot.get()
+                .map_or(false, |bp| bp.wanted && bp.address == address)
+        }) {
+            return Some(());
+        }
+        let instruction = self.process_map_or(None, |process| {
+            process.debugger_breakpoint_instruction(kind)
+        })?;
+        let length = instruction.len();
+        let (result, original) = self.process_map_or((ReturnCode::EINVAL, [0; 4]), |process| {
+            let mut original = [0; 4];
+            (
+                process.debugger_read_memory(address, &mut original[..length]),
+                original,
+            )
+        });
+        if result != ReturnCode::SUCCESS {
+            return None;
+        }
+        let slot = self.breakpoints.iter().find(|slot| slot.get().is_none())?;
+        slot.set(Some(Breakpoint {
+            address: address,
+            kind: kind,
+            original: original,
+            length: length,
+            wanted: true,
+            inserted: false,
+            step: step,
+        }));
+        Some(())
+    }
+
+    fn remove_breakpoint(&self, address: usize) {
+        for slot in self.breakpoints.iter() {
+            slot.get().map(|mut bp| {
+                if bp.address == address && !bp.step {
+                    bp.wanted = false;
+                    slot.set(Some(bp));
+                }
+            });
+        }
+    }
+
+    // Write breakpoint instructions into memory, or restore the original
+    // memory, until the memory matches the breakpoints the stub wants. Then
+    // do what the command that changed the breakpoints asked for.
+    fn flush_breakpoints(&self) {
+        // A flash write is in progress, and calls this when it completes.
+        if self.flash_writing.get() {
+            return;
+        }
+        for (i, slot) in self.breakpoints.iter().enumerate() {
+            let bp = match slot.get() {
+                Some(bp) if bp.wanted != bp.inserted => bp,
+                _ => continue,
+            };
+            let instruction = if bp.wanted {
+                self.process_map_or(None, |process| {
+                    process.debugger_breakpoint_instruction(bp.kind)
+                })
+            } else {
+                None
+            };
+            let data = instruction.unwrap_or(&bp.original[..bp.length]);
+            match self.write_memory(bp.address, data) {
+                Ok(Completion::Done) => slot.set(finish_write(bp)),
+                Ok(Completion::InFlash) => {
+                    self.flash_breakpoint.set(Some(i));
+                    return;
+                }
+                Err(_) => {
+                    // Forget the breakpoint, as there is no way to fix its
+                    // memory.
+                    self.write_failed.set(true);
+                    slot.set(None);
+                }
+            }
+        }
+
+        match self.then.replace(Then::Nothing) {
+            Then::Nothing => {}
+            Then::Reply => {
+                if self.write_failed.replace(false) {
+                    self.send_reply(Reply::Error);
+                } else {
+                    self.send_reply(Reply::Ok);
+                }
+            }
+            Then::Resume => {
+                self.write_failed.set(false);
+                self.running.set(true);
+                self.process_map_or((), |process| process.resume());
+            }
+            Then::Stop(signal) => {
+                self.write_failed.set(false);
+                self.send_reply(Reply::Stop(signal));
+            }
+            Then::Detach => {
+                self.write_failed.set(false);
+                self.attached.set(false);
+                self.running.set(true);
+                self.process_map_or((), |process| process.resume());
+                self.send_reply(Reply::Ok);
+            }
+        }
+    }
+
+    // Stop the process before it runs again.
+    fn halt(&self) {
+        self.running.set(false);
+        self.process_map_or((), |process| process.stop());
+    }
+
+    fn resume(&self) {
+        self.then.set(Then::Resume);
+        self.flush_breakpoints();
+    }
+
+    // Let the process execute one instruction by trapping at every address it
+    // can execute next.
+    fn step(&self) {
+        let addresses =
+            self.process_map_or([None, None], |process| process.debugger_step_addresses());
+        let mut trapped = false;
+        for (address, kind) in addresses.iter().filter_map(|&address| address) {
+            trapped |= self.insert_breakpoint(address, kind, true).is_some();
+        }
+        if trapped {
+            self.resume();
+        } else {
+            self.send_reply(Reply::Error);
+        }
+    }
+
+    fn interrupt(&self) {
+        if self.attached.get() && self.running.get() {
+            self.halt();
+            self.stopped(SIGINT);
+        }
+    }
+
+    // Remove the single-stepping breakpoints and report to GDB that the
+    // process stopped.
+    fn stopped(&self, signal: u8) {
+        for slot in self.breakpoints.iter() {
+            slot.get().map(|mut bp| {
+                if bp.step {
+                    bp.wanted = false;
+                    slot.set(Some(bp));
+                }
+            });
+        }
+        self.then.set(Then::Stop(signal));
+        self.flush_breakpoints();
+    }
+
+    fn detach(&self) {
+        for slot in self.breakpoints.iter() {
+            slot.get().map(|mut bp| {
+                bp.wanted = false;
+                slot.set(Some(bp));
+            });
+        }
+        self.then.set(Then::Detach);
+        self.flush_breakpoints();
+    }
+
+    fn send_reply(&self, reply: Reply) {
+        if self.tx_buffer.is_none() {
+            self.pending_reply.set(Some(reply));
+            return;
+        }
+        match reply {
+            Reply::Ok => self.send_packet(b"OK"),
+            Reply::Error => self.send_packet(b"E01"),
+            Reply::Stop(signal) => {
+                self.send_packet(&[b'S', hex_char(signal >> 4), hex_char(signal)])
+            }
+        }
+    }
+
+    fn send_packet(&self, data: &[u8]) {
+        self.send_packet_with(|payload| {
+            let len = cmp::min(data.len(), payload.len());
+            payload[..len].copy_from_slice(&data[..len]);
+            len
+        });
+    }
+
+    // Send a packet whose data `fill` writes, acknowledging the last packet
+    // GDB sent if needed. `fill` returns the length of the data.
+    fn send_packet_with<F: FnOnce(&mut [u8]) -> usize>(&self, fill: F) {
+        self.tx_buffer.take().map(|buffer| {
+            let mut start = 0;
+            if self.ack_pending.replace(false) {
+                buffer[0] = b'+';
+                start = 1;
+            }
+            buffer[start] = b'$';
+            let end = buffer.len() - 3;
+            let len = fill(&mut buffer[start + 1..end]);
+            let checksum = buffer[start + 1..start + 1 + len]
+                .iter()
+                .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            let tail = start + 1 + len;
+            buffer[tail] = b'#';
+            buffer[tail + 1] = hex_char(checksum >> 4);
+            buffer[tail + 2] = hex_char(checksum);
+            self.uart.transmit_buffer(buffer, tail + 3);
+        });
+    }
+
+    fn send_raw(&self, data: &[u8]) {
+        self.tx_buffer.take().map(|buffer| {
+            self.ack_pending.set(false);
+            buffer[..data.len()].copy_from_slice(data);
+            self.uart.transmit_buffer(buffer, data.len());
+        });
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> ProcessDebugger for GdbStub<'a, C> {
+    fn breakpoint_hit(&self, appid: AppId) -> bool {
+        if !self.attached.get() || appid.idx() != self.app.get() {
+            return false;
+        }
+        // The kernel stops the process.
+        self.running.set(false);
+        self.stopped(SIGTRAP);
+        true
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> uart::TransmitClient for GdbStub<'a, C> {
+    fn transmitted_buffer(&self, buffer: &'static mut [u8], _tx_len: usize, _rcode: ReturnCode) {
+        self.tx_buffer.replace(buffer);
+        if let Some(reply) = self.pending_reply.take() {
+            self.send_reply(reply);
+        } else if self.ack_pending.get() {
+            self.send_raw(b"+");
+        }
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> uart::ReceiveClient for GdbStub<'a, C> {
+    fn received_buffer(
+        &self,
+        read_buf: &'static mut [u8],
+        rx_len: usize,
+        _rcode: ReturnCode,
+        error: uart::Error,
+    ) {
+        let byte = read_buf[0];
+        self.uart.receive_buffer(read_buf, 1);
+        if error == uart::Error::None && rx_len == 1 {
+            self.receive_byte(byte);
+        }
+    }
+}
+
+impl<'a, C: ProcessManagementCapability> hil::nonvolatile_storage::NonvolatileStorageClient<'static>
+    for GdbStub<'a, C>
+{
+    fn read_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.flash_buffer.replace(buffer);
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        self.flash_buffer.replace(buffer);
+        self.flash_writing.set(false);
+        self.flash_breakpoint.take().map(|i| {
+            self.breakpoints[i].get().map(|bp| {
+                self.breakpoints[i].set(finish_write(bp));
+            });
+        });
+        self.flush_breakpoints();
+    }
+}
+
+// The breakpoint after its instruction or the original memory was written.
+fn finish_write(mut bp: Breakpoint) -> Option<Breakpoint> {
+    bp.inserted = bp.wanted;
+    if bp.wanted {
+        Some(bp)
+    } else {
+        None
+    }
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn hex_char(value: u8) -> u8 {
+    b"0123456789abcdef"[(value & 0xf) as usize]
+}
+
+fn parse_hex(hex: &[u8]) -> Option<usize> {
+    if hex.is_empty() || hex.len() > 2 * core::mem::size_of::<usize>() {
+        return None;
+    }
+    hex.iter().try_fold(0, |value, &c| {
+        hex_digit(c).map(|digit| value << 4 | digit as usize)
+    })
+}
+
+// Parse `address,length`.
+fn parse_address_length(arguments: &[u8]) -> Option<(usize, usize)> {
+    let mut fields = arguments.splitn(2, |&b| b == b',');
+    let address = fields.next().and_then(parse_hex)?;
+    let length = fields.next().and_then(parse_hex)?;
+    Some((address, length))
+}
+
+// Parse a 32-bit register value, which GDB sends in target byte order.
+fn parse_register_value(hex: &[u8]) -> Option<usize> {
+    let mut bytes = [0; 4];
+    if hex.len() != 8 {
+        return None;
+    }
+    decode_hex(hex, &mut bytes)?;
+    Some(u32::from_le_bytes(bytes) as usize)
+}
+
+fn decode_hex(hex: &[u8], bytes: &mut [u8]) -> Option<()> {
+    for (byte, pair) in bytes.iter_mut().zip(hex.chunks(2)) {
+        *byte = hex_digit(pair[0])? << 4 | hex_digit(*pair.get(1)?)?;
+    }
+    Some(())
+}
+
+fn write_hex_bytes(hex: &mut [u8], bytes: &[u8]) {
+    for (pair, byte) in hex.chunks_mut(2).zip(bytes) {
+        pair[0] = hex_char(byte >> 4);
+        pair[1] = hex_char(*byte);
+    }
+}
+
+// Write `value` in hexadecimal without leading zeros and return the number of
+// digits.
+fn write_hex_number(hex: &mut [u8], value: usize) -> usize {
+    let mut digits = 1;
+    while digits < 2 * core::mem::size_of::<usize>() && value >> (4 * digits) != 0 {
+        digits += 1;
+    }
+    for i in 0..digits {
+        hex[i] = hex_char((value >> (4 * (digits - 1 - i))) as u8);
+    }
+    digits
+}
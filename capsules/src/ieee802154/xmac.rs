@@ -64,6 +64,14 @@
 //! xmac.set_receive_client(mac_device);
 //! xmac.set_config_client(mac_device);
 //! ```
+//!
+//! Tracing
+//! -------
+//!
+//! XMAC traces its state changes, preambles and backoffs with `trace!`, and
+//! warns about failed transmissions. To see the trace, compile the kernel with
+//! the `log_level_trace` feature and enable it at runtime, for example with
+//! `log xmac trace` in the process console.
 
 //
 // TODO: Test no-preamble transmission with randomized backoff, requires 3
@@ -84,6 +92,7 @@ use kernel::hil::radio;
 use kernel::hil::rng::{self, Rng};
 use kernel::hil::time::{self, Alarm, Frequency, Time};
 use kernel::ReturnCode;
+use kernel::{trace, warn};
 
 // Time the radio will remain awake listening for packets before sleeping.
 // Observing the RF233, receive callbacks for preambles are generated only after
@@ -110,7 +119,7 @@ const MAX_TX_BACKOFF_MS: u32 = 10;
 const MAX_RX_SLEEP_DELAY_MS: u32 = MAX_TX_BACKOFF_MS;
 
 #[allow(non_camel_case_types)]
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 enum XMacState {
     // The primary purpose of these states is to manage the timer that runs the
     // protocol and determines the state of the radio (e.g. if in SLEEP, a fired
@@ -182,6 +191,11 @@ impl<R: radio::Radio, A: Alarm<'a>> XMac<'a, R, A> {
         }
     }
 
+    fn set_state(&self, state: XMacState) {
+        trace!("{:?} -> {:?}", self.state.get(), state);
+        self.state.set(state);
+    }
+
     fn sleep_time(&self) -> u32 {
         // TODO (ongoing) modify based on traffic load to efficiently schedule
         // sleep. Currently sleeps for a constant amount of time.
@@ -193,13 +207,13 @@ impl<R: radio::Radio, A: Alarm<'a>> XMac<'a, R, A> {
         if self.state.get() == XMacState::AWAKE {
             // If we should delay sleep (completed RX), set timer accordingly
             if self.delay_sleep.get() {
-                self.state.set(XMacState::DELAY_SLEEP);
+                self.set_state(XMacState::DELAY_SLEEP);
                 self.set_timer_ms::<A>(MAX_RX_SLEEP_DELAY_MS);
 
             // Otherwise, don't sleep if expecting a data packet or transmitting
             } else if !self.rx_pending.get() {
                 self.radio.stop();
-                self.state.set(XMacState::SLEEP);
+                self.set_state(XMacState::SLEEP);
                 self.set_timer_ms::<A>(self.sleep_time());
             }
         }
@@ -243,6 +257,7 @@ impl<R: radio::Radio, A: Alarm<'a>> XMac<'a, R, A> {
                 payload_ies_len: 0,
             };
 
+            trace!("sending preamble {}", self.tx_preamble_seq_num.get());
             self.tx_preamble_seq_num
                 .set(self.tx_preamble_seq_num.get() + 1);
 
@@ -252,6 +267,7 @@ impl<R: radio::Radio, A: Alarm<'a>> XMac<'a, R, A> {
                     result = self.radio.transmit(buf, data_offset + radio::PSDU_OFFSET);
                 }
                 None => {
+                    warn!("cannot encode preamble header");
                     self.tx_preamble_buf.replace(buf);
                     self.call_tx_client(self.tx_payload.take().unwrap(), false, ReturnCode::FAIL);
                     return;
@@ -261,6 +277,7 @@ impl<R: radio::Radio, A: Alarm<'a>> XMac<'a, R, A> {
 
         // If the transmission fails, callback directly back into the client
         if result.0 != ReturnCode::SUCCESS {
+            warn!("preamble transmission failed: {:?}", result.0);
             self.call_tx_client(result.1.unwrap(), false, result.0);
         }
     }
@@ -275,6 +292,7 @@ impl<R: radio::Radio, A: Alarm<'a>> XMac<'a, R, A> {
             result = self.radio.transmit(tx_buf, self.tx_len.get());
 
             if result.0 != ReturnCode::SUCCESS {
+                warn!("data transmission failed: {:?}", result.0);
                 self.call_tx_client(result.1.unwrap(), false, result.0);
             }
         }
@@ -283,7 +301,7 @@ impl<R: radio::Radio, A: Alarm<'a>> XMac<'a, R, A> {
     // Reports back to client that transmission is complete, radio can turn off
     // if not kept awake by other portions of the protocol.
     fn call_tx_client(&self, buf: &'static mut [u8], acked: bool, result: ReturnCode) {
-        self.state.set(XMacState::AWAKE);
+        self.set_state(XMacState::AWAKE);
         self.sleep();
         self.tx_client.map(move |c| {
             c.send_done(buf, acked, result);
@@ -329,6 +347,11 @@ impl<R: radio::Radio, A: Alarm<'a>> rng::Client for XMac<'a, R, A> {
                         (((self.alarm.get_alarm().wrapping_sub(self.alarm.now())) as f32
                             / <A::Frequency>::frequency() as f32)
                             * 1000.0) as u32;
+                    trace!(
+                        "backing off {} of {} ms",
+                        random % time_remaining_ms,
+                        time_remaining_ms
+                    );
                     self.set_timer_ms::<A>(random % time_remaining_ms);
                 }
                 rng::Continue::Done
@@ -342,7 +365,7 @@ impl<R: radio::Radio, A: Alarm<'a>> rng::Client for XMac<'a, R, A> {
 impl<R: radio::Radio, A: Alarm<'a>> Mac for XMac<'a, R, A> {
     fn initialize(&self, mac_buf: &'static mut [u8]) -> ReturnCode {
         self.tx_preamble_buf.replace(mac_buf);
-        self.state.set(XMacState::STARTUP);
+        self.set_state(XMacState::STARTUP);
         ReturnCode::SUCCESS
     }
 
@@ -442,14 +465,14 @@ impl<R: radio::Radio, A: Alarm<'a>> Mac for XMac<'a, R, A> {
 
         // If the radio is on, start the preamble timer and start transmitting
         if self.radio.is_on() {
-            self.state.set(XMacState::TX);
+            self.set_state(XMacState::TX);
             //self.set_timer_ms::<A>(PREAMBLE_TX_MS);
             self.transmit_packet();
 
         // If the radio is currently sleeping, wake it and indicate that when
         // ready, it should begin transmitting preambles
         } else {
-            self.state.set(XMacState::STARTUP);
+            self.set_state(XMacState::STARTUP);
             self.tx_preamble_pending.set(true);
             self.radio.start();
         }
@@ -467,11 +490,11 @@ impl<R: radio::Radio, A: Alarm<'a>> time::AlarmClient for XMac<'a, R, A> {
                 // If asleep, start the radio and wait for the PowerClient to
                 // indicate that the radio is ready
                 if !self.radio.is_on() {
-                    self.state.set(XMacState::STARTUP);
+                    self.set_state(XMacState::STARTUP);
                     self.radio.start();
                 } else {
                     self.set_timer_ms::<A>(WAKE_TIME_MS);
-                    self.state.set(XMacState::AWAKE);
+                    self.set_state(XMacState::AWAKE);
                 }
             }
             // If we've been delaying sleep or haven't heard any incoming
@@ -481,18 +504,19 @@ impl<R: radio::Radio, A: Alarm<'a>> time::AlarmClient for XMac<'a, R, A> {
             }
             XMacState::DELAY_SLEEP => {
                 self.delay_sleep.set(false);
-                self.state.set(XMacState::AWAKE);
+                self.set_state(XMacState::AWAKE);
                 self.sleep();
             }
             // If we've sent preambles for longer than the maximum sleep time of
             // any node in the network, then our destination is non-responsive;
             // return ENOACK to the client.
             XMacState::TX_PREAMBLE => {
+                warn!("no answer to {} preambles", self.tx_preamble_seq_num.get());
                 self.call_tx_client(self.tx_payload.take().unwrap(), false, ReturnCode::ENOACK);
             }
             // After a randomized backoff period, transmit the data directly.
             XMacState::TX_DELAY => {
-                self.state.set(XMacState::TX);
+                self.set_state(XMacState::TX);
                 self.transmit_packet();
             }
             _ => {}
@@ -509,11 +533,11 @@ impl<R: radio::Radio, A: Alarm<'a>> radio::PowerClient for XMac<'a, R, A> {
             if let XMacState::STARTUP = self.state.get() {
                 if self.tx_preamble_pending.get() {
                     self.tx_preamble_pending.set(false);
-                    self.state.set(XMacState::TX);
+                    self.set_state(XMacState::TX);
                     //self.set_timer_ms::<A>(PREAMBLE_TX_MS);
                     self.transmit_packet();
                 } else {
-                    self.state.set(XMacState::AWAKE);
+                    self.set_state(XMacState::AWAKE);
                     self.set_timer_ms::<A>(WAKE_TIME_MS);
                 }
             }
@@ -533,7 +557,7 @@ impl<R: radio::Radio, A: Alarm<'a>> radio::TxClient for XMac<'a, R, A> {
                 self.tx_preamble_buf.replace(buf);
                 if acked {
                     // Destination signals ready to receive data
-                    self.state.set(XMacState::TX);
+                    self.set_state(XMacState::TX);
                     self.transmit_packet();
                 } else {
                     // Continue resending preambles
@@ -586,7 +610,7 @@ impl<R: radio::Radio, A: Alarm<'a>> radio::RxClient for XMac<'a, R, A> {
                                 // Randomize backoff - since the callback is asynchronous, set the
                                 // timer for the max and adjust later. As a result, we can't
                                 // backoff for more than the Rng generation time.
-                                self.state.set(XMacState::TX_DELAY);
+                                self.set_state(XMacState::TX_DELAY);
                                 self.rng.get();
                                 self.set_timer_ms::<A>(MAX_TX_BACKOFF_MS);
                                 continue_sleep = false;
@@ -597,10 +621,12 @@ impl<R: radio::Radio, A: Alarm<'a>> radio::RxClient for XMac<'a, R, A> {
                     // We've received either a preamble or data packet
                     match header.frame_type {
                         FrameType::Multipurpose => {
+                            trace!("received preamble, waiting for data");
                             continue_sleep = false;
                             self.rx_pending.set(true);
                         }
                         FrameType::Data => {
+                            trace!("received {} byte data frame", frame_len);
                             continue_sleep = false;
                             data_received = true;
                         }
@@ -616,7 +642,7 @@ impl<R: radio::Radio, A: Alarm<'a>> radio::RxClient for XMac<'a, R, A> {
         // RF233 with the added line at rf233.rs:744. In progress: it might be
         // possible to remove this requirement.
         if self.state.get() == XMacState::SLEEP {
-            self.state.set(XMacState::AWAKE);
+            self.set_state(XMacState::AWAKE);
         }
 
         if data_received {
@@ -0,0 +1,75 @@
+//! Time source for the timeouts of IPC messages.
+//!
+//! The kernel checks whether IPC messages timed out every time it loops. This
+//! capsule tells it the time and sets an alarm so that the chip does not sleep
+//! past the next timeout. Without it, IPC messages never time out.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ipc_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let ipc_timer = static_init!(
+//!     capsules::ipc_timer::IpcTimer<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     capsules::ipc_timer::IpcTimer::new(ipc_alarm)
+//! );
+//! ipc_alarm.set_client(ipc_timer);
+//! platform.ipc.set_timer(ipc_timer);
+//! ```
+
+use core::cmp;
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::ipc;
+
+pub struct IpcTimer<'a, A: Alarm<'a>> {
+    alarm: &'a A,
+}
+
+impl<A: Alarm<'a>> IpcTimer<'a, A> {
+    pub fn new(alarm: &'a A) -> IpcTimer<'a, A> {
+        IpcTimer { alarm: alarm }
+    }
+}
+
+impl<A: Alarm<'a>> ipc::Timer for IpcTimer<'a, A> {
+    fn now(&self) -> u32 {
+        self.alarm.now()
+    }
+
+    fn ms_to_tics(&self, ms: u32) -> u32 {
+        let tics = ms as u64 * <A::Frequency>::frequency() as u64 / 1000;
+        // Longer timeouts are cut to half the range of the clock, so that the
+        // time elapsed does not wrap around before the kernel checks it
+        cmp::min(tics, (self.alarm.max_tics() / 2) as u64) as u32
+    }
+
+    fn wake_at(&self, tics: u32) {
+        self.alarm.set_alarm(tics);
+    }
+}
+
+impl<A: Alarm<'a>> time::AlarmClient for IpcTimer<'a, A> {
+    fn fired(&self) {
+        // Nothing to do, the interrupt woke up the kernel loop, which checks
+        // the timeouts.
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net::mock::MockAlarm;
+    use kernel::ipc::Timer;
+
+    #[test]
+    fn long_timeouts_are_cut() {
+        let alarm = MockAlarm::new();
+        let timer = IpcTimer::new(&alarm);
+        // The mock alarm counts milliseconds
+        assert_eq!(timer.ms_to_tics(1500), 1500);
+        assert_eq!(timer.ms_to_tics(core::u32::MAX), core::u32::MAX / 2);
+    }
+}
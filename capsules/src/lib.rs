@@ -25,12 +25,14 @@ pub mod debug_process_restart;
 pub mod driver;
 pub mod fm25cl;
 pub mod fxos8700cq;
+pub mod gdb_stub;
 pub mod gpio;
 pub mod gpio_async;
 pub mod humidity;
 pub mod i2c_master;
 pub mod i2c_master_slave_driver;
 pub mod ieee802154;
+pub mod ipc_timer;
 pub mod isl29035;
 pub mod led;
 pub mod low_level_debug;
@@ -45,6 +47,7 @@ pub mod nonvolatile_to_pages;
 pub mod nrf51822_serialization;
 pub mod pca9544a;
 pub mod process_console;
+pub mod process_info;
 pub mod rf233;
 pub mod rf233_const;
 pub mod rng;
@@ -56,9 +59,12 @@ pub mod temperature;
 pub mod tmp006;
 pub mod tsl2561;
 pub mod usb;
+pub mod virtual_aes_ccm;
 pub mod virtual_alarm;
+pub mod virtual_entropy;
 pub mod virtual_flash;
 pub mod virtual_i2c;
 pub mod virtual_pwm;
 pub mod virtual_spi;
 pub mod virtual_uart;
+pub mod watchdog;
@@ -0,0 +1,1700 @@
+//! This file implements the messaging and request/response layers of CoAP
+//! (RFC 7252) over UDP, for a client and a server that share port 5683.
+//!
+//! Confirmable messages are retransmitted with exponential backoff until they
+//! are acknowledged, and the message IDs of received messages are remembered
+//! for `EXCHANGE_LIFETIME` so that duplicates are acknowledged again instead
+//! of being processed twice.
+//!
+//! The client sends one request at a time. Its payload, and the payload of
+//! the response, can be larger than a message: the request is then sent in
+//! Block1 blocks, and the response is fetched in Block2 blocks (RFC 7959)
+//! and reassembled in the buffer of the request. The token of each request
+//! is drawn from a random number generator, which also reseeds the
+//! generator of the random retransmission timeouts.
+//!
+//! The server passes the requests it receives to its `CoapServer` and
+//! answers each with the response given to `respond`. It handles one request
+//! at a time, and answers the others with 5.03 (Service Unavailable) in the
+//! meantime. If the response is not given shortly, the request is
+//! acknowledged right away and the response follows in a confirmable
+//! message of its own. Requests sent in Block1 blocks are reassembled before
+//! they are passed on, and responses larger than a block are kept to answer
+//! the requests for their later Block2 blocks.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use capsules::net::coap::coap::{Coap, COAP_PORT};
+//! # use kernel::static_init;
+//! let coap = static_init!(
+//!     Coap<'static, VirtualMuxAlarm<'static, Ast>, UDPSendStruct<'static, ...>>,
+//!     Coap::new(udp_send, coap_alarm, rng, seed, &mut CLIENT_TX_BUF, &mut SERVER_TX_BUF, &mut BODY_BUF)
+//! );
+//! udp_send.set_client(coap);
+//! coap_alarm.set_client(coap);
+//! rng.set_client(coap);
+//! // Receives the packets sent to the CoAP port
+//! let coap_udp_client = static_init!(UDPKernelClient<'static>, UDPKernelClient::new(COAP_PORT, coap));
+//! udp_recv.add_kernel_client(coap_udp_client);
+//! coap.set_client(client);
+//! coap.set_server(server);
+//! ```
+
+// Known Limitations
+// -----------------
+// - Requests with critical options other than Uri-Host, Uri-Port, Uri-Path,
+//   Block1 and Block2, e.g. Uri-Query, are rejected with 4.02 (Bad Option).
+// - Observe (RFC 7641) is not implemented.
+// - The client does not send requests in blocks smaller than `BLOCK_SZX`,
+//   but follows the server if it asks for smaller ones.
+// - Only the last reply that does not carry a payload and the last
+//   piggybacked response are kept to answer duplicate requests: duplicates
+//   of older requests are acknowledged with an empty ACK.
+
+use crate::net::coap::message::{self, code, option_number};
+use crate::net::coap::message::{BlockOption, Header, Message, MessageType, MessageWriter, Token};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+use core::cell::Cell;
+use core::cmp::min;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::rng::{self, Rng};
+use kernel::hil::time::{self, Frequency};
+use kernel::ReturnCode;
+
+pub const COAP_PORT: u16 = 5683;
+
+/// The largest message sent, which the transmit buffers should hold.
+pub const MAX_MESSAGE_LEN: usize = 160;
+
+/// The size exponent of the blocks of large payloads, for blocks of 64
+/// bytes.
+pub const BLOCK_SZX: u8 = 2;
+pub const BLOCK_SIZE: usize = 1 << (BLOCK_SZX + 4);
+
+/// The longest path of a resource, without its leading '/'.
+pub const MAX_PATH_LEN: usize = 32;
+
+const TICK_MS: u32 = 100;
+
+// Transmission parameters (RFC 7252, Section 4.8). The first timeout of a
+// confirmable message is picked between ACK_TIMEOUT and ACK_TIMEOUT times
+// ACK_RANDOM_FACTOR, which is 1.5, and doubles at each retransmission.
+const ACK_TIMEOUT_MS: u32 = 2_000;
+const MAX_RETRANSMIT: u8 = 4;
+const EXCHANGE_LIFETIME_MS: u32 = 247_000;
+const MAX_TRANSMIT_WAIT_MS: u32 = 93_000;
+
+// The server acknowledges a request right away if the response is not given
+// within ACK_DELAY_MS, and answers it with 5.00 (Internal Server Error) if
+// the response is not given within RESPONSE_TIMEOUT_MS.
+const ACK_DELAY_MS: u32 = 1_000;
+const RESPONSE_TIMEOUT_MS: u32 = 10_000;
+
+// The number of received message IDs remembered to detect duplicates
+const SEEN_LEN: usize = 8;
+
+// Replies carry no payload, and at most a Block1 and a Size1 option
+const REPLY_LEN: usize = 4 + 8 + 4 + 6;
+
+/// The client of the requests sent with `Coap::request`.
+pub trait CoapClient {
+    /// Called when a request ends. `result` is SUCCESS if a response
+    /// arrived, in which case `code` is its code and the first `len` bytes
+    /// of `buf` are its payload; ESIZE if its payload did not fit in `buf`,
+    /// of which `len` bytes are the start of it; ENOACK if the server did
+    /// not acknowledge the request; and FAIL if the server reset it or did
+    /// not respond in time.
+    fn response(&self, result: ReturnCode, code: u8, buf: &'static mut [u8], len: usize);
+}
+
+/// The server that answers the requests received.
+pub trait CoapServer {
+    /// Called when a request for `path`, whose segments are separated by
+    /// '/', arrives. Returns SUCCESS if the server answers it later with
+    /// `Coap::respond`, and ENOSUPPORT if it has no resource at `path`.
+    fn request(&self, code: u8, path: &[u8], payload: &[u8]) -> ReturnCode;
+}
+
+/// The address and port of a peer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Endpoint {
+    pub addr: IPAddr,
+    pub port: u16,
+}
+
+/// The path of a resource, as segments separated by '/'.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Path {
+    bytes: [u8; MAX_PATH_LEN],
+    len: usize,
+}
+
+impl Path {
+    fn new() -> Path {
+        Path {
+            bytes: [0; MAX_PATH_LEN],
+            len: 0,
+        }
+    }
+
+    /// Returns the path made of the segments of `path`, without empty
+    /// segments, or None if it is too long.
+    pub fn parse(path: &[u8]) -> Option<Path> {
+        let mut parsed = Path::new();
+        for segment in path.split(|b| *b == b'/').filter(|s| !s.is_empty()) {
+            if !parsed.push_segment(segment) {
+                return None;
+            }
+        }
+        Some(parsed)
+    }
+
+    /// Returns the path made of the Uri-Path options of `message`, or None
+    /// if it is too long.
+    fn from_message(message: &Message) -> Option<Path> {
+        let mut path = Path::new();
+        for (number, value) in message.options() {
+            if number == option_number::URI_PATH && !path.push_segment(value) {
+                return None;
+            }
+        }
+        Some(path)
+    }
+
+    fn push_segment(&mut self, segment: &[u8]) -> bool {
+        let separator = if self.len > 0 { 1 } else { 0 };
+        if self.len + separator + segment.len() > MAX_PATH_LEN {
+            return false;
+        }
+        if separator > 0 {
+            self.bytes[self.len] = b'/';
+        }
+        let start = self.len + separator;
+        self.bytes[start..start + segment.len()].copy_from_slice(segment);
+        self.len = start + segment.len();
+        true
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// The state of the retransmissions of a confirmable message.
+#[derive(Copy, Clone)]
+struct Retransmit {
+    timeout_ms: u32,
+    count: u8,
+}
+
+impl Retransmit {
+    fn next(self) -> Retransmit {
+        Retransmit {
+            timeout_ms: self.timeout_ms * 2,
+            count: self.count + 1,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+enum RequestState {
+    /// Waiting for the random token, before the first message is sent.
+    NoToken,
+    /// Waiting for the acknowledgement of a confirmable request, which may
+    /// carry the response.
+    WaitAck { retransmit: Retransmit },
+    /// Waiting for the response, which is sent separately.
+    WaitResponse,
+}
+
+/// The request of the client.
+#[derive(Copy, Clone)]
+struct Request {
+    endpoint: Endpoint,
+    code: u8,
+    confirmable: bool,
+    token: Token,
+    message_id: u16,
+    path: Path,
+    // The length of the payload in the buffer of the request
+    len: usize,
+    // The block of the payload in the last message, if it is sent in blocks
+    block1: Option<BlockOption>,
+    // The block of the response requested by the last message, if the
+    // response is fetched in blocks
+    block2: Option<BlockOption>,
+    // The length of the response received so far
+    received: usize,
+    state: RequestState,
+}
+
+#[derive(Copy, Clone)]
+enum ExchangeState {
+    /// Waiting for the server to respond. `acked` is whether the request
+    /// was acknowledged already.
+    Processing { acked: bool },
+    /// Waiting for the acknowledgement of the confirmable message that
+    /// carries the response.
+    Responding {
+        message_id: u16,
+        retransmit: Retransmit,
+    },
+}
+
+/// A request received by the server, and the state of its response.
+#[derive(Copy, Clone)]
+struct Exchange {
+    endpoint: Endpoint,
+    confirmable: bool,
+    message_id: u16,
+    token: Token,
+    path: Path,
+    // The last block of a request sent in blocks, which the response echoes
+    block1: Option<BlockOption>,
+    // The block of the response requested
+    block2: Option<BlockOption>,
+    state: ExchangeState,
+}
+
+impl Exchange {
+    fn new(
+        endpoint: Endpoint,
+        header: &Header,
+        path: Path,
+        block1: Option<BlockOption>,
+        block2: Option<BlockOption>,
+    ) -> Exchange {
+        Exchange {
+            endpoint,
+            confirmable: header.mtype == MessageType::Confirmable,
+            message_id: header.message_id,
+            token: header.token,
+            path,
+            block1,
+            block2,
+            state: ExchangeState::Processing { acked: false },
+        }
+    }
+}
+
+/// What the body buffer of the server holds.
+#[derive(Copy, Clone)]
+enum Body {
+    /// The first `len` bytes of a request sent in blocks.
+    Upload {
+        endpoint: Endpoint,
+        path: Path,
+        len: usize,
+    },
+    /// The last response, of `len` bytes, whose later blocks may be
+    /// requested.
+    Response {
+        endpoint: Endpoint,
+        path: Path,
+        code: u8,
+        len: usize,
+    },
+}
+
+/// A message received recently, to detect duplicates.
+#[derive(Copy, Clone)]
+struct Seen {
+    endpoint: Endpoint,
+    message_id: u16,
+    time: u32,
+}
+
+/// A message without payload: an empty acknowledgement or reset, or a reply
+/// to a request that does not involve the server.
+#[derive(Copy, Clone)]
+struct Reply {
+    endpoint: Endpoint,
+    header: Header,
+    block1: Option<BlockOption>,
+    size1: Option<u32>,
+}
+
+impl Reply {
+    fn empty(endpoint: Endpoint, mtype: MessageType, message_id: u16) -> Reply {
+        Reply {
+            endpoint,
+            header: Header {
+                mtype,
+                code: code::EMPTY,
+                message_id,
+                token: Token::empty(),
+            },
+            block1: None,
+            size1: None,
+        }
+    }
+
+    fn encode(&self, buf: &mut [u8]) -> Result<usize, ReturnCode> {
+        let mut writer = MessageWriter::new(buf, &self.header)?;
+        if let Some(block1) = self.block1 {
+            writer.add_uint_option(option_number::BLOCK1, block1.encode())?;
+        }
+        if let Some(size1) = self.size1 {
+            writer.add_uint_option(option_number::SIZE1, size1)?;
+        }
+        Ok(writer.finish())
+    }
+}
+
+pub struct Coap<'a, A: time::Alarm<'a>, U: UDPSender<'a>> {
+    udp_sender: &'a U,
+    alarm: &'a A,
+    rng: &'a dyn Rng<'a>,
+    client: OptionalCell<&'a dyn CoapClient>,
+    server: OptionalCell<&'a dyn CoapServer>,
+
+    // Messages are sent one at a time, and the reply, the message of the
+    // server and the message of the client wait for their turn in this
+    // order
+    sending: Cell<bool>,
+    reply: Cell<Option<Reply>>,
+    last_reply: Cell<Option<Reply>>,
+    seen: Cell<[Option<Seen>; SEEN_LEN]>,
+    message_id: Cell<u16>,
+
+    request: Cell<Option<Request>>,
+    request_buf: TakeCell<'static, [u8]>,
+    client_tx_buf: TakeCell<'static, [u8]>,
+    client_tx_len: Cell<usize>,
+    client_queued: Cell<bool>,
+    client_timer: Cell<Option<u32>>,
+
+    exchange: Cell<Option<Exchange>>,
+    server_tx_buf: TakeCell<'static, [u8]>,
+    server_tx_len: Cell<usize>,
+    server_tx_endpoint: Cell<Endpoint>,
+    server_queued: Cell<bool>,
+    server_timer: Cell<Option<u32>>,
+    // The request that the piggybacked response in `server_tx_buf` answers
+    server_acked: Cell<Option<(Endpoint, u16)>>,
+    body: TakeCell<'static, [u8]>,
+    body_state: Cell<Option<Body>>,
+
+    ticking: Cell<bool>,
+    // State of the generator of the timeouts, which is never 0
+    random: Cell<u32>,
+}
+
+impl<A: time::Alarm<'a>, U: UDPSender<'a>> Coap<'a, A, U> {
+    /// `seed` should differ between devices, e.g. be derived from their
+    /// extended address, as message IDs start from it. `client_tx_buf` and
+    /// `server_tx_buf` should be `MAX_MESSAGE_LEN` bytes long. `body` holds
+    /// the requests received in blocks and the responses sent in blocks,
+    /// which are limited to its length.
+    pub fn new(
+        udp_sender: &'a U,
+        alarm: &'a A,
+        rng: &'a dyn Rng<'a>,
+        seed: u32,
+        client_tx_buf: &'static mut [u8],
+        server_tx_buf: &'static mut [u8],
+        body: &'static mut [u8],
+    ) -> Coap<'a, A, U> {
+        Coap {
+            udp_sender,
+            alarm,
+            rng,
+            client: OptionalCell::empty(),
+            server: OptionalCell::empty(),
+            sending: Cell::new(false),
+            reply: Cell::new(None),
+            last_reply: Cell::new(None),
+            seen: Cell::new([None; SEEN_LEN]),
+            message_id: Cell::new(seed as u16),
+            request: Cell::new(None),
+            request_buf: TakeCell::empty(),
+            client_tx_buf: TakeCell::new(client_tx_buf),
+            client_tx_len: Cell::new(0),
+            client_queued: Cell::new(false),
+            client_timer: Cell::new(None),
+            exchange: Cell::new(None),
+            server_tx_buf: TakeCell::new(server_tx_buf),
+            server_tx_len: Cell::new(0),
+            server_tx_endpoint: Cell::new(Endpoint {
+                addr: IPAddr::new(),
+                port: 0,
+            }),
+            server_queued: Cell::new(false),
+            server_timer: Cell::new(None),
+            server_acked: Cell::new(None),
+            body: TakeCell::new(body),
+            body_state: Cell::new(None),
+            ticking: Cell::new(false),
+            random: Cell::new(seed | 1),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn CoapClient) {
+        self.client.set(client);
+    }
+
+    pub fn set_server(&self, server: &'a dyn CoapServer) {
+        self.server.set(server);
+    }
+
+    /// Sends a request with the first `len` bytes of `buf` as payload to the
+    /// resource at `path` of `endpoint`, and receives the response into
+    /// `buf`. The request is sent once it has a random token, and the client
+    /// is told when it ends. Returns EBUSY if a request is in progress,
+    /// EINVAL if `code` is not the code of a request or `len` is larger than
+    /// `buf`, and ESIZE if `path` is too long.
+    pub fn request(
+        &self,
+        endpoint: Endpoint,
+        code: u8,
+        confirmable: bool,
+        path: &[u8],
+        buf: &'static mut [u8],
+        len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.request.get().is_some() {
+            return (ReturnCode::EBUSY, Some(buf));
+        }
+        if !message::is_request(code) || len > buf.len() {
+            return (ReturnCode::EINVAL, Some(buf));
+        }
+        let path = match Path::parse(path) {
+            Some(path) => path,
+            None => return (ReturnCode::ESIZE, Some(buf)),
+        };
+
+        let block1 = if len > BLOCK_SIZE {
+            Some(BlockOption {
+                num: 0,
+                more: true,
+                szx: BLOCK_SZX,
+            })
+        } else {
+            None
+        };
+        let request = Request {
+            endpoint,
+            code,
+            confirmable,
+            token: Token::empty(),
+            message_id: 0,
+            path,
+            len,
+            block1,
+            block2: None,
+            received: 0,
+            state: RequestState::NoToken,
+        };
+        let result = self.rng.get();
+        if result != ReturnCode::SUCCESS {
+            return (result, Some(buf));
+        }
+        self.request_buf.replace(buf);
+        self.request.set(Some(request));
+        self.set_timer(&self.client_timer, MAX_TRANSMIT_WAIT_MS);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    /// Answers the request passed to the server with a response of `code`
+    /// with `payload`, which is sent in blocks if it is larger than a block.
+    /// Returns EINVAL if no request waits for a response or `code` is not
+    /// the code of a response, ESIZE if `payload` is larger than the body
+    /// buffer, and EBUSY if it is called while the request is passed to the
+    /// server.
+    pub fn respond(&self, code: u8, payload: &[u8]) -> ReturnCode {
+        let exchange = match self.exchange.get() {
+            Some(exchange) => exchange,
+            None => return ReturnCode::EINVAL,
+        };
+        if let ExchangeState::Responding { .. } = exchange.state {
+            return ReturnCode::EINVAL;
+        }
+        if !message::is_response(code) {
+            return ReturnCode::EINVAL;
+        }
+        let result = self.body.map_or(ReturnCode::EBUSY, |body| {
+            if payload.len() > body.len() {
+                return ReturnCode::ESIZE;
+            }
+            body[..payload.len()].copy_from_slice(payload);
+            ReturnCode::SUCCESS
+        });
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+        self.body_state.set(Some(Body::Response {
+            endpoint: exchange.endpoint,
+            path: exchange.path,
+            code,
+            len: payload.len(),
+        }));
+        self.send_response(exchange, code, payload.len())
+    }
+
+    fn ms_to_tics(ms: u32) -> u32 {
+        (ms as u64 * <A::Frequency>::frequency() as u64 / 1000) as u32
+    }
+
+    /// Starts a countdown timer, which is counted down in ticks.
+    fn set_timer(&self, timer: &Cell<Option<u32>>, ms: u32) {
+        timer.set(Some(ms));
+        self.start_tick();
+    }
+
+    fn start_tick(&self) {
+        if !self.ticking.get() {
+            self.ticking.set(true);
+            self.alarm
+                .set_alarm(self.alarm.now().wrapping_add(Self::ms_to_tics(TICK_MS)));
+        }
+    }
+
+    /// Counts down a timer, and returns whether it expired.
+    fn expired(timer: &Cell<Option<u32>>) -> bool {
+        match timer.get() {
+            Some(remaining) if remaining <= TICK_MS => {
+                timer.set(None);
+                true
+            }
+            Some(remaining) => {
+                timer.set(Some(remaining - TICK_MS));
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Returns a pseudo-random number below `max`, or any number if `max`
+    /// is 0. The generator is reseeded from the random number generator at
+    /// each request.
+    fn random_below(&self, max: u32) -> u32 {
+        // xorshift32
+        let mut x = self.random.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random.set(x);
+        if max == 0 {
+            x
+        } else {
+            x % max
+        }
+    }
+
+    fn new_retransmit(&self) -> Retransmit {
+        Retransmit {
+            timeout_ms: ACK_TIMEOUT_MS + self.random_below(ACK_TIMEOUT_MS / 2),
+            count: 0,
+        }
+    }
+
+    fn next_message_id(&self) -> u16 {
+        let message_id = self.message_id.get().wrapping_add(1);
+        self.message_id.set(message_id);
+        message_id
+    }
+
+    /// Returns whether the message was received before, and remembers it
+    /// otherwise in place of the oldest message remembered.
+    fn is_duplicate(&self, endpoint: Endpoint, message_id: u16) -> bool {
+        let now = self.alarm.now();
+        let lifetime = Self::ms_to_tics(EXCHANGE_LIFETIME_MS);
+        let mut seen = self.seen.get();
+        let duplicate = seen.iter().flatten().any(|s| {
+            s.endpoint == endpoint
+                && s.message_id == message_id
+                && now.wrapping_sub(s.time) < lifetime
+        });
+        if duplicate {
+            return true;
+        }
+        let oldest = seen
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, s)| s.map_or(u32::max_value(), |s| now.wrapping_sub(s.time)))
+            .map_or(0, |(i, _)| i);
+        seen[oldest] = Some(Seen {
+            endpoint,
+            message_id,
+            time: now,
+        });
+        self.seen.set(seen);
+        false
+    }
+
+    fn send(&self, endpoint: Endpoint, buf: &[u8]) -> bool {
+        let result = self
+            .udp_sender
+            .send_to(endpoint.addr, endpoint.port, COAP_PORT, buf);
+        self.sending.set(result == ReturnCode::SUCCESS);
+        self.sending.get()
+    }
+
+    /// Sends the next message waiting for its turn, if no message is being
+    /// sent.
+    fn flush(&self) {
+        if self.sending.get() {
+            return;
+        }
+        if let Some(reply) = self.reply.take() {
+            let mut buf = [0; REPLY_LEN];
+            if let Ok(len) = reply.encode(&mut buf) {
+                if self.send(reply.endpoint, &buf[..len]) {
+                    return;
+                }
+            }
+        }
+        if self.server_queued.replace(false) {
+            let endpoint = self.server_tx_endpoint.get();
+            let len = self.server_tx_len.get();
+            let sent = self
+                .server_tx_buf
+                .map_or(false, |buf| self.send(endpoint, &buf[..len]));
+            if sent {
+                return;
+            }
+        }
+        if self.client_queued.replace(false) {
+            if let Some(request) = self.request.get() {
+                let len = self.client_tx_len.get();
+                self.client_tx_buf
+                    .map(|buf| self.send(request.endpoint, &buf[..len]));
+            }
+        }
+    }
+
+    fn queue_reply(&self, reply: Reply) {
+        self.reply.set(Some(reply));
+        self.last_reply.set(Some(reply));
+        self.flush();
+    }
+
+    /// Replies to a request without involving the server. The reply is
+    /// piggybacked on the acknowledgement of a confirmable request.
+    fn reply_to(
+        &self,
+        endpoint: Endpoint,
+        header: &Header,
+        code: u8,
+        block1: Option<BlockOption>,
+        size1: Option<u32>,
+    ) {
+        let (mtype, message_id) = match header.mtype {
+            MessageType::Confirmable => (MessageType::Acknowledgement, header.message_id),
+            _ => (MessageType::NonConfirmable, self.next_message_id()),
+        };
+        self.queue_reply(Reply {
+            endpoint,
+            header: Header {
+                mtype,
+                code,
+                message_id,
+                token: header.token,
+            },
+            block1,
+            size1,
+        });
+    }
+
+    /// Sends the next message of the request.
+    fn send_request(&self, mut request: Request) -> ReturnCode {
+        request.message_id = self.next_message_id();
+        let header = Header {
+            mtype: if request.confirmable {
+                MessageType::Confirmable
+            } else {
+                MessageType::NonConfirmable
+            },
+            code: request.code,
+            message_id: request.message_id,
+            token: request.token,
+        };
+        let result = self
+            .client_tx_buf
+            .map_or(Err(ReturnCode::ENOMEM), |tx_buf| {
+                self.request_buf.map_or(Err(ReturnCode::ENOMEM), |buf| {
+                    encode_request(tx_buf, &header, &request, buf)
+                })
+            });
+        let len = match result {
+            Ok(len) => len,
+            Err(err) => return err,
+        };
+        self.client_tx_len.set(len);
+        if request.confirmable {
+            let retransmit = self.new_retransmit();
+            request.state = RequestState::WaitAck { retransmit };
+            self.set_timer(&self.client_timer, retransmit.timeout_ms);
+        } else {
+            request.state = RequestState::WaitResponse;
+            self.set_timer(&self.client_timer, MAX_TRANSMIT_WAIT_MS);
+        }
+        self.request.set(Some(request));
+        self.client_queued.set(true);
+        self.flush();
+        ReturnCode::SUCCESS
+    }
+
+    /// Ends the request, and gives its buffer back to the client.
+    fn finish_request(&self, result: ReturnCode, code: u8, len: usize) {
+        self.request.set(None);
+        self.client_timer.set(None);
+        self.client_queued.set(false);
+        if let Some(buf) = self.request_buf.take() {
+            self.client
+                .map(move |client| client.response(result, code, buf, len));
+        }
+    }
+
+    fn client_timeout(&self) {
+        let mut request = match self.request.get() {
+            Some(request) => request,
+            None => return,
+        };
+        match request.state {
+            RequestState::WaitAck { retransmit } if retransmit.count < MAX_RETRANSMIT => {
+                let retransmit = retransmit.next();
+                request.state = RequestState::WaitAck { retransmit };
+                self.request.set(Some(request));
+                self.set_timer(&self.client_timer, retransmit.timeout_ms);
+                self.client_queued.set(true);
+                self.flush();
+            }
+            RequestState::WaitAck { .. } => self.finish_request(ReturnCode::ENOACK, 0, 0),
+            RequestState::NoToken | RequestState::WaitResponse => {
+                self.finish_request(ReturnCode::FAIL, 0, 0)
+            }
+        }
+    }
+
+    /// Sends the response to a request of the exchange, or the block of it
+    /// that the request asked for, from the first `len` bytes of the body
+    /// buffer.
+    fn send_response(&self, mut exchange: Exchange, code: u8, len: usize) -> ReturnCode {
+        // Blocks are at most BLOCK_SIZE long, and start at the same offset
+        // as the block requested
+        let block2 = match exchange.block2 {
+            Some(block2) if block2.szx > BLOCK_SZX => Some(BlockOption {
+                num: block2.num << (block2.szx - BLOCK_SZX),
+                more: false,
+                szx: BLOCK_SZX,
+            }),
+            Some(block2) => Some(block2),
+            None if len > BLOCK_SIZE => Some(BlockOption {
+                num: 0,
+                more: false,
+                szx: BLOCK_SZX,
+            }),
+            None => None,
+        };
+        let (code, block2, len) = match block2 {
+            Some(block2) if block2.offset() > len || (block2.offset() == len && len > 0) => {
+                (code::BAD_OPTION, None, 0)
+            }
+            Some(block2) => (
+                code,
+                Some(BlockOption {
+                    more: block2.offset() + block2.size() < len,
+                    ..block2
+                }),
+                len,
+            ),
+            None => (code, None, len),
+        };
+
+        let (mtype, message_id) = match exchange.state {
+            ExchangeState::Processing { acked: false } if exchange.confirmable => {
+                (MessageType::Acknowledgement, exchange.message_id)
+            }
+            _ if exchange.confirmable => (MessageType::Confirmable, self.next_message_id()),
+            _ => (MessageType::NonConfirmable, self.next_message_id()),
+        };
+        let header = Header {
+            mtype,
+            code,
+            message_id,
+            token: exchange.token,
+        };
+        let result = self
+            .server_tx_buf
+            .map_or(Err(ReturnCode::ENOMEM), |tx_buf| {
+                self.body.map_or(Err(ReturnCode::EBUSY), |body| {
+                    encode_response(tx_buf, &header, block2, exchange.block1, &body[..len])
+                })
+            });
+        let tx_len = match result {
+            Ok(tx_len) => tx_len,
+            Err(err) => {
+                self.exchange.set(None);
+                self.server_timer.set(None);
+                return err;
+            }
+        };
+
+        self.server_tx_len.set(tx_len);
+        self.server_tx_endpoint.set(exchange.endpoint);
+        self.server_acked.set(None);
+        if mtype == MessageType::Confirmable {
+            let retransmit = self.new_retransmit();
+            exchange.state = ExchangeState::Responding {
+                message_id,
+                retransmit,
+            };
+            self.exchange.set(Some(exchange));
+            self.set_timer(&self.server_timer, retransmit.timeout_ms);
+        } else {
+            if mtype == MessageType::Acknowledgement {
+                self.server_acked.set(Some((exchange.endpoint, message_id)));
+            }
+            self.exchange.set(None);
+            self.server_timer.set(None);
+        }
+        self.server_queued.set(true);
+        self.flush();
+        ReturnCode::SUCCESS
+    }
+
+    fn server_timeout(&self) {
+        let mut exchange = match self.exchange.get() {
+            Some(exchange) => exchange,
+            None => return,
+        };
+        match exchange.state {
+            ExchangeState::Processing { acked: false } if exchange.confirmable => {
+                // The response follows separately
+                self.queue_reply(Reply::empty(
+                    exchange.endpoint,
+                    MessageType::Acknowledgement,
+                    exchange.message_id,
+                ));
+                exchange.state = ExchangeState::Processing { acked: true };
+                self.exchange.set(Some(exchange));
+                self.set_timer(&self.server_timer, RESPONSE_TIMEOUT_MS - ACK_DELAY_MS);
+            }
+            ExchangeState::Processing { .. } => {
+                self.body_state.set(None);
+                self.send_response(exchange, code::INTERNAL_SERVER_ERROR, 0);
+            }
+            ExchangeState::Responding {
+                message_id,
+                retransmit,
+            } if retransmit.count < MAX_RETRANSMIT => {
+                let retransmit = retransmit.next();
+                exchange.state = ExchangeState::Responding {
+                    message_id,
+                    retransmit,
+                };
+                self.exchange.set(Some(exchange));
+                self.set_timer(&self.server_timer, retransmit.timeout_ms);
+                self.server_queued.set(true);
+                self.flush();
+            }
+            // The client is gone
+            ExchangeState::Responding { .. } => self.exchange.set(None),
+        }
+    }
+
+    /// Acknowledges a duplicate confirmable message again, with the reply or
+    /// the piggybacked response it got if they are still known.
+    fn receive_duplicate(&self, endpoint: Endpoint, header: &Header) {
+        if header.mtype != MessageType::Confirmable {
+            return;
+        }
+        let acked = |reply_endpoint, message_id| {
+            reply_endpoint == endpoint && message_id == header.message_id
+        };
+        match self.last_reply.get() {
+            Some(reply) if acked(reply.endpoint, reply.header.message_id) => {
+                self.queue_reply(reply);
+            }
+            _ => match self.server_acked.get() {
+                Some((server_endpoint, message_id)) if acked(server_endpoint, message_id) => {
+                    self.server_queued.set(true);
+                    self.flush();
+                }
+                _ => self.queue_reply(Reply::empty(
+                    endpoint,
+                    MessageType::Acknowledgement,
+                    header.message_id,
+                )),
+            },
+        }
+    }
+
+    fn receive_ack(&self, endpoint: Endpoint, message: &Message) {
+        let header = message.header;
+        if let Some(mut request) = self.request.get() {
+            if let RequestState::WaitAck { .. } = request.state {
+                if request.message_id == header.message_id && request.endpoint == endpoint {
+                    if header.mtype == MessageType::Reset {
+                        self.finish_request(ReturnCode::FAIL, 0, 0);
+                    } else if header.code == code::EMPTY {
+                        request.state = RequestState::WaitResponse;
+                        self.request.set(Some(request));
+                        self.set_timer(&self.client_timer, MAX_TRANSMIT_WAIT_MS);
+                    } else {
+                        self.receive_response(endpoint, message);
+                    }
+                    return;
+                }
+            }
+        }
+        if let Some(exchange) = self.exchange.get() {
+            if let ExchangeState::Responding { message_id, .. } = exchange.state {
+                if message_id == header.message_id && exchange.endpoint == endpoint {
+                    self.exchange.set(None);
+                    self.server_timer.set(None);
+                }
+            }
+        }
+    }
+
+    fn receive_response(&self, endpoint: Endpoint, message: &Message) {
+        let header = message.header;
+        let request = match self.request.get() {
+            // The request was not sent yet
+            Some(Request {
+                state: RequestState::NoToken,
+                ..
+            }) => None,
+            request => request,
+        };
+        let mut request = match request {
+            Some(request)
+                if request.token == header.token
+                    && request.endpoint.port == endpoint.port
+                    && (request.endpoint.addr == endpoint.addr
+                        || request.endpoint.addr.is_multicast()) =>
+            {
+                request
+            }
+            _ => {
+                if header.mtype == MessageType::Confirmable {
+                    self.queue_reply(Reply::empty(
+                        endpoint,
+                        MessageType::Reset,
+                        header.message_id,
+                    ));
+                }
+                return;
+            }
+        };
+        match header.mtype {
+            // A piggybacked response to an earlier message of the request
+            MessageType::Acknowledgement if header.message_id != request.message_id => return,
+            MessageType::Confirmable => self.queue_reply(Reply::empty(
+                endpoint,
+                MessageType::Acknowledgement,
+                header.message_id,
+            )),
+            _ => {}
+        }
+
+        if let Some(block1) = request.block1 {
+            if header.code == code::CONTINUE {
+                // The server may ask for smaller blocks
+                let szx = message
+                    .block_option(option_number::BLOCK1)
+                    .map_or(block1.szx, |b| min(b.szx, block1.szx));
+                let offset = block1.offset() + block1.size();
+                let size = 1 << (szx + 4);
+                if offset >= request.len {
+                    self.finish_request(ReturnCode::FAIL, 0, 0);
+                    return;
+                }
+                request.block1 = Some(BlockOption {
+                    num: (offset / size) as u32,
+                    more: offset + size < request.len,
+                    szx,
+                });
+                let result = self.send_request(request);
+                if result != ReturnCode::SUCCESS {
+                    self.finish_request(result, 0, 0);
+                }
+                return;
+            }
+            request.block1 = None;
+        }
+
+        let block2 = message.block_option(option_number::BLOCK2);
+        let offset = block2.map_or(0, |block2| block2.offset());
+        if offset != request.received {
+            self.finish_request(ReturnCode::FAIL, 0, 0);
+            return;
+        }
+        let capacity = self.request_buf.map_or(0, |buf| buf.len());
+        let end = offset + message.payload.len();
+        if end > capacity {
+            self.request_buf
+                .map(|buf| buf[offset..].copy_from_slice(&message.payload[..capacity - offset]));
+            self.finish_request(ReturnCode::ESIZE, header.code, capacity);
+            return;
+        }
+        self.request_buf
+            .map(|buf| buf[offset..end].copy_from_slice(message.payload));
+        request.received = end;
+
+        match block2 {
+            Some(block2) if block2.more => {
+                request.block2 = Some(BlockOption {
+                    num: block2.num + 1,
+                    more: false,
+                    szx: block2.szx,
+                });
+                let result = self.send_request(request);
+                if result != ReturnCode::SUCCESS {
+                    self.finish_request(result, 0, 0);
+                }
+            }
+            _ => self.finish_request(ReturnCode::SUCCESS, header.code, end),
+        }
+    }
+
+    fn receive_request(&self, endpoint: Endpoint, message: &Message) {
+        let header = message.header;
+        if has_unsupported_critical(message) {
+            self.reply_to(endpoint, &header, code::BAD_OPTION, None, None);
+            return;
+        }
+        let path = match Path::from_message(message) {
+            Some(path) => path,
+            None => {
+                self.reply_to(endpoint, &header, code::NOT_FOUND, None, None);
+                return;
+            }
+        };
+        if self.exchange.get().is_some() {
+            self.reply_to(endpoint, &header, code::SERVICE_UNAVAILABLE, None, None);
+            return;
+        }
+
+        let block1 = message.block_option(option_number::BLOCK1);
+        let block2 = message.block_option(option_number::BLOCK2);
+        // The later blocks of the last response are sent from the body
+        // buffer, without asking the server again
+        if let (None, Some(block2)) = (block1, block2) {
+            if let Some(Body::Response {
+                endpoint: body_endpoint,
+                path: body_path,
+                code,
+                len,
+            }) = self.body_state.get()
+            {
+                if block2.num > 0 && body_endpoint == endpoint && body_path == path {
+                    let exchange = Exchange::new(endpoint, &header, path, None, Some(block2));
+                    self.send_response(exchange, code, len);
+                    return;
+                }
+            }
+        }
+
+        let len = match block1 {
+            Some(block1) => {
+                match self.receive_block1(endpoint, &header, path, block1, message.payload) {
+                    Some(len) => len,
+                    None => return,
+                }
+            }
+            None => message.payload.len(),
+        };
+        let block1 = block1.map(|block1| BlockOption {
+            more: false,
+            ..block1
+        });
+        self.exchange
+            .set(Some(Exchange::new(endpoint, &header, path, block1, block2)));
+        let result = self.server.map_or(ReturnCode::ENOSUPPORT, |server| {
+            if block1.is_some() {
+                match self.body.take() {
+                    Some(body) => {
+                        let result = server.request(header.code, path.as_slice(), &body[..len]);
+                        self.body.replace(body);
+                        result
+                    }
+                    None => ReturnCode::FAIL,
+                }
+            } else {
+                server.request(header.code, path.as_slice(), message.payload)
+            }
+        });
+        match result {
+            ReturnCode::SUCCESS => {
+                if let Some(Exchange {
+                    state: ExchangeState::Processing { .. },
+                    confirmable,
+                    ..
+                }) = self.exchange.get()
+                {
+                    let timeout = if confirmable {
+                        ACK_DELAY_MS
+                    } else {
+                        RESPONSE_TIMEOUT_MS
+                    };
+                    self.set_timer(&self.server_timer, timeout);
+                }
+            }
+            result => {
+                self.exchange.set(None);
+                let code = if result == ReturnCode::ENOSUPPORT {
+                    code::NOT_FOUND
+                } else {
+                    code::INTERNAL_SERVER_ERROR
+                };
+                self.reply_to(endpoint, &header, code, block1, None);
+            }
+        }
+    }
+
+    /// Adds a block of a request sent in blocks to the body buffer, and
+    /// asks for the next block. Returns the length of the request once its
+    /// last block arrived.
+    fn receive_block1(
+        &self,
+        endpoint: Endpoint,
+        header: &Header,
+        path: Path,
+        block1: BlockOption,
+        payload: &[u8],
+    ) -> Option<usize> {
+        let offset = match self.body_state.get() {
+            _ if block1.num == 0 => Some(0),
+            Some(Body::Upload {
+                endpoint: body_endpoint,
+                path: body_path,
+                len,
+            }) if body_endpoint == endpoint && body_path == path => Some(len),
+            _ => None,
+        };
+        if offset != Some(block1.offset()) {
+            self.reply_to(
+                endpoint,
+                header,
+                code::REQUEST_ENTITY_INCOMPLETE,
+                None,
+                None,
+            );
+            return None;
+        }
+        let capacity = self.body.map_or(0, |body| body.len());
+        let end = block1.offset() + payload.len();
+        if end > capacity {
+            self.body_state.set(None);
+            self.reply_to(
+                endpoint,
+                header,
+                code::REQUEST_ENTITY_TOO_LARGE,
+                None,
+                Some(capacity as u32),
+            );
+            return None;
+        }
+        self.body
+            .map(|body| body[block1.offset()..end].copy_from_slice(payload));
+        if block1.more {
+            self.body_state.set(Some(Body::Upload {
+                endpoint,
+                path,
+                len: end,
+            }));
+            self.reply_to(endpoint, header, code::CONTINUE, Some(block1), None);
+            None
+        } else {
+            self.body_state.set(None);
+            Some(end)
+        }
+    }
+}
+
+/// Returns whether `message` has a critical option that the server does not
+/// understand.
+fn has_unsupported_critical(message: &Message) -> bool {
+    message.options().any(|(number, _)| match number {
+        option_number::URI_HOST
+        | option_number::URI_PORT
+        | option_number::URI_PATH
+        | option_number::BLOCK2
+        | option_number::BLOCK1 => false,
+        _ => number & 1 == 1,
+    })
+}
+
+/// Encodes the next message of `request` into `tx_buf`, with its payload
+/// from `buf`, and returns its length.
+fn encode_request(
+    tx_buf: &mut [u8],
+    header: &Header,
+    request: &Request,
+    buf: &[u8],
+) -> Result<usize, ReturnCode> {
+    let mut writer = MessageWriter::new(tx_buf, header)?;
+    writer.add_path(request.path.as_slice())?;
+    if let Some(block2) = request.block2 {
+        writer.add_uint_option(option_number::BLOCK2, block2.encode())?;
+    }
+    match request.block1 {
+        Some(block1) => {
+            writer.add_uint_option(option_number::BLOCK1, block1.encode())?;
+            if block1.num == 0 {
+                writer.add_uint_option(option_number::SIZE1, request.len as u32)?;
+            }
+            let end = min(block1.offset() + block1.size(), request.len);
+            writer.set_payload(&buf[block1.offset()..end])?;
+        }
+        // Requests for the later blocks of the response carry no payload
+        None if request.block2.is_none() => writer.set_payload(&buf[..request.len])?,
+        None => {}
+    }
+    Ok(writer.finish())
+}
+
+/// Encodes a response into `tx_buf`, with the block `block2` of `body` or
+/// all of it, and returns its length.
+fn encode_response(
+    tx_buf: &mut [u8],
+    header: &Header,
+    block2: Option<BlockOption>,
+    block1: Option<BlockOption>,
+    body: &[u8],
+) -> Result<usize, ReturnCode> {
+    let mut writer = MessageWriter::new(tx_buf, header)?;
+    if let Some(block2) = block2 {
+        writer.add_uint_option(option_number::BLOCK2, block2.encode())?;
+    }
+    if let Some(block1) = block1 {
+        writer.add_uint_option(option_number::BLOCK1, block1.encode())?;
+    }
+    match block2 {
+        Some(block2) => {
+            if block2.num == 0 {
+                writer.add_uint_option(option_number::SIZE2, body.len() as u32)?;
+            }
+            let end = min(block2.offset() + block2.size(), body.len());
+            writer.set_payload(&body[block2.offset()..end])?;
+        }
+        None => writer.set_payload(body)?,
+    }
+    Ok(writer.finish())
+}
+
+impl<A: time::Alarm<'a>, U: UDPSender<'a>> UDPRecvClient for Coap<'a, A, U> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        _dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) -> bool {
+        let endpoint = Endpoint {
+            addr: src_addr,
+            port: src_port,
+        };
+        // Malformed messages are ignored
+        let message = match Message::decode(payload).done() {
+            Some((_, message)) => message,
+            None => return true,
+        };
+        let header = message.header;
+        match header.mtype {
+            MessageType::Acknowledgement | MessageType::Reset => {
+                self.receive_ack(endpoint, &message)
+            }
+            // An empty confirmable message is a ping
+            _ if header.code == code::EMPTY => {
+                if header.mtype == MessageType::Confirmable {
+                    self.queue_reply(Reply::empty(
+                        endpoint,
+                        MessageType::Reset,
+                        header.message_id,
+                    ));
+                }
+            }
+            _ if self.is_duplicate(endpoint, header.message_id) => {
+                self.receive_duplicate(endpoint, &header)
+            }
+            _ if message::is_request(header.code) => self.receive_request(endpoint, &message),
+            _ => self.receive_response(endpoint, &message),
+        }
+        true
+    }
+}
+
+impl<A: time::Alarm<'a>, U: UDPSender<'a>> rng::Client for Coap<'a, A, U> {
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        _error: ReturnCode,
+    ) -> rng::Continue {
+        let mut request = match self.request.get() {
+            Some(
+                request @ Request {
+                    state: RequestState::NoToken,
+                    ..
+                },
+            ) => request,
+            _ => return rng::Continue::Done,
+        };
+        let (token, seed) = match (randomness.next(), randomness.next()) {
+            (Some(token), Some(seed)) => (token, seed),
+            _ => return rng::Continue::More,
+        };
+        request.token = Token::new(&token.to_be_bytes());
+        self.random.set(seed | 1);
+        let result = self.send_request(request);
+        if result != ReturnCode::SUCCESS {
+            self.finish_request(result, 0, 0);
+        }
+        rng::Continue::Done
+    }
+}
+
+impl<A: time::Alarm<'a>, U: UDPSender<'a>> UDPSendClient for Coap<'a, A, U> {
+    fn send_done(&self, _result: ReturnCode) {
+        self.sending.set(false);
+        self.flush();
+    }
+}
+
+impl<A: time::Alarm<'a>, U: UDPSender<'a>> time::AlarmClient for Coap<'a, A, U> {
+    fn fired(&self) {
+        self.ticking.set(false);
+        if Self::expired(&self.client_timer) {
+            self.client_timeout();
+        }
+        if Self::expired(&self.server_timer) {
+            self.server_timeout();
+        }
+        // Keeps ticking while a timer runs
+        if self.client_timer.get().is_some() || self.server_timer.get().is_some() {
+            self.start_tick();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use crate::net::mock::{buf, MockAlarm, MockRng, MockUdpSender};
+    use core::cell::RefCell;
+    use std::vec::Vec;
+
+    type TestCoap<'a> = Coap<'a, MockAlarm, MockUdpSender>;
+
+    struct Client {
+        responses: RefCell<Vec<(ReturnCode, u8, Vec<u8>)>>,
+    }
+
+    impl CoapClient for Client {
+        fn response(&self, result: ReturnCode, code: u8, buf: &'static mut [u8], len: usize) {
+            self.responses
+                .borrow_mut()
+                .push((result, code, buf[..len].to_vec()));
+        }
+    }
+
+    struct Server {
+        requests: RefCell<Vec<(u8, Vec<u8>)>>,
+    }
+
+    impl CoapServer for Server {
+        fn request(&self, code: u8, path: &[u8], _payload: &[u8]) -> ReturnCode {
+            self.requests.borrow_mut().push((code, path.to_vec()));
+            ReturnCode::SUCCESS
+        }
+    }
+
+    struct Mocks {
+        sender: MockUdpSender,
+        alarm: MockAlarm,
+        rng: MockRng,
+        client: Client,
+        server: Server,
+    }
+
+    const PEER_PORT: u16 = 40000;
+
+    fn peer() -> Endpoint {
+        let mut addr = IPAddr::new();
+        addr.0[..2].copy_from_slice(&[0xfe, 0x80]);
+        addr.0[15] = 2;
+        Endpoint {
+            addr,
+            port: PEER_PORT,
+        }
+    }
+
+    impl Mocks {
+        fn new() -> Mocks {
+            Mocks {
+                sender: MockUdpSender::new(),
+                alarm: MockAlarm::new(),
+                rng: MockRng::new(),
+                client: Client {
+                    responses: RefCell::new(Vec::new()),
+                },
+                server: Server {
+                    requests: RefCell::new(Vec::new()),
+                },
+            }
+        }
+
+        fn coap(&self) -> TestCoap {
+            let coap = Coap::new(
+                &self.sender,
+                &self.alarm,
+                &self.rng,
+                0x1234,
+                buf(MAX_MESSAGE_LEN),
+                buf(MAX_MESSAGE_LEN),
+                buf(512),
+            );
+            coap.set_client(&self.client);
+            coap.set_server(&self.server);
+            coap
+        }
+
+        // The messages that CoAP sent, letting it send all it has.
+        fn sent(&self, coap: &TestCoap) -> Vec<Vec<u8>> {
+            let mut messages = Vec::new();
+            loop {
+                let sent = self.sender.take();
+                if sent.is_empty() {
+                    return messages;
+                }
+                for datagram in sent {
+                    assert_eq!(datagram.dst, peer().addr);
+                    messages.push(datagram.payload);
+                }
+                UDPSendClient::send_done(coap, ReturnCode::SUCCESS);
+            }
+        }
+
+        fn sent_one(&self, coap: &TestCoap) -> Vec<u8> {
+            let mut sent = self.sent(coap);
+            assert_eq!(sent.len(), 1);
+            sent.remove(0)
+        }
+
+        fn responses(&self) -> Vec<(ReturnCode, u8, Vec<u8>)> {
+            self.client.responses.replace(Vec::new())
+        }
+    }
+
+    fn encode(
+        mtype: MessageType,
+        code: u8,
+        message_id: u16,
+        token: &[u8],
+        options: &[(u16, u32)],
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let header = Header {
+            mtype,
+            code,
+            message_id,
+            token: Token::new(token),
+        };
+        let mut buf = [0; MAX_MESSAGE_LEN];
+        let mut writer = MessageWriter::new(&mut buf, &header).unwrap();
+        writer.add_path(b"data").unwrap();
+        for &(number, value) in options {
+            writer.add_uint_option(number, value).unwrap();
+        }
+        writer.set_payload(payload).unwrap();
+        let len = writer.finish();
+        buf[..len].to_vec()
+    }
+
+    // Passes a message from `endpoint` to CoAP.
+    fn receive(coap: &TestCoap, endpoint: Endpoint, message: &[u8]) {
+        UDPRecvClient::receive(
+            coap,
+            endpoint.addr,
+            IPAddr::new(),
+            endpoint.port,
+            COAP_PORT,
+            message,
+        );
+    }
+
+    #[test]
+    fn responds_in_smaller_blocks() {
+        let mocks = Mocks::new();
+        let coap = mocks.coap();
+        let body: Vec<u8> = (0..512).map(|i| i as u8).collect();
+
+        // The peer asks for blocks of 1024 bytes
+        let block2 = BlockOption {
+            num: 0,
+            more: false,
+            szx: 6,
+        };
+        receive(
+            &coap,
+            peer(),
+            &encode(
+                MessageType::Confirmable,
+                code::GET,
+                1,
+                &[7],
+                &[(option_number::BLOCK2, block2.encode())],
+                &[],
+            ),
+        );
+        assert_eq!(
+            mocks.server.requests.replace(Vec::new()),
+            [(code::GET, b"data".to_vec())]
+        );
+        assert_eq!(coap.respond(code::CONTENT, &body), ReturnCode::SUCCESS);
+        let sent = mocks.sent_one(&coap);
+        let (_, message) = Message::decode(&sent).done().unwrap();
+        assert_eq!(message.header.mtype, MessageType::Acknowledgement);
+        assert_eq!(message.header.code, code::CONTENT);
+        assert_eq!(message.header.message_id, 1);
+        assert_eq!(message.header.token, Token::new(&[7]));
+        assert_eq!(
+            message.block_option(option_number::BLOCK2),
+            Some(BlockOption {
+                num: 0,
+                more: true,
+                szx: BLOCK_SZX,
+            })
+        );
+        assert_eq!(message.uint_option(option_number::SIZE2), Some(512));
+        assert_eq!(message.payload, &body[..BLOCK_SIZE]);
+
+        // The block of 128 bytes that starts at 128 is sent as the block of
+        // 64 bytes that starts there, from the body kept
+        let block2 = BlockOption {
+            num: 1,
+            more: false,
+            szx: 3,
+        };
+        receive(
+            &coap,
+            peer(),
+            &encode(
+                MessageType::Confirmable,
+                code::GET,
+                2,
+                &[8],
+                &[(option_number::BLOCK2, block2.encode())],
+                &[],
+            ),
+        );
+        assert!(mocks.server.requests.borrow().is_empty());
+        let sent = mocks.sent_one(&coap);
+        let (_, message) = Message::decode(&sent).done().unwrap();
+        assert_eq!(message.header.mtype, MessageType::Acknowledgement);
+        assert_eq!(message.header.message_id, 2);
+        assert_eq!(
+            message.block_option(option_number::BLOCK2),
+            Some(BlockOption {
+                num: 2,
+                more: true,
+                szx: BLOCK_SZX,
+            })
+        );
+        assert_eq!(message.uint_option(option_number::SIZE2), None);
+        assert_eq!(message.payload, &body[128..192]);
+    }
+
+    #[test]
+    fn request_waits_for_token() {
+        let mocks = Mocks::new();
+        let coap = mocks.coap();
+        let (result, returned) = coap.request(peer(), code::GET, true, b"/data", buf(64), 0);
+        assert_eq!(result, ReturnCode::SUCCESS);
+        assert!(returned.is_none());
+        assert_eq!(mocks.rng.requests.get(), 1);
+        assert!(mocks.sent(&coap).is_empty());
+
+        // A response before the request is sent is not for it, and is reset
+        receive(
+            &coap,
+            peer(),
+            &encode(MessageType::Confirmable, code::CONTENT, 9, &[], &[], b"x"),
+        );
+        let sent = mocks.sent_one(&coap);
+        let (_, message) = Message::decode(&sent).done().unwrap();
+        assert_eq!(message.header.mtype, MessageType::Reset);
+        assert!(mocks.responses().is_empty());
+
+        // A second request has to wait for the first
+        let other = buf(64);
+        let (result, _) = coap.request(peer(), code::GET, true, b"/data", other, 0);
+        assert_eq!(result, ReturnCode::EBUSY);
+
+        rng::Client::randomness_available(
+            &coap,
+            &mut [0x0102_0304, 5].iter().cloned(),
+            ReturnCode::SUCCESS,
+        );
+        let sent = mocks.sent_one(&coap);
+        let (_, message) = Message::decode(&sent).done().unwrap();
+        assert_eq!(message.header.mtype, MessageType::Confirmable);
+        assert_eq!(message.header.code, code::GET);
+        assert_eq!(message.header.token, Token::new(&[1, 2, 3, 4]));
+        assert_eq!(message.option(option_number::URI_PATH), Some(&b"data"[..]));
+    }
+
+    #[test]
+    fn ignores_acks_from_other_addresses() {
+        let mocks = Mocks::new();
+        let coap = mocks.coap();
+        coap.request(peer(), code::GET, true, b"/data", buf(64), 0);
+        rng::Client::randomness_available(
+            &coap,
+            &mut [0x0102_0304, 5].iter().cloned(),
+            ReturnCode::SUCCESS,
+        );
+        let sent = mocks.sent_one(&coap);
+        let (_, request) = Message::decode(&sent).done().unwrap();
+        let message_id = request.header.message_id;
+
+        // The same port and message ID from another address
+        let mut other = peer();
+        other.addr.0[15] = 3;
+        receive(
+            &coap,
+            other,
+            &encode(MessageType::Reset, code::EMPTY, message_id, &[], &[], &[]),
+        );
+        receive(
+            &coap,
+            other,
+            &encode(
+                MessageType::Acknowledgement,
+                code::CONTENT,
+                message_id,
+                &[1, 2, 3, 4],
+                &[],
+                b"spoofed",
+            ),
+        );
+        assert!(mocks.responses().is_empty());
+
+        receive(
+            &coap,
+            peer(),
+            &encode(
+                MessageType::Acknowledgement,
+                code::CONTENT,
+                message_id,
+                &[1, 2, 3, 4],
+                &[],
+                b"hi",
+            ),
+        );
+        assert_eq!(
+            mocks.responses(),
+            [(ReturnCode::SUCCESS, code::CONTENT, b"hi".to_vec())]
+        );
+    }
+}
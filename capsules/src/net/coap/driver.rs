@@ -0,0 +1,360 @@
+//! CoAP userspace interface.
+//!
+//! Lets apps act as CoAP servers, by registering the paths of their
+//! resources and answering the requests for them, and as CoAP clients, by
+//! sending requests to other endpoints. The `Coap` capsule below handles
+//! retransmissions, duplicates and block-wise transfers for them.
+//!
+//! One request is sent at a time across all apps, and the server answers
+//! one request at a time: the app that a request is passed to should answer
+//! it quickly with `respond`.
+
+use crate::net::coap::coap::{Coap, CoapClient, CoapServer, Endpoint, Path};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::udp::udp_send::UDPSender;
+use core::cmp::min;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::time;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+
+/// Syscall number
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Coap as usize;
+
+/// Size of an endpoint in the endpoint buffer: an IPv6 address followed by a
+/// port in network byte order.
+pub const ENDPOINT_LEN: usize = 18;
+
+/// The number of resources each app can register.
+pub const MAX_RESOURCES: usize = 4;
+
+/// Set in the first argument of command 3 to send a non-confirmable
+/// request.
+const NON_CONFIRMABLE: usize = 0x100;
+
+#[derive(Default)]
+pub struct App {
+    request_callback: Option<Callback>,
+    response_callback: Option<Callback>,
+    app_path: Option<AppSlice<Shared, u8>>,
+    app_client: Option<AppSlice<Shared, u8>>,
+    app_endpoint: Option<AppSlice<Shared, u8>>,
+    app_server: Option<AppSlice<Shared, u8>>,
+    resources: [Option<Path>; MAX_RESOURCES],
+}
+
+pub struct CoapDriver<'a, A: time::Alarm<'a>, U: UDPSender<'a>> {
+    coap: &'a Coap<'a, A, U>,
+    apps: Grant<App>,
+    // Holds the payload of the request of an app, and then the response
+    client_buf: TakeCell<'static, [u8]>,
+    client_app: OptionalCell<AppId>,
+    // The app whose resource the request being answered is for
+    server_app: OptionalCell<AppId>,
+}
+
+impl<A: time::Alarm<'a>, U: UDPSender<'a>> CoapDriver<'a, A, U> {
+    /// `client_buf` limits the length of the requests that apps send and of
+    /// the responses they receive.
+    pub fn new(
+        coap: &'a Coap<'a, A, U>,
+        grant: Grant<App>,
+        client_buf: &'static mut [u8],
+    ) -> CoapDriver<'a, A, U> {
+        CoapDriver {
+            coap,
+            apps: grant,
+            client_buf: TakeCell::new(client_buf),
+            client_app: OptionalCell::empty(),
+            server_app: OptionalCell::empty(),
+        }
+    }
+
+    /// Utility function to perform an action on an app in a system call.
+    #[inline]
+    fn do_with_app<F>(&self, appid: AppId, closure: F) -> ReturnCode
+    where
+        F: FnOnce(&mut App) -> ReturnCode,
+    {
+        self.apps
+            .enter(appid, |app, _| closure(app))
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn parse_endpoint(buf: &[u8]) -> Endpoint {
+        let mut addr = IPAddr::new();
+        addr.0.copy_from_slice(&buf[..16]);
+        let port = (buf[16] as u16) << 8 | buf[17] as u16;
+        Endpoint { addr, port }
+    }
+
+    /// Returns the path in the path buffer, which ends at the first NUL
+    /// byte if there is one.
+    fn parse_path(buf: &[u8]) -> Option<Path> {
+        let len = buf
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or_else(|| buf.len());
+        Path::parse(&buf[..len])
+    }
+
+    /// Returns whether an app registered the resource at `path`.
+    fn is_registered(&self, path: &Path) -> bool {
+        let mut registered = false;
+        for app in self.apps.iter() {
+            app.enter(|other_app, _| {
+                if other_app.resources.iter().any(|r| r.as_ref() == Some(path)) {
+                    registered = true;
+                }
+            });
+        }
+        registered
+    }
+
+    fn request(&self, app: &mut App, appid: AppId, arg1: usize, len: usize) -> ReturnCode {
+        if self.client_app.is_some() {
+            return ReturnCode::EBUSY;
+        }
+        let endpoint = match app.app_endpoint {
+            Some(ref cfg) if cfg.len() >= ENDPOINT_LEN => Self::parse_endpoint(cfg.as_ref()),
+            _ => return ReturnCode::EINVAL,
+        };
+        let path = match app.app_path {
+            Some(ref path) => match Self::parse_path(path.as_ref()) {
+                Some(path) => path,
+                None => return ReturnCode::ESIZE,
+            },
+            None => return ReturnCode::EINVAL,
+        };
+        let payload = match app.app_client {
+            Some(ref data) if len <= data.len() => &data.as_ref()[..len],
+            None if len == 0 => &[],
+            _ => return ReturnCode::EINVAL,
+        };
+        let buf = match self.client_buf.take() {
+            Some(buf) => buf,
+            None => return ReturnCode::EBUSY,
+        };
+        if len > buf.len() {
+            self.client_buf.replace(buf);
+            return ReturnCode::ESIZE;
+        }
+        buf[..len].copy_from_slice(payload);
+
+        let confirmable = arg1 & NON_CONFIRMABLE == 0;
+        let (result, buf) =
+            self.coap
+                .request(endpoint, arg1 as u8, confirmable, path.as_slice(), buf, len);
+        if let Some(buf) = buf {
+            self.client_buf.replace(buf);
+        }
+        if result == ReturnCode::SUCCESS {
+            self.client_app.set(appid);
+        }
+        result
+    }
+}
+
+impl<A: time::Alarm<'a>, U: UDPSender<'a>> CoapClient for CoapDriver<'a, A, U> {
+    fn response(&self, result: ReturnCode, code: u8, buf: &'static mut [u8], len: usize) {
+        if let Some(appid) = self.client_app.take() {
+            let _ = self.apps.enter(appid, |app, _| {
+                let len = app.app_client.as_mut().map_or(0, |data| {
+                    let len = min(len, data.len());
+                    data.as_mut()[..len].copy_from_slice(&buf[..len]);
+                    len
+                });
+                app.response_callback
+                    .map(|mut cb| cb.schedule(usize::from(result), code as usize, len));
+            });
+        }
+        self.client_buf.replace(buf);
+    }
+}
+
+impl<A: time::Alarm<'a>, U: UDPSender<'a>> CoapServer for CoapDriver<'a, A, U> {
+    fn request(&self, code: u8, path: &[u8], payload: &[u8]) -> ReturnCode {
+        for app in self.apps.iter() {
+            let handled = app.enter(|app, _| {
+                let resource = app
+                    .resources
+                    .iter()
+                    .position(|r| r.map_or(false, |r| r.as_slice() == path));
+                let resource = match resource {
+                    Some(resource) => resource,
+                    None => return false,
+                };
+                let len = app.app_server.as_mut().map_or(0, |data| {
+                    let len = min(payload.len(), data.len());
+                    data.as_mut()[..len].copy_from_slice(&payload[..len]);
+                    len
+                });
+                app.request_callback
+                    .map(|mut cb| cb.schedule(code as usize, resource, len));
+                self.server_app.set(app.appid());
+                true
+            });
+            if handled {
+                return ReturnCode::SUCCESS;
+            }
+        }
+        ReturnCode::ENOSUPPORT
+    }
+}
+
+impl<A: time::Alarm<'a>, U: UDPSender<'a>> Driver for CoapDriver<'a, A, U> {
+    /// Setup buffers to read/write from.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Path buffer. Contains the path of the resource to register or
+    ///        to send a request to, e.g. `sensors/temperature`, which ends at
+    ///        the first NUL byte or at the end of the buffer.
+    /// - `1`: Client buffer. Contains the payload of the request to send,
+    ///        and receives the payload of the response.
+    /// - `2`: Endpoint buffer. Contains the endpoint to send a request to: a
+    ///        16 byte IPv6 address followed by a 2 byte port in network byte
+    ///        order.
+    /// - `3`: Server buffer. Receives the payload of the requests for the
+    ///        resources of the app, and contains the payload of the response
+    ///        to send.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 | 2 | 3 => self.do_with_app(appid, |app| {
+                match allow_num {
+                    0 => app.app_path = slice,
+                    1 => app.app_client = slice,
+                    2 => app.app_endpoint = slice,
+                    3 => app.app_server = slice,
+                    _ => {}
+                }
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: A request for a resource of the app arrived. The callback
+    ///        receives the code of the request, the number of the resource
+    ///        returned by command 1, and the length of the payload copied
+    ///        into the server buffer.
+    /// - `1`: The request sent by command 3 ended. The callback receives
+    ///        SUCCESS, the code of the response, and the length of its
+    ///        payload in the client buffer; or ESIZE with the length of the
+    ///        start of the payload that fit; or ENOACK if the server did not
+    ///        acknowledge the request and FAIL if it reset it or did not
+    ///        respond in time.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 | 1 => self.do_with_app(app_id, |app| {
+                match subscribe_num {
+                    0 => app.request_callback = callback,
+                    1 => app.response_callback = callback,
+                    _ => {}
+                }
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// CoAP control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Register the resource at the path in the path buffer. Returns
+    ///        the number of the resource, EALREADY if an app registered it
+    ///        already, ENOMEM if the app registered `MAX_RESOURCES` already
+    ///        and ESIZE if the path is too long.
+    /// - `2`: Unregister the resource numbered `arg1`.
+    /// - `3`: Send a request to the resource at the path in the path buffer
+    ///        of the endpoint in the endpoint buffer, with the first `arg2`
+    ///        bytes of the client buffer as payload. The low byte of `arg1`
+    ///        is the code of the request, e.g. 1 for GET, and the request is
+    ///        non-confirmable if bit 8 of `arg1` is set. Returns EBUSY if a
+    ///        request is in progress.
+    /// - `4`: Respond to the last request for a resource of the app with the
+    ///        code `arg1`, e.g. 0x45 for 2.05 (Content), and the first `arg2`
+    ///        bytes of the server buffer as payload. Returns EINVAL if no
+    ///        request for a resource of the app waits for a response.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => self.do_with_app(appid, |app| {
+                let path = match app.app_path {
+                    Some(ref path) => match Self::parse_path(path.as_ref()) {
+                        Some(path) => path,
+                        None => return ReturnCode::ESIZE,
+                    },
+                    None => return ReturnCode::EINVAL,
+                };
+                if self.is_registered(&path) {
+                    return ReturnCode::EALREADY;
+                }
+                match app.resources.iter().position(|r| r.is_none()) {
+                    Some(resource) => {
+                        app.resources[resource] = Some(path);
+                        ReturnCode::SuccessWithValue { value: resource }
+                    }
+                    None => ReturnCode::ENOMEM,
+                }
+            }),
+
+            2 => self.do_with_app(appid, |app| match app.resources.get_mut(arg1) {
+                Some(resource) if resource.is_some() => {
+                    *resource = None;
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::EINVAL,
+            }),
+
+            3 => {
+                if arg1 > 0x1ff {
+                    return ReturnCode::EINVAL;
+                }
+                self.do_with_app(appid, |app| self.request(app, appid, arg1, arg2))
+            }
+
+            4 => {
+                if self
+                    .server_app
+                    .map_or(true, |server_app| *server_app != appid)
+                    || arg1 > 0xff
+                {
+                    return ReturnCode::EINVAL;
+                }
+                self.do_with_app(appid, |app| {
+                    let result = match app.app_server {
+                        Some(ref data) if arg2 <= data.len() => {
+                            self.coap.respond(arg1 as u8, &data.as_ref()[..arg2])
+                        }
+                        None if arg2 == 0 => self.coap.respond(arg1 as u8, &[]),
+                        _ => ReturnCode::EINVAL,
+                    };
+                    if result == ReturnCode::SUCCESS {
+                        self.server_app.clear();
+                    }
+                    result
+                })
+            }
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
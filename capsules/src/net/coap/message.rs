@@ -0,0 +1,673 @@
+//! This file contains the format of CoAP messages (RFC 7252) and the methods
+//! to encode and decode them. A message consists of a 4 byte header, a token
+//! of up to 8 bytes, a series of options, and a payload that follows a
+//! payload marker.
+//!
+//! Options are sorted by their number, and each encodes the difference from
+//! the number of the previous option, so `Options` decodes them in order and
+//! `MessageWriter` only adds them in order:
+//!
+//! ```rust
+//! # use capsules::net::coap::message::{option_number, Message, MessageWriter};
+//! let mut writer = MessageWriter::new(&mut buf, &header)?;
+//! writer.add_path(b"sensors/temperature")?;
+//! writer.add_uint_option(option_number::CONTENT_FORMAT, 0)?;
+//! writer.set_payload(b"21.5")?;
+//! let len = writer.finish();
+//!
+//! if let Some((_, message)) = Message::decode(&buf[..len]).done() {
+//!     for (number, value) in message.options() {
+//!         // ...
+//!     }
+//! }
+//! ```
+//!
+//! Block-wise transfers (RFC 7959) split a payload that does not fit in one
+//! message across several messages, each of which carries a `BlockOption`
+//! with the number and size of its block.
+
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_u16, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u8};
+use kernel::ReturnCode;
+
+pub const VERSION: u8 = 1;
+pub const MAX_TOKEN_LEN: usize = 8;
+pub const PAYLOAD_MARKER: u8 = 0xff;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MessageType {
+    Confirmable = 0,
+    NonConfirmable = 1,
+    Acknowledgement = 2,
+    Reset = 3,
+}
+
+impl MessageType {
+    fn from_bits(bits: u8) -> MessageType {
+        match bits & 0x3 {
+            0 => MessageType::Confirmable,
+            1 => MessageType::NonConfirmable,
+            2 => MessageType::Acknowledgement,
+            _ => MessageType::Reset,
+        }
+    }
+}
+
+/// Codes of the requests and responses, as `class << 5 | detail`.
+pub mod code {
+    pub const EMPTY: u8 = 0x00;
+
+    pub const GET: u8 = 0x01;
+    pub const POST: u8 = 0x02;
+    pub const PUT: u8 = 0x03;
+    pub const DELETE: u8 = 0x04;
+
+    pub const CREATED: u8 = 0x41;
+    pub const DELETED: u8 = 0x42;
+    pub const VALID: u8 = 0x43;
+    pub const CHANGED: u8 = 0x44;
+    pub const CONTENT: u8 = 0x45;
+    pub const CONTINUE: u8 = 0x5f;
+
+    pub const BAD_REQUEST: u8 = 0x80;
+    pub const BAD_OPTION: u8 = 0x82;
+    pub const NOT_FOUND: u8 = 0x84;
+    pub const METHOD_NOT_ALLOWED: u8 = 0x85;
+    pub const REQUEST_ENTITY_INCOMPLETE: u8 = 0x88;
+    pub const REQUEST_ENTITY_TOO_LARGE: u8 = 0x8d;
+
+    pub const INTERNAL_SERVER_ERROR: u8 = 0xa0;
+    pub const SERVICE_UNAVAILABLE: u8 = 0xa3;
+}
+
+/// Numbers of the options. Options with odd numbers are critical: an
+/// endpoint that does not understand them must reject the message.
+pub mod option_number {
+    pub const URI_HOST: u16 = 3;
+    pub const URI_PORT: u16 = 7;
+    pub const URI_PATH: u16 = 11;
+    pub const CONTENT_FORMAT: u16 = 12;
+    pub const URI_QUERY: u16 = 15;
+    pub const BLOCK2: u16 = 23;
+    pub const BLOCK1: u16 = 27;
+    pub const SIZE2: u16 = 28;
+    pub const SIZE1: u16 = 60;
+}
+
+/// Returns whether `code` is the code of a request.
+pub fn is_request(code: u8) -> bool {
+    code != code::EMPTY && code >> 5 == 0
+}
+
+/// Returns whether `code` is the code of a response.
+pub fn is_response(code: u8) -> bool {
+    code >> 5 >= 2 && code >> 5 <= 5
+}
+
+/// The token that matches a response to its request.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Token {
+    len: u8,
+    bytes: [u8; MAX_TOKEN_LEN],
+}
+
+impl Token {
+    /// Returns the token made of the first `MAX_TOKEN_LEN` bytes of `bytes`.
+    pub fn new(bytes: &[u8]) -> Token {
+        let len = bytes.len().min(MAX_TOKEN_LEN);
+        let mut token = Token {
+            len: len as u8,
+            bytes: [0; MAX_TOKEN_LEN],
+        };
+        token.bytes[..len].copy_from_slice(&bytes[..len]);
+        token
+    }
+
+    pub fn empty() -> Token {
+        Token::new(&[])
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Header {
+    pub mtype: MessageType,
+    pub code: u8,
+    pub message_id: u16,
+    pub token: Token,
+}
+
+impl Header {
+    pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
+        let token = self.token.as_slice();
+        stream_len_cond!(buf, 4 + token.len());
+        let first = VERSION << 6 | (self.mtype as u8) << 4 | token.len() as u8;
+        let mut off = enc_consume!(buf, 0; encode_u8, first);
+        off = enc_consume!(buf, off; encode_u8, self.code);
+        off = enc_consume!(buf, off; encode_u16, self.message_id);
+        off = enc_consume!(buf, off; encode_bytes, token);
+        stream_done!(off, off);
+    }
+
+    pub fn decode(buf: &[u8]) -> SResult<Header> {
+        let (off, first) = dec_try!(buf, 0; decode_u8);
+        stream_cond!(first >> 6 == VERSION);
+        let token_len = (first & 0xf) as usize;
+        stream_cond!(token_len <= MAX_TOKEN_LEN);
+        let (off, code) = dec_try!(buf, off; decode_u8);
+        let (off, message_id) = dec_try!(buf, off; decode_u16);
+        stream_len_cond!(buf, off + token_len);
+        stream_done!(
+            off + token_len,
+            Header {
+                mtype: MessageType::from_bits(first >> 4),
+                code,
+                message_id,
+                token: Token::new(&buf[off..off + token_len]),
+            }
+        );
+    }
+}
+
+/// A received message. Decoding it checks that its options are well formed.
+pub struct Message<'b> {
+    pub header: Header,
+    options: &'b [u8],
+    pub payload: &'b [u8],
+}
+
+impl Message<'b> {
+    pub fn decode(buf: &'b [u8]) -> SResult<Message<'b>> {
+        let (off, header) = dec_try!(buf; Header::decode);
+        let mut options = Options::new(&buf[off..]);
+        while options.next().is_some() {}
+        stream_cond!(!options.malformed);
+        let payload_off = off + options.offset;
+        let payload = if payload_off < buf.len() {
+            // A payload marker must be followed by a payload
+            stream_cond!(payload_off + 1 < buf.len());
+            &buf[payload_off + 1..]
+        } else {
+            &[]
+        };
+        stream_done!(
+            buf.len(),
+            Message {
+                header,
+                options: &buf[off..payload_off],
+                payload,
+            }
+        );
+    }
+
+    pub fn options(&self) -> Options<'b> {
+        Options::new(self.options)
+    }
+
+    /// Returns the value of the first option numbered `number`.
+    pub fn option(&self, number: u16) -> Option<&'b [u8]> {
+        self.options()
+            .find(|(n, _)| *n == number)
+            .map(|(_, value)| value)
+    }
+
+    pub fn uint_option(&self, number: u16) -> Option<u32> {
+        self.option(number).and_then(decode_uint)
+    }
+
+    pub fn block_option(&self, number: u16) -> Option<BlockOption> {
+        self.option(number).and_then(BlockOption::decode)
+    }
+}
+
+/// Iterates over the options of a message, as pairs of a number and a
+/// value, until the payload marker.
+pub struct Options<'b> {
+    buf: &'b [u8],
+    offset: usize,
+    number: u16,
+    malformed: bool,
+}
+
+impl Options<'b> {
+    fn new(buf: &'b [u8]) -> Options<'b> {
+        Options {
+            buf,
+            offset: 0,
+            number: 0,
+            malformed: false,
+        }
+    }
+}
+
+impl Iterator for Options<'b> {
+    type Item = (u16, &'b [u8]);
+
+    fn next(&mut self) -> Option<(u16, &'b [u8])> {
+        let rest = &self.buf[self.offset..];
+        if self.malformed || rest.is_empty() || rest[0] == PAYLOAD_MARKER {
+            return None;
+        }
+        match decode_option_header(rest).done() {
+            Some((off, (delta, len))) if self.number as u32 + delta <= 0xffff => {
+                self.number += delta as u16;
+                self.offset += off + len as usize;
+                Some((self.number, &rest[off..off + len as usize]))
+            }
+            _ => {
+                self.malformed = true;
+                None
+            }
+        }
+    }
+}
+
+/// Decodes the nibble of an option delta or length, extended by the bytes
+/// that follow the first byte of the option.
+fn decode_extended(buf: &[u8], nibble: u8) -> SResult<u32> {
+    match nibble {
+        13 => {
+            let (off, value) = dec_try!(buf; decode_u8);
+            stream_done!(off, value as u32 + 13);
+        }
+        14 => {
+            let (off, value) = dec_try!(buf; decode_u16);
+            stream_done!(off, value as u32 + 269);
+        }
+        15 => stream_err!(),
+        _ => stream_done!(0, nibble as u32),
+    }
+}
+
+/// Decodes the delta and the length of an option, and checks that its value
+/// is in `buf`.
+fn decode_option_header(buf: &[u8]) -> SResult<(u32, u32)> {
+    let (off, first) = dec_try!(buf; decode_u8);
+    let (off, delta) = dec_try!(buf, off; decode_extended, first >> 4);
+    let (off, len) = dec_try!(buf, off; decode_extended, first & 0xf);
+    stream_len_cond!(buf, off + len as usize);
+    stream_done!(off, (delta, len));
+}
+
+/// Splits an option delta or length into the nibble of the first byte of the
+/// option and the extended bytes that follow it.
+fn split_extended(value: u32) -> (u8, [u8; 2], usize) {
+    if value < 13 {
+        (value as u8, [0; 2], 0)
+    } else if value < 269 {
+        (13, [(value - 13) as u8, 0], 1)
+    } else {
+        (14, ((value - 269) as u16).to_be_bytes(), 2)
+    }
+}
+
+/// Decodes the value of an option that is an unsigned integer.
+pub fn decode_uint(value: &[u8]) -> Option<u32> {
+    if value.len() > 4 {
+        return None;
+    }
+    Some(value.iter().fold(0, |n, b| n << 8 | *b as u32))
+}
+
+/// The value of a Block1 or Block2 option: the number of the block, whether
+/// more blocks follow, and the size exponent of the blocks.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BlockOption {
+    pub num: u32,
+    pub more: bool,
+    pub szx: u8,
+}
+
+impl BlockOption {
+    /// The largest size exponent, for blocks of 1024 bytes.
+    pub const MAX_SZX: u8 = 6;
+
+    pub fn decode(value: &[u8]) -> Option<BlockOption> {
+        let value = decode_uint(value)?;
+        if value > 0xff_ffff || value & 0x7 > BlockOption::MAX_SZX as u32 {
+            return None;
+        }
+        Some(BlockOption {
+            num: value >> 4,
+            more: value & 0x8 != 0,
+            szx: (value & 0x7) as u8,
+        })
+    }
+
+    pub fn encode(self) -> u32 {
+        self.num << 4 | (self.more as u32) << 3 | self.szx as u32
+    }
+
+    pub fn size(self) -> usize {
+        1 << (self.szx + 4)
+    }
+
+    /// Returns the offset of the block in the payload.
+    pub fn offset(self) -> usize {
+        self.num as usize * self.size()
+    }
+}
+
+/// Writes a message into a buffer: its header, then its options in
+/// increasing order of their numbers, then its payload.
+pub struct MessageWriter<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+    number: u16,
+    has_payload: bool,
+}
+
+impl MessageWriter<'b> {
+    /// Returns ESIZE if the header does not fit in `buf`.
+    pub fn new(buf: &'b mut [u8], header: &Header) -> Result<MessageWriter<'b>, ReturnCode> {
+        match header.encode(buf).done() {
+            Some((len, _)) => Ok(MessageWriter {
+                buf,
+                len,
+                number: 0,
+                has_payload: false,
+            }),
+            None => Err(ReturnCode::ESIZE),
+        }
+    }
+
+    /// Adds an option. Returns EINVAL if an option with a larger number or
+    /// the payload was already added, and ESIZE if the option does not fit.
+    pub fn add_option(&mut self, number: u16, value: &[u8]) -> Result<(), ReturnCode> {
+        if number < self.number || self.has_payload || value.len() > 0xffff {
+            return Err(ReturnCode::EINVAL);
+        }
+        let (delta_nibble, delta_ext, delta_ext_len) =
+            split_extended((number - self.number) as u32);
+        let (len_nibble, len_ext, len_ext_len) = split_extended(value.len() as u32);
+        let option_len = 1 + delta_ext_len + len_ext_len + value.len();
+        if self.len + option_len > self.buf.len() {
+            return Err(ReturnCode::ESIZE);
+        }
+
+        let buf = &mut self.buf[self.len..self.len + option_len];
+        buf[0] = delta_nibble << 4 | len_nibble;
+        let mut off = 1;
+        buf[off..off + delta_ext_len].copy_from_slice(&delta_ext[..delta_ext_len]);
+        off += delta_ext_len;
+        buf[off..off + len_ext_len].copy_from_slice(&len_ext[..len_ext_len]);
+        off += len_ext_len;
+        buf[off..].copy_from_slice(value);
+        self.len += option_len;
+        self.number = number;
+        Ok(())
+    }
+
+    /// Adds an option whose value is an unsigned integer, in as few bytes as
+    /// it takes.
+    pub fn add_uint_option(&mut self, number: u16, value: u32) -> Result<(), ReturnCode> {
+        let bytes = value.to_be_bytes();
+        let skip = (value.leading_zeros() / 8) as usize;
+        self.add_option(number, &bytes[skip..])
+    }
+
+    /// Adds a Uri-Path option for each segment of `path`, whose segments are
+    /// separated by '/'.
+    pub fn add_path(&mut self, path: &[u8]) -> Result<(), ReturnCode> {
+        for segment in path.split(|b| *b == b'/').filter(|s| !s.is_empty()) {
+            self.add_option(option_number::URI_PATH, segment)?;
+        }
+        Ok(())
+    }
+
+    /// Adds the payload, after which no option can be added. An empty
+    /// payload is left out, along with its marker.
+    pub fn set_payload(&mut self, payload: &[u8]) -> Result<(), ReturnCode> {
+        if self.has_payload {
+            return Err(ReturnCode::EINVAL);
+        }
+        if payload.is_empty() {
+            return Ok(());
+        }
+        if self.len + 1 + payload.len() > self.buf.len() {
+            return Err(ReturnCode::ESIZE);
+        }
+        self.buf[self.len] = PAYLOAD_MARKER;
+        self.buf[self.len + 1..self.len + 1 + payload.len()].copy_from_slice(payload);
+        self.len += 1 + payload.len();
+        self.has_payload = true;
+        Ok(())
+    }
+
+    /// Returns the length of the message written.
+    pub fn finish(self) -> usize {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    fn header() -> Header {
+        Header {
+            mtype: MessageType::Confirmable,
+            code: code::GET,
+            message_id: 0x1234,
+            token: Token::new(&[0xaa, 0xbb]),
+        }
+    }
+
+    fn options(message: &Message) -> Vec<(u16, Vec<u8>)> {
+        message
+            .options()
+            .map(|(number, value)| (number, value.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn writes_and_decodes_messages() {
+        let mut buf = [0; 64];
+        let mut writer = MessageWriter::new(&mut buf, &header()).unwrap();
+        writer.add_path(b"/sensors//temp").unwrap();
+        writer
+            .add_uint_option(option_number::CONTENT_FORMAT, 0)
+            .unwrap();
+        writer.add_uint_option(option_number::BLOCK2, 0x1a).unwrap();
+        writer.set_payload(b"21.5").unwrap();
+        let len = writer.finish();
+        assert_eq!(
+            &buf[..len],
+            &[
+                0x42, 0x01, 0x12, 0x34, 0xaa, 0xbb, // header and token
+                0xb7, b's', b'e', b'n', b's', b'o', b'r', b's', // Uri-Path
+                0x04, b't', b'e', b'm', b'p', // Uri-Path
+                0x10, // Content-Format 0
+                0xb1, 0x1a, // Block2
+                0xff, b'2', b'1', b'.', b'5',
+            ][..]
+        );
+
+        let (off, message) = Message::decode(&buf[..len]).done().unwrap();
+        assert_eq!(off, len);
+        assert_eq!(message.header.mtype, MessageType::Confirmable);
+        assert_eq!(message.header.code, code::GET);
+        assert_eq!(message.header.message_id, 0x1234);
+        assert_eq!(message.header.token, Token::new(&[0xaa, 0xbb]));
+        assert_eq!(
+            options(&message),
+            [
+                (option_number::URI_PATH, b"sensors".to_vec()),
+                (option_number::URI_PATH, b"temp".to_vec()),
+                (option_number::CONTENT_FORMAT, Vec::new()),
+                (option_number::BLOCK2, [0x1a].to_vec()),
+            ]
+        );
+        assert_eq!(message.uint_option(option_number::CONTENT_FORMAT), Some(0));
+        assert_eq!(message.option(option_number::URI_QUERY), None);
+        assert_eq!(message.payload, b"21.5");
+    }
+
+    #[test]
+    fn extended_deltas_and_lengths() {
+        let short = [0x11; 20];
+        let long = [0x22; 300];
+        let mut buf = [0; 400];
+        let mut writer = MessageWriter::new(&mut buf, &header()).unwrap();
+        // Delta 60 and length 20 take one extended byte each, and delta 2000
+        // and length 300 two
+        writer.add_option(60, &short).unwrap();
+        writer.add_option(2060, &long).unwrap();
+        let len = writer.finish();
+        assert_eq!(&buf[6..9], &[0xdd, 60 - 13, 20 - 13]);
+        let second = 9 + short.len();
+        assert_eq!(
+            &buf[second..second + 5],
+            &[0xee, 0x06, 0xc3, 0x00, (300 - 269) as u8]
+        );
+        assert_eq!(len, second + 5 + long.len());
+
+        let (_, message) = Message::decode(&buf[..len]).done().unwrap();
+        assert_eq!(
+            options(&message),
+            [(60, short.to_vec()), (2060, long.to_vec())]
+        );
+        assert!(message.payload.is_empty());
+
+        // The smallest values of each form
+        for &(value, first, ext) in &[
+            (12, 12, &[][..]),
+            (13, 13, &[0][..]),
+            (268, 13, &[255][..]),
+            (269, 14, &[0, 0][..]),
+        ] {
+            let (nibble, bytes, ext_len) = split_extended(value);
+            assert_eq!((nibble, &bytes[..ext_len]), (first, ext));
+            let mut option = [0; 3];
+            option[0] = nibble;
+            option[1..=ext_len].copy_from_slice(&bytes[..ext_len]);
+            assert_eq!(
+                decode_extended(&option[1..], nibble).done(),
+                Some((ext_len, value))
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        // Truncated headers and tokens need more bytes
+        assert!(Message::decode(&[0x40, 0x01, 0x00]).is_needed());
+        assert!(Message::decode(&[0x42, 0x01, 0x00, 0x01, 0xaa]).is_needed());
+        // Another version, and tokens longer than 8 bytes
+        assert!(Message::decode(&[0x80, 0x01, 0x00, 0x01]).is_err());
+        assert!(Message::decode(&[0x49, 0x01, 0x00, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+
+        let malformed: &[&[u8]] = &[
+            // A delta or length nibble of 15 outside of the payload marker
+            &[0xf0],
+            &[0x0f],
+            // Extended deltas and lengths that are cut short
+            &[0xd0],
+            &[0xe0, 0x00],
+            &[0x0d],
+            &[0x0e, 0x00],
+            // A value that runs past the end
+            &[0x03, b'a', b'b'],
+            &[0x0d, 0x00, b'a'],
+            // Option numbers past 65535
+            &[0xe0, 0xff, 0xff, 0xe0, 0x00, 0x00],
+            // A payload marker that is not followed by a payload
+            &[0xff],
+            &[0xb1, b'a', 0xff],
+        ];
+        for bytes in malformed {
+            let mut buf = [0x40, 0x01, 0x00, 0x01].to_vec();
+            buf.extend_from_slice(bytes);
+            assert!(Message::decode(&buf).is_err(), "{:x?}", bytes);
+        }
+
+        // A payload marker right after the header, and the largest option
+        // number
+        let (_, message) = Message::decode(&[0x40, 0x01, 0x00, 0x01, 0xff, 0x00])
+            .done()
+            .unwrap();
+        assert!(options(&message).is_empty());
+        assert_eq!(message.payload, &[0x00]);
+        let (_, message) = Message::decode(&[0x40, 0x01, 0x00, 0x01, 0xe0, 0xfe, 0xf2])
+            .done()
+            .unwrap();
+        assert_eq!(options(&message), [(0xffff, Vec::new())]);
+    }
+
+    #[test]
+    fn writer_rejects_misplaced_options() {
+        let mut buf = [0; 16];
+        let mut writer = MessageWriter::new(&mut buf, &header()).unwrap();
+        writer.add_option(option_number::URI_PATH, b"a").unwrap();
+        // Options are added in order, and before the payload
+        assert_eq!(
+            writer.add_option(option_number::URI_HOST, b"h"),
+            Err(ReturnCode::EINVAL)
+        );
+        // An empty payload is left out
+        writer.set_payload(&[]).unwrap();
+        writer.add_option(option_number::URI_PATH, b"b").unwrap();
+        assert_eq!(writer.set_payload(b"too long"), Err(ReturnCode::ESIZE));
+        writer.set_payload(b"ok").unwrap();
+        assert_eq!(
+            writer.add_option(option_number::SIZE1, &[]),
+            Err(ReturnCode::EINVAL)
+        );
+        assert_eq!(writer.set_payload(b"x"), Err(ReturnCode::EINVAL));
+        assert_eq!(writer.finish(), 6 + 2 + 2 + 3);
+
+        // The header and the token do not fit
+        assert!(MessageWriter::new(&mut buf[..5], &header()).is_err());
+    }
+
+    #[test]
+    fn block_options() {
+        let block = BlockOption {
+            num: 5,
+            more: true,
+            szx: 2,
+        };
+        assert_eq!(block.encode(), 0x5a);
+        assert_eq!(BlockOption::decode(&[0x5a]), Some(block));
+        assert_eq!(block.size(), 64);
+        assert_eq!(block.offset(), 320);
+
+        let last = BlockOption {
+            num: 0xf_ffff,
+            more: false,
+            szx: BlockOption::MAX_SZX,
+        };
+        assert_eq!(BlockOption::decode(&[0xff, 0xff, 0xf6]), Some(last));
+        assert_eq!(last.size(), 1024);
+
+        // An empty value is the first block of 16 bytes
+        assert_eq!(
+            BlockOption::decode(&[]),
+            Some(BlockOption {
+                num: 0,
+                more: false,
+                szx: 0,
+            })
+        );
+        // A size exponent of 7 is reserved, and values are at most 3 bytes
+        assert_eq!(BlockOption::decode(&[0x07]), None);
+        assert_eq!(BlockOption::decode(&[0x01, 0x00, 0x00, 0x00]), None);
+
+        let mut buf = [0; 16];
+        let mut writer = MessageWriter::new(&mut buf, &header()).unwrap();
+        writer
+            .add_uint_option(option_number::BLOCK1, last.encode())
+            .unwrap();
+        let len = writer.finish();
+        let (_, message) = Message::decode(&buf[..len]).done().unwrap();
+        assert_eq!(message.block_option(option_number::BLOCK1), Some(last));
+        assert_eq!(message.block_option(option_number::BLOCK2), None);
+    }
+}
@@ -0,0 +1,6 @@
+pub mod coap;
+pub mod driver;
+pub mod message;
+
+pub use self::driver::CoapDriver;
+pub use self::driver::DRIVER_NUM;
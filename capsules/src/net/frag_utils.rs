@@ -27,6 +27,19 @@ impl Bitmap {
         self.map[map_idx] |= 1 << (idx % 8);
     }
 
+    pub fn get_bit(&self, idx: usize) -> bool {
+        let map_idx = idx / 8;
+        self.map[map_idx] & (1 << (idx % 8)) != 0
+    }
+
+    // Returns the number of bits set from start_idx (inclusive) to end_idx
+    // (exclusive).
+    pub fn count_bits(&self, start_idx: usize, end_idx: usize) -> usize {
+        (start_idx..end_idx)
+            .filter(|&idx| self.get_bit(idx))
+            .count()
+    }
+
     // Sets bits from start_idx (inclusive) to end_idx (exclusive).
     // Returns false if any bits set overlap with already set bits,
     // true otherwise.
@@ -40,6 +53,10 @@ impl Bitmap {
         if start_idx > end_idx {
             return false;
         }
+        if start_idx == end_idx {
+            // Nothing to set, and `end_idx` may be past the end of the map
+            return true;
+        }
         let start_byte_idx = start_idx / 8;
         let end_byte_idx = end_idx / 8;
         let first = 0xff << (start_idx % 8);
@@ -54,9 +71,13 @@ impl Bitmap {
             result
         } else {
             let mut result = (self.map[start_byte_idx] & first) == 0;
-            result = result && ((self.map[end_byte_idx] & second) == 0);
             self.map[start_byte_idx] |= first;
-            self.map[end_byte_idx] |= second;
+            // No bits of the end byte are set when `end_idx` is a multiple
+            // of 8, and it is past the end of the map for a 1280 byte packet
+            if second != 0 {
+                result = result && ((self.map[end_byte_idx] & second) == 0);
+                self.map[end_byte_idx] |= second;
+            }
             // Set all bytes between start and end bytes.
             for i in start_byte_idx + 1..end_byte_idx {
                 result = result && (self.map[i] == 0);
@@ -71,9 +92,45 @@ impl Bitmap {
         for i in 0..total_length / 8 {
             result = result && (self.map[i] == 0xff);
         }
-        // Check last byte.
-        let mask = 0xff >> (8 - (total_length % 8));
-        result = result && (self.map[total_length / 8] == mask);
+        // Check last byte, if the length does not end on a byte boundary.
+        if total_length % 8 != 0 {
+            let mask = 0xff >> (8 - (total_length % 8));
+            result = result && (self.map[total_length / 8] == mask);
+        }
         result
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_bits_and_complete() {
+        let mut bitmap = Bitmap::new();
+        assert!(bitmap.set_bits(0, 7));
+        assert!(!bitmap.is_complete(10));
+        assert!(bitmap.set_bits(7, 10));
+        assert!(bitmap.is_complete(10));
+        assert!(!bitmap.set_bits(9, 11));
+        assert_eq!(bitmap.count_bits(0, 12), 11);
+    }
+
+    #[test]
+    fn lengths_on_byte_boundaries() {
+        // A 64 byte datagram
+        let mut bitmap = Bitmap::new();
+        assert!(bitmap.set_bits(0, 7));
+        assert!(!bitmap.is_complete(8));
+        assert!(bitmap.set_bits(7, 8));
+        assert!(bitmap.is_complete(8));
+
+        // A datagram of the IPv6 minimum MTU fills the whole map
+        bitmap.clear();
+        assert!(bitmap.set_bits(0, 80));
+        assert!(bitmap.set_bits(80, 160));
+        assert!(bitmap.set_bits(160, 160));
+        assert!(bitmap.is_complete(160));
+        assert_eq!(bitmap.count_bits(0, 160), 160);
+    }
+}
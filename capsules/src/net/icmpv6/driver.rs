@@ -0,0 +1,309 @@
+//! ICMPv6 echo (ping) userspace interface.
+//!
+//! Lets processes send Echo Requests and receive the Echo Replies to them,
+//! as well as the error messages sent back for them, e.g. when the
+//! destination is unreachable. The identifier of the requests of a process is
+//! the index of its AppId, and the process picks the sequence number of each
+//! request.
+//!
+//! Echo Requests sent to this device are answered by the `ICMP6RecvStruct`,
+//! whether or not a process uses this driver.
+
+use crate::net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::icmpv6::icmpv6_recv::ICMP6RecvClient;
+use crate::net::icmpv6::icmpv6_send::{ICMP6SendClient, ICMP6Sender};
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6::IP6Header;
+use core::cell::Cell;
+use core::cmp::min;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReadOnly, ReturnCode, Shared};
+
+/// Syscall number
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Icmp6 as usize;
+
+// Offsets in the body of an error message, which starts with the IPv6 header
+// of the packet that caused the error, followed by its ICMPv6 header.
+const ERR_NEXT_HEADER: usize = 6;
+const ERR_ICMP_TYPE: usize = 40;
+const ERR_ECHO_ID: usize = 44;
+const ERR_ECHO_SEQNO: usize = 46;
+const ERR_ECHO_END: usize = 48;
+
+#[derive(Clone, Copy)]
+struct EchoRequest {
+    dst_addr: IPAddr,
+    seqno: u16,
+    len: usize,
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+    app_read: Option<AppSlice<Shared, u8>>,
+    app_write: Option<AppSlice<ReadOnly, u8>>,
+    app_cfg: Option<AppSlice<Shared, u8>>,
+    pending_tx: Option<EchoRequest>,
+}
+
+pub struct ICMP6Driver<'a> {
+    icmp_sender: &'a dyn ICMP6Sender<'a>,
+    apps: Grant<App>,
+    /// Maximum length of the data of an Echo Request
+    max_payload_len: usize,
+}
+
+impl<'a> ICMP6Driver<'a> {
+    pub fn new(
+        icmp_sender: &'a dyn ICMP6Sender<'a>,
+        grant: Grant<App>,
+        max_payload_len: usize,
+    ) -> ICMP6Driver<'a> {
+        ICMP6Driver {
+            icmp_sender,
+            apps: grant,
+            max_payload_len,
+        }
+    }
+
+    /// Utility function to perform an action on an app in a system call.
+    #[inline]
+    fn do_with_app<F>(&self, appid: AppId, closure: F) -> ReturnCode
+    where
+        F: FnOnce(&mut App) -> ReturnCode,
+    {
+        self.apps
+            .enter(appid, |app, _| closure(app))
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Sends the pending Echo Request of `app`. It stays pending if the
+    /// sender is busy, and is sent again when the sender is done.
+    fn send_request(&self, appid: AppId, app: &mut App) -> ReturnCode {
+        // Taken first, as the sender may call `send_done` before returning
+        let request = match app.pending_tx.take() {
+            Some(request) => request,
+            None => return ReturnCode::SUCCESS,
+        };
+        let mut icmp_header = ICMP6Header::new(ICMP6Type::Type128);
+        icmp_header.set_options(ICMP6HeaderOptions::Type128 {
+            id: appid.idx() as u16,
+            seqno: request.seqno,
+        });
+        let result = match app.app_write {
+            Some(ref data) => {
+                self.icmp_sender
+                    .send(request.dst_addr, icmp_header, &data.as_ref()[..request.len])
+            }
+            None => self.icmp_sender.send(request.dst_addr, icmp_header, &[]),
+        };
+        if result == ReturnCode::EBUSY {
+            app.pending_tx = Some(request);
+            return ReturnCode::SUCCESS;
+        }
+        result
+    }
+
+    /// Performs an action on the app whose Echo Requests have the identifier
+    /// `id`.
+    fn do_with_echo_id<F>(&self, id: u16, closure: F)
+    where
+        F: Fn(&mut App),
+    {
+        self.apps.each(|app| {
+            if app.appid().idx() as u16 == id {
+                closure(app);
+            }
+        });
+    }
+}
+
+impl<'a> Driver for ICMP6Driver<'a> {
+    /// Setup buffers to read/write from.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Read buffer. Receives the data of Echo Replies.
+    /// - `1`: Write buffer. Contains the data of Echo Requests. Prefer
+    ///        `allow_readonly`.
+    /// - `2`: Config buffer. Contains the 16 byte IPv6 address Echo Requests
+    ///        are sent to.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 | 2 => self.do_with_app(appid, |app| {
+                match allow_num {
+                    0 => app.app_read = slice,
+                    1 => app.app_write = slice.map(AppSlice::from),
+                    2 => app.app_cfg = slice,
+                    _ => {}
+                }
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup buffers to read from.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `1`: Write buffer. Contains the data of Echo Requests.
+    fn allow_readonly(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<ReadOnly, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            1 => self.do_with_app(appid, |app| {
+                app.app_write = slice;
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: A message was received for an Echo Request of the process. The
+    ///        first argument is the ICMPv6 type of the message and the second
+    ///        one is the sequence number of the request. For Echo Replies
+    ///        (type 129), the third argument is the length of the data of the
+    ///        reply, which is copied to the read buffer. For error messages
+    ///        (types 1, 3 and 4), it is the ICMPv6 code of the error. If the
+    ///        type is 0, the request could not be sent, and the third
+    ///        argument is the error.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self.do_with_app(app_id, |app| {
+                app.callback = callback;
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// ICMPv6 echo control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Send an Echo Request to the address in the config buffer, with
+    ///        the first `arg1` bytes of the write buffer as data and `arg2`
+    ///        as sequence number. Returns EBUSY if the previous request of
+    ///        the process was not sent yet, EINVAL if the config buffer does
+    ///        not hold an address and ESIZE if the data is too long.
+    /// - `2`: Get the maximum length of the data of an Echo Request.
+    fn command(&self, command_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => self.do_with_app(appid, |app| {
+                if app.pending_tx.is_some() {
+                    return ReturnCode::EBUSY;
+                }
+                let dst_addr = match app.app_cfg {
+                    Some(ref cfg) if cfg.len() >= 16 => {
+                        let mut addr = IPAddr::new();
+                        addr.0.copy_from_slice(&cfg.as_ref()[..16]);
+                        addr
+                    }
+                    _ => return ReturnCode::EINVAL,
+                };
+                if arg2 > 0xffff || dst_addr.is_unspecified() {
+                    return ReturnCode::EINVAL;
+                }
+                let write_len = app.app_write.as_ref().map_or(0, |data| data.len());
+                if arg1 > self.max_payload_len || arg1 > write_len {
+                    return ReturnCode::ESIZE;
+                }
+                app.pending_tx = Some(EchoRequest {
+                    dst_addr,
+                    seqno: arg2 as u16,
+                    len: arg1,
+                });
+                self.send_request(appid, app)
+            }),
+
+            2 => ReturnCode::SuccessWithValue {
+                value: self.max_payload_len,
+            },
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<'a> ICMP6SendClient for ICMP6Driver<'a> {
+    /// Sends the next pending Echo Request. The completed send may have been
+    /// an Echo Reply or an error message of the `ICMP6RecvStruct`, which
+    /// shares the sender.
+    fn send_done(&self, _result: ReturnCode) {
+        let done = Cell::new(false);
+        self.apps.each(|app| {
+            if done.get() || app.pending_tx.is_none() {
+                return;
+            }
+            let appid = app.appid();
+            let seqno = app.pending_tx.map_or(0, |request| request.seqno);
+            let result = self.send_request(appid, app);
+            if result == ReturnCode::SUCCESS {
+                done.set(true);
+            } else {
+                app.callback
+                    .map(|mut cb| cb.schedule(0, seqno as usize, usize::from(result)));
+            }
+        });
+    }
+}
+
+impl<'a> ICMP6RecvClient for ICMP6Driver<'a> {
+    fn receive(&self, _ip6_header: &IP6Header, icmp_header: ICMP6Header, payload: &[u8]) {
+        let icmp_type = icmp_header.get_type_as_int() as usize;
+        match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type129 { id, seqno } => {
+                self.do_with_echo_id(id, |app| {
+                    let len = app.app_read.as_mut().map_or(0, |buf| {
+                        let len = min(buf.len(), payload.len());
+                        buf.as_mut()[..len].copy_from_slice(&payload[..len]);
+                        len
+                    });
+                    app.callback
+                        .map(|mut cb| cb.schedule(icmp_type, seqno as usize, len));
+                });
+            }
+            ICMP6HeaderOptions::Type1 { .. }
+            | ICMP6HeaderOptions::Type3 { .. }
+            | ICMP6HeaderOptions::Type4 { .. } => {
+                // Only errors caused by Echo Requests are passed to processes
+                if payload.len() < ERR_ECHO_END
+                    || payload[ERR_NEXT_HEADER] != ip6_nh::ICMP
+                    || payload[ERR_ICMP_TYPE] != 128
+                {
+                    return;
+                }
+                let id = (payload[ERR_ECHO_ID] as u16) << 8 | payload[ERR_ECHO_ID + 1] as u16;
+                let seqno =
+                    (payload[ERR_ECHO_SEQNO] as u16) << 8 | payload[ERR_ECHO_SEQNO + 1] as u16;
+                let code = icmp_header.get_code() as usize;
+                self.do_with_echo_id(id, |app| {
+                    app.callback
+                        .map(|mut cb| cb.schedule(icmp_type, seqno as usize, code));
+                });
+            }
+            _ => {}
+        }
+    }
+}
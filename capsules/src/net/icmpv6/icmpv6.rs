@@ -19,18 +19,75 @@ pub struct ICMP6Header {
 
 #[derive(Copy, Clone)]
 pub enum ICMP6HeaderOptions {
-    Type1 { unused: u32 },
-    Type3 { unused: u32 },
-    Type128 { id: u16, seqno: u16 },
-    Type129 { id: u16, seqno: u16 },
+    Type1 {
+        unused: u32,
+    },
+    Type3 {
+        unused: u32,
+    },
+    Type4 {
+        pointer: u32,
+    },
+    Type128 {
+        id: u16,
+        seqno: u16,
+    },
+    Type129 {
+        id: u16,
+        seqno: u16,
+    },
+    Type133 {
+        reserved: u32,
+    },
+    Type134 {
+        hop_limit: u8,
+        flags: u8,
+        router_lifetime: u16,
+    },
+    Type135 {
+        reserved: u32,
+    },
+    Type136 {
+        flags: u32,
+    },
+    // The body of RPL control messages starts right after the checksum
+    Type155,
+}
+
+/// Codes of Destination Unreachable messages.
+pub mod dest_unreachable_code {
+    pub const NO_ROUTE: u8 = 0;
+    pub const ADMIN_PROHIBITED: u8 = 1;
+    pub const BEYOND_SCOPE: u8 = 2;
+    pub const ADDRESS_UNREACHABLE: u8 = 3;
+    pub const PORT_UNREACHABLE: u8 = 4;
+}
+
+/// Codes of Time Exceeded messages.
+pub mod time_exceeded_code {
+    pub const HOP_LIMIT_EXCEEDED: u8 = 0;
+    pub const REASSEMBLY_TIME_EXCEEDED: u8 = 1;
+}
+
+/// Codes of Parameter Problem messages.
+pub mod param_problem_code {
+    pub const ERRONEOUS_HEADER_FIELD: u8 = 0;
+    pub const UNRECOGNIZED_NEXT_HEADER: u8 = 1;
+    pub const UNRECOGNIZED_OPTION: u8 = 2;
 }
 
 #[derive(Copy, Clone)]
 pub enum ICMP6Type {
     Type1,   // Destination Unreachable
     Type3,   // Time Exceeded
+    Type4,   // Parameter Problem
     Type128, // Echo Request
     Type129, // Echo Reply
+    Type133, // Router Solicitation
+    Type134, // Router Advertisement
+    Type135, // Neighbor Solicitation
+    Type136, // Neighbor Advertisement
+    Type155, // RPL Control Message
 }
 
 impl ICMP6Header {
@@ -38,8 +95,18 @@ impl ICMP6Header {
         let options = match icmp_type {
             ICMP6Type::Type1 => ICMP6HeaderOptions::Type1 { unused: 0 },
             ICMP6Type::Type3 => ICMP6HeaderOptions::Type3 { unused: 0 },
+            ICMP6Type::Type4 => ICMP6HeaderOptions::Type4 { pointer: 0 },
             ICMP6Type::Type128 => ICMP6HeaderOptions::Type128 { id: 0, seqno: 0 },
             ICMP6Type::Type129 => ICMP6HeaderOptions::Type129 { id: 0, seqno: 0 },
+            ICMP6Type::Type133 => ICMP6HeaderOptions::Type133 { reserved: 0 },
+            ICMP6Type::Type134 => ICMP6HeaderOptions::Type134 {
+                hop_limit: 0,
+                flags: 0,
+                router_lifetime: 0,
+            },
+            ICMP6Type::Type135 => ICMP6HeaderOptions::Type135 { reserved: 0 },
+            ICMP6Type::Type136 => ICMP6HeaderOptions::Type136 { flags: 0 },
+            ICMP6Type::Type155 => ICMP6HeaderOptions::Type155,
         };
 
         ICMP6Header {
@@ -51,12 +118,7 @@ impl ICMP6Header {
     }
 
     pub fn set_type(&mut self, icmp_type: ICMP6Type) {
-        match icmp_type {
-            ICMP6Type::Type1 => self.set_options(ICMP6HeaderOptions::Type1 { unused: 0 }),
-            ICMP6Type::Type3 => self.set_options(ICMP6HeaderOptions::Type3 { unused: 0 }),
-            ICMP6Type::Type128 => self.set_options(ICMP6HeaderOptions::Type128 { id: 0, seqno: 0 }),
-            ICMP6Type::Type129 => self.set_options(ICMP6HeaderOptions::Type129 { id: 0, seqno: 0 }),
-        }
+        self.set_options(ICMP6Header::new(icmp_type).options);
     }
 
     pub fn set_code(&mut self, code: u8) {
@@ -79,8 +141,14 @@ impl ICMP6Header {
         match self.options {
             ICMP6HeaderOptions::Type1 { .. } => ICMP6Type::Type1,
             ICMP6HeaderOptions::Type3 { .. } => ICMP6Type::Type3,
+            ICMP6HeaderOptions::Type4 { .. } => ICMP6Type::Type4,
             ICMP6HeaderOptions::Type128 { .. } => ICMP6Type::Type128,
             ICMP6HeaderOptions::Type129 { .. } => ICMP6Type::Type129,
+            ICMP6HeaderOptions::Type133 { .. } => ICMP6Type::Type133,
+            ICMP6HeaderOptions::Type134 { .. } => ICMP6Type::Type134,
+            ICMP6HeaderOptions::Type135 { .. } => ICMP6Type::Type135,
+            ICMP6HeaderOptions::Type136 { .. } => ICMP6Type::Type136,
+            ICMP6HeaderOptions::Type155 => ICMP6Type::Type155,
         }
     }
 
@@ -88,8 +156,14 @@ impl ICMP6Header {
         match self.get_type() {
             ICMP6Type::Type1 => 1,
             ICMP6Type::Type3 => 3,
+            ICMP6Type::Type4 => 4,
             ICMP6Type::Type128 => 128,
             ICMP6Type::Type129 => 129,
+            ICMP6Type::Type133 => 133,
+            ICMP6Type::Type134 => 134,
+            ICMP6Type::Type135 => 135,
+            ICMP6Type::Type136 => 136,
+            ICMP6Type::Type155 => 155,
         }
     }
 
@@ -110,7 +184,16 @@ impl ICMP6Header {
     }
 
     pub fn get_hdr_size(&self) -> usize {
-        8
+        match self.options {
+            ICMP6HeaderOptions::Type155 => 4,
+            _ => 8,
+        }
+    }
+
+    /// Returns whether this is an error message rather than an informational
+    /// message, which must never be answered with an error.
+    pub fn is_error(&self) -> bool {
+        self.get_type_as_int() < 128
     }
 
     /// Serializes an `ICMP6Header` into a buffer.
@@ -132,14 +215,33 @@ impl ICMP6Header {
         off = enc_consume!(buf, off; encode_u16, self.cksum);
 
         match self.options {
-            ICMP6HeaderOptions::Type1 { unused } | ICMP6HeaderOptions::Type3 { unused } => {
+            ICMP6HeaderOptions::Type1 { unused }
+            | ICMP6HeaderOptions::Type3 { unused }
+            | ICMP6HeaderOptions::Type133 { reserved: unused }
+            | ICMP6HeaderOptions::Type135 { reserved: unused } => {
                 off = enc_consume!(buf, off; encode_u32, unused);
             }
+            ICMP6HeaderOptions::Type4 { pointer } => {
+                off = enc_consume!(buf, off; encode_u32, pointer);
+            }
             ICMP6HeaderOptions::Type128 { id, seqno }
             | ICMP6HeaderOptions::Type129 { id, seqno } => {
                 off = enc_consume!(buf, off; encode_u16, id);
                 off = enc_consume!(buf, off; encode_u16, seqno);
             }
+            ICMP6HeaderOptions::Type134 {
+                hop_limit,
+                flags,
+                router_lifetime,
+            } => {
+                off = enc_consume!(buf, off; encode_u8, hop_limit);
+                off = enc_consume!(buf, off; encode_u8, flags);
+                off = enc_consume!(buf, off; encode_u16, router_lifetime);
+            }
+            ICMP6HeaderOptions::Type136 { flags } => {
+                off = enc_consume!(buf, off; encode_u32, flags);
+            }
+            ICMP6HeaderOptions::Type155 => {}
         }
 
         stream_done!(off, off);
@@ -149,11 +251,13 @@ impl ICMP6Header {
     ///
     /// # Arguments
     ///
-    /// `buf` - The byte array corresponding to the serialized `ICMP6Header`
+    /// `buf` - The byte array corresponding to the serialized `ICMP6Header`,
+    /// followed by the body of the message
     ///
     /// # Return Value
     ///
-    /// This function returns the `ICMP6Header`, wrapped in an SResult
+    /// This function returns the `ICMP6Header`, wrapped in an SResult. The
+    /// `len` of the header is the length of `buf`.
     pub fn decode(buf: &[u8]) -> SResult<ICMP6Header> {
         let off = 0;
         let (off, type_num) = dec_try!(buf, off; decode_u8);
@@ -161,8 +265,14 @@ impl ICMP6Header {
         let icmp_type = match type_num {
             1 => ICMP6Type::Type1,
             3 => ICMP6Type::Type3,
+            4 => ICMP6Type::Type4,
             128 => ICMP6Type::Type128,
             129 => ICMP6Type::Type129,
+            133 => ICMP6Type::Type133,
+            134 => ICMP6Type::Type134,
+            135 => ICMP6Type::Type135,
+            136 => ICMP6Type::Type136,
+            155 => ICMP6Type::Type155,
             _ => return SResult::Error(()),
         };
 
@@ -171,35 +281,121 @@ impl ICMP6Header {
         let (off, code) = dec_try!(buf, off; decode_u8);
         icmp_header.set_code(code);
         let (off, cksum) = dec_try!(buf, off; decode_u16);
-        icmp_header.set_cksum(u16::from_be(cksum));
+        icmp_header.set_cksum(cksum);
 
-        match icmp_type {
+        let off = match icmp_type {
             ICMP6Type::Type1 => {
-                let (_off, unused) = dec_try!(buf, off; decode_u32);
-                let unused = u32::from_be(unused);
+                let (off, unused) = dec_try!(buf, off; decode_u32);
                 icmp_header.set_options(ICMP6HeaderOptions::Type1 { unused });
+                off
             }
             ICMP6Type::Type3 => {
-                let (_off, unused) = dec_try!(buf, off; decode_u32);
-                let unused = u32::from_be(unused);
+                let (off, unused) = dec_try!(buf, off; decode_u32);
                 icmp_header.set_options(ICMP6HeaderOptions::Type3 { unused });
+                off
+            }
+            ICMP6Type::Type4 => {
+                let (off, pointer) = dec_try!(buf, off; decode_u32);
+                icmp_header.set_options(ICMP6HeaderOptions::Type4 { pointer });
+                off
             }
             ICMP6Type::Type128 => {
-                let (_off, id) = dec_try!(buf, off; decode_u16);
-                let id = u16::from_be(id);
-                let (_off, seqno) = dec_try!(buf, off; decode_u16);
-                let seqno = u16::from_be(seqno);
+                let (off, id) = dec_try!(buf, off; decode_u16);
+                let (off, seqno) = dec_try!(buf, off; decode_u16);
                 icmp_header.set_options(ICMP6HeaderOptions::Type128 { id, seqno });
+                off
             }
             ICMP6Type::Type129 => {
-                let (_off, id) = dec_try!(buf, off; decode_u16);
-                let id = u16::from_be(id);
-                let (_off, seqno) = dec_try!(buf, off; decode_u16);
-                let seqno = u16::from_be(seqno);
+                let (off, id) = dec_try!(buf, off; decode_u16);
+                let (off, seqno) = dec_try!(buf, off; decode_u16);
                 icmp_header.set_options(ICMP6HeaderOptions::Type129 { id, seqno });
+                off
             }
-        }
+            ICMP6Type::Type133 => {
+                let (off, reserved) = dec_try!(buf, off; decode_u32);
+                icmp_header.set_options(ICMP6HeaderOptions::Type133 { reserved });
+                off
+            }
+            ICMP6Type::Type134 => {
+                let (off, hop_limit) = dec_try!(buf, off; decode_u8);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, router_lifetime) = dec_try!(buf, off; decode_u16);
+                icmp_header.set_options(ICMP6HeaderOptions::Type134 {
+                    hop_limit,
+                    flags,
+                    router_lifetime,
+                });
+                off
+            }
+            ICMP6Type::Type135 => {
+                let (off, reserved) = dec_try!(buf, off; decode_u32);
+                icmp_header.set_options(ICMP6HeaderOptions::Type135 { reserved });
+                off
+            }
+            ICMP6Type::Type136 => {
+                let (off, flags) = dec_try!(buf, off; decode_u32);
+                icmp_header.set_options(ICMP6HeaderOptions::Type136 { flags });
+                off
+            }
+            ICMP6Type::Type155 => off,
+        };
+        icmp_header.set_len(buf.len() as u16);
 
         stream_done!(off, icmp_header);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_decode_echo_request() {
+        let mut header = ICMP6Header::new(ICMP6Type::Type128);
+        header.set_cksum(0xac1d);
+        header.set_options(ICMP6HeaderOptions::Type128 {
+            id: 0x1234,
+            seqno: 0x0102,
+        });
+        let mut buf = [0; 11];
+        assert_eq!(
+            header.encode(&mut buf, 0).done().map(|(off, _)| off),
+            Some(8)
+        );
+        assert_eq!(buf[..8], [128, 0, 0xac, 0x1d, 0x12, 0x34, 0x01, 0x02]);
+
+        let (off, decoded) = ICMP6Header::decode(&buf).done().unwrap();
+        assert_eq!(off, 8);
+        assert_eq!(decoded.get_type_as_int(), 128);
+        assert_eq!(decoded.get_cksum(), 0xac1d);
+        assert_eq!(decoded.get_len(), 11);
+        match decoded.get_options() {
+            ICMP6HeaderOptions::Type128 { id, seqno } => assert_eq!((id, seqno), (0x1234, 0x0102)),
+            _ => panic!("not an echo request"),
+        }
+    }
+
+    #[test]
+    fn decode_parameter_problem() {
+        let buf = [4, 1, 0, 0, 0, 0, 0, 6, 0x60];
+        let (_, decoded) = ICMP6Header::decode(&buf).done().unwrap();
+        assert!(decoded.is_error());
+        assert_eq!(
+            decoded.get_code(),
+            param_problem_code::UNRECOGNIZED_NEXT_HEADER
+        );
+        match decoded.get_options() {
+            ICMP6HeaderOptions::Type4 { pointer } => assert_eq!(pointer, 6),
+            _ => panic!("not a parameter problem"),
+        }
+    }
+
+    #[test]
+    fn decode_rejects_malformed_headers() {
+        // An unassigned type
+        assert!(ICMP6Header::decode(&[99, 0, 0, 0, 0, 0, 0, 0]).is_err());
+        // Truncated
+        assert_eq!(ICMP6Header::decode(&[128, 0, 0, 0, 0, 0]).needed(), Some(8));
+        assert!(ICMP6Header::decode(&[]).is_needed());
+    }
+}
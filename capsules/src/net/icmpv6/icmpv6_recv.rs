@@ -0,0 +1,204 @@
+//! This file contains the ICMPv6 receive path. The
+//! [ICMP6RecvStruct](struct.ICMP6RecvStruct.html) receives the IPv6 packets
+//! with the ICMP next header. It answers Echo Requests itself, and passes
+//! Echo Replies and error messages to its
+//! [ICMP6RecvClient](trait.ICMP6RecvClient.html), for example the ping
+//! driver, Neighbor Discovery messages to a second client, usually
+//! `NeighborDiscovery`, and RPL control messages to a third one, usually
+//! `RPLNode`.
+//!
+//! The `ICMP6RecvStruct` also implements
+//! [ICMP6ErrorReporter](trait.ICMP6ErrorReporter.html), through which the
+//! other layers of the stack report the packets they cannot deliver, such as
+//! UDP datagrams for a port nobody is bound to, or packets with a next header
+//! nobody handles. It answers them with an ICMPv6 error message that holds as
+//! much of the packet as fits.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use capsules::net::icmpv6::icmpv6_recv::ICMP6RecvStruct;
+//! # use capsules::net::ipv6::ip_utils::ip6_nh;
+//! # use capsules::net::ipv6::ipv6_recv::IP6RecvMuxClient;
+//! # use kernel::static_init;
+//! let icmp_recv = static_init!(
+//!     ICMP6RecvStruct<'static>,
+//!     ICMP6RecvStruct::new(icmp_send, &mut ICMP_BUF)
+//! );
+//! let icmp_mux_client = static_init!(
+//!     IP6RecvMuxClient<'static>,
+//!     IP6RecvMuxClient::new(ip6_nh::ICMP, icmp_recv)
+//! );
+//! ip_recv_mux.add_client(icmp_mux_client);
+//! ip_recv_mux.set_error_reporter(icmp_recv);
+//! // The mux forwards the errors its clients report
+//! udp_recv.set_error_reporter(ip_recv_mux);
+//! ```
+
+// Known Limitations
+// -----------------
+// - Error messages are rate limited only by sending one at a time: packets
+//   that arrive while the `ICMP6Sender` is busy are not answered.
+// - Echo Replies are not sent if the data of the request does not fit in the
+//   buffer of the `ICMP6RecvStruct`.
+
+use crate::net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::icmpv6::icmpv6_send::ICMP6Sender;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6::IP6Header;
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use core::cmp::min;
+use kernel::common::cells::{OptionalCell, TakeCell};
+
+/// Size of the IPv6 header that starts the body of error messages.
+const IP6_HDR_LEN: usize = 40;
+
+/// A trait for a client of an `ICMP6RecvStruct`.
+pub trait ICMP6RecvClient {
+    /// Called for each message received that is passed to the client.
+    ///
+    /// # Arguments
+    ///
+    /// `ip6_header` - The IPv6 header of the message
+    /// `icmp_header` - The header of the message
+    /// `payload` - The body of the message, after the header. For error
+    /// messages, it starts with the packet that caused the error.
+    fn receive(&self, ip6_header: &IP6Header, icmp_header: ICMP6Header, payload: &[u8]);
+}
+
+/// A trait for reporting packets that cannot be delivered to their sender.
+pub trait ICMP6ErrorReporter {
+    /// Sends an error message to the source of a received packet, unless
+    /// RFC 4443 forbids it, e.g. because the packet was sent to a multicast
+    /// address or is an error message itself.
+    ///
+    /// # Arguments
+    ///
+    /// `icmp_header` - The header of the error message, e.g. a Destination
+    /// Unreachable message with the `PORT_UNREACHABLE` code
+    /// `ip6_header` - The IPv6 header of the received packet
+    /// `payload` - The payload of the received packet
+    fn report_error(&self, icmp_header: ICMP6Header, ip6_header: &IP6Header, payload: &[u8]);
+}
+
+pub struct ICMP6RecvStruct<'a> {
+    icmp_sender: &'a dyn ICMP6Sender<'a>,
+    client: OptionalCell<&'a dyn ICMP6RecvClient>,
+    nd_client: OptionalCell<&'a dyn ICMP6RecvClient>,
+    rpl_client: OptionalCell<&'a dyn ICMP6RecvClient>,
+    // Holds the body of error messages, and bounds the size of Echo Replies
+    buf: TakeCell<'static, [u8]>,
+}
+
+impl<'a> ICMP6RecvStruct<'a> {
+    /// `buf` must not be longer than the payload buffer of the `IP6Sender`
+    /// used by `icmp_sender`, and should be longer than the 40 byte IPv6
+    /// header for error messages to be sent.
+    pub fn new(
+        icmp_sender: &'a dyn ICMP6Sender<'a>,
+        buf: &'static mut [u8],
+    ) -> ICMP6RecvStruct<'a> {
+        ICMP6RecvStruct {
+            icmp_sender,
+            client: OptionalCell::empty(),
+            nd_client: OptionalCell::empty(),
+            rpl_client: OptionalCell::empty(),
+            buf: TakeCell::new(buf),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn ICMP6RecvClient) {
+        self.client.set(client);
+    }
+
+    /// Sets the client that receives the Router Solicitations and
+    /// Advertisements and the Neighbor Solicitations and Advertisements.
+    pub fn set_nd_client(&self, client: &'a dyn ICMP6RecvClient) {
+        self.nd_client.set(client);
+    }
+
+    /// Sets the client that receives the RPL control messages, usually
+    /// `RPLNode`.
+    pub fn set_rpl_client(&self, client: &'a dyn ICMP6RecvClient) {
+        self.rpl_client.set(client);
+    }
+
+    fn send_echo_reply(&self, dst_addr: IPAddr, id: u16, seqno: u16, payload: &[u8]) {
+        if dst_addr.is_unspecified() || dst_addr.is_multicast() {
+            return;
+        }
+        let fits = self.buf.map_or(false, |buf| payload.len() <= buf.len());
+        if fits {
+            let mut icmp_header = ICMP6Header::new(ICMP6Type::Type129);
+            icmp_header.set_options(ICMP6HeaderOptions::Type129 { id, seqno });
+            // Dropped if the sender is busy, like a lost packet
+            self.icmp_sender.send(dst_addr, icmp_header, payload);
+        }
+    }
+}
+
+impl<'a> IP6RecvClient for ICMP6RecvStruct<'a> {
+    fn receive(&self, ip6_header: IP6Header, payload: &[u8]) {
+        if ip6_header.get_next_header() != ip6_nh::ICMP {
+            return;
+        }
+        let icmp_header = match ICMP6Header::decode(payload).done() {
+            Some((_, icmp_header)) => icmp_header,
+            // Unknown informational messages are silently dropped
+            None => return,
+        };
+        let body = &payload[icmp_header.get_hdr_size()..];
+        let src_addr = ip6_header.get_src_addr();
+        match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type128 { id, seqno } => {
+                self.send_echo_reply(src_addr, id, seqno, body);
+            }
+            ICMP6HeaderOptions::Type1 { .. }
+            | ICMP6HeaderOptions::Type3 { .. }
+            | ICMP6HeaderOptions::Type4 { .. }
+            | ICMP6HeaderOptions::Type129 { .. } => {
+                self.client
+                    .map(|client| client.receive(&ip6_header, icmp_header, body));
+            }
+            ICMP6HeaderOptions::Type133 { .. }
+            | ICMP6HeaderOptions::Type134 { .. }
+            | ICMP6HeaderOptions::Type135 { .. }
+            | ICMP6HeaderOptions::Type136 { .. } => {
+                self.nd_client
+                    .map(|client| client.receive(&ip6_header, icmp_header, body));
+            }
+            ICMP6HeaderOptions::Type155 => {
+                self.rpl_client
+                    .map(|client| client.receive(&ip6_header, icmp_header, body));
+            }
+        }
+    }
+}
+
+impl<'a> ICMP6ErrorReporter for ICMP6RecvStruct<'a> {
+    fn report_error(&self, icmp_header: ICMP6Header, ip6_header: &IP6Header, payload: &[u8]) {
+        let src_addr = ip6_header.get_src_addr();
+        if src_addr.is_unspecified()
+            || src_addr.is_multicast()
+            || ip6_header.get_dst_addr().is_multicast()
+        {
+            return;
+        }
+        // Error messages are never answered with an error message
+        if ip6_header.get_next_header() == ip6_nh::ICMP
+            && payload.first().map_or(true, |t| *t < 128)
+        {
+            return;
+        }
+        self.buf.map(|buf| {
+            if buf.len() < IP6_HDR_LEN {
+                return;
+            }
+            ip6_header.encode(buf);
+            let len = min(buf.len(), IP6_HDR_LEN + payload.len());
+            buf[IP6_HDR_LEN..len].copy_from_slice(&payload[..len - IP6_HDR_LEN]);
+            self.icmp_sender.send(src_addr, icmp_header, &buf[..len]);
+        });
+    }
+}
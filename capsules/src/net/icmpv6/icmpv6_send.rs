@@ -11,6 +11,7 @@ use crate::net::icmpv6::icmpv6::ICMP6Header;
 use crate::net::ipv6::ip_utils::IPAddr;
 use crate::net::ipv6::ipv6::TransportHeader;
 use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use core::cell::Cell;
 use kernel::common::cells::OptionalCell;
 use kernel::ReturnCode;
 
@@ -38,20 +39,39 @@ pub trait ICMP6Sender<'a> {
     ///
     /// `dest` - The destination IP address
     /// `icmp_header` - The ICMPv6 header to be sent
-    /// `buf` - The byte array containing the ICMPv6 payload
+    /// `buf` - The byte array containing the ICMPv6 payload, which is copied
+    /// before this function returns
     ///
     /// # Return Value
     ///
     /// This function returns a code reporting either success or any
-    /// synchronous errors. Note that any asynchronous errors are returned
-    /// via the callback.
-    fn send(&self, dest: IPAddr, icmp_header: ICMP6Header, buf: &'a [u8]) -> ReturnCode;
+    /// synchronous errors, including EBUSY if a packet is still being sent.
+    /// Note that any asynchronous errors are returned via the callback.
+    fn send(&self, dest: IPAddr, icmp_header: ICMP6Header, buf: &[u8]) -> ReturnCode;
+
+    /// Like `send`, but sends the packet from the given source address
+    /// instead of letting the IPv6 layer pick one.
+    ///
+    /// # Arguments
+    ///
+    /// `src` - The source IP address
+    /// `dest` - The destination IP address
+    /// `icmp_header` - The ICMPv6 header to be sent
+    /// `buf` - The byte array containing the ICMPv6 payload
+    fn send_from(
+        &self,
+        src: IPAddr,
+        dest: IPAddr,
+        icmp_header: ICMP6Header,
+        buf: &[u8],
+    ) -> ReturnCode;
 }
 
 /// A struct that implements the `ICMP6Sender` trait.
 pub struct ICMP6SendStruct<'a, T: IP6Sender<'a>> {
     ip_send_struct: &'a T,
     client: OptionalCell<&'a dyn ICMP6SendClient>,
+    busy: Cell<bool>,
 }
 
 impl<T: IP6Sender<'a>> ICMP6SendStruct<'a, T> {
@@ -59,7 +79,35 @@ impl<T: IP6Sender<'a>> ICMP6SendStruct<'a, T> {
         ICMP6SendStruct {
             ip_send_struct: ip_send_struct,
             client: OptionalCell::empty(),
+            busy: Cell::new(false),
+        }
+    }
+
+    fn send_with(
+        &self,
+        src: Option<IPAddr>,
+        dest: IPAddr,
+        mut icmp_header: ICMP6Header,
+        buf: &[u8],
+    ) -> ReturnCode {
+        // The `IP6Sender` overwrites the packet it is sending
+        if self.busy.get() {
+            return ReturnCode::EBUSY;
+        }
+        let total_len = buf.len() + icmp_header.get_hdr_size();
+        icmp_header.set_len(total_len as u16);
+        let transport_header = TransportHeader::ICMP(icmp_header);
+        self.busy.set(true);
+        let result = match src {
+            Some(src) => self
+                .ip_send_struct
+                .send_from(src, dest, transport_header, buf),
+            None => self.ip_send_struct.send_to(dest, transport_header, buf),
+        };
+        if result != ReturnCode::SUCCESS {
+            self.busy.set(false);
         }
+        result
     }
 }
 
@@ -68,11 +116,18 @@ impl<T: IP6Sender<'a>> ICMP6Sender<'a> for ICMP6SendStruct<'a, T> {
         self.client.set(client);
     }
 
-    fn send(&self, dest: IPAddr, mut icmp_header: ICMP6Header, buf: &'a [u8]) -> ReturnCode {
-        let total_len = buf.len() + icmp_header.get_hdr_size();
-        icmp_header.set_len(total_len as u16);
-        let transport_header = TransportHeader::ICMP(icmp_header);
-        self.ip_send_struct.send_to(dest, transport_header, buf)
+    fn send(&self, dest: IPAddr, icmp_header: ICMP6Header, buf: &[u8]) -> ReturnCode {
+        self.send_with(None, dest, icmp_header, buf)
+    }
+
+    fn send_from(
+        &self,
+        src: IPAddr,
+        dest: IPAddr,
+        icmp_header: ICMP6Header,
+        buf: &[u8],
+    ) -> ReturnCode {
+        self.send_with(Some(src), dest, icmp_header, buf)
     }
 }
 
@@ -80,6 +135,7 @@ impl<T: IP6Sender<'a>> IP6SendClient for ICMP6SendStruct<'a, T> {
     /// Forwards callback received from the `IP6Sender` to the
     /// `ICMP6SendClient`.
     fn send_done(&self, result: ReturnCode) {
+        self.busy.set(false);
         self.client.map(|client| client.send_done(result));
     }
 }
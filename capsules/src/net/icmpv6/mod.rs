@@ -1,2 +1,9 @@
+pub mod driver;
 pub mod icmpv6;
+pub mod icmpv6_recv;
 pub mod icmpv6_send;
+pub mod nd;
+pub mod nd_options;
+
+pub use self::driver::ICMP6Driver;
+pub use self::driver::DRIVER_NUM;
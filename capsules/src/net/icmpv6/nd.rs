@@ -0,0 +1,845 @@
+//! This file implements the host side of Neighbor Discovery for 6LoWPAN
+//! networks (RFC 4861, as optimized by RFC 6775). `NeighborDiscovery` fills in
+//! the state of an [IP6Interface](../../ipv6/ipv6_interface/struct.IP6Interface.html):
+//!
+//! - It solicits routers until one advertises itself, and makes it the
+//!   default router for its advertised lifetime. Before that lifetime runs
+//!   out, it solicits routers again.
+//! - It configures a link-local address from the 802.15.4 extended address
+//!   (EUI-64) of the device, and a global address from each prefix the router
+//!   advertises for autonomous configuration (SLAAC, RFC 4862).
+//! - Instead of detecting duplicate addresses with multicast, it registers
+//!   each global address with the default router, with the Address
+//!   Registration option of 6LoWPAN-ND, and refreshes the registrations
+//!   before they expire. Addresses the router refuses are removed.
+//! - It answers Neighbor Solicitations for the addresses of the device, and
+//!   keeps the link-layer addresses that neighbors include in the messages
+//!   they send in the neighbor cache.
+//! - When it is given a `ContextTable`, it adds the 6LoWPAN compression
+//!   contexts the router advertises to it, for their advertised lifetime, so
+//!   that the device compresses its packets against the prefixes of the
+//!   network.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use capsules::net::icmpv6::nd::NeighborDiscovery;
+//! # use kernel::static_init;
+//! let nd = static_init!(
+//!     NeighborDiscovery<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     NeighborDiscovery::new(icmp_send, interface, nd_alarm, eui64)
+//! );
+//! nd_alarm.set_client(nd);
+//! nd.set_context_table(contexts);
+//! icmp_recv.set_nd_client(nd);
+//! nd.start();
+//! ```
+
+// Known Limitations
+// -----------------
+// - Messages are sent through an `ICMP6Sender` shared with other users: a
+//   message that cannot be sent because the sender is busy is dropped, and
+//   sent again when its timer expires.
+// - Only one router is used at a time, and its addresses are assumed to be
+//   reachable through it: prefixes are never considered on-link, as RFC 6775
+//   recommends.
+// - Routers are solicited with the intervals of RFC 6775, without its
+//   exponential backoff.
+
+use crate::net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::icmpv6::icmpv6_recv::ICMP6RecvClient;
+use crate::net::icmpv6::icmpv6_send::ICMP6Sender;
+use crate::net::icmpv6::nd_options::{aro_status, prefix_flags, NDOption, NDOptions};
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6::IP6Header;
+use crate::net::ipv6::ipv6_interface::{IP6Interface, Neighbor};
+use crate::net::sixlowpan::sixlowpan_compression::Context;
+use crate::net::sixlowpan::sixlowpan_context::ContextTable;
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Frequency};
+
+/// The all-routers link-local multicast address, ff02::2.
+pub const ALL_ROUTERS_ADDR: IPAddr =
+    IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02]);
+
+// Timers, in seconds
+const TICK_S: u32 = 1;
+const RTR_SOLICITATION_INTERVAL_S: u32 = 10;
+const MAX_RTR_SOLICITATION_INTERVAL_S: u32 = 60;
+const MAX_RTR_SOLICITATIONS: u8 = 3;
+const RETRANS_TIMER_S: u32 = 1;
+const MAX_UNICAST_SOLICIT: u8 = 3;
+// Routers are solicited again when their lifetime is this close to expiring
+const ROUTER_REFRESH_S: u32 = 60;
+// Lifetime of the neighbors learned from their messages
+const NEIGHBOR_LIFETIME_S: u32 = 300;
+
+// Lifetime of the registrations, in units of 60 seconds
+const REGISTRATION_LIFETIME: u16 = 60;
+
+// Size of the fixed part of the body of Router Advertisements, and of
+// Neighbor Solicitations and Advertisements (the target address)
+const RA_BODY_LEN: usize = 8;
+const NS_BODY_LEN: usize = 16;
+
+// Flags of Neighbor Advertisements
+const NA_FLAG_SOLICITED: u32 = 0x4000_0000;
+const NA_FLAG_OVERRIDE: u32 = 0x2000_0000;
+
+// Large enough for the body of any message sent: a target address, a
+// link-layer address option and an Address Registration option.
+const SEND_BUF_LEN: usize = 48;
+
+pub struct NeighborDiscovery<'a, A: time::Alarm<'a>> {
+    icmp_sender: &'a dyn ICMP6Sender<'a>,
+    interface: &'a IP6Interface,
+    contexts: OptionalCell<&'a ContextTable<'a, A>>,
+    alarm: &'a A,
+    eui64: [u8; 8],
+    // Whether `start` was called: routers are only solicited from then on
+    started: Cell<bool>,
+
+    // Router solicitation: whether routers are being solicited, how many
+    // solicitations were sent, and the time until the next one
+    soliciting: Cell<bool>,
+    rs_count: Cell<u8>,
+    rs_timer: Cell<u32>,
+
+    // Address registration: the address being registered, how many times
+    // it was sent, the time until it is sent again, and the time until the
+    // registrations are refreshed
+    registering: Cell<Option<IPAddr>>,
+    reg_count: Cell<u8>,
+    reg_timer: Cell<u32>,
+    refresh_timer: Cell<Option<u32>>,
+
+    // Whether the alarm is set for the next tick
+    ticking: Cell<bool>,
+}
+
+impl<A: time::Alarm<'a>> NeighborDiscovery<'a, A> {
+    /// `eui64` is the extended address of the device, from which the
+    /// interface identifier of its addresses is derived.
+    pub fn new(
+        icmp_sender: &'a dyn ICMP6Sender<'a>,
+        interface: &'a IP6Interface,
+        alarm: &'a A,
+        eui64: [u8; 8],
+    ) -> NeighborDiscovery<'a, A> {
+        NeighborDiscovery {
+            icmp_sender,
+            interface,
+            contexts: OptionalCell::empty(),
+            alarm,
+            eui64,
+            started: Cell::new(false),
+            soliciting: Cell::new(false),
+            rs_count: Cell::new(0),
+            rs_timer: Cell::new(0),
+            registering: Cell::new(None),
+            reg_count: Cell::new(0),
+            reg_timer: Cell::new(0),
+            refresh_timer: Cell::new(None),
+            ticking: Cell::new(false),
+        }
+    }
+
+    /// Sets the table to which the contexts advertised by routers are
+    /// added.
+    pub fn set_context_table(&self, contexts: &'a ContextTable<'a, A>) {
+        self.contexts.set(contexts);
+    }
+
+    /// Configures the link-local address of the interface and starts
+    /// soliciting routers, from the next tick on.
+    pub fn start(&self) {
+        let link_local = IPAddr::generate_from_mac(MacAddress::Long(self.eui64));
+        self.interface.add_addr(link_local, None);
+        self.started.set(true);
+        self.start_solicitation();
+        self.start_tick();
+    }
+
+    fn start_tick(&self) {
+        if !self.ticking.get() {
+            self.ticking.set(true);
+            let tics = TICK_S * <A::Frequency>::frequency();
+            self.alarm.set_alarm(self.alarm.now().wrapping_add(tics));
+        }
+    }
+
+    /// Returns whether a timer or a lifetime is counting down, for which the
+    /// alarm has to keep ticking.
+    fn has_timers(&self) -> bool {
+        self.soliciting.get()
+            || self.registering.get().is_some()
+            || self.refresh_timer.get().is_some()
+            || self.interface.has_lifetimes()
+    }
+
+    fn ll_addr_option(&self) -> NDOption<'static> {
+        NDOption::SourceLLAddr(MacAddress::Long(self.eui64))
+    }
+
+    fn start_solicitation(&self) {
+        self.soliciting.set(true);
+        self.rs_count.set(0);
+        self.rs_timer.set(TICK_S);
+    }
+
+    fn send_router_solicitation(&self) {
+        let mut buf = [0; SEND_BUF_LEN];
+        let len = match self.ll_addr_option().encode(&mut buf, 0).done() {
+            Some((_, len)) => len,
+            None => return,
+        };
+        // Refresh the current router directly, and find one with multicast
+        let dst = self
+            .interface
+            .default_router()
+            .map_or(ALL_ROUTERS_ADDR, |router| router.ip_addr);
+        let icmp_header = ICMP6Header::new(ICMP6Type::Type133);
+        self.icmp_sender.send(dst, icmp_header, &buf[..len]);
+
+        self.rs_count.set(self.rs_count.get().saturating_add(1));
+        self.rs_timer
+            .set(if self.rs_count.get() < MAX_RTR_SOLICITATIONS {
+                RTR_SOLICITATION_INTERVAL_S
+            } else {
+                MAX_RTR_SOLICITATION_INTERVAL_S
+            });
+    }
+
+    /// Registers the next global address that is not registered yet, if
+    /// there is a router and no registration is in progress.
+    fn register_next(&self) {
+        if self.registering.get().is_some() || self.interface.default_router().is_none() {
+            return;
+        }
+        let next = (0..self.interface.addr_count())
+            .filter_map(|i| self.interface.get_addr(i))
+            .find(|entry| !entry.registered && !entry.addr.is_unicast_link_local());
+        if let Some(entry) = next {
+            self.registering.set(Some(entry.addr));
+            self.reg_count.set(0);
+            self.send_registration();
+        }
+    }
+
+    fn send_registration(&self) {
+        let (addr, router) = match (self.registering.get(), self.interface.default_router()) {
+            (Some(addr), Some(router)) => (addr, router),
+            _ => return,
+        };
+        let aro = NDOption::AddrRegistration {
+            status: aro_status::SUCCESS,
+            lifetime: REGISTRATION_LIFETIME,
+            eui64: self.eui64,
+        };
+        let mut buf = [0; SEND_BUF_LEN];
+        buf[..NS_BODY_LEN].copy_from_slice(&router.ip_addr.0);
+        let len = self
+            .ll_addr_option()
+            .encode(&mut buf, NS_BODY_LEN)
+            .done()
+            .and_then(|(_, off)| aro.encode(&mut buf, off).done());
+        if let Some((_, len)) = len {
+            // The address being registered is the source of the solicitation
+            let icmp_header = ICMP6Header::new(ICMP6Type::Type135);
+            self.icmp_sender
+                .send_from(addr, router.ip_addr, icmp_header, &buf[..len]);
+        }
+        self.reg_count.set(self.reg_count.get() + 1);
+        self.reg_timer.set(RETRANS_TIMER_S);
+    }
+
+    fn send_neighbor_advertisement(&self, target: IPAddr, dst: IPAddr) {
+        let mut buf = [0; SEND_BUF_LEN];
+        buf[..NS_BODY_LEN].copy_from_slice(&target.0);
+        let option = NDOption::TargetLLAddr(MacAddress::Long(self.eui64));
+        if let Some((_, len)) = option.encode(&mut buf, NS_BODY_LEN).done() {
+            let mut icmp_header = ICMP6Header::new(ICMP6Type::Type136);
+            icmp_header.set_options(ICMP6HeaderOptions::Type136 {
+                flags: NA_FLAG_SOLICITED | NA_FLAG_OVERRIDE,
+            });
+            self.icmp_sender
+                .send_from(target, dst, icmp_header, &buf[..len]);
+        }
+    }
+
+    fn receive_router_advertisement(&self, src_addr: IPAddr, router_lifetime: u16, body: &[u8]) {
+        if body.len() < RA_BODY_LEN || !src_addr.is_unicast_link_local() {
+            return;
+        }
+        let options = &body[RA_BODY_LEN..];
+        let mac_addr = NDOptions::new(options)
+            .filter_map(|option| match option {
+                NDOption::SourceLLAddr(mac_addr) => Some(mac_addr),
+                _ => None,
+            })
+            .next()
+            .unwrap_or_else(|| src_addr.mac_from_iid());
+
+        let current = self.interface.default_router();
+        if router_lifetime == 0 {
+            if current.map_or(false, |router| router.ip_addr == src_addr) {
+                self.interface.set_default_router(None);
+            }
+            return;
+        }
+        if current.map_or(true, |router| router.ip_addr != src_addr) {
+            // Addresses must be registered with the new router
+            self.interface.clear_registrations();
+            self.registering.set(None);
+        }
+        self.interface.set_default_router(Some(Neighbor {
+            ip_addr: src_addr,
+            mac_addr,
+            lifetime: router_lifetime as u32,
+        }));
+        self.soliciting.set(false);
+
+        for option in NDOptions::new(options) {
+            match option {
+                NDOption::PrefixInfo {
+                    prefix_len,
+                    flags,
+                    valid_lifetime,
+                    preferred_lifetime,
+                    prefix,
+                } => {
+                    // Addresses are formed from 64 bit prefixes and the EUI-64
+                    if flags & prefix_flags::AUTONOMOUS == 0
+                        || prefix_len != 64
+                        || prefix.is_unicast_link_local()
+                        || preferred_lifetime > valid_lifetime
+                    {
+                        continue;
+                    }
+                    let mut addr = prefix;
+                    addr.set_iid_from_mac(MacAddress::Long(self.eui64));
+                    match valid_lifetime {
+                        0 => self.interface.remove_addr(addr),
+                        0xffff_ffff => {
+                            self.interface.add_addr(addr, None);
+                        }
+                        lifetime => {
+                            self.interface.add_addr(addr, Some(lifetime));
+                        }
+                    }
+                }
+                NDOption::SixlowpanContext {
+                    context_len,
+                    compress,
+                    cid,
+                    valid_lifetime,
+                    prefix,
+                } => self.receive_context(context_len, compress, cid, valid_lifetime, prefix),
+                _ => {}
+            }
+        }
+        self.register_next();
+    }
+
+    // Adds an advertised context to the table, or removes it if its lifetime
+    // is zero. The lifetime is in units of 60 seconds.
+    fn receive_context(
+        &self,
+        context_len: u8,
+        compress: bool,
+        cid: u8,
+        valid_lifetime: u16,
+        prefix: IPAddr,
+    ) {
+        self.contexts.map(|contexts| {
+            if valid_lifetime == 0 {
+                contexts.remove_context(cid);
+                return;
+            }
+            // The bits after the prefix may be set in the option
+            let mut masked_prefix = IPAddr::new();
+            masked_prefix.set_prefix(&prefix.0, context_len);
+            contexts.set_context(
+                Context {
+                    prefix: masked_prefix.0,
+                    prefix_len: context_len,
+                    id: cid,
+                    compress,
+                },
+                Some(valid_lifetime as u32 * 60),
+            );
+        });
+    }
+
+    fn receive_neighbor_solicitation(&self, ip6_header: &IP6Header, body: &[u8]) {
+        if body.len() < NS_BODY_LEN {
+            return;
+        }
+        let mut target = IPAddr::new();
+        target.0.copy_from_slice(&body[..NS_BODY_LEN]);
+        let src_addr = ip6_header.get_src_addr();
+        if target.is_multicast() || !self.interface.has_addr(&target) || src_addr.is_unspecified() {
+            return;
+        }
+        for option in NDOptions::new(&body[NS_BODY_LEN..]) {
+            if let NDOption::SourceLLAddr(mac_addr) = option {
+                self.interface.update_neighbor(Neighbor {
+                    ip_addr: src_addr,
+                    mac_addr,
+                    lifetime: NEIGHBOR_LIFETIME_S,
+                });
+            }
+        }
+        self.send_neighbor_advertisement(target, src_addr);
+    }
+
+    fn receive_neighbor_advertisement(&self, ip6_header: &IP6Header, body: &[u8]) {
+        if body.len() < NS_BODY_LEN {
+            return;
+        }
+        let mut target = IPAddr::new();
+        target.0.copy_from_slice(&body[..NS_BODY_LEN]);
+        for option in NDOptions::new(&body[NS_BODY_LEN..]) {
+            match option {
+                NDOption::AddrRegistration { status, eui64, .. } => {
+                    // The answer to a registration is sent to the address
+                    // being registered
+                    let addr = ip6_header.get_dst_addr();
+                    if eui64 != self.eui64 || self.registering.get() != Some(addr) {
+                        continue;
+                    }
+                    self.registering.set(None);
+                    if status == aro_status::SUCCESS {
+                        self.interface.set_registered(addr, true);
+                        let lifetime_s = REGISTRATION_LIFETIME as u32 * 60;
+                        self.refresh_timer.set(Some(lifetime_s * 3 / 4));
+                    } else {
+                        // Duplicate address, or the router cannot keep it
+                        self.interface.remove_addr(addr);
+                    }
+                    self.register_next();
+                }
+                NDOption::TargetLLAddr(mac_addr) if !target.is_multicast() => {
+                    self.interface.update_neighbor(Neighbor {
+                        ip_addr: target,
+                        mac_addr,
+                        lifetime: NEIGHBOR_LIFETIME_S,
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<A: time::Alarm<'a>> ICMP6RecvClient for NeighborDiscovery<'a, A> {
+    fn receive(&self, ip6_header: &IP6Header, icmp_header: ICMP6Header, payload: &[u8]) {
+        // Messages that may come from another link are invalid (RFC 4861)
+        if ip6_header.get_hop_limit() != 255 || icmp_header.get_code() != 0 {
+            return;
+        }
+        let options_offset = match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type134 { .. } => RA_BODY_LEN,
+            ICMP6HeaderOptions::Type135 { .. } | ICMP6HeaderOptions::Type136 { .. } => NS_BODY_LEN,
+            _ => return,
+        };
+        if payload.len() < options_offset || !NDOptions::is_valid(&payload[options_offset..]) {
+            return;
+        }
+        match icmp_header.get_options() {
+            ICMP6HeaderOptions::Type134 {
+                router_lifetime, ..
+            } => {
+                self.receive_router_advertisement(
+                    ip6_header.get_src_addr(),
+                    router_lifetime,
+                    payload,
+                );
+            }
+            ICMP6HeaderOptions::Type135 { .. } => {
+                self.receive_neighbor_solicitation(ip6_header, payload);
+            }
+            ICMP6HeaderOptions::Type136 { .. } => {
+                self.receive_neighbor_advertisement(ip6_header, payload);
+            }
+            _ => {}
+        }
+        // The message may have added a router or a neighbor
+        if self.has_timers() {
+            self.start_tick();
+        }
+    }
+}
+
+impl<A: time::Alarm<'a>> time::AlarmClient for NeighborDiscovery<'a, A> {
+    fn fired(&self) {
+        self.ticking.set(false);
+        self.interface.tick(TICK_S);
+
+        let router = self.interface.default_router();
+        if self.started.get()
+            && !self.soliciting.get()
+            && router.map_or(true, |router| router.lifetime <= ROUTER_REFRESH_S)
+        {
+            self.start_solicitation();
+        }
+        if self.soliciting.get() {
+            self.rs_timer
+                .set(self.rs_timer.get().saturating_sub(TICK_S));
+            if self.rs_timer.get() == 0 {
+                self.send_router_solicitation();
+            }
+        }
+
+        if self.registering.get().is_some() {
+            self.reg_timer
+                .set(self.reg_timer.get().saturating_sub(TICK_S));
+            if self.reg_timer.get() == 0 {
+                if self.reg_count.get() < MAX_UNICAST_SOLICIT {
+                    self.send_registration();
+                } else {
+                    // The router is unreachable: look for another one
+                    self.registering.set(None);
+                    self.interface.set_default_router(None);
+                    self.start_solicitation();
+                }
+            }
+        } else if let Some(refresh) = self.refresh_timer.get() {
+            if refresh <= TICK_S {
+                self.refresh_timer.set(None);
+                self.interface.clear_registrations();
+            } else {
+                self.refresh_timer.set(Some(refresh - TICK_S));
+            }
+        }
+        self.register_next();
+
+        // Keeps ticking while a timer runs
+        if self.has_timers() {
+            self.start_tick();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use crate::net::mock::{MockAlarm, MockIcmpSender};
+    use kernel::hil::time::{Alarm, AlarmClient};
+    use std::vec::Vec;
+
+    const EUI64: [u8; 8] = [0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77];
+    const ROUTER_MAC: MacAddress = MacAddress::Short(0x0001);
+
+    struct Mocks {
+        sender: MockIcmpSender,
+        alarm: MockAlarm,
+        interface: IP6Interface,
+        contexts_alarm: MockAlarm,
+    }
+
+    type TestNd<'a> = NeighborDiscovery<'a, MockAlarm>;
+
+    impl Mocks {
+        fn new() -> Mocks {
+            Mocks {
+                sender: MockIcmpSender::new(),
+                alarm: MockAlarm::new(),
+                interface: IP6Interface::new(),
+                contexts_alarm: MockAlarm::new(),
+            }
+        }
+
+        fn nd(&self) -> TestNd {
+            NeighborDiscovery::new(&self.sender, &self.interface, &self.alarm, EUI64)
+        }
+
+        fn contexts(&self) -> ContextTable<MockAlarm> {
+            ContextTable::new(&self.contexts_alarm)
+        }
+
+        // Lets the alarm fire `seconds` times.
+        fn tick(&self, nd: &TestNd, seconds: u32) {
+            for _ in 0..seconds {
+                assert!(self.alarm.advance(1000));
+                nd.fired();
+            }
+        }
+
+        // Takes the message sent, if any.
+        fn sent(&self) -> Option<(Option<IPAddr>, IPAddr, ICMP6Header, Vec<u8>)> {
+            let mut sent = self.sender.take();
+            assert!(sent.len() <= 1);
+            let message = sent.pop()?;
+            Some((message.src, message.dst, message.header, message.payload))
+        }
+    }
+
+    fn router_addr() -> IPAddr {
+        IPAddr::generate_from_mac(ROUTER_MAC)
+    }
+
+    fn prefix() -> IPAddr {
+        let mut prefix = IPAddr::new();
+        prefix.0[..8].copy_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 1, 0, 0]);
+        prefix
+    }
+
+    fn global_addr() -> IPAddr {
+        let mut addr = prefix();
+        addr.set_iid_from_mac(MacAddress::Long(EUI64));
+        addr
+    }
+
+    fn receive(nd: &TestNd, src: IPAddr, dst: IPAddr, header: ICMP6Header, body: &[u8]) {
+        let mut ip6_header = IP6Header::new();
+        ip6_header.src_addr = src;
+        ip6_header.dst_addr = dst;
+        ip6_header.set_hop_limit(255);
+        nd.receive(&ip6_header, header, body);
+    }
+
+    // Advertises the router, with the 2001:db8:0:1::/64 prefix as context 1,
+    // whose lifetime is in minutes.
+    fn advertise(nd: &TestNd, router_lifetime: u16, context_lifetime: u16) {
+        let mut body = [0; 72];
+        let mut off = RA_BODY_LEN;
+        let options = [
+            NDOption::SourceLLAddr(ROUTER_MAC),
+            NDOption::PrefixInfo {
+                prefix_len: 64,
+                flags: prefix_flags::ON_LINK | prefix_flags::AUTONOMOUS,
+                valid_lifetime: 7200,
+                preferred_lifetime: 3600,
+                prefix: prefix(),
+            },
+            NDOption::SixlowpanContext {
+                context_len: 64,
+                compress: true,
+                cid: 1,
+                valid_lifetime: context_lifetime,
+                prefix: prefix(),
+            },
+        ];
+        for option in options.iter() {
+            off = option.encode(&mut body, off).done().unwrap().0;
+        }
+        let mut header = ICMP6Header::new(ICMP6Type::Type134);
+        header.set_options(ICMP6HeaderOptions::Type134 {
+            hop_limit: 64,
+            flags: 0,
+            router_lifetime,
+        });
+        receive(nd, router_addr(), ALL_NODES_ADDR, header, &body[..off]);
+    }
+
+    const ALL_NODES_ADDR: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+
+    fn options(body: &[u8]) -> Vec<NDOption> {
+        NDOptions::new(body).collect()
+    }
+
+    #[test]
+    fn solicits_routers() {
+        let mocks = Mocks::new();
+        let nd = mocks.nd();
+        nd.start();
+        let link_local = IPAddr::generate_from_mac(MacAddress::Long(EUI64));
+        assert!(mocks.interface.has_addr(&link_local));
+        assert!(mocks.sent().is_none());
+
+        mocks.tick(&nd, 1);
+        let (_, dst, header, body) = mocks.sent().unwrap();
+        assert_eq!(dst, ALL_ROUTERS_ADDR);
+        assert_eq!(header.get_type_as_int(), 133);
+        match options(&body)[..] {
+            [NDOption::SourceLLAddr(MacAddress::Long(eui64))] => assert_eq!(eui64, EUI64),
+            ref options => panic!("{:?}", options),
+        }
+
+        for count in 1..MAX_RTR_SOLICITATIONS {
+            mocks.tick(&nd, RTR_SOLICITATION_INTERVAL_S - 1);
+            assert!(mocks.sent().is_none());
+            mocks.tick(&nd, 1);
+            assert!(mocks.sent().is_some(), "solicitation {}", count + 1);
+        }
+        mocks.tick(&nd, MAX_RTR_SOLICITATION_INTERVAL_S - 1);
+        assert!(mocks.sent().is_none());
+        mocks.tick(&nd, 1);
+        assert!(mocks.sent().is_some());
+    }
+
+    #[test]
+    fn registers_addresses_with_the_router() {
+        let mocks = Mocks::new();
+        let contexts = mocks.contexts();
+        let nd = mocks.nd();
+        nd.set_context_table(&contexts);
+        nd.start();
+        mocks.tick(&nd, 1);
+        mocks.sent();
+
+        advertise(&nd, 1800, 10);
+        let router = mocks.interface.default_router().unwrap();
+        assert_eq!(router.ip_addr, router_addr());
+        assert_eq!(router.mac_addr, ROUTER_MAC);
+        assert_eq!(router.lifetime, 1800);
+        let context = contexts.get_entry(1).unwrap();
+        assert_eq!(context.lifetime, Some(600));
+        assert!(context.context.compress);
+        assert!(mocks.interface.has_addr(&global_addr()));
+
+        // The address is registered with a Neighbor Solicitation from it
+        let (src, dst, header, body) = mocks.sent().unwrap();
+        assert_eq!((src, dst), (Some(global_addr()), router_addr()));
+        assert_eq!(header.get_type_as_int(), 135);
+        assert_eq!(body[..NS_BODY_LEN], router_addr().0);
+        match options(&body[NS_BODY_LEN..])[..] {
+            [NDOption::SourceLLAddr(_), NDOption::AddrRegistration {
+                status: aro_status::SUCCESS,
+                lifetime: REGISTRATION_LIFETIME,
+                eui64,
+            }] => assert_eq!(eui64, EUI64),
+            ref options => panic!("{:?}", options),
+        }
+
+        // Sent again until the router answers
+        mocks.tick(&nd, RETRANS_TIMER_S);
+        assert_eq!(mocks.sent().unwrap().2.get_type_as_int(), 135);
+
+        let mut body = [0; NS_BODY_LEN + 16];
+        body[..NS_BODY_LEN].copy_from_slice(&global_addr().0);
+        NDOption::AddrRegistration {
+            status: aro_status::SUCCESS,
+            lifetime: REGISTRATION_LIFETIME,
+            eui64: EUI64,
+        }
+        .encode(&mut body, NS_BODY_LEN);
+        let header = ICMP6Header::new(ICMP6Type::Type136);
+        receive(&nd, router_addr(), global_addr(), header, &body);
+        let registered = (0..mocks.interface.addr_count())
+            .filter_map(|i| mocks.interface.get_addr(i))
+            .find(|entry| entry.addr == global_addr())
+            .unwrap()
+            .registered;
+        assert!(registered);
+        mocks.tick(&nd, RETRANS_TIMER_S);
+        assert!(mocks.sent().is_none());
+    }
+
+    #[test]
+    fn removes_refused_addresses() {
+        let mocks = Mocks::new();
+        let nd = mocks.nd();
+        nd.start();
+        advertise(&nd, 1800, 10);
+        mocks.sent();
+
+        let mut body = [0; NS_BODY_LEN + 16];
+        NDOption::AddrRegistration {
+            status: aro_status::DUPLICATE,
+            lifetime: 0,
+            eui64: EUI64,
+        }
+        .encode(&mut body, NS_BODY_LEN);
+        let header = ICMP6Header::new(ICMP6Type::Type136);
+        receive(&nd, router_addr(), global_addr(), header, &body);
+        assert!(!mocks.interface.has_addr(&global_addr()));
+    }
+
+    #[test]
+    fn ignores_messages_from_other_links() {
+        let mocks = Mocks::new();
+        let nd = mocks.nd();
+        let mut ip6_header = IP6Header::new();
+        ip6_header.src_addr = router_addr();
+        ip6_header.set_hop_limit(64);
+        let mut header = ICMP6Header::new(ICMP6Type::Type134);
+        header.set_options(ICMP6HeaderOptions::Type134 {
+            hop_limit: 64,
+            flags: 0,
+            router_lifetime: 1800,
+        });
+        nd.receive(&ip6_header, header, &[0; RA_BODY_LEN]);
+        assert!(mocks.interface.default_router().is_none());
+
+        // Nor messages with malformed options
+        receive(
+            &nd,
+            router_addr(),
+            ALL_NODES_ADDR,
+            header,
+            &[0; RA_BODY_LEN + 8],
+        );
+        assert!(mocks.interface.default_router().is_none());
+    }
+
+    #[test]
+    fn answers_solicitations_and_stops_ticking() {
+        // Without routers, so that only the neighbor is timed
+        let mocks = Mocks::new();
+        let nd = mocks.nd();
+        mocks.interface.add_addr(global_addr(), None);
+        assert!(!mocks.alarm.is_enabled());
+
+        let neighbor = IPAddr::generate_from_mac(MacAddress::Short(2));
+        let mut body = [0; NS_BODY_LEN + 8];
+        body[..NS_BODY_LEN].copy_from_slice(&global_addr().0);
+        NDOption::SourceLLAddr(MacAddress::Short(2)).encode(&mut body, NS_BODY_LEN);
+        let header = ICMP6Header::new(ICMP6Type::Type135);
+        receive(&nd, neighbor, global_addr(), header, &body);
+
+        let (src, dst, advertisement, target) = mocks.sent().unwrap();
+        assert_eq!((src, dst), (Some(global_addr()), neighbor));
+        assert_eq!(advertisement.get_type_as_int(), 136);
+        assert_eq!(target[..NS_BODY_LEN], global_addr().0);
+        assert_eq!(
+            mocks.interface.lookup_neighbor(&neighbor),
+            Some(MacAddress::Short(2))
+        );
+
+        // The alarm ticks until the neighbor expires
+        assert!(mocks.alarm.is_enabled());
+        mocks.tick(&nd, NEIGHBOR_LIFETIME_S);
+        assert_eq!(mocks.interface.lookup_neighbor(&neighbor), None);
+        assert!(!mocks.alarm.is_enabled());
+
+        // Solicitations for other addresses are not answered
+        body[15] ^= 1;
+        receive(&nd, neighbor, global_addr(), header, &body);
+        assert!(mocks.sent().is_none());
+    }
+
+    #[test]
+    fn learns_contexts_from_router_advertisements() {
+        let mocks = Mocks::new();
+        let contexts = mocks.contexts();
+        let nd = mocks.nd();
+        nd.set_context_table(&contexts);
+        advertise(&nd, 1800, 2);
+        let entry = contexts.get_entry(1).unwrap();
+        assert_eq!(entry.context.prefix[..8], prefix().0[..8]);
+        assert_eq!(entry.context.prefix_len, 64);
+        assert!(entry.context.compress);
+        assert_eq!(entry.lifetime, Some(120));
+
+        // The table ages the context on its own alarm
+        for _ in 0..119 {
+            assert!(mocks.contexts_alarm.advance(1000));
+            contexts.fired();
+        }
+        assert_eq!(contexts.get_entry(1).unwrap().lifetime, Some(1));
+        // Advertised again, the lifetime starts over
+        advertise(&nd, 1800, 2);
+        assert_eq!(contexts.get_entry(1).unwrap().lifetime, Some(120));
+
+        // A lifetime of zero removes the context
+        advertise(&nd, 1800, 0);
+        assert!(contexts.get_entry(1).is_none());
+    }
+}
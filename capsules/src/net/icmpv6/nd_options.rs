@@ -0,0 +1,545 @@
+//! This file contains the options of Neighbor Discovery messages (RFC 4861),
+//! with the format of 802.15.4 link-layer addresses from RFC 4944 and the
+//! options added by 6LoWPAN-ND (RFC 6775), and the methods to encode and
+//! decode them.
+//!
+//! The options follow the fixed part of the body of a message, e.g. the
+//! target address of a Neighbor Solicitation. `NDOptions` iterates over
+//! them:
+//!
+//! ```rust
+//! # use capsules::net::icmpv6::nd_options::{NDOption, NDOptions};
+//! for option in NDOptions::new(&body[16..]) {
+//!     if let NDOption::SourceLLAddr(mac_addr) = option {
+//!         // ...
+//!     }
+//! }
+//! ```
+
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_bytes, decode_u16, decode_u32, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u32, encode_u8};
+
+/// Types of the options.
+pub mod nd_option_type {
+    pub const SOURCE_LL_ADDR: u8 = 1;
+    pub const TARGET_LL_ADDR: u8 = 2;
+    pub const PREFIX_INFO: u8 = 3;
+    pub const MTU: u8 = 5;
+    pub const ADDR_REGISTRATION: u8 = 33;
+    pub const SIXLOWPAN_CONTEXT: u8 = 34;
+    pub const AUTH_BORDER_ROUTER: u8 = 35;
+}
+
+/// Flags of the Prefix Information option.
+pub mod prefix_flags {
+    pub const ON_LINK: u8 = 0x80;
+    pub const AUTONOMOUS: u8 = 0x40;
+}
+
+/// Flags of the 6LoWPAN Context option, with the context identifier in the
+/// low 4 bits.
+pub mod context_flags {
+    pub const COMPRESS: u8 = 0x10;
+    pub const CID_MASK: u8 = 0x0f;
+}
+
+/// Status of the Address Registration option.
+pub mod aro_status {
+    pub const SUCCESS: u8 = 0;
+    pub const DUPLICATE: u8 = 1;
+    pub const CACHE_FULL: u8 = 2;
+}
+
+// The length of options is counted in units of 8 bytes.
+const UNIT_LEN: usize = 8;
+const PREFIX_INFO_LEN: usize = 32;
+const ADDR_REGISTRATION_LEN: usize = 16;
+// The prefix of a 6LoWPAN Context option takes 8 bytes if it is at most 64
+// bits long, and 16 bytes otherwise.
+const SIXLOWPAN_CONTEXT_HDR_LEN: usize = 8;
+
+#[derive(Copy, Clone, Debug)]
+pub enum NDOption<'b> {
+    SourceLLAddr(MacAddress),
+    TargetLLAddr(MacAddress),
+    PrefixInfo {
+        prefix_len: u8,
+        flags: u8,
+        valid_lifetime: u32,
+        preferred_lifetime: u32,
+        prefix: IPAddr,
+    },
+    /// The lifetime is in units of 60 seconds.
+    AddrRegistration {
+        status: u8,
+        lifetime: u16,
+        eui64: [u8; 8],
+    },
+    /// The lifetime is in units of 60 seconds.
+    SixlowpanContext {
+        context_len: u8,
+        compress: bool,
+        cid: u8,
+        valid_lifetime: u16,
+        prefix: IPAddr,
+    },
+    /// Any other option, with its data after the type and length.
+    Other {
+        option_type: u8,
+        data: &'b [u8],
+    },
+}
+
+impl NDOption<'b> {
+    pub fn get_type(&self) -> u8 {
+        match *self {
+            NDOption::SourceLLAddr(_) => nd_option_type::SOURCE_LL_ADDR,
+            NDOption::TargetLLAddr(_) => nd_option_type::TARGET_LL_ADDR,
+            NDOption::PrefixInfo { .. } => nd_option_type::PREFIX_INFO,
+            NDOption::AddrRegistration { .. } => nd_option_type::ADDR_REGISTRATION,
+            NDOption::SixlowpanContext { .. } => nd_option_type::SIXLOWPAN_CONTEXT,
+            NDOption::Other { option_type, .. } => option_type,
+        }
+    }
+
+    /// Returns the size of the option, including its type and length.
+    pub fn get_size(&self) -> usize {
+        match *self {
+            NDOption::SourceLLAddr(mac_addr) | NDOption::TargetLLAddr(mac_addr) => match mac_addr {
+                MacAddress::Short(_) => UNIT_LEN,
+                MacAddress::Long(_) => 2 * UNIT_LEN,
+            },
+            NDOption::PrefixInfo { .. } => PREFIX_INFO_LEN,
+            NDOption::AddrRegistration { .. } => ADDR_REGISTRATION_LEN,
+            NDOption::SixlowpanContext { context_len, .. } => {
+                if context_len > 64 {
+                    SIXLOWPAN_CONTEXT_HDR_LEN + 16
+                } else {
+                    SIXLOWPAN_CONTEXT_HDR_LEN + 8
+                }
+            }
+            NDOption::Other { data, .. } => (data.len() + 2 + UNIT_LEN - 1) / UNIT_LEN * UNIT_LEN,
+        }
+    }
+
+    /// Serializes the option into a buffer, padding it with zeros to a
+    /// multiple of 8 bytes.
+    ///
+    /// # Arguments
+    ///
+    /// `buf` - A buffer to serialize the option into
+    /// `offset` - The current offset into the provided buffer
+    ///
+    /// # Return Value
+    ///
+    /// This function returns the new offset into the buffer, wrapped in an
+    /// SResult
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        let size = self.get_size();
+        stream_len_cond!(buf, offset + size);
+        for b in buf[offset..offset + size].iter_mut() {
+            *b = 0;
+        }
+
+        let mut off = offset;
+        off = enc_consume!(buf, off; encode_u8, self.get_type());
+        off = enc_consume!(buf, off; encode_u8, (size / UNIT_LEN) as u8);
+        match *self {
+            NDOption::SourceLLAddr(mac_addr) | NDOption::TargetLLAddr(mac_addr) => match mac_addr {
+                MacAddress::Short(short_addr) => {
+                    enc_consume!(buf, off; encode_u16, short_addr);
+                }
+                MacAddress::Long(ref long_addr) => {
+                    enc_consume!(buf, off; encode_bytes, long_addr);
+                }
+            },
+            NDOption::PrefixInfo {
+                prefix_len,
+                flags,
+                valid_lifetime,
+                preferred_lifetime,
+                prefix,
+            } => {
+                off = enc_consume!(buf, off; encode_u8, prefix_len);
+                off = enc_consume!(buf, off; encode_u8, flags);
+                off = enc_consume!(buf, off; encode_u32, valid_lifetime);
+                off = enc_consume!(buf, off; encode_u32, preferred_lifetime);
+                // Reserved
+                off += 4;
+                enc_consume!(buf, off; encode_bytes, &prefix.0);
+            }
+            NDOption::AddrRegistration {
+                status,
+                lifetime,
+                ref eui64,
+            } => {
+                off = enc_consume!(buf, off; encode_u8, status);
+                // Reserved
+                off += 3;
+                off = enc_consume!(buf, off; encode_u16, lifetime);
+                enc_consume!(buf, off; encode_bytes, eui64);
+            }
+            NDOption::SixlowpanContext {
+                context_len,
+                compress,
+                cid,
+                valid_lifetime,
+                prefix,
+            } => {
+                let mut flags = cid & context_flags::CID_MASK;
+                if compress {
+                    flags |= context_flags::COMPRESS;
+                }
+                off = enc_consume!(buf, off; encode_u8, context_len);
+                off = enc_consume!(buf, off; encode_u8, flags);
+                // Reserved
+                off += 2;
+                off = enc_consume!(buf, off; encode_u16, valid_lifetime);
+                enc_consume!(buf, off; encode_bytes, &prefix.0[..size - SIXLOWPAN_CONTEXT_HDR_LEN]);
+            }
+            NDOption::Other { data, .. } => {
+                enc_consume!(buf, off; encode_bytes, data);
+            }
+        }
+        stream_done!(offset + size, offset + size);
+    }
+
+    /// Deserializes the option at the start of a buffer.
+    ///
+    /// # Return Value
+    ///
+    /// This function returns the option and the offset of the next option,
+    /// wrapped in an SResult. Options of length zero, which RFC 4861 requires
+    /// to be discarded with their message, are errors.
+    pub fn decode(buf: &'b [u8]) -> SResult<NDOption<'b>> {
+        let (off, option_type) = dec_try!(buf, 0; decode_u8);
+        let (off, len) = dec_try!(buf, off; decode_u8);
+        let size = len as usize * UNIT_LEN;
+        stream_cond!(size > 0);
+        stream_len_cond!(buf, size);
+
+        let option = match option_type {
+            nd_option_type::SOURCE_LL_ADDR | nd_option_type::TARGET_LL_ADDR => {
+                let mac_addr = match len {
+                    1 => {
+                        let (_, short_addr) = dec_try!(buf, off; decode_u16);
+                        MacAddress::Short(short_addr)
+                    }
+                    2 => {
+                        let mut long_addr = [0; 8];
+                        dec_consume!(buf, off; decode_bytes, &mut long_addr);
+                        MacAddress::Long(long_addr)
+                    }
+                    _ => stream_err!(),
+                };
+                if option_type == nd_option_type::SOURCE_LL_ADDR {
+                    NDOption::SourceLLAddr(mac_addr)
+                } else {
+                    NDOption::TargetLLAddr(mac_addr)
+                }
+            }
+            nd_option_type::PREFIX_INFO => {
+                stream_cond!(size == PREFIX_INFO_LEN);
+                let (off, prefix_len) = dec_try!(buf, off; decode_u8);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, valid_lifetime) = dec_try!(buf, off; decode_u32);
+                let (off, preferred_lifetime) = dec_try!(buf, off; decode_u32);
+                let mut prefix = IPAddr::new();
+                dec_consume!(buf, off + 4; decode_bytes, &mut prefix.0);
+                NDOption::PrefixInfo {
+                    prefix_len,
+                    flags,
+                    valid_lifetime,
+                    preferred_lifetime,
+                    prefix,
+                }
+            }
+            nd_option_type::ADDR_REGISTRATION => {
+                stream_cond!(size == ADDR_REGISTRATION_LEN);
+                let (off, status) = dec_try!(buf, off; decode_u8);
+                let (off, lifetime) = dec_try!(buf, off + 3; decode_u16);
+                let mut eui64 = [0; 8];
+                dec_consume!(buf, off; decode_bytes, &mut eui64);
+                NDOption::AddrRegistration {
+                    status,
+                    lifetime,
+                    eui64,
+                }
+            }
+            nd_option_type::SIXLOWPAN_CONTEXT => {
+                stream_cond!(
+                    size == SIXLOWPAN_CONTEXT_HDR_LEN + 8 || size == SIXLOWPAN_CONTEXT_HDR_LEN + 16
+                );
+                let (off, context_len) = dec_try!(buf, off; decode_u8);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, valid_lifetime) = dec_try!(buf, off + 2; decode_u16);
+                stream_cond!(context_len as usize <= (size - SIXLOWPAN_CONTEXT_HDR_LEN) * 8);
+                let mut prefix = IPAddr::new();
+                dec_consume!(buf, off; decode_bytes, &mut prefix.0[..size - off]);
+                NDOption::SixlowpanContext {
+                    context_len,
+                    compress: flags & context_flags::COMPRESS != 0,
+                    cid: flags & context_flags::CID_MASK,
+                    valid_lifetime,
+                    prefix,
+                }
+            }
+            _ => NDOption::Other {
+                option_type,
+                data: &buf[off..size],
+            },
+        };
+        stream_done!(size, option);
+    }
+}
+
+/// An iterator over the options of a message, which stops at the first
+/// malformed option.
+pub struct NDOptions<'b> {
+    buf: &'b [u8],
+}
+
+impl NDOptions<'b> {
+    pub fn new(buf: &'b [u8]) -> NDOptions<'b> {
+        NDOptions { buf }
+    }
+
+    /// Returns whether all the options of the message are well-formed.
+    /// Messages with malformed options must be discarded.
+    pub fn is_valid(buf: &[u8]) -> bool {
+        let mut off = 0;
+        while off < buf.len() {
+            match NDOption::decode(&buf[off..]).done() {
+                Some((size, _)) => off += size,
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+impl Iterator for NDOptions<'b> {
+    type Item = NDOption<'b>;
+
+    fn next(&mut self) -> Option<NDOption<'b>> {
+        let (size, option) = NDOption::decode(self.buf).done()?;
+        self.buf = &self.buf[size..];
+        Some(option)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+
+    const EUI64: [u8; 8] = [0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77];
+
+    fn prefix() -> IPAddr {
+        let mut prefix = IPAddr::new();
+        prefix.0[..8].copy_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 1, 0, 0]);
+        prefix
+    }
+
+    // Encodes `option`, checks its size and decodes it again.
+    fn round_trip<'b>(option: NDOption, encoded: &'b mut [u8]) -> NDOption<'b> {
+        let size = option.get_size();
+        assert_eq!(size % UNIT_LEN, 0);
+        assert_eq!(
+            option.encode(encoded, 0).done().map(|(off, _)| off),
+            Some(size)
+        );
+        assert_eq!(encoded[0], option.get_type());
+        assert_eq!(encoded[1] as usize * UNIT_LEN, size);
+        let (off, decoded) = NDOption::decode(&*encoded).done().unwrap();
+        assert_eq!(off, size);
+        decoded
+    }
+
+    #[test]
+    fn link_layer_addresses() {
+        let mut buf = [0xff; 24];
+        match round_trip(NDOption::SourceLLAddr(MacAddress::Short(0xabcd)), &mut buf) {
+            NDOption::SourceLLAddr(MacAddress::Short(0xabcd)) => {}
+            option => panic!("{:?}", option),
+        }
+        // Padded with zeros
+        assert_eq!(buf[..8], [1, 1, 0xab, 0xcd, 0, 0, 0, 0]);
+
+        match round_trip(NDOption::TargetLLAddr(MacAddress::Long(EUI64)), &mut buf) {
+            NDOption::TargetLLAddr(MacAddress::Long(eui64)) => assert_eq!(eui64, EUI64),
+            option => panic!("{:?}", option),
+        }
+        assert_eq!(
+            buf[..16],
+            [2, 2, 2, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0, 0, 0, 0, 0, 0]
+        );
+
+        // Link-layer addresses are one or two units long
+        assert!(NDOption::decode(&[
+            1, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn prefix_information() {
+        let mut buf = [0; 32];
+        let option = NDOption::PrefixInfo {
+            prefix_len: 64,
+            flags: prefix_flags::ON_LINK | prefix_flags::AUTONOMOUS,
+            valid_lifetime: 0x0102_0304,
+            preferred_lifetime: 3600,
+            prefix: prefix(),
+        };
+        match round_trip(option, &mut buf) {
+            NDOption::PrefixInfo {
+                prefix_len: 64,
+                flags: 0xc0,
+                valid_lifetime: 0x0102_0304,
+                preferred_lifetime: 3600,
+                prefix: decoded,
+            } => assert_eq!(decoded, prefix()),
+            option => panic!("{:?}", option),
+        }
+        assert_eq!(buf[..8], [3, 4, 64, 0xc0, 1, 2, 3, 4]);
+        assert_eq!(buf[16..24], prefix().0[..8]);
+
+        // The option has a fixed length
+        buf[1] = 3;
+        assert!(NDOption::decode(&buf).is_err());
+    }
+
+    #[test]
+    fn address_registration() {
+        let mut buf = [0; 16];
+        let option = NDOption::AddrRegistration {
+            status: aro_status::DUPLICATE,
+            lifetime: 60,
+            eui64: EUI64,
+        };
+        match round_trip(option, &mut buf) {
+            NDOption::AddrRegistration {
+                status: aro_status::DUPLICATE,
+                lifetime: 60,
+                eui64,
+            } => assert_eq!(eui64, EUI64),
+            option => panic!("{:?}", option),
+        }
+        assert_eq!(buf[..8], [33, 2, 1, 0, 0, 0, 0, 60]);
+    }
+
+    #[test]
+    fn sixlowpan_context() {
+        // Prefixes of up to 64 bits take one unit
+        let mut buf = [0; 24];
+        let option = NDOption::SixlowpanContext {
+            context_len: 48,
+            compress: true,
+            cid: 3,
+            valid_lifetime: 10,
+            prefix: prefix(),
+        };
+        assert_eq!(option.get_size(), 16);
+        match round_trip(option, &mut buf) {
+            NDOption::SixlowpanContext {
+                context_len: 48,
+                compress: true,
+                cid: 3,
+                valid_lifetime: 10,
+                prefix: decoded,
+            } => assert_eq!(decoded, prefix()),
+            option => panic!("{:?}", option),
+        }
+        assert_eq!(buf[..8], [34, 2, 48, 0x13, 0, 0, 0, 10]);
+
+        let option = NDOption::SixlowpanContext {
+            context_len: 128,
+            compress: false,
+            cid: 15,
+            valid_lifetime: 0,
+            prefix: prefix(),
+        };
+        assert_eq!(option.get_size(), 24);
+        match round_trip(option, &mut buf) {
+            NDOption::SixlowpanContext {
+                context_len: 128,
+                compress: false,
+                cid: 15,
+                ..
+            } => {}
+            option => panic!("{:?}", option),
+        }
+
+        // The context is longer than the prefix in the option
+        buf[1] = 2;
+        assert!(NDOption::decode(&buf[..16]).is_err());
+    }
+
+    #[test]
+    fn other_options() {
+        let mut buf = [0; 16];
+        let data = [0, 0, 0, 0, 0x05, 0xdc];
+        let option = NDOption::Other {
+            option_type: nd_option_type::MTU,
+            data: &data,
+        };
+        assert_eq!(option.get_size(), 8);
+        match round_trip(option, &mut buf) {
+            NDOption::Other {
+                option_type: nd_option_type::MTU,
+                data: decoded,
+            } => assert_eq!(decoded, data),
+            option => panic!("{:?}", option),
+        }
+    }
+
+    #[test]
+    fn malformed_options() {
+        // Options of length zero
+        assert!(NDOption::decode(&[1, 0, 0, 0, 0, 0, 0, 0]).is_err());
+        // Options longer than the message
+        assert!(NDOption::decode(&[1, 2, 0, 0, 0, 0, 0, 0]).is_needed());
+        assert!(NDOption::decode(&[1]).is_needed());
+    }
+
+    #[test]
+    fn iterate_options() {
+        let mut buf = [0; 48];
+        let off = NDOption::SourceLLAddr(MacAddress::Short(1))
+            .encode(&mut buf, 0)
+            .done()
+            .unwrap()
+            .0;
+        let off = NDOption::AddrRegistration {
+            status: aro_status::SUCCESS,
+            lifetime: 1,
+            eui64: EUI64,
+        }
+        .encode(&mut buf, off)
+        .done()
+        .unwrap()
+        .0;
+        assert_eq!(off, 24);
+
+        fn types(buf: &[u8]) -> std::vec::Vec<u8> {
+            NDOptions::new(buf)
+                .map(|option| option.get_type())
+                .collect()
+        }
+        assert_eq!(types(&buf[..off]), [1, 33]);
+        assert!(NDOptions::is_valid(&buf[..off]));
+        assert!(NDOptions::is_valid(&[]));
+
+        // A malformed option stops the iteration, and makes the message
+        // invalid
+        buf[off] = 1;
+        assert_eq!(types(&buf[..off + 8]), [1, 33]);
+        assert!(!NDOptions::is_valid(&buf[..off + 8]));
+        assert!(!NDOptions::is_valid(&buf[..=off]));
+    }
+}
@@ -2,7 +2,7 @@
 //! of the IP stack. Note that this file also contains the definition for the
 //! [IPAddr](struct.IPAddr.html) struct and associated helper functions.
 
-use crate::net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions};
+use crate::net::icmpv6::icmpv6::ICMP6Header;
 use crate::net::ieee802154::MacAddress;
 use crate::net::ipv6::ipv6::IP6Header;
 use crate::net::udp::udp::UDPHeader;
@@ -47,31 +47,43 @@ impl IPAddr {
     /// Based off of section 3.2.2 of rfc 6282
     pub fn generate_from_mac(mac_addr: MacAddress) -> IPAddr {
         let mut ip_addr = IPAddr([0; 16]);
+        ip_addr.set_unicast_link_local();
+        ip_addr.set_iid_from_mac(mac_addr);
+        ip_addr
+    }
+
+    /// Sets the interface identifier, the last 64 bits of the address, to
+    /// the one derived from a short or extended 15.4 MAC address
+    pub fn set_iid_from_mac(&mut self, mac_addr: MacAddress) {
         match mac_addr {
             MacAddress::Long(ref long_addr) => {
-                ip_addr.set_unicast_link_local();
-                ip_addr.0[15] = long_addr[7];
-                ip_addr.0[14] = long_addr[6];
-                ip_addr.0[13] = long_addr[5];
-                ip_addr.0[12] = long_addr[4];
-                ip_addr.0[11] = long_addr[3];
-                ip_addr.0[10] = long_addr[2];
-                ip_addr.0[9] = long_addr[1];
-                ip_addr.0[8] = long_addr[0] ^ 0b00000010;
+                self.0[8..16].copy_from_slice(long_addr);
+                self.0[8] ^= 0x02;
             }
             MacAddress::Short(ref short_addr) => {
-                ip_addr.set_unicast_link_local();
-                ip_addr.0[15] = (short_addr & 0x00ff) as u8;
-                ip_addr.0[14] = ((short_addr & 0xff00) >> 8) as u8;
-                ip_addr.0[13] = 0x00;
-                ip_addr.0[12] = 0xfe;
-                ip_addr.0[11] = 0xff;
-                ip_addr.0[10] = 0x00;
-                ip_addr.0[9] = 0x00;
-                ip_addr.0[8] = 0x00;
+                self.0[15] = (short_addr & 0x00ff) as u8;
+                self.0[14] = ((short_addr & 0xff00) >> 8) as u8;
+                self.0[13] = 0x00;
+                self.0[12] = 0xfe;
+                self.0[11] = 0xff;
+                self.0[10] = 0x00;
+                self.0[9] = 0x00;
+                self.0[8] = 0x00;
             }
         }
-        ip_addr
+    }
+
+    /// Returns the 15.4 MAC address the interface identifier was derived
+    /// from, the inverse of `set_iid_from_mac`
+    pub fn mac_from_iid(&self) -> MacAddress {
+        if self.0[8..14] == [0x00, 0x00, 0x00, 0xff, 0xfe, 0x00] {
+            MacAddress::Short((self.0[14] as u16) << 8 | self.0[15] as u16)
+        } else {
+            let mut long_addr = [0; 8];
+            long_addr.copy_from_slice(&self.0[8..16]);
+            long_addr[0] ^= 0x02;
+            MacAddress::Long(long_addr)
+        }
     }
 
     pub fn is_unspecified(&self) -> bool {
@@ -111,6 +123,12 @@ impl IPAddr {
     pub fn is_multicast(&self) -> bool {
         self.0[0] == 0xff
     }
+
+    /// Returns whether the address is a link-local unicast address, or a
+    /// multicast address whose scope does not extend beyond the link
+    pub fn is_link_local_scope(&self) -> bool {
+        self.is_unicast_link_local() || (self.is_multicast() && (self.0[1] & 0x0f) <= 2)
+    }
 }
 
 pub fn compute_udp_checksum(
@@ -180,48 +198,71 @@ pub fn compute_udp_checksum(
     (sum as u16) //Return result as u16 in host byte order */
 }
 
+/// Computes the checksum of an ICMPv6 message for transmission. The checksum
+/// field of `icmp_header` is ignored.
 pub fn compute_icmp_checksum(
     ipv6_header: &IP6Header,
     icmp_header: &ICMP6Header,
     payload: &[u8],
 ) -> u16 {
+    let mut icmp_header = *icmp_header;
+    icmp_header.set_cksum(0);
+    let hdr_size = icmp_header.get_hdr_size();
+    let mut header = [0 as u8; 8];
+    icmp_header.encode(&mut header, 0);
+    let payload_len = icmp_header.get_len() as usize - hdr_size;
+    compute_transport_checksum(
+        ipv6_header,
+        ip6_nh::ICMP,
+        &header[..hdr_size],
+        &payload[..payload_len],
+    )
+}
+
+/// Computes the checksum of a TCP segment, given its encoded header and its
+/// data. The checksum field of the header must be zero when the checksum is
+/// computed for transmission; a received segment is valid if the result is
+/// zero.
+pub fn compute_tcp_checksum(ip6_header: &IP6Header, header: &[u8], payload: &[u8]) -> u16 {
+    compute_transport_checksum(ip6_header, ip6_nh::TCP, header, payload)
+}
+
+/// Computes the checksum of an upper-layer packet with the pseudo-header of
+/// RFC 8200, given its encoded header and its payload. The checksum field of
+/// the header must be zero when the checksum is computed for transmission; a
+/// received packet is valid if the result is zero.
+pub fn compute_transport_checksum(
+    ip6_header: &IP6Header,
+    next_header: u8,
+    header: &[u8],
+    payload: &[u8],
+) -> u16 {
+    let length = (header.len() + payload.len()) as u32;
     let mut sum: u32 = 0;
 
     // add ipv6 pseudo-header
-    sum += compute_ipv6_ph_sum(ipv6_header);
-
-    // add type and code
-    let msb = (icmp_header.get_type_as_int() as u32) << 8;
-    let lsb = icmp_header.get_code() as u32;
-    sum += msb + lsb;
-
-    // add options
-    match icmp_header.get_options() {
-        ICMP6HeaderOptions::Type1 { unused } | ICMP6HeaderOptions::Type3 { unused } => {
-            sum += unused >> 16; // upper 16 bits
-            sum += unused & 0xffff; // lower 16 bits
-        }
-        ICMP6HeaderOptions::Type128 { id, seqno } | ICMP6HeaderOptions::Type129 { id, seqno } => {
-            sum += id as u32;
-            sum += seqno as u32;
-        }
+    for i in (0..16).step_by(2) {
+        sum += (ip6_header.src_addr.0[i] as u32) << 8 | ip6_header.src_addr.0[i + 1] as u32;
+        sum += (ip6_header.dst_addr.0[i] as u32) << 8 | ip6_header.dst_addr.0[i + 1] as u32;
+    }
+    sum += length >> 16;
+    sum += length & 0xffff;
+    sum += next_header as u32;
+
+    // add the header, which has an even length, and the payload, padded
+    // with a zero byte if its length is odd
+    for chunk in header.chunks(2).chain(payload.chunks(2)) {
+        let msb = (chunk[0] as u32) << 8;
+        let lsb = if chunk.len() > 1 { chunk[1] as u32 } else { 0 };
+        sum += msb + lsb;
     }
-
-    // add icmp payload
-    let payload_len = icmp_header.get_len() - icmp_header.get_hdr_size() as u16;
-    sum += compute_sum(payload, payload_len);
 
     // carry overflow
     while sum > 0xffff {
-        let sum_upper = sum >> 16;
-        let sum_lower = sum & 0xffff;
-        sum += sum_upper + sum_lower;
+        sum = (sum >> 16) + (sum & 0xffff);
     }
 
-    sum = !sum;
-    sum = sum & 0xffff;
-
-    sum as u16
+    !sum as u16
 }
 
 pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
@@ -260,3 +301,90 @@ pub fn compute_sum(buf: &[u8], len: u16) -> u32 {
 
     sum
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net::icmpv6::icmpv6::{ICMP6HeaderOptions, ICMP6Type};
+    use kernel::ReturnCode;
+
+    fn ip6_header(payload_len: usize) -> IP6Header {
+        let mut header = IP6Header::new();
+        header.src_addr.0[..2].copy_from_slice(&[0xfe, 0x80]);
+        header.src_addr.0[15] = 1;
+        header.dst_addr.0[..2].copy_from_slice(&[0xfe, 0x80]);
+        header.dst_addr.0[15] = 2;
+        header.set_next_header(ip6_nh::ICMP);
+        header.set_payload_len(payload_len as u16);
+        header
+    }
+
+    fn echo_request(payload: &[u8]) -> ICMP6Header {
+        let mut header = ICMP6Header::new(ICMP6Type::Type128);
+        header.set_options(ICMP6HeaderOptions::Type128 {
+            id: 0x1234,
+            seqno: 1,
+        });
+        header.set_len((header.get_hdr_size() + payload.len()) as u16);
+        header
+    }
+
+    #[test]
+    fn icmp_checksum_of_echo_request() {
+        // An odd length, so that the payload is padded
+        let payload = b"abc";
+        let mut header = echo_request(payload);
+        let ip6_header = ip6_header(11);
+        assert_eq!(compute_icmp_checksum(&ip6_header, &header, payload), 0xac1d);
+
+        // The checksum field does not count
+        header.set_cksum(0x5555);
+        assert_eq!(compute_icmp_checksum(&ip6_header, &header, payload), 0xac1d);
+        // Neither do bytes past the length of the message
+        assert_eq!(
+            compute_icmp_checksum(&ip6_header, &header, b"abcdef"),
+            0xac1d
+        );
+    }
+
+    #[test]
+    fn received_icmp_checksum() {
+        let mut header = echo_request(b"abc");
+        let ip6_header = ip6_header(11);
+        header.set_cksum(compute_icmp_checksum(&ip6_header, &header, b"abc"));
+        let mut buf = [0; 11];
+        header.encode(&mut buf, 0).done().unwrap();
+        buf[8..].copy_from_slice(b"abc");
+        assert_eq!(
+            ip6_header.check_transport_checksum(&buf),
+            ReturnCode::SUCCESS
+        );
+
+        for i in 0..buf.len() {
+            let mut corrupted = buf;
+            corrupted[i] ^= 0x01;
+            assert_eq!(
+                ip6_header.check_transport_checksum(&corrupted),
+                ReturnCode::FAIL
+            );
+        }
+        assert_eq!(
+            ip6_header.check_transport_checksum(&buf[..7]),
+            ReturnCode::FAIL
+        );
+    }
+
+    #[test]
+    fn received_icmp_checksum_of_unknown_type() {
+        let ip6_header = ip6_header(8);
+        let mut buf = [200, 0, 0, 0, 1, 2, 3, 4];
+        let checksum = compute_transport_checksum(&ip6_header, ip6_nh::ICMP, &buf, &[]);
+        buf[2..4].copy_from_slice(&checksum.to_be_bytes());
+        assert_eq!(
+            ip6_header.check_transport_checksum(&buf),
+            ReturnCode::SUCCESS
+        );
+        buf[7] = 5;
+        assert_eq!(ip6_header.check_transport_checksum(&buf), ReturnCode::FAIL);
+    }
+}
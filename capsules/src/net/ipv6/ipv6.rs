@@ -65,16 +65,20 @@
 // (as required by 6LoWPAN) difficult.
 
 use crate::net::icmpv6::icmpv6::ICMP6Header;
-use crate::net::ipv6::ip_utils::{compute_icmp_checksum, compute_udp_checksum, ip6_nh, IPAddr};
+use crate::net::ipv6::ip_utils::{
+    compute_icmp_checksum, compute_tcp_checksum, compute_transport_checksum, compute_udp_checksum,
+    ip6_nh, IPAddr,
+};
 use crate::net::stream::SResult;
 use crate::net::stream::{decode_bytes, decode_u16, decode_u8};
 use crate::net::stream::{encode_bytes, encode_u16, encode_u8};
-use crate::net::tcp::TCPHeader;
+use crate::net::tcp::tcp::TCPHeader;
 use crate::net::udp::udp::UDPHeader;
 use kernel::ReturnCode;
 
 pub const UDP_HDR_LEN: usize = 8;
 pub const ICMP_HDR_LEN: usize = 8;
+pub const TCP_HDR_LEN: usize = 20;
 
 /// This is the struct definition for an IPv6 header. It contains (in order)
 /// the same fields as a normal IPv6 header.
@@ -278,21 +282,33 @@ impl IP6Header {
                 ReturnCode::SUCCESS
             }
             ip6_nh::ICMP => {
-                // Untested (10/5/18)
-                let mut icmp_header: [u8; ICMP_HDR_LEN] = [0; ICMP_HDR_LEN];
-                icmp_header.copy_from_slice(&buf[..ICMP_HDR_LEN]);
-                let checksum = match ICMP6Header::decode(&icmp_header).done() {
-                    Some((_offset, mut hdr)) => {
-                        hdr.set_len(buf.len() as u16);
-                        u16::from_be(compute_icmp_checksum(&self, &hdr, &buf[ICMP_HDR_LEN..]))
-                    }
-                    None => 0xffff, //Will be dropped, as ones comp -0 checksum is invalid
-                };
+                if buf.len() < ICMP_HDR_LEN {
+                    return ReturnCode::FAIL;
+                }
+                // Computed over the received header, as ICMPv6 messages of
+                // types `ICMP6Header` does not decode are also checked
+                let checksum = compute_transport_checksum(
+                    &self,
+                    ip6_nh::ICMP,
+                    &buf[..ICMP_HDR_LEN],
+                    &buf[ICMP_HDR_LEN..],
+                );
                 if checksum != 0 {
                     return ReturnCode::FAIL; //Incorrect cksum
                 }
                 ReturnCode::SUCCESS
             }
+            ip6_nh::TCP => {
+                if buf.len() < TCP_HDR_LEN {
+                    return ReturnCode::FAIL;
+                }
+                // The checksum covers the options, so it is computed over the
+                // received header rather than a decoded TCPHeader
+                if compute_tcp_checksum(&self, &buf[..TCP_HDR_LEN], &buf[TCP_HDR_LEN..]) != 0 {
+                    return ReturnCode::FAIL; //Incorrect cksum
+                }
+                ReturnCode::SUCCESS
+            }
             _ => ReturnCode::ENOSUPPORT,
         }
     }
@@ -358,10 +374,15 @@ impl IPPayload<'a> {
             TransportHeader::ICMP(mut icmp_header) => {
                 let length = (payload.len() + icmp_header.get_hdr_size()) as u16;
                 icmp_header.set_len(length);
-                self.header = transport_header;
+                self.header = TransportHeader::ICMP(icmp_header);
                 (ip6_nh::ICMP, length)
             }
-            _ => (ip6_nh::NO_NEXT, payload.len() as u16),
+            TransportHeader::TCP(mut tcp_header) => {
+                let length = (payload.len() + tcp_header.get_hdr_size()) as u16;
+                tcp_header.set_len(length);
+                self.header = TransportHeader::TCP(tcp_header);
+                (ip6_nh::TCP, length)
+            }
         }
     }
 
@@ -380,9 +401,7 @@ impl IPPayload<'a> {
         let (offset, _) = match self.header {
             TransportHeader::UDP(udp_header) => udp_header.encode(buf, offset).done().unwrap(),
             TransportHeader::ICMP(icmp_header) => icmp_header.encode(buf, offset).done().unwrap(),
-            _ => {
-                unimplemented!();
-            }
+            TransportHeader::TCP(tcp_header) => tcp_header.encode(buf, offset).done().unwrap(),
         };
         let payload_length = self.get_payload_length();
         let offset = enc_consume!(buf, offset; encode_bytes, &self.payload[..payload_length]);
@@ -397,8 +416,8 @@ impl IPPayload<'a> {
             TransportHeader::ICMP(icmp_header) => {
                 icmp_header.get_len() as usize - icmp_header.get_hdr_size()
             }
-            _ => {
-                unimplemented!();
+            TransportHeader::TCP(tcp_header) => {
+                tcp_header.get_len() as usize - tcp_header.get_hdr_size()
             }
         }
     }
@@ -446,7 +465,7 @@ impl IP6Packet<'a> {
         let transport_hdr_size = match self.payload.header {
             TransportHeader::UDP(udp_hdr) => udp_hdr.get_hdr_size(),
             TransportHeader::ICMP(icmp_header) => icmp_header.get_hdr_size(),
-            _ => unimplemented!(),
+            TransportHeader::TCP(tcp_header) => tcp_header.get_hdr_size(),
         };
         40 + transport_hdr_size
     }
@@ -472,8 +491,18 @@ impl IP6Packet<'a> {
                 let cksum = compute_icmp_checksum(&self.header, &icmp_header, self.payload.payload);
                 icmp_header.set_cksum(cksum);
             }
-            _ => {
-                unimplemented!();
+            TransportHeader::TCP(ref mut tcp_header) => {
+                let mut header = [0 as u8; 60];
+                tcp_header.set_cksum(0);
+                let hdr_size = tcp_header.get_hdr_size();
+                let payload_len = tcp_header.get_len() as usize - hdr_size;
+                tcp_header.encode(&mut header, 0);
+                let cksum = compute_tcp_checksum(
+                    &self.header,
+                    &header[..hdr_size],
+                    &self.payload.payload[..payload_len],
+                );
+                tcp_header.set_cksum(cksum);
             }
         }
     }
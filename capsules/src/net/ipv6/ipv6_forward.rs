@@ -0,0 +1,503 @@
+//! This file contains the forwarding path of a router: the packets that the
+//! `IP6RecvStruct` receives for other destinations are queued, and sent on
+//! to the next hop towards their destination, which the `IP6Interface` finds
+//! in its routing table, e.g. with the routes that RPL adds. They are sent
+//! with their own `IP6Sender`, whose 6LoWPAN `TxState` fragments them again
+//! for the next link.
+//!
+//! Before queueing a packet, the forwarder decrements its hop limit, and
+//! drops it, answering with an ICMPv6 error message when the error reporter
+//! is set, if the hop limit runs out or if there is no route to its
+//! destination. The queue is a buffer split into `FORWARD_QUEUE_LEN` slots;
+//! packets that arrive while it is full, or that are longer than a slot, are
+//! dropped. `get_stats` counts the packets forwarded and dropped.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use capsules::net::ipv6::ipv6_forward::IP6Forwarder;
+//! # use kernel::static_init;
+//! let forwarder = static_init!(
+//!     IP6Forwarder<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, Ast>>>,
+//!     IP6Forwarder::new(ip_send, interface, &mut FORWARD_QUEUE_BUF)
+//! );
+//! ip_send.set_client(forwarder);
+//! forwarder.set_error_reporter(ip_recv_mux);
+//! ip_receive.set_interface(interface);
+//! ip_receive.set_forward_client(forwarder);
+//! ```
+
+// Known Limitations
+// -----------------
+// The packets are rebuilt from their IPv6 and transport headers, as the
+// `IP6Sender` compresses an `IP6Packet`, so only UDP, TCP and the ICMPv6
+// messages the stack knows of can be forwarded. Packets too long for a slot
+// are dropped without a Packet Too Big message.
+
+use crate::net::icmpv6::icmpv6::{dest_unreachable_code, time_exceeded_code};
+use crate::net::icmpv6::icmpv6::{ICMP6Header, ICMP6Type};
+use crate::net::icmpv6::icmpv6_recv::ICMP6ErrorReporter;
+use crate::net::ipv6::ip_utils::ip6_nh;
+use crate::net::ipv6::ipv6::{IP6Header, TransportHeader, TCP_HDR_LEN};
+use crate::net::ipv6::ipv6_interface::IP6Interface;
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::tcp::tcp::TCPHeader;
+use crate::net::udp::udp::UDPHeader;
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::ReturnCode;
+
+/// Number of packets the forwarder can queue.
+pub const FORWARD_QUEUE_LEN: usize = 4;
+
+/// Counts of the packets the forwarder received.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ForwardStats {
+    /// Packets passed to the `IP6Sender`
+    pub forwarded: u32,
+    /// Packets dropped because the queue was full or they did not fit in a
+    /// slot
+    pub queue_full: u32,
+    pub hop_limit_exceeded: u32,
+    pub no_route: u32,
+    /// Packets that must not or cannot be forwarded, e.g. link-local ones,
+    /// and packets the `IP6Sender` failed to send
+    pub other: u32,
+}
+
+pub struct IP6Forwarder<'a, T: IP6Sender<'a>> {
+    ip_sender: &'a T,
+    interface: &'a IP6Interface,
+    // The queue holds `queue_len` packets, with their IPv6 header, in the
+    // slots of `buf` from the slot at `head`
+    buf: TakeCell<'static, [u8]>,
+    lens: [Cell<usize>; FORWARD_QUEUE_LEN],
+    head: Cell<usize>,
+    queue_len: Cell<usize>,
+    sending: Cell<bool>,
+    error_reporter: OptionalCell<&'a dyn ICMP6ErrorReporter>,
+    stats: Cell<ForwardStats>,
+}
+
+impl<T: IP6Sender<'a>> IP6Forwarder<'a, T> {
+    /// Each of the `FORWARD_QUEUE_LEN` slots of `buf` holds a packet with its
+    /// 40 byte IPv6 header, and the payload buffer of `ip_sender` must hold
+    /// the transport payload of a slot.
+    pub fn new(
+        ip_sender: &'a T,
+        interface: &'a IP6Interface,
+        buf: &'static mut [u8],
+    ) -> IP6Forwarder<'a, T> {
+        IP6Forwarder {
+            ip_sender,
+            interface,
+            buf: TakeCell::new(buf),
+            lens: Default::default(),
+            head: Cell::new(0),
+            queue_len: Cell::new(0),
+            sending: Cell::new(false),
+            error_reporter: OptionalCell::empty(),
+            stats: Cell::new(ForwardStats::default()),
+        }
+    }
+
+    /// Sets the error reporter that answers the packets that are dropped
+    /// because their hop limit ran out or there is no route to their
+    /// destination.
+    pub fn set_error_reporter(&self, error_reporter: &'a dyn ICMP6ErrorReporter) {
+        self.error_reporter.set(error_reporter);
+    }
+
+    pub fn get_stats(&self) -> ForwardStats {
+        self.stats.get()
+    }
+
+    fn count(&self, counter: fn(&mut ForwardStats) -> &mut u32) {
+        let mut stats = self.stats.get();
+        let value = counter(&mut stats);
+        *value = value.wrapping_add(1);
+        self.stats.set(stats);
+    }
+
+    fn report_error(&self, icmp_type: ICMP6Type, code: u8, header: &IP6Header, payload: &[u8]) {
+        let mut icmp_header = ICMP6Header::new(icmp_type);
+        icmp_header.set_code(code);
+        self.error_reporter
+            .map(|reporter| reporter.report_error(icmp_header, header, payload));
+    }
+
+    fn slot_len(&self) -> usize {
+        self.buf.map_or(0, |buf| buf.len() / FORWARD_QUEUE_LEN)
+    }
+
+    // Copies a packet to the slot at the tail of the queue
+    fn enqueue(&self, header: &IP6Header, payload: &[u8]) -> ReturnCode {
+        let slot_len = self.slot_len();
+        let len = header.get_total_len() as usize;
+        if self.queue_len.get() == FORWARD_QUEUE_LEN || len > slot_len {
+            return ReturnCode::ENOMEM;
+        }
+        let index = (self.head.get() + self.queue_len.get()) % FORWARD_QUEUE_LEN;
+        let result = self.buf.map_or(ReturnCode::ENOMEM, |buf| {
+            let slot = &mut buf[index * slot_len..(index + 1) * slot_len];
+            match header.encode(slot).done() {
+                Some((off, _)) => {
+                    slot[off..len].copy_from_slice(payload);
+                    ReturnCode::SUCCESS
+                }
+                None => ReturnCode::FAIL,
+            }
+        });
+        if result == ReturnCode::SUCCESS {
+            self.lens[index].set(len);
+            self.queue_len.set(self.queue_len.get() + 1);
+        }
+        result
+    }
+
+    // Passes the packets at the head of the queue to the `IP6Sender`, until
+    // it accepts one or the queue is empty
+    fn send_next(&self) {
+        while !self.sending.get() && self.queue_len.get() > 0 {
+            let index = self.head.get();
+            let slot_len = self.slot_len();
+            let len = self.lens[index].get();
+            self.sending.set(true);
+            let result = self.buf.map_or(ReturnCode::ENOMEM, |buf| {
+                let packet = &buf[index * slot_len..index * slot_len + len];
+                match IP6Header::decode(packet).done() {
+                    Some((off, header)) => match transport_header(&header, &packet[off..]) {
+                        Some((transport_header, payload_off)) => self.ip_sender.forward(
+                            header,
+                            transport_header,
+                            &packet[off + payload_off..],
+                        ),
+                        None => ReturnCode::FAIL,
+                    },
+                    None => ReturnCode::FAIL,
+                }
+            });
+            // The `IP6Sender` copies the packet, so the slot is free
+            self.head.set((index + 1) % FORWARD_QUEUE_LEN);
+            self.queue_len.set(self.queue_len.get() - 1);
+            if result == ReturnCode::SUCCESS {
+                self.count(|stats| &mut stats.forwarded);
+            } else {
+                self.sending.set(false);
+                self.count(|stats| &mut stats.other);
+            }
+        }
+    }
+}
+
+// Returns the transport header of a packet, and the offset of the transport
+// payload, so that the `IP6Sender` encodes the same bytes again
+fn transport_header(header: &IP6Header, payload: &[u8]) -> Option<(TransportHeader, usize)> {
+    match header.get_next_header() {
+        ip6_nh::UDP => {
+            let (off, udp_header) = UDPHeader::decode(payload).done()?;
+            Some((TransportHeader::UDP(udp_header), off))
+        }
+        ip6_nh::TCP => {
+            // The options are left in the payload, as they are
+            let (_, mut tcp_header) = TCPHeader::decode(payload).done()?;
+            tcp_header.mss = None;
+            Some((TransportHeader::TCP(tcp_header), TCP_HDR_LEN))
+        }
+        ip6_nh::ICMP => {
+            let (off, icmp_header) = ICMP6Header::decode(payload).done()?;
+            Some((TransportHeader::ICMP(icmp_header), off))
+        }
+        _ => None,
+    }
+}
+
+impl<T: IP6Sender<'a>> IP6RecvClient for IP6Forwarder<'a, T> {
+    fn receive(&self, header: IP6Header, payload: &[u8]) {
+        let src_addr = header.get_src_addr();
+        let dst_addr = header.get_dst_addr();
+        // Packets with link-local addresses never leave their link
+        if src_addr.is_unspecified()
+            || src_addr.is_multicast()
+            || src_addr.is_link_local_scope()
+            || dst_addr.is_multicast()
+            || dst_addr.is_link_local_scope()
+            || header.get_payload_len() as usize != payload.len()
+            || transport_header(&header, payload).is_none()
+        {
+            self.count(|stats| &mut stats.other);
+            return;
+        }
+        if header.get_hop_limit() <= 1 {
+            self.count(|stats| &mut stats.hop_limit_exceeded);
+            self.report_error(
+                ICMP6Type::Type3,
+                time_exceeded_code::HOP_LIMIT_EXCEEDED,
+                &header,
+                payload,
+            );
+            return;
+        }
+        if self.interface.next_hop(&dst_addr).is_none() {
+            self.count(|stats| &mut stats.no_route);
+            self.report_error(
+                ICMP6Type::Type1,
+                dest_unreachable_code::NO_ROUTE,
+                &header,
+                payload,
+            );
+            return;
+        }
+
+        let mut forwarded_header = header;
+        forwarded_header.set_hop_limit(header.get_hop_limit() - 1);
+        if self.enqueue(&forwarded_header, payload) != ReturnCode::SUCCESS {
+            // Tail drop
+            self.count(|stats| &mut stats.queue_full);
+            return;
+        }
+        self.send_next();
+    }
+}
+
+impl<T: IP6Sender<'a>> IP6SendClient for IP6Forwarder<'a, T> {
+    fn send_done(&self, _result: ReturnCode) {
+        self.sending.set(false);
+        // When the `IP6Sender` fails synchronously, this is called from
+        // `send_next`, which goes on with the next packet itself
+        if self.buf.is_some() {
+            self.send_next();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use crate::net::ipv6::ip_utils::IPAddr;
+    use crate::net::ipv6::ipv6_interface::Route;
+    use crate::net::mock::{buf, MockSender};
+    use core::cell::RefCell;
+    use std::vec::Vec;
+
+    // Slots of 40 byte headers and 40 byte payloads
+    const SLOT_LEN: usize = 80;
+
+    struct Reporter {
+        errors: RefCell<Vec<(u8, u8)>>,
+    }
+
+    impl ICMP6ErrorReporter for Reporter {
+        fn report_error(&self, icmp_header: ICMP6Header, _ip6_header: &IP6Header, _payload: &[u8]) {
+            self.errors
+                .borrow_mut()
+                .push((icmp_header.get_type_as_int(), icmp_header.get_code()));
+        }
+    }
+
+    fn addr(subnet: u8, host: u8) -> IPAddr {
+        let mut addr = IPAddr::new();
+        addr.0[..6].copy_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, subnet]);
+        addr.0[15] = host;
+        addr
+    }
+
+    struct Mocks {
+        sender: MockSender,
+        interface: IP6Interface,
+        reporter: Reporter,
+    }
+
+    type TestForwarder<'a> = IP6Forwarder<'a, MockSender>;
+
+    impl Mocks {
+        // The interface routes 2001:db8:0:2::/64 through fe80::2
+        fn new() -> Mocks {
+            let interface = IP6Interface::new();
+            let mut next_hop = IPAddr::new();
+            next_hop.set_unicast_link_local();
+            next_hop.0[15] = 2;
+            interface.add_route(Route {
+                prefix: addr(2, 0),
+                prefix_len: 64,
+                next_hop,
+                lifetime: 600,
+            });
+            Mocks {
+                sender: MockSender::new(),
+                interface,
+                reporter: Reporter {
+                    errors: RefCell::new(Vec::new()),
+                },
+            }
+        }
+
+        fn forwarder(&self) -> TestForwarder {
+            let forwarder = IP6Forwarder::new(
+                &self.sender,
+                &self.interface,
+                buf(SLOT_LEN * FORWARD_QUEUE_LEN),
+            );
+            forwarder.set_error_reporter(&self.reporter);
+            forwarder
+        }
+    }
+
+    fn ip6_header(dst: IPAddr, hop_limit: u8, next_header: u8, payload_len: usize) -> IP6Header {
+        let mut header = IP6Header::new();
+        header.src_addr = addr(1, 1);
+        header.dst_addr = dst;
+        header.set_hop_limit(hop_limit);
+        header.set_next_header(next_header);
+        header.set_payload_len(payload_len as u16);
+        header
+    }
+
+    // A UDP datagram from 2001:db8:0:1::1 to `dst` with `data`, from port
+    // 1000 to port 2000.
+    fn udp(dst: IPAddr, hop_limit: u8, data: &[u8]) -> (IP6Header, Vec<u8>) {
+        let len = 8 + data.len() as u8;
+        let mut payload = std::vec![0x03, 0xe8, 0x07, 0xd0, 0, len, 0x12, 0x34];
+        payload.extend_from_slice(data);
+        (
+            ip6_header(dst, hop_limit, ip6_nh::UDP, payload.len()),
+            payload,
+        )
+    }
+
+    fn receive(forwarder: &TestForwarder, (header, payload): (IP6Header, Vec<u8>)) {
+        forwarder.receive(header, &payload);
+    }
+
+    #[test]
+    fn forwards_along_the_route() {
+        let mocks = Mocks::new();
+        let forwarder = mocks.forwarder();
+        receive(&forwarder, udp(addr(2, 5), 64, b"hello"));
+        let sent = mocks.sender.take();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].src, Some(addr(1, 1)));
+        assert_eq!(sent[0].dst, addr(2, 5));
+        assert_eq!(sent[0].hop_limit, Some(63));
+        match sent[0].header {
+            TransportHeader::UDP(udp_header) => {
+                assert_eq!(udp_header.get_dst_port(), 2000);
+                assert_eq!(udp_header.get_cksum(), 0x1234);
+            }
+            _ => panic!("not a UDP datagram"),
+        }
+        assert_eq!(sent[0].payload, b"hello");
+        assert_eq!(forwarder.get_stats().forwarded, 1);
+    }
+
+    #[test]
+    fn forwards_tcp_options_as_they_are() {
+        let mocks = Mocks::new();
+        let forwarder = mocks.forwarder();
+        let mut tcp_header = TCPHeader::new();
+        tcp_header.set_src_port(1000);
+        tcp_header.set_dst_port(80);
+        tcp_header.set_mss(Some(100));
+        let mut payload = std::vec![0; 24];
+        tcp_header.encode(&mut payload, 0).done().unwrap();
+        payload.extend_from_slice(b"data");
+        receive(
+            &forwarder,
+            (ip6_header(addr(2, 5), 64, ip6_nh::TCP, 28), payload),
+        );
+
+        let sent = mocks.sender.take();
+        match sent[0].header {
+            TransportHeader::TCP(tcp_header) => {
+                assert_eq!(tcp_header.get_dst_port(), 80);
+                assert_eq!(tcp_header.get_mss(), None);
+            }
+            _ => panic!("not a TCP segment"),
+        }
+        assert_eq!(sent[0].payload, [2, 4, 0, 100, b'd', b'a', b't', b'a']);
+    }
+
+    #[test]
+    fn queues_while_sending() {
+        let mocks = Mocks::new();
+        let forwarder = mocks.forwarder();
+        for i in 0..FORWARD_QUEUE_LEN as u8 + 2 {
+            receive(&forwarder, udp(addr(2, 5), 64, &[i]));
+        }
+        // One packet is being sent, the queue is full and the last one is
+        // dropped
+        assert_eq!(mocks.sender.take()[0].payload, [0]);
+        assert_eq!(forwarder.get_stats().queue_full, 1);
+        for i in 1..=FORWARD_QUEUE_LEN as u8 {
+            forwarder.send_done(ReturnCode::SUCCESS);
+            assert_eq!(mocks.sender.take()[0].payload, [i]);
+        }
+        forwarder.send_done(ReturnCode::SUCCESS);
+        assert!(mocks.sender.take().is_empty());
+        assert_eq!(forwarder.get_stats().forwarded, 5);
+
+        // Packets longer than a slot are dropped
+        receive(&forwarder, udp(addr(2, 5), 64, &[0; SLOT_LEN]));
+        assert!(mocks.sender.take().is_empty());
+        assert_eq!(forwarder.get_stats().queue_full, 2);
+    }
+
+    #[test]
+    fn goes_on_after_a_failed_send() {
+        let mocks = Mocks::new();
+        let forwarder = mocks.forwarder();
+        mocks.sender.result.set(ReturnCode::EBUSY);
+        receive(&forwarder, udp(addr(2, 5), 64, &[1]));
+        assert_eq!(forwarder.get_stats().other, 1);
+        mocks.sender.result.set(ReturnCode::SUCCESS);
+        mocks.sender.take();
+        receive(&forwarder, udp(addr(2, 5), 64, &[2]));
+        assert_eq!(mocks.sender.take()[0].payload, [2]);
+    }
+
+    #[test]
+    fn reports_undeliverable_packets() {
+        let mocks = Mocks::new();
+        let forwarder = mocks.forwarder();
+        receive(&forwarder, udp(addr(2, 5), 1, &[]));
+        receive(&forwarder, udp(addr(3, 5), 64, &[]));
+        assert!(mocks.sender.take().is_empty());
+        assert_eq!(
+            *mocks.reporter.errors.borrow(),
+            [
+                (3, time_exceeded_code::HOP_LIMIT_EXCEEDED),
+                (1, dest_unreachable_code::NO_ROUTE)
+            ]
+        );
+        let stats = forwarder.get_stats();
+        assert_eq!((stats.hop_limit_exceeded, stats.no_route), (1, 1));
+    }
+
+    #[test]
+    fn keeps_packets_on_their_link() {
+        let mocks = Mocks::new();
+        let forwarder = mocks.forwarder();
+        let mut link_local = IPAddr::new();
+        link_local.set_unicast_link_local();
+        link_local.0[15] = 2;
+        receive(&forwarder, udp(link_local, 64, &[]));
+        let mut multicast = addr(2, 5);
+        multicast.0[0] = 0xff;
+        receive(&forwarder, udp(multicast, 64, &[]));
+        // Nor packets whose length is wrong, or whose headers are malformed
+        let (header, payload) = udp(addr(2, 5), 64, &[1, 2]);
+        forwarder.receive(header, &payload[..9]);
+        receive(
+            &forwarder,
+            (ip6_header(addr(2, 5), 64, ip6_nh::UDP, 4), std::vec![0; 4]),
+        );
+
+        assert!(mocks.sender.take().is_empty());
+        assert!(mocks.reporter.errors.borrow().is_empty());
+        assert_eq!(forwarder.get_stats().other, 4);
+    }
+}
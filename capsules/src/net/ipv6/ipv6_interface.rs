@@ -0,0 +1,572 @@
+//! This file contains the dynamic state of an IPv6 interface: the addresses
+//! assigned to it, its default router, its neighbor cache and its routing
+//! table. Neighbor Discovery fills it in as routers advertise themselves and
+//! addresses are configured, a routing protocol such as RPL adds the routes
+//! through the other nodes of a mesh, and the rest of the stack reads it: the
+//! `IP6SendStruct` picks the source address and the next hop of each packet
+//! with it, and the UDP driver checks the addresses processes bind to against
+//! it.
+//!
+//! The interface is also a member of multicast groups, whose packets the
+//! `IP6RecvStruct` receives: the all-nodes groups and the solicited-node
+//! groups of its addresses (RFC 4291), and the groups that in-kernel
+//! protocols and processes join. Groups are counted, so that each user can
+//! leave the groups it joined without affecting the others.
+//!
+//! Lifetimes are counted in seconds, and elapse when the owner of the
+//! interface, usually Neighbor Discovery, calls `tick`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use capsules::net::ipv6::ipv6_interface::IP6Interface;
+//! # use kernel::static_init;
+//! let interface = static_init!(IP6Interface, IP6Interface::new());
+//! // An address that never expires
+//! interface.add_addr(IPAddr::generate_from_mac(src_mac), None);
+//! ip_send.set_interface(interface);
+//! ```
+
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::util;
+use core::cell::Cell;
+use kernel::ReturnCode;
+
+/// Maximum number of addresses of an interface.
+pub const MAX_ADDRS: usize = 6;
+
+/// Number of entries of the neighbor cache.
+pub const NEIGHBOR_CACHE_LEN: usize = 4;
+
+/// Number of entries of the routing table.
+pub const ROUTE_TABLE_LEN: usize = 8;
+
+/// Multicast packets are sent in 802.15.4 broadcast frames.
+pub const BROADCAST_MAC_ADDR: MacAddress = MacAddress::Short(0xffff);
+
+/// Maximum number of multicast groups joined, besides the groups the
+/// interface is always a member of.
+pub const MAX_GROUPS: usize = 4;
+
+/// The all-nodes interface-local multicast address, ff01::1.
+pub const ALL_NODES_INTERFACE_LOCAL_ADDR: IPAddr =
+    IPAddr([0xff, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01]);
+
+/// The all-nodes link-local multicast address, ff02::1.
+pub const ALL_NODES_ADDR: IPAddr =
+    IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01]);
+
+// The prefix of solicited-node multicast addresses, ff02::1:ff00:0/104
+const SOLICITED_NODE_PREFIX: [u8; 13] = [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xff];
+
+/// An address assigned to the interface.
+#[derive(Copy, Clone, Debug)]
+pub struct IP6InterfaceAddr {
+    pub addr: IPAddr,
+    /// Remaining lifetime, or `None` if the address never expires
+    pub lifetime: Option<u32>,
+    /// Whether the address is registered with the default router
+    /// (RFC 6775)
+    pub registered: bool,
+}
+
+/// A neighbor on the link, with its remaining lifetime.
+#[derive(Copy, Clone, Debug)]
+pub struct Neighbor {
+    pub ip_addr: IPAddr,
+    pub mac_addr: MacAddress,
+    pub lifetime: u32,
+}
+
+/// A route to the addresses that start with a prefix, through a neighbor,
+/// with its remaining lifetime. A prefix of length 0 is a default route.
+#[derive(Copy, Clone, Debug)]
+pub struct Route {
+    pub prefix: IPAddr,
+    pub prefix_len: u8,
+    /// The link-local address of the neighbor the packets are sent to
+    pub next_hop: IPAddr,
+    pub lifetime: u32,
+}
+
+fn is_all_nodes(addr: &IPAddr) -> bool {
+    *addr == ALL_NODES_ADDR || *addr == ALL_NODES_INTERFACE_LOCAL_ADDR
+}
+
+/// A multicast group joined, with the number of users that joined it.
+#[derive(Copy, Clone, Debug)]
+struct Group {
+    addr: IPAddr,
+    users: usize,
+}
+
+impl Route {
+    fn matches(&self, prefix: &IPAddr, prefix_len: u8) -> bool {
+        self.prefix_len == prefix_len && util::matches_prefix(&self.prefix.0, &prefix.0, prefix_len)
+    }
+}
+
+pub struct IP6Interface {
+    // Kept contiguous: the first `addr_count()` entries are set
+    addrs: [Cell<Option<IP6InterfaceAddr>>; MAX_ADDRS],
+    default_router: Cell<Option<Neighbor>>,
+    neighbors: [Cell<Option<Neighbor>>; NEIGHBOR_CACHE_LEN],
+    routes: [Cell<Option<Route>>; ROUTE_TABLE_LEN],
+    groups: [Cell<Option<Group>>; MAX_GROUPS],
+}
+
+impl IP6Interface {
+    pub fn new() -> IP6Interface {
+        IP6Interface {
+            addrs: Default::default(),
+            default_router: Cell::new(None),
+            neighbors: Default::default(),
+            routes: Default::default(),
+            groups: Default::default(),
+        }
+    }
+
+    /// Assigns an address to the interface, or updates the lifetime of an
+    /// address it already has. Returns ENOMEM if the interface has
+    /// `MAX_ADDRS` addresses.
+    pub fn add_addr(&self, addr: IPAddr, lifetime: Option<u32>) -> ReturnCode {
+        for entry in self.addrs.iter() {
+            match entry.get() {
+                Some(mut current) if current.addr == addr => {
+                    current.lifetime = lifetime;
+                    entry.set(Some(current));
+                    return ReturnCode::SUCCESS;
+                }
+                Some(_) => {}
+                None => {
+                    entry.set(Some(IP6InterfaceAddr {
+                        addr,
+                        lifetime,
+                        registered: false,
+                    }));
+                    return ReturnCode::SUCCESS;
+                }
+            }
+        }
+        ReturnCode::ENOMEM
+    }
+
+    pub fn remove_addr(&self, addr: IPAddr) {
+        let mut removed = false;
+        for i in 0..MAX_ADDRS {
+            if !removed
+                && self.addrs[i]
+                    .get()
+                    .map_or(false, |entry| entry.addr == addr)
+            {
+                removed = true;
+            }
+            if removed {
+                let next = self.addrs.get(i + 1).and_then(|entry| entry.get());
+                self.addrs[i].set(next);
+            }
+        }
+    }
+
+    pub fn has_addr(&self, addr: &IPAddr) -> bool {
+        self.addrs
+            .iter()
+            .any(|entry| entry.get().map_or(false, |entry| entry.addr == *addr))
+    }
+
+    pub fn addr_count(&self) -> usize {
+        self.addrs
+            .iter()
+            .take_while(|entry| entry.get().is_some())
+            .count()
+    }
+
+    pub fn get_addr(&self, index: usize) -> Option<IP6InterfaceAddr> {
+        self.addrs.get(index).and_then(|entry| entry.get())
+    }
+
+    pub fn set_registered(&self, addr: IPAddr, registered: bool) {
+        for entry in self.addrs.iter() {
+            if let Some(mut current) = entry.get() {
+                if current.addr == addr {
+                    current.registered = registered;
+                    entry.set(Some(current));
+                }
+            }
+        }
+    }
+
+    /// Marks all addresses as not registered, e.g. when the default router
+    /// changes.
+    pub fn clear_registrations(&self) {
+        for entry in self.addrs.iter() {
+            entry.set(entry.get().map(|mut current| {
+                current.registered = false;
+                current
+            }));
+        }
+    }
+
+    /// Returns the source address for packets sent to `dst`: a link-local
+    /// address if `dst` is link-local, and an address of a larger scope
+    /// otherwise, falling back to any address.
+    pub fn source_addr(&self, dst: &IPAddr) -> Option<IPAddr> {
+        let link_local = dst.is_link_local_scope();
+        let mut fallback = None;
+        for entry in self.addrs.iter() {
+            if let Some(entry) = entry.get() {
+                if entry.addr.is_unicast_link_local() == link_local {
+                    return Some(entry.addr);
+                }
+                fallback = fallback.or(Some(entry.addr));
+            }
+        }
+        fallback
+    }
+
+    /// Joins the multicast group `addr`, or counts one more user of it if
+    /// it was joined already. Returns EINVAL if `addr` is not a multicast
+    /// address, and ENOMEM if `MAX_GROUPS` groups are joined. The all-nodes
+    /// groups are always joined, and do not count towards `MAX_GROUPS`.
+    pub fn join_group(&self, addr: IPAddr) -> ReturnCode {
+        if !addr.is_multicast() {
+            return ReturnCode::EINVAL;
+        }
+        if is_all_nodes(&addr) {
+            return ReturnCode::SUCCESS;
+        }
+        let slot = self
+            .groups
+            .iter()
+            .find(|entry| entry.get().map_or(false, |group| group.addr == addr))
+            .or_else(|| self.groups.iter().find(|entry| entry.get().is_none()));
+        match slot {
+            Some(slot) => {
+                let users = slot.get().map_or(0, |group| group.users);
+                slot.set(Some(Group {
+                    addr,
+                    users: users + 1,
+                }));
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ENOMEM,
+        }
+    }
+
+    /// Counts one less user of the multicast group `addr`, and leaves it
+    /// once nobody uses it. Returns EINVAL if the group was not joined.
+    pub fn leave_group(&self, addr: IPAddr) -> ReturnCode {
+        if is_all_nodes(&addr) {
+            return ReturnCode::SUCCESS;
+        }
+        for entry in self.groups.iter() {
+            if let Some(mut group) = entry.get() {
+                if group.addr == addr {
+                    group.users -= 1;
+                    entry.set(if group.users == 0 { None } else { Some(group) });
+                    return ReturnCode::SUCCESS;
+                }
+            }
+        }
+        ReturnCode::EINVAL
+    }
+
+    /// Returns whether the interface is a member of the multicast group
+    /// `addr`: all-nodes groups and the solicited-node groups of the
+    /// addresses of the interface are always joined.
+    pub fn is_member(&self, addr: &IPAddr) -> bool {
+        if is_all_nodes(addr) {
+            return true;
+        }
+        if addr.0[..13] == SOLICITED_NODE_PREFIX {
+            let solicited = (0..self.addr_count())
+                .filter_map(|i| self.get_addr(i))
+                .any(|entry| entry.addr.0[13..] == addr.0[13..]);
+            if solicited {
+                return true;
+            }
+        }
+        self.groups
+            .iter()
+            .any(|entry| entry.get().map_or(false, |group| group.addr == *addr))
+    }
+
+    pub fn default_router(&self) -> Option<Neighbor> {
+        self.default_router.get()
+    }
+
+    pub fn set_default_router(&self, router: Option<Neighbor>) {
+        self.default_router.set(router);
+    }
+
+    pub fn lookup_neighbor(&self, addr: &IPAddr) -> Option<MacAddress> {
+        self.neighbors
+            .iter()
+            .filter_map(|entry| entry.get())
+            .find(|neighbor| neighbor.ip_addr == *addr)
+            .map(|neighbor| neighbor.mac_addr)
+    }
+
+    /// Adds a neighbor to the cache, or updates it if the cache already has
+    /// it. When the cache is full, the neighbor with the shortest remaining
+    /// lifetime is evicted.
+    pub fn update_neighbor(&self, neighbor: Neighbor) {
+        let mut slot = &self.neighbors[0];
+        for entry in self.neighbors.iter() {
+            match entry.get() {
+                Some(current) if current.ip_addr == neighbor.ip_addr => {
+                    slot = entry;
+                    break;
+                }
+                Some(current) => {
+                    if slot.get().map_or(false, |s| current.lifetime < s.lifetime) {
+                        slot = entry;
+                    }
+                }
+                None => {
+                    if slot.get().is_some() {
+                        slot = entry;
+                    }
+                }
+            }
+        }
+        slot.set(Some(neighbor));
+    }
+
+    pub fn remove_neighbor(&self, addr: &IPAddr) {
+        for entry in self.neighbors.iter() {
+            if entry
+                .get()
+                .map_or(false, |neighbor| neighbor.ip_addr == *addr)
+            {
+                entry.set(None);
+            }
+        }
+    }
+
+    /// Adds a route to the routing table, or updates the route the table
+    /// already has for the same prefix. Returns ENOMEM if the table is full.
+    pub fn add_route(&self, route: Route) -> ReturnCode {
+        let slot = self
+            .routes
+            .iter()
+            .find(|entry| {
+                entry.get().map_or(false, |current| {
+                    current.matches(&route.prefix, route.prefix_len)
+                })
+            })
+            .or_else(|| self.routes.iter().find(|entry| entry.get().is_none()));
+        match slot {
+            Some(slot) => {
+                slot.set(Some(route));
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ENOMEM,
+        }
+    }
+
+    pub fn remove_route(&self, prefix: &IPAddr, prefix_len: u8) {
+        for entry in self.routes.iter() {
+            if entry
+                .get()
+                .map_or(false, |route| route.matches(prefix, prefix_len))
+            {
+                entry.set(None);
+            }
+        }
+    }
+
+    /// Removes the routes through a neighbor, e.g. when it becomes
+    /// unreachable.
+    pub fn remove_routes_via(&self, next_hop: &IPAddr) {
+        for entry in self.routes.iter() {
+            if entry
+                .get()
+                .map_or(false, |route| route.next_hop == *next_hop)
+            {
+                entry.set(None);
+            }
+        }
+    }
+
+    /// Returns the entry of the routing table at `index`, which may be
+    /// empty.
+    pub fn get_route(&self, index: usize) -> Option<Route> {
+        self.routes.get(index).and_then(|entry| entry.get())
+    }
+
+    /// Returns the route with the longest prefix that `dst` starts with.
+    pub fn lookup_route(&self, dst: &IPAddr) -> Option<Route> {
+        self.routes
+            .iter()
+            .filter_map(|entry| entry.get())
+            .filter(|route| util::matches_prefix(&route.prefix.0, &dst.0, route.prefix_len))
+            .max_by_key(|route| route.prefix_len)
+    }
+
+    fn link_local_next_hop(&self, dst: &IPAddr) -> Option<MacAddress> {
+        self.lookup_neighbor(dst).or_else(|| {
+            if dst.is_unicast_link_local() {
+                Some(dst.mac_from_iid())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Returns the link-layer address of the next hop towards `dst`, if it is
+    /// known: multicast packets are broadcast, neighbors in the cache and
+    /// link-local addresses are reached directly, as the link-layer address
+    /// of the latter is derived from their interface identifier (RFC 6775),
+    /// packets to the destinations of the routing table are sent to the next
+    /// hop of their route, and everything else is sent to the default
+    /// router.
+    pub fn next_hop(&self, dst: &IPAddr) -> Option<MacAddress> {
+        if dst.is_multicast() {
+            return Some(BROADCAST_MAC_ADDR);
+        }
+        self.link_local_next_hop(dst)
+            .or_else(|| {
+                self.lookup_route(dst)
+                    .and_then(|route| self.link_local_next_hop(&route.next_hop))
+            })
+            .or_else(|| self.default_router.get().map(|router| router.mac_addr))
+    }
+
+    /// Returns whether the interface has an address, router, neighbor or
+    /// route with a lifetime, which `tick` has to count down.
+    pub fn has_lifetimes(&self) -> bool {
+        self.addrs
+            .iter()
+            .any(|entry| entry.get().map_or(false, |entry| entry.lifetime.is_some()))
+            || self.default_router.get().is_some()
+            || self.neighbors.iter().any(|entry| entry.get().is_some())
+            || self.routes.iter().any(|entry| entry.get().is_some())
+    }
+
+    /// Lets `seconds` elapse, and removes the addresses, router, neighbors
+    /// and routes whose lifetime ran out.
+    pub fn tick(&self, seconds: u32) {
+        for i in (0..MAX_ADDRS).rev() {
+            if let Some(mut entry) = self.addrs[i].get() {
+                match entry.lifetime {
+                    Some(lifetime) if lifetime <= seconds => self.remove_addr(entry.addr),
+                    Some(lifetime) => {
+                        entry.lifetime = Some(lifetime - seconds);
+                        self.addrs[i].set(Some(entry));
+                    }
+                    None => {}
+                }
+            }
+        }
+        let age = |neighbor: Option<Neighbor>| {
+            neighbor.and_then(|mut neighbor| {
+                if neighbor.lifetime <= seconds {
+                    None
+                } else {
+                    neighbor.lifetime -= seconds;
+                    Some(neighbor)
+                }
+            })
+        };
+        self.default_router.set(age(self.default_router.get()));
+        for entry in self.neighbors.iter() {
+            entry.set(age(entry.get()));
+        }
+        for entry in self.routes.iter() {
+            entry.set(entry.get().and_then(|mut route| {
+                if route.lifetime <= seconds {
+                    None
+                } else {
+                    route.lifetime -= seconds;
+                    Some(route)
+                }
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn group(last: u8) -> IPAddr {
+        let mut addr = IPAddr::new();
+        addr.0[..2].copy_from_slice(&[0xff, 0x03]);
+        addr.0[15] = last;
+        addr
+    }
+
+    fn solicited_node(addr: &IPAddr) -> IPAddr {
+        let mut solicited = IPAddr::new();
+        solicited.0[..13].copy_from_slice(&SOLICITED_NODE_PREFIX);
+        solicited.0[13..].copy_from_slice(&addr.0[13..]);
+        solicited
+    }
+
+    #[test]
+    fn groups_are_counted() {
+        let interface = IP6Interface::new();
+        assert!(!interface.is_member(&group(1)));
+        assert_eq!(interface.join_group(group(1)), ReturnCode::SUCCESS);
+        assert_eq!(interface.join_group(group(1)), ReturnCode::SUCCESS);
+        assert!(interface.is_member(&group(1)));
+
+        // The group is left once both users left it
+        assert_eq!(interface.leave_group(group(1)), ReturnCode::SUCCESS);
+        assert!(interface.is_member(&group(1)));
+        assert_eq!(interface.leave_group(group(1)), ReturnCode::SUCCESS);
+        assert!(!interface.is_member(&group(1)));
+        assert_eq!(interface.leave_group(group(1)), ReturnCode::EINVAL);
+    }
+
+    #[test]
+    fn groups_are_limited() {
+        let interface = IP6Interface::new();
+        let mut unicast = IPAddr::new();
+        unicast.0[..2].copy_from_slice(&[0xfe, 0x80]);
+        assert_eq!(interface.join_group(unicast), ReturnCode::EINVAL);
+
+        for i in 0..MAX_GROUPS as u8 {
+            assert_eq!(interface.join_group(group(i)), ReturnCode::SUCCESS);
+        }
+        assert_eq!(
+            interface.join_group(group(MAX_GROUPS as u8)),
+            ReturnCode::ENOMEM
+        );
+        // Joining a group again takes no room
+        assert_eq!(interface.join_group(group(0)), ReturnCode::SUCCESS);
+        // Neither do the all-nodes groups, which cannot be left
+        assert_eq!(interface.join_group(ALL_NODES_ADDR), ReturnCode::SUCCESS);
+        assert_eq!(interface.leave_group(ALL_NODES_ADDR), ReturnCode::SUCCESS);
+        assert!(interface.is_member(&ALL_NODES_ADDR));
+        assert!(interface.is_member(&ALL_NODES_INTERFACE_LOCAL_ADDR));
+
+        interface.leave_group(group(1));
+        assert_eq!(
+            interface.join_group(group(MAX_GROUPS as u8)),
+            ReturnCode::SUCCESS
+        );
+    }
+
+    #[test]
+    fn solicited_node_groups_follow_addresses() {
+        let interface = IP6Interface::new();
+        let mut addr = IPAddr::new();
+        addr.0[..2].copy_from_slice(&[0xfe, 0x80]);
+        addr.0[13..].copy_from_slice(&[0x12, 0x34, 0x56]);
+        let solicited = solicited_node(&addr);
+        assert!(!interface.is_member(&solicited));
+
+        assert_eq!(interface.add_addr(addr, None), ReturnCode::SUCCESS);
+        assert!(interface.is_member(&solicited));
+        let mut other = solicited;
+        other.0[15] = 0x57;
+        assert!(!interface.is_member(&other));
+
+        interface.remove_addr(addr);
+        assert!(!interface.is_member(&solicited));
+    }
+}
@@ -1,6 +1,11 @@
+use crate::net::icmpv6::icmpv6::{param_problem_code, ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::icmpv6::icmpv6_recv::ICMP6ErrorReporter;
+use crate::net::ipv6::ip_utils::ip6_nh;
 use crate::net::ipv6::ipv6::IP6Header;
+use crate::net::ipv6::ipv6_interface::IP6Interface;
 use crate::net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
 use kernel::common::cells::OptionalCell;
+use kernel::common::{List, ListLink, ListNode};
 use kernel::debug;
 use kernel::ReturnCode;
 
@@ -25,6 +30,17 @@ use kernel::ReturnCode;
   udp_recv, a `UDPReceive` struct.
 - The UDPReceive struct is a field of the UDPDriver, which ultimately passes the
   packets up to userland.
+- When more than one transport protocol is in use, the client of `IP6RecvStruct`
+  is instead an `IP6RecvMux`, which passes each packet to the `IP6RecvMuxClient`
+  registered for its next header (e.g. the UDPReceiver and the TCP stack).
+- The `ICMP6RecvStruct` is the client of the mux for ICMPv6, and its error
+  reporter: it answers the packets the mux and its clients cannot deliver with
+  ICMPv6 error messages.
+- Once `IP6RecvStruct` is given the `IP6Interface`, it only passes its client
+  the packets sent to one of the addresses of the interface or to a multicast
+  group the interface is a member of. The packets for other destinations go to
+  its forward client, an `IP6Forwarder`, if the device is a router, and are
+  dropped otherwise.
 */
 
 pub trait IP6RecvClient {
@@ -36,13 +52,16 @@ pub trait IP6RecvClient {
 /// as we do not multiplex received packets based on the address.
 /// The receiver receives IP packets destined for any local address.
 /// The receiver should drop any packets with destination addresses
-/// that are not among the local addresses of this device.
+/// that are not among the local addresses of this device, or forward them
+/// if the device is a router.
 pub trait IP6Receiver<'a> {
     fn set_client(&self, client: &'a dyn IP6RecvClient);
 }
 
 pub struct IP6RecvStruct<'a> {
     client: OptionalCell<&'a dyn IP6RecvClient>,
+    interface: OptionalCell<&'a IP6Interface>,
+    forward_client: OptionalCell<&'a dyn IP6RecvClient>,
 }
 
 impl<'a> IP6Receiver<'a> for IP6RecvStruct<'a> {
@@ -55,8 +74,34 @@ impl<'a> IP6RecvStruct<'a> {
     pub fn new() -> IP6RecvStruct<'a> {
         IP6RecvStruct {
             client: OptionalCell::empty(),
+            interface: OptionalCell::empty(),
+            forward_client: OptionalCell::empty(),
         }
     }
+
+    /// Sets the interface whose addresses or multicast groups the packets
+    /// passed to the client must be sent to. Without it, all packets are
+    /// passed to the client.
+    pub fn set_interface(&self, interface: &'a IP6Interface) {
+        self.interface.set(interface);
+    }
+
+    /// Sets the client that receives the packets for other destinations,
+    /// usually an `IP6Forwarder`. Without it, they are dropped.
+    pub fn set_forward_client(&self, forward_client: &'a dyn IP6RecvClient) {
+        self.forward_client.set(forward_client);
+    }
+
+    fn is_local(&self, ip6_header: &IP6Header) -> bool {
+        let dst_addr = ip6_header.get_dst_addr();
+        self.interface.map_or(true, |interface| {
+            if dst_addr.is_multicast() {
+                interface.is_member(&dst_addr)
+            } else {
+                interface.has_addr(&dst_addr)
+            }
+        })
+    }
 }
 
 impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
@@ -67,6 +112,12 @@ impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
         }
         match IP6Header::decode(buf).done() {
             Some((offset, ip6_header)) => {
+                if !self.is_local(&ip6_header) {
+                    // The checksum is left for the destination to verify
+                    self.forward_client
+                        .map(|client| client.receive(ip6_header, &buf[offset..len]));
+                    return;
+                }
                 let checksum_result = ip6_header.check_transport_checksum(&buf[offset..len]);
                 if checksum_result == ReturnCode::FAIL {
                     debug!("dropped!: {:?}", checksum_result);
@@ -84,3 +135,77 @@ impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
         }
     }
 }
+
+/// Passes received packets to the client registered for their next header,
+/// so that several transport protocols can share one `IP6Receiver`. Packets
+/// with a next header nobody registered for are dropped, and reported to the
+/// error reporter if there is one. The mux is also an `ICMP6ErrorReporter`
+/// that forwards errors to it, so that its clients can be given their error
+/// reporter before it exists.
+pub struct IP6RecvMux<'a> {
+    clients: List<'a, IP6RecvMuxClient<'a>>,
+    error_reporter: OptionalCell<&'a dyn ICMP6ErrorReporter>,
+}
+
+impl<'a> IP6RecvMux<'a> {
+    pub fn new() -> IP6RecvMux<'a> {
+        IP6RecvMux {
+            clients: List::new(),
+            error_reporter: OptionalCell::empty(),
+        }
+    }
+
+    pub fn add_client(&self, client: &'a IP6RecvMuxClient<'a>) {
+        self.clients.push_tail(client);
+    }
+
+    pub fn set_error_reporter(&self, error_reporter: &'a dyn ICMP6ErrorReporter) {
+        self.error_reporter.set(error_reporter);
+    }
+}
+
+impl<'a> IP6RecvClient for IP6RecvMux<'a> {
+    fn receive(&self, header: IP6Header, payload: &[u8]) {
+        let next_header = header.get_next_header();
+        if let Some(mux_client) = self.clients.iter().find(|c| c.next_header == next_header) {
+            mux_client.client.receive(header, payload);
+        } else if next_header != ip6_nh::NO_NEXT {
+            // Points at the next header field of the IPv6 header
+            let mut icmp_header = ICMP6Header::new(ICMP6Type::Type4);
+            icmp_header.set_code(param_problem_code::UNRECOGNIZED_NEXT_HEADER);
+            icmp_header.set_options(ICMP6HeaderOptions::Type4 { pointer: 6 });
+            self.report_error(icmp_header, &header, payload);
+        }
+    }
+}
+
+impl<'a> ICMP6ErrorReporter for IP6RecvMux<'a> {
+    fn report_error(&self, icmp_header: ICMP6Header, ip6_header: &IP6Header, payload: &[u8]) {
+        self.error_reporter
+            .map(|reporter| reporter.report_error(icmp_header, ip6_header, payload));
+    }
+}
+
+/// The registration of an `IP6RecvClient` with an `IP6RecvMux`.
+pub struct IP6RecvMuxClient<'a> {
+    next_header: u8,
+    client: &'a dyn IP6RecvClient,
+    next: ListLink<'a, IP6RecvMuxClient<'a>>,
+}
+
+impl<'a> IP6RecvMuxClient<'a> {
+    /// `next_header` is one of the `ip6_nh` values.
+    pub fn new(next_header: u8, client: &'a dyn IP6RecvClient) -> IP6RecvMuxClient<'a> {
+        IP6RecvMuxClient {
+            next_header,
+            client,
+            next: ListLink::empty(),
+        }
+    }
+}
+
+impl<'a> ListNode<'a, IP6RecvMuxClient<'a>> for IP6RecvMuxClient<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, IP6RecvMuxClient<'a>> {
+        &self.next
+    }
+}
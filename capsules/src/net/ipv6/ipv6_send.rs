@@ -5,7 +5,11 @@
 //! when a transmission has completed.
 //!
 //! This file also includes an implementation of the `IP6Sender` trait, which
-//! sends an IPv6 packet using 6LoWPAN.
+//! sends an IPv6 packet using 6LoWPAN. If it is given an `IP6Interface`, it
+//! picks the source address and the next hop of each packet with it, and
+//! falls back to the source address and gateway set with `set_addr` and
+//! `set_gateway` otherwise. Besides the packets it builds, it can send the
+//! packets of other nodes that a router forwards, which keep their header.
 
 // Additional Work and Known Problems
 // ----------------------------------
@@ -20,6 +24,7 @@ use crate::ieee802154::device::{MacDevice, TxClient};
 use crate::net::ieee802154::MacAddress;
 use crate::net::ipv6::ip_utils::IPAddr;
 use crate::net::ipv6::ipv6::{IP6Header, IP6Packet, TransportHeader};
+use crate::net::ipv6::ipv6_interface::{IP6Interface, BROADCAST_MAC_ADDR};
 use crate::net::sixlowpan::sixlowpan_state::TxState;
 use core::cell::Cell;
 use kernel::common::cells::{OptionalCell, TakeCell};
@@ -34,6 +39,15 @@ pub trait IP6SendClient {
     fn send_done(&self, result: ReturnCode);
 }
 
+/// This trait is implemented by the layers that monitor the links to the
+/// neighbors, e.g. to estimate their quality. The client must then call
+/// `IP6SendStruct.set_link_client` to receive the `frame_sent` callback.
+pub trait IP6LinkClient {
+    /// Called after each frame sent to a single neighbor, with whether the
+    /// neighbor acknowledged it.
+    fn frame_sent(&self, dst_mac_addr: MacAddress, acked: bool);
+}
+
 /// This trait provides a basic IPv6 sending interface. It exposes basic
 /// configuration information for the IPv6 layer (setting the source address,
 /// setting the gateway MAC address), as well as a way to send an IPv6
@@ -78,6 +92,39 @@ pub trait IP6Sender<'a> {
     /// `payload` - The transport payload for the packet being sent
     fn send_to(&self, dst: IPAddr, transport_header: TransportHeader, payload: &[u8])
         -> ReturnCode;
+
+    /// This method is like `send_to`, but sends the packet from the given
+    /// source address instead of picking one, e.g. for the messages of
+    /// Neighbor Discovery that are about a specific address of this device
+    ///
+    /// # Arguments
+    /// `src` - IPv6 address to send the packet from
+    /// `dst` - IPv6 address to send the packet to
+    /// `transport_header` - The `TransportHeader` for the packet being sent
+    /// `payload` - The transport payload for the packet being sent
+    fn send_from(
+        &self,
+        src: IPAddr,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &[u8],
+    ) -> ReturnCode;
+
+    /// This method sends a packet that already has its IPv6 header, e.g. a
+    /// packet of another node that is forwarded, to the next hop towards
+    /// its destination. Unlike `send_to`, it keeps the source address, hop
+    /// limit and transport checksum of the packet as they are.
+    ///
+    /// # Arguments
+    /// `ip6_header` - The `IP6Header` of the packet being sent
+    /// `transport_header` - The `TransportHeader` for the packet being sent
+    /// `payload` - The transport payload for the packet being sent
+    fn forward(
+        &self,
+        ip6_header: IP6Header,
+        transport_header: TransportHeader,
+        payload: &[u8],
+    ) -> ReturnCode;
 }
 
 /// This struct is a specific implementation of the `IP6Sender` trait. This
@@ -93,9 +140,11 @@ pub struct IP6SendStruct<'a, A: time::Alarm<'a>> {
     tx_buf: TakeCell<'static, [u8]>,
     sixlowpan: TxState<'a>,
     radio: &'a dyn MacDevice<'a>,
-    dst_mac_addr: MacAddress,
     src_mac_addr: MacAddress,
+    dst_mac_addr: Cell<MacAddress>,
+    interface: OptionalCell<&'a IP6Interface>,
     client: OptionalCell<&'a dyn IP6SendClient>,
+    link_client: OptionalCell<&'a dyn IP6LinkClient>,
 }
 
 impl<A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
@@ -122,15 +171,43 @@ impl<A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         transport_header: TransportHeader,
         payload: &[u8],
     ) -> ReturnCode {
-        self.sixlowpan.init(
-            self.src_mac_addr,
-            self.dst_mac_addr,
-            self.radio.get_pan(),
-            None,
-        );
-        self.init_packet(dst, transport_header, payload);
-        let ret = self.send_next_fragment();
-        ret
+        let src = self
+            .interface
+            .and_then(|interface| interface.source_addr(&dst))
+            .unwrap_or_else(|| self.src_addr.get());
+        self.send_from(src, dst, transport_header, payload)
+    }
+
+    fn send_from(
+        &self,
+        src: IPAddr,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &[u8],
+    ) -> ReturnCode {
+        self.init_link(&dst);
+        self.init_packet(src, dst, transport_header, payload);
+        self.send_next_fragment()
+    }
+
+    fn forward(
+        &self,
+        ip6_header: IP6Header,
+        transport_header: TransportHeader,
+        payload: &[u8],
+    ) -> ReturnCode {
+        let fits = self.ip6_packet.map_or(false, |ip6_packet| {
+            payload.len() <= ip6_packet.payload.payload.len()
+        });
+        if !fits {
+            return ReturnCode::ESIZE;
+        }
+        self.init_link(&ip6_header.get_dst_addr());
+        self.ip6_packet.map(|ip6_packet| {
+            ip6_packet.header = ip6_header;
+            ip6_packet.set_payload(transport_header, payload);
+        });
+        self.send_next_fragment()
     }
 }
 
@@ -152,16 +229,48 @@ impl<A: time::Alarm<'a>> IP6SendStruct<'a, A> {
             tx_buf: TakeCell::new(tx_buf),
             sixlowpan: sixlowpan,
             radio: radio,
-            dst_mac_addr: dst_mac_addr,
             src_mac_addr: src_mac_addr,
+            dst_mac_addr: Cell::new(dst_mac_addr),
+            interface: OptionalCell::empty(),
             client: OptionalCell::empty(),
+            link_client: OptionalCell::empty(),
         }
     }
 
-    fn init_packet(&self, dst_addr: IPAddr, transport_header: TransportHeader, payload: &[u8]) {
+    /// Sets the interface that provides the source address and the next hop
+    /// of the packets sent.
+    pub fn set_interface(&self, interface: &'a IP6Interface) {
+        self.interface.set(interface);
+    }
+
+    /// Sets the client that is told whether the frames sent to a neighbor
+    /// were acknowledged.
+    pub fn set_link_client(&self, link_client: &'a dyn IP6LinkClient) {
+        self.link_client.set(link_client);
+    }
+
+    // Picks the next hop towards `dst`, and prepares the fragmentation of the
+    // packet sent to it
+    fn init_link(&self, dst: &IPAddr) {
+        let dst_mac_addr = self
+            .interface
+            .and_then(|interface| interface.next_hop(dst))
+            .unwrap_or_else(|| self.gateway.get());
+        self.dst_mac_addr.set(dst_mac_addr);
+        self.sixlowpan
+            .init(self.src_mac_addr, dst_mac_addr, self.radio.get_pan(), None);
+    }
+
+    fn init_packet(
+        &self,
+        src_addr: IPAddr,
+        dst_addr: IPAddr,
+        transport_header: TransportHeader,
+        payload: &[u8],
+    ) {
         self.ip6_packet.map(|ip6_packet| {
             ip6_packet.header = IP6Header::default();
-            ip6_packet.header.src_addr = self.src_addr.get();
+            ip6_packet.header.src_addr = src_addr;
             ip6_packet.header.dst_addr = dst_addr;
             ip6_packet.set_payload(transport_header, payload);
             ip6_packet.set_transport_checksum();
@@ -230,10 +339,15 @@ impl<A: time::Alarm<'a>> time::AlarmClient for IP6SendStruct<'a, A> {
 }
 
 impl<A: time::Alarm<'a>> TxClient for IP6SendStruct<'a, A> {
-    fn send_done(&self, tx_buf: &'static mut [u8], _acked: bool, result: ReturnCode) {
+    fn send_done(&self, tx_buf: &'static mut [u8], acked: bool, result: ReturnCode) {
         self.tx_buf.replace(tx_buf);
         if result != ReturnCode::SUCCESS && result != ReturnCode::ENOACK {
             //debug!("Send Failed: {:?}, acked: {}", result, acked);
+        } else if self.dst_mac_addr.get() != BROADCAST_MAC_ADDR {
+            // Broadcast frames are never acknowledged
+            let dst_mac_addr = self.dst_mac_addr.get();
+            self.link_client
+                .map(|link_client| link_client.frame_sent(dst_mac_addr, acked));
         }
         // Below code adds delay between fragments. Despite some efforts
         // to fix this bug, I find that without it the receiving imix cannot
@@ -0,0 +1,452 @@
+//! Stand-ins for the alarm, the random number generator, the AES engine, the
+//! nonvolatile storage and the IPv6, ICMPv6 and UDP senders below the
+//! network capsules, for their tests.
+
+extern crate std;
+
+use crate::net::icmpv6::icmpv6::ICMP6Header;
+use crate::net::icmpv6::icmpv6_send::{ICMP6SendClient, ICMP6Sender};
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6::{IP6Header, TransportHeader};
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::udp::udp::UDPHeader;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+use core::cell::{Cell, RefCell};
+use kernel::common::cells::TakeCell;
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::hil::rng::{self, Rng};
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM};
+use kernel::hil::time::{self, Freq1KHz};
+use kernel::ReturnCode;
+use std::boxed::Box;
+use std::vec;
+use std::vec::Vec;
+
+/// Returns a zeroed buffer of `len` bytes that lives for the rest of the
+/// test, for the capsules that keep their buffers.
+pub fn buf(len: usize) -> &'static mut [u8] {
+    Box::leak(vec![0; len].into_boxed_slice())
+}
+
+/// An alarm that counts milliseconds and only moves when told to.
+pub struct MockAlarm {
+    now: Cell<u32>,
+    alarm: Cell<Option<u32>>,
+}
+
+impl MockAlarm {
+    pub fn new() -> MockAlarm {
+        MockAlarm {
+            now: Cell::new(0),
+            alarm: Cell::new(None),
+        }
+    }
+
+    /// Moves the time forward by `ms`, and returns whether that passed the
+    /// alarm, in which case the alarm is disabled and the test has to call
+    /// its client.
+    pub fn advance(&self, ms: u32) -> bool {
+        let now = self.now.get().wrapping_add(ms);
+        self.now.set(now);
+        match self.alarm.get() {
+            Some(alarm) if (now.wrapping_sub(alarm) as i32) >= 0 => {
+                self.alarm.set(None);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl time::Time for MockAlarm {
+    type Frequency = Freq1KHz;
+
+    fn now(&self) -> u32 {
+        self.now.get()
+    }
+
+    fn max_tics(&self) -> u32 {
+        core::u32::MAX
+    }
+}
+
+impl<'a> time::Alarm<'a> for MockAlarm {
+    fn set_alarm(&self, tics: u32) {
+        self.alarm.set(Some(tics));
+    }
+
+    fn get_alarm(&self) -> u32 {
+        self.alarm.get().unwrap_or(0)
+    }
+
+    fn set_client(&'a self, _client: &'a dyn time::AlarmClient) {}
+
+    fn is_enabled(&self) -> bool {
+        self.alarm.get().is_some()
+    }
+
+    fn disable(&self) {
+        self.alarm.set(None);
+    }
+}
+
+/// A random number generator that counts the requests for randomness: the
+/// test calls `randomness_available` of the client with the numbers it
+/// wants.
+pub struct MockRng {
+    pub requests: Cell<usize>,
+}
+
+impl MockRng {
+    pub fn new() -> MockRng {
+        MockRng {
+            requests: Cell::new(0),
+        }
+    }
+}
+
+impl<'a> Rng<'a> for MockRng {
+    fn get(&self) -> ReturnCode {
+        self.requests.set(self.requests.get() + 1);
+        ReturnCode::SUCCESS
+    }
+
+    fn cancel(&self) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn set_client(&'a self, _client: &'a dyn rng::Client) {}
+}
+
+/// An AES-CCM engine that leaves the messages as they are. It keeps the
+/// buffer of the operation in progress: the test takes it and calls
+/// `crypt_done` of the client.
+pub struct MockAes {
+    pub buf: TakeCell<'static, [u8]>,
+    pub encrypting: Cell<bool>,
+    pub enabled: Cell<bool>,
+}
+
+impl MockAes {
+    pub fn new() -> MockAes {
+        MockAes {
+            buf: TakeCell::empty(),
+            encrypting: Cell::new(false),
+            enabled: Cell::new(false),
+        }
+    }
+}
+
+impl<'a> AES128CCM<'a> for MockAes {
+    fn enable(&'a self) {
+        self.enabled.set(true);
+    }
+
+    fn disable(&'a self) {
+        self.enabled.set(false);
+    }
+
+    fn set_client(&'a self, _client: &'a dyn CCMClient) {}
+
+    fn set_key(&self, _key: &[u8]) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn set_nonce(&self, _nonce: &[u8]) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        _a_off: usize,
+        _m_off: usize,
+        _m_len: usize,
+        _mic_len: usize,
+        _confidential: bool,
+        encrypting: bool,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.buf.is_some() {
+            return (ReturnCode::EBUSY, Some(buf));
+        }
+        self.buf.replace(buf);
+        self.encrypting.set(encrypting);
+        (ReturnCode::SUCCESS, None)
+    }
+}
+
+/// Nonvolatile storage held in memory. Reads and writes that start fail
+/// with `result` if it is not SUCCESS; the others wait for the test to
+/// `finish` them and hand the buffer back to the client.
+pub struct MockStorage {
+    pub data: RefCell<Vec<u8>>,
+    pub result: Cell<ReturnCode>,
+    // Whether the operation in progress writes, and its address and length
+    op: Cell<Option<(bool, usize, usize)>>,
+    buf: TakeCell<'static, [u8]>,
+}
+
+impl MockStorage {
+    pub fn new(len: usize) -> MockStorage {
+        MockStorage {
+            data: RefCell::new(vec![0; len]),
+            result: Cell::new(ReturnCode::SUCCESS),
+            op: Cell::new(None),
+            buf: TakeCell::empty(),
+        }
+    }
+
+    /// Carries out the read or write in progress, and returns its buffer
+    /// and whether it was a write.
+    pub fn finish(&self) -> Option<(&'static mut [u8], bool)> {
+        let (writing, address, length) = self.op.take()?;
+        let buf = self.buf.take()?;
+        let mut data = self.data.borrow_mut();
+        if writing {
+            data[address..address + length].copy_from_slice(&buf[..length]);
+        } else {
+            buf[..length].copy_from_slice(&data[address..address + length]);
+        }
+        Some((buf, writing))
+    }
+
+    fn start(
+        &self,
+        buffer: &'static mut [u8],
+        writing: bool,
+        address: usize,
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.buf.is_some() {
+            return (ReturnCode::EBUSY, Some(buffer));
+        }
+        if self.result.get() != ReturnCode::SUCCESS {
+            return (self.result.get(), Some(buffer));
+        }
+        self.op.set(Some((writing, address, length)));
+        self.buf.replace(buffer);
+        (ReturnCode::SUCCESS, None)
+    }
+}
+
+impl NonvolatileStorage<'static> for MockStorage {
+    fn set_client(&self, _client: &'static dyn NonvolatileStorageClient<'static>) {}
+
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.start(buffer, false, address, length)
+    }
+
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.start(buffer, true, address, length)
+    }
+}
+
+/// A packet given to `MockSender`.
+pub struct Sent {
+    pub src: Option<IPAddr>,
+    pub dst: IPAddr,
+    /// The hop limit of forwarded packets
+    pub hop_limit: Option<u8>,
+    pub header: TransportHeader,
+    pub payload: Vec<u8>,
+}
+
+/// An `IP6Sender` that keeps the packets, which the test then takes. It
+/// sends at once: the test calls `send_done` of the client if it wants.
+pub struct MockSender {
+    pub sent: RefCell<Vec<Sent>>,
+    pub result: Cell<ReturnCode>,
+}
+
+impl MockSender {
+    pub fn new() -> MockSender {
+        MockSender {
+            sent: RefCell::new(Vec::new()),
+            result: Cell::new(ReturnCode::SUCCESS),
+        }
+    }
+
+    /// Takes the packets sent so far.
+    pub fn take(&self) -> Vec<Sent> {
+        self.sent.replace(Vec::new())
+    }
+
+    fn push(&self, sent: Sent) -> ReturnCode {
+        self.sent.borrow_mut().push(sent);
+        self.result.get()
+    }
+}
+
+impl<'a> IP6Sender<'a> for MockSender {
+    fn set_client(&self, _client: &'a dyn IP6SendClient) {}
+
+    fn set_addr(&self, _src_addr: IPAddr) {}
+
+    fn set_gateway(&self, _gateway: MacAddress) {}
+
+    fn set_header(&mut self, _ip6_header: IP6Header) {}
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &[u8],
+    ) -> ReturnCode {
+        self.push(Sent {
+            src: None,
+            dst,
+            hop_limit: None,
+            header: transport_header,
+            payload: payload.to_vec(),
+        })
+    }
+
+    fn send_from(
+        &self,
+        src: IPAddr,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &[u8],
+    ) -> ReturnCode {
+        self.push(Sent {
+            src: Some(src),
+            dst,
+            hop_limit: None,
+            header: transport_header,
+            payload: payload.to_vec(),
+        })
+    }
+
+    fn forward(
+        &self,
+        ip6_header: IP6Header,
+        transport_header: TransportHeader,
+        payload: &[u8],
+    ) -> ReturnCode {
+        self.push(Sent {
+            src: Some(ip6_header.get_src_addr()),
+            dst: ip6_header.get_dst_addr(),
+            hop_limit: Some(ip6_header.get_hop_limit()),
+            header: transport_header,
+            payload: payload.to_vec(),
+        })
+    }
+}
+
+/// A message given to `MockIcmpSender`.
+pub struct IcmpSent {
+    pub src: Option<IPAddr>,
+    pub dst: IPAddr,
+    pub header: ICMP6Header,
+    pub payload: Vec<u8>,
+}
+
+/// An `ICMP6Sender` that keeps the messages, which the test then takes. It
+/// is never busy.
+pub struct MockIcmpSender {
+    pub sent: RefCell<Vec<IcmpSent>>,
+}
+
+impl MockIcmpSender {
+    pub fn new() -> MockIcmpSender {
+        MockIcmpSender {
+            sent: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Takes the messages sent so far.
+    pub fn take(&self) -> Vec<IcmpSent> {
+        self.sent.replace(Vec::new())
+    }
+}
+
+impl<'a> ICMP6Sender<'a> for MockIcmpSender {
+    fn set_client(&self, _client: &'a dyn ICMP6SendClient) {}
+
+    fn send(&self, dest: IPAddr, icmp_header: ICMP6Header, buf: &[u8]) -> ReturnCode {
+        self.sent.borrow_mut().push(IcmpSent {
+            src: None,
+            dst: dest,
+            header: icmp_header,
+            payload: buf.to_vec(),
+        });
+        ReturnCode::SUCCESS
+    }
+
+    fn send_from(
+        &self,
+        src: IPAddr,
+        dest: IPAddr,
+        icmp_header: ICMP6Header,
+        buf: &[u8],
+    ) -> ReturnCode {
+        self.sent.borrow_mut().push(IcmpSent {
+            src: Some(src),
+            dst: dest,
+            header: icmp_header,
+            payload: buf.to_vec(),
+        });
+        ReturnCode::SUCCESS
+    }
+}
+
+/// A datagram given to `MockUdpSender`.
+pub struct UdpSent {
+    pub dst: IPAddr,
+    pub dst_port: u16,
+    pub src_port: u16,
+    pub payload: Vec<u8>,
+}
+
+/// A `UDPSender` that keeps the datagrams, which the test then takes. It
+/// sends at once: the test calls `send_done` of the client if it wants.
+pub struct MockUdpSender {
+    pub sent: RefCell<Vec<UdpSent>>,
+}
+
+impl MockUdpSender {
+    pub fn new() -> MockUdpSender {
+        MockUdpSender {
+            sent: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Takes the datagrams sent so far.
+    pub fn take(&self) -> Vec<UdpSent> {
+        self.sent.replace(Vec::new())
+    }
+}
+
+impl<'a> UDPSender<'a> for MockUdpSender {
+    fn set_client(&self, _client: &'a dyn UDPSendClient) {}
+
+    fn send_to(&self, dest: IPAddr, dst_port: u16, src_port: u16, buf: &[u8]) -> ReturnCode {
+        self.sent.borrow_mut().push(UdpSent {
+            dst: dest,
+            dst_port,
+            src_port,
+            payload: buf.to_vec(),
+        });
+        ReturnCode::SUCCESS
+    }
+
+    fn send(&self, dest: IPAddr, udp_header: UDPHeader, buf: &[u8]) -> ReturnCode {
+        self.send_to(
+            dest,
+            udp_header.get_dst_port(),
+            udp_header.get_src_port(),
+            buf,
+        )
+    }
+}
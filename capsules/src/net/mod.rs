@@ -1,13 +1,17 @@
 //! Modules for IPv6 over 6LoWPAN stack
 
 pub mod frag_utils;
+#[cfg(test)]
+pub(crate) mod mock;
 pub mod sixlowpan;
 pub mod util;
 #[macro_use]
 pub mod stream;
+pub mod coap;
 pub mod icmpv6;
 pub mod ieee802154;
 pub mod ipv6;
+pub mod rpl;
 pub mod tcp;
 pub mod thread;
 pub mod udp;
@@ -0,0 +1,1375 @@
+//! This file implements RPL, the IPv6 Routing Protocol for Low-Power and
+//! Lossy Networks (RFC 6550), in storing mode. The nodes of a mesh organize
+//! themselves into a Destination-Oriented DAG (DODAG) rooted at a border
+//! router, and `RPLNode` fills in the routing table of an
+//! [IP6Interface](../../ipv6/ipv6_interface/struct.IP6Interface.html) with
+//! the routes through it, which the `IP6SendStruct` consults to pick the next
+//! hop of each packet:
+//!
+//! - The root advertises the DODAG in DODAG Information Objects (DIOs), with
+//!   its parameters and a prefix from which the nodes configure a global
+//!   address. Every node that joins the DODAG advertises it in turn, with
+//!   its rank, the distance to the root computed by the objective function of
+//!   the DODAG. DIOs are sent to all RPL nodes on a Trickle timer (RFC 6206),
+//!   often while the DODAG changes and rarely once it is stable. Nodes that
+//!   are not part of a DODAG solicit DIOs with DODAG Information
+//!   Solicitations (DIS).
+//! - Each node selects as its preferred parent the neighbor through which its
+//!   rank is lowest, and routes everything it has no better route for
+//!   through it. Two objective functions are supported: OF0 (RFC 6552), which
+//!   counts hops, and MRHOF (RFC 6719), which adds up the expected number of
+//!   transmissions (ETX) of the links, as estimated from the acknowledgements
+//!   of the frames sent to the neighbors.
+//! - Each node advertises its global addresses, and the addresses that can be
+//!   reached through it, to its preferred parent in Destination Advertisement
+//!   Objects (DAOs). The parent installs routes to them through the node, and
+//!   advertises them to its own parent, so that the root and the nodes on
+//!   the way can reach every node of the DODAG. DAOs are acknowledged, and
+//!   a parent that does not acknowledge them is replaced.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use capsules::net::rpl::rpl::RPLNode;
+//! # use kernel::static_init;
+//! let rpl = static_init!(
+//!     RPLNode<'static, VirtualMuxAlarm<'static, sam4l::ast::Ast>>,
+//!     RPLNode::new(icmp_send, interface, rpl_alarm, eui64)
+//! );
+//! rpl_alarm.set_client(rpl);
+//! icmp_recv.set_rpl_client(rpl);
+//! // Estimates the ETX of links from the frames the sender sends
+//! ip_send.set_link_client(rpl);
+//! // Joins the DODAGs advertised by neighbors
+//! rpl.start();
+//! // Or, on the border router, creates a DODAG for a prefix
+//! rpl.start_root(prefix, 64, objective_code_point::MRHOF);
+//! ```
+
+// Known Limitations
+// -----------------
+// - Only storing mode is supported: DODAGs in non-storing mode, which
+//   requires source routing headers, are not joined.
+// - A node is part of a single DODAG of a single RPL instance at a time, and
+//   DIOs for other DODAGs are ignored, even if they are better.
+// - Messages are sent through an `ICMP6Sender` shared with other users: a
+//   message that cannot be sent because the sender is busy is dropped, and
+//   sent again when its timer expires.
+// - The targets of the DAOs of a node are its global addresses and the
+//   destinations of its routes, up to the number that fits in a message.
+//   Routes that are removed with a No-Path DAO expire at the nodes further
+//   up the DODAG instead of being removed, and so do the routes through a
+//   node at its previous parent when it changes parents, as it does not send
+//   No-Path DAOs itself.
+// - The RPL option of data packets (RFC 6553), with which nodes detect
+//   loops, is not sent.
+// - The routes expire when the owner of the interface calls `tick`, usually
+//   Neighbor Discovery.
+
+use crate::net::icmpv6::icmpv6::{ICMP6Header, ICMP6Type};
+use crate::net::icmpv6::icmpv6_recv::ICMP6RecvClient;
+use crate::net::icmpv6::icmpv6_send::ICMP6Sender;
+use crate::net::icmpv6::nd_options::prefix_flags;
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6::IP6Header;
+use crate::net::ipv6::ipv6_interface::{IP6Interface, Route, ROUTE_TABLE_LEN};
+use crate::net::ipv6::ipv6_send::IP6LinkClient;
+use crate::net::rpl::rpl_control::{mode_of_operation, objective_code_point, rpl_code};
+use crate::net::rpl::rpl_control::{DAOAck, DODAGConfig, PrefixInfo, DAO, DIO, DIS_LEN};
+use crate::net::rpl::rpl_control::{RPLOption, RPLOptions, INFINITE_RANK};
+use core::cell::Cell;
+use core::cmp::min;
+use kernel::hil::time::{self, Frequency};
+use kernel::ReturnCode;
+
+/// The all-RPL-nodes link-local multicast address, ff02::1a.
+pub const ALL_RPL_NODES_ADDR: IPAddr =
+    IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x1a]);
+
+/// Number of neighbors considered as parents.
+pub const MAX_CANDIDATES: usize = 4;
+
+/// The parameters of the DODAGs created by `start_root`, which are also used
+/// in DODAGs whose DIOs do not carry a DODAG Configuration option.
+pub const DEFAULT_CONFIG: DODAGConfig = DODAGConfig {
+    dio_interval_doublings: 8,
+    dio_interval_min: 12,
+    dio_redundancy: 10,
+    max_rank_increase: 7 * 256,
+    min_hop_rank_increase: 256,
+    ocp: objective_code_point::OF0,
+    default_lifetime: 30,
+    lifetime_unit: 60,
+};
+
+const INSTANCE_ID: u8 = 0;
+// Versions are lollipop counters that start at 240 (RFC 6550)
+const INITIAL_VERSION: u8 = 240;
+
+// Timers, in milliseconds
+const TICK_MS: u32 = 100;
+const DIS_INTERVAL_MS: u32 = 10_000;
+// DAOs are delayed to advertise several changes at once
+const DAO_DELAY_MS: u32 = 1_000;
+const DAO_ACK_TIMEOUT_MS: u32 = 2_000;
+const MAX_DAO_TRANSMISSIONS: u8 = 3;
+
+// Statuses of DAO-ACKs
+const DAO_ACK_ACCEPTED: u8 = 0;
+const DAO_ACK_TABLE_FULL: u8 = 128;
+
+// ETX is counted in units of 1/128 transmission (RFC 6719). Unacknowledged
+// frames count as this many transmissions.
+const ETX_DIVISOR: u32 = 128;
+const INITIAL_ETX: u16 = 2 * 128;
+const NO_ACK_ETX: u16 = 8 * 128;
+const MAX_LINK_METRIC: u16 = 4 * 128;
+const PARENT_SWITCH_THRESHOLD: u16 = 192;
+
+// The rank increase of OF0, in units of MinHopRankIncrease
+const DEFAULT_STEP_OF_RANK: u16 = 3;
+
+// A lifetime of 0xff units is infinite
+const INFINITE_LIFETIME: u8 = 0xff;
+
+// Large enough for a DIO with a DODAG Configuration and a Prefix Information
+// option, or a DAO with five targets.
+const SEND_BUF_LEN: usize = 128;
+
+/// A neighbor that advertised the DODAG, with its rank and the ETX of the
+/// link to it.
+#[derive(Copy, Clone, Debug)]
+struct Candidate {
+    addr: IPAddr,
+    rank: u16,
+    etx: u16,
+    dtsn: u8,
+}
+
+/// The DODAG the node is part of.
+#[derive(Copy, Clone, Debug)]
+struct DODAG {
+    /// The DIO the node advertises, with its own rank
+    dio: DIO,
+    config: DODAGConfig,
+    prefix: Option<PrefixInfo>,
+    /// The lowest rank the node advertised in this version of the DODAG,
+    /// which bounds its rank
+    lowest_rank: u16,
+}
+
+pub struct RPLNode<'a, A: time::Alarm<'a>> {
+    icmp_sender: &'a dyn ICMP6Sender<'a>,
+    interface: &'a IP6Interface,
+    alarm: &'a A,
+    eui64: [u8; 8],
+
+    root: Cell<bool>,
+    dodag: Cell<Option<DODAG>>,
+    candidates: [Cell<Option<Candidate>>; MAX_CANDIDATES],
+    parent: Cell<Option<IPAddr>>,
+
+    // Trickle timer of DIOs: the current interval, the time elapsed in it,
+    // the time in it at which a DIO is sent, and the number of consistent
+    // DIOs received in it
+    dio_interval: Cell<u32>,
+    dio_elapsed: Cell<u32>,
+    dio_send_time: Cell<Option<u32>>,
+    dio_counter: Cell<u8>,
+
+    dis_timer: Cell<Option<u32>>,
+
+    // DAOs: the sequence number of the last one, whether it is waiting for
+    // an acknowledgement, how many times it was sent, and the time until it
+    // is sent again or refreshed
+    dao_sequence: Cell<u8>,
+    dao_pending: Cell<bool>,
+    dao_count: Cell<u8>,
+    dao_timer: Cell<Option<u32>>,
+
+    // State of the generator of the random times of the Trickle timer
+    random: Cell<u32>,
+}
+
+impl<A: time::Alarm<'a>> RPLNode<'a, A> {
+    /// `eui64` is the extended address of the device, from which the
+    /// interface identifier of its addresses is derived.
+    pub fn new(
+        icmp_sender: &'a dyn ICMP6Sender<'a>,
+        interface: &'a IP6Interface,
+        alarm: &'a A,
+        eui64: [u8; 8],
+    ) -> RPLNode<'a, A> {
+        let seed = eui64
+            .iter()
+            .fold(0x2545_f491, |seed: u32, b| seed.rotate_left(5) ^ *b as u32);
+        RPLNode {
+            icmp_sender,
+            interface,
+            alarm,
+            eui64,
+            root: Cell::new(false),
+            dodag: Cell::new(None),
+            candidates: Default::default(),
+            parent: Cell::new(None),
+            dio_interval: Cell::new(0),
+            dio_elapsed: Cell::new(0),
+            dio_send_time: Cell::new(None),
+            dio_counter: Cell::new(0),
+            dis_timer: Cell::new(None),
+            dao_sequence: Cell::new(INITIAL_VERSION),
+            dao_pending: Cell::new(false),
+            dao_count: Cell::new(0),
+            dao_timer: Cell::new(None),
+            random: Cell::new(seed),
+        }
+    }
+
+    /// Configures the link-local address of the interface, joins the
+    /// all-RPL-nodes group and starts soliciting DIOs, to join the first
+    /// DODAG advertised.
+    pub fn start(&self) {
+        self.join_link();
+        self.dis_timer.set(Some(TICK_MS));
+        self.set_tick();
+    }
+
+    /// Creates a grounded DODAG in storing mode, rooted at this node, in
+    /// which nodes form their global addresses from `prefix`. The DODAG ID,
+    /// the address of the root, is formed from the prefix as well.
+    ///
+    /// # Arguments
+    ///
+    /// `prefix` - The prefix of the DODAG, whose length must be 64 bits for
+    /// nodes to form addresses from it
+    /// `prefix_len` - The length of the prefix, in bits
+    /// `ocp` - The objective function of the DODAG, one of
+    /// `objective_code_point`
+    pub fn start_root(&self, prefix: IPAddr, prefix_len: u8, ocp: u16) {
+        self.join_link();
+        let mut dodag_id = prefix;
+        dodag_id.set_iid_from_mac(MacAddress::Long(self.eui64));
+        self.interface.add_addr(dodag_id, None);
+
+        let config = DODAGConfig {
+            ocp,
+            ..DEFAULT_CONFIG
+        };
+        let rank = config.min_hop_rank_increase;
+        self.root.set(true);
+        self.dis_timer.set(None);
+        self.dodag.set(Some(DODAG {
+            dio: DIO {
+                instance_id: INSTANCE_ID,
+                version: INITIAL_VERSION,
+                rank,
+                grounded: true,
+                mop: mode_of_operation::STORING,
+                preference: 0,
+                dtsn: INITIAL_VERSION,
+                dodag_id,
+            },
+            config,
+            prefix: Some(PrefixInfo {
+                prefix_len,
+                flags: prefix_flags::AUTONOMOUS,
+                valid_lifetime: 0xffff_ffff,
+                preferred_lifetime: 0xffff_ffff,
+                prefix,
+            }),
+            lowest_rank: rank,
+        }));
+        self.reset_trickle();
+        self.set_tick();
+    }
+
+    /// Makes the nodes rebuild the DODAG from scratch, by advertising a new
+    /// version of it. Only the root can do this.
+    pub fn global_repair(&self) {
+        if !self.root.get() {
+            return;
+        }
+        if let Some(mut dodag) = self.dodag.get() {
+            dodag.dio.version = dodag.dio.version.wrapping_add(1);
+            self.dodag.set(Some(dodag));
+            self.reset_trickle();
+        }
+    }
+
+    /// Returns the rank of the node, which is infinite if it is not part of
+    /// a DODAG.
+    pub fn get_rank(&self) -> u16 {
+        self.dodag
+            .get()
+            .map_or(INFINITE_RANK, |dodag| dodag.dio.rank)
+    }
+
+    /// Returns the link-local address of the preferred parent.
+    pub fn get_parent(&self) -> Option<IPAddr> {
+        self.parent.get()
+    }
+
+    /// Configures the link-local address of the interface, and joins the
+    /// all-RPL-nodes group that DIOs and DISes are multicast to.
+    fn join_link(&self) {
+        let link_local = IPAddr::generate_from_mac(MacAddress::Long(self.eui64));
+        self.interface.add_addr(link_local, None);
+        self.interface.join_group(ALL_RPL_NODES_ADDR);
+    }
+
+    fn set_tick(&self) {
+        let tics = TICK_MS * <A::Frequency>::frequency() / 1000;
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(tics));
+    }
+
+    /// Returns a pseudo-random number below `max`.
+    fn random_below(&self, max: u32) -> u32 {
+        // xorshift32
+        let mut x = self.random.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random.set(x);
+        if max == 0 {
+            0
+        } else {
+            x % max
+        }
+    }
+
+    /// Counts down a timer, and returns whether it expired.
+    fn expired(timer: &Cell<Option<u32>>) -> bool {
+        match timer.get() {
+            Some(remaining) if remaining <= TICK_MS => {
+                timer.set(None);
+                true
+            }
+            Some(remaining) => {
+                timer.set(Some(remaining - TICK_MS));
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn route_lifetime(config: &DODAGConfig, lifetime: u8) -> u32 {
+        if lifetime == INFINITE_LIFETIME {
+            u32::max_value()
+        } else {
+            lifetime as u32 * config.lifetime_unit as u32
+        }
+    }
+
+    // Trickle timer
+
+    fn reset_trickle(&self) {
+        if let Some(dodag) = self.dodag.get() {
+            let interval_min = 1u32 << min(dodag.config.dio_interval_min, 24);
+            self.dio_interval.set(interval_min);
+            self.start_trickle_interval();
+        }
+    }
+
+    fn start_trickle_interval(&self) {
+        let interval = self.dio_interval.get();
+        self.dio_elapsed.set(0);
+        self.dio_counter.set(0);
+        self.dio_send_time
+            .set(Some(interval / 2 + self.random_below(interval / 2)));
+    }
+
+    fn trickle_tick(&self, dodag: &DODAG) {
+        self.dio_elapsed.set(self.dio_elapsed.get() + TICK_MS);
+        if self
+            .dio_send_time
+            .get()
+            .map_or(false, |time| time <= self.dio_elapsed.get())
+        {
+            self.dio_send_time.set(None);
+            // A redundancy constant of zero disables suppression
+            let redundancy = dodag.config.dio_redundancy;
+            if redundancy == 0 || self.dio_counter.get() < redundancy {
+                self.send_dio(ALL_RPL_NODES_ADDR);
+            }
+        }
+        if self.dio_elapsed.get() >= self.dio_interval.get() {
+            let exponent =
+                dodag.config.dio_interval_min as u32 + dodag.config.dio_interval_doublings as u32;
+            let interval_max = 1u32 << min(exponent, 24);
+            self.dio_interval
+                .set(min(self.dio_interval.get() * 2, interval_max));
+            self.start_trickle_interval();
+        }
+    }
+
+    // Parent selection
+
+    fn find_candidate(&self, addr: &IPAddr) -> Option<Candidate> {
+        self.candidates
+            .iter()
+            .filter_map(|entry| entry.get())
+            .find(|candidate| candidate.addr == *addr)
+    }
+
+    /// Adds a candidate, or updates it if it is known. When the table is
+    /// full, the candidate with the highest rank that is not the preferred
+    /// parent is replaced, if its rank is higher than the new one.
+    fn update_candidate(&self, candidate: Candidate) {
+        let parent = self.parent.get();
+        let slot = self
+            .candidates
+            .iter()
+            .find(|entry| {
+                entry
+                    .get()
+                    .map_or(false, |current| current.addr == candidate.addr)
+            })
+            .or_else(|| self.candidates.iter().find(|entry| entry.get().is_none()))
+            .or_else(|| {
+                self.candidates
+                    .iter()
+                    .filter(|entry| {
+                        entry.get().map_or(false, |current| {
+                            Some(current.addr) != parent && current.rank > candidate.rank
+                        })
+                    })
+                    .max_by_key(|entry| entry.get().map_or(0, |current| current.rank))
+            });
+        if let Some(slot) = slot {
+            slot.set(Some(candidate));
+        }
+    }
+
+    fn remove_candidate(&self, addr: &IPAddr) {
+        for entry in self.candidates.iter() {
+            if entry
+                .get()
+                .map_or(false, |candidate| candidate.addr == *addr)
+            {
+                entry.set(None);
+            }
+        }
+    }
+
+    /// Returns the rank of the node if it selected `candidate` as its
+    /// parent, computed with the objective function of the DODAG.
+    fn rank_via(config: &DODAGConfig, candidate: &Candidate) -> u16 {
+        let min_increase = config.min_hop_rank_increase as u32;
+        let increase = match config.ocp {
+            objective_code_point::MRHOF => {
+                let increase = candidate.etx as u32 * min_increase / ETX_DIVISOR;
+                if increase < min_increase {
+                    min_increase
+                } else {
+                    increase
+                }
+            }
+            _ => DEFAULT_STEP_OF_RANK as u32 * min_increase,
+        };
+        min(candidate.rank as u32 + increase, INFINITE_RANK as u32) as u16
+    }
+
+    /// Selects the preferred parent among the candidates, and leaves the
+    /// DODAG if there is none. The current parent is kept unless another
+    /// candidate is better by more than a threshold, so that the parent does
+    /// not change for small variations of the metric.
+    fn select_parent(&self) {
+        let mut dodag = match self.dodag.get() {
+            Some(dodag) if !self.root.get() => dodag,
+            _ => return,
+        };
+        let config = dodag.config;
+        let max_rank = if config.max_rank_increase == 0 {
+            INFINITE_RANK
+        } else {
+            dodag.lowest_rank.saturating_add(config.max_rank_increase)
+        };
+        let acceptable = |candidate: &Candidate| {
+            candidate.rank != INFINITE_RANK
+                && (config.ocp != objective_code_point::MRHOF || candidate.etx <= MAX_LINK_METRIC)
+                && Self::rank_via(&config, candidate) < INFINITE_RANK
+                && Self::rank_via(&config, candidate) <= max_rank
+        };
+        let best = self
+            .candidates
+            .iter()
+            .filter_map(|entry| entry.get())
+            .filter(&acceptable)
+            .min_by_key(|candidate| Self::rank_via(&config, candidate));
+        let current = self
+            .parent
+            .get()
+            .and_then(|addr| self.find_candidate(&addr))
+            .filter(&acceptable);
+        let threshold = match config.ocp {
+            objective_code_point::MRHOF => PARENT_SWITCH_THRESHOLD,
+            _ => config.min_hop_rank_increase,
+        };
+        let parent = match (current, best) {
+            (Some(current), Some(best))
+                if Self::rank_via(&config, &best).saturating_add(threshold)
+                    > Self::rank_via(&config, &current) =>
+            {
+                Some(current)
+            }
+            (_, best) => best,
+        };
+
+        let parent = match parent {
+            Some(parent) => parent,
+            None => {
+                self.leave();
+                return;
+            }
+        };
+        dodag.dio.rank = Self::rank_via(&config, &parent);
+        dodag.lowest_rank = min(dodag.lowest_rank, dodag.dio.rank);
+        self.dodag.set(Some(dodag));
+        if self.parent.get() != Some(parent.addr) {
+            self.parent.set(Some(parent.addr));
+            self.add_default_route(parent.addr, &config);
+            self.reset_trickle();
+            self.schedule_dao();
+        }
+    }
+
+    fn add_default_route(&self, parent: IPAddr, config: &DODAGConfig) {
+        self.interface.add_route(Route {
+            prefix: IPAddr::new(),
+            prefix_len: 0,
+            next_hop: parent,
+            lifetime: Self::route_lifetime(config, config.default_lifetime),
+        });
+    }
+
+    /// Leaves the DODAG, after telling the children that the node cannot be
+    /// their parent anymore, and starts soliciting DIOs again.
+    fn leave(&self) {
+        if let Some(mut dodag) = self.dodag.get() {
+            if dodag.dio.rank != INFINITE_RANK {
+                dodag.dio.rank = INFINITE_RANK;
+                self.dodag.set(Some(dodag));
+                self.send_dio(ALL_RPL_NODES_ADDR);
+            }
+        }
+        if let Some(parent) = self.parent.get() {
+            self.interface.remove_routes_via(&parent);
+        }
+        self.dodag.set(None);
+        self.parent.set(None);
+        for entry in self.candidates.iter() {
+            entry.set(None);
+        }
+        self.dio_send_time.set(None);
+        self.dao_timer.set(None);
+        self.dao_pending.set(false);
+        self.dis_timer.set(Some(DIS_INTERVAL_MS));
+    }
+
+    /// Configures a global address from a 64 bit prefix that allows
+    /// autonomous configuration, as Neighbor Discovery does.
+    fn configure_prefix(&self, info: &PrefixInfo) {
+        if info.flags & prefix_flags::AUTONOMOUS == 0
+            || info.prefix_len != 64
+            || info.prefix.is_unicast_link_local()
+            || info.preferred_lifetime > info.valid_lifetime
+        {
+            return;
+        }
+        let mut addr = info.prefix;
+        addr.set_iid_from_mac(MacAddress::Long(self.eui64));
+        match info.valid_lifetime {
+            0 => self.interface.remove_addr(addr),
+            0xffff_ffff => {
+                self.interface.add_addr(addr, None);
+            }
+            lifetime => {
+                self.interface.add_addr(addr, Some(lifetime));
+            }
+        }
+    }
+
+    // DAOs
+
+    /// Sends a new DAO to the preferred parent after a short delay, e.g.
+    /// because the parent or the routes through the node changed.
+    fn schedule_dao(&self) {
+        let storing = self
+            .dodag
+            .get()
+            .map_or(false, |dodag| dodag.dio.mop == mode_of_operation::STORING);
+        if !storing || self.root.get() {
+            return;
+        }
+        if !self.dao_pending.get() && self.dao_timer.get().map_or(false, |t| t <= DAO_DELAY_MS) {
+            // A new DAO is already about to be sent
+            return;
+        }
+        self.dao_pending.set(false);
+        self.dao_timer.set(Some(DAO_DELAY_MS));
+    }
+
+    fn send_dao(&self) {
+        let (dodag, parent) = match (self.dodag.get(), self.parent.get()) {
+            (Some(dodag), Some(parent)) => (dodag, parent),
+            _ => return,
+        };
+        let dao = DAO {
+            instance_id: dodag.dio.instance_id,
+            ack_requested: true,
+            sequence: self.dao_sequence.get(),
+            dodag_id: Some(dodag.dio.dodag_id),
+        };
+        let transit = RPLOption::TransitInfo {
+            path_sequence: self.dao_sequence.get(),
+            path_lifetime: dodag.config.default_lifetime,
+        };
+        let mut buf = [0; SEND_BUF_LEN];
+        let mut off = match dao.encode(&mut buf, 0).done() {
+            Some((off, _)) => off,
+            None => return,
+        };
+        // The global addresses of the node, and the destinations of the
+        // routes through it
+        let addrs = (0..self.interface.addr_count())
+            .filter_map(|i| self.interface.get_addr(i))
+            .filter(|entry| !entry.addr.is_unicast_link_local())
+            .map(|entry| (entry.addr, 128));
+        let routes = (0..ROUTE_TABLE_LEN)
+            .filter_map(|i| self.interface.get_route(i))
+            .filter(|route| route.prefix_len > 0 && route.next_hop != parent)
+            .map(|route| (route.prefix, route.prefix_len));
+        let mut targets = 0;
+        for (prefix, prefix_len) in addrs.chain(routes) {
+            let target = RPLOption::Target { prefix_len, prefix };
+            if off + target.get_size() + transit.get_size() > buf.len() {
+                break;
+            }
+            if let Some((next, _)) = target.encode(&mut buf, off).done() {
+                off = next;
+                targets += 1;
+            }
+        }
+        if targets == 0 {
+            // Nothing to advertise, e.g. before a global address is formed
+            self.dao_timer.set(Some(DIS_INTERVAL_MS));
+            return;
+        }
+        let len = match transit.encode(&mut buf, off).done() {
+            Some((len, _)) => len,
+            None => return,
+        };
+        let mut icmp_header = ICMP6Header::new(ICMP6Type::Type155);
+        icmp_header.set_code(rpl_code::DAO);
+        self.icmp_sender.send(parent, icmp_header, &buf[..len]);
+
+        self.dao_pending.set(true);
+        self.dao_count.set(self.dao_count.get() + 1);
+        self.dao_timer.set(Some(DAO_ACK_TIMEOUT_MS));
+    }
+
+    fn send_dao_ack(&self, dst: IPAddr, dodag: &DODAG, sequence: u8, status: u8) {
+        let dao_ack = DAOAck {
+            instance_id: dodag.dio.instance_id,
+            sequence,
+            status,
+            dodag_id: Some(dodag.dio.dodag_id),
+        };
+        let mut buf = [0; SEND_BUF_LEN];
+        if let Some((len, _)) = dao_ack.encode(&mut buf, 0).done() {
+            let mut icmp_header = ICMP6Header::new(ICMP6Type::Type155);
+            icmp_header.set_code(rpl_code::DAO_ACK);
+            self.icmp_sender.send(dst, icmp_header, &buf[..len]);
+        }
+    }
+
+    /// Replaces the preferred parent, which did not acknowledge DAOs or
+    /// refused them.
+    fn parent_unreachable(&self) {
+        if let Some(parent) = self.parent.get() {
+            self.remove_candidate(&parent);
+            self.interface.remove_routes_via(&parent);
+            self.parent.set(None);
+        }
+        self.dao_pending.set(false);
+        self.dao_timer.set(None);
+        self.select_parent();
+    }
+
+    // Sending
+
+    fn send_dis(&self) {
+        let buf = [0; DIS_LEN];
+        let mut icmp_header = ICMP6Header::new(ICMP6Type::Type155);
+        icmp_header.set_code(rpl_code::DIS);
+        self.icmp_sender.send(ALL_RPL_NODES_ADDR, icmp_header, &buf);
+    }
+
+    fn send_dio(&self, dst: IPAddr) {
+        let dodag = match self.dodag.get() {
+            Some(dodag) => dodag,
+            None => return,
+        };
+        let mut buf = [0; SEND_BUF_LEN];
+        let len = dodag
+            .dio
+            .encode(&mut buf, 0)
+            .done()
+            .and_then(|(off, _)| {
+                RPLOption::DODAGConfig(dodag.config)
+                    .encode(&mut buf, off)
+                    .done()
+            })
+            .and_then(|(off, _)| match dodag.prefix {
+                Some(info) => RPLOption::PrefixInfo(info).encode(&mut buf, off).done(),
+                None => Some((off, off)),
+            });
+        if let Some((len, _)) = len {
+            let mut icmp_header = ICMP6Header::new(ICMP6Type::Type155);
+            icmp_header.set_code(rpl_code::DIO);
+            self.icmp_sender.send(dst, icmp_header, &buf[..len]);
+        }
+    }
+
+    // Receiving
+
+    fn receive_dis(&self, ip6_header: &IP6Header) {
+        if self.dodag.get().is_none() {
+            return;
+        }
+        if ip6_header.get_dst_addr().is_multicast() {
+            // Advertise the DODAG to the new node soon
+            self.reset_trickle();
+        } else {
+            self.send_dio(ip6_header.get_src_addr());
+        }
+    }
+
+    fn receive_dio(&self, src_addr: IPAddr, body: &[u8]) {
+        let (off, dio) = match DIO::decode(body).done() {
+            Some(result) => result,
+            None => return,
+        };
+        let options = &body[off..];
+        if !src_addr.is_unicast_link_local() || !RPLOptions::is_valid(options) {
+            return;
+        }
+        let mut config = None;
+        let mut prefix = None;
+        for option in RPLOptions::new(options) {
+            match option {
+                RPLOption::DODAGConfig(c) => config = Some(c),
+                RPLOption::PrefixInfo(info) => prefix = Some(info),
+                _ => {}
+            }
+        }
+
+        let mut dodag = match self.dodag.get() {
+            Some(dodag) => dodag,
+            None => {
+                let config = config.unwrap_or(DEFAULT_CONFIG);
+                let supported_mop = dio.mop == mode_of_operation::NO_DOWNWARD_ROUTES
+                    || dio.mop == mode_of_operation::STORING;
+                let supported_ocp = config.ocp == objective_code_point::OF0
+                    || config.ocp == objective_code_point::MRHOF;
+                if self.root.get()
+                    || !supported_mop
+                    || !supported_ocp
+                    || dio.rank == INFINITE_RANK
+                    || config.min_hop_rank_increase == 0
+                {
+                    return;
+                }
+                // Join the DODAG
+                self.dis_timer.set(None);
+                DODAG {
+                    dio: DIO {
+                        rank: INFINITE_RANK,
+                        ..dio
+                    },
+                    config,
+                    prefix,
+                    lowest_rank: INFINITE_RANK,
+                }
+            }
+        };
+        if dio.instance_id != dodag.dio.instance_id || dio.dodag_id != dodag.dio.dodag_id {
+            return;
+        }
+
+        // Versions are compared as sequence numbers
+        let version_diff = dio.version.wrapping_sub(dodag.dio.version) as i8;
+        if self.root.get() {
+            if version_diff != 0 {
+                // Spread the current version
+                self.reset_trickle();
+            } else if dio.rank != INFINITE_RANK {
+                self.dio_counter
+                    .set(self.dio_counter.get().saturating_add(1));
+            }
+            return;
+        }
+        if version_diff < 0 {
+            self.reset_trickle();
+            return;
+        }
+        if version_diff > 0 {
+            // Global repair: rebuild the routes in the new version
+            if let Some(parent) = self.parent.get() {
+                self.interface.remove_routes_via(&parent);
+            }
+            self.parent.set(None);
+            for entry in self.candidates.iter() {
+                entry.set(None);
+            }
+            dodag.dio.version = dio.version;
+            dodag.dio.rank = INFINITE_RANK;
+            dodag.lowest_rank = INFINITE_RANK;
+            self.reset_trickle();
+        } else if dio.rank != INFINITE_RANK {
+            self.dio_counter
+                .set(self.dio_counter.get().saturating_add(1));
+        }
+        if let Some(config) = config {
+            dodag.config = config;
+        }
+        if let Some(info) = prefix {
+            self.configure_prefix(&info);
+            dodag.prefix = Some(info);
+        }
+        self.dodag.set(Some(dodag));
+
+        if dio.rank == INFINITE_RANK {
+            // The neighbor left the DODAG
+            self.remove_candidate(&src_addr);
+        } else {
+            let previous = self.find_candidate(&src_addr);
+            self.update_candidate(Candidate {
+                addr: src_addr,
+                rank: dio.rank,
+                etx: previous.map_or(INITIAL_ETX, |candidate| candidate.etx),
+                dtsn: dio.dtsn,
+            });
+            if self.parent.get() == Some(src_addr) {
+                self.add_default_route(src_addr, &dodag.config);
+                if previous.map_or(false, |candidate| candidate.dtsn != dio.dtsn) {
+                    // The parent asks for new DAOs, and so do we
+                    dodag.dio.dtsn = dodag.dio.dtsn.wrapping_add(1);
+                    self.dodag.set(Some(dodag));
+                    self.schedule_dao();
+                }
+            }
+        }
+        self.select_parent();
+    }
+
+    fn receive_dao(&self, src_addr: IPAddr, body: &[u8]) {
+        let dodag = match self.dodag.get() {
+            Some(dodag) if dodag.dio.mop == mode_of_operation::STORING => dodag,
+            _ => return,
+        };
+        let (off, dao) = match DAO::decode(body).done() {
+            Some(result) => result,
+            None => return,
+        };
+        let options = &body[off..];
+        if dao.instance_id != dodag.dio.instance_id
+            || dao
+                .dodag_id
+                .map_or(false, |dodag_id| dodag_id != dodag.dio.dodag_id)
+            || !src_addr.is_unicast_link_local()
+            || !RPLOptions::is_valid(options)
+            // Routes through the parent would form a loop
+            || self.parent.get() == Some(src_addr)
+        {
+            return;
+        }
+        let path_lifetime = RPLOptions::new(options).find_map(|option| match option {
+            RPLOption::TransitInfo { path_lifetime, .. } => Some(path_lifetime),
+            _ => None,
+        });
+        let path_lifetime = match path_lifetime {
+            Some(path_lifetime) => path_lifetime,
+            None => return,
+        };
+
+        let mut status = DAO_ACK_ACCEPTED;
+        let mut new_routes = false;
+        for option in RPLOptions::new(options) {
+            if let RPLOption::Target { prefix_len, prefix } = option {
+                let current = self
+                    .interface
+                    .lookup_route(&prefix)
+                    .filter(|route| route.prefix_len == prefix_len);
+                if path_lifetime == 0 {
+                    // No-Path: the target cannot be reached through the
+                    // sender anymore
+                    if current.map_or(false, |route| route.next_hop == src_addr) {
+                        self.interface.remove_route(&prefix, prefix_len);
+                    }
+                    continue;
+                }
+                let route = Route {
+                    prefix,
+                    prefix_len,
+                    next_hop: src_addr,
+                    lifetime: Self::route_lifetime(&dodag.config, path_lifetime),
+                };
+                if self.interface.add_route(route) != ReturnCode::SUCCESS {
+                    status = DAO_ACK_TABLE_FULL;
+                } else if current.map_or(true, |route| route.next_hop != src_addr) {
+                    new_routes = true;
+                }
+            }
+        }
+        if dao.ack_requested {
+            self.send_dao_ack(src_addr, &dodag, dao.sequence, status);
+        }
+        if new_routes {
+            // Advertise the new destinations up the DODAG
+            self.schedule_dao();
+        }
+    }
+
+    fn receive_dao_ack(&self, src_addr: IPAddr, body: &[u8]) {
+        let dao_ack = match DAOAck::decode(body).done() {
+            Some((_, dao_ack)) => dao_ack,
+            None => return,
+        };
+        let dodag = match self.dodag.get() {
+            Some(dodag) => dodag,
+            None => return,
+        };
+        if !self.dao_pending.get()
+            || self.parent.get() != Some(src_addr)
+            || dao_ack.instance_id != dodag.dio.instance_id
+            || dao_ack.sequence != self.dao_sequence.get()
+        {
+            return;
+        }
+        self.dao_pending.set(false);
+        if dao_ack.status < DAO_ACK_TABLE_FULL {
+            // Refresh the routes halfway through their lifetime
+            let lifetime_s = Self::route_lifetime(&dodag.config, dodag.config.default_lifetime);
+            self.dao_timer
+                .set(Some((lifetime_s / 2).saturating_mul(1000)));
+        } else {
+            self.parent_unreachable();
+        }
+    }
+}
+
+impl<A: time::Alarm<'a>> ICMP6RecvClient for RPLNode<'a, A> {
+    fn receive(&self, ip6_header: &IP6Header, icmp_header: ICMP6Header, payload: &[u8]) {
+        let src_addr = ip6_header.get_src_addr();
+        match icmp_header.get_code() {
+            rpl_code::DIS => self.receive_dis(ip6_header),
+            rpl_code::DIO => self.receive_dio(src_addr, payload),
+            rpl_code::DAO => self.receive_dao(src_addr, payload),
+            rpl_code::DAO_ACK => self.receive_dao_ack(src_addr, payload),
+            // Secure messages are not supported
+            _ => {}
+        }
+    }
+}
+
+impl<A: time::Alarm<'a>> IP6LinkClient for RPLNode<'a, A> {
+    fn frame_sent(&self, dst_mac_addr: MacAddress, acked: bool) {
+        let sample = if acked {
+            ETX_DIVISOR as u16
+        } else {
+            NO_ACK_ETX
+        };
+        let mut updated = false;
+        for entry in self.candidates.iter() {
+            if let Some(mut candidate) = entry.get() {
+                if self.interface.next_hop(&candidate.addr) == Some(dst_mac_addr) {
+                    // Exponentially weighted moving average
+                    candidate.etx = ((candidate.etx as u32 * 7 + sample as u32) / 8) as u16;
+                    entry.set(Some(candidate));
+                    updated = true;
+                }
+            }
+        }
+        let mrhof = self.dodag.get().map_or(false, |dodag| {
+            dodag.config.ocp == objective_code_point::MRHOF
+        });
+        if updated && mrhof {
+            self.select_parent();
+        }
+    }
+}
+
+impl<A: time::Alarm<'a>> time::AlarmClient for RPLNode<'a, A> {
+    fn fired(&self) {
+        if let Some(dodag) = self.dodag.get() {
+            self.trickle_tick(&dodag);
+        }
+        if Self::expired(&self.dis_timer) {
+            self.send_dis();
+            self.dis_timer.set(Some(DIS_INTERVAL_MS));
+        }
+        if Self::expired(&self.dao_timer) {
+            if !self.dao_pending.get() {
+                // A new DAO, or a refresh of the routes
+                self.dao_sequence
+                    .set(self.dao_sequence.get().wrapping_add(1));
+                self.dao_count.set(0);
+                self.send_dao();
+            } else if self.dao_count.get() < MAX_DAO_TRANSMISSIONS {
+                self.send_dao();
+            } else {
+                self.parent_unreachable();
+            }
+        }
+        self.set_tick();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use crate::net::mock::{MockAlarm, MockIcmpSender};
+    use kernel::hil::time::AlarmClient;
+    use std::vec::Vec;
+
+    const EUI64: [u8; 8] = [0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77];
+
+    struct Mocks {
+        sender: MockIcmpSender,
+        interface: IP6Interface,
+        alarm: MockAlarm,
+    }
+
+    type TestNode<'a> = RPLNode<'a, MockAlarm>;
+
+    impl Mocks {
+        fn new() -> Mocks {
+            Mocks {
+                sender: MockIcmpSender::new(),
+                interface: IP6Interface::new(),
+                alarm: MockAlarm::new(),
+            }
+        }
+
+        fn node(&self) -> TestNode {
+            RPLNode::new(&self.sender, &self.interface, &self.alarm, EUI64)
+        }
+
+        /// Lets the alarm fire until `ms` passed.
+        fn wait(&self, node: &TestNode, ms: u32) {
+            for _ in 0..ms / TICK_MS {
+                assert!(self.alarm.advance(TICK_MS));
+                node.fired();
+            }
+        }
+
+        /// Takes the RPL messages sent, with their destinations and codes.
+        fn sent(&self) -> Vec<(IPAddr, u8, Vec<u8>)> {
+            self.sender
+                .take()
+                .into_iter()
+                .map(|sent| (sent.dst, sent.header.get_code(), sent.payload))
+                .collect()
+        }
+    }
+
+    fn neighbor(short_addr: u16) -> IPAddr {
+        IPAddr::generate_from_mac(MacAddress::Short(short_addr))
+    }
+
+    fn prefix() -> IPAddr {
+        let mut prefix = IPAddr::new();
+        prefix.0[..8].copy_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 1, 0, 0]);
+        prefix
+    }
+
+    fn global_addr(short_addr: u16) -> IPAddr {
+        let mut addr = prefix();
+        addr.set_iid_from_mac(MacAddress::Short(short_addr));
+        addr
+    }
+
+    fn receive(node: &TestNode, src: IPAddr, dst: IPAddr, code: u8, body: &[u8]) {
+        let mut ip6_header = IP6Header::new();
+        ip6_header.src_addr = src;
+        ip6_header.dst_addr = dst;
+        let mut icmp_header = ICMP6Header::new(ICMP6Type::Type155);
+        icmp_header.set_code(code);
+        ICMP6RecvClient::receive(node, &ip6_header, icmp_header, body);
+    }
+
+    /// Delivers a DIO of the DODAG rooted at node 1 from a neighbor.
+    fn receive_dio(node: &TestNode, src: IPAddr, rank: u16, config: DODAGConfig) {
+        let dio = DIO {
+            instance_id: INSTANCE_ID,
+            version: INITIAL_VERSION,
+            rank,
+            grounded: true,
+            mop: mode_of_operation::STORING,
+            preference: 0,
+            dtsn: INITIAL_VERSION,
+            dodag_id: global_addr(1),
+        };
+        let mut body = [0; SEND_BUF_LEN];
+        let (off, _) = dio.encode(&mut body, 0).done().unwrap();
+        let (len, _) = RPLOption::DODAGConfig(config)
+            .encode(&mut body, off)
+            .done()
+            .unwrap();
+        receive(node, src, ALL_RPL_NODES_ADDR, rpl_code::DIO, &body[..len]);
+    }
+
+    /// Returns a DAO for a target, from a node in the DODAG.
+    fn dao_body(target: IPAddr, lifetime: u8) -> Vec<u8> {
+        let dao = DAO {
+            instance_id: INSTANCE_ID,
+            ack_requested: true,
+            sequence: 7,
+            dodag_id: None,
+        };
+        let options = [
+            RPLOption::Target {
+                prefix_len: 128,
+                prefix: target,
+            },
+            RPLOption::TransitInfo {
+                path_sequence: 7,
+                path_lifetime: lifetime,
+            },
+        ];
+        let mut body = [0; SEND_BUF_LEN];
+        let (mut off, _) = dao.encode(&mut body, 0).done().unwrap();
+        for option in options.iter() {
+            off = option.encode(&mut body, off).done().unwrap().0;
+        }
+        body[..off].to_vec()
+    }
+
+    /// Delivers a DAO for a target, and returns the status of the DAO-ACK.
+    fn receive_dao(
+        node: &TestNode,
+        mocks: &Mocks,
+        src: IPAddr,
+        target: IPAddr,
+        lifetime: u8,
+    ) -> u8 {
+        let own_addr = IPAddr::generate_from_mac(MacAddress::Long(EUI64));
+        receive(
+            node,
+            src,
+            own_addr,
+            rpl_code::DAO,
+            &dao_body(target, lifetime),
+        );
+
+        let mut sent = mocks.sent();
+        assert_eq!(sent.len(), 1);
+        let (dst, code, body) = sent.remove(0);
+        assert_eq!(dst, src);
+        assert_eq!(code, rpl_code::DAO_ACK);
+        let (_, dao_ack) = DAOAck::decode(&body).done().unwrap();
+        assert_eq!(dao_ack.sequence, 7);
+        dao_ack.status
+    }
+
+    fn default_route(interface: &IP6Interface) -> Option<IPAddr> {
+        interface
+            .lookup_route(&global_addr(0x99))
+            .filter(|route| route.prefix_len == 0)
+            .map(|route| route.next_hop)
+    }
+
+    fn candidate(rank: u16, etx: u16) -> Candidate {
+        Candidate {
+            addr: neighbor(2),
+            rank,
+            etx,
+            dtsn: 0,
+        }
+    }
+
+    #[test]
+    fn rank_via_follows_objective_function() {
+        let of0 = DEFAULT_CONFIG;
+        let mrhof = DODAGConfig {
+            ocp: objective_code_point::MRHOF,
+            ..DEFAULT_CONFIG
+        };
+        // OF0 counts hops, whatever the link
+        assert_eq!(
+            TestNode::rank_via(&of0, &candidate(256, 128)),
+            256 + 3 * 256
+        );
+        assert_eq!(
+            TestNode::rank_via(&of0, &candidate(256, 512)),
+            256 + 3 * 256
+        );
+        // MRHOF adds the ETX of the link, but at least MinHopRankIncrease
+        assert_eq!(TestNode::rank_via(&mrhof, &candidate(256, 384)), 256 + 768);
+        assert_eq!(TestNode::rank_via(&mrhof, &candidate(256, 64)), 256 + 256);
+        // Ranks stop at infinity
+        assert_eq!(
+            TestNode::rank_via(&of0, &candidate(INFINITE_RANK - 1, 128)),
+            INFINITE_RANK
+        );
+    }
+
+    #[test]
+    fn selects_parent_with_lowest_rank() {
+        let mocks = Mocks::new();
+        let node = mocks.node();
+        node.start();
+
+        receive_dio(&node, neighbor(2), 512, DEFAULT_CONFIG);
+        assert_eq!(node.get_parent(), Some(neighbor(2)));
+        assert_eq!(node.get_rank(), 512 + 768);
+        assert_eq!(default_route(&mocks.interface), Some(neighbor(2)));
+
+        // Not better by MinHopRankIncrease, so the parent is kept
+        receive_dio(&node, neighbor(3), 300, DEFAULT_CONFIG);
+        assert_eq!(node.get_parent(), Some(neighbor(2)));
+        assert_eq!(node.get_rank(), 512 + 768);
+
+        receive_dio(&node, neighbor(4), 256, DEFAULT_CONFIG);
+        assert_eq!(node.get_parent(), Some(neighbor(4)));
+        assert_eq!(node.get_rank(), 256 + 768);
+        assert_eq!(default_route(&mocks.interface), Some(neighbor(4)));
+    }
+
+    #[test]
+    fn mrhof_keeps_parent_within_threshold() {
+        let mocks = Mocks::new();
+        let node = mocks.node();
+        let mrhof = DODAGConfig {
+            ocp: objective_code_point::MRHOF,
+            ..DEFAULT_CONFIG
+        };
+        node.start();
+
+        receive_dio(&node, neighbor(2), 512, mrhof);
+        assert_eq!(node.get_parent(), Some(neighbor(2)));
+        assert_eq!(node.get_rank(), 512 + 512);
+
+        // Better, but by less than the threshold
+        receive_dio(&node, neighbor(3), 512 - 128, mrhof);
+        assert_eq!(node.get_parent(), Some(neighbor(2)));
+
+        receive_dio(&node, neighbor(3), 512 - 256, mrhof);
+        assert_eq!(node.get_parent(), Some(neighbor(3)));
+        assert_eq!(node.get_rank(), 256 + 512);
+    }
+
+    #[test]
+    fn leaves_when_no_parent_is_left() {
+        let mocks = Mocks::new();
+        let node = mocks.node();
+        node.start();
+        receive_dio(&node, neighbor(2), 512, DEFAULT_CONFIG);
+        assert_eq!(node.get_parent(), Some(neighbor(2)));
+        assert!(mocks.sent().is_empty());
+
+        // The parent leaves the DODAG
+        receive_dio(&node, neighbor(2), INFINITE_RANK, DEFAULT_CONFIG);
+        assert_eq!(node.get_parent(), None);
+        assert_eq!(node.get_rank(), INFINITE_RANK);
+        assert_eq!(default_route(&mocks.interface), None);
+
+        // The children are told that the node left
+        let mut sent = mocks.sent();
+        assert_eq!(sent.len(), 1);
+        let (dst, code, body) = sent.remove(0);
+        assert_eq!(dst, ALL_RPL_NODES_ADDR);
+        assert_eq!(code, rpl_code::DIO);
+        let (_, dio) = DIO::decode(&body).done().unwrap();
+        assert_eq!(dio.rank, INFINITE_RANK);
+
+        // And DIOs are solicited again
+        mocks.wait(&node, DIS_INTERVAL_MS - TICK_MS);
+        assert!(mocks.sent().is_empty());
+        mocks.wait(&node, TICK_MS);
+        let sent = mocks.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].1, rpl_code::DIS);
+    }
+
+    #[test]
+    fn rejects_parents_past_max_rank_increase() {
+        let mocks = Mocks::new();
+        let node = mocks.node();
+        node.start();
+        receive_dio(&node, neighbor(2), 256, DEFAULT_CONFIG);
+        assert_eq!(node.get_rank(), 1024);
+
+        // Through the parent, the rank would grow by more than the
+        // DODAG allows past the lowest rank of the node
+        receive_dio(&node, neighbor(2), 256 + 8 * 256, DEFAULT_CONFIG);
+        assert_eq!(node.get_parent(), None);
+        assert_eq!(node.get_rank(), INFINITE_RANK);
+    }
+
+    #[test]
+    fn installs_and_removes_routes_from_daos() {
+        let mocks = Mocks::new();
+        let node = mocks.node();
+        node.start_root(prefix(), 64, objective_code_point::OF0);
+        mocks.sent();
+
+        let child = neighbor(2);
+        let target = global_addr(2);
+        assert_eq!(
+            receive_dao(&node, &mocks, child, target, 30),
+            DAO_ACK_ACCEPTED
+        );
+        let route = mocks.interface.lookup_route(&target).unwrap();
+        assert_eq!(route.prefix_len, 128);
+        assert_eq!(route.next_hop, child);
+        assert_eq!(route.lifetime, 30 * 60);
+
+        // Only the next hop of the route can remove it
+        assert_eq!(
+            receive_dao(&node, &mocks, neighbor(3), target, 0),
+            DAO_ACK_ACCEPTED
+        );
+        assert!(mocks.interface.lookup_route(&target).is_some());
+        assert_eq!(
+            receive_dao(&node, &mocks, child, target, 0),
+            DAO_ACK_ACCEPTED
+        );
+        assert!(mocks.interface.lookup_route(&target).is_none());
+    }
+
+    #[test]
+    fn refuses_routes_when_table_is_full() {
+        let mocks = Mocks::new();
+        let node = mocks.node();
+        node.start_root(prefix(), 64, objective_code_point::OF0);
+
+        for i in 0..ROUTE_TABLE_LEN as u16 {
+            let status = receive_dao(&node, &mocks, neighbor(2), global_addr(0x10 + i), 30);
+            assert_eq!(status, DAO_ACK_ACCEPTED);
+        }
+        let status = receive_dao(&node, &mocks, neighbor(2), global_addr(0x100), 30);
+        assert_eq!(status, DAO_ACK_TABLE_FULL);
+        assert!(mocks.interface.lookup_route(&global_addr(0x100)).is_none());
+    }
+
+    #[test]
+    fn ignores_daos_from_parent() {
+        let mocks = Mocks::new();
+        let node = mocks.node();
+        node.start();
+        receive_dio(&node, neighbor(2), 256, DEFAULT_CONFIG);
+
+        let body = dao_body(global_addr(2), 30);
+        receive(&node, neighbor(2), neighbor(9), rpl_code::DAO, &body);
+
+        assert!(mocks.sent().is_empty());
+        let route = mocks.interface.lookup_route(&global_addr(2)).unwrap();
+        assert_eq!(route.prefix_len, 0);
+    }
+}
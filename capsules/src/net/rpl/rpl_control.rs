@@ -0,0 +1,765 @@
+//! This file contains the RPL control messages (RFC 6550), which are sent as
+//! ICMPv6 messages of type 155 whose code is one of `rpl_code`, and the
+//! methods to encode and decode them: the base of the DODAG Information
+//! Solicitation (DIS), the DODAG Information Object (DIO), the Destination
+//! Advertisement Object (DAO) and its acknowledgement (DAO-ACK), and the
+//! options that follow them.
+//!
+//! `RPLOptions` iterates over the options that follow the base of a message:
+//!
+//! ```rust
+//! # use capsules::net::rpl::rpl_control::{RPLOption, RPLOptions, DIO};
+//! for option in RPLOptions::new(&body[DIO::LEN..]) {
+//!     if let RPLOption::DODAGConfig(config) = option {
+//!         // ...
+//!     }
+//! }
+//! ```
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_bytes, decode_u16, decode_u32, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u32, encode_u8};
+
+/// Codes of the RPL control messages.
+pub mod rpl_code {
+    pub const DIS: u8 = 0x00;
+    pub const DIO: u8 = 0x01;
+    pub const DAO: u8 = 0x02;
+    pub const DAO_ACK: u8 = 0x03;
+}
+
+/// Types of the options.
+pub mod rpl_option_type {
+    pub const PAD1: u8 = 0;
+    pub const PADN: u8 = 1;
+    pub const DODAG_CONFIG: u8 = 4;
+    pub const TARGET: u8 = 5;
+    pub const TRANSIT_INFO: u8 = 6;
+    pub const PREFIX_INFO: u8 = 8;
+}
+
+/// Modes of operation of a DODAG.
+pub mod mode_of_operation {
+    pub const NO_DOWNWARD_ROUTES: u8 = 0;
+    pub const NON_STORING: u8 = 1;
+    pub const STORING: u8 = 2;
+    pub const STORING_MULTICAST: u8 = 3;
+}
+
+/// Objective Code Points, which identify the objective function of a DODAG.
+pub mod objective_code_point {
+    /// Objective Function Zero (RFC 6552), based on hop count
+    pub const OF0: u16 = 0;
+    /// Minimum Rank with Hysteresis Objective Function (RFC 6719), based on
+    /// the expected transmission count (ETX) of links
+    pub const MRHOF: u16 = 1;
+}
+
+/// The rank of nodes that are not part of a DODAG.
+pub const INFINITE_RANK: u16 = 0xffff;
+
+/// Size of the base of a DIS.
+pub const DIS_LEN: usize = 2;
+
+// Flags of the base of messages
+const DIO_GROUNDED: u8 = 0x80;
+const DAO_ACK_REQUESTED: u8 = 0x80;
+const DAO_DODAG_ID_PRESENT: u8 = 0x40;
+const DAO_ACK_DODAG_ID_PRESENT: u8 = 0x80;
+
+// Length of the data of options, after their type and length
+const DODAG_CONFIG_LEN: u8 = 14;
+const TRANSIT_INFO_LEN: u8 = 4;
+const PREFIX_INFO_LEN: u8 = 30;
+
+/// The base of a DODAG Information Object.
+#[derive(Copy, Clone, Debug)]
+pub struct DIO {
+    pub instance_id: u8,
+    pub version: u8,
+    pub rank: u16,
+    pub grounded: bool,
+    /// One of `mode_of_operation`
+    pub mop: u8,
+    pub preference: u8,
+    pub dtsn: u8,
+    pub dodag_id: IPAddr,
+}
+
+impl DIO {
+    pub const LEN: usize = 24;
+
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        stream_len_cond!(buf, offset + DIO::LEN);
+        let mut off = offset;
+        off = enc_consume!(buf, off; encode_u8, self.instance_id);
+        off = enc_consume!(buf, off; encode_u8, self.version);
+        off = enc_consume!(buf, off; encode_u16, self.rank);
+        let flags = if self.grounded { DIO_GROUNDED } else { 0 }
+            | (self.mop & 0x7) << 3
+            | (self.preference & 0x7);
+        off = enc_consume!(buf, off; encode_u8, flags);
+        off = enc_consume!(buf, off; encode_u8, self.dtsn);
+        // Flags and reserved
+        off = enc_consume!(buf, off; encode_u16, 0);
+        off = enc_consume!(buf, off; encode_bytes, &self.dodag_id.0);
+        stream_done!(off, off);
+    }
+
+    pub fn decode(buf: &[u8]) -> SResult<DIO> {
+        let (off, instance_id) = dec_try!(buf, 0; decode_u8);
+        let (off, version) = dec_try!(buf, off; decode_u8);
+        let (off, rank) = dec_try!(buf, off; decode_u16);
+        let (off, flags) = dec_try!(buf, off; decode_u8);
+        let (off, dtsn) = dec_try!(buf, off; decode_u8);
+        let mut dodag_id = IPAddr::new();
+        let off = dec_consume!(buf, off + 2; decode_bytes, &mut dodag_id.0);
+        stream_done!(
+            off,
+            DIO {
+                instance_id,
+                version,
+                rank,
+                grounded: flags & DIO_GROUNDED != 0,
+                mop: (flags >> 3) & 0x7,
+                preference: flags & 0x7,
+                dtsn,
+                dodag_id,
+            }
+        );
+    }
+}
+
+/// The base of a Destination Advertisement Object.
+#[derive(Copy, Clone, Debug)]
+pub struct DAO {
+    pub instance_id: u8,
+    pub ack_requested: bool,
+    pub sequence: u8,
+    pub dodag_id: Option<IPAddr>,
+}
+
+impl DAO {
+    pub fn get_size(&self) -> usize {
+        4 + self.dodag_id.map_or(0, |_| 16)
+    }
+
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        let mut off = offset;
+        off = enc_consume!(buf, off; encode_u8, self.instance_id);
+        let flags = if self.ack_requested {
+            DAO_ACK_REQUESTED
+        } else {
+            0
+        } | self.dodag_id.map_or(0, |_| DAO_DODAG_ID_PRESENT);
+        off = enc_consume!(buf, off; encode_u8, flags);
+        // Reserved
+        off = enc_consume!(buf, off; encode_u8, 0);
+        off = enc_consume!(buf, off; encode_u8, self.sequence);
+        if let Some(dodag_id) = self.dodag_id {
+            off = enc_consume!(buf, off; encode_bytes, &dodag_id.0);
+        }
+        stream_done!(off, off);
+    }
+
+    pub fn decode(buf: &[u8]) -> SResult<DAO> {
+        let (off, instance_id) = dec_try!(buf, 0; decode_u8);
+        let (off, flags) = dec_try!(buf, off; decode_u8);
+        let (off, sequence) = dec_try!(buf, off + 1; decode_u8);
+        let (off, dodag_id) = if flags & DAO_DODAG_ID_PRESENT != 0 {
+            let mut dodag_id = IPAddr::new();
+            let off = dec_consume!(buf, off; decode_bytes, &mut dodag_id.0);
+            (off, Some(dodag_id))
+        } else {
+            (off, None)
+        };
+        stream_done!(
+            off,
+            DAO {
+                instance_id,
+                ack_requested: flags & DAO_ACK_REQUESTED != 0,
+                sequence,
+                dodag_id,
+            }
+        );
+    }
+}
+
+/// A DAO-ACK. Statuses below 128 are accepted, the others are rejected.
+#[derive(Copy, Clone, Debug)]
+pub struct DAOAck {
+    pub instance_id: u8,
+    pub sequence: u8,
+    pub status: u8,
+    pub dodag_id: Option<IPAddr>,
+}
+
+impl DAOAck {
+    pub fn get_size(&self) -> usize {
+        4 + self.dodag_id.map_or(0, |_| 16)
+    }
+
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        let mut off = offset;
+        off = enc_consume!(buf, off; encode_u8, self.instance_id);
+        let flags = self.dodag_id.map_or(0, |_| DAO_ACK_DODAG_ID_PRESENT);
+        off = enc_consume!(buf, off; encode_u8, flags);
+        off = enc_consume!(buf, off; encode_u8, self.sequence);
+        off = enc_consume!(buf, off; encode_u8, self.status);
+        if let Some(dodag_id) = self.dodag_id {
+            off = enc_consume!(buf, off; encode_bytes, &dodag_id.0);
+        }
+        stream_done!(off, off);
+    }
+
+    pub fn decode(buf: &[u8]) -> SResult<DAOAck> {
+        let (off, instance_id) = dec_try!(buf, 0; decode_u8);
+        let (off, flags) = dec_try!(buf, off; decode_u8);
+        let (off, sequence) = dec_try!(buf, off; decode_u8);
+        let (off, status) = dec_try!(buf, off; decode_u8);
+        let (off, dodag_id) = if flags & DAO_ACK_DODAG_ID_PRESENT != 0 {
+            let mut dodag_id = IPAddr::new();
+            let off = dec_consume!(buf, off; decode_bytes, &mut dodag_id.0);
+            (off, Some(dodag_id))
+        } else {
+            (off, None)
+        };
+        stream_done!(
+            off,
+            DAOAck {
+                instance_id,
+                sequence,
+                status,
+                dodag_id,
+            }
+        );
+    }
+}
+
+/// The parameters of a DODAG, which its root distributes in the DODAG
+/// Configuration option.
+#[derive(Copy, Clone, Debug)]
+pub struct DODAGConfig {
+    /// The minimum interval of the Trickle timer of DIOs is
+    /// 2^`dio_interval_min` milliseconds, and it doubles up to
+    /// `dio_interval_doublings` times
+    pub dio_interval_doublings: u8,
+    pub dio_interval_min: u8,
+    pub dio_redundancy: u8,
+    pub max_rank_increase: u16,
+    pub min_hop_rank_increase: u16,
+    /// One of `objective_code_point`
+    pub ocp: u16,
+    /// The lifetime of routes, in units of `lifetime_unit` seconds
+    pub default_lifetime: u8,
+    pub lifetime_unit: u16,
+}
+
+/// A prefix of the DODAG, as in the Prefix Information option of Neighbor
+/// Discovery.
+#[derive(Copy, Clone, Debug)]
+pub struct PrefixInfo {
+    pub prefix_len: u8,
+    /// The flags of `nd_options::prefix_flags`
+    pub flags: u8,
+    pub valid_lifetime: u32,
+    pub preferred_lifetime: u32,
+    pub prefix: IPAddr,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum RPLOption<'b> {
+    DODAGConfig(DODAGConfig),
+    /// An address or prefix that can be reached through the sender of a DAO
+    Target {
+        prefix_len: u8,
+        prefix: IPAddr,
+    },
+    /// The lifetime of the routes to the preceding targets, in units of
+    /// `lifetime_unit` seconds. A lifetime of zero removes the routes.
+    TransitInfo {
+        path_sequence: u8,
+        path_lifetime: u8,
+    },
+    PrefixInfo(PrefixInfo),
+    /// Any other option, including padding, with its data after the type
+    /// and length.
+    Other {
+        option_type: u8,
+        data: &'b [u8],
+    },
+}
+
+impl RPLOption<'b> {
+    pub fn get_type(&self) -> u8 {
+        match *self {
+            RPLOption::DODAGConfig(_) => rpl_option_type::DODAG_CONFIG,
+            RPLOption::Target { .. } => rpl_option_type::TARGET,
+            RPLOption::TransitInfo { .. } => rpl_option_type::TRANSIT_INFO,
+            RPLOption::PrefixInfo(_) => rpl_option_type::PREFIX_INFO,
+            RPLOption::Other { option_type, .. } => option_type,
+        }
+    }
+
+    /// Returns the size of the option, including its type and length.
+    pub fn get_size(&self) -> usize {
+        2 + match *self {
+            RPLOption::DODAGConfig(_) => DODAG_CONFIG_LEN as usize,
+            RPLOption::Target { prefix_len, .. } => 2 + (prefix_len as usize + 7) / 8,
+            RPLOption::TransitInfo { .. } => TRANSIT_INFO_LEN as usize,
+            RPLOption::PrefixInfo(_) => PREFIX_INFO_LEN as usize,
+            RPLOption::Other { data, .. } => data.len(),
+        }
+    }
+
+    /// Serializes the option into a buffer.
+    ///
+    /// # Arguments
+    ///
+    /// `buf` - A buffer to serialize the option into
+    /// `offset` - The current offset into the provided buffer
+    ///
+    /// # Return Value
+    ///
+    /// This function returns the new offset into the buffer, wrapped in an
+    /// SResult
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        let size = self.get_size();
+        stream_len_cond!(buf, offset + size);
+
+        let mut off = offset;
+        off = enc_consume!(buf, off; encode_u8, self.get_type());
+        off = enc_consume!(buf, off; encode_u8, (size - 2) as u8);
+        match *self {
+            RPLOption::DODAGConfig(config) => {
+                // No authentication, and the default path control size
+                off = enc_consume!(buf, off; encode_u8, 0);
+                off = enc_consume!(buf, off; encode_u8, config.dio_interval_doublings);
+                off = enc_consume!(buf, off; encode_u8, config.dio_interval_min);
+                off = enc_consume!(buf, off; encode_u8, config.dio_redundancy);
+                off = enc_consume!(buf, off; encode_u16, config.max_rank_increase);
+                off = enc_consume!(buf, off; encode_u16, config.min_hop_rank_increase);
+                off = enc_consume!(buf, off; encode_u16, config.ocp);
+                // Reserved
+                off = enc_consume!(buf, off; encode_u8, 0);
+                off = enc_consume!(buf, off; encode_u8, config.default_lifetime);
+                enc_consume!(buf, off; encode_u16, config.lifetime_unit);
+            }
+            RPLOption::Target { prefix_len, prefix } => {
+                // Flags
+                off = enc_consume!(buf, off; encode_u8, 0);
+                off = enc_consume!(buf, off; encode_u8, prefix_len);
+                enc_consume!(buf, off; encode_bytes, &prefix.0[..size - 4]);
+            }
+            RPLOption::TransitInfo {
+                path_sequence,
+                path_lifetime,
+            } => {
+                // Flags and path control
+                off = enc_consume!(buf, off; encode_u16, 0);
+                off = enc_consume!(buf, off; encode_u8, path_sequence);
+                enc_consume!(buf, off; encode_u8, path_lifetime);
+            }
+            RPLOption::PrefixInfo(info) => {
+                off = enc_consume!(buf, off; encode_u8, info.prefix_len);
+                off = enc_consume!(buf, off; encode_u8, info.flags);
+                off = enc_consume!(buf, off; encode_u32, info.valid_lifetime);
+                off = enc_consume!(buf, off; encode_u32, info.preferred_lifetime);
+                // Reserved
+                off = enc_consume!(buf, off; encode_u32, 0);
+                enc_consume!(buf, off; encode_bytes, &info.prefix.0);
+            }
+            RPLOption::Other { data, .. } => {
+                enc_consume!(buf, off; encode_bytes, data);
+            }
+        }
+        stream_done!(offset + size, offset + size);
+    }
+
+    /// Deserializes the option at the start of a buffer.
+    ///
+    /// # Return Value
+    ///
+    /// This function returns the option and the offset of the next option,
+    /// wrapped in an SResult. The Pad1 option, which has no length, is
+    /// returned as an `Other` option without data.
+    pub fn decode(buf: &'b [u8]) -> SResult<RPLOption<'b>> {
+        let (off, option_type) = dec_try!(buf, 0; decode_u8);
+        if option_type == rpl_option_type::PAD1 {
+            stream_done!(
+                off,
+                RPLOption::Other {
+                    option_type,
+                    data: &buf[off..off],
+                }
+            );
+        }
+        let (off, len) = dec_try!(buf, off; decode_u8);
+        let size = 2 + len as usize;
+        stream_len_cond!(buf, size);
+
+        let option = match option_type {
+            rpl_option_type::DODAG_CONFIG => {
+                stream_cond!(len == DODAG_CONFIG_LEN);
+                let (off, dio_interval_doublings) = dec_try!(buf, off + 1; decode_u8);
+                let (off, dio_interval_min) = dec_try!(buf, off; decode_u8);
+                let (off, dio_redundancy) = dec_try!(buf, off; decode_u8);
+                let (off, max_rank_increase) = dec_try!(buf, off; decode_u16);
+                let (off, min_hop_rank_increase) = dec_try!(buf, off; decode_u16);
+                let (off, ocp) = dec_try!(buf, off; decode_u16);
+                let (off, default_lifetime) = dec_try!(buf, off + 1; decode_u8);
+                let (_, lifetime_unit) = dec_try!(buf, off; decode_u16);
+                RPLOption::DODAGConfig(DODAGConfig {
+                    dio_interval_doublings,
+                    dio_interval_min,
+                    dio_redundancy,
+                    max_rank_increase,
+                    min_hop_rank_increase,
+                    ocp,
+                    default_lifetime,
+                    lifetime_unit,
+                })
+            }
+            rpl_option_type::TARGET => {
+                let (off, prefix_len) = dec_try!(buf, off + 1; decode_u8);
+                let prefix_bytes = (prefix_len as usize + 7) / 8;
+                stream_cond!(prefix_len <= 128 && size >= off + prefix_bytes);
+                let mut prefix = IPAddr::new();
+                prefix.0[..prefix_bytes].copy_from_slice(&buf[off..off + prefix_bytes]);
+                RPLOption::Target { prefix_len, prefix }
+            }
+            rpl_option_type::TRANSIT_INFO => {
+                // The parent address is only present in non-storing mode
+                stream_cond!(len >= TRANSIT_INFO_LEN);
+                let (off, path_sequence) = dec_try!(buf, off + 2; decode_u8);
+                let (_, path_lifetime) = dec_try!(buf, off; decode_u8);
+                RPLOption::TransitInfo {
+                    path_sequence,
+                    path_lifetime,
+                }
+            }
+            rpl_option_type::PREFIX_INFO => {
+                stream_cond!(len == PREFIX_INFO_LEN);
+                let (off, prefix_len) = dec_try!(buf, off; decode_u8);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, valid_lifetime) = dec_try!(buf, off; decode_u32);
+                let (off, preferred_lifetime) = dec_try!(buf, off; decode_u32);
+                let mut prefix = IPAddr::new();
+                dec_consume!(buf, off + 4; decode_bytes, &mut prefix.0);
+                RPLOption::PrefixInfo(PrefixInfo {
+                    prefix_len,
+                    flags,
+                    valid_lifetime,
+                    preferred_lifetime,
+                    prefix,
+                })
+            }
+            _ => RPLOption::Other {
+                option_type,
+                data: &buf[off..size],
+            },
+        };
+        stream_done!(size, option);
+    }
+}
+
+/// An iterator over the options of a message, which stops at the first
+/// malformed option.
+pub struct RPLOptions<'b> {
+    buf: &'b [u8],
+}
+
+impl RPLOptions<'b> {
+    pub fn new(buf: &'b [u8]) -> RPLOptions<'b> {
+        RPLOptions { buf }
+    }
+
+    /// Returns whether all the options of the message are well-formed.
+    pub fn is_valid(buf: &[u8]) -> bool {
+        let mut off = 0;
+        while off < buf.len() {
+            match RPLOption::decode(&buf[off..]).done() {
+                Some((size, _)) => off += size,
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+impl Iterator for RPLOptions<'b> {
+    type Item = RPLOption<'b>;
+
+    fn next(&mut self) -> Option<RPLOption<'b>> {
+        let (size, option) = RPLOption::decode(self.buf).done()?;
+        self.buf = &self.buf[size..];
+        Some(option)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    fn dodag_id() -> IPAddr {
+        let mut addr = IPAddr::new();
+        addr.0[..2].copy_from_slice(&[0x20, 0x01]);
+        addr.0[15] = 1;
+        addr
+    }
+
+    // Encodes `option`, checks its size and decodes it again.
+    fn round_trip<'b>(option: RPLOption, encoded: &'b mut [u8]) -> RPLOption<'b> {
+        let size = option.get_size();
+        assert_eq!(
+            option.encode(encoded, 0).done().map(|(off, _)| off),
+            Some(size)
+        );
+        assert_eq!(encoded[0], option.get_type());
+        assert_eq!(encoded[1] as usize + 2, size);
+        let (off, decoded) = RPLOption::decode(&*encoded).done().unwrap();
+        assert_eq!(off, size);
+        decoded
+    }
+
+    fn types(buf: &[u8]) -> Vec<u8> {
+        RPLOptions::new(buf)
+            .map(|option| option.get_type())
+            .collect()
+    }
+
+    #[test]
+    fn dio() {
+        let dio = DIO {
+            instance_id: 30,
+            version: 240,
+            rank: 256,
+            grounded: true,
+            mop: mode_of_operation::STORING,
+            preference: 1,
+            dtsn: 7,
+            dodag_id: dodag_id(),
+        };
+        let mut buf = [0xff; DIO::LEN];
+        assert_eq!(
+            dio.encode(&mut buf, 0).done().map(|(off, _)| off),
+            Some(DIO::LEN)
+        );
+        assert_eq!(buf[..8], [30, 240, 1, 0, 0x91, 7, 0, 0]);
+        assert_eq!(buf[8..], dodag_id().0);
+
+        let (off, decoded) = DIO::decode(&buf).done().unwrap();
+        assert_eq!(off, DIO::LEN);
+        assert_eq!(
+            (
+                decoded.instance_id,
+                decoded.version,
+                decoded.rank,
+                decoded.dtsn
+            ),
+            (30, 240, 256, 7)
+        );
+        assert!(decoded.grounded);
+        assert_eq!(decoded.mop, mode_of_operation::STORING);
+        assert_eq!(decoded.preference, 1);
+        assert_eq!(decoded.dodag_id, dodag_id());
+
+        assert!(DIO::decode(&buf[..DIO::LEN - 1]).is_needed());
+        assert!(dio.encode(&mut buf, 1).is_needed());
+    }
+
+    #[test]
+    fn dao_and_ack() {
+        let mut buf = [0; 20];
+        for &id in [None, Some(dodag_id())].iter() {
+            let dao = DAO {
+                instance_id: 30,
+                ack_requested: true,
+                sequence: 9,
+                dodag_id: id,
+            };
+            let size = dao.get_size();
+            assert_eq!(
+                dao.encode(&mut buf, 0).done().map(|(off, _)| off),
+                Some(size)
+            );
+            let (off, decoded) = DAO::decode(&buf[..size]).done().unwrap();
+            assert_eq!(off, size);
+            assert_eq!((decoded.instance_id, decoded.sequence), (30, 9));
+            assert!(decoded.ack_requested);
+            assert_eq!(decoded.dodag_id, id);
+
+            let ack = DAOAck {
+                instance_id: 30,
+                sequence: 9,
+                status: 128,
+                dodag_id: id,
+            };
+            let size = ack.get_size();
+            assert_eq!(
+                ack.encode(&mut buf, 0).done().map(|(off, _)| off),
+                Some(size)
+            );
+            let (off, decoded) = DAOAck::decode(&buf[..size]).done().unwrap();
+            assert_eq!(off, size);
+            assert_eq!(
+                (decoded.instance_id, decoded.sequence, decoded.status),
+                (30, 9, 128)
+            );
+            assert_eq!(decoded.dodag_id, id);
+        }
+        assert_eq!(buf[..4], [30, DAO_ACK_DODAG_ID_PRESENT, 9, 128]);
+
+        // The DODAG ID is announced but missing
+        assert!(DAO::decode(&[30, DAO_DODAG_ID_PRESENT, 0, 9, 0x20]).is_needed());
+        assert!(DAOAck::decode(&[30, 0, 9]).is_needed());
+    }
+
+    #[test]
+    fn dodag_config() {
+        let config = DODAGConfig {
+            dio_interval_doublings: 20,
+            dio_interval_min: 3,
+            dio_redundancy: 10,
+            max_rank_increase: 0x0700,
+            min_hop_rank_increase: 256,
+            ocp: objective_code_point::MRHOF,
+            default_lifetime: 30,
+            lifetime_unit: 60,
+        };
+        let mut buf = [0; 16];
+        match round_trip(RPLOption::DODAGConfig(config), &mut buf) {
+            RPLOption::DODAGConfig(decoded) => {
+                assert_eq!(
+                    (
+                        decoded.dio_interval_doublings,
+                        decoded.dio_interval_min,
+                        decoded.dio_redundancy
+                    ),
+                    (20, 3, 10)
+                );
+                assert_eq!(decoded.max_rank_increase, 0x0700);
+                assert_eq!(decoded.min_hop_rank_increase, 256);
+                assert_eq!(decoded.ocp, objective_code_point::MRHOF);
+                assert_eq!((decoded.default_lifetime, decoded.lifetime_unit), (30, 60));
+            }
+            option => panic!("{:?}", option),
+        }
+        assert_eq!(buf[..6], [4, 14, 0, 20, 3, 10]);
+
+        // The option has a fixed length
+        buf[1] = 13;
+        assert!(RPLOption::decode(&buf).is_err());
+    }
+
+    #[test]
+    fn targets() {
+        let mut buf = [0xff; 20];
+        for &(prefix_len, size) in [(0, 4), (64, 12), (65, 13), (128, 20)].iter() {
+            let mut prefix = IPAddr::new();
+            prefix.set_prefix(&dodag_id().0, prefix_len);
+            let option = RPLOption::Target { prefix_len, prefix };
+            assert_eq!(option.get_size(), size);
+            match round_trip(option, &mut buf) {
+                RPLOption::Target {
+                    prefix_len: decoded_len,
+                    prefix: decoded,
+                } => {
+                    assert_eq!(decoded_len, prefix_len);
+                    assert_eq!(decoded, prefix);
+                }
+                option => panic!("{:?}", option),
+            }
+        }
+
+        // Prefixes longer than the option, or than an address
+        assert!(RPLOption::decode(&[5, 3, 0, 16, 0x20]).is_err());
+        let mut long = [0; 35];
+        long[..4].copy_from_slice(&[5, 33, 0, 129]);
+        assert!(RPLOption::decode(&long).is_err());
+    }
+
+    #[test]
+    fn transit_information() {
+        let mut buf = [0; 6];
+        let option = RPLOption::TransitInfo {
+            path_sequence: 3,
+            path_lifetime: 0,
+        };
+        match round_trip(option, &mut buf) {
+            RPLOption::TransitInfo {
+                path_sequence: 3,
+                path_lifetime: 0,
+            } => {}
+            option => panic!("{:?}", option),
+        }
+
+        // With the parent address of non-storing mode
+        let mut buf = [0; 22];
+        buf[..6].copy_from_slice(&[6, 20, 0, 0, 4, 30]);
+        match RPLOption::decode(&buf).done() {
+            Some((
+                22,
+                RPLOption::TransitInfo {
+                    path_sequence: 4,
+                    path_lifetime: 30,
+                },
+            )) => {}
+            option => panic!("{:?}", option),
+        }
+        assert!(RPLOption::decode(&[6, 3, 0, 0, 4]).is_err());
+    }
+
+    #[test]
+    fn prefix_information() {
+        let info = PrefixInfo {
+            prefix_len: 64,
+            flags: 0x40,
+            valid_lifetime: 0xffff_ffff,
+            preferred_lifetime: 3600,
+            prefix: dodag_id(),
+        };
+        let mut buf = [0; 32];
+        match round_trip(RPLOption::PrefixInfo(info), &mut buf) {
+            RPLOption::PrefixInfo(decoded) => {
+                assert_eq!((decoded.prefix_len, decoded.flags), (64, 0x40));
+                assert_eq!(decoded.valid_lifetime, 0xffff_ffff);
+                assert_eq!(decoded.preferred_lifetime, 3600);
+                assert_eq!(decoded.prefix, dodag_id());
+            }
+            option => panic!("{:?}", option),
+        }
+        assert_eq!(buf[16..], dodag_id().0);
+        buf[1] = 29;
+        assert!(RPLOption::decode(&buf).is_err());
+    }
+
+    #[test]
+    fn padding_and_iteration() {
+        let mut buf = [0; 16];
+        // Pad1, PadN with two bytes, then a Transit Information option
+        buf[..4].copy_from_slice(&[rpl_option_type::PAD1, rpl_option_type::PADN, 2, 0]);
+        let off = RPLOption::TransitInfo {
+            path_sequence: 0,
+            path_lifetime: 1,
+        }
+        .encode(&mut buf, 5)
+        .done()
+        .unwrap()
+        .0;
+        assert_eq!(off, 11);
+        assert_eq!(types(&buf[..off]), [0, 1, 6]);
+        assert!(RPLOptions::is_valid(&buf[..off]));
+        assert!(RPLOptions::is_valid(&[]));
+
+        // An option longer than the message stops the iteration
+        buf[off..off + 2].copy_from_slice(&[8, 30]);
+        assert_eq!(types(&buf), [0, 1, 6]);
+        assert!(!RPLOptions::is_valid(&buf));
+        assert!(!RPLOptions::is_valid(&buf[..=off]));
+    }
+}
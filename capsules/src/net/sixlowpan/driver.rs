@@ -0,0 +1,205 @@
+//! 6LoWPAN compression context userspace interface.
+//!
+//! Lets a privileged app, e.g. one that configures the network of the
+//! board, read and set the contexts of a `ContextTable`. As the contexts
+//! change how every packet is compressed, only the apps whose package names
+//! are listed when the driver is created may use it. All other apps get
+//! `ENOSUPPORT`, as if the driver did not exist.
+//!
+//! Context Records
+//! ---------------
+//!
+//! Contexts are read from and written to the buffer shared with allow 0, as
+//! a record of 24 bytes. The lifetime is a little-endian 32 bit word:
+//!
+//! ```text
+//! 0x00  prefix (16 bytes)
+//! 0x10  prefix length, in bits
+//! 0x11  flags: bit 0 is set if the context may be used for compression
+//! 0x12  reserved (2 bytes)
+//! 0x14  lifetime in seconds, 0xffffffff if the context never expires
+//! ```
+
+use crate::net::sixlowpan::sixlowpan_compression::Context;
+use crate::net::sixlowpan::sixlowpan_context::{ContextTable, MAX_CONTEXTS};
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::hil::time;
+use kernel::introspection::KernelInfo;
+use kernel::{AppId, AppSlice, Driver, Grant, Kernel, ReturnCode, Shared};
+
+/// Syscall number
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::SixlowpanContext as usize;
+
+/// Size of a context record.
+pub const RECORD_LEN: usize = 24;
+
+const FLAG_COMPRESS: u8 = 0x01;
+const INFINITE_LIFETIME: u32 = 0xffff_ffff;
+
+#[derive(Default)]
+pub struct App {
+    buffer: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct ContextDriver<'a, A: time::Alarm<'a>, C: ProcessManagementCapability> {
+    contexts: &'a ContextTable<'a, A>,
+    kernel_info: KernelInfo,
+    apps: Grant<App>,
+    privileged_apps: &'static [&'static str],
+    capability: C,
+}
+
+impl<A: time::Alarm<'a>, C: ProcessManagementCapability> ContextDriver<'a, A, C> {
+    pub fn new(
+        contexts: &'a ContextTable<'a, A>,
+        kernel: &'static Kernel,
+        grant: Grant<App>,
+        privileged_apps: &'static [&'static str],
+        capability: C,
+    ) -> ContextDriver<'a, A, C> {
+        ContextDriver {
+            contexts,
+            kernel_info: KernelInfo::new(kernel),
+            apps: grant,
+            privileged_apps,
+            capability,
+        }
+    }
+
+    /// Only apps the board explicitly listed may use this driver.
+    fn is_privileged(&self, appid: AppId) -> bool {
+        let name = self.kernel_info.process_name(appid, &self.capability);
+        self.privileged_apps.iter().any(|allowed| *allowed == name)
+    }
+
+    /// Utility function to perform an action on the buffer of an app, which
+    /// must hold a context record.
+    fn do_with_record<F>(&self, appid: AppId, closure: F) -> ReturnCode
+    where
+        F: FnOnce(&mut [u8]) -> ReturnCode,
+    {
+        self.apps
+            .enter(appid, |app, _| {
+                app.buffer.as_mut().map_or(ReturnCode::ERESERVE, |buffer| {
+                    if buffer.len() < RECORD_LEN {
+                        return ReturnCode::ESIZE;
+                    }
+                    closure(&mut buffer.as_mut()[..RECORD_LEN])
+                })
+            })
+            .unwrap_or_else(|err| err.into())
+    }
+
+    fn read_context(&self, id: u8, record: &mut [u8]) -> ReturnCode {
+        self.contexts
+            .get_entry(id)
+            .map_or(ReturnCode::EINVAL, |entry| {
+                let context = entry.context;
+                record[..16].copy_from_slice(&context.prefix);
+                record[16] = context.prefix_len;
+                record[17] = if context.compress { FLAG_COMPRESS } else { 0 };
+                record[18] = 0;
+                record[19] = 0;
+                write_u32(
+                    &mut record[20..],
+                    entry.lifetime.unwrap_or(INFINITE_LIFETIME),
+                );
+                ReturnCode::SUCCESS
+            })
+    }
+
+    fn write_context(&self, id: u8, record: &[u8]) -> ReturnCode {
+        let mut prefix = [0; 16];
+        prefix.copy_from_slice(&record[..16]);
+        let context = Context {
+            prefix,
+            prefix_len: record[16],
+            id,
+            compress: record[17] & FLAG_COMPRESS != 0,
+        };
+        let lifetime = match read_u32(&record[20..]) {
+            INFINITE_LIFETIME => None,
+            lifetime => Some(lifetime),
+        };
+        self.contexts.set_context(context, lifetime)
+    }
+}
+
+fn write_u32(buf: &mut [u8], value: u32) {
+    buf[0] = value as u8;
+    buf[1] = (value >> 8) as u8;
+    buf[2] = (value >> 16) as u8;
+    buf[3] = (value >> 24) as u8;
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    (buf[0] as u32) | (buf[1] as u32) << 8 | (buf[2] as u32) << 16 | (buf[3] as u32) << 24
+}
+
+impl<A: time::Alarm<'a>, C: ProcessManagementCapability> Driver for ContextDriver<'a, A, C> {
+    /// Setup shared buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Buffer that context records are read from and written to.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        if !self.is_privileged(appid) {
+            return ReturnCode::ENOSUPPORT;
+        }
+
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Read and set contexts.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check. Fails for apps that are not privileged.
+    /// - `1`: Copy the record of the context with identifier `data` into the
+    ///        buffer shared with allow 0. Returns `EINVAL` if the table has
+    ///        no such context.
+    /// - `2`: Set the context with identifier `data` from the record in the
+    ///        buffer shared with allow 0, replacing the context the table has
+    ///        with this identifier. Returns `EINVAL` if the prefix length is
+    ///        larger than 128 or leaves bits of the prefix set.
+    /// - `3`: Remove the context with identifier `data`.
+    ///
+    /// Commands 1 to 3 return `EINVAL` if the identifier is not less than 16.
+    /// Commands 1 and 2 return `ERESERVE` if no buffer is shared, and `ESIZE`
+    /// if it cannot hold a record.
+    fn command(&self, command_num: usize, data: usize, _: usize, appid: AppId) -> ReturnCode {
+        if !self.is_privileged(appid) {
+            return ReturnCode::ENOSUPPORT;
+        }
+
+        if (1..=3).contains(&command_num) && data >= MAX_CONTEXTS {
+            return ReturnCode::EINVAL;
+        }
+        let id = data as u8;
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.do_with_record(appid, |record| self.read_context(id, record)),
+            2 => self.do_with_record(appid, |record| self.write_context(id, record)),
+            3 => {
+                self.contexts.remove_context(id);
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -1,2 +1,7 @@
+pub mod driver;
 pub mod sixlowpan_compression;
+pub mod sixlowpan_context;
 pub mod sixlowpan_state;
+
+pub use self::driver::ContextDriver;
+pub use self::driver::DRIVER_NUM;
@@ -92,17 +92,13 @@ pub struct Context {
 }
 
 /// LoWPAN encoding requires being able to look up the existence of contexts,
-/// which are essentially IPv6 address prefixes. Any implementation must ensure
-/// that context 0 is always available and contains the mesh-local prefix.
+/// which are essentially IPv6 address prefixes. Context 0 is the default
+/// context, which contains the mesh-local prefix. Contexts may come and go,
+/// e.g. as routers advertise them: packets compressed against a context the
+/// store does not have cannot be decompressed.
 pub trait ContextStore {
     fn get_context_from_addr(&self, ip_addr: IPAddr) -> Option<Context>;
     fn get_context_from_id(&self, ctx_id: u8) -> Option<Context>;
-    fn get_context_0(&self) -> Context {
-        match self.get_context_from_id(0) {
-            Some(ctx) => ctx,
-            None => panic!("Context 0 not found"),
-        }
-    }
     fn get_context_from_prefix(&self, prefix: &[u8], prefix_len: u8) -> Option<Context>;
 }
 
@@ -126,6 +122,20 @@ pub fn compute_iid(mac_addr: &MacAddress) -> [u8; 8] {
     }
 }
 
+impl<T: ContextStore> ContextStore for &T {
+    fn get_context_from_addr(&self, ip_addr: IPAddr) -> Option<Context> {
+        (*self).get_context_from_addr(ip_addr)
+    }
+
+    fn get_context_from_id(&self, ctx_id: u8) -> Option<Context> {
+        (*self).get_context_from_id(ctx_id)
+    }
+
+    fn get_context_from_prefix(&self, prefix: &[u8], prefix_len: u8) -> Option<Context> {
+        (*self).get_context_from_prefix(prefix, prefix_len)
+    }
+}
+
 impl ContextStore for Context {
     fn get_context_from_addr(&self, ip_addr: IPAddr) -> Option<Context> {
         if util::matches_prefix(&ip_addr.0, &self.prefix, self.prefix_len) {
@@ -575,7 +585,8 @@ fn compress_udp_checksum(udp_header: &UDPHeader, buf: &mut [u8], written: &mut u
 ///
 /// # Arguments
 ///
-/// * `ctx_store` - ???
+/// * `ctx_store` - The contexts the header may be compressed against. The
+/// decompression fails if the header uses a context the store does not have.
 ///
 /// * `buf` - A slice containing the 6LowPAN packet along with its payload.
 ///
@@ -617,7 +628,7 @@ pub fn decompress(
     let mut written: usize = mem::size_of::<IP6Header>();
 
     // Decompress CID and CIE fields if they exist
-    let (src_ctx, dst_ctx) = decompress_cie(ctx_store, iphc_header_1, &buf, &mut consumed)?;
+    let (src_ctx, dst_ctx) = decompress_cie(ctx_store, iphc_header_2, &buf, &mut consumed)?;
 
     // Traffic Class & Flow Label
     decompress_tf(&mut ip6_header, iphc_header_1, &buf, &mut consumed);
@@ -819,27 +830,25 @@ pub fn decompress(
     Ok((consumed, written))
 }
 
+// Returns the source and destination contexts, which are `None` if the store
+// does not have them. The addresses that use a missing context cannot be
+// decompressed.
 fn decompress_cie(
     ctx_store: &dyn ContextStore,
     iphc_header: u8,
     buf: &[u8],
     consumed: &mut usize,
-) -> Result<(Context, Context), ()> {
-    let ctx_0 = ctx_store.get_context_0();
-    let (mut src_ctx, mut dst_ctx) = (ctx_0, ctx_0);
+) -> Result<(Option<Context>, Option<Context>), ()> {
+    let (mut sci, mut dci) = (0, 0);
     if iphc_header & iphc::CID != 0 {
-        let sci = buf[*consumed] >> 4;
-        let dci = buf[*consumed] & 0xf;
+        sci = buf[*consumed] >> 4;
+        dci = buf[*consumed] & 0xf;
         *consumed += 1;
-
-        if sci != 0 {
-            src_ctx = ctx_store.get_context_from_id(sci).ok_or(())?;
-        }
-        if dci != 0 {
-            dst_ctx = ctx_store.get_context_from_id(dci).ok_or(())?;
-        }
     }
-    Ok((src_ctx, dst_ctx))
+    Ok((
+        ctx_store.get_context_from_id(sci),
+        ctx_store.get_context_from_id(dci),
+    ))
 }
 
 fn decompress_tf(ip6_header: &mut IP6Header, iphc_header: u8, buf: &[u8], consumed: &mut usize) {
@@ -906,7 +915,7 @@ fn decompress_src(
     ip6_header: &mut IP6Header,
     iphc_header: u8,
     mac_addr: &MacAddress,
-    ctx: &Context,
+    ctx: &Option<Context>,
     buf: &[u8],
     consumed: &mut usize,
 ) -> Result<(), ()> {
@@ -920,7 +929,7 @@ fn decompress_src(
             sam_mode,
             &mut ip6_header.src_addr,
             mac_addr,
-            ctx,
+            ctx.as_ref().ok_or(())?,
             buf,
             consumed,
         )?;
@@ -935,7 +944,7 @@ fn decompress_dst(
     ip6_header: &mut IP6Header,
     iphc_header: u8,
     mac_addr: &MacAddress,
-    ctx: &Context,
+    ctx: &Option<Context>,
     buf: &[u8],
     consumed: &mut usize,
 ) -> Result<(), ()> {
@@ -950,7 +959,7 @@ fn decompress_dst(
             dam_mode,
             &mut ip6_header.dst_addr,
             mac_addr,
-            ctx,
+            ctx.as_ref().ok_or(())?,
             buf,
             consumed,
         )?;
@@ -964,7 +973,7 @@ fn decompress_dst(
 fn decompress_multicast(
     ip6_header: &mut IP6Header,
     iphc_header: u8,
-    ctx: &Context,
+    ctx: &Option<Context>,
     buf: &[u8],
     consumed: &mut usize,
 ) -> Result<(), ()> {
@@ -976,6 +985,7 @@ fn decompress_multicast(
             iphc::DAM_INLINE => {
                 // DAC = 1, DAM = 00: 48 bits
                 // ffXX:XXLL:PPPP:PPPP:PPPP:PPPP:XXXX:XXXX
+                let ctx = ctx.as_ref().ok_or(())?;
                 let prefix_bytes = ((ctx.prefix_len + 7) / 8) as usize;
                 if prefix_bytes > 8 {
                     // The maximum prefix length for this mode is 64 bits.
@@ -0,0 +1,315 @@
+//! This file contains a table of the 6LoWPAN compression contexts (RFC 6282)
+//! that can change at runtime. A node joining a network does not know the
+//! prefixes of the network beforehand: Neighbor Discovery learns them from
+//! the 6LoWPAN Context options of Router Advertisements (RFC 6775), and a
+//! privileged app can set them through the context driver.
+//!
+//! Each context has a lifetime in seconds, which the table counts down with
+//! its own alarm while a context has one. A context is removed when its
+//! lifetime runs out, so that neither the compression nor the decompression
+//! of packets uses it. Contexts whose `compress` flag is cleared are only
+//! used to decompress the packets of other nodes.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use capsules::net::sixlowpan::sixlowpan_context::ContextTable;
+//! # use kernel::static_init;
+//! let contexts = static_init!(
+//!     ContextTable<'static, VirtualMuxAlarm<'static, Ast>>,
+//!     ContextTable::new(ctx_alarm)
+//! );
+//! ctx_alarm.set_client(contexts);
+//! // The default context, which never expires
+//! contexts.set_context(
+//!     Context {
+//!         prefix: DEFAULT_CTX_PREFIX,
+//!         prefix_len: DEFAULT_CTX_PREFIX_LEN,
+//!         id: 0,
+//!         compress: true,
+//!     },
+//!     None,
+//! );
+//! let sixlowpan = static_init!(
+//!     Sixlowpan<
+//!         'static,
+//!         VirtualMuxAlarm<'static, Ast>,
+//!         &'static ContextTable<'static, VirtualMuxAlarm<'static, Ast>>,
+//!     >,
+//!     Sixlowpan::new(contexts, sixlowpan_alarm)
+//! );
+//! ```
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::sixlowpan::sixlowpan_compression::{Context, ContextStore};
+use crate::net::util;
+use core::cell::Cell;
+use kernel::hil::time::{self, Frequency};
+use kernel::ReturnCode;
+
+/// Number of contexts, which the 4 bit context identifiers of LOWPAN_IPHC
+/// headers index.
+pub const MAX_CONTEXTS: usize = 16;
+
+// The lifetimes are counted down once a second
+const TICK_S: u32 = 1;
+
+/// A context of the table, with its remaining lifetime.
+#[derive(Copy, Clone, Debug)]
+pub struct ContextEntry {
+    pub context: Context,
+    /// Remaining lifetime, or `None` if the context never expires
+    pub lifetime: Option<u32>,
+}
+
+pub struct ContextTable<'a, A: time::Alarm<'a>> {
+    // Indexed by context identifier
+    entries: [Cell<Option<ContextEntry>>; MAX_CONTEXTS],
+    alarm: &'a A,
+    // Whether the alarm is set for the next tick
+    ticking: Cell<bool>,
+}
+
+impl<A: time::Alarm<'a>> ContextTable<'a, A> {
+    pub fn new(alarm: &'a A) -> ContextTable<'a, A> {
+        ContextTable {
+            entries: Default::default(),
+            alarm,
+            ticking: Cell::new(false),
+        }
+    }
+
+    /// Adds the context with the identifier `context.id`, replacing the
+    /// context the table has with the same identifier. Returns EINVAL if the
+    /// identifier or the prefix length is out of range.
+    pub fn set_context(&self, context: Context, lifetime: Option<u32>) -> ReturnCode {
+        if context.id as usize >= MAX_CONTEXTS
+            || !util::verify_prefix_len(&context.prefix, context.prefix_len)
+        {
+            return ReturnCode::EINVAL;
+        }
+        self.entries[context.id as usize].set(Some(ContextEntry { context, lifetime }));
+        if lifetime.is_some() {
+            self.start_tick();
+        }
+        ReturnCode::SUCCESS
+    }
+
+    pub fn remove_context(&self, id: u8) {
+        if let Some(entry) = self.entries.get(id as usize) {
+            entry.set(None);
+        }
+    }
+
+    pub fn get_entry(&self, id: u8) -> Option<ContextEntry> {
+        self.entries.get(id as usize).and_then(|entry| entry.get())
+    }
+
+    fn contexts<'b>(&'b self) -> impl Iterator<Item = Context> + 'b {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.get())
+            .map(|entry| entry.context)
+    }
+
+    fn has_lifetimes(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.get().map_or(false, |entry| entry.lifetime.is_some()))
+    }
+
+    fn start_tick(&self) {
+        if !self.ticking.get() {
+            self.ticking.set(true);
+            let tics = TICK_S * <A::Frequency>::frequency();
+            self.alarm.set_alarm(self.alarm.now().wrapping_add(tics));
+        }
+    }
+
+    /// Lets `seconds` elapse, and removes the contexts whose lifetime ran
+    /// out.
+    fn tick(&self, seconds: u32) {
+        for entry in self.entries.iter() {
+            entry.set(entry.get().and_then(|mut current| match current.lifetime {
+                Some(lifetime) if lifetime <= seconds => None,
+                Some(lifetime) => {
+                    current.lifetime = Some(lifetime - seconds);
+                    Some(current)
+                }
+                None => Some(current),
+            }));
+        }
+    }
+}
+
+impl<A: time::Alarm<'a>> time::AlarmClient for ContextTable<'a, A> {
+    fn fired(&self) {
+        self.ticking.set(false);
+        self.tick(TICK_S);
+        // Keeps ticking while a context has a lifetime
+        if self.has_lifetimes() {
+            self.start_tick();
+        }
+    }
+}
+
+impl<A: time::Alarm<'a>> ContextStore for ContextTable<'a, A> {
+    /// Returns the context with the longest prefix that `ip_addr` starts
+    /// with, preferring the contexts that may be used for compression.
+    fn get_context_from_addr(&self, ip_addr: IPAddr) -> Option<Context> {
+        self.contexts()
+            .filter(|ctx| util::matches_prefix(&ip_addr.0, &ctx.prefix, ctx.prefix_len))
+            .max_by_key(|ctx| (ctx.compress, ctx.prefix_len))
+    }
+
+    fn get_context_from_id(&self, ctx_id: u8) -> Option<Context> {
+        self.get_entry(ctx_id).map(|entry| entry.context)
+    }
+
+    fn get_context_from_prefix(&self, prefix: &[u8], prefix_len: u8) -> Option<Context> {
+        self.contexts()
+            .filter(|ctx| ctx.prefix_len == prefix_len)
+            .find(|ctx| util::matches_prefix(prefix, &ctx.prefix, prefix_len))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net::icmpv6::icmpv6::{ICMP6Header, ICMP6Type};
+    use crate::net::ieee802154::MacAddress;
+    use crate::net::ipv6::ip_utils::ip6_nh;
+    use crate::net::ipv6::ipv6::{IP6Header, IP6Packet, IPPayload, TransportHeader};
+    use crate::net::mock::MockAlarm;
+    use crate::net::sixlowpan::sixlowpan_compression;
+    use kernel::hil::time::{Alarm, AlarmClient};
+
+    const SRC: MacAddress = MacAddress::Short(0x0001);
+    const DST: MacAddress = MacAddress::Short(0x0002);
+    // The CID flag of the second byte of LOWPAN_IPHC
+    const IPHC_CID: u8 = 0x80;
+
+    fn prefix() -> [u8; 16] {
+        let mut prefix = [0; 16];
+        prefix[..8].copy_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 1, 0, 0]);
+        prefix
+    }
+
+    fn context(id: u8, compress: bool) -> Context {
+        Context {
+            prefix: prefix(),
+            prefix_len: 64,
+            id,
+            compress,
+        }
+    }
+
+    fn addr(mac: MacAddress) -> IPAddr {
+        let mut addr = IPAddr(prefix());
+        addr.set_iid_from_mac(mac);
+        addr
+    }
+
+    /// Compresses the header of a packet between two addresses of the
+    /// prefix, and returns the frame.
+    fn compress(contexts: &dyn ContextStore, frame: &mut [u8]) -> usize {
+        let mut payload = [0; 8];
+        let mut packet = IP6Packet::new(IPPayload::new(
+            TransportHeader::ICMP(ICMP6Header::new(ICMP6Type::Type128)),
+            &mut payload,
+        ));
+        packet.header = IP6Header::new();
+        packet.header.src_addr = addr(SRC);
+        packet.header.dst_addr = addr(DST);
+        packet.header.set_next_header(ip6_nh::ICMP);
+        packet.header.set_hop_limit(64);
+        packet.header.set_payload_len(8);
+        let (_, written) =
+            sixlowpan_compression::compress(contexts, &packet, SRC, DST, frame).unwrap();
+        written
+    }
+
+    /// Decompresses the header of a frame, and returns it.
+    fn decompress(contexts: &dyn ContextStore, frame: &[u8]) -> Result<IP6Header, ()> {
+        let mut out = [0; 64];
+        sixlowpan_compression::decompress(contexts, frame, SRC, DST, &mut out, 0, false)?;
+        Ok(IP6Header::decode(&out).done().unwrap().1)
+    }
+
+    #[test]
+    fn contexts_expire() {
+        let alarm = MockAlarm::new();
+        let contexts = ContextTable::new(&alarm);
+        contexts.set_context(context(0, true), None);
+        // Contexts that never expire do not need the alarm
+        assert!(!alarm.is_enabled());
+
+        contexts.set_context(context(1, true), Some(2));
+        assert!(alarm.advance(1000));
+        contexts.fired();
+        assert_eq!(contexts.get_entry(1).unwrap().lifetime, Some(1));
+        assert!(alarm.advance(1000));
+        contexts.fired();
+        assert!(contexts.get_entry(1).is_none());
+        assert!(contexts.get_context_from_id(1).is_none());
+        assert!(contexts.get_entry(0).is_some());
+        // Nothing is left to count down
+        assert!(!alarm.is_enabled());
+    }
+
+    #[test]
+    fn rejects_invalid_contexts() {
+        let alarm = MockAlarm::new();
+        let contexts = ContextTable::new(&alarm);
+        let mut invalid = context(MAX_CONTEXTS as u8, true);
+        assert_eq!(contexts.set_context(invalid, None), ReturnCode::EINVAL);
+        invalid.id = 1;
+        invalid.prefix_len = 16;
+        assert_eq!(contexts.set_context(invalid, None), ReturnCode::EINVAL);
+        assert!(contexts.get_entry(1).is_none());
+    }
+
+    #[test]
+    fn compresses_against_context_id() {
+        let alarm = MockAlarm::new();
+        let contexts = ContextTable::new(&alarm);
+        contexts.set_context(context(1, true), None);
+
+        let mut frame = [0; 64];
+        let len = compress(&contexts, &mut frame);
+        // The context identifiers follow LOWPAN_IPHC, whose CID flag is in
+        // the second byte
+        assert_ne!(frame[1] & IPHC_CID, 0);
+        assert_eq!(frame[2], 0x11);
+
+        let header = decompress(&contexts, &frame[..len]).unwrap();
+        assert_eq!(header.src_addr, addr(SRC));
+        assert_eq!(header.dst_addr, addr(DST));
+
+        // The frame cannot be decompressed once the context is gone
+        contexts.remove_context(1);
+        assert!(decompress(&contexts, &frame[..len]).is_err());
+    }
+
+    #[test]
+    fn compress_flag_only_limits_compression() {
+        let alarm = MockAlarm::new();
+        let sender = ContextTable::new(&alarm);
+        sender.set_context(context(1, true), None);
+        let receiver = ContextTable::new(&alarm);
+        receiver.set_context(context(1, false), None);
+
+        // The receiver does not compress against the context...
+        let mut frame = [0; 64];
+        let len = compress(&receiver, &mut frame);
+        assert_eq!(frame[1] & IPHC_CID, 0);
+        assert!(len > compress(&sender, &mut [0; 64]));
+
+        // ...but decompresses the frames of the nodes that do
+        let len = compress(&sender, &mut frame);
+        let header = decompress(&receiver, &frame[..len]).unwrap();
+        assert_eq!(header.src_addr, addr(SRC));
+        assert_eq!(header.dst_addr, addr(DST));
+    }
+}
@@ -159,8 +159,15 @@
 //
 // The RxState struct maintains the in-progress packet buffer, a bitmap
 // indicating which 8-byte chunks have not yet been received, the source/dest
-// mac address pair, datagram size and tag, and a start time (to lazily
-// expire timed-out reassembly processes).
+// mac address pair, datagram size and tag, and a start time. A reassembly is
+// identified by the source address, tag and size of its datagram (the
+// destination is always this node), and is abandoned when it is not complete
+// `FRAG_TIMEOUT` seconds after its first fragment arrived: the Sixlowpan
+// object sets its alarm for the earliest of these deadlines. When all
+// RxStates are busy, the oldest reassembly is evicted to make room for a new
+// fragmented datagram, while packets that fit in a single frame are dropped.
+// The Sixlowpan object counts these events, and the duplicate and overlapping
+// fragments it receives, in its `RxStats`.
 //
 // SixlowpanRxClient:
 // The SixlowpanRxClient trait has a single function, `receive`. Upper layers
@@ -221,7 +228,8 @@
 //
 //   * On imix, the reciever sometimes fails to receive a fragment. This
 //     occurs below the Mac layer, and prevents the packet from being fully
-//     reassembled.
+//     reassembled. Its RxState is freed when the reassembly times out, or
+//     when it is the oldest one and another packet needs an RxState.
 //
 
 use crate::ieee802154::device::{MacDevice, RxClient};
@@ -240,10 +248,27 @@ use kernel::hil::radio;
 use kernel::hil::time;
 use kernel::hil::time::Frequency;
 use kernel::ReturnCode;
+use kernel::{trace, warn};
 
 // Reassembly timeout in seconds
 const FRAG_TIMEOUT: u32 = 60;
 
+/// Counts of the events of reassembly, which help diagnose lossy links.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RxStats {
+    /// Fragmented packets fully reassembled
+    pub reassembled: u32,
+    /// Reassemblies abandoned after `FRAG_TIMEOUT` seconds
+    pub timeouts: u32,
+    /// Reassemblies abandoned to make room for a new fragmented datagram
+    pub evictions: u32,
+    /// Fragments that overlap part of the datagram already received, which
+    /// make the reassembly fail
+    pub overlaps: u32,
+    /// Fragments received again, which are ignored
+    pub duplicates: u32,
+}
+
 /// Objects that implement this trait can set themselves to be the client
 /// for the [Sixlowpan](struct.Sixlowpan.html) struct, and will then receive
 /// a callback once an IPv6 packet has been fully reassembled.
@@ -300,6 +325,7 @@ pub trait SixlowpanState<'a> {
     fn get_ctx_store(&self) -> &dyn ContextStore;
     fn add_rx_state(&self, rx_state: &'a RxState<'a>);
     fn set_rx_client(&'a self, client: &'a dyn SixlowpanRxClient);
+    fn get_rx_stats(&self) -> RxStats;
 }
 
 /// Tracks the compression state for a single IPv6 packet.
@@ -461,6 +487,12 @@ impl TxState<'a> {
         self.busy.set(true);
         self.dgram_size.set(ip6_packet.get_total_len());
         self.dgram_tag.set(self.sixlowpan.next_dgram_tag());
+        trace!(
+            "sending datagram {} of {} bytes to {:?}",
+            self.dgram_tag.get(),
+            self.dgram_size.get(),
+            self.dst_mac_addr.get()
+        );
         self.prepare_first_fragment(ip6_packet, frame, ctx_store)
     }
 
@@ -481,7 +513,10 @@ impl TxState<'a> {
                 self.dst_mac_addr.get(),
                 &mut lowpan_packet,
             ) {
-                Err(_) => return Err((ReturnCode::FAIL, frame.into_buf())),
+                Err(_) => {
+                    warn!("cannot compress datagram {}", self.dgram_tag.get());
+                    return Err((ReturnCode::FAIL, frame.into_buf()));
+                }
                 Ok(result) => result,
             }
         };
@@ -504,6 +539,7 @@ impl TxState<'a> {
             frame.append_payload(&lowpan_packet[0..written]);
             remaining_capacity -= written;
         } else {
+            warn!("compressed headers do not fit in the first fragment");
             return Err((ReturnCode::ESIZE, frame.into_buf()));
         }
 
@@ -573,8 +609,9 @@ impl TxState<'a> {
             // TODO: Note that in order to serialize the headers, we need to
             // statically allocate room on the stack. However, we do not know
             // how many additional headers we have until runtime. This
-            // functionality should be fixed in the future.
-            let mut headers = [0 as u8; 60];
+            // functionality should be fixed in the future. The buffer fits
+            // the IPv6 header and a TCP header with the largest options.
+            let mut headers = [0 as u8; 100];
             ip6_packet.encode(&mut headers);
             frame.append_payload(&headers[dgram_offset..dgram_offset + headers_to_write]);
             payload_len -= headers_to_write;
@@ -666,28 +703,16 @@ impl RxState<'a> {
         }
     }
 
-    fn is_my_fragment(
-        &self,
-        src_mac_addr: MacAddress,
-        dst_mac_addr: MacAddress,
-        dgram_size: u16,
-        dgram_tag: u16,
-    ) -> bool {
+    fn is_my_fragment(&self, src_mac_addr: MacAddress, dgram_size: u16, dgram_tag: u16) -> bool {
         self.busy.get()
             && (self.dgram_tag.get() == dgram_tag)
             && (self.dgram_size.get() == dgram_size)
             && (self.src_mac_addr.get() == src_mac_addr)
-            && (self.dst_mac_addr.get() == dst_mac_addr)
     }
 
-    // Checks if a given RxState is free or expired (and thus, can be freed).
-    // This function implements the reassembly timeout for 6LoWPAN lazily.
-    fn is_busy(&self, frequency: u32, current_time: u32) -> bool {
-        let expired = current_time >= (self.start_time.get() + FRAG_TIMEOUT * frequency);
-        if expired {
-            self.end_receive(None, ReturnCode::FAIL);
-        }
-        self.busy.get()
+    // The number of tics since the reassembly started
+    fn age(&self, current_tics: u32) -> u32 {
+        current_tics.wrapping_sub(self.start_time.get())
     }
 
     fn start_receive(
@@ -709,7 +734,7 @@ impl RxState<'a> {
 
     // This function assumes that the payload is a slice starting from the
     // actual payload (no 802.15.4 headers, no fragmentation headers), and
-    // returns true if the packet is completely reassembled.
+    // returns whether the packet is completely reassembled.
     fn receive_next_frame(
         &self,
         payload: &[u8],
@@ -717,7 +742,7 @@ impl RxState<'a> {
         dgram_size: u16,
         dgram_offset: usize,
         ctx_store: &dyn ContextStore,
-    ) -> Result<bool, ReturnCode> {
+    ) -> Result<FragmentStatus, ReturnCode> {
         let mut packet = self.packet.take().ok_or(ReturnCode::ENOMEM)?;
         let uncompressed_len = if dgram_offset == 0 {
             let (consumed, written) = sixlowpan_compression::decompress(
@@ -729,7 +754,10 @@ impl RxState<'a> {
                 dgram_size,
                 true,
             )
-            .map_err(|_| ReturnCode::FAIL)?;
+            .map_err(|_| {
+                warn!("cannot decompress datagram {}", self.dgram_tag.get());
+                ReturnCode::FAIL
+            })?;
             let remaining = payload_len - consumed;
             packet[written..written + remaining]
                 .copy_from_slice(&payload[consumed..consumed + remaining]);
@@ -740,17 +768,31 @@ impl RxState<'a> {
             payload_len
         };
         self.packet.replace(packet);
-        if !self.bitmap.map_or(false, |bitmap| {
-            bitmap.set_bits(dgram_offset / 8, (dgram_offset + uncompressed_len) / 8)
-        }) {
-            // If this fails, we received an overlapping fragment. We can simply
-            // drop the packet in this case.
-            Err(ReturnCode::FAIL)
-        } else {
-            self.bitmap
-                .map(|bitmap| bitmap.is_complete((dgram_size as usize) / 8))
-                .ok_or(ReturnCode::FAIL)
-        }
+        let (start_idx, end_idx) = (dgram_offset / 8, (dgram_offset + uncompressed_len) / 8);
+        self.bitmap
+            .map(|bitmap| match bitmap.count_bits(start_idx, end_idx) {
+                0 => {
+                    bitmap.set_bits(start_idx, end_idx);
+                    if bitmap.is_complete((dgram_size as usize) / 8) {
+                        FragmentStatus::Complete
+                    } else {
+                        FragmentStatus::Incomplete
+                    }
+                }
+                // A retransmission of a fragment we already have, e.g. after
+                // a lost acknowledgement, which left the buffer unchanged
+                count if count == end_idx - start_idx => FragmentStatus::Duplicate,
+                _ => {
+                    // We can simply drop the packet in this case.
+                    warn!(
+                        "overlapping fragment at offset {} of datagram {}",
+                        dgram_offset,
+                        self.dgram_tag.get()
+                    );
+                    FragmentStatus::Overlap
+                }
+            })
+            .ok_or(ReturnCode::FAIL)
     }
 
     fn end_receive(&self, client: Option<&'a dyn SixlowpanRxClient>, result: ReturnCode) {
@@ -771,6 +813,15 @@ impl RxState<'a> {
     }
 }
 
+// The outcome of receiving a fragment
+#[derive(Copy, Clone, PartialEq)]
+enum FragmentStatus {
+    Incomplete,
+    Complete,
+    Duplicate,
+    Overlap,
+}
+
 /// Sends a receives IPv6 packets via 6loWPAN compression and fragmentation.
 ///
 /// # Initialization
@@ -782,7 +833,8 @@ impl RxState<'a> {
 /// packets concurrently.
 ///
 /// Finally, `set_client` controls the client that will receive transmission
-/// completion and reception callbacks.
+/// completion and reception callbacks. The `Sixlowpan` must also be the client
+/// of its alarm, which times out reassemblies.
 pub struct Sixlowpan<'a, A: time::Alarm<'a>, C: ContextStore> {
     pub ctx_store: C,
     clock: &'a A,
@@ -791,6 +843,7 @@ pub struct Sixlowpan<'a, A: time::Alarm<'a>, C: ContextStore> {
 
     // Receive state
     rx_states: List<'a, RxState<'a>>,
+    rx_stats: Cell<RxStats>,
 }
 
 // This function is called after receiving a frame
@@ -844,6 +897,17 @@ impl<A: time::Alarm<'a>, C: ContextStore> SixlowpanState<'a> for Sixlowpan<'a, A
     fn set_rx_client(&'a self, client: &'a dyn SixlowpanRxClient) {
         self.rx_client.set(Some(client));
     }
+
+    fn get_rx_stats(&self) -> RxStats {
+        self.rx_stats.get()
+    }
+}
+
+impl<A: time::Alarm<'a>, C: ContextStore> time::AlarmClient for Sixlowpan<'a, A, C> {
+    fn fired(&self) {
+        self.expire_rx_states();
+        self.set_rx_timeout();
+    }
 }
 
 impl<A: time::Alarm<'a>, C: ContextStore> Sixlowpan<'a, A, C> {
@@ -858,8 +922,9 @@ impl<A: time::Alarm<'a>, C: ContextStore> Sixlowpan<'a, A, C> {
     /// frame.
     ///
     /// * `clock` - A implementation of `Alarm` used for tracking the timing of
-    /// frame arrival. The clock should be continue running during sleep and
-    /// have an accuracy of at least 60 seconds.
+    /// frame arrival and timing out reassemblies. The clock should be continue
+    /// running during sleep and have an accuracy of at least 60 seconds, and
+    /// its client must be set to the `Sixlowpan`.
     pub fn new(ctx_store: C, clock: &'a A) -> Sixlowpan<'a, A, C> {
         Sixlowpan {
             ctx_store: ctx_store,
@@ -868,9 +933,69 @@ impl<A: time::Alarm<'a>, C: ContextStore> Sixlowpan<'a, A, C> {
             rx_client: Cell::new(None),
 
             rx_states: List::new(),
+            rx_stats: Cell::new(RxStats::default()),
         }
     }
 
+    fn count(&self, counter: fn(&mut RxStats) -> &mut u32) {
+        let mut stats = self.rx_stats.get();
+        let value = counter(&mut stats);
+        *value = value.wrapping_add(1);
+        self.rx_stats.set(stats);
+    }
+
+    fn rx_timeout(&self) -> u32 {
+        FRAG_TIMEOUT * A::Frequency::frequency()
+    }
+
+    // Frees the RxStates whose reassembly timed out
+    fn expire_rx_states(&self) {
+        let now = self.clock.now();
+        for state in self.rx_states.iter() {
+            if state.busy.get() && state.age(now) >= self.rx_timeout() {
+                warn!("reassembly of datagram {} timed out", state.dgram_tag.get());
+                self.count(|stats| &mut stats.timeouts);
+                state.end_receive(None, ReturnCode::FAIL);
+            }
+        }
+    }
+
+    // Sets the alarm for the earliest timeout of the reassemblies in progress
+    fn set_rx_timeout(&self) {
+        let now = self.clock.now();
+        let oldest = self
+            .rx_states
+            .iter()
+            .filter(|state| state.busy.get())
+            .max_by_key(|state| state.age(now));
+        if let Some(state) = oldest {
+            self.clock
+                .set_alarm(state.start_time.get().wrapping_add(self.rx_timeout()));
+        }
+    }
+
+    // Returns a free RxState, if any
+    fn get_free_rx_state(&self) -> Option<&RxState<'a>> {
+        self.expire_rx_states();
+        self.rx_states.iter().find(|state| !state.busy.get())
+    }
+
+    // Abandons the oldest reassembly and returns its RxState, when they are
+    // all busy and a new fragmented datagram arrives
+    fn evict_rx_state(&self) -> Option<&RxState<'a>> {
+        let now = self.clock.now();
+        let oldest = self.rx_states.iter().max_by_key(|state| state.age(now));
+        oldest.map(|state| {
+            warn!(
+                "no free reassembly state, evicting datagram {}",
+                state.dgram_tag.get()
+            );
+            self.count(|stats| &mut stats.evictions);
+            state.end_receive(None, ReturnCode::FAIL);
+            state
+        })
+    }
+
     fn receive_frame(
         &self,
         packet: &[u8],
@@ -906,10 +1031,13 @@ impl<A: time::Alarm<'a>, C: ContextStore> Sixlowpan<'a, A, C> {
         src_mac_addr: MacAddress,
         dst_mac_addr: MacAddress,
     ) -> (Option<&RxState<'a>>, ReturnCode) {
-        let rx_state = self
-            .rx_states
-            .iter()
-            .find(|state| !state.is_busy(self.clock.now(), A::Frequency::frequency()));
+        let rx_state = self.get_free_rx_state();
+        if rx_state.is_none() {
+            warn!(
+                "no reassembly state, dropping packet from {:?}",
+                src_mac_addr
+            );
+        }
         rx_state.map_or((None, ReturnCode::ENOMEM), |state| {
             state.start_receive(
                 src_mac_addr,
@@ -944,13 +1072,20 @@ impl<A: time::Alarm<'a>, C: ContextStore> Sixlowpan<'a, A, C> {
                         state.dgram_size.set((written + remaining) as u16);
                     }
                     Err(_) => {
-                        return (None, ReturnCode::FAIL);
+                        warn!("cannot decompress packet from {:?}", src_mac_addr);
+                        state.packet.replace(packet);
+                        return (Some(state), ReturnCode::FAIL);
                     }
                 }
             } else {
                 packet[0..payload_len].copy_from_slice(&payload[0..payload_len]);
             }
             state.packet.replace(packet);
+            trace!(
+                "received {} byte packet from {:?}",
+                state.dgram_size.get(),
+                src_mac_addr
+            );
             (Some(state), ReturnCode::SUCCESS)
         })
     }
@@ -968,18 +1103,22 @@ impl<A: time::Alarm<'a>, C: ContextStore> Sixlowpan<'a, A, C> {
         dgram_tag: u16,
         dgram_offset: usize,
     ) -> (Option<&RxState<'a>>, ReturnCode) {
+        trace!(
+            "received fragment at offset {} of datagram {} ({} bytes) from {:?}",
+            dgram_offset,
+            dgram_tag,
+            dgram_size,
+            src_mac_addr
+        );
         // First try to find an rx_state in the middle of assembly
         let mut rx_state = self
             .rx_states
             .iter()
-            .find(|state| state.is_my_fragment(src_mac_addr, dst_mac_addr, dgram_size, dgram_tag));
+            .find(|state| state.is_my_fragment(src_mac_addr, dgram_size, dgram_tag));
 
-        // Else find a free state
+        // Else find a free state, or make room for this datagram
         if rx_state.is_none() {
-            rx_state = self
-                .rx_states
-                .iter()
-                .find(|state| !state.is_busy(self.clock.now(), A::Frequency::frequency()));
+            rx_state = self.get_free_rx_state().or_else(|| self.evict_rx_state());
             // Initialize new state
             rx_state.map(|state| {
                 state.start_receive(
@@ -991,8 +1130,13 @@ impl<A: time::Alarm<'a>, C: ContextStore> Sixlowpan<'a, A, C> {
                 )
             });
             if rx_state.is_none() {
+                warn!(
+                    "no reassembly state, dropping fragment of datagram {}",
+                    dgram_tag
+                );
                 return (None, ReturnCode::ENOMEM);
             }
+            self.set_rx_timeout();
         }
         rx_state.map_or((None, ReturnCode::ENOMEM), |state| {
             // Returns true if the full packet is reassembled
@@ -1006,15 +1150,22 @@ impl<A: time::Alarm<'a>, C: ContextStore> Sixlowpan<'a, A, C> {
             match res {
                 // Some error occurred
                 Err(_) => (Some(state), ReturnCode::FAIL),
-                Ok(complete) => {
-                    if complete {
-                        // Packet fully reassembled
-                        (Some(state), ReturnCode::SUCCESS)
-                    } else {
-                        // Packet not fully reassembled
-                        (None, ReturnCode::SUCCESS)
-                    }
+                Ok(FragmentStatus::Overlap) => {
+                    self.count(|stats| &mut stats.overlaps);
+                    (Some(state), ReturnCode::FAIL)
                 }
+                Ok(FragmentStatus::Duplicate) => {
+                    self.count(|stats| &mut stats.duplicates);
+                    (None, ReturnCode::SUCCESS)
+                }
+                Ok(FragmentStatus::Complete) => {
+                    // Packet fully reassembled
+                    trace!("reassembled datagram {}", dgram_tag);
+                    self.count(|stats| &mut stats.reassembled);
+                    (Some(state), ReturnCode::SUCCESS)
+                }
+                // Packet not fully reassembled
+                Ok(FragmentStatus::Incomplete) => (None, ReturnCode::SUCCESS),
             }
         })
     }
@@ -1031,3 +1182,219 @@ impl<A: time::Alarm<'a>, C: ContextStore> Sixlowpan<'a, A, C> {
         // TODO: Need to get buffer back from Mac layer on disassociation
     }
 }
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use crate::net::mock::{buf, MockAlarm};
+    use crate::net::sixlowpan::sixlowpan_context::ContextTable;
+    use core::cell::RefCell;
+    use kernel::hil::time::{Alarm, AlarmClient};
+    use std::vec::Vec;
+
+    type TestSixlowpan<'a> = Sixlowpan<'a, MockAlarm, ContextTable<'a, MockAlarm>>;
+
+    const SRC: MacAddress = MacAddress::Short(0x0001);
+    const DST: MacAddress = MacAddress::Short(0x0002);
+    // The datagrams are a 40 byte IPv6 header and 24 bytes of payload, sent
+    // in a first fragment with the compressed header and 16 bytes of
+    // payload, and a second fragment with the last 8 bytes
+    const DGRAM_SIZE: u16 = 64;
+    // LOWPAN_IPHC with everything elided but the next header, No Next
+    // Header
+    const IPHC: [u8; 3] = [0x7b, 0x33, 59];
+    const TIMEOUT_MS: u32 = FRAG_TIMEOUT * 1000;
+
+    struct Client {
+        received: RefCell<Vec<(usize, ReturnCode)>>,
+    }
+
+    impl SixlowpanRxClient for Client {
+        fn receive(&self, _buf: &[u8], len: usize, result: ReturnCode) {
+            self.received.borrow_mut().push((len, result));
+        }
+    }
+
+    struct Mocks<'a> {
+        alarm: MockAlarm,
+        contexts_alarm: MockAlarm,
+        client: Client,
+        rx_states: Vec<RxState<'a>>,
+    }
+
+    impl Mocks<'a> {
+        fn new(rx_states: usize) -> Mocks<'a> {
+            Mocks {
+                alarm: MockAlarm::new(),
+                contexts_alarm: MockAlarm::new(),
+                client: Client {
+                    received: RefCell::new(Vec::new()),
+                },
+                rx_states: (0..rx_states).map(|_| RxState::new(buf(128))).collect(),
+            }
+        }
+
+        fn sixlowpan(&'a self) -> TestSixlowpan<'a> {
+            let contexts = ContextTable::new(&self.contexts_alarm);
+            let sixlowpan = Sixlowpan::new(contexts, &self.alarm);
+            for state in self.rx_states.iter() {
+                sixlowpan.add_rx_state(state);
+            }
+            sixlowpan.rx_client.set(Some(&self.client));
+            sixlowpan
+        }
+
+        fn received(&self) -> Vec<(usize, ReturnCode)> {
+            self.client.received.replace(Vec::new())
+        }
+    }
+
+    fn first_fragment(tag: u16) -> Vec<u8> {
+        let mut frame = std::vec![0; lowpan_frag::FRAG1_HDR_SIZE];
+        set_frag_hdr(DGRAM_SIZE, tag, 0, &mut frame, true);
+        frame.extend_from_slice(&IPHC);
+        frame.extend_from_slice(&[1; 16]);
+        frame
+    }
+
+    fn next_fragment(tag: u16, offset: usize, len: usize) -> Vec<u8> {
+        let mut frame = std::vec![0; lowpan_frag::FRAGN_HDR_SIZE];
+        set_frag_hdr(DGRAM_SIZE, tag, offset, &mut frame, false);
+        frame.extend(std::iter::repeat(2).take(len));
+        frame
+    }
+
+    fn last_fragment(tag: u16) -> Vec<u8> {
+        next_fragment(tag, 56, 8)
+    }
+
+    // Receives a frame, as `RxClient::receive` does once it removed the
+    // 802.15.4 header
+    fn receive(sixlowpan: &TestSixlowpan, frame: &[u8]) {
+        let (state, result) = sixlowpan.receive_frame(frame, frame.len(), SRC, DST);
+        if let Some(state) = state {
+            state.end_receive(sixlowpan.rx_client.get(), result);
+        }
+    }
+
+    fn busy_states(sixlowpan: &TestSixlowpan) -> usize {
+        sixlowpan
+            .rx_states
+            .iter()
+            .filter(|state| state.busy.get())
+            .count()
+    }
+
+    #[test]
+    fn reassembles_fragments() {
+        let mocks = Mocks::new(1);
+        let sixlowpan = mocks.sixlowpan();
+        // In any order
+        receive(&sixlowpan, &last_fragment(7));
+        assert!(mocks.received().is_empty());
+        receive(&sixlowpan, &first_fragment(7));
+        assert_eq!(mocks.received(), [(64, ReturnCode::SUCCESS)]);
+        assert_eq!(busy_states(&sixlowpan), 0);
+        assert_eq!(sixlowpan.get_rx_stats().reassembled, 1);
+
+        // Packets that are not fragmented
+        let mut frame = IPHC.to_vec();
+        frame.extend_from_slice(&[3; 5]);
+        receive(&sixlowpan, &frame);
+        assert_eq!(mocks.received(), [(45, ReturnCode::SUCCESS)]);
+    }
+
+    #[test]
+    fn ignores_duplicates_and_drops_overlaps() {
+        let mocks = Mocks::new(1);
+        let sixlowpan = mocks.sixlowpan();
+        receive(&sixlowpan, &first_fragment(7));
+        receive(&sixlowpan, &first_fragment(7));
+        assert!(mocks.received().is_empty());
+        assert_eq!(sixlowpan.get_rx_stats().duplicates, 1);
+        receive(&sixlowpan, &last_fragment(7));
+        assert_eq!(mocks.received(), [(64, ReturnCode::SUCCESS)]);
+
+        // A fragment that covers the end of the first one and more
+        receive(&sixlowpan, &first_fragment(8));
+        receive(&sixlowpan, &next_fragment(8, 48, 16));
+        assert_eq!(mocks.received(), [(64, ReturnCode::FAIL)]);
+        assert_eq!(sixlowpan.get_rx_stats().overlaps, 1);
+        assert_eq!(busy_states(&sixlowpan), 0);
+    }
+
+    #[test]
+    fn times_out_reassemblies() {
+        let mocks = Mocks::new(2);
+        let sixlowpan = mocks.sixlowpan();
+        receive(&sixlowpan, &first_fragment(1));
+        assert!(mocks.alarm.is_enabled());
+        assert!(!mocks.alarm.advance(TIMEOUT_MS / 2));
+        receive(&sixlowpan, &first_fragment(2));
+
+        // The alarm is set for the oldest reassembly, then for the next one
+        assert!(mocks.alarm.advance(TIMEOUT_MS / 2));
+        sixlowpan.fired();
+        assert_eq!(busy_states(&sixlowpan), 1);
+        assert_eq!(sixlowpan.get_rx_stats().timeouts, 1);
+        assert!(!mocks.alarm.advance(TIMEOUT_MS / 2 - 1));
+        receive(&sixlowpan, &last_fragment(2));
+        assert_eq!(mocks.received(), [(64, ReturnCode::SUCCESS)]);
+
+        // Abandoned reassemblies are not passed to the client, and their
+        // fragments start new ones
+        receive(&sixlowpan, &last_fragment(1));
+        assert!(mocks.received().is_empty());
+        assert_eq!(busy_states(&sixlowpan), 1);
+        assert!(mocks.alarm.advance(TIMEOUT_MS));
+        sixlowpan.fired();
+        assert_eq!(busy_states(&sixlowpan), 0);
+        assert_eq!(sixlowpan.get_rx_stats().timeouts, 2);
+        assert!(!mocks.alarm.is_enabled());
+        assert!(mocks.received().is_empty());
+    }
+
+    #[test]
+    fn evicts_the_oldest_reassembly() {
+        let mocks = Mocks::new(2);
+        let sixlowpan = mocks.sixlowpan();
+        receive(&sixlowpan, &first_fragment(1));
+        mocks.alarm.advance(10);
+        receive(&sixlowpan, &first_fragment(2));
+        mocks.alarm.advance(10);
+        receive(&sixlowpan, &first_fragment(3));
+        assert_eq!(sixlowpan.get_rx_stats().evictions, 1);
+        assert_eq!(busy_states(&sixlowpan), 2);
+
+        receive(&sixlowpan, &last_fragment(2));
+        receive(&sixlowpan, &last_fragment(3));
+        assert_eq!(
+            mocks.received(),
+            [(64, ReturnCode::SUCCESS), (64, ReturnCode::SUCCESS)]
+        );
+        // The first datagram starts over
+        receive(&sixlowpan, &last_fragment(1));
+        assert!(mocks.received().is_empty());
+        assert_eq!(busy_states(&sixlowpan), 1);
+    }
+
+    #[test]
+    fn drops_packets_when_all_states_are_busy() {
+        let mocks = Mocks::new(1);
+        let sixlowpan = mocks.sixlowpan();
+        receive(&sixlowpan, &first_fragment(1));
+        let mut frame = IPHC.to_vec();
+        frame.extend_from_slice(&[3; 5]);
+        receive(&sixlowpan, &frame);
+        assert!(mocks.received().is_empty());
+        assert_eq!(sixlowpan.get_rx_stats().evictions, 0);
+
+        // The reassembly goes on
+        receive(&sixlowpan, &last_fragment(1));
+        assert_eq!(mocks.received(), [(64, ReturnCode::SUCCESS)]);
+        receive(&sixlowpan, &frame);
+        assert_eq!(mocks.received(), [(45, ReturnCode::SUCCESS)]);
+    }
+}
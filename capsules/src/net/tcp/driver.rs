@@ -0,0 +1,343 @@
+//! TCP userspace interface.
+//!
+//! Lets each process open one TCP connection at a time, either to a peer
+//! (connect) or from a peer (listen), and send and receive data on it. The
+//! connections and their buffers are owned by a `TCPStack`; the driver copies
+//! data between them and the buffers of the processes.
+//!
+//! Sending and receiving never block: `send` queues as much data as fits in
+//! the send buffer of the connection and returns the number of bytes queued,
+//! and `receive` returns the data received so far. Callbacks tell the process
+//! when there is new data and when there is space to send more.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::tcp::tcp_stack::{TCPClient, TCPStack};
+use core::cmp::min;
+use kernel::{AppId, AppSlice, Callback, Driver, Grant, ReadOnly, ReturnCode, Shared};
+
+/// Syscall number
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Tcp as usize;
+
+/// Size of an endpoint in the config buffer: an IPv6 address followed by a
+/// port in network byte order.
+pub const ENDPOINT_LEN: usize = 18;
+
+/// Events reported by the callback of subscribe number 2.
+mod event {
+    pub const CONNECTED: usize = 0;
+    pub const PEER_CLOSED: usize = 1;
+    pub const CLOSED: usize = 2;
+}
+
+#[derive(Default)]
+pub struct App {
+    rx_callback: Option<Callback>,
+    tx_callback: Option<Callback>,
+    event_callback: Option<Callback>,
+    app_read: Option<AppSlice<Shared, u8>>,
+    app_write: Option<AppSlice<ReadOnly, u8>>,
+    app_cfg: Option<AppSlice<Shared, u8>>,
+    connection: Option<usize>,
+}
+
+pub struct TCPDriver<'a> {
+    stack: &'a dyn TCPStack<'a>,
+    apps: Grant<App>,
+}
+
+impl<'a> TCPDriver<'a> {
+    pub fn new(stack: &'a dyn TCPStack<'a>, grant: Grant<App>) -> TCPDriver<'a> {
+        TCPDriver { stack, apps: grant }
+    }
+
+    /// Utility function to perform an action on an app in a system call.
+    #[inline]
+    fn do_with_app<F>(&self, appid: AppId, closure: F) -> ReturnCode
+    where
+        F: FnOnce(&mut App) -> ReturnCode,
+    {
+        self.apps
+            .enter(appid, |app, _| closure(app))
+            .unwrap_or_else(|err| err.into())
+    }
+
+    /// Performs an action on the app whose connection is `id`.
+    fn do_with_connection<F>(&self, id: usize, closure: F)
+    where
+        F: Fn(&mut App),
+    {
+        self.apps.each(|app| {
+            if app.connection == Some(id) {
+                closure(app);
+            }
+        });
+    }
+
+    /// Gives the app's previous connection back to the stack before it opens
+    /// a new one. Returns EBUSY if that connection is still open.
+    fn release_connection(&self, app: &mut App) -> ReturnCode {
+        match app.connection {
+            Some(id) => {
+                let result = self.stack.release(id);
+                if result == ReturnCode::SUCCESS {
+                    app.connection = None;
+                }
+                result
+            }
+            None => ReturnCode::SUCCESS,
+        }
+    }
+
+    fn parse_endpoint(buf: &[u8]) -> (IPAddr, u16) {
+        let mut addr = IPAddr::new();
+        addr.0.copy_from_slice(&buf[..16]);
+        let port = (buf[16] as u16) << 8 | buf[17] as u16;
+        (addr, port)
+    }
+
+    fn schedule_event(&self, id: usize, event: usize, result: ReturnCode) {
+        self.do_with_connection(id, |app| {
+            app.event_callback
+                .map(|mut cb| cb.schedule(event, usize::from(result), 0));
+        });
+    }
+}
+
+impl<'a> Driver for TCPDriver<'a> {
+    /// Setup buffers to read/write from.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Read buffer. `receive` copies received data into it.
+    /// - `1`: Write buffer. Contains the data to send. Prefer
+    ///        `allow_readonly`.
+    /// - `2`: Config buffer. Contains the endpoint of the peer for `connect`
+    ///        and receives it for `get remote endpoint`: a 16 byte IPv6
+    ///        address followed by a 2 byte port in network byte order.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            0 | 1 | 2 => self.do_with_app(appid, |app| {
+                match allow_num {
+                    0 => app.app_read = slice,
+                    1 => app.app_write = slice.map(AppSlice::from),
+                    2 => app.app_cfg = slice,
+                    _ => {}
+                }
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup buffers to read from.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `1`: Write buffer. Contains the data to send.
+    fn allow_readonly(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<ReadOnly, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            1 => self.do_with_app(appid, |app| {
+                app.app_write = slice;
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Data was received and can be read with `receive`.
+    /// - `1`: The peer acknowledged data, so there is space to send more.
+    /// - `2`: Connection events. The first argument is `0` when the
+    ///        connection is established, `1` when the peer closed its side of
+    ///        the connection, and `2` when the connection is closed, with the
+    ///        result in the second argument: SUCCESS if both sides closed it,
+    ///        ECANCEL if the peer reset it and ENOACK if the peer stopped
+    ///        responding.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 | 1 | 2 => self.do_with_app(app_id, |app| {
+                match subscribe_num {
+                    0 => app.rx_callback = callback,
+                    1 => app.tx_callback = callback,
+                    2 => app.event_callback = callback,
+                    _ => {}
+                }
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// TCP control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Connect to the endpoint in the config buffer. Returns EBUSY if
+    ///        the process already has an open connection, EINVAL if the
+    ///        config buffer does not hold an endpoint and ENOMEM if all
+    ///        connections are in use.
+    /// - `2`: Listen for a connection on the port `arg1`. Returns the same
+    ///        errors as `connect`.
+    /// - `3`: Send the first `arg1` bytes of the write buffer. Returns the
+    ///        number of bytes queued, which is smaller than `arg1` if the send
+    ///        buffer of the connection is full.
+    /// - `4`: Receive at most `arg1` bytes into the read buffer. Returns the
+    ///        number of bytes received.
+    /// - `5`: Close the connection once the queued data is sent.
+    /// - `6`: Reset the connection.
+    /// - `7`: Write the endpoint of the peer into the config buffer.
+    fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+
+            1 => self.do_with_app(appid, |app| {
+                let endpoint = match app.app_cfg {
+                    Some(ref cfg) if cfg.len() >= ENDPOINT_LEN => {
+                        Self::parse_endpoint(cfg.as_ref())
+                    }
+                    _ => return ReturnCode::EINVAL,
+                };
+                let result = self.release_connection(app);
+                if result != ReturnCode::SUCCESS {
+                    return result;
+                }
+                match self.stack.connect(endpoint.0, endpoint.1, Some(appid)) {
+                    Ok(id) => {
+                        app.connection = Some(id);
+                        ReturnCode::SUCCESS
+                    }
+                    Err(err) => err,
+                }
+            }),
+
+            2 => self.do_with_app(appid, |app| {
+                if arg1 == 0 || arg1 > 0xffff {
+                    return ReturnCode::EINVAL;
+                }
+                let result = self.release_connection(app);
+                if result != ReturnCode::SUCCESS {
+                    return result;
+                }
+                match self.stack.listen(arg1 as u16, Some(appid)) {
+                    Ok(id) => {
+                        app.connection = Some(id);
+                        ReturnCode::SUCCESS
+                    }
+                    Err(err) => err,
+                }
+            }),
+
+            3 => self.do_with_app(appid, |app| {
+                let id = match app.connection {
+                    Some(id) => id,
+                    None => return ReturnCode::EINVAL,
+                };
+                let result = app.app_write.as_ref().map(|data| {
+                    let len = min(arg1, data.len());
+                    self.stack.send(id, &data.as_ref()[..len])
+                });
+                match result {
+                    Some(Ok(len)) => ReturnCode::SuccessWithValue { value: len },
+                    Some(Err(err)) => err,
+                    None => ReturnCode::ENOMEM,
+                }
+            }),
+
+            4 => self.do_with_app(appid, |app| {
+                let id = match app.connection {
+                    Some(id) => id,
+                    None => return ReturnCode::EINVAL,
+                };
+                let result = app.app_read.as_mut().map(|buf| {
+                    let len = min(arg1, buf.len());
+                    self.stack.receive(id, &mut buf.as_mut()[..len])
+                });
+                match result {
+                    Some(Ok(len)) => ReturnCode::SuccessWithValue { value: len },
+                    Some(Err(err)) => err,
+                    None => ReturnCode::ENOMEM,
+                }
+            }),
+
+            5 | 6 => self.do_with_app(appid, |app| match app.connection {
+                Some(id) if command_num == 5 => self.stack.close(id),
+                Some(id) => self.stack.abort(id),
+                None => ReturnCode::EINVAL,
+            }),
+
+            7 => self.do_with_app(appid, |app| {
+                let endpoint = match app.connection {
+                    Some(id) => self.stack.remote_endpoint(id),
+                    None => None,
+                };
+                match (endpoint, app.app_cfg.as_mut()) {
+                    (Some((addr, port)), Some(cfg)) if cfg.len() >= ENDPOINT_LEN => {
+                        let cfg = cfg.as_mut();
+                        cfg[..16].copy_from_slice(&addr.0);
+                        cfg[16] = (port >> 8) as u8;
+                        cfg[17] = port as u8;
+                        ReturnCode::SUCCESS
+                    }
+                    (None, _) => ReturnCode::EINVAL,
+                    (_, _) => ReturnCode::ESIZE,
+                }
+            }),
+
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
+
+impl<'a> TCPClient for TCPDriver<'a> {
+    fn connected(&self, id: usize) {
+        self.schedule_event(id, event::CONNECTED, ReturnCode::SUCCESS);
+    }
+
+    fn received(&self, id: usize) {
+        self.do_with_connection(id, |app| {
+            app.rx_callback.map(|mut cb| cb.schedule(0, 0, 0));
+        });
+    }
+
+    fn sent(&self, id: usize) {
+        self.do_with_connection(id, |app| {
+            app.tx_callback.map(|mut cb| cb.schedule(0, 0, 0));
+        });
+    }
+
+    fn peer_closed(&self, id: usize) {
+        self.schedule_event(id, event::PEER_CLOSED, ReturnCode::SUCCESS);
+    }
+
+    fn closed(&self, id: usize, result: ReturnCode) {
+        self.schedule_event(id, event::CLOSED, result);
+    }
+
+    fn owner_uses(&self, id: usize, owner: AppId) -> bool {
+        // A process that is gone has no grant, and one that restarted has a
+        // new grant, without the connection.
+        self.apps.grant(owner).map_or(false, |grant| {
+            grant.enter(|app, _| app.connection == Some(id))
+        })
+    }
+}
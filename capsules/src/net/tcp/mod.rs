@@ -0,0 +1,6 @@
+pub mod driver;
+pub mod tcp;
+pub mod tcp_stack;
+
+pub use self::driver::TCPDriver;
+pub use self::driver::DRIVER_NUM;
@@ -0,0 +1,350 @@
+//! This file contains the structs and methods associated with the TCP header.
+//! This includes getters and setters for the various header fields, as well
+//! as the encode/decode functionality required for serializing the struct
+//! for transmission.
+//!
+//! The only option supported is the maximum segment size, which is sent with
+//! SYN segments. Other options of received segments are skipped.
+
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_u16, decode_u32, decode_u8};
+use crate::net::stream::{encode_u16, encode_u32, encode_u8};
+
+/// Control bits of the TCP header.
+pub mod tcp_flags {
+    pub const FIN: u16 = 0x01;
+    pub const SYN: u16 = 0x02;
+    pub const RST: u16 = 0x04;
+    pub const PSH: u16 = 0x08;
+    pub const ACK: u16 = 0x10;
+    pub const URG: u16 = 0x20;
+}
+
+// Size of a TCP header without options.
+const TCP_HDR_LEN: usize = 20;
+
+// Option kinds and the length of the maximum segment size option.
+const OPTION_END: u8 = 0;
+const OPTION_NOP: u8 = 1;
+const OPTION_MSS: u8 = 2;
+const OPTION_MSS_LEN: u8 = 4;
+
+/// The `TCPHeader` struct follows the layout of the TCP header. Unlike
+/// `UDPHeader`, its fields are stored in host byte order, and converted when
+/// the header is encoded or decoded.
+#[derive(Copy, Clone, Debug)]
+pub struct TCPHeader {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq_num: u32,
+    pub ack_num: u32,
+    pub offset_and_control: u16,
+    pub window: u16,
+    pub cksum: u16,
+    pub urg_ptr: u16,
+    pub mss: Option<u16>,
+    pub len: u16, // Not a real TCP field: the length of the header and the data
+}
+
+impl Default for TCPHeader {
+    fn default() -> TCPHeader {
+        TCPHeader {
+            src_port: 0,
+            dst_port: 0,
+            seq_num: 0,
+            ack_num: 0,
+            offset_and_control: ((TCP_HDR_LEN / 4) as u16) << 12,
+            window: 0,
+            cksum: 0,
+            urg_ptr: 0,
+            mss: None,
+            len: TCP_HDR_LEN as u16,
+        }
+    }
+}
+
+impl TCPHeader {
+    pub fn new() -> TCPHeader {
+        TCPHeader::default()
+    }
+
+    pub fn set_src_port(&mut self, port: u16) {
+        self.src_port = port;
+    }
+
+    pub fn set_dst_port(&mut self, port: u16) {
+        self.dst_port = port;
+    }
+
+    pub fn set_seq_num(&mut self, seq_num: u32) {
+        self.seq_num = seq_num;
+    }
+
+    pub fn set_ack_num(&mut self, ack_num: u32) {
+        self.ack_num = ack_num;
+    }
+
+    /// Sets the control bits, a combination of the `tcp_flags` constants.
+    pub fn set_flags(&mut self, flags: u16) {
+        self.offset_and_control = (self.offset_and_control & !0x3f) | (flags & 0x3f);
+    }
+
+    pub fn set_window(&mut self, window: u16) {
+        self.window = window;
+    }
+
+    pub fn set_cksum(&mut self, cksum: u16) {
+        self.cksum = cksum;
+    }
+
+    /// Sets the maximum segment size option, and the data offset to match.
+    pub fn set_mss(&mut self, mss: Option<u16>) {
+        self.mss = mss;
+        let words = (self.get_hdr_size() / 4) as u16;
+        self.offset_and_control = (self.offset_and_control & 0x0fff) | (words << 12);
+    }
+
+    pub fn set_len(&mut self, len: u16) {
+        self.len = len;
+    }
+
+    pub fn get_src_port(&self) -> u16 {
+        self.src_port
+    }
+
+    pub fn get_dst_port(&self) -> u16 {
+        self.dst_port
+    }
+
+    pub fn get_seq_num(&self) -> u32 {
+        self.seq_num
+    }
+
+    pub fn get_ack_num(&self) -> u32 {
+        self.ack_num
+    }
+
+    pub fn get_flags(&self) -> u16 {
+        self.offset_and_control & 0x3f
+    }
+
+    pub fn has_flags(&self, flags: u16) -> bool {
+        self.get_flags() & flags == flags
+    }
+
+    pub fn get_window(&self) -> u16 {
+        self.window
+    }
+
+    pub fn get_cksum(&self) -> u16 {
+        self.cksum
+    }
+
+    pub fn get_mss(&self) -> Option<u16> {
+        self.mss
+    }
+
+    pub fn get_len(&self) -> u16 {
+        self.len
+    }
+
+    /// Returns the size of the header as encoded by `encode`.
+    pub fn get_hdr_size(&self) -> usize {
+        TCP_HDR_LEN + if self.mss.is_some() { 4 } else { 0 }
+    }
+
+    /// This function serializes the `TCPHeader` into the provided buffer.
+    ///
+    /// # Arguments
+    ///
+    /// `buf` - A mutable buffer to serialize the `TCPHeader` into
+    /// `offset` - The current offset into the provided buffer
+    ///
+    /// # Return Value
+    ///
+    /// This function returns the new offset into the buffer wrapped in an
+    /// SResult.
+    pub fn encode(&self, buf: &mut [u8], offset: usize) -> SResult<usize> {
+        stream_len_cond!(buf, self.get_hdr_size() + offset);
+
+        let mut off = offset;
+        off = enc_consume!(buf, off; encode_u16, self.src_port);
+        off = enc_consume!(buf, off; encode_u16, self.dst_port);
+        off = enc_consume!(buf, off; encode_u32, self.seq_num);
+        off = enc_consume!(buf, off; encode_u32, self.ack_num);
+        off = enc_consume!(buf, off; encode_u16, self.offset_and_control);
+        off = enc_consume!(buf, off; encode_u16, self.window);
+        off = enc_consume!(buf, off; encode_u16, self.cksum);
+        off = enc_consume!(buf, off; encode_u16, self.urg_ptr);
+        if let Some(mss) = self.mss {
+            off = enc_consume!(buf, off; encode_u8, OPTION_MSS);
+            off = enc_consume!(buf, off; encode_u8, OPTION_MSS_LEN);
+            off = enc_consume!(buf, off; encode_u16, mss);
+        }
+        stream_done!(off, off);
+    }
+
+    /// This function deserializes the `TCPHeader` from the provided buffer.
+    ///
+    /// # Arguments
+    ///
+    /// `buf` - The byte array corresponding to a serialized `TCPHeader`,
+    /// followed by the segment data
+    ///
+    /// # Return Value
+    ///
+    /// This function returns a `TCPHeader` struct wrapped in an SResult, with
+    /// the offset of the segment data. The `len` of the header is the length
+    /// of `buf`.
+    pub fn decode(buf: &[u8]) -> SResult<TCPHeader> {
+        stream_len_cond!(buf, TCP_HDR_LEN);
+        let mut tcp_header = Self::new();
+        let off = 0;
+        let (off, src_port) = dec_try!(buf, off; decode_u16);
+        tcp_header.src_port = src_port;
+        let (off, dst_port) = dec_try!(buf, off; decode_u16);
+        tcp_header.dst_port = dst_port;
+        let (off, seq_num) = dec_try!(buf, off; decode_u32);
+        tcp_header.seq_num = seq_num;
+        let (off, ack_num) = dec_try!(buf, off; decode_u32);
+        tcp_header.ack_num = ack_num;
+        let (off, offset_and_control) = dec_try!(buf, off; decode_u16);
+        tcp_header.offset_and_control = offset_and_control;
+        let (off, window) = dec_try!(buf, off; decode_u16);
+        tcp_header.window = window;
+        let (off, cksum) = dec_try!(buf, off; decode_u16);
+        tcp_header.cksum = cksum;
+        let (mut off, urg_ptr) = dec_try!(buf, off; decode_u16);
+        tcp_header.urg_ptr = urg_ptr;
+
+        let data_offset = ((offset_and_control >> 12) as usize) * 4;
+        stream_cond!(data_offset >= TCP_HDR_LEN);
+        stream_len_cond!(buf, data_offset);
+        while off < data_offset {
+            let (next, kind) = dec_try!(buf, off; decode_u8);
+            match kind {
+                OPTION_END => break,
+                OPTION_NOP => off = next,
+                _ => {
+                    stream_cond!(next < data_offset);
+                    let (_, length) = dec_try!(buf, next; decode_u8);
+                    stream_cond!(length >= 2 && off + length as usize <= data_offset);
+                    if kind == OPTION_MSS && length == OPTION_MSS_LEN {
+                        let (_, mss) = dec_try!(buf, next + 1; decode_u16);
+                        tcp_header.mss = Some(mss);
+                    }
+                    off += length as usize;
+                }
+            }
+        }
+        tcp_header.len = buf.len() as u16;
+        stream_done!(data_offset, tcp_header);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_decode_with_mss() {
+        let mut header = TCPHeader::new();
+        header.set_src_port(49152);
+        header.set_dst_port(80);
+        header.set_seq_num(0x0102_0304);
+        header.set_ack_num(0xfffe_fdfc);
+        header.set_flags(tcp_flags::SYN | tcp_flags::ACK);
+        header.set_window(1024);
+        header.set_mss(Some(1220));
+
+        let mut buf = [0; 32];
+        assert_eq!(
+            header.encode(&mut buf, 2).done().map(|(off, _)| off),
+            Some(26)
+        );
+        assert_eq!(buf[2..6], [0xc0, 0x00, 0x00, 80]);
+        assert_eq!(buf[14], 0x60);
+        assert_eq!(buf[22..26], [OPTION_MSS, OPTION_MSS_LEN, 0x04, 0xc4]);
+
+        buf[26..30].copy_from_slice(b"data");
+        let (offset, decoded) = TCPHeader::decode(&buf[2..30]).done().unwrap();
+        assert_eq!(offset, 24);
+        assert_eq!(decoded.get_src_port(), 49152);
+        assert_eq!(decoded.get_dst_port(), 80);
+        assert_eq!(decoded.get_seq_num(), 0x0102_0304);
+        assert_eq!(decoded.get_ack_num(), 0xfffe_fdfc);
+        assert_eq!(decoded.get_flags(), tcp_flags::SYN | tcp_flags::ACK);
+        assert!(decoded.has_flags(tcp_flags::SYN));
+        assert!(!decoded.has_flags(tcp_flags::SYN | tcp_flags::FIN));
+        assert_eq!(decoded.get_window(), 1024);
+        assert_eq!(decoded.get_mss(), Some(1220));
+        assert_eq!(decoded.get_len(), 28);
+    }
+
+    #[test]
+    fn encode_needs_room_for_options() {
+        let mut header = TCPHeader::new();
+        header.set_mss(Some(536));
+        assert_eq!(header.get_hdr_size(), 24);
+        assert!(header.encode(&mut [0; 23], 0).done().is_none());
+        header.set_mss(None);
+        assert_eq!(header.get_hdr_size(), 20);
+        assert!(header.encode(&mut [0; 20], 0).done().is_some());
+    }
+
+    // A header with the data offset of `words` and the given options.
+    fn with_options(words: u8, options: &[u8]) -> [u8; 40] {
+        let mut buf = [0; 40];
+        buf[12] = words << 4;
+        buf[20..20 + options.len()].copy_from_slice(options);
+        buf
+    }
+
+    #[test]
+    fn decode_skips_other_options() {
+        // NOP, window scale, NOP, NOP, MSS
+        let buf = with_options(8, &[1, 3, 3, 7, 1, 1, 2, 4, 0x02, 0x18]);
+        let (offset, header) = TCPHeader::decode(&buf[..32]).done().unwrap();
+        assert_eq!(offset, 32);
+        assert_eq!(header.get_mss(), Some(536));
+
+        // Options after the end of the option list are ignored
+        let buf = with_options(6, &[0, 0, 2, 4, 0x02, 0x18]);
+        let (offset, header) = TCPHeader::decode(&buf[..24]).done().unwrap();
+        assert_eq!(offset, 24);
+        assert_eq!(header.get_mss(), None);
+
+        // An MSS option of the wrong length is skipped
+        let buf = with_options(6, &[2, 3, 0, 0]);
+        assert_eq!(
+            TCPHeader::decode(&buf[..24]).done().unwrap().1.get_mss(),
+            None
+        );
+    }
+
+    #[test]
+    fn decode_rejects_malformed_headers() {
+        // Too short
+        assert!(TCPHeader::decode(&[0; 19]).done().is_none());
+        // Data offset smaller than the header
+        assert!(TCPHeader::decode(&with_options(4, &[])[..20])
+            .done()
+            .is_none());
+        // Data offset past the end of the segment
+        assert!(TCPHeader::decode(&with_options(6, &[])[..20])
+            .done()
+            .is_none());
+        // Option longer than the options
+        assert!(TCPHeader::decode(&with_options(6, &[2, 8, 0, 0])[..24])
+            .done()
+            .is_none());
+        // Option with a length too small to skip it
+        assert!(TCPHeader::decode(&with_options(6, &[3, 1, 0, 0])[..24])
+            .done()
+            .is_none());
+        // Option kind at the end of the options, without its length
+        assert!(TCPHeader::decode(&with_options(6, &[1, 1, 1, 3])[..24])
+            .done()
+            .is_none());
+    }
+}
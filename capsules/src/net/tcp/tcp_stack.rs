@@ -0,0 +1,1741 @@
+//! This file contains a TCP implementation for the IPv6 stack. The
+//! [TCPStack](trait.TCPStack.html) trait is the interface used by the
+//! userspace driver and by kernel capsules to open, use and close
+//! connections, and the [TCPClient](trait.TCPClient.html) trait receives the
+//! events of the connections.
+//!
+//! [TCPStackStruct](struct.TCPStackStruct.html) implements `TCPStack` on top
+//! of an `IP6Sender` and an `IP6RecvClient`, with a fixed number of
+//! connections, each with its own statically allocated send and receive
+//! buffers. The buffers bound the windows, so that connections work with a
+//! few hundred bytes of RAM.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use capsules::net::tcp::tcp_stack::{TCPConnection, TCPStackStruct};
+//! # use kernel::static_init;
+//! let tcp_connections = static_init!(
+//!     [TCPConnection; 2],
+//!     [
+//!         TCPConnection::new(&mut TCP_SEND_BUF_0, &mut TCP_RECV_BUF_0),
+//!         TCPConnection::new(&mut TCP_SEND_BUF_1, &mut TCP_RECV_BUF_1),
+//!     ]
+//! );
+//! let tcp_stack = static_init!(
+//!     TCPStackStruct<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, Ast>>,
+//!         VirtualMuxAlarm<'static, Ast>>,
+//!     TCPStackStruct::new(ip_send, tcp_alarm, tcp_connections, TCP_MSS)
+//! );
+//! ip_send.set_client(tcp_stack);
+//! tcp_alarm.set_client(tcp_stack);
+//! ```
+//!
+//! The stack must also receive the IPv6 packets with the TCP next header,
+//! for example from an `IP6RecvMux`.
+
+// Known Limitations
+// -----------------
+// - Segments that arrive out of order are dropped, and the peer retransmits
+//   them after the segments before them arrived. This keeps the receive
+//   buffer a plain byte array.
+// - Lost segments are recovered with go-back-N, after a retransmission
+//   timeout or three duplicate acknowledgements.
+// - The only option is the maximum segment size. Window scaling, timestamps
+//   and selective acknowledgements are not supported, and neither is urgent
+//   data.
+// - A listening connection accepts a single peer; listen on several
+//   connections to accept several peers on the same port.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6::{IP6Header, TransportHeader};
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::tcp::tcp::{tcp_flags, TCPHeader};
+use core::cell::Cell;
+use core::cmp::{max, min};
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::time::{self, Frequency};
+use kernel::{AppId, ReturnCode};
+
+// Retransmission timeout bounds, from RFC 6298, in milliseconds.
+const INITIAL_RTO: u32 = 1000;
+const MIN_RTO: u32 = 1000;
+const MAX_RTO: u32 = 60000;
+
+// Number of retransmissions of a segment before the connection is dropped.
+const MAX_RETRANSMISSIONS: u8 = 6;
+
+// Time spent in TIME-WAIT, in milliseconds. This is twice a maximum segment
+// lifetime of 15 seconds, rather than the 2 minutes of RFC 793, so that
+// connection slots are not held for too long.
+const TIME_WAIT: u32 = 30000;
+
+// Maximum segment size assumed when the peer does not send the option, from
+// RFC 8200: the IPv6 minimum MTU minus the IPv6 and TCP headers.
+const DEFAULT_PEER_MSS: u16 = 1220;
+
+// Local ports of active opens are picked from the dynamic port range.
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+
+// Duplicate acknowledgements that trigger a retransmission.
+const DUP_ACK_THRESHOLD: u8 = 3;
+
+/// The states of a TCP connection, from RFC 793.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TCPState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+impl TCPState {
+    // Whether the connection sends our FIN once the send buffer is empty.
+    fn sends_fin(self) -> bool {
+        match self {
+            TCPState::FinWait1 | TCPState::Closing | TCPState::LastAck => true,
+            _ => false,
+        }
+    }
+
+    // Whether the connection can send data.
+    fn sends_data(self) -> bool {
+        match self {
+            TCPState::Established | TCPState::CloseWait => true,
+            _ => self.sends_fin(),
+        }
+    }
+
+    // Whether the connection accepts data from the peer.
+    fn receives_data(self) -> bool {
+        match self {
+            TCPState::Established | TCPState::FinWait1 | TCPState::FinWait2 => true,
+            _ => false,
+        }
+    }
+}
+
+/// Receives the events of the connections of a `TCPStack`, identified by the
+/// ids returned by `TCPStack::connect` and `TCPStack::listen`.
+pub trait TCPClient {
+    /// The connection was established.
+    fn connected(&self, id: usize);
+
+    /// Data was received and can be read with `TCPStack::receive`.
+    fn received(&self, id: usize);
+
+    /// The peer acknowledged data, which freed space in the send buffer.
+    fn sent(&self, id: usize);
+
+    /// The peer closed its side of the connection: no more data will be
+    /// received after the data in the receive buffer.
+    fn peer_closed(&self, id: usize);
+
+    /// The connection is closed. `result` is SUCCESS if both sides closed the
+    /// connection, ECANCEL if the peer reset it, and ENOACK if the peer
+    /// stopped acknowledging segments.
+    fn closed(&self, id: usize, result: ReturnCode);
+
+    /// Returns whether the process `owner` still uses the connection. The
+    /// stack takes back the connections of processes that are gone.
+    fn owner_uses(&self, id: usize, owner: AppId) -> bool;
+}
+
+/// The interface to open and use TCP connections.
+pub trait TCPStack<'a> {
+    fn set_client(&self, client: &'a dyn TCPClient);
+
+    /// Opens a connection to `port` at `addr`, from an ephemeral port, for
+    /// the process `owner` or for the kernel if it is `None`. Returns the id
+    /// of the connection, which is reported by `TCPClient::connected` when
+    /// the connection is established, or ENOMEM if all connections are in
+    /// use.
+    fn connect(&self, addr: IPAddr, port: u16, owner: Option<AppId>) -> Result<usize, ReturnCode>;
+
+    /// Waits for a peer to connect to `port`, for the process `owner` or for
+    /// the kernel. Returns the id of the connection, or ENOMEM if all
+    /// connections are in use.
+    fn listen(&self, port: u16, owner: Option<AppId>) -> Result<usize, ReturnCode>;
+
+    /// Queues as much of `data` as fits in the send buffer, and returns the
+    /// number of bytes queued. Returns EINVAL if the connection is closed or
+    /// we closed it.
+    fn send(&self, id: usize, data: &[u8]) -> Result<usize, ReturnCode>;
+
+    /// Moves received data into `buf`, and returns the number of bytes
+    /// moved.
+    fn receive(&self, id: usize, buf: &mut [u8]) -> Result<usize, ReturnCode>;
+
+    /// Closes our side of the connection once the queued data is sent. A
+    /// connection that is not established yet is closed immediately, without
+    /// a call to `TCPClient::closed`.
+    fn close(&self, id: usize) -> ReturnCode;
+
+    /// Resets the connection and discards the data in its buffers. There is
+    /// no call to `TCPClient::closed`.
+    fn abort(&self, id: usize) -> ReturnCode;
+
+    /// Gives a closed connection back to the stack, for use by another
+    /// `connect` or `listen`. Returns EBUSY if the connection is not closed.
+    fn release(&self, id: usize) -> ReturnCode;
+
+    fn state(&self, id: usize) -> Option<TCPState>;
+
+    /// Returns the address and the port of the peer of a connection.
+    fn remote_endpoint(&self, id: usize) -> Option<(IPAddr, u16)>;
+}
+
+// Sequence number comparisons, modulo 2^32.
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) <= 0
+}
+
+/// The state and the buffers of a TCP connection. A `TCPStackStruct` is
+/// given a fixed array of connections when it is created.
+pub struct TCPConnection {
+    in_use: Cell<bool>,
+    // The process that opened the connection, if not the kernel
+    owner: Cell<Option<AppId>>,
+    state: Cell<TCPState>,
+    passive: Cell<bool>,
+    local_port: Cell<u16>,
+    remote_port: Cell<u16>,
+    remote_addr: Cell<IPAddr>,
+
+    // Send sequence variables of RFC 793
+    iss: Cell<u32>,
+    snd_una: Cell<u32>,
+    snd_nxt: Cell<u32>,
+    // The highest SND.NXT, which SND.NXT falls behind after a retransmission
+    snd_max: Cell<u32>,
+    snd_wnd: Cell<u32>,
+    snd_wl1: Cell<u32>,
+    snd_wl2: Cell<u32>,
+    snd_mss: Cell<u16>,
+
+    // Congestion control of RFC 5681
+    cwnd: Cell<u32>,
+    ssthresh: Cell<u32>,
+    dup_acks: Cell<u8>,
+
+    // Receive sequence variables of RFC 793
+    rcv_nxt: Cell<u32>,
+    ack_pending: Cell<bool>,
+
+    // The send buffer holds the data from SND.UNA, the receive buffer the
+    // data before RCV.NXT that was not read yet.
+    send_buffer: TakeCell<'static, [u8]>,
+    send_capacity: usize,
+    send_len: Cell<usize>,
+    recv_buffer: TakeCell<'static, [u8]>,
+    recv_capacity: usize,
+    recv_len: Cell<usize>,
+
+    // Retransmission and persist timer of RFC 6298, in alarm tics
+    deadline: Cell<Option<u32>>,
+    retransmissions: Cell<u8>,
+    probe: Cell<bool>,
+    rto: Cell<u32>,
+    srtt: Cell<u32>,
+    rttvar: Cell<u32>,
+    // The end of the segment being timed, and the time it was sent
+    rtt_sample: Cell<Option<(u32, u32)>>,
+}
+
+impl TCPConnection {
+    pub fn new(send_buffer: &'static mut [u8], recv_buffer: &'static mut [u8]) -> TCPConnection {
+        TCPConnection {
+            in_use: Cell::new(false),
+            owner: Cell::new(None),
+            state: Cell::new(TCPState::Closed),
+            passive: Cell::new(false),
+            local_port: Cell::new(0),
+            remote_port: Cell::new(0),
+            remote_addr: Cell::new(IPAddr::new()),
+            iss: Cell::new(0),
+            snd_una: Cell::new(0),
+            snd_nxt: Cell::new(0),
+            snd_max: Cell::new(0),
+            snd_wnd: Cell::new(0),
+            snd_wl1: Cell::new(0),
+            snd_wl2: Cell::new(0),
+            snd_mss: Cell::new(0),
+            cwnd: Cell::new(0),
+            ssthresh: Cell::new(0),
+            dup_acks: Cell::new(0),
+            rcv_nxt: Cell::new(0),
+            ack_pending: Cell::new(false),
+            send_capacity: send_buffer.len(),
+            send_buffer: TakeCell::new(send_buffer),
+            send_len: Cell::new(0),
+            recv_capacity: recv_buffer.len(),
+            recv_buffer: TakeCell::new(recv_buffer),
+            recv_len: Cell::new(0),
+            deadline: Cell::new(None),
+            retransmissions: Cell::new(0),
+            probe: Cell::new(false),
+            rto: Cell::new(INITIAL_RTO),
+            srtt: Cell::new(0),
+            rttvar: Cell::new(0),
+            rtt_sample: Cell::new(None),
+        }
+    }
+
+    fn reset(&self, state: TCPState, local_port: u16, iss: u32) {
+        self.state.set(state);
+        self.passive.set(state == TCPState::Listen);
+        self.local_port.set(local_port);
+        self.remote_port.set(0);
+        self.remote_addr.set(IPAddr::new());
+        self.iss.set(iss);
+        self.snd_una.set(iss);
+        self.snd_nxt.set(iss);
+        self.snd_max.set(iss);
+        self.snd_wnd.set(0);
+        self.snd_wl1.set(0);
+        self.snd_wl2.set(0);
+        self.snd_mss.set(0);
+        self.cwnd.set(0);
+        self.ssthresh.set(0xffff);
+        self.dup_acks.set(0);
+        self.rcv_nxt.set(0);
+        self.ack_pending.set(false);
+        self.send_len.set(0);
+        self.recv_len.set(0);
+        self.deadline.set(None);
+        self.retransmissions.set(0);
+        self.probe.set(false);
+        self.rto.set(INITIAL_RTO);
+        self.srtt.set(0);
+        self.rttvar.set(0);
+        self.rtt_sample.set(None);
+    }
+
+    // Sequence space from SND.UNA to SND.NXT, including SYN and FIN.
+    fn in_flight(&self) -> u32 {
+        self.snd_nxt.get().wrapping_sub(self.snd_una.get())
+    }
+
+    // Sequence space sent and not acknowledged, including SYN and FIN.
+    fn outstanding(&self) -> u32 {
+        self.snd_max.get().wrapping_sub(self.snd_una.get())
+    }
+
+    // Bytes of the send buffer from SND.NXT, which are sent next.
+    fn unsent(&self) -> usize {
+        let mut sent = self.in_flight() as usize;
+        match self.state.get() {
+            TCPState::SynSent | TCPState::SynReceived => sent -= min(sent, 1),
+            _ => {}
+        }
+        self.send_len.get() - min(sent, self.send_len.get())
+    }
+
+    // The sequence number of our FIN, which follows the data of the send
+    // buffer once the connection is closing.
+    fn fin_seq(&self) -> Option<u32> {
+        if self.state.get().sends_fin() {
+            Some(self.snd_una.get().wrapping_add(self.send_len.get() as u32))
+        } else {
+            None
+        }
+    }
+
+    // The window to advertise: the free space of the receive buffer.
+    fn rcv_wnd(&self) -> u32 {
+        min(self.recv_capacity - self.recv_len.get(), 0xffff) as u32
+    }
+
+    // Sets the maximum segment size and the initial congestion window, from
+    // RFC 5681.
+    fn set_mss(&self, local_mss: u16, peer_mss: Option<u16>) {
+        let mss = max(min(local_mss, peer_mss.unwrap_or(DEFAULT_PEER_MSS)), 1);
+        self.snd_mss.set(mss);
+        let mss = mss as u32;
+        self.cwnd.set(min(4 * mss, max(2 * mss, 4380)));
+    }
+
+    // Discards the first `len` bytes of the send buffer.
+    fn consume_send_buffer(&self, len: usize) {
+        let remaining = self.send_len.get() - len;
+        self.send_buffer.map(|buffer| {
+            buffer.copy_within(len..len + remaining, 0);
+        });
+        self.send_len.set(remaining);
+    }
+
+    // Updates the retransmission timeout with a round-trip time sample, in
+    // milliseconds.
+    fn update_rto(&self, rtt: u32) {
+        if self.srtt.get() == 0 {
+            self.srtt.set(max(rtt, 1));
+            self.rttvar.set(rtt / 2);
+        } else {
+            let srtt = self.srtt.get();
+            let delta = if srtt > rtt { srtt - rtt } else { rtt - srtt };
+            self.rttvar.set((3 * self.rttvar.get() + delta) / 4);
+            self.srtt.set(max((7 * srtt + rtt) / 8, 1));
+        }
+        let rto = self.srtt.get() + max(4 * self.rttvar.get(), 1);
+        self.rto.set(min(max(rto, MIN_RTO), MAX_RTO));
+    }
+}
+
+// A reset to send for a segment that does not belong to a connection, or
+// for an aborted connection.
+#[derive(Copy, Clone)]
+struct Reset {
+    addr: IPAddr,
+    local_port: u16,
+    remote_port: u16,
+    seq: u32,
+    ack: Option<u32>,
+}
+
+/// Implements `TCPStack` on an `IP6Sender`, with an alarm for the
+/// retransmission, persist and TIME-WAIT timers of the connections.
+pub struct TCPStackStruct<'a, T: IP6Sender<'a>, A: time::Alarm<'a>> {
+    ip_send_struct: &'a T,
+    alarm: &'a A,
+    connections: &'a [TCPConnection],
+    client: OptionalCell<&'a dyn TCPClient>,
+    // Maximum segment size we accept, which must fit in the payload buffer of
+    // the `IP6Sender`
+    mss: u16,
+    sending: Cell<bool>,
+    in_output: Cell<bool>,
+    next_connection: Cell<usize>,
+    pending_reset: Cell<Option<Reset>>,
+    next_port: Cell<u16>,
+    iss_counter: Cell<u32>,
+}
+
+impl<T: IP6Sender<'a>, A: time::Alarm<'a>> TCPStackStruct<'a, T, A> {
+    /// `mss` is the largest segment the connections send and receive, which
+    /// must not be larger than the payload buffer of `ip_send_struct`.
+    pub fn new(
+        ip_send_struct: &'a T,
+        alarm: &'a A,
+        connections: &'a [TCPConnection],
+        mss: u16,
+    ) -> TCPStackStruct<'a, T, A> {
+        TCPStackStruct {
+            ip_send_struct,
+            alarm,
+            connections,
+            client: OptionalCell::empty(),
+            mss,
+            sending: Cell::new(false),
+            in_output: Cell::new(false),
+            next_connection: Cell::new(0),
+            pending_reset: Cell::new(None),
+            next_port: Cell::new(FIRST_EPHEMERAL_PORT),
+            iss_counter: Cell::new(0),
+        }
+    }
+
+    fn ms_to_tics(ms: u32) -> u32 {
+        (ms as u64 * <A::Frequency>::frequency() as u64 / 1000) as u32
+    }
+
+    fn tics_to_ms(tics: u32) -> u32 {
+        (tics as u64 * 1000 / <A::Frequency>::frequency() as u64) as u32
+    }
+
+    // Picks an initial sequence number, which depends on the time as in
+    // RFC 793, and differs between connections opened at the same time.
+    fn new_iss(&self) -> u32 {
+        let counter = self.iss_counter.get().wrapping_add(1);
+        self.iss_counter.set(counter);
+        self.alarm
+            .now()
+            .wrapping_mul(2_654_435_761)
+            .wrapping_add(counter.wrapping_mul(0x10000))
+    }
+
+    // Picks a local port for an active open which no connection uses.
+    fn new_port(&self) -> u16 {
+        loop {
+            let port = self.next_port.get();
+            self.next_port.set(if port == 0xffff {
+                FIRST_EPHEMERAL_PORT
+            } else {
+                port + 1
+            });
+            let used = self
+                .connections
+                .iter()
+                .any(|conn| conn.state.get() != TCPState::Closed && conn.local_port.get() == port);
+            if !used {
+                return port;
+            }
+        }
+    }
+
+    fn allocate(&self, owner: Option<AppId>) -> Result<usize, ReturnCode> {
+        self.reclaim();
+        self.connections
+            .iter()
+            .position(|conn| !conn.in_use.get() && conn.state.get() == TCPState::Closed)
+            .map(|id| {
+                self.connections[id].in_use.set(true);
+                self.connections[id].owner.set(owner);
+                id
+            })
+            .ok_or(ReturnCode::ENOMEM)
+    }
+
+    // Resets and frees the connections of processes that are gone, so that
+    // they neither take up connections nor accept peers.
+    fn reclaim(&self) {
+        for (id, conn) in self.connections.iter().enumerate() {
+            let owner = match conn.owner.get() {
+                Some(owner) if conn.in_use.get() => owner,
+                _ => continue,
+            };
+            if self
+                .client
+                .map_or(true, |client| client.owner_uses(id, owner))
+            {
+                continue;
+            }
+            if conn.state.get() != TCPState::TimeWait {
+                self.reset_connection(conn);
+                conn.state.set(TCPState::Closed);
+                self.clear_deadline(conn);
+            }
+            conn.in_use.set(false);
+            conn.owner.set(None);
+        }
+    }
+
+    fn get(&self, id: usize) -> Result<&TCPConnection, ReturnCode> {
+        match self.connections.get(id) {
+            Some(conn) if conn.in_use.get() => Ok(conn),
+            _ => Err(ReturnCode::EINVAL),
+        }
+    }
+
+    fn set_deadline(&self, conn: &TCPConnection, ms: u32) {
+        conn.deadline
+            .set(Some(self.alarm.now().wrapping_add(Self::ms_to_tics(ms))));
+        self.update_alarm();
+    }
+
+    fn clear_deadline(&self, conn: &TCPConnection) {
+        conn.deadline.set(None);
+        self.update_alarm();
+    }
+
+    // Sets the alarm for the earliest deadline of the connections.
+    fn update_alarm(&self) {
+        let now = self.alarm.now();
+        let next = self
+            .connections
+            .iter()
+            .filter_map(|conn| conn.deadline.get())
+            .min_by_key(|deadline| max(deadline.wrapping_sub(now) as i32, 0));
+        match next {
+            Some(deadline) if (deadline.wrapping_sub(now) as i32) > 0 => {
+                self.alarm.set_alarm(deadline)
+            }
+            Some(_) => self.alarm.set_alarm(now.wrapping_add(1)),
+            None => self.alarm.disable(),
+        }
+    }
+
+    fn close_connection(&self, id: usize, result: ReturnCode) {
+        let conn = &self.connections[id];
+        conn.state.set(TCPState::Closed);
+        self.clear_deadline(conn);
+        self.client.map(|client| client.closed(id, result));
+    }
+
+    fn reset_connection(&self, conn: &TCPConnection) {
+        match conn.state.get() {
+            TCPState::Closed | TCPState::Listen | TCPState::SynSent | TCPState::TimeWait => {}
+            _ => self.pending_reset.set(Some(Reset {
+                addr: conn.remote_addr.get(),
+                local_port: conn.local_port.get(),
+                remote_port: conn.remote_port.get(),
+                seq: conn.snd_nxt.get(),
+                ack: None,
+            })),
+        }
+    }
+
+    // Builds the next segment of a connection, if it has one to send, and
+    // updates the connection as if it was sent. Returns the header, and the
+    // offset and the length of the data in the send buffer.
+    fn next_segment(&self, conn: &TCPConnection) -> Option<(TCPHeader, usize, usize)> {
+        let state = conn.state.get();
+        let mut header = TCPHeader::new();
+        header.set_src_port(conn.local_port.get());
+        header.set_dst_port(conn.remote_port.get());
+        header.set_seq_num(conn.snd_nxt.get());
+        header.set_ack_num(conn.rcv_nxt.get());
+        header.set_window(conn.rcv_wnd() as u16);
+        let mut flags = tcp_flags::ACK;
+        let mut offset = 0;
+        let mut len = 0;
+
+        match state {
+            TCPState::Closed | TCPState::Listen => return None,
+            TCPState::SynSent | TCPState::SynReceived => {
+                if conn.snd_nxt.get() != conn.iss.get() {
+                    if state == TCPState::SynSent || !conn.ack_pending.get() {
+                        return None;
+                    }
+                // Acknowledge a segment that was not acceptable
+                } else {
+                    flags = if state == TCPState::SynSent {
+                        tcp_flags::SYN
+                    } else {
+                        tcp_flags::SYN | tcp_flags::ACK
+                    };
+                    header.set_mss(Some(self.mss));
+                    conn.snd_nxt.set(conn.iss.get().wrapping_add(1));
+                }
+            }
+            _ if state.sends_data() => {
+                let in_flight = conn.in_flight();
+                let unsent = conn.unsent();
+                let window = min(conn.snd_wnd.get(), conn.cwnd.get());
+                let usable = window.saturating_sub(in_flight) as usize;
+                len = min(min(unsent, usable), conn.snd_mss.get() as usize);
+                if len == 0 && unsent > 0 && conn.probe.get() {
+                    // Probe a zero window with one byte
+                    len = 1;
+                }
+                conn.probe.set(false);
+                offset = conn.send_len.get() - unsent;
+                if len > 0 && len == unsent {
+                    flags |= tcp_flags::PSH;
+                }
+                let mut consumed = len as u32;
+                let seq = conn.snd_nxt.get().wrapping_add(consumed);
+                if conn.fin_seq() == Some(seq) {
+                    flags |= tcp_flags::FIN;
+                    consumed += 1;
+                }
+                if consumed == 0 && !conn.ack_pending.get() {
+                    return None;
+                }
+                conn.snd_nxt.set(conn.snd_nxt.get().wrapping_add(consumed));
+            }
+            _ => {
+                // FIN-WAIT-2 and TIME-WAIT only acknowledge segments
+                if !conn.ack_pending.get() {
+                    return None;
+                }
+            }
+        }
+
+        // Time the segment and start the retransmission timer if it uses
+        // sequence space
+        if seq_lt(conn.snd_max.get(), conn.snd_nxt.get()) {
+            conn.snd_max.set(conn.snd_nxt.get());
+        }
+        if conn.snd_nxt.get() != header.get_seq_num() {
+            if conn.rtt_sample.get().is_none() && conn.retransmissions.get() == 0 {
+                conn.rtt_sample
+                    .set(Some((conn.snd_nxt.get(), self.alarm.now())));
+            }
+            if conn.deadline.get().is_none() {
+                self.set_deadline(conn, conn.rto.get());
+            }
+        }
+        if flags & tcp_flags::ACK != 0 {
+            conn.ack_pending.set(false);
+        } else {
+            header.set_ack_num(0);
+        }
+        header.set_flags(flags);
+        Some((header, offset, len))
+    }
+
+    // Sends segments until a transmission is in progress or there is
+    // nothing to send, visiting the connections in turn.
+    fn output(&self) {
+        if self.sending.get() || self.in_output.get() {
+            return;
+        }
+        self.in_output.set(true);
+        while !self.sending.get() {
+            if let Some(reset) = self.pending_reset.take() {
+                let mut header = TCPHeader::new();
+                header.set_src_port(reset.local_port);
+                header.set_dst_port(reset.remote_port);
+                header.set_seq_num(reset.seq);
+                match reset.ack {
+                    Some(ack) => {
+                        header.set_ack_num(ack);
+                        header.set_flags(tcp_flags::RST | tcp_flags::ACK);
+                    }
+                    None => header.set_flags(tcp_flags::RST),
+                }
+                self.transmit(reset.addr, header, &[]);
+                continue;
+            }
+
+            let count = self.connections.len();
+            let start = self.next_connection.get();
+            let segment = (0..count).map(|i| (start + i) % count).find_map(|id| {
+                let conn = &self.connections[id];
+                self.next_segment(conn).map(|segment| (id, segment))
+            });
+            match segment {
+                Some((id, (header, offset, len))) => {
+                    self.next_connection.set((id + 1) % count);
+                    let conn = &self.connections[id];
+                    let addr = conn.remote_addr.get();
+                    conn.send_buffer.map(|buffer| {
+                        self.transmit(addr, header, &buffer[offset..offset + len]);
+                    });
+                }
+                None => break,
+            }
+        }
+        self.in_output.set(false);
+    }
+
+    fn transmit(&self, addr: IPAddr, header: TCPHeader, payload: &[u8]) {
+        self.sending.set(true);
+        let result = self
+            .ip_send_struct
+            .send_to(addr, TransportHeader::TCP(header), payload);
+        if result != ReturnCode::SUCCESS {
+            // The segment is lost, and is retransmitted after a timeout if it
+            // used sequence space
+            self.sending.set(false);
+        }
+    }
+
+    // Handles a segment for a connection in the LISTEN state.
+    fn listen_segment(&self, conn: &TCPConnection, ip_header: &IP6Header, header: &TCPHeader) {
+        if header.has_flags(tcp_flags::RST) {
+            return;
+        }
+        if header.has_flags(tcp_flags::ACK) {
+            self.reply_reset(ip_header, header, 0);
+            return;
+        }
+        if !header.has_flags(tcp_flags::SYN) {
+            return;
+        }
+        conn.state.set(TCPState::SynReceived);
+        conn.remote_addr.set(ip_header.get_src_addr());
+        conn.remote_port.set(header.get_src_port());
+        let iss = self.new_iss();
+        conn.iss.set(iss);
+        conn.snd_una.set(iss);
+        conn.snd_nxt.set(iss);
+        conn.snd_wnd.set(header.get_window() as u32);
+        conn.snd_wl1.set(header.get_seq_num());
+        conn.snd_wl2.set(iss);
+        conn.set_mss(self.mss, header.get_mss());
+        conn.rcv_nxt.set(header.get_seq_num().wrapping_add(1));
+    }
+
+    // Handles a segment for a connection in the SYN-SENT state.
+    fn syn_sent_segment(&self, id: usize, ip_header: &IP6Header, header: &TCPHeader) {
+        let conn = &self.connections[id];
+        let ack = header.get_ack_num();
+        let has_ack = header.has_flags(tcp_flags::ACK);
+        if has_ack && (seq_le(ack, conn.iss.get()) || seq_lt(conn.snd_max.get(), ack)) {
+            if !header.has_flags(tcp_flags::RST) {
+                self.reply_reset(ip_header, header, 0);
+            }
+            return;
+        }
+        if header.has_flags(tcp_flags::RST) {
+            if has_ack {
+                // The connection was refused
+                self.close_connection(id, ReturnCode::ECANCEL);
+            }
+            return;
+        }
+        if !header.has_flags(tcp_flags::SYN) {
+            return;
+        }
+        conn.rcv_nxt.set(header.get_seq_num().wrapping_add(1));
+        conn.set_mss(self.mss, header.get_mss());
+        conn.snd_wnd.set(header.get_window() as u32);
+        conn.snd_wl1.set(header.get_seq_num());
+        conn.snd_wl2.set(ack);
+        conn.ack_pending.set(true);
+        if has_ack {
+            conn.snd_una.set(ack);
+            conn.state.set(TCPState::Established);
+            conn.retransmissions.set(0);
+            self.take_rtt_sample(conn, ack);
+            self.clear_deadline(conn);
+            self.client.map(|client| client.connected(id));
+        } else {
+            // Simultaneous open: send a SYN-ACK
+            conn.state.set(TCPState::SynReceived);
+            conn.snd_nxt.set(conn.iss.get());
+        }
+    }
+
+    fn take_rtt_sample(&self, conn: &TCPConnection, ack: u32) {
+        if let Some((end, sent)) = conn.rtt_sample.get() {
+            if seq_le(end, ack) {
+                conn.rtt_sample.set(None);
+                let rtt = Self::tics_to_ms(self.alarm.now().wrapping_sub(sent));
+                conn.update_rto(rtt);
+            }
+        }
+    }
+
+    // Handles a segment for a synchronized connection, from RFC 793 section
+    // 3.9.
+    fn synchronized_segment(
+        &self,
+        id: usize,
+        ip_header: &IP6Header,
+        header: &TCPHeader,
+        data: &[u8],
+    ) {
+        let conn = &self.connections[id];
+        let seq = header.get_seq_num();
+        let rcv_nxt = conn.rcv_nxt.get();
+        let rcv_wnd = conn.rcv_wnd();
+        let seg_len = data.len() as u32
+            + header.has_flags(tcp_flags::SYN) as u32
+            + header.has_flags(tcp_flags::FIN) as u32;
+
+        // Check the sequence number
+        let in_window =
+            |seq: u32| seq_le(rcv_nxt, seq) && seq_lt(seq, rcv_nxt.wrapping_add(rcv_wnd));
+        let acceptable = if seg_len == 0 || rcv_wnd == 0 {
+            // Acknowledgements are processed even when the window is closed
+            seq == rcv_nxt || (rcv_wnd > 0 && in_window(seq))
+        } else {
+            in_window(seq) || in_window(seq.wrapping_add(seg_len - 1))
+        };
+        if !acceptable {
+            if !header.has_flags(tcp_flags::RST) {
+                conn.ack_pending.set(true);
+            }
+            return;
+        }
+
+        if header.has_flags(tcp_flags::RST) {
+            if conn.state.get() == TCPState::SynReceived && conn.passive.get() {
+                let port = conn.local_port.get();
+                conn.reset(TCPState::Listen, port, 0);
+                self.update_alarm();
+            } else {
+                conn.send_len.set(0);
+                self.close_connection(id, ReturnCode::ECANCEL);
+            }
+            return;
+        }
+
+        if header.has_flags(tcp_flags::SYN) {
+            self.reset_connection(conn);
+            self.close_connection(id, ReturnCode::ECANCEL);
+            return;
+        }
+
+        if !header.has_flags(tcp_flags::ACK) {
+            return;
+        }
+        let ack = header.get_ack_num();
+
+        if conn.state.get() == TCPState::SynReceived {
+            if seq_lt(conn.snd_una.get(), ack) && seq_le(ack, conn.snd_max.get()) {
+                conn.state.set(TCPState::Established);
+                conn.snd_una.set(conn.iss.get().wrapping_add(1));
+                conn.snd_wnd.set(header.get_window() as u32);
+                conn.snd_wl1.set(seq);
+                conn.snd_wl2.set(ack);
+                conn.retransmissions.set(0);
+                self.take_rtt_sample(conn, ack);
+                self.clear_deadline(conn);
+                self.client.map(|client| client.connected(id));
+            } else {
+                self.reply_reset(ip_header, header, data.len());
+                return;
+            }
+        }
+
+        if !self.process_ack(id, header, data) {
+            return;
+        }
+
+        // Process the data and the FIN
+        let state = conn.state.get();
+        let mut fin_accepted = header.has_flags(tcp_flags::FIN);
+        if seq_lt(conn.rcv_nxt.get(), seq) {
+            // A segment arrived out of order: drop it and send a duplicate
+            // acknowledgement
+            conn.ack_pending.set(true);
+            fin_accepted = false;
+        } else if state.receives_data() && !data.is_empty() {
+            let skip = conn.rcv_nxt.get().wrapping_sub(seq) as usize;
+            let new_data = if skip < data.len() {
+                &data[skip..]
+            } else {
+                &[]
+            };
+            let copied = min(new_data.len(), conn.rcv_wnd() as usize);
+            if copied > 0 {
+                let recv_len = conn.recv_len.get();
+                conn.recv_buffer.map(|buffer| {
+                    buffer[recv_len..recv_len + copied].copy_from_slice(&new_data[..copied]);
+                });
+                conn.recv_len.set(recv_len + copied);
+                conn.rcv_nxt
+                    .set(conn.rcv_nxt.get().wrapping_add(copied as u32));
+            }
+            conn.ack_pending.set(true);
+            fin_accepted &= seq.wrapping_add(data.len() as u32) == conn.rcv_nxt.get();
+            if copied > 0 {
+                self.client.map(|client| client.received(id));
+            }
+        } else if !data.is_empty() {
+            // Data after the FIN of the peer is ignored
+            conn.ack_pending.set(true);
+            fin_accepted &= seq.wrapping_add(data.len() as u32) == conn.rcv_nxt.get();
+        }
+
+        if fin_accepted && state.receives_data() {
+            self.fin_received(id);
+        }
+    }
+
+    // Processes the acknowledgement and the window of a segment. Returns
+    // false if the segment must be dropped.
+    fn process_ack(&self, id: usize, header: &TCPHeader, data: &[u8]) -> bool {
+        let conn = &self.connections[id];
+        let seq = header.get_seq_num();
+        let ack = header.get_ack_num();
+        let window = header.get_window() as u32;
+        let snd_una = conn.snd_una.get();
+
+        if seq_lt(conn.snd_max.get(), ack) {
+            // Acknowledges data that was not sent
+            conn.ack_pending.set(true);
+            return false;
+        }
+
+        if seq_lt(snd_una, ack) {
+            let acked = ack.wrapping_sub(snd_una);
+            let fin_acked = conn.fin_seq().map_or(false, |fin| seq_lt(fin, ack));
+            let data_acked = min(acked as usize, conn.send_len.get());
+            conn.consume_send_buffer(data_acked);
+            conn.snd_una.set(ack);
+            if seq_lt(conn.snd_nxt.get(), ack) {
+                // Segments sent before a retransmission were acknowledged
+                conn.snd_nxt.set(ack);
+            }
+            conn.retransmissions.set(0);
+            conn.dup_acks.set(0);
+            self.take_rtt_sample(conn, ack);
+
+            // Grow the congestion window
+            let mss = conn.snd_mss.get() as u32;
+            let cwnd = conn.cwnd.get();
+            if cwnd < conn.ssthresh.get() {
+                conn.cwnd.set(cwnd + min(acked, mss));
+            } else {
+                conn.cwnd.set(cwnd + max(mss * mss / cwnd, 1));
+            }
+
+            if conn.outstanding() == 0 {
+                self.clear_deadline(conn);
+            } else {
+                self.set_deadline(conn, conn.rto.get());
+            }
+            if data_acked > 0 {
+                self.client.map(|client| client.sent(id));
+            }
+
+            if fin_acked {
+                match conn.state.get() {
+                    TCPState::FinWait1 => conn.state.set(TCPState::FinWait2),
+                    TCPState::Closing => {
+                        conn.state.set(TCPState::TimeWait);
+                        self.set_deadline(conn, TIME_WAIT);
+                        self.client
+                            .map(|client| client.closed(id, ReturnCode::SUCCESS));
+                    }
+                    TCPState::LastAck => {
+                        self.close_connection(id, ReturnCode::SUCCESS);
+                        return false;
+                    }
+                    _ => {}
+                }
+            }
+        } else if ack == snd_una
+            && data.is_empty()
+            && !header.has_flags(tcp_flags::FIN)
+            && window == conn.snd_wnd.get()
+            && conn.outstanding() > 0
+        {
+            // A duplicate acknowledgement: retransmit from SND.UNA after
+            // three of them, and halve the congestion window
+            let dup_acks = conn.dup_acks.get() + 1;
+            conn.dup_acks.set(dup_acks);
+            if dup_acks == DUP_ACK_THRESHOLD {
+                self.go_back(conn);
+                conn.cwnd.set(conn.ssthresh.get());
+            }
+        }
+
+        // Update the send window
+        let wl1 = conn.snd_wl1.get();
+        if seq_le(conn.snd_una.get(), ack)
+            && (seq_lt(wl1, seq) || (wl1 == seq && seq_le(conn.snd_wl2.get(), ack)))
+        {
+            conn.snd_wnd.set(window);
+            conn.snd_wl1.set(seq);
+            conn.snd_wl2.set(ack);
+        }
+
+        if conn.snd_wnd.get() == 0 {
+            // The peer is alive, but cannot receive: keep probing it
+            conn.retransmissions.set(0);
+            if conn.outstanding() == 0 && conn.unsent() > 0 && conn.deadline.get().is_none() {
+                self.set_deadline(conn, conn.rto.get());
+            }
+        }
+        true
+    }
+
+    // Resends the sequence space from SND.UNA.
+    fn go_back(&self, conn: &TCPConnection) {
+        let mss = conn.snd_mss.get() as u32;
+        conn.ssthresh.set(max(conn.outstanding() / 2, 2 * mss));
+        conn.snd_nxt.set(conn.snd_una.get());
+        conn.rtt_sample.set(None);
+        conn.dup_acks.set(0);
+    }
+
+    fn fin_received(&self, id: usize) {
+        let conn = &self.connections[id];
+        conn.rcv_nxt.set(conn.rcv_nxt.get().wrapping_add(1));
+        conn.ack_pending.set(true);
+        match conn.state.get() {
+            TCPState::Established => {
+                conn.state.set(TCPState::CloseWait);
+                self.client.map(|client| client.peer_closed(id));
+            }
+            TCPState::FinWait1 => {
+                // Our FIN was not acknowledged yet
+                conn.state.set(TCPState::Closing);
+                self.client.map(|client| client.peer_closed(id));
+            }
+            TCPState::FinWait2 => {
+                conn.state.set(TCPState::TimeWait);
+                self.set_deadline(conn, TIME_WAIT);
+                self.client.map(|client| client.peer_closed(id));
+                self.client
+                    .map(|client| client.closed(id, ReturnCode::SUCCESS));
+            }
+            _ => {}
+        }
+    }
+
+    // Queues a reset in reply to a segment, from RFC 793 section 3.4.
+    fn reply_reset(&self, ip_header: &IP6Header, header: &TCPHeader, data_len: usize) {
+        let reset = if header.has_flags(tcp_flags::ACK) {
+            Reset {
+                addr: ip_header.get_src_addr(),
+                local_port: header.get_dst_port(),
+                remote_port: header.get_src_port(),
+                seq: header.get_ack_num(),
+                ack: None,
+            }
+        } else {
+            let seg_len = data_len as u32
+                + header.has_flags(tcp_flags::SYN) as u32
+                + header.has_flags(tcp_flags::FIN) as u32;
+            Reset {
+                addr: ip_header.get_src_addr(),
+                local_port: header.get_dst_port(),
+                remote_port: header.get_src_port(),
+                seq: 0,
+                ack: Some(header.get_seq_num().wrapping_add(seg_len)),
+            }
+        };
+        self.pending_reset.set(Some(reset));
+    }
+}
+
+impl<T: IP6Sender<'a>, A: time::Alarm<'a>> TCPStack<'a> for TCPStackStruct<'a, T, A> {
+    fn set_client(&self, client: &'a dyn TCPClient) {
+        self.client.set(client);
+    }
+
+    fn connect(&self, addr: IPAddr, port: u16, owner: Option<AppId>) -> Result<usize, ReturnCode> {
+        if port == 0 || addr.is_unspecified() || addr.is_multicast() {
+            return Err(ReturnCode::EINVAL);
+        }
+        let id = self.allocate(owner)?;
+        let conn = &self.connections[id];
+        conn.reset(TCPState::SynSent, self.new_port(), self.new_iss());
+        conn.remote_addr.set(addr);
+        conn.remote_port.set(port);
+        conn.set_mss(self.mss, None);
+        self.output();
+        Ok(id)
+    }
+
+    fn listen(&self, port: u16, owner: Option<AppId>) -> Result<usize, ReturnCode> {
+        if port == 0 {
+            return Err(ReturnCode::EINVAL);
+        }
+        let id = self.allocate(owner)?;
+        self.connections[id].reset(TCPState::Listen, port, 0);
+        Ok(id)
+    }
+
+    fn send(&self, id: usize, data: &[u8]) -> Result<usize, ReturnCode> {
+        let conn = self.get(id)?;
+        match conn.state.get() {
+            TCPState::SynSent
+            | TCPState::SynReceived
+            | TCPState::Established
+            | TCPState::CloseWait => {}
+            _ => return Err(ReturnCode::EINVAL),
+        }
+        let send_len = conn.send_len.get();
+        let len = min(data.len(), conn.send_capacity - send_len);
+        conn.send_buffer.map(|buffer| {
+            buffer[send_len..send_len + len].copy_from_slice(&data[..len]);
+        });
+        conn.send_len.set(send_len + len);
+        self.output();
+        Ok(len)
+    }
+
+    fn receive(&self, id: usize, buf: &mut [u8]) -> Result<usize, ReturnCode> {
+        let conn = self.get(id)?;
+        let recv_len = conn.recv_len.get();
+        let len = min(buf.len(), recv_len);
+        if len == 0 {
+            return Ok(0);
+        }
+        let window_before = conn.rcv_wnd();
+        conn.recv_buffer.map(|buffer| {
+            buf[..len].copy_from_slice(&buffer[..len]);
+            buffer.copy_within(len..recv_len, 0);
+        });
+        conn.recv_len.set(recv_len - len);
+
+        // Tell the peer the window opened if it was too small for a segment
+        let mss = self.mss as u32;
+        if window_before < mss && conn.rcv_wnd() >= min(mss, conn.recv_capacity as u32 / 2) {
+            conn.ack_pending.set(true);
+            self.output();
+        }
+        Ok(len)
+    }
+
+    fn close(&self, id: usize) -> ReturnCode {
+        let conn = match self.get(id) {
+            Ok(conn) => conn,
+            Err(err) => return err,
+        };
+        match conn.state.get() {
+            TCPState::Listen | TCPState::SynSent => {
+                conn.state.set(TCPState::Closed);
+                self.clear_deadline(conn);
+            }
+            TCPState::SynReceived | TCPState::Established => {
+                conn.state.set(TCPState::FinWait1);
+                self.output();
+            }
+            TCPState::CloseWait => {
+                conn.state.set(TCPState::LastAck);
+                self.output();
+            }
+            _ => return ReturnCode::EALREADY,
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn abort(&self, id: usize) -> ReturnCode {
+        let conn = match self.get(id) {
+            Ok(conn) => conn,
+            Err(err) => return err,
+        };
+        if conn.state.get() == TCPState::Closed {
+            return ReturnCode::EALREADY;
+        }
+        self.reset_connection(conn);
+        conn.state.set(TCPState::Closed);
+        conn.send_len.set(0);
+        conn.recv_len.set(0);
+        self.clear_deadline(conn);
+        self.output();
+        ReturnCode::SUCCESS
+    }
+
+    fn release(&self, id: usize) -> ReturnCode {
+        let conn = match self.get(id) {
+            Ok(conn) => conn,
+            Err(err) => return err,
+        };
+        match conn.state.get() {
+            // A connection in TIME-WAIT is reused once the timer expires
+            TCPState::Closed | TCPState::TimeWait => {
+                conn.in_use.set(false);
+                conn.owner.set(None);
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::EBUSY,
+        }
+    }
+
+    fn state(&self, id: usize) -> Option<TCPState> {
+        self.get(id).ok().map(|conn| conn.state.get())
+    }
+
+    fn remote_endpoint(&self, id: usize) -> Option<(IPAddr, u16)> {
+        self.get(id).ok().and_then(|conn| match conn.state.get() {
+            TCPState::Closed | TCPState::Listen => None,
+            _ => Some((conn.remote_addr.get(), conn.remote_port.get())),
+        })
+    }
+}
+
+impl<T: IP6Sender<'a>, A: time::Alarm<'a>> IP6RecvClient for TCPStackStruct<'a, T, A> {
+    fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        let (offset, header) = match TCPHeader::decode(payload).done() {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        if ip_header.get_dst_addr().is_multicast() {
+            return;
+        }
+        let data = &payload[offset..];
+        let src_addr = ip_header.get_src_addr();
+        let local_port = header.get_dst_port();
+        let remote_port = header.get_src_port();
+        if header.has_flags(tcp_flags::SYN) {
+            // Do not let a process that is gone accept the peer
+            self.reclaim();
+        }
+
+        let connection = self.connections.iter().position(|conn| {
+            let state = conn.state.get();
+            state != TCPState::Closed
+                && state != TCPState::Listen
+                && conn.local_port.get() == local_port
+                && conn.remote_port.get() == remote_port
+                && conn.remote_addr.get() == src_addr
+        });
+        let listener = || {
+            self.connections.iter().position(|conn| {
+                conn.state.get() == TCPState::Listen && conn.local_port.get() == local_port
+            })
+        };
+
+        match connection.or_else(listener) {
+            Some(id) => {
+                let conn = &self.connections[id];
+                match conn.state.get() {
+                    TCPState::Listen => self.listen_segment(conn, &ip_header, &header),
+                    TCPState::SynSent => self.syn_sent_segment(id, &ip_header, &header),
+                    _ => self.synchronized_segment(id, &ip_header, &header, data),
+                }
+            }
+            None => {
+                if !header.has_flags(tcp_flags::RST) {
+                    self.reply_reset(&ip_header, &header, data.len());
+                }
+            }
+        }
+        self.output();
+    }
+}
+
+impl<T: IP6Sender<'a>, A: time::Alarm<'a>> IP6SendClient for TCPStackStruct<'a, T, A> {
+    fn send_done(&self, _result: ReturnCode) {
+        self.sending.set(false);
+        self.output();
+    }
+}
+
+impl<T: IP6Sender<'a>, A: time::Alarm<'a>> time::AlarmClient for TCPStackStruct<'a, T, A> {
+    fn fired(&self) {
+        let now = self.alarm.now();
+        for (id, conn) in self.connections.iter().enumerate() {
+            match conn.deadline.get() {
+                Some(deadline) if (now.wrapping_sub(deadline) as i32) >= 0 => {}
+                _ => continue,
+            }
+            conn.deadline.set(None);
+
+            if conn.state.get() == TCPState::TimeWait {
+                conn.state.set(TCPState::Closed);
+            } else if conn.outstanding() > 0 {
+                let retransmissions = conn.retransmissions.get() + 1;
+                if retransmissions > MAX_RETRANSMISSIONS {
+                    self.reset_connection(conn);
+                    conn.send_len.set(0);
+                    self.close_connection(id, ReturnCode::ENOACK);
+                    continue;
+                }
+                conn.retransmissions.set(retransmissions);
+                conn.rto.set(min(2 * conn.rto.get(), MAX_RTO));
+                self.go_back(conn);
+                conn.cwnd.set(conn.snd_mss.get() as u32);
+                conn.deadline
+                    .set(Some(now.wrapping_add(Self::ms_to_tics(conn.rto.get()))));
+            } else if conn.unsent() > 0 && conn.state.get().sends_data() {
+                // The persist timer: probe the closed window
+                conn.probe.set(true);
+                conn.rto.set(min(2 * conn.rto.get(), MAX_RTO));
+                conn.deadline
+                    .set(Some(now.wrapping_add(Self::ms_to_tics(conn.rto.get()))));
+            }
+        }
+        self.update_alarm();
+        self.output();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use crate::net::mock::{buf, MockAlarm, MockSender};
+    use core::cell::RefCell;
+    use std::vec::Vec;
+
+    type TestStack<'a> = TCPStackStruct<'a, MockSender, MockAlarm>;
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Connected,
+        Received,
+        Sent,
+        PeerClosed,
+        Closed(ReturnCode),
+    }
+
+    struct Client {
+        events: RefCell<Vec<(usize, Event)>>,
+    }
+
+    impl TCPClient for Client {
+        fn connected(&self, id: usize) {
+            self.events.borrow_mut().push((id, Event::Connected));
+        }
+        fn received(&self, id: usize) {
+            self.events.borrow_mut().push((id, Event::Received));
+        }
+        fn sent(&self, id: usize) {
+            self.events.borrow_mut().push((id, Event::Sent));
+        }
+        fn peer_closed(&self, id: usize) {
+            self.events.borrow_mut().push((id, Event::PeerClosed));
+        }
+        fn closed(&self, id: usize, result: ReturnCode) {
+            self.events.borrow_mut().push((id, Event::Closed(result)));
+        }
+        fn owner_uses(&self, _id: usize, _owner: AppId) -> bool {
+            true
+        }
+    }
+
+    const PEER_PORT: u16 = 80;
+
+    fn peer() -> IPAddr {
+        let mut addr = IPAddr::new();
+        addr.0[..2].copy_from_slice(&[0xfe, 0x80]);
+        addr.0[15] = 2;
+        addr
+    }
+
+    struct Mocks {
+        sender: MockSender,
+        alarm: MockAlarm,
+        client: Client,
+        connections: [TCPConnection; 2],
+    }
+
+    impl Mocks {
+        fn new() -> Mocks {
+            Mocks {
+                sender: MockSender::new(),
+                alarm: MockAlarm::new(),
+                client: Client {
+                    events: RefCell::new(Vec::new()),
+                },
+                connections: [
+                    TCPConnection::new(buf(64), buf(64)),
+                    TCPConnection::new(buf(64), buf(64)),
+                ],
+            }
+        }
+
+        fn stack(&self) -> TestStack {
+            let stack = TCPStackStruct::new(&self.sender, &self.alarm, &self.connections, 32);
+            stack.set_client(&self.client);
+            stack
+        }
+
+        // The segments the stack sent, letting it send all it has.
+        fn sent(&self, stack: &TestStack) -> Vec<(TCPHeader, Vec<u8>)> {
+            let mut segments = Vec::new();
+            loop {
+                let sent = self.sender.take();
+                if sent.is_empty() {
+                    return segments;
+                }
+                for packet in sent {
+                    assert_eq!(packet.dst, peer());
+                    match packet.header {
+                        TransportHeader::TCP(header) => segments.push((header, packet.payload)),
+                        _ => panic!("not a TCP segment"),
+                    }
+                }
+                stack.send_done(ReturnCode::SUCCESS);
+            }
+        }
+
+        fn sent_one(&self, stack: &TestStack) -> (TCPHeader, Vec<u8>) {
+            let mut sent = self.sent(stack);
+            assert_eq!(sent.len(), 1);
+            sent.remove(0)
+        }
+
+        fn events(&self) -> Vec<(usize, Event)> {
+            self.client.events.replace(Vec::new())
+        }
+
+        // Passes a segment from the peer to the stack.
+        fn segment(
+            &self,
+            stack: &TestStack,
+            local_port: u16,
+            flags: u16,
+            seq: u32,
+            ack: u32,
+            data: &[u8],
+        ) {
+            let mut header = TCPHeader::new();
+            header.set_src_port(PEER_PORT);
+            header.set_dst_port(local_port);
+            header.set_seq_num(seq);
+            header.set_ack_num(ack);
+            header.set_flags(flags);
+            header.set_window(1000);
+            if flags & tcp_flags::SYN != 0 {
+                header.set_mss(Some(100));
+            }
+            let mut payload = std::vec![0; header.get_hdr_size()];
+            header.encode(&mut payload, 0).done().unwrap();
+            payload.extend_from_slice(data);
+            let mut ip_header = IP6Header::new();
+            ip_header.src_addr = peer();
+            ip_header.dst_addr.0[..2].copy_from_slice(&[0xfe, 0x80]);
+            ip_header.dst_addr.0[15] = 1;
+            IP6RecvClient::receive(stack, ip_header, &payload);
+        }
+
+        // Opens a connection to the peer, whose initial sequence number is
+        // 1000. Returns its id, its local port and the next sequence number.
+        fn establish(&self, stack: &TestStack) -> (usize, u16, u32) {
+            let id = stack.connect(peer(), PEER_PORT, None).unwrap();
+            let (syn, _) = self.sent_one(stack);
+            let port = syn.get_src_port();
+            let iss = syn.get_seq_num();
+            self.segment(
+                stack,
+                port,
+                tcp_flags::SYN | tcp_flags::ACK,
+                1000,
+                iss + 1,
+                &[],
+            );
+            assert_eq!(stack.state(id), Some(TCPState::Established));
+            assert_eq!(self.events(), [(id, Event::Connected)]);
+            let (ack, _) = self.sent_one(stack);
+            assert_eq!(ack.get_ack_num(), 1001);
+            (id, port, iss + 1)
+        }
+    }
+
+    #[test]
+    fn connect_sends_syn() {
+        let mocks = Mocks::new();
+        let stack = mocks.stack();
+        let id = stack.connect(peer(), PEER_PORT, None).unwrap();
+        assert_eq!(stack.state(id), Some(TCPState::SynSent));
+        let (syn, data) = mocks.sent_one(&stack);
+        assert_eq!(syn.get_flags(), tcp_flags::SYN);
+        assert_eq!(syn.get_dst_port(), PEER_PORT);
+        assert!(syn.get_src_port() >= FIRST_EPHEMERAL_PORT);
+        assert_eq!(syn.get_mss(), Some(32));
+        assert!(data.is_empty());
+        assert_eq!(stack.remote_endpoint(id), Some((peer(), PEER_PORT)));
+
+        assert_eq!(
+            stack.connect(IPAddr::new(), PEER_PORT, None),
+            Err(ReturnCode::EINVAL)
+        );
+        assert_eq!(stack.connect(peer(), 0, None), Err(ReturnCode::EINVAL));
+        stack.connect(peer(), PEER_PORT, None).unwrap();
+        assert_eq!(
+            stack.connect(peer(), PEER_PORT, None),
+            Err(ReturnCode::ENOMEM)
+        );
+    }
+
+    #[test]
+    fn send_receive_and_close() {
+        let mocks = Mocks::new();
+        let stack = mocks.stack();
+        let (id, port, seq) = mocks.establish(&stack);
+
+        // Data is sent in segments of the MSS
+        assert_eq!(stack.send(id, &[7; 40]), Ok(40));
+        let sent = mocks.sent(&stack);
+        assert_eq!(sent.len(), 2);
+        assert_eq!((sent[0].0.get_seq_num(), sent[0].1.len()), (seq, 32));
+        assert_eq!((sent[1].0.get_seq_num(), sent[1].1.len()), (seq + 32, 8));
+        assert!(sent[1].0.has_flags(tcp_flags::PSH));
+        mocks.segment(&stack, port, tcp_flags::ACK, 1001, seq + 40, &[]);
+        assert_eq!(mocks.events(), [(id, Event::Sent)]);
+        assert!(mocks.sent(&stack).is_empty());
+
+        // The send buffer bounds what is queued
+        assert_eq!(stack.send(id, &[0; 100]), Ok(64));
+        mocks.sent(&stack);
+        mocks.segment(&stack, port, tcp_flags::ACK, 1001, seq + 104, &[]);
+        mocks.events();
+
+        // Data and the FIN of the peer
+        mocks.segment(
+            &stack,
+            port,
+            tcp_flags::ACK | tcp_flags::FIN,
+            1001,
+            seq + 104,
+            b"hi",
+        );
+        assert_eq!(
+            mocks.events(),
+            [(id, Event::Received), (id, Event::PeerClosed)]
+        );
+        assert_eq!(stack.state(id), Some(TCPState::CloseWait));
+        let (ack, _) = mocks.sent_one(&stack);
+        assert_eq!(ack.get_ack_num(), 1004);
+        let mut buf = [0; 8];
+        assert_eq!(TCPStack::receive(&stack, id, &mut buf), Ok(2));
+        assert_eq!(&buf[..2], b"hi");
+        assert_eq!(TCPStack::receive(&stack, id, &mut buf), Ok(0));
+
+        // Our FIN
+        assert_eq!(stack.release(id), ReturnCode::EBUSY);
+        assert_eq!(stack.close(id), ReturnCode::SUCCESS);
+        assert_eq!(stack.state(id), Some(TCPState::LastAck));
+        let (fin, _) = mocks.sent_one(&stack);
+        assert_eq!(fin.get_flags(), tcp_flags::FIN | tcp_flags::ACK);
+        assert_eq!(fin.get_seq_num(), seq + 104);
+        mocks.segment(&stack, port, tcp_flags::ACK, 1004, seq + 105, &[]);
+        assert_eq!(mocks.events(), [(id, Event::Closed(ReturnCode::SUCCESS))]);
+        assert_eq!(stack.state(id), Some(TCPState::Closed));
+        assert_eq!(stack.release(id), ReturnCode::SUCCESS);
+        assert_eq!(stack.state(id), None);
+    }
+
+    #[test]
+    fn active_close_waits_in_time_wait() {
+        let mocks = Mocks::new();
+        let stack = mocks.stack();
+        let (id, port, seq) = mocks.establish(&stack);
+        assert_eq!(stack.close(id), ReturnCode::SUCCESS);
+        assert_eq!(
+            mocks.sent_one(&stack).0.get_flags(),
+            tcp_flags::FIN | tcp_flags::ACK
+        );
+        mocks.segment(&stack, port, tcp_flags::ACK, 1001, seq + 1, &[]);
+        assert_eq!(stack.state(id), Some(TCPState::FinWait2));
+        mocks.segment(
+            &stack,
+            port,
+            tcp_flags::ACK | tcp_flags::FIN,
+            1001,
+            seq + 1,
+            &[],
+        );
+        assert_eq!(stack.state(id), Some(TCPState::TimeWait));
+        assert_eq!(
+            mocks.events(),
+            [
+                (id, Event::PeerClosed),
+                (id, Event::Closed(ReturnCode::SUCCESS))
+            ]
+        );
+        assert_eq!(mocks.sent_one(&stack).0.get_ack_num(), 1002);
+
+        assert!(!mocks.alarm.advance(TIME_WAIT - 1));
+        assert!(mocks.alarm.advance(1));
+        time::AlarmClient::fired(&stack);
+        assert_eq!(stack.state(id), Some(TCPState::Closed));
+    }
+
+    #[test]
+    fn listen_accepts_a_peer() {
+        let mocks = Mocks::new();
+        let stack = mocks.stack();
+        let id = stack.listen(7, None).unwrap();
+        assert_eq!(stack.state(id), Some(TCPState::Listen));
+        assert_eq!(stack.remote_endpoint(id), None);
+
+        mocks.segment(&stack, 7, tcp_flags::SYN, 5000, 0, &[]);
+        assert_eq!(stack.state(id), Some(TCPState::SynReceived));
+        let (syn_ack, _) = mocks.sent_one(&stack);
+        assert_eq!(syn_ack.get_flags(), tcp_flags::SYN | tcp_flags::ACK);
+        assert_eq!(syn_ack.get_ack_num(), 5001);
+        assert_eq!(syn_ack.get_src_port(), 7);
+
+        // A reset sends the connection back to LISTEN
+        let iss = syn_ack.get_seq_num();
+        mocks.segment(&stack, 7, tcp_flags::RST, 5001, 0, &[]);
+        assert_eq!(stack.state(id), Some(TCPState::Listen));
+
+        mocks.segment(&stack, 7, tcp_flags::SYN, 6000, 0, &[]);
+        let (syn_ack, _) = mocks.sent_one(&stack);
+        assert_ne!(syn_ack.get_seq_num(), iss);
+        mocks.segment(
+            &stack,
+            7,
+            tcp_flags::ACK,
+            6001,
+            syn_ack.get_seq_num() + 1,
+            b"x",
+        );
+        assert_eq!(stack.state(id), Some(TCPState::Established));
+        assert_eq!(
+            mocks.events(),
+            [(id, Event::Connected), (id, Event::Received)]
+        );
+        assert_eq!(stack.remote_endpoint(id), Some((peer(), PEER_PORT)));
+    }
+
+    #[test]
+    fn unknown_segments_are_reset() {
+        let mocks = Mocks::new();
+        let stack = mocks.stack();
+        mocks.segment(&stack, 9, tcp_flags::SYN, 100, 0, b"ab");
+        let (reset, _) = mocks.sent_one(&stack);
+        assert_eq!(reset.get_flags(), tcp_flags::RST | tcp_flags::ACK);
+        assert_eq!(reset.get_seq_num(), 0);
+        assert_eq!(reset.get_ack_num(), 103);
+
+        mocks.segment(&stack, 9, tcp_flags::ACK, 100, 4242, &[]);
+        let (reset, _) = mocks.sent_one(&stack);
+        assert_eq!(reset.get_flags(), tcp_flags::RST);
+        assert_eq!(reset.get_seq_num(), 4242);
+
+        // Resets are never answered
+        mocks.segment(&stack, 9, tcp_flags::RST, 100, 0, &[]);
+        assert!(mocks.sent(&stack).is_empty());
+    }
+
+    #[test]
+    fn peer_reset_closes_the_connection() {
+        let mocks = Mocks::new();
+        let stack = mocks.stack();
+        let (id, port, seq) = mocks.establish(&stack);
+        // Out of the window: ignored
+        mocks.segment(&stack, port, tcp_flags::RST, 5000, 0, &[]);
+        assert_eq!(stack.state(id), Some(TCPState::Established));
+        mocks.segment(&stack, port, tcp_flags::RST, 1001, seq, &[]);
+        assert_eq!(stack.state(id), Some(TCPState::Closed));
+        assert!(mocks
+            .events()
+            .contains(&(id, Event::Closed(ReturnCode::ECANCEL))));
+    }
+
+    #[test]
+    fn retransmits_until_it_gives_up() {
+        let mocks = Mocks::new();
+        let stack = mocks.stack();
+        let id = stack.connect(peer(), PEER_PORT, None).unwrap();
+        let (syn, _) = mocks.sent_one(&stack);
+        let mut timeout = INITIAL_RTO;
+        for _ in 0..MAX_RETRANSMISSIONS {
+            assert!(!mocks.alarm.advance(timeout - 1));
+            assert!(mocks.alarm.advance(1));
+            time::AlarmClient::fired(&stack);
+            let (again, _) = mocks.sent_one(&stack);
+            assert_eq!(again.get_seq_num(), syn.get_seq_num());
+            assert!(again.has_flags(tcp_flags::SYN));
+            timeout = min(2 * timeout, MAX_RTO);
+        }
+        assert!(mocks.alarm.advance(timeout));
+        time::AlarmClient::fired(&stack);
+        assert_eq!(stack.state(id), Some(TCPState::Closed));
+        assert_eq!(mocks.events(), [(id, Event::Closed(ReturnCode::ENOACK))]);
+    }
+
+    #[test]
+    fn three_duplicate_acks_resend() {
+        let mocks = Mocks::new();
+        let stack = mocks.stack();
+        let (id, port, seq) = mocks.establish(&stack);
+        stack.send(id, &[1; 64]).unwrap();
+        assert_eq!(mocks.sent(&stack).len(), 2);
+        for _ in 0..2 {
+            mocks.segment(&stack, port, tcp_flags::ACK, 1001, seq, &[]);
+            assert!(mocks.sent(&stack).is_empty());
+        }
+        mocks.segment(&stack, port, tcp_flags::ACK, 1001, seq, &[]);
+        let sent = mocks.sent(&stack);
+        assert_eq!(sent[0].0.get_seq_num(), seq);
+        assert_eq!(sent[0].1, [1; 32]);
+    }
+
+    #[test]
+    fn state_predicates() {
+        use TCPState::*;
+        let states = [
+            Closed,
+            Listen,
+            SynSent,
+            SynReceived,
+            Established,
+            FinWait1,
+            FinWait2,
+            CloseWait,
+            Closing,
+            LastAck,
+            TimeWait,
+        ];
+        let sends_fin = [FinWait1, Closing, LastAck];
+        let sends_data = [Established, CloseWait, FinWait1, Closing, LastAck];
+        let receives_data = [Established, FinWait1, FinWait2];
+        for &state in states.iter() {
+            assert_eq!(state.sends_fin(), sends_fin.contains(&state));
+            assert_eq!(state.sends_data(), sends_data.contains(&state));
+            assert_eq!(state.receives_data(), receives_data.contains(&state));
+        }
+        assert!(seq_lt(0xffff_fff0, 0x10));
+        assert!(!seq_lt(0x10, 0xffff_fff0));
+        assert!(seq_le(5, 5));
+    }
+}
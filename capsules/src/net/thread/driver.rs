@@ -0,0 +1,123 @@
+//! Thread userspace interface.
+//!
+//! Lets apps attach the device to a Thread network as a Sleepy End Device,
+//! detach it, and learn whether it is attached. The attachment is shared by
+//! all apps: each app that subscribed is told when the device attaches and
+//! when it detaches, whichever app started it.
+
+use crate::net::ipv6::ipv6_send::IP6Sender;
+use crate::net::thread::mle::{Mle, MleClient, State};
+use kernel::hil::symmetric_encryption::AES128CCM;
+use kernel::hil::time;
+use kernel::{AppId, Callback, Driver, Grant, ReturnCode};
+
+/// Syscall number
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Thread as usize;
+
+/// Events reported by the callback of subscribe number 0.
+mod event {
+    pub const ATTACHED: usize = 0;
+    pub const DETACHED: usize = 1;
+}
+
+#[derive(Default)]
+pub struct App {
+    callback: Option<Callback>,
+}
+
+pub struct ThreadDriver<'a, A: time::Alarm<'a>, S: IP6Sender<'a>, C: AES128CCM<'a>> {
+    mle: &'a Mle<'a, A, S, C>,
+    apps: Grant<App>,
+}
+
+impl<A: time::Alarm<'a>, S: IP6Sender<'a>, C: AES128CCM<'a>> ThreadDriver<'a, A, S, C> {
+    pub fn new(mle: &'a Mle<'a, A, S, C>, grant: Grant<App>) -> ThreadDriver<'a, A, S, C> {
+        ThreadDriver { mle, apps: grant }
+    }
+
+    fn schedule_event(&self, event: usize, data: usize) {
+        self.apps.each(|app| {
+            app.callback.map(|mut cb| cb.schedule(event, data, 0));
+        });
+    }
+}
+
+impl<A: time::Alarm<'a>, S: IP6Sender<'a>, C: AES128CCM<'a>> MleClient
+    for ThreadDriver<'a, A, S, C>
+{
+    fn attached(&self, rloc16: u16) {
+        self.schedule_event(event::ATTACHED, rloc16 as usize);
+    }
+
+    fn detached(&self, result: ReturnCode) {
+        self.schedule_event(event::DETACHED, usize::from(result));
+    }
+}
+
+impl<A: time::Alarm<'a>, S: IP6Sender<'a>, C: AES128CCM<'a>> Driver for ThreadDriver<'a, A, S, C> {
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Attachment events. The callback receives `0` and the RLOC16 of
+    ///        the device when it attaches, and `1` and a return code when it
+    ///        detaches: SUCCESS if detached by command 2, ECANCEL if the
+    ///        attach was canceled by command 2, and FAIL if the attach
+    ///        failed or the attachment timed out.
+    fn subscribe(
+        &self,
+        subscribe_num: usize,
+        callback: Option<Callback>,
+        app_id: AppId,
+    ) -> ReturnCode {
+        match subscribe_num {
+            0 => self
+                .apps
+                .enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Attach and detach.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Attach to a parent. Returns EALREADY if the device is attached
+    ///        or attaching.
+    /// - `2`: Detach, or cancel the attach in progress. Returns EALREADY if
+    ///        the device is detached.
+    /// - `3`: Get the state of the attachment: `0` if detached, `1` if
+    ///        attaching and `2` if attached.
+    /// - `4`: Get the RLOC16 of the device. Returns ERESERVE if it is not
+    ///        attached.
+    fn command(&self, command_num: usize, _: usize, _: usize, _: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => self.mle.attach(),
+            2 => self.mle.detach(),
+            3 => {
+                let state = match self.mle.get_state() {
+                    State::Detached => 0,
+                    State::Attached { .. } => 2,
+                    _ => 1,
+                };
+                ReturnCode::SuccessWithValue { value: state }
+            }
+            4 => self
+                .mle
+                .get_rloc16()
+                .map_or(ReturnCode::ERESERVE, |rloc16| {
+                    ReturnCode::SuccessWithValue {
+                        value: rloc16 as usize,
+                    }
+                }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
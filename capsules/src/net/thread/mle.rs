@@ -0,0 +1,1333 @@
+//! This file implements the Mesh Link Establishment (MLE) handshake with
+//! which a Sleepy End Device (SED) attaches to a Thread network, as outlined
+//! in Chapter 4 of the Thread 1.1.1 Specification. MLE messages are sent
+//! over UDP port 19788, and consist of a command type and a series of TLV
+//! parameters, encoded and decoded by the `tlv` module.
+//!
+//! Attaching takes four steps:
+//!
+//!     1. The child multicasts a Parent Request to all routers, and then to
+//!        all routers and router-eligible end devices (REEDs) if no router
+//!        answered.
+//!     2. Each potential parent unicasts a Parent Response to the child.
+//!     3. The child selects a parent by a hierarchy of connectivity metrics:
+//!        the quality of the link to the parent, the priority the parent
+//!        advertises, and the number of links of each quality the parent
+//!        has. It unicasts a Child ID Request to the selected parent.
+//!     4. The parent unicasts a Child ID Response, which answers the
+//!        challenge of the Child ID Request and assigns the child its
+//!        RLOC16, the short address the child has in the network.
+//!
+//! Challenges and responses bind each answer to the request it answers, and
+//! every message is secured with MLE security: the command and the TLVs are
+//! encrypted with AES-CCM* under the MLE key of the network, and
+//! authenticated along with the source and destination addresses of the
+//! packet and the auxiliary security header.
+//!
+//! The challenges are drawn from a random number generator. The frame
+//! counter of the messages, which is part of their nonce, must never repeat
+//! under a key, including across reboots: the frame counters are reserved in
+//! blocks of `FRAME_COUNTER_RESERVE`, by writing the end of the block to
+//! nonvolatile storage before any of them is used. After a reboot, the frame
+//! counter starts at the end of the last block reserved. A reservation that
+//! fails is tried again with the next message sent, or the next Parent
+//! Request if no frame counter is left.
+//!
+//! Once attached, the parent is the default router of the interface, and
+//! the interface has the Routing Locator (RLOC) address formed from the
+//! mesh-local prefix of the network and the RLOC16.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use capsules::net::thread::mle::Mle;
+//! # use kernel::static_init;
+//! // 4 bytes of nonvolatile storage hold the end of the reserved frame
+//! // counters
+//! let mle = static_init!(
+//!     Mle<'static, VirtualMuxAlarm<'static, Ast>, IP6SendStruct<'static, ...>, VirtualAES128CCM<'static, ...>>,
+//!     Mle::new(ip_send, interface, mle_alarm, mle_aes_ccm, rng, storage, storage_address, eui64,
+//!              &mut MLE_TX_BUF, &mut MLE_RX_BUF, &mut MLE_STORAGE_BUF)
+//! );
+//! mle.set_key(key_sequence, mle_key);
+//! ip_send.set_client(mle);
+//! mle_alarm.set_client(mle);
+//! mle_aes_ccm.set_client(mle);
+//! rng.set_client(mle);
+//! storage.set_client(mle);
+//! // Receives the packets sent to the MLE port
+//! let mle_udp_client = static_init!(UDPKernelClient<'static>, UDPKernelClient::new(MLE_PORT, mle));
+//! udp_recv.add_kernel_client(mle_udp_client);
+//! mle.attach();
+//! ```
+
+// Known Limitations
+// -----------------
+// - Only MLE messages are secured: the 802.15.4 frames are sent without MAC
+//   layer security, and the MAC layer keeps its short address.
+// - The MLE key is supplied directly, instead of being derived from the
+//   master key of the network with HMAC-SHA256.
+// - The quality of the link to a parent is taken from the link margin the
+//   parent measured for the Parent Request, as the radio does not report the
+//   signal strength of the Parent Response.
+// - Child Update is not implemented: the device does not keep its parent
+//   informed that it is alive, and considers itself detached once the child
+//   timeout elapses. It must attach again then.
+// - Messages that arrive while the AES engine is busy with another message
+//   of MLE are dropped, and so are messages that cannot be sent because the
+//   sender is busy or no frame counter is reserved. The timers of the
+//   handshake retry them.
+// - Up to `FRAME_COUNTER_RESERVE` frame counters are skipped at each reboot.
+
+use crate::net::icmpv6::nd::ALL_ROUTERS_ADDR;
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6::TransportHeader;
+use crate::net::ipv6::ipv6_interface::{IP6Interface, Neighbor};
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::thread::tlv::{LinkMode, MulticastResponder, NetworkManagementTlv, Tlv, TlvType};
+use crate::net::udp::udp::UDPHeader;
+use crate::net::udp::udp_recv::UDPRecvClient;
+use core::cell::Cell;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::nonvolatile_storage::{NonvolatileStorage, NonvolatileStorageClient};
+use kernel::hil::rng::{self, Rng};
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM, AES128_KEY_SIZE, CCM_NONCE_LENGTH};
+use kernel::hil::time::{self, Frequency};
+use kernel::ReturnCode;
+
+/// The UDP port of MLE.
+pub const MLE_PORT: u16 = 19788;
+
+/// The size of the buffers MLE messages are secured in, which bounds the
+/// size of the messages that can be sent and received.
+pub const MLE_BUF_LEN: usize = 256;
+
+/// The size of the nonvolatile storage, and of its buffer, that holds the
+/// end of the reserved frame counters.
+pub const MLE_STORAGE_LEN: usize = 4;
+
+/// Number of frame counters reserved in nonvolatile storage at a time. The
+/// next block is reserved once half of the current one is used.
+const FRAME_COUNTER_RESERVE: u32 = 1024;
+
+/// The first byte of MLE messages, for messages secured with MLE security.
+/// Unsecured messages, which start with 255, are only used for discovery.
+const SECURITY_SUITE: u8 = 0;
+
+// The auxiliary security header: security level 5 (ENC-MIC-32), with the
+// key identified by a 4 byte key source, the key sequence, and a key index
+const SECURITY_LEVEL: u8 = 5;
+const KEY_ID_MODE_2: u8 = 0b10 << 3;
+const SECURITY_CONTROL: u8 = SECURITY_LEVEL | KEY_ID_MODE_2;
+const AUX_HDR_LEN: usize = 10;
+const MIC_LEN: usize = 4;
+
+// The authenticated data of a message is the source and destination
+// addresses followed by the auxiliary security header. Messages are secured
+// in buffers that start with it.
+const AUX_HDR_OFF: usize = 32;
+const AUTH_LEN: usize = AUX_HDR_OFF + AUX_HDR_LEN;
+
+mod command {
+    pub const PARENT_REQUEST: u8 = 9;
+    pub const PARENT_RESPONSE: u8 = 10;
+    pub const CHILD_ID_REQUEST: u8 = 11;
+    pub const CHILD_ID_RESPONSE: u8 = 12;
+}
+
+// Times to wait for the answers to requests
+const PARENT_REQUEST_ROUTER_TIMEOUT_MS: u32 = 750;
+const PARENT_REQUEST_REED_TIMEOUT_MS: u32 = 1_250;
+const CHILD_ID_RESPONSE_TIMEOUT_MS: u32 = 1_250;
+
+/// Number of times the handshake is tried before the attach fails.
+const MAX_ATTACH_ATTEMPTS: u8 = 3;
+
+/// Time after which the parent removes a child that did not talk to it, sent
+/// in the Timeout TLV.
+const CHILD_TIMEOUT_S: u32 = 240;
+
+/// The version of the Thread protocol, 2 for Thread 1.1.
+const THREAD_VERSION: u16 = 2;
+
+/// The link mode of a sleepy end device: its receiver is off when idle, and
+/// it only needs the stable network data.
+const SED_MODE: u8 = LinkMode::SecureDataRequests as u8;
+
+/// The TLVs the Child ID Request asks for. The Active Operational Dataset,
+/// with the mesh-local prefix, is sent without being asked for, as the
+/// request has no Active Timestamp TLV.
+const REQUESTED_TLVS: [u8; 2] = [TlvType::Address16 as u8, TlvType::NetworkData as u8];
+
+/// Receives the events of the attachment of an `Mle`.
+pub trait MleClient {
+    /// Called when the device attached to a parent, which assigned it
+    /// `rloc16`.
+    fn attached(&self, rloc16: u16);
+
+    /// Called when the device is no longer attached or attaching. `result`
+    /// is SUCCESS if `detach` ended the attachment, ECANCEL if `detach`
+    /// canceled an attach in progress, and FAIL if no parent accepted the
+    /// device or the attachment timed out.
+    fn detached(&self, result: ReturnCode);
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum State {
+    Detached,
+    /// Waiting for the Parent Responses to a Parent Request, sent to the
+    /// routers only or to the REEDs as well.
+    ParentRequest {
+        reeds: bool,
+    },
+    /// Waiting for the Child ID Response of the selected parent.
+    ChildIdRequest,
+    Attached {
+        rloc16: u16,
+    },
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum CryptOp {
+    Idle,
+    Encrypt,
+    Decrypt,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum StorageOp {
+    Idle,
+    Read,
+    Write,
+}
+
+/// A potential parent, from its Parent Response.
+#[derive(Copy, Clone)]
+struct Parent {
+    ll_addr: IPAddr,
+    eui64: [u8; 8],
+    rloc16: u16,
+    // The challenge of the parent, which the Child ID Request answers
+    challenge: [u8; 8],
+    // The last frame counter of the MLE messages of the parent
+    frame_counter: u32,
+    // Parents are ranked by the link quality to them, their priority and
+    // their number of links of quality 3, 2 and 1, in this order
+    rank: (u8, i8, u8, u8, u8),
+}
+
+pub struct Mle<'a, A: time::Alarm<'a>, S: IP6Sender<'a>, C: AES128CCM<'a>> {
+    ip_sender: &'a S,
+    interface: &'a IP6Interface,
+    alarm: &'a A,
+    aes_ccm: &'a C,
+    rng: &'a dyn Rng<'a>,
+    storage: &'a dyn NonvolatileStorage<'static>,
+    storage_address: usize,
+    eui64: [u8; 8],
+    key_sequence: Cell<u32>,
+    key: Cell<[u8; AES128_KEY_SIZE]>,
+    client: OptionalCell<&'a dyn MleClient>,
+
+    state: Cell<State>,
+    attempts: Cell<u8>,
+    challenge: Cell<[u8; 8]>,
+    // The challenge of the Child ID Request, drawn along with the one of the
+    // Parent Request
+    child_id_challenge: Cell<[u8; 8]>,
+    // The Parent Request waits for its challenge, and then for frame
+    // counters to be reserved
+    awaiting_challenge: Cell<bool>,
+    request_pending: Cell<bool>,
+    parent: Cell<Option<Parent>>,
+    rloc_addr: Cell<Option<IPAddr>>,
+    frame_counter: Cell<u32>,
+    // The frame counters below the limit are reserved. It is unknown until
+    // it is read from the nonvolatile storage, at the first attach.
+    frame_counter_limit: Cell<Option<u32>>,
+    storage_buf: TakeCell<'static, [u8]>,
+    storage_op: Cell<StorageOp>,
+
+    // A message is secured in `tx_buf` before it is sent, and in `rx_buf`
+    // before it is processed. The AES engine handles one of them at a time.
+    tx_buf: TakeCell<'static, [u8]>,
+    tx_dst: Cell<IPAddr>,
+    tx_len: Cell<usize>,
+    sending: Cell<bool>,
+    rx_buf: TakeCell<'static, [u8]>,
+    rx_src: Cell<IPAddr>,
+    rx_frame_counter: Cell<u32>,
+    rx_len: Cell<usize>,
+    crypt_op: Cell<CryptOp>,
+}
+
+impl<A: time::Alarm<'a>, S: IP6Sender<'a>, C: AES128CCM<'a>> Mle<'a, A, S, C> {
+    /// `eui64` is the extended address of the device, from which its
+    /// link-local address is derived. `tx_buf` and `rx_buf` should be
+    /// `MLE_BUF_LEN` bytes long. The frame counters are reserved in the
+    /// `MLE_STORAGE_LEN` bytes of `storage` at `storage_address`, through
+    /// `storage_buf`, which should be `MLE_STORAGE_LEN` bytes long. The key
+    /// of the network must be set with `set_key` before attaching.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        ip_sender: &'a S,
+        interface: &'a IP6Interface,
+        alarm: &'a A,
+        aes_ccm: &'a C,
+        rng: &'a dyn Rng<'a>,
+        storage: &'a dyn NonvolatileStorage<'static>,
+        storage_address: usize,
+        eui64: [u8; 8],
+        tx_buf: &'static mut [u8],
+        rx_buf: &'static mut [u8],
+        storage_buf: &'static mut [u8],
+    ) -> Mle<'a, A, S, C> {
+        Mle {
+            ip_sender,
+            interface,
+            alarm,
+            aes_ccm,
+            rng,
+            storage,
+            storage_address,
+            eui64,
+            key_sequence: Cell::new(0),
+            key: Cell::new([0; AES128_KEY_SIZE]),
+            client: OptionalCell::empty(),
+            state: Cell::new(State::Detached),
+            attempts: Cell::new(0),
+            challenge: Cell::new([0; 8]),
+            child_id_challenge: Cell::new([0; 8]),
+            awaiting_challenge: Cell::new(false),
+            request_pending: Cell::new(false),
+            parent: Cell::new(None),
+            rloc_addr: Cell::new(None),
+            frame_counter: Cell::new(0),
+            frame_counter_limit: Cell::new(None),
+            storage_buf: TakeCell::new(storage_buf),
+            storage_op: Cell::new(StorageOp::Idle),
+            tx_buf: TakeCell::new(tx_buf),
+            tx_dst: Cell::new(IPAddr::new()),
+            tx_len: Cell::new(0),
+            sending: Cell::new(false),
+            rx_buf: TakeCell::new(rx_buf),
+            rx_src: Cell::new(IPAddr::new()),
+            rx_frame_counter: Cell::new(0),
+            rx_len: Cell::new(0),
+            crypt_op: Cell::new(CryptOp::Idle),
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn MleClient) {
+        self.client.set(client);
+    }
+
+    /// Sets the current key sequence of the network, and the MLE key derived
+    /// for it.
+    pub fn set_key(&self, key_sequence: u32, key: [u8; AES128_KEY_SIZE]) {
+        self.key_sequence.set(key_sequence);
+        self.key.set(key);
+    }
+
+    pub fn get_state(&self) -> State {
+        self.state.get()
+    }
+
+    /// Returns the RLOC16 of the device, if it is attached.
+    pub fn get_rloc16(&self) -> Option<u16> {
+        match self.state.get() {
+            State::Attached { rloc16 } => Some(rloc16),
+            _ => None,
+        }
+    }
+
+    /// Starts attaching to a parent. The client is told whether it
+    /// succeeded. Returns EALREADY if the device is attached or attaching.
+    pub fn attach(&self) -> ReturnCode {
+        if self.state.get() != State::Detached {
+            return ReturnCode::EALREADY;
+        }
+        self.interface.add_addr(self.link_local_addr(), None);
+        self.aes_ccm.enable();
+        self.attempts.set(0);
+        self.send_parent_request(false);
+        ReturnCode::SUCCESS
+    }
+
+    /// Ends the attachment, or cancels the attach in progress. Returns
+    /// EALREADY if the device is detached.
+    pub fn detach(&self) -> ReturnCode {
+        match self.state.get() {
+            State::Detached => ReturnCode::EALREADY,
+            State::Attached { .. } => {
+                self.leave(ReturnCode::SUCCESS);
+                ReturnCode::SUCCESS
+            }
+            _ => {
+                self.leave(ReturnCode::ECANCEL);
+                ReturnCode::SUCCESS
+            }
+        }
+    }
+
+    fn link_local_addr(&self) -> IPAddr {
+        IPAddr::generate_from_mac(MacAddress::Long(self.eui64))
+    }
+
+    fn set_timer(&self, ms: u32) {
+        let tics = ms as u64 * <A::Frequency>::frequency() as u64 / 1000;
+        self.alarm
+            .set_alarm(self.alarm.now().wrapping_add(tics as u32));
+    }
+
+    /// Reserves the next block of frame counters, after reading the end of
+    /// the last one if it is not known yet. If the storage does not start,
+    /// its buffer is kept for the next try.
+    fn reserve_frame_counters(&self) {
+        if self.storage_op.get() != StorageOp::Idle {
+            return;
+        }
+        let buf = match self.storage_buf.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        let (op, (res, buf)) = match self.frame_counter_limit.get() {
+            None => (
+                StorageOp::Read,
+                self.storage
+                    .read(buf, self.storage_address, MLE_STORAGE_LEN),
+            ),
+            Some(limit) => {
+                let limit = limit.saturating_add(FRAME_COUNTER_RESERVE);
+                buf[..MLE_STORAGE_LEN].copy_from_slice(&limit.to_le_bytes());
+                (
+                    StorageOp::Write,
+                    self.storage
+                        .write(buf, self.storage_address, MLE_STORAGE_LEN),
+                )
+            }
+        };
+        if res == ReturnCode::SUCCESS {
+            self.storage_op.set(op);
+        }
+        buf.map(|buf| self.storage_buf.replace(buf));
+    }
+
+    /// Starts a Parent Request, which is sent once it has new challenges
+    /// and frame counters are reserved. The timer runs meanwhile, so that
+    /// the handshake moves on if they do not come.
+    fn send_parent_request(&self, reeds: bool) {
+        if !self.has_frame_counter() {
+            self.reserve_frame_counters();
+        }
+        self.parent.set(None);
+        self.state.set(State::ParentRequest { reeds });
+        self.request_pending.set(false);
+        self.awaiting_challenge.set(true);
+        self.rng.get();
+        self.set_timer(if reeds {
+            PARENT_REQUEST_REED_TIMEOUT_MS
+        } else {
+            PARENT_REQUEST_ROUTER_TIMEOUT_MS
+        });
+    }
+
+    /// Sends the pending Parent Request, if frame counters are reserved.
+    fn send_pending_parent_request(&self) {
+        let reeds = match self.state.get() {
+            State::ParentRequest { reeds } if self.request_pending.get() => reeds,
+            _ => return,
+        };
+        if !self.has_frame_counter() {
+            return;
+        }
+        self.request_pending.set(false);
+        let scan_mask = if reeds {
+            MulticastResponder::Router as u8 | MulticastResponder::EndDevice as u8
+        } else {
+            MulticastResponder::Router as u8
+        };
+        self.send_message(
+            ALL_ROUTERS_ADDR,
+            command::PARENT_REQUEST,
+            &[
+                Tlv::Mode(SED_MODE),
+                Tlv::Challenge(self.challenge.get()),
+                Tlv::ScanMask(scan_mask),
+                Tlv::Version(THREAD_VERSION),
+            ],
+        );
+    }
+
+    fn send_child_id_request(&self, parent: Parent) {
+        self.state.set(State::ChildIdRequest);
+        self.send_message(
+            parent.ll_addr,
+            command::CHILD_ID_REQUEST,
+            &[
+                Tlv::Response(parent.challenge),
+                Tlv::Challenge(self.child_id_challenge.get()),
+                Tlv::LinkLayerFrameCounter(0),
+                Tlv::MleFrameCounter(self.frame_counter.get()),
+                Tlv::Mode(SED_MODE),
+                Tlv::Timeout(CHILD_TIMEOUT_S),
+                Tlv::Version(THREAD_VERSION),
+                Tlv::TlvRequest(&REQUESTED_TLVS),
+            ],
+        );
+        self.set_timer(CHILD_ID_RESPONSE_TIMEOUT_MS);
+    }
+
+    /// Starts the handshake over, or gives up after `MAX_ATTACH_ATTEMPTS`.
+    fn retry_attach(&self) {
+        let attempts = self.attempts.get() + 1;
+        self.attempts.set(attempts);
+        if attempts < MAX_ATTACH_ATTEMPTS {
+            self.send_parent_request(false);
+        } else {
+            self.leave(ReturnCode::FAIL);
+        }
+    }
+
+    fn attached(&self, parent: Parent, rloc16: u16, mesh_local_prefix: Option<[u8; 8]>) {
+        self.interface.set_default_router(Some(Neighbor {
+            ip_addr: parent.ll_addr,
+            mac_addr: MacAddress::Long(parent.eui64),
+            lifetime: CHILD_TIMEOUT_S,
+        }));
+        if let Some(prefix) = mesh_local_prefix {
+            let mut rloc_addr = IPAddr::new();
+            rloc_addr.set_prefix(&prefix, 64);
+            rloc_addr.set_iid_from_mac(MacAddress::Short(rloc16));
+            if self.interface.add_addr(rloc_addr, None) == ReturnCode::SUCCESS {
+                self.rloc_addr.set(Some(rloc_addr));
+            }
+        }
+        self.parent.set(Some(parent));
+        self.state.set(State::Attached { rloc16 });
+        self.set_timer(CHILD_TIMEOUT_S * 1000);
+        self.client.map(|client| client.attached(rloc16));
+    }
+
+    fn leave(&self, result: ReturnCode) {
+        self.alarm.disable();
+        self.aes_ccm.disable();
+        if self.awaiting_challenge.replace(false) {
+            self.rng.cancel();
+        }
+        self.request_pending.set(false);
+        if let Some(rloc_addr) = self.rloc_addr.take() {
+            self.interface.remove_addr(rloc_addr);
+        }
+        if let State::Attached { .. } = self.state.get() {
+            let parent = self.parent.get().map(|parent| parent.ll_addr);
+            if self.interface.default_router().map(|router| router.ip_addr) == parent {
+                self.interface.set_default_router(None);
+            }
+        }
+        self.parent.set(None);
+        self.state.set(State::Detached);
+        self.client.map(|client| client.detached(result));
+    }
+
+    /// Returns whether the next frame counter is reserved.
+    fn has_frame_counter(&self) -> bool {
+        self.frame_counter_limit
+            .get()
+            .map_or(false, |limit| self.frame_counter.get() < limit)
+    }
+
+    /// Fills in the nonce of a message from `src_eui64` with
+    /// `frame_counter`.
+    fn set_nonce(&self, src_eui64: [u8; 8], frame_counter: u32) -> ReturnCode {
+        let mut nonce = [0; CCM_NONCE_LENGTH];
+        nonce[..8].copy_from_slice(&src_eui64);
+        nonce[8..12].copy_from_slice(&frame_counter.to_be_bytes());
+        nonce[12] = SECURITY_LEVEL;
+        self.aes_ccm.set_nonce(&nonce)
+    }
+
+    /// Encodes a message and starts securing it, after which it is sent to
+    /// `dst` from the link-local address.
+    fn send_message(&self, dst: IPAddr, command: u8, tlvs: &[Tlv]) -> ReturnCode {
+        if self.sending.get() || self.crypt_op.get() != CryptOp::Idle || !self.has_frame_counter() {
+            return ReturnCode::EBUSY;
+        }
+        let buf = match self.tx_buf.take() {
+            Some(buf) => buf,
+            None => return ReturnCode::EBUSY,
+        };
+        let frame_counter = self.frame_counter.get();
+        buf[..16].copy_from_slice(&self.link_local_addr().0);
+        buf[16..AUX_HDR_OFF].copy_from_slice(&dst.0);
+        encode_aux_header(
+            &mut buf[AUX_HDR_OFF..AUTH_LEN],
+            frame_counter,
+            self.key_sequence.get(),
+        );
+        let m_len = match encode_message(&mut buf[AUTH_LEN..], command, tlvs) {
+            Some(len) if AUTH_LEN + len + MIC_LEN <= buf.len() => len,
+            _ => {
+                self.tx_buf.replace(buf);
+                return ReturnCode::ESIZE;
+            }
+        };
+
+        let res = self.aes_ccm.set_key(&self.key.get());
+        if res != ReturnCode::SUCCESS {
+            self.tx_buf.replace(buf);
+            return res;
+        }
+        let res = self.set_nonce(self.eui64, frame_counter);
+        if res != ReturnCode::SUCCESS {
+            self.tx_buf.replace(buf);
+            return res;
+        }
+        let (res, buf) = self
+            .aes_ccm
+            .crypt(buf, 0, AUTH_LEN, m_len, MIC_LEN, true, true);
+        if let Some(buf) = buf {
+            self.tx_buf.replace(buf);
+        }
+        if res == ReturnCode::SUCCESS {
+            self.frame_counter.set(frame_counter + 1);
+            // The next block is reserved ahead, so that the frame counters
+            // do not run out
+            let limit = self.frame_counter_limit.get().unwrap_or(0);
+            if limit - frame_counter <= FRAME_COUNTER_RESERVE / 2 {
+                self.reserve_frame_counters();
+            }
+            self.crypt_op.set(CryptOp::Encrypt);
+            self.tx_dst.set(dst);
+            self.tx_len.set(m_len);
+            self.sending.set(true);
+        }
+        res
+    }
+
+    /// Sends a message secured in `buf`.
+    fn send_secured(&self, buf: &mut [u8]) {
+        // The security suite precedes the auxiliary security header, in place
+        // of the end of the destination address
+        let start = AUX_HDR_OFF - 1;
+        buf[start] = SECURITY_SUITE;
+        let payload = &buf[start..AUTH_LEN + self.tx_len.get() + MIC_LEN];
+
+        let mut udp_header = UDPHeader::new();
+        udp_header.set_src_port(MLE_PORT);
+        udp_header.set_dst_port(MLE_PORT);
+        udp_header.set_len((payload.len() + udp_header.get_hdr_size()) as u16);
+        let res = self.ip_sender.send_from(
+            self.link_local_addr(),
+            self.tx_dst.get(),
+            TransportHeader::UDP(udp_header),
+            payload,
+        );
+        if res != ReturnCode::SUCCESS {
+            self.sending.set(false);
+        }
+    }
+
+    /// Checks the security of a received message and starts decrypting it.
+    fn receive_secured(&self, src_addr: IPAddr, dst_addr: IPAddr, payload: &[u8]) {
+        if payload.len() < 1 + AUX_HDR_LEN + MIC_LEN
+            || payload[0] != SECURITY_SUITE
+            || payload[1] != SECURITY_CONTROL
+            || self.crypt_op.get() != CryptOp::Idle
+        {
+            return;
+        }
+        let aux_header = &payload[1..=AUX_HDR_LEN];
+        let frame_counter =
+            u32::from_le_bytes([aux_header[1], aux_header[2], aux_header[3], aux_header[4]]);
+        let mut expected_aux_header = [0; AUX_HDR_LEN];
+        encode_aux_header(
+            &mut expected_aux_header,
+            frame_counter,
+            self.key_sequence.get(),
+        );
+        if aux_header != expected_aux_header {
+            return;
+        }
+        let src_eui64 = match src_addr.mac_from_iid() {
+            MacAddress::Long(eui64) => eui64,
+            MacAddress::Short(_) => return,
+        };
+
+        let secured = &payload[1 + AUX_HDR_LEN..];
+        let m_len = secured.len() - MIC_LEN;
+        let buf = match self.rx_buf.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        if AUTH_LEN + secured.len() > buf.len() {
+            self.rx_buf.replace(buf);
+            return;
+        }
+        buf[..16].copy_from_slice(&src_addr.0);
+        buf[16..AUX_HDR_OFF].copy_from_slice(&dst_addr.0);
+        buf[AUX_HDR_OFF..AUTH_LEN].copy_from_slice(aux_header);
+        buf[AUTH_LEN..AUTH_LEN + secured.len()].copy_from_slice(secured);
+
+        if self.aes_ccm.set_key(&self.key.get()) != ReturnCode::SUCCESS
+            || self.set_nonce(src_eui64, frame_counter) != ReturnCode::SUCCESS
+        {
+            self.rx_buf.replace(buf);
+            return;
+        }
+        let (res, buf) = self
+            .aes_ccm
+            .crypt(buf, 0, AUTH_LEN, m_len, MIC_LEN, true, false);
+        if let Some(buf) = buf {
+            self.rx_buf.replace(buf);
+        }
+        if res == ReturnCode::SUCCESS {
+            self.crypt_op.set(CryptOp::Decrypt);
+            self.rx_src.set(src_addr);
+            self.rx_frame_counter.set(frame_counter);
+            self.rx_len.set(m_len);
+        }
+    }
+
+    /// Processes a decrypted message: its command followed by its TLVs.
+    fn receive_message(&self, src_addr: IPAddr, frame_counter: u32, message: &[u8]) {
+        if message.is_empty() {
+            return;
+        }
+        let tlvs = &message[1..];
+        match (message[0], self.state.get()) {
+            (command::PARENT_RESPONSE, State::ParentRequest { .. }) => {
+                self.receive_parent_response(src_addr, frame_counter, tlvs)
+            }
+            (command::CHILD_ID_RESPONSE, State::ChildIdRequest) => {
+                self.receive_child_id_response(src_addr, frame_counter, tlvs)
+            }
+            _ => {}
+        }
+    }
+
+    fn receive_parent_response(&self, src_addr: IPAddr, frame_counter: u32, tlvs: &[u8]) {
+        let mut rloc16 = None;
+        let mut response = None;
+        let mut challenge = None;
+        let mut link_margin = None;
+        let mut connectivity = None;
+        for tlv in decode_tlvs(tlvs) {
+            match tlv {
+                Tlv::SourceAddress(addr) => rloc16 = Some(addr),
+                Tlv::Response(value) => response = Some(value),
+                Tlv::Challenge(value) => challenge = Some(value),
+                Tlv::LinkMargin(margin) => link_margin = Some(margin),
+                Tlv::Connectivity {
+                    parent_priority,
+                    link_quality_3,
+                    link_quality_2,
+                    link_quality_1,
+                    ..
+                } => {
+                    // The priority is a signed 2 bit number in the upper bits
+                    let priority = parent_priority as i8 >> 6;
+                    connectivity = Some((priority, link_quality_3, link_quality_2, link_quality_1));
+                }
+                _ => {}
+            }
+        }
+        if response != Some(self.challenge.get()) {
+            return;
+        }
+        let (rloc16, challenge, link_margin, connectivity) =
+            match (rloc16, challenge, link_margin, connectivity) {
+                (Some(a), Some(b), Some(c), Some(d)) => (a, b, c, d),
+                _ => return,
+            };
+        let eui64 = match src_addr.mac_from_iid() {
+            MacAddress::Long(eui64) => eui64,
+            MacAddress::Short(_) => return,
+        };
+        let (priority, lq3, lq2, lq1) = connectivity;
+        let parent = Parent {
+            ll_addr: src_addr,
+            eui64,
+            rloc16,
+            challenge,
+            frame_counter,
+            rank: (link_quality(link_margin), priority, lq3, lq2, lq1),
+        };
+        if self
+            .parent
+            .get()
+            .map_or(true, |current| parent.rank > current.rank)
+        {
+            self.parent.set(Some(parent));
+        }
+    }
+
+    fn receive_child_id_response(&self, src_addr: IPAddr, frame_counter: u32, tlvs: &[u8]) {
+        let parent = match self.parent.get() {
+            Some(parent) => parent,
+            None => return,
+        };
+        // Only the selected parent answers, and its messages are not
+        // replayed
+        if src_addr != parent.ll_addr || frame_counter <= parent.frame_counter {
+            return;
+        }
+        let mut rloc16 = None;
+        let mut response = None;
+        let mut source_address = None;
+        let mut mesh_local_prefix = None;
+        for tlv in decode_tlvs(tlvs) {
+            match tlv {
+                Tlv::Address16(addr) => rloc16 = Some(addr),
+                Tlv::Response(value) => response = Some(value),
+                Tlv::SourceAddress(addr) => source_address = Some(addr),
+                Tlv::ActiveOperationalDataset(dataset) => {
+                    for tlv in split_tlvs(dataset) {
+                        if let Some((_, NetworkManagementTlv::NetworkMeshLocalPrefix(prefix))) =
+                            NetworkManagementTlv::decode(tlv).done()
+                        {
+                            mesh_local_prefix = Some(prefix);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if response != Some(self.child_id_challenge.get())
+            || source_address.map_or(false, |addr| addr != parent.rloc16)
+        {
+            return;
+        }
+        if let Some(rloc16) = rloc16 {
+            self.attached(
+                Parent {
+                    frame_counter,
+                    ..parent
+                },
+                rloc16,
+                mesh_local_prefix,
+            );
+        }
+    }
+}
+
+/// Encodes the auxiliary security header of a message secured with the MLE
+/// key of `key_sequence`.
+fn encode_aux_header(buf: &mut [u8], frame_counter: u32, key_sequence: u32) {
+    buf[0] = SECURITY_CONTROL;
+    buf[1..5].copy_from_slice(&frame_counter.to_le_bytes());
+    buf[5..9].copy_from_slice(&key_sequence.to_be_bytes());
+    buf[9] = (key_sequence & 0x7f) as u8 + 1;
+}
+
+/// Encodes the command and the TLVs of a message, and returns its length.
+fn encode_message(buf: &mut [u8], command: u8, tlvs: &[Tlv]) -> Option<usize> {
+    *buf.first_mut()? = command;
+    let mut off = 1;
+    for tlv in tlvs {
+        let (len, ()) = tlv.encode(&mut buf[off..]).done()?;
+        off += len;
+    }
+    Some(off)
+}
+
+/// Returns the link quality, from 0 to 3, of a link with `link_margin` dB.
+fn link_quality(link_margin: u8) -> u8 {
+    match link_margin {
+        m if m > 20 => 3,
+        m if m > 10 => 2,
+        m if m > 2 => 1,
+        _ => 0,
+    }
+}
+
+/// Splits a sequence of TLVs into the TLVs, up to the first truncated one.
+fn split_tlvs(buf: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut rest = buf;
+    core::iter::from_fn(move || {
+        if rest.len() < 2 || rest.len() < 2 + rest[1] as usize {
+            return None;
+        }
+        let (tlv, next) = rest.split_at(2 + rest[1] as usize);
+        rest = next;
+        Some(tlv)
+    })
+}
+
+/// Decodes a sequence of MLE TLVs, skipping those that are not supported.
+fn decode_tlvs(buf: &[u8]) -> impl Iterator<Item = Tlv> {
+    split_tlvs(buf).filter_map(|tlv| Tlv::decode(tlv).done().map(|(_, tlv)| tlv))
+}
+
+impl<A: time::Alarm<'a>, S: IP6Sender<'a>, C: AES128CCM<'a>> CCMClient for Mle<'a, A, S, C> {
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        match self.crypt_op.replace(CryptOp::Idle) {
+            CryptOp::Encrypt => {
+                if res == ReturnCode::SUCCESS {
+                    self.send_secured(buf);
+                } else {
+                    self.sending.set(false);
+                }
+                self.tx_buf.replace(buf);
+            }
+            CryptOp::Decrypt => {
+                if res == ReturnCode::SUCCESS && tag_is_valid {
+                    let len = self.rx_len.get();
+                    self.receive_message(
+                        self.rx_src.get(),
+                        self.rx_frame_counter.get(),
+                        &buf[AUTH_LEN..AUTH_LEN + len],
+                    );
+                }
+                self.rx_buf.replace(buf);
+            }
+            CryptOp::Idle => {}
+        }
+    }
+}
+
+impl<A: time::Alarm<'a>, S: IP6Sender<'a>, C: AES128CCM<'a>> rng::Client for Mle<'a, A, S, C> {
+    fn randomness_available(
+        &self,
+        randomness: &mut dyn Iterator<Item = u32>,
+        _error: ReturnCode,
+    ) -> rng::Continue {
+        if !self.awaiting_challenge.get() {
+            return rng::Continue::Done;
+        }
+        // The challenges of the Parent Request and of the Child ID Request
+        // take two numbers each
+        let mut challenges = [0; 16];
+        for chunk in challenges.chunks_mut(4) {
+            match randomness.next() {
+                Some(number) => chunk.copy_from_slice(&number.to_be_bytes()),
+                None => return rng::Continue::More,
+            }
+        }
+        let mut challenge = [0; 8];
+        challenge.copy_from_slice(&challenges[..8]);
+        self.challenge.set(challenge);
+        challenge.copy_from_slice(&challenges[8..]);
+        self.child_id_challenge.set(challenge);
+        self.awaiting_challenge.set(false);
+        self.request_pending.set(true);
+        self.send_pending_parent_request();
+        rng::Continue::Done
+    }
+}
+
+impl<A: time::Alarm<'a>, S: IP6Sender<'a>, C: AES128CCM<'a>> NonvolatileStorageClient<'static>
+    for Mle<'a, A, S, C>
+{
+    fn read_done(&self, buffer: &'static mut [u8], _length: usize) {
+        let mut limit = [0; MLE_STORAGE_LEN];
+        limit.copy_from_slice(&buffer[..MLE_STORAGE_LEN]);
+        let limit = u32::from_le_bytes(limit);
+        self.storage_buf.replace(buffer);
+        self.storage_op.set(StorageOp::Idle);
+        // The frame counters up to the end of the last block reserved may
+        // have been used before the reboot, so the next block follows it
+        self.frame_counter.set(self.frame_counter.get().max(limit));
+        self.frame_counter_limit.set(Some(self.frame_counter.get()));
+        self.reserve_frame_counters();
+    }
+
+    fn write_done(&self, buffer: &'static mut [u8], _length: usize) {
+        let mut limit = [0; MLE_STORAGE_LEN];
+        limit.copy_from_slice(&buffer[..MLE_STORAGE_LEN]);
+        self.frame_counter_limit
+            .set(Some(u32::from_le_bytes(limit)));
+        self.storage_buf.replace(buffer);
+        self.storage_op.set(StorageOp::Idle);
+        self.send_pending_parent_request();
+    }
+}
+
+impl<A: time::Alarm<'a>, S: IP6Sender<'a>, C: AES128CCM<'a>> UDPRecvClient for Mle<'a, A, S, C> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) -> bool {
+        // Messages are only processed while attaching, and come from the
+        // MLE port of a neighbor
+        let attaching = match self.state.get() {
+            State::ParentRequest { .. } | State::ChildIdRequest => true,
+            _ => false,
+        };
+        if attaching && src_port == MLE_PORT && src_addr.is_unicast_link_local() {
+            self.receive_secured(src_addr, dst_addr, payload);
+        }
+        // The port is bound even if the message is dropped
+        true
+    }
+}
+
+impl<A: time::Alarm<'a>, S: IP6Sender<'a>, C: AES128CCM<'a>> IP6SendClient for Mle<'a, A, S, C> {
+    fn send_done(&self, _result: ReturnCode) {
+        self.sending.set(false);
+    }
+}
+
+impl<A: time::Alarm<'a>, S: IP6Sender<'a>, C: AES128CCM<'a>> time::AlarmClient
+    for Mle<'a, A, S, C>
+{
+    fn fired(&self) {
+        match self.state.get() {
+            State::ParentRequest { reeds } => match self.parent.get() {
+                Some(parent) => self.send_child_id_request(parent),
+                None if !reeds => self.send_parent_request(true),
+                None => self.retry_attach(),
+            },
+            State::ChildIdRequest => self.retry_attach(),
+            // The parent removes the device once the child timeout elapses,
+            // as Child Update is not implemented
+            State::Attached { .. } => self.leave(ReturnCode::FAIL),
+            State::Detached => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use crate::net::mock::{buf, MockAes, MockAlarm, MockRng, MockSender, MockStorage};
+    use kernel::hil::time::{Alarm, AlarmClient};
+    use std::vec::Vec;
+
+    const EUI64: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+    // The challenges drawn from the numbers 1 to 4
+    const PARENT_REQUEST_CHALLENGE: [u8; 8] = [0, 0, 0, 1, 0, 0, 0, 2];
+    const CHILD_ID_CHALLENGE: [u8; 8] = [0, 0, 0, 3, 0, 0, 0, 4];
+
+    #[derive(Default)]
+    struct Client {
+        rloc16: Cell<Option<u16>>,
+        detached: Cell<Option<ReturnCode>>,
+    }
+
+    impl MleClient for Client {
+        fn attached(&self, rloc16: u16) {
+            self.rloc16.set(Some(rloc16));
+        }
+
+        fn detached(&self, result: ReturnCode) {
+            self.detached.set(Some(result));
+        }
+    }
+
+    struct Mocks {
+        sender: MockSender,
+        interface: IP6Interface,
+        alarm: MockAlarm,
+        aes: MockAes,
+        rng: MockRng,
+        storage: MockStorage,
+        client: Client,
+    }
+
+    type TestMle<'a> = Mle<'a, MockAlarm, MockSender, MockAes>;
+
+    impl Mocks {
+        fn new() -> Mocks {
+            Mocks {
+                sender: MockSender::new(),
+                interface: IP6Interface::new(),
+                alarm: MockAlarm::new(),
+                aes: MockAes::new(),
+                rng: MockRng::new(),
+                storage: MockStorage::new(MLE_STORAGE_LEN),
+                client: Client::default(),
+            }
+        }
+
+        fn mle(&self) -> TestMle {
+            let mle = Mle::new(
+                &self.sender,
+                &self.interface,
+                &self.alarm,
+                &self.aes,
+                &self.rng,
+                &self.storage,
+                0,
+                EUI64,
+                buf(MLE_BUF_LEN),
+                buf(MLE_BUF_LEN),
+                buf(MLE_STORAGE_LEN),
+            );
+            mle.set_client(&self.client);
+            mle
+        }
+
+        /// Hands the buffer of the storage operation in progress back.
+        fn finish_storage(&self, mle: &TestMle) {
+            match self.storage.finish() {
+                Some((buf, true)) => mle.write_done(buf, MLE_STORAGE_LEN),
+                Some((buf, false)) => mle.read_done(buf, MLE_STORAGE_LEN),
+                None => panic!("no storage operation"),
+            }
+        }
+
+        fn finish_crypt(&self, mle: &TestMle) {
+            let buf = self.aes.buf.take().expect("no AES operation");
+            mle.crypt_done(buf, ReturnCode::SUCCESS, true);
+        }
+
+        /// Secures and sends the message MLE started, and returns its
+        /// destination, command and TLVs.
+        fn sent(&self, mle: &TestMle) -> (IPAddr, u8, Vec<u8>) {
+            assert!(self.aes.encrypting.get());
+            self.finish_crypt(mle);
+            let mut sent = self.sender.take();
+            assert_eq!(sent.len(), 1);
+            let sent = sent.remove(0);
+            IP6SendClient::send_done(mle, ReturnCode::SUCCESS);
+            let message = &sent.payload[1 + AUX_HDR_LEN..sent.payload.len() - MIC_LEN];
+            (sent.dst, message[0], message[1..].to_vec())
+        }
+
+        /// Delivers a message from `src` to MLE.
+        fn receive(
+            &self,
+            mle: &TestMle,
+            src: IPAddr,
+            frame_counter: u32,
+            command: u8,
+            tlvs: &[Tlv],
+        ) {
+            let mut payload = [0; MLE_BUF_LEN];
+            payload[0] = SECURITY_SUITE;
+            encode_aux_header(&mut payload[1..=AUX_HDR_LEN], frame_counter, 0);
+            let len = encode_message(&mut payload[1 + AUX_HDR_LEN..], command, tlvs).unwrap();
+            let payload = &payload[..1 + AUX_HDR_LEN + len + MIC_LEN];
+            UDPRecvClient::receive(mle, src, mle.link_local_addr(), MLE_PORT, MLE_PORT, payload);
+            if self.aes.buf.is_some() {
+                self.finish_crypt(mle);
+            }
+        }
+
+        /// Starts attaching with the storage and the numbers given at once,
+        /// and returns the Parent Request.
+        fn attach(&self, mle: &TestMle) -> (IPAddr, u8, Vec<u8>) {
+            assert_eq!(mle.attach(), ReturnCode::SUCCESS);
+            // The end of the reserved frame counters is read, and then the
+            // next block is reserved
+            self.finish_storage(mle);
+            self.finish_storage(mle);
+            self.give_numbers(mle);
+            self.sent(mle)
+        }
+
+        fn give_numbers(&self, mle: &TestMle) {
+            let numbers = [1, 2, 3, 4];
+            rng::Client::randomness_available(
+                mle,
+                &mut numbers.iter().cloned(),
+                ReturnCode::SUCCESS,
+            );
+        }
+
+        fn parent_response(&self, mle: &TestMle, parent: u8, link_margin: u8, response: [u8; 8]) {
+            self.receive(
+                mle,
+                parent_addr(parent),
+                1,
+                command::PARENT_RESPONSE,
+                &[
+                    Tlv::SourceAddress(parent_rloc16(parent)),
+                    Tlv::Response(response),
+                    Tlv::Challenge([parent; 8]),
+                    Tlv::LinkMargin(link_margin),
+                    Tlv::Connectivity {
+                        parent_priority: 0,
+                        link_quality_3: 1,
+                        link_quality_2: 0,
+                        link_quality_1: 0,
+                        leader_cost: 0,
+                        id_sequence: 0,
+                        active_routers: 1,
+                        sed_buffer_size: None,
+                        sed_datagram_count: None,
+                    },
+                ],
+            );
+        }
+
+        /// Answers the Parent Request from `parent`, which is selected.
+        fn select_parent(&self, mle: &TestMle, parent: u8) {
+            self.parent_response(mle, parent, 30, PARENT_REQUEST_CHALLENGE);
+            assert!(self.alarm.advance(PARENT_REQUEST_ROUTER_TIMEOUT_MS));
+            mle.fired();
+            self.sent(mle);
+        }
+    }
+
+    fn parent_addr(parent: u8) -> IPAddr {
+        IPAddr::generate_from_mac(MacAddress::Long([parent; 8]))
+    }
+
+    fn parent_rloc16(parent: u8) -> u16 {
+        (parent as u16) << 10
+    }
+
+    fn child_id_response_tlvs(parent: u8, response: [u8; 8]) -> [Tlv<'static>; 3] {
+        [
+            Tlv::SourceAddress(parent_rloc16(parent)),
+            Tlv::Address16(parent_rloc16(parent) | 1),
+            Tlv::Response(response),
+        ]
+    }
+
+    #[test]
+    fn attaches_to_best_parent() {
+        let mocks = Mocks::new();
+        let mle = mocks.mle();
+        let (dst, command, tlvs) = mocks.attach(&mle);
+        assert_eq!(dst, ALL_ROUTERS_ADDR);
+        assert_eq!(command, command::PARENT_REQUEST);
+        assert!(decode_tlvs(&tlvs).any(|tlv| match tlv {
+            Tlv::Challenge(challenge) => challenge == PARENT_REQUEST_CHALLENGE,
+            _ => false,
+        }));
+
+        mocks.parent_response(&mle, 1, 5, PARENT_REQUEST_CHALLENGE);
+        mocks.parent_response(&mle, 2, 25, PARENT_REQUEST_CHALLENGE);
+        // The best link, but it does not answer the challenge
+        mocks.parent_response(&mle, 3, 30, [3; 8]);
+        mocks.parent_response(&mle, 4, 15, PARENT_REQUEST_CHALLENGE);
+        assert!(mocks.alarm.advance(PARENT_REQUEST_ROUTER_TIMEOUT_MS));
+        mle.fired();
+
+        let (dst, command, tlvs) = mocks.sent(&mle);
+        assert_eq!(mle.get_state(), State::ChildIdRequest);
+        assert_eq!(dst, parent_addr(2));
+        assert_eq!(command, command::CHILD_ID_REQUEST);
+        let mut response = None;
+        let mut challenge = None;
+        for tlv in decode_tlvs(&tlvs) {
+            match tlv {
+                Tlv::Response(value) => response = Some(value),
+                Tlv::Challenge(value) => challenge = Some(value),
+                _ => {}
+            }
+        }
+        assert_eq!(response, Some([2; 8]));
+        assert_eq!(challenge, Some(CHILD_ID_CHALLENGE));
+
+        let tlvs = child_id_response_tlvs(2, CHILD_ID_CHALLENGE);
+        mocks.receive(&mle, parent_addr(2), 2, command::CHILD_ID_RESPONSE, &tlvs);
+        assert_eq!(mle.get_state(), State::Attached { rloc16: 0x0801 });
+        assert_eq!(mle.get_rloc16(), Some(0x0801));
+        assert_eq!(mocks.client.rloc16.get(), Some(0x0801));
+        assert_eq!(
+            mocks
+                .interface
+                .default_router()
+                .map(|router| router.ip_addr),
+            Some(parent_addr(2))
+        );
+
+        assert_eq!(mle.detach(), ReturnCode::SUCCESS);
+        assert_eq!(mocks.client.detached.get(), Some(ReturnCode::SUCCESS));
+        assert!(mocks.interface.default_router().is_none());
+    }
+
+    #[test]
+    fn child_id_response_must_answer_challenge() {
+        let mocks = Mocks::new();
+        let mle = mocks.mle();
+        mocks.attach(&mle);
+        mocks.select_parent(&mle, 1);
+
+        // The challenge of the Parent Request is not the one asked
+        let tlvs = child_id_response_tlvs(1, PARENT_REQUEST_CHALLENGE);
+        mocks.receive(&mle, parent_addr(1), 2, command::CHILD_ID_RESPONSE, &tlvs);
+        assert_eq!(mle.get_state(), State::ChildIdRequest);
+        let tlvs = child_id_response_tlvs(1, CHILD_ID_CHALLENGE);
+        mocks.receive(
+            &mle,
+            parent_addr(1),
+            3,
+            command::CHILD_ID_RESPONSE,
+            &tlvs[..2],
+        );
+        assert_eq!(mle.get_state(), State::ChildIdRequest);
+        // Nor is an answer from another router
+        mocks.receive(&mle, parent_addr(2), 3, command::CHILD_ID_RESPONSE, &tlvs);
+        assert_eq!(mle.get_state(), State::ChildIdRequest);
+        assert_eq!(mocks.client.rloc16.get(), None);
+
+        mocks.receive(&mle, parent_addr(1), 3, command::CHILD_ID_RESPONSE, &tlvs);
+        assert_eq!(mle.get_state(), State::Attached { rloc16: 0x0401 });
+    }
+
+    #[test]
+    fn asks_reeds_and_gives_up() {
+        let mocks = Mocks::new();
+        let mle = mocks.mle();
+        mocks.attach(&mle);
+        for attempt in 0..MAX_ATTACH_ATTEMPTS {
+            assert_eq!(mle.get_state(), State::ParentRequest { reeds: false });
+            assert!(mocks.alarm.advance(PARENT_REQUEST_ROUTER_TIMEOUT_MS));
+            mle.fired();
+            assert_eq!(mle.get_state(), State::ParentRequest { reeds: true });
+            // Each Parent Request has new challenges
+            assert_eq!(mocks.rng.requests.get(), 2 * attempt as usize + 2);
+            assert_eq!(mocks.client.detached.get(), None);
+            assert!(mocks.alarm.advance(PARENT_REQUEST_REED_TIMEOUT_MS));
+            mle.fired();
+        }
+        assert_eq!(mle.get_state(), State::Detached);
+        assert_eq!(mocks.client.detached.get(), Some(ReturnCode::FAIL));
+        assert!(!mocks.alarm.is_enabled());
+        assert!(!mocks.aes.enabled.get());
+    }
+
+    #[test]
+    fn child_id_request_times_out() {
+        let mocks = Mocks::new();
+        let mle = mocks.mle();
+        mocks.attach(&mle);
+        mocks.select_parent(&mle, 1);
+        assert!(mocks.alarm.advance(CHILD_ID_RESPONSE_TIMEOUT_MS));
+        mle.fired();
+        assert_eq!(mle.get_state(), State::ParentRequest { reeds: false });
+        assert_eq!(mle.detach(), ReturnCode::SUCCESS);
+        assert_eq!(mocks.client.detached.get(), Some(ReturnCode::ECANCEL));
+        assert_eq!(mle.detach(), ReturnCode::EALREADY);
+    }
+
+    #[test]
+    fn retries_frame_counter_reservation() {
+        let mocks = Mocks::new();
+        let mle = mocks.mle();
+        // The frame counters up to 5000 were reserved before a reboot
+        mocks.storage.data.borrow_mut()[..4].copy_from_slice(&5000u32.to_le_bytes());
+        mocks.storage.result.set(ReturnCode::FAIL);
+        mle.attach();
+        assert!(mocks.storage.finish().is_none());
+        // The request waits for frame counters
+        mocks.give_numbers(&mle);
+        assert!(mocks.aes.buf.is_none());
+
+        mocks.storage.result.set(ReturnCode::SUCCESS);
+        assert!(mocks.alarm.advance(PARENT_REQUEST_ROUTER_TIMEOUT_MS));
+        mle.fired();
+        mocks.finish_storage(&mle);
+        mocks.finish_storage(&mle);
+        assert_eq!(mocks.storage.data.borrow()[..4], 6024u32.to_le_bytes());
+        mocks.give_numbers(&mle);
+        mocks.finish_crypt(&mle);
+        let sent = mocks.sender.take();
+        assert_eq!(sent.len(), 1);
+        // The frame counter of the auxiliary security header
+        assert_eq!(sent[0].payload[2..6], 5000u32.to_le_bytes());
+    }
+}
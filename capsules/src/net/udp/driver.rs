@@ -2,25 +2,36 @@
 //!
 //! Implements a userspace interface for sending and receiving UDP messages.
 //! Processes use this driver to send UDP packets from a common interface
-//! and bind to UDP ports for receiving packets.
-//! Also exposes a list of interface addresses to the application (currently
-//! hard-coded).
+//! and bind sockets to UDP ports for receiving packets, on the addresses of
+//! the interface or on multicast groups, which the interface joins while a
+//! socket is bound to them. The ports of in-kernel UDP users are reserved in
+//! the port table of the `UDPReceiver`, and processes cannot bind to them.
+//! Also exposes the list of addresses of the `IP6Interface` to the
+//! application, which changes as addresses are configured and expire, and
+//! the reassembly counters of the 6LoWPAN layer below.
 
 use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_interface::{IP6Interface, MAX_GROUPS};
+use crate::net::sixlowpan::sixlowpan_state::SixlowpanState;
 use crate::net::stream::encode_u16;
 use crate::net::stream::encode_u8;
 use crate::net::stream::SResult;
+use crate::net::udp::udp_port_table::UDPPortUser;
 use crate::net::udp::udp_recv::{UDPReceiver, UDPRecvClient};
 use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
 use core::cell::Cell;
 use core::{cmp, mem};
-use kernel::{debug, AppId, AppSlice, Callback, Driver, Grant, ReturnCode, Shared};
+use kernel::common::cells::OptionalCell;
+use kernel::{debug, AppId, AppSlice, Callback, Driver, Grant, ReadOnly, ReturnCode, Shared};
 
 /// Syscall number
 use crate::driver;
 pub const DRIVER_NUM: usize = driver::NUM::Udp as usize;
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+/// The number of sockets each process can bind.
+pub const MAX_BINDINGS: usize = 4;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct UDPEndpoint {
     addr: IPAddr,
     port: u16,
@@ -60,11 +71,11 @@ pub struct App {
     rx_callback: Option<Callback>,
     tx_callback: Option<Callback>,
     app_read: Option<AppSlice<Shared, u8>>,
-    app_write: Option<AppSlice<Shared, u8>>,
+    app_write: Option<AppSlice<ReadOnly, u8>>,
     app_cfg: Option<AppSlice<Shared, u8>>,
     app_rx_cfg: Option<AppSlice<Shared, u8>>,
     pending_tx: Option<[UDPEndpoint; 2]>,
-    bound_port: Option<UDPEndpoint>,
+    bindings: [Option<UDPEndpoint>; MAX_BINDINGS],
 }
 
 #[allow(dead_code)]
@@ -80,11 +91,17 @@ pub struct UDPDriver<'a> {
     /// ID of app whose transmission request is being processed.
     current_app: Cell<Option<AppId>>,
 
-    /// List of IP Addresses of the interfaces on the device
-    interface_list: &'static [IPAddr],
+    /// Interface whose addresses apps can bind to
+    interface: &'a IP6Interface,
 
     /// Maximum length payload that an app can transmit via this driver
     max_tx_pyld_len: usize,
+
+    /// 6LoWPAN layer whose reassembly counters apps can read
+    sixlowpan_state: OptionalCell<&'a dyn SixlowpanState<'a>>,
+
+    /// Multicast groups the interface joined for the sockets of apps
+    groups: [Cell<Option<IPAddr>>; MAX_GROUPS],
 }
 
 impl<'a> UDPDriver<'a> {
@@ -92,7 +109,7 @@ impl<'a> UDPDriver<'a> {
         sender: &'a dyn UDPSender<'a>,
         receiver: &'a UDPReceiver<'a>,
         grant: Grant<App>,
-        interface_list: &'static [IPAddr],
+        interface: &'a IP6Interface,
         max_tx_pyld_len: usize,
     ) -> UDPDriver<'a> {
         UDPDriver {
@@ -100,11 +117,17 @@ impl<'a> UDPDriver<'a> {
             receiver: receiver,
             apps: grant,
             current_app: Cell::new(None),
-            interface_list: interface_list,
+            interface: interface,
             max_tx_pyld_len: max_tx_pyld_len,
+            sixlowpan_state: OptionalCell::empty(),
+            groups: Default::default(),
         }
     }
 
+    pub fn set_sixlowpan_state(&self, sixlowpan_state: &'a dyn SixlowpanState<'a>) {
+        self.sixlowpan_state.set(sixlowpan_state);
+    }
+
     /// Utility function to perform an action on an app in a system call.
     #[inline]
     fn do_with_app<F>(&self, appid: AppId, closure: F) -> ReturnCode
@@ -190,6 +213,101 @@ impl<'a> UDPDriver<'a> {
             .unwrap_or_else(|err| err.into())
     }
 
+    /// Returns whether a socket of an app is bound to an endpoint for which
+    /// `matches` holds.
+    fn is_bound_to<F>(&self, matches: F) -> bool
+    where
+        F: Fn(&UDPEndpoint) -> bool,
+    {
+        let mut bound = false;
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                if app
+                    .bindings
+                    .iter()
+                    .any(|b| b.as_ref().map_or(false, &matches))
+                {
+                    bound = true;
+                }
+            });
+        }
+        bound
+    }
+
+    /// Joins the multicast group `addr` for the sockets of apps, unless it
+    /// was joined already.
+    fn join_group(&self, addr: IPAddr) -> ReturnCode {
+        if self.groups.iter().any(|group| group.get() == Some(addr)) {
+            return ReturnCode::SUCCESS;
+        }
+        match self.add_group(addr) {
+            // The groups of the apps that exited may take up the room
+            ReturnCode::ENOMEM => {
+                self.leave_unused_groups();
+                self.add_group(addr)
+            }
+            result => result,
+        }
+    }
+
+    fn add_group(&self, addr: IPAddr) -> ReturnCode {
+        let slot = match self.groups.iter().find(|group| group.get().is_none()) {
+            Some(slot) => slot,
+            None => return ReturnCode::ENOMEM,
+        };
+        let result = self.interface.join_group(addr);
+        if result == ReturnCode::SUCCESS {
+            slot.set(Some(addr));
+        }
+        result
+    }
+
+    /// Leaves the multicast groups no socket is bound to anymore, as their
+    /// sockets were closed or their apps exited. The driver is not told when
+    /// an app exits, so this is also done when no room is left for another
+    /// group, and when a packet sent to a group finds no socket.
+    fn leave_unused_groups(&self) {
+        for group in self.groups.iter() {
+            if let Some(addr) = group.get() {
+                if !self.is_bound_to(|b| b.addr == addr) {
+                    self.interface.leave_group(addr);
+                    group.set(None);
+                }
+            }
+        }
+    }
+
+    /// Binds a socket of `app` to `endpoint`, and returns its number.
+    fn bind(&self, app: &mut App, endpoint: UDPEndpoint) -> ReturnCode {
+        let multicast = endpoint.addr.is_multicast();
+        // Check that requested addr is a local interface or a multicast group
+        if endpoint.port == 0 || !(multicast || self.interface.has_addr(&endpoint.addr)) {
+            return ReturnCode::EINVAL;
+        }
+        if app.bindings.contains(&Some(endpoint)) {
+            return ReturnCode::EALREADY;
+        }
+        let slot = match app.bindings.iter().position(|b| b.is_none()) {
+            Some(slot) => slot,
+            None => return ReturnCode::ENOMEM,
+        };
+        // The ports of in-kernel users are never free, and the sockets of
+        // several apps can only be bound to the same multicast group
+        if self.receiver.port_table().is_reserved(endpoint.port)
+            || (!multicast && self.is_bound_to(|b| *b == endpoint))
+        {
+            return ReturnCode::EBUSY;
+        }
+        if multicast {
+            let result = self.join_group(endpoint.addr);
+            if result != ReturnCode::SUCCESS {
+                return result;
+            }
+        }
+        app.bindings[slot] = Some(endpoint);
+        ReturnCode::SuccessWithValue { value: slot }
+    }
+
     /// If the driver is currently idle and there are pending transmissions,
     /// pick an app with a pending transmission and return its `AppId`.
     fn get_next_tx_if_idle(&self) -> Option<AppId> {
@@ -317,7 +435,8 @@ impl<'a> Driver for UDPDriver<'a> {
     /// ### `allow_num`
     ///
     /// - `0`: Read buffer. Will contain the received payload.
-    /// - `1`: Write buffer. Contains the UDP payload to be transmitted.
+    /// - `1`: Write buffer. Contains the UDP payload to be transmitted. Prefer
+    ///        `allow_readonly`.
     /// - `2`: Config buffer. Used to contain miscellaneous data associated with
     ///        some commands, namely source/destination addresses and ports.
     /// - `3`: Rx config buffer. Used to contain source/destination addresses
@@ -333,7 +452,7 @@ impl<'a> Driver for UDPDriver<'a> {
             0 | 1 | 2 | 3 => self.do_with_app(appid, |app| {
                 match allow_num {
                     0 => app.app_read = slice,
-                    1 => app.app_write = slice,
+                    1 => app.app_write = slice.map(AppSlice::from),
                     2 => app.app_cfg = slice,
                     3 => app.app_rx_cfg = slice,
                     _ => {}
@@ -344,13 +463,35 @@ impl<'a> Driver for UDPDriver<'a> {
         }
     }
 
+    /// Setup buffers to read from.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `1`: Write buffer. Contains the UDP payload to be transmitted.
+    fn allow_readonly(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<ReadOnly, u8>>,
+    ) -> ReturnCode {
+        match allow_num {
+            1 => self.do_with_app(appid, |app| {
+                app.app_write = slice;
+                ReturnCode::SUCCESS
+            }),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
     /// Setup callbacks.
     ///
     /// ### `subscribe_num`
     ///
-    /// - `0`: Setup callback for when packet is received. If no port has
-    ///        been bound, return ERESERVE to indicate that port binding is
-    ///        is a prerequisite to reception.
+    /// - `0`: Setup callback for when packet is received. The callback
+    ///        receives the length of the payload and the number of the
+    ///        socket it was received on. If no port has been bound, return
+    ///        ERESERVE to indicate that port binding is is a prerequisite to
+    ///        reception.
     /// - `1`: Setup callback for when packet is transmitted. Notably,
     ///        this callback receives the result of the send_done callback
     ///        from udp_send.rs, which does not currently pass information
@@ -363,7 +504,7 @@ impl<'a> Driver for UDPDriver<'a> {
     ) -> ReturnCode {
         match subscribe_num {
             0 => self.do_with_app(app_id, |app| {
-                if app.bound_port.is_some() {
+                if app.bindings.iter().any(Option::is_some) {
                     app.rx_callback = callback;
                     ReturnCode::SUCCESS
                 } else {
@@ -401,29 +542,40 @@ impl<'a> Driver for UDPDriver<'a> {
     ///        was being passed down to the radio. Any successful return value indicates that
     ///        the app should wait for a send_done() callback before attempting to queue another
     ///        packet.
-    ///        Currently, only will transmit if the app has bound to the port passed in the tx_cfg
-    ///        buf as the source address. If no port is bound, returns ERESERVE, if it tries to
-    ///        send on a port other than the port which is bound, returns EINVALID.
+    ///        Currently, only will transmit if the app has bound a socket to the address and
+    ///        port passed in the tx_cfg buf as the source address, which cannot be a multicast
+    ///        group. If no port is bound, returns ERESERVE, if it tries to send on a port other
+    ///        than the ports which are bound, returns EINVALID.
     ///
     ///        Notably, the currently transmit implementation allows for starvation - an
     ///        an app with a lower app id can send constantly and starve an app with a
     ///        later ID.
-    /// - `3`: Bind to the address in rx_cfg. Returns SUCCESS if that addr/port combo is free,
-    ///        returns EINVAL if the address requested is not a local interface, or if the port
-    ///        requested is 0. Returns EBUSY if that port is already bound to by another app.
-    ///        This command should be called after allow() is called on the rx_cfg buffer, and
-    ///        before subscribe() is used to set up the recv callback. Additionally, apps can only
-    ///        send on ports after they have bound to said port. If this command is called
-    ///        and the address in rx_cfg is 0::0 : 0, this command will reset the option
-    ///        containing the bound port to None and set the rx callback to None. Notably,
-    ///        the current implementation of this only allows for each app to bind to a single
-    ///        port at a time, as such an implementation conserves memory (and is similar
-    ///        to the approach applied by TinyOS and Riot). Further, there is
-    ///        currently no mechanism for anything in the kernel to bind to ports, and there
-    ///        is no distinction between ephemeral ports and reserved ports.
+    /// - `3`: Bind a socket to the address in rx_cfg. Returns the number of the socket if
+    ///        that addr/port combo is free, returns EINVAL if the address requested is neither
+    ///        a local interface nor a multicast group, or if the port requested is 0. Returns
+    ///        EBUSY if that port is already bound to by another app, EALREADY if the app
+    ///        already bound a socket to it, and ENOMEM if the app bound `MAX_BINDINGS`
+    ///        sockets or the interface cannot join more multicast groups. Binding to a
+    ///        multicast group joins it, and the sockets of several apps can be bound to the
+    ///        same group and port. This command should be called after allow() is called on
+    ///        the rx_cfg buffer, and before subscribe() is used to set up the recv callback.
+    ///        Additionally, apps can only send on ports after they have bound to said port.
+    ///        If this command is called and the address in rx_cfg is 0::0 : 0, this command
+    ///        will close all the sockets of the app and set the rx callback to None. The
+    ///        ports that in-kernel protocols reserved in the port table, e.g. the MLE port of
+    ///        Thread, are always busy. There is no distinction between ephemeral ports and
+    ///        reserved ports.
     /// - `4`: Returns the maximum payload that can be transmitted by apps using this driver.
     ///        This represents the size of the payload buffer in the kernel. Apps can use this
     ///        syscall to ensure they do not attempt to send too-large messages.
+    /// - `5`: Returns a counter of the reassembly of fragmented packets, selected by
+    ///        `arg1`: 0 for the packets reassembled, 1 for the reassemblies that timed out,
+    ///        2 for the reassemblies evicted to make room for another packet, 3 for the
+    ///        overlapping fragments and 4 for the duplicate fragments received. Returns
+    ///        EINVAL for other values of `arg1`, and ENOSUPPORT if the driver cannot read
+    ///        the counters of the 6LoWPAN layer.
+    /// - `6`: Close the socket numbered `arg1`, leaving its multicast group if no other socket
+    ///        is bound to it. Returns EINVAL if the app did not bind that socket.
 
     fn command(&self, command_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
         match command_num {
@@ -432,16 +584,16 @@ impl<'a> Driver for UDPDriver<'a> {
             //  Writes the requested number of network interface addresses
             // `arg1`: number of interfaces requested that will fit into the buffer
             1 => self.do_with_cfg_mut(appid, arg1 * mem::size_of::<IPAddr>(), |cfg| {
-                let n_ifaces_to_copy = cmp::min(arg1, self.interface_list.len());
+                let n_ifaces = self.interface.addr_count();
+                let n_ifaces_to_copy = cmp::min(arg1, n_ifaces);
                 let iface_size = mem::size_of::<IPAddr>();
                 for i in 0..n_ifaces_to_copy {
-                    cfg[i * iface_size..(i + 1) * iface_size]
-                        .copy_from_slice(&self.interface_list[i].0);
+                    if let Some(entry) = self.interface.get_addr(i) {
+                        cfg[i * iface_size..(i + 1) * iface_size].copy_from_slice(&entry.addr.0);
+                    }
                 }
                 // Returns total number of interfaces
-                ReturnCode::SuccessWithValue {
-                    value: self.interface_list.len(),
-                }
+                ReturnCode::SuccessWithValue { value: n_ifaces }
             }),
 
             // Transmits UDP packet stored in tx_buf
@@ -451,7 +603,7 @@ impl<'a> Driver for UDPDriver<'a> {
                         // Cannot support more than one pending tx per process.
                         return ReturnCode::EBUSY;
                     }
-                    if app.bound_port.is_none() {
+                    if app.bindings.iter().all(Option::is_none) {
                         // Currently, apps need to bind to a port before they can send from said port
                         return ReturnCode::ERESERVE;
                     }
@@ -464,7 +616,7 @@ impl<'a> Driver for UDPDriver<'a> {
                             self.parse_ip_port_pair(&cfg.as_ref()[mem::size_of::<UDPEndpoint>()..]),
                             self.parse_ip_port_pair(&cfg.as_ref()[..mem::size_of::<UDPEndpoint>()]),
                         ) {
-                            if Some(src.clone()) == app.bound_port {
+                            if !src.addr.is_multicast() && app.bindings.contains(&Some(src)) {
                                 Some([src, dst])
                             } else {
                                 None
@@ -483,68 +635,53 @@ impl<'a> Driver for UDPDriver<'a> {
             3 => {
                 self.do_with_app(appid, |app| {
                     // Move UDPEndpoint into udp.rs?
-                    let mut requested_addr_opt = app.app_rx_cfg.as_ref().and_then(|cfg| {
+                    let requested_addr_opt = app.app_rx_cfg.as_ref().and_then(|cfg| {
                         if cfg.len() != 2 * mem::size_of::<UDPEndpoint>() {
                             None
-                        } else if let Some(local_iface) =
-                            self.parse_ip_port_pair(&cfg.as_ref()[mem::size_of::<UDPEndpoint>()..])
-                        {
-                            Some(local_iface)
                         } else {
-                            None
+                            self.parse_ip_port_pair(&cfg.as_ref()[mem::size_of::<UDPEndpoint>()..])
                         }
                     });
-                    if requested_addr_opt.is_none() {
-                        return ReturnCode::EINVAL;
-                    }
-                    if requested_addr_opt.is_some() {
-                        let requested_addr = requested_addr_opt.unwrap();
-                        // If zero address, close any already bound socket
-                        if requested_addr.is_zero() {
+                    match requested_addr_opt {
+                        // If zero address, close all bound sockets
+                        Some(requested_addr) if requested_addr.is_zero() => {
                             app.rx_callback = None;
-                            app.bound_port = None;
-                            return ReturnCode::SUCCESS;
-                        }
-                        // Check that requested addr is a local interface
-                        let mut requested_is_local = false;
-                        for i in 0..self.interface_list.len() {
-                            if requested_addr.addr == self.interface_list[i] {
-                                requested_is_local = true;
-                            }
-                        }
-                        if !requested_is_local {
-                            return ReturnCode::EINVAL;
-                        }
-                        let mut addr_already_bound = false;
-                        for app in self.apps.iter() {
-                            app.enter(|other_app, _| {
-                                if other_app.bound_port.is_some() {
-                                    let other_addr_opt = other_app.bound_port.clone();
-                                    let other_addr = other_addr_opt.unwrap();
-                                    if other_addr.port == requested_addr.port {
-                                        if other_addr.addr == requested_addr.addr {
-                                            addr_already_bound = true;
-                                        }
-                                    }
-                                }
-                            });
-                        }
-                        if addr_already_bound {
-                            ReturnCode::EBUSY
-                        } else {
-                            requested_addr_opt = Some(requested_addr);
-                            // If this point is reached, the requested addr is free and valid
-                            app.bound_port = requested_addr_opt;
+                            app.bindings = Default::default();
+                            self.leave_unused_groups();
                             ReturnCode::SUCCESS
                         }
-                    } else {
-                        ReturnCode::EINVAL
+                        Some(requested_addr) => self.bind(app, requested_addr),
+                        None => ReturnCode::EINVAL,
                     }
                 })
             }
             4 => ReturnCode::SuccessWithValue {
                 value: self.max_tx_pyld_len,
             },
+            5 => self
+                .sixlowpan_state
+                .map_or(ReturnCode::ENOSUPPORT, |sixlowpan_state| {
+                    let stats = sixlowpan_state.get_rx_stats();
+                    let counter = match arg1 {
+                        0 => stats.reassembled,
+                        1 => stats.timeouts,
+                        2 => stats.evictions,
+                        3 => stats.overlaps,
+                        4 => stats.duplicates,
+                        _ => return ReturnCode::EINVAL,
+                    };
+                    ReturnCode::SuccessWithValue {
+                        value: counter as usize,
+                    }
+                }),
+            6 => self.do_with_app(appid, |app| match app.bindings.get_mut(arg1) {
+                Some(binding) if binding.is_some() => {
+                    *binding = None;
+                    self.leave_unused_groups();
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::EINVAL,
+            }),
             _ => ReturnCode::ENOSUPPORT,
         }
     }
@@ -571,44 +708,58 @@ impl<'a> UDPRecvClient for UDPDriver<'a> {
         src_port: u16,
         dst_port: u16,
         payload: &[u8],
-    ) {
+    ) -> bool {
+        let bound = Cell::new(false);
         self.apps.each(|app| {
-            if app.bound_port.is_some() {
+            let socket = app
+                .bindings
+                .iter()
+                .position(|b| b.map_or(false, |b| b.addr == dst_addr && b.port == dst_port));
+            if let Some(socket) = socket {
                 let appid = app.appid();
                 self.do_with_app(app.appid(), |app| {
-                    let mut for_me = false;
-                    app.bound_port.as_ref().map(|requested_addr| {
-                        if requested_addr.addr == dst_addr && requested_addr.port == dst_port {
-                            for_me = true;
+                    bound.set(true);
+                    let mut app_read = app.app_read.take();
+                    app_read.as_mut().map(|rbuf| {
+                        let rbuf = rbuf.as_mut();
+                        let len = payload.len();
+                        if rbuf.len() >= len {
+                            // silently ignore packets that don't fit?
+                            rbuf[..len].copy_from_slice(&payload[..len]);
+
+                            // Write address of sender into rx_cfg so it can be read by client
+                            let sender_addr = UDPEndpoint {
+                                addr: src_addr,
+                                port: src_port,
+                            };
+                            let cfg_len = 2 * mem::size_of::<UDPEndpoint>();
+                            self.do_with_rx_cfg_mut(appid, cfg_len, |cfg| {
+                                sender_addr.encode(cfg, 0);
+                                ReturnCode::SUCCESS
+                            });
+                            app.rx_callback.map(|mut cb| cb.schedule(len, socket, 0));
                         }
                     });
-                    if for_me {
-                        let mut app_read = app.app_read.take();
-                        app_read.as_mut().map(|rbuf| {
-                            let rbuf = rbuf.as_mut();
-                            let len = payload.len();
-                            if rbuf.len() >= len {
-                                // silently ignore packets that don't fit?
-                                rbuf[..len].copy_from_slice(&payload[..len]);
-
-                                // Write address of sender into rx_cfg so it can be read by client
-                                let sender_addr = UDPEndpoint {
-                                    addr: src_addr,
-                                    port: src_port,
-                                };
-                                let cfg_len = 2 * mem::size_of::<UDPEndpoint>();
-                                self.do_with_rx_cfg_mut(appid, cfg_len, |cfg| {
-                                    sender_addr.encode(cfg, 0);
-                                    ReturnCode::SUCCESS
-                                });
-                                app.rx_callback.map(|mut cb| cb.schedule(len, 0, 0));
-                            }
-                        });
-                        app.app_read = app_read;
-                    }
+                    app.app_read = app_read;
                     ReturnCode::SUCCESS
                 });
             }
         });
+        // The apps bound to the group exited
+        if !bound.get()
+            && self
+                .groups
+                .iter()
+                .any(|group| group.get() == Some(dst_addr))
+        {
+            self.leave_unused_groups();
+        }
+        bound.get()
+    }
+}
+
+impl<'a> UDPPortUser for UDPDriver<'a> {
+    fn is_bound(&self, port: u16) -> bool {
+        self.is_bound_to(|b| b.port == port)
     }
 }
@@ -0,0 +1,155 @@
+//! The table of the UDP ports in use, shared by in-kernel UDP users and the
+//! sockets of processes so that they cannot bind to the same port.
+//!
+//! In-kernel users reserve their ports in the table, either through the
+//! `UDPReceiver` when they receive on them, or directly when they only send
+//! from them. The sockets of processes are bound in the grants of the UDP
+//! driver, which the table asks through the `UDPPortUser` trait, as they go
+//! away with their processes.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! // Reserved as the kernel client is added
+//! udp_recv.add_kernel_client(mle_udp_client);
+//! // Reserved by a user that only sends from its port
+//! udp_recv.port_table().reserve(SRC_PORT);
+//! // Lets the table check the ports processes are bound to
+//! udp_recv.port_table().set_user(udp_driver);
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::ReturnCode;
+
+/// Maximum number of ports that in-kernel users can reserve.
+pub const MAX_KERNEL_PORTS: usize = 8;
+
+/// Implemented by the UDP driver, which binds the sockets of processes to
+/// ports.
+pub trait UDPPortUser {
+    /// Returns whether a socket of a process is bound to `port`.
+    fn is_bound(&self, port: u16) -> bool;
+}
+
+pub struct UDPPortTable<'a> {
+    kernel_ports: [Cell<Option<u16>>; MAX_KERNEL_PORTS],
+    user: OptionalCell<&'a dyn UDPPortUser>,
+}
+
+impl<'a> UDPPortTable<'a> {
+    pub fn new() -> UDPPortTable<'a> {
+        UDPPortTable {
+            kernel_ports: Default::default(),
+            user: OptionalCell::empty(),
+        }
+    }
+
+    /// Sets the driver whose processes bind to ports.
+    pub fn set_user(&self, user: &'a dyn UDPPortUser) {
+        self.user.set(user);
+    }
+
+    /// Reserves `port` for an in-kernel user. Returns EINVAL if `port` is 0,
+    /// EBUSY if it is reserved already or a process is bound to it, and
+    /// ENOMEM if `MAX_KERNEL_PORTS` ports are reserved.
+    pub fn reserve(&self, port: u16) -> ReturnCode {
+        if port == 0 {
+            return ReturnCode::EINVAL;
+        }
+        if !self.is_free(port) {
+            return ReturnCode::EBUSY;
+        }
+        match self.kernel_ports.iter().find(|entry| entry.get().is_none()) {
+            Some(entry) => {
+                entry.set(Some(port));
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ENOMEM,
+        }
+    }
+
+    /// Releases a port reserved by an in-kernel user.
+    pub fn release(&self, port: u16) {
+        for entry in self.kernel_ports.iter() {
+            if entry.get() == Some(port) {
+                entry.set(None);
+            }
+        }
+    }
+
+    /// Returns whether an in-kernel user reserved `port`.
+    pub fn is_reserved(&self, port: u16) -> bool {
+        self.kernel_ports
+            .iter()
+            .any(|entry| entry.get() == Some(port))
+    }
+
+    /// Returns whether nobody, in the kernel or in a process, uses `port`.
+    pub fn is_free(&self, port: u16) -> bool {
+        !self.is_reserved(port) && !self.user.map_or(false, |user| user.is_bound(port))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Driver {
+        port: Cell<Option<u16>>,
+    }
+
+    impl UDPPortUser for Driver {
+        fn is_bound(&self, port: u16) -> bool {
+            self.port.get() == Some(port)
+        }
+    }
+
+    #[test]
+    fn reserves_ports() {
+        let table = UDPPortTable::new();
+        assert_eq!(table.reserve(0), ReturnCode::EINVAL);
+        assert_eq!(table.reserve(19788), ReturnCode::SUCCESS);
+        assert_eq!(table.reserve(19788), ReturnCode::EBUSY);
+        assert!(table.is_reserved(19788));
+        assert!(!table.is_free(19788));
+        assert!(table.is_free(5683));
+
+        table.release(19788);
+        assert!(!table.is_reserved(19788));
+        assert!(table.is_free(19788));
+        // Releasing a port that is not reserved does nothing
+        table.release(19788);
+        assert_eq!(table.reserve(19788), ReturnCode::SUCCESS);
+    }
+
+    #[test]
+    fn kernel_ports_are_limited() {
+        let table = UDPPortTable::new();
+        for port in 1..=MAX_KERNEL_PORTS as u16 {
+            assert_eq!(table.reserve(port), ReturnCode::SUCCESS);
+        }
+        assert_eq!(table.reserve(100), ReturnCode::ENOMEM);
+        table.release(1);
+        assert_eq!(table.reserve(100), ReturnCode::SUCCESS);
+    }
+
+    #[test]
+    fn ports_of_processes_are_not_free() {
+        let table = UDPPortTable::new();
+        let driver = Driver {
+            port: Cell::new(Some(1000)),
+        };
+        table.set_user(&driver);
+        assert!(!table.is_free(1000));
+        // But they are not reserved, which only in-kernel users do
+        assert!(!table.is_reserved(1000));
+        assert_eq!(table.reserve(1000), ReturnCode::EBUSY);
+
+        // The port is free again once the socket goes away
+        driver.port.set(None);
+        assert!(table.is_free(1000));
+        assert_eq!(table.reserve(1000), ReturnCode::SUCCESS);
+    }
+}
@@ -1,9 +1,15 @@
-use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::icmpv6::icmpv6::{dest_unreachable_code, param_problem_code};
+use crate::net::icmpv6::icmpv6::{ICMP6Header, ICMP6HeaderOptions, ICMP6Type};
+use crate::net::icmpv6::icmpv6_recv::ICMP6ErrorReporter;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
 use crate::net::ipv6::ipv6::IP6Header;
 use crate::net::ipv6::ipv6_recv::IP6RecvClient;
 use crate::net::udp::udp::UDPHeader;
+use crate::net::udp::udp_port_table::UDPPortTable;
 use kernel::common::cells::OptionalCell;
+use kernel::common::{List, ListLink, ListNode};
 use kernel::debug;
+use kernel::ReturnCode;
 
 /// The UDP driver implements this client interface trait to receive
 /// packets passed up the network stack to the UDPReceiver, and then
@@ -11,6 +17,8 @@ use kernel::debug;
 /// Kernel apps can also instantiate structs that implement this trait
 /// in order to receive UDP packets
 pub trait UDPRecvClient {
+    /// Returns whether something is bound to `dst_addr` and `dst_port`, as
+    /// the sender of the packet is told otherwise.
     fn receive(
         &self,
         src_addr: IPAddr,
@@ -18,49 +26,127 @@ pub trait UDPRecvClient {
         src_port: u16,
         dst_port: u16,
         payload: &[u8],
-    );
+    ) -> bool;
 }
 
 /// This struct is set as the client of an IP6Receiver, and passes
 /// received packets up to whatever app layer client assigns itself
-/// as the UDPRecvClient held by this UDPReciever.
+/// as the UDPRecvClient held by this UDPReciever. The packets sent to the
+/// ports of in-kernel protocols, e.g. MLE, go to the kernel clients bound
+/// to these ports instead. The receiver holds the table of the ports in use,
+/// which its client and the kernel clients share.
 pub struct UDPReceiver<'a> {
     client: OptionalCell<&'a dyn UDPRecvClient>,
+    kernel_clients: List<'a, UDPKernelClient<'a>>,
+    port_table: UDPPortTable<'a>,
+    error_reporter: OptionalCell<&'a dyn ICMP6ErrorReporter>,
 }
 
 impl<'a> UDPReceiver<'a> {
     pub fn new() -> UDPReceiver<'a> {
         UDPReceiver {
             client: OptionalCell::empty(),
+            kernel_clients: List::new(),
+            port_table: UDPPortTable::new(),
+            error_reporter: OptionalCell::empty(),
         }
     }
 
     pub fn set_client(&self, client: &'a dyn UDPRecvClient) {
         self.client.set(client);
     }
+
+    /// Binds a kernel client to its port, which is reserved in the port
+    /// table so that the client of the `UDPReceiver` can no longer bind to
+    /// it. Returns the result of the reservation, e.g. EBUSY if the port is
+    /// in use already.
+    pub fn add_kernel_client(&self, kernel_client: &'a UDPKernelClient<'a>) -> ReturnCode {
+        let result = self.port_table.reserve(kernel_client.port);
+        if result == ReturnCode::SUCCESS {
+            self.kernel_clients.push_tail(kernel_client);
+        }
+        result
+    }
+
+    /// Returns the table of the ports in use.
+    pub fn port_table(&self) -> &UDPPortTable<'a> {
+        &self.port_table
+    }
+
+    /// Sets the reporter that tells the senders of datagrams that are
+    /// malformed or sent to a port nobody is bound to.
+    pub fn set_error_reporter(&self, error_reporter: &'a dyn ICMP6ErrorReporter) {
+        self.error_reporter.set(error_reporter);
+    }
+
+    fn report_error(&self, icmp_header: ICMP6Header, ip_header: &IP6Header, payload: &[u8]) {
+        self.error_reporter
+            .map(|reporter| reporter.report_error(icmp_header, ip_header, payload));
+    }
 }
 
 impl<'a> IP6RecvClient for UDPReceiver<'a> {
     fn receive(&self, ip_header: IP6Header, payload: &[u8]) {
+        if ip_header.get_next_header() != ip6_nh::UDP {
+            return;
+        }
         match UDPHeader::decode(payload).done() {
             Some((offset, udp_header)) => {
                 let len = udp_header.get_len() as usize;
                 if len > payload.len() {
-                    // TODO: ERROR
                     debug!("[UDP_RECV] Error: UDP length too long");
+                    // Points at the length field of the UDP header
+                    let mut icmp_header = ICMP6Header::new(ICMP6Type::Type4);
+                    icmp_header.set_code(param_problem_code::ERRONEOUS_HEADER_FIELD);
+                    icmp_header.set_options(ICMP6HeaderOptions::Type4 { pointer: 40 + 4 });
+                    self.report_error(icmp_header, &ip_header, payload);
                     return;
                 }
-                self.client.map(|client| {
+                let dst_port = udp_header.get_dst_port();
+                let deliver = |client: &dyn UDPRecvClient| {
                     client.receive(
                         ip_header.get_src_addr(),
                         ip_header.get_dst_addr(),
                         udp_header.get_src_port(),
-                        udp_header.get_dst_port(),
+                        dst_port,
                         &payload[offset..],
-                    );
-                });
+                    )
+                };
+                let delivered = match self.kernel_clients.iter().find(|c| c.port == dst_port) {
+                    Some(kernel_client) => deliver(kernel_client.client),
+                    None => self.client.map_or(false, |client| deliver(*client)),
+                };
+                if !delivered {
+                    let mut icmp_header = ICMP6Header::new(ICMP6Type::Type1);
+                    icmp_header.set_code(dest_unreachable_code::PORT_UNREACHABLE);
+                    self.report_error(icmp_header, &ip_header, payload);
+                }
             }
             None => {}
         }
     }
 }
+
+/// The binding of an in-kernel `UDPRecvClient` to a port of a
+/// `UDPReceiver`.
+pub struct UDPKernelClient<'a> {
+    port: u16,
+    client: &'a dyn UDPRecvClient,
+    next: ListLink<'a, UDPKernelClient<'a>>,
+}
+
+impl<'a> UDPKernelClient<'a> {
+    pub fn new(port: u16, client: &'a dyn UDPRecvClient) -> UDPKernelClient<'a> {
+        UDPKernelClient {
+            port,
+            client,
+            next: ListLink::empty(),
+        }
+    }
+}
+
+impl<'a> ListNode<'a, UDPKernelClient<'a>> for UDPKernelClient<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, UDPKernelClient<'a>> {
+        &self.next
+    }
+}
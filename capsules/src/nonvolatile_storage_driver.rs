@@ -288,15 +288,7 @@ impl NonvolatileStorage<'a> {
                             // Nothing is using this, lets go!
                             self.current_user.set(NonvolatileUser::Kernel);
 
-                            match command {
-                                NonvolatileCommand::KernelRead => {
-                                    self.driver.read(kernel_buffer, offset, active_len)
-                                }
-                                NonvolatileCommand::KernelWrite => {
-                                    self.driver.write(kernel_buffer, offset, active_len)
-                                }
-                                _ => ReturnCode::FAIL,
-                            }
+                            self.kernel_call_driver(command, kernel_buffer, offset, active_len)
                         } else {
                             if self.kernel_pending_command.get() == true {
                                 ReturnCode::ENOMEM
@@ -330,18 +322,64 @@ impl NonvolatileStorage<'a> {
             let active_len = cmp::min(length, buffer.len());
 
             // self.current_app.set(Some(appid));
-            match command {
+            let (res, buffer) = match command {
                 NonvolatileCommand::UserspaceRead => {
                     self.driver.read(buffer, physical_address, active_len)
                 }
                 NonvolatileCommand::UserspaceWrite => {
                     self.driver.write(buffer, physical_address, active_len)
                 }
-                _ => ReturnCode::FAIL,
+                _ => (ReturnCode::FAIL, Some(buffer)),
+            };
+            // Keep the buffer for the next command if this one did not start.
+            if res != ReturnCode::SUCCESS {
+                buffer.map(|buffer| self.buffer.replace(buffer));
+                self.current_user.clear();
             }
+            res
         })
     }
 
+    fn kernel_call_driver(
+        &self,
+        command: NonvolatileCommand,
+        kernel_buffer: &'static mut [u8],
+        offset: usize,
+        length: usize,
+    ) -> ReturnCode {
+        let (res, kernel_buffer) = match command {
+            NonvolatileCommand::KernelRead => self.driver.read(kernel_buffer, offset, length),
+            NonvolatileCommand::KernelWrite => self.driver.write(kernel_buffer, offset, length),
+            _ => (ReturnCode::FAIL, Some(kernel_buffer)),
+        };
+        // Hold on to the buffer so that it can be given back to the kernel.
+        if res != ReturnCode::SUCCESS {
+            kernel_buffer.map(|kernel_buffer| self.kernel_buffer.replace(kernel_buffer));
+            self.current_user.clear();
+        }
+        res
+    }
+
+    // Queue a command of the kernel, and give its buffer back if the command
+    // is not queued or does not start.
+    fn kernel_enqueue(
+        &self,
+        command: NonvolatileCommand,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        // The buffer of the command already queued is held until it runs.
+        if self.kernel_pending_command.get() {
+            return (ReturnCode::EBUSY, Some(buffer));
+        }
+        self.kernel_buffer.replace(buffer);
+        match self.enqueue_command(command, address, length, None) {
+            ReturnCode::SUCCESS => (ReturnCode::SUCCESS, None),
+            error => (error, self.kernel_buffer.take()),
+        }
+    }
+
     fn check_queue(&self) {
         // Check if there are any pending events.
         if self.kernel_pending_command.get() {
@@ -349,18 +387,22 @@ impl NonvolatileStorage<'a> {
                 self.kernel_pending_command.set(false);
                 self.current_user.set(NonvolatileUser::Kernel);
 
-                match self.kernel_command.get() {
-                    NonvolatileCommand::KernelRead => self.driver.read(
-                        kernel_buffer,
-                        self.kernel_readwrite_address.get(),
-                        self.kernel_readwrite_length.get(),
-                    ),
-                    NonvolatileCommand::KernelWrite => self.driver.write(
-                        kernel_buffer,
-                        self.kernel_readwrite_address.get(),
-                        self.kernel_readwrite_length.get(),
-                    ),
-                    _ => ReturnCode::FAIL,
+                let command = self.kernel_command.get();
+                let res = self.kernel_call_driver(
+                    command,
+                    kernel_buffer,
+                    self.kernel_readwrite_address.get(),
+                    self.kernel_readwrite_length.get(),
+                );
+                // The kernel waits for a callback, so it gets its buffer
+                // back with nothing read or written.
+                if res != ReturnCode::SUCCESS {
+                    if let Some(kernel_buffer) = self.kernel_buffer.take() {
+                        self.kernel_client.map(move |client| match command {
+                            NonvolatileCommand::KernelRead => client.read_done(kernel_buffer, 0),
+                            _ => client.write_done(kernel_buffer, 0),
+                        });
+                    }
                 }
             });
         } else {
@@ -453,14 +495,22 @@ impl hil::nonvolatile_storage::NonvolatileStorage<'static> for NonvolatileStorag
         self.kernel_client.set(client);
     }
 
-    fn read(&self, buffer: &'static mut [u8], address: usize, length: usize) -> ReturnCode {
-        self.kernel_buffer.replace(buffer);
-        self.enqueue_command(NonvolatileCommand::KernelRead, address, length, None)
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.kernel_enqueue(NonvolatileCommand::KernelRead, buffer, address, length)
     }
 
-    fn write(&self, buffer: &'static mut [u8], address: usize, length: usize) -> ReturnCode {
-        self.kernel_buffer.replace(buffer);
-        self.enqueue_command(NonvolatileCommand::KernelWrite, address, length, None)
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        self.kernel_enqueue(NonvolatileCommand::KernelWrite, buffer, address, length)
     }
 }
 
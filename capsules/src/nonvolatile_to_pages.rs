@@ -83,6 +83,17 @@ impl<F: hil::flash::Flash> NonvolatileToPages<'a, F> {
             buffer_index: Cell::new(0),
         }
     }
+
+    /// Gives the buffer of the user back if the flash driver did not start
+    /// the first page.
+    fn started(&self, res: ReturnCode) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if res == ReturnCode::SUCCESS {
+            (res, None)
+        } else {
+            self.state.set(State::Idle);
+            (res, self.buffer.take())
+        }
+    }
 }
 
 impl<F: hil::flash::Flash> hil::nonvolatile_storage::NonvolatileStorage<'static>
@@ -92,64 +103,74 @@ impl<F: hil::flash::Flash> hil::nonvolatile_storage::NonvolatileStorage<'static>
         self.client.set(client);
     }
 
-    fn read(&self, buffer: &'static mut [u8], address: usize, length: usize) -> ReturnCode {
+    fn read(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
         if self.state.get() != State::Idle {
-            return ReturnCode::EBUSY;
+            return (ReturnCode::EBUSY, Some(buffer));
         }
+        let pagebuffer = match self.pagebuffer.take() {
+            Some(pagebuffer) => pagebuffer,
+            None => return (ReturnCode::ERESERVE, Some(buffer)),
+        };
+        let page_size = pagebuffer.as_mut().len();
 
-        self.pagebuffer
-            .take()
-            .map_or(ReturnCode::ERESERVE, move |pagebuffer| {
-                let page_size = pagebuffer.as_mut().len();
-
-                // Just start reading. We'll worry about how much of the page we
-                // want later.
-                self.state.set(State::Read);
-                self.buffer.replace(buffer);
-                self.address.set(address);
-                self.length.set(length);
-                self.remaining_length.set(length);
-                self.buffer_index.set(0);
-                self.driver.read_page(address / page_size, pagebuffer)
-            })
+        // Just start reading. We'll worry about how much of the page we
+        // want later.
+        self.state.set(State::Read);
+        self.buffer.replace(buffer);
+        self.address.set(address);
+        self.length.set(length);
+        self.remaining_length.set(length);
+        self.buffer_index.set(0);
+        let res = self.driver.read_page(address / page_size, pagebuffer);
+        self.started(res)
     }
 
-    fn write(&self, buffer: &'static mut [u8], address: usize, length: usize) -> ReturnCode {
+    fn write(
+        &self,
+        buffer: &'static mut [u8],
+        address: usize,
+        length: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
         if self.state.get() != State::Idle {
-            return ReturnCode::EBUSY;
+            return (ReturnCode::EBUSY, Some(buffer));
         }
+        let pagebuffer = match self.pagebuffer.take() {
+            Some(pagebuffer) => pagebuffer,
+            None => return (ReturnCode::ERESERVE, Some(buffer)),
+        };
+        let page_size = pagebuffer.as_mut().len();
 
-        self.pagebuffer
-            .take()
-            .map_or(ReturnCode::ERESERVE, move |pagebuffer| {
-                let page_size = pagebuffer.as_mut().len();
-
-                self.state.set(State::Write);
-                self.length.set(length);
+        self.state.set(State::Write);
+        self.length.set(length);
 
-                if address % page_size == 0 && length >= page_size {
-                    // This write is aligned to a page and we are writing an entire
-                    // page or more.
+        let res = if address % page_size == 0 && length >= page_size {
+            // This write is aligned to a page and we are writing an entire
+            // page or more.
 
-                    // Copy data into page buffer.
-                    for i in 0..page_size {
-                        pagebuffer.as_mut()[i] = buffer[i];
-                    }
+            // Copy data into page buffer.
+            for i in 0..page_size {
+                pagebuffer.as_mut()[i] = buffer[i];
+            }
 
-                    self.buffer.replace(buffer);
-                    self.address.set(address + page_size);
-                    self.remaining_length.set(length - page_size);
-                    self.buffer_index.set(page_size);
-                    self.driver.write_page(address / page_size, pagebuffer)
-                } else {
-                    // Need to do a read first.
-                    self.buffer.replace(buffer);
-                    self.address.set(address);
-                    self.remaining_length.set(length);
-                    self.buffer_index.set(0);
-                    self.driver.read_page(address / page_size, pagebuffer)
-                }
-            })
+            self.buffer.replace(buffer);
+            self.address.set(address + page_size);
+            self.remaining_length.set(length - page_size);
+            self.buffer_index.set(page_size);
+            self.driver.write_page(address / page_size, pagebuffer)
+        } else {
+            // Need to do a read first.
+            self.buffer.replace(buffer);
+            self.address.set(address);
+            self.remaining_length.set(length);
+            self.buffer_index.set(0);
+            self.driver.read_page(address / page_size, pagebuffer)
+        };
+        self.started(res)
     }
 }
 
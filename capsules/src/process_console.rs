@@ -5,13 +5,30 @@
 //! --------
 //!
 //! This module provides a simple text-based console to inspect and control
-//! which processes are running. The console has five commands:
+//! which processes are running. The console has these commands:
 //!  - 'help' prints the available commands and arguments
 //!  - 'status' prints the current system status
 //!  - 'list' lists the current processes with their IDs and running state
+//!  - 'top' shows how much CPU time each process uses
+//!  - 'queue' shows the queued tasks and dropped callbacks of each process
+//!  - 'grants' shows how much of its grant region each process uses
+//!  - 'interrupts' shows how many times each interrupt was serviced
+//!  - 'dump n' prints the memory ranges of the process with name n
+//!  - 'dump n a l' prints l bytes (64 by default, at most 128) of the RAM or
+//!    flash of the process with name n, starting at hex address a
 //!  - 'stop n' stops the process with name n
 //!  - 'start n' starts the stopped process with name n
+//!  - 'restart n' restarts the process with name n from its entry point
+//!  - 'terminate n' terminates the process with name n
 //!  - 'fault n' forces the process with name n into a fault state
+//!  - 'reboot' resets the board, if the board set a reset function
+//!  - 'log' shows the levels of kernel log messages, 'log l' sets the default
+//!    level to l, 'log m l' sets the level of module m to l and
+//!    'log m default' makes module m use the default level again
+//!
+//! The command line can be edited with the left and right arrow keys, home,
+//! end, backspace and delete, and ctrl-a, ctrl-e and ctrl-u. The up and down
+//! arrow keys recall the last commands.
 //!
 //! Setup
 //! -----
@@ -30,20 +47,35 @@
 //!                  &mut console::WRITE_BUF,
 //!                  &mut console::READ_BUF,
 //!                  &mut console::COMMAND_BUF,
+//!                  &mut console::HISTORY_BUF,
 //!                  kernel,
 //!                  Capability);
 //! hil::uart::UART::set_client(&usart::USART0, pconsole);
+//! pconsole.set_reset_function(reboot);
 //!
 //! pconsole.initialize();
 //! pconsole.start();
 //! ```
 //!
+//! where `reboot` is a function of the board that resets it:
+//!
+//! ```rust
+//! fn reboot() -> ! {
+//!     unsafe {
+//!         cortexm4::scb::reset();
+//!     }
+//!     loop {}
+//! }
+//! ```
+//!
 //! Buffer use and output
 //! ---------------------
 //! `ProcessConsole` does not use its own write buffer for output:
 //! it uses the debug!() buffer, so as not to repeat all of its buffering and
 //! to maintain a correct ordering with debug!() calls. The write buffer of
-//! `ProcessConsole` is used solely for echoing what someone types.
+//! `ProcessConsole` is used solely for echoing what someone types. If an echo
+//! cannot be sent because the UART is busy, the console redraws the whole
+//! command line once the UART is done.
 //!
 //! Using ProcessConsole
 //! --------------------
@@ -83,7 +115,45 @@
 //! Timeslice expirations: 0
 //! ```
 //!
-//! and you can control processes with the `start` and `stop` commands:
+//! To see which processes use the CPU, use the top command. The CPU column is
+//! the share of the CPU time measured over the last one to two windows of the
+//! kernel's CPU time statistics, and the other columns are totals since the
+//! processes were loaded. Without a cycle counter, the kernel measures the
+//! time of processes in microseconds and cannot measure its own time.
+//!
+//! ```text
+//! top
+//!  PID    Name                  CPU     Userspace     Syscalls  (cycles)
+//!   00    blink                 0.4%       211053        99862
+//!   01    c_hello               0.0%         8710         3011
+//!         kernel               99.6%
+//! ```
+//!
+//! The grants command shows the size of the grant region of each process,
+//! how many bytes of it the grants use, and the bytes of each grant the
+//! process uses by grant number:
+//!
+//! ```text
+//! grants
+//!  PID    Name                Region  Grants  Bytes per grant
+//!   00    blink                  532     148  0:24 3:124
+//!   01    c_hello                476      96  0:96
+//! ```
+//!
+//! To look at the memory of a process, print its memory ranges with `dump`
+//! and then dump an address range:
+//!
+//! ```text
+//! dump blink
+//! RAM    0x20004000-0x20006000, grant region from 0x20005dec
+//! Flash  0x00030000-0x00030800
+//! dump blink 20004000 32
+//! 20004000  00 00 00 00 18 45 00 20  00 00 00 00 00 00 00 00  |.....E. ........|
+//! 20004010  68 65 6c 6c 6f 00 00 00  00 00 00 00 00 00 00 00  |hello...........|
+//! ```
+//!
+//! and you can control processes with the `start`, `stop`, `restart` and
+//! `terminate` commands:
 //!
 //! ```text
 //! stop blink
@@ -92,24 +162,51 @@
 
 use core::cell::Cell;
 use core::cmp;
+use core::fmt;
 use core::str;
 use kernel::capabilities::ProcessManagementCapability;
-use kernel::common::cells::TakeCell;
+use kernel::common::cells::{OptionalCell, TakeCell};
 use kernel::debug;
 use kernel::hil::uart;
-use kernel::introspection::KernelInfo;
+use kernel::introspection::{CpuTimeUnit, KernelInfo};
+use kernel::procs::ProcessType;
 use kernel::Kernel;
 use kernel::ReturnCode;
 
-// Since writes are character echoes, we do not need more than 4 bytes:
-// the longest write is 3 bytes for a backspace (backspace, space, backspace).
-pub static mut WRITE_BUF: [u8; 4] = [0; 4];
+// Most writes are character echoes, but editing in the middle of the command
+// redraws the whole line: a carriage return, the command, an erase to the end
+// of the line and a cursor movement back to the edit.
+pub static mut WRITE_BUF: [u8; 80] = [0; 80];
 // Since reads are byte-by-byte, to properly echo what's typed,
 // we can use a very small read buffer.
 pub static mut READ_BUF: [u8; 4] = [0; 4];
-// Commands can be up to 32 bytes long: since commands themselves are 4-5
-// characters, limiting arguments to 25 bytes or so seems fine for now.
-pub static mut COMMAND_BUF: [u8; 32] = [0; 32];
+// Commands can be up to 64 bytes long, enough for a process name and the
+// address and length arguments of `dump`.
+pub static mut COMMAND_BUF: [u8; 64] = [0; 64];
+// The history keeps the last commands in slots as long as the command buffer,
+// so this holds 4 commands.
+pub static mut HISTORY_BUF: [u8; 256] = [0; 256];
+
+const COMMANDS: &str = "help status list top queue grants interrupts dump \
+                        stop start restart terminate fault reboot log";
+
+// How many bytes `dump` prints by default, and at most so that the output fits
+// in the debug buffer.
+const DEFAULT_DUMP_LENGTH: usize = 64;
+const MAX_DUMP_LENGTH: usize = 128;
+
+/// How far the console got through a terminal escape sequence, which the
+/// arrow and editing keys send.
+#[derive(Copy, Clone, PartialEq)]
+enum Escape {
+    None,
+    /// Received ESC.
+    Start,
+    /// Received ESC and `[` or `O`.
+    Csi,
+    /// Received ESC, `[` and a digit, which a `~` ends.
+    Parameter(u8),
+}
 
 pub struct ProcessConsole<'a, C: ProcessManagementCapability> {
     uart: &'a dyn uart::UartData<'a>,
@@ -118,7 +215,27 @@ pub struct ProcessConsole<'a, C: ProcessManagementCapability> {
     rx_in_progress: Cell<bool>,
     rx_buffer: TakeCell<'static, [u8]>,
     command_buffer: TakeCell<'static, [u8]>,
+    /// Length of the command in the command buffer.
     command_index: Cell<usize>,
+    /// Position of the cursor in the command.
+    cursor: Cell<usize>,
+    escape: Cell<Escape>,
+    /// An echo was lost because the UART was busy, so the command line has to
+    /// be redrawn.
+    redraw_pending: Cell<bool>,
+    /// The line break echoed for a command was lost because the UART was busy.
+    newline_pending: Cell<bool>,
+    history_buffer: TakeCell<'static, [u8]>,
+    /// Size of each slot of the history, the size of the command buffer.
+    history_slot_size: usize,
+    /// How many commands the history holds.
+    history_count: Cell<usize>,
+    /// Slot of the most recent command in the history.
+    history_newest: Cell<usize>,
+    /// Which command of the history the command line shows, counting back from
+    /// the most recent one, or `None` for a new command.
+    history_position: Cell<Option<usize>>,
+    reset_function: OptionalCell<fn() -> !>,
     running: Cell<bool>,
     kernel: &'static Kernel,
     capability: C,
@@ -130,6 +247,7 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
         tx_buffer: &'static mut [u8],
         rx_buffer: &'static mut [u8],
         cmd_buffer: &'static mut [u8],
+        history_buffer: &'static mut [u8],
         kernel: &'static Kernel,
         capability: C,
     ) -> ProcessConsole<'a, C> {
@@ -139,14 +257,29 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
             tx_buffer: TakeCell::new(tx_buffer),
             rx_in_progress: Cell::new(false),
             rx_buffer: TakeCell::new(rx_buffer),
+            history_slot_size: cmd_buffer.len(),
             command_buffer: TakeCell::new(cmd_buffer),
             command_index: Cell::new(0),
+            cursor: Cell::new(0),
+            escape: Cell::new(Escape::None),
+            redraw_pending: Cell::new(false),
+            newline_pending: Cell::new(false),
+            history_buffer: TakeCell::new(history_buffer),
+            history_count: Cell::new(0),
+            history_newest: Cell::new(0),
+            history_position: Cell::new(None),
+            reset_function: OptionalCell::empty(),
             running: Cell::new(false),
             kernel: kernel,
             capability: capability,
         }
     }
 
+    /// Let the `reboot` command reset the board with `reset`.
+    pub fn set_reset_function(&self, reset: fn() -> !) {
+        self.reset_function.set(reset);
+    }
+
     pub fn start(&self) -> ReturnCode {
         if self.running.get() == false {
             self.rx_buffer.take().map(|buffer| {
@@ -162,124 +295,574 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
     // Process the command in the command buffer and clear the buffer.
     fn read_command(&self) {
         self.command_buffer.map(|command| {
-            let mut terminator = 0;
-            let len = command.len();
-            for i in 0..len {
-                if command[i] == 0 {
-                    terminator = i;
-                    break;
-                }
-            }
-            //debug!("Command: {}-{} {:?}", start, terminator, command);
-            // A command is valid only if it starts inside the buffer,
-            // ends before the beginning of the buffer, and ends after
-            // it starts.
+            let terminator = self.command_index.get();
             if terminator > 0 {
                 let cmd_str = str::from_utf8(&command[0..terminator]);
                 match cmd_str {
                     Ok(s) => {
                         let clean_str = s.trim();
-                        if clean_str.starts_with("help") {
-                            debug!("Welcome to the process console.");
-                            debug!("Valid commands are: help status list stop start");
-                        } else if clean_str.starts_with("start") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel.process_each_capability(
-                                    &self.capability,
-                                    |_i, proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.resume();
-                                            debug!("Process {} resumed.", name);
-                                        }
-                                    },
+                        self.save_history(clean_str.as_bytes());
+                        let mut arguments = clean_str.split_whitespace();
+                        match arguments.next() {
+                            Some("help") => {
+                                debug!("Welcome to the process console.");
+                                debug!("Valid commands are: {}", COMMANDS);
+                            }
+                            Some("start") => self.with_process(arguments.next(), |proc| {
+                                proc.resume();
+                                debug!("Process {} resumed.", proc.get_process_name());
+                            }),
+                            Some("stop") => self.with_process(arguments.next(), |proc| {
+                                proc.stop();
+                                debug!("Process {} stopped", proc.get_process_name());
+                            }),
+                            Some("restart") => self.with_process(arguments.next(), |proc| {
+                                proc.restart();
+                                debug!("Process {} restarted", proc.get_process_name());
+                            }),
+                            Some("terminate") => self.with_process(arguments.next(), |proc| {
+                                proc.terminate();
+                                debug!("Process {} terminated", proc.get_process_name());
+                            }),
+                            Some("fault") => self.with_process(arguments.next(), |proc| {
+                                proc.set_fault_state();
+                                debug!("Process {} now faulted", proc.get_process_name());
+                            }),
+                            Some("list") => {
+                                debug!(" PID    Name                Quanta  Syscalls  Dropped Callbacks    State");
+                                self.kernel
+                                    .process_each_capability(&self.capability, |i, proc| {
+                                        let pname = proc.get_process_name();
+                                        debug!(
+                                            "  {:02}\t{:<20}{:6}{:10}{:19}  {:?}",
+                                            i,
+                                            pname,
+                                            proc.debug_timeslice_expiration_count(),
+                                            proc.debug_syscall_count(),
+                                            proc.debug_dropped_callback_count(),
+                                            proc.get_state()
+                                        );
+                                    });
+                            }
+                            Some("top") => self.top(),
+                            Some("queue") => self.queue(),
+                            Some("grants") => self.grants(),
+                            Some("interrupts") => self.interrupts(),
+                            Some("dump") => {
+                                let name = arguments.next();
+                                let address = arguments.next();
+                                let length = arguments.next();
+                                self.dump(name, address, length);
+                            }
+                            Some("status") => {
+                                let info: KernelInfo = KernelInfo::new(self.kernel);
+                                debug!(
+                                    "Total processes: {}",
+                                    info.number_loaded_processes(&self.capability)
                                 );
-                            });
-                        } else if clean_str.starts_with("stop") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel.process_each_capability(
-                                    &self.capability,
-                                    |_i, proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.stop();
-                                            debug!("Process {} stopped", proc_name);
-                                        }
-                                    },
+                                debug!(
+                                    "Active processes: {}",
+                                    info.number_active_processes(&self.capability)
                                 );
-                            });
-                        } else if clean_str.starts_with("fault") {
-                            let argument = clean_str.split_whitespace().nth(1);
-                            argument.map(|name| {
-                                self.kernel.process_each_capability(
-                                    &self.capability,
-                                    |_i, proc| {
-                                        let proc_name = proc.get_process_name();
-                                        if proc_name == name {
-                                            proc.set_fault_state();
-                                            debug!("Process {} now faulted", proc_name);
-                                        }
-                                    },
+                                debug!(
+                                    "Timeslice expirations: {}",
+                                    info.timeslice_expirations(&self.capability)
                                 );
-                            });
-                        } else if clean_str.starts_with("list") {
-                            debug!(" PID    Name                Quanta  Syscalls  Dropped Callbacks    State");
-                            self.kernel
-                                .process_each_capability(&self.capability, |i, proc| {
-                                    let pname = proc.get_process_name();
-                                    debug!(
-                                        "  {:02}\t{:<20}{:6}{:10}{:19}  {:?}",
-                                        i,
-                                        pname,
-                                        proc.debug_timeslice_expiration_count(),
-                                        proc.debug_syscall_count(),
-                                        proc.debug_dropped_callback_count(),
-                                        proc.get_state()
-                                    );
-                                });
-                        } else if clean_str.starts_with("status") {
-                            let info: KernelInfo = KernelInfo::new(self.kernel);
-                            debug!(
-                                "Total processes: {}",
-                                info.number_loaded_processes(&self.capability)
-                            );
-                            debug!(
-                                "Active processes: {}",
-                                info.number_active_processes(&self.capability)
-                            );
+                            }
+                            Some("reboot") => self.reset_function.map_or_else(
+                                || debug!("This board cannot reboot from the console"),
+                                |reset| reset(),
+                            ),
+                            Some("log") => {
+                                let first = arguments.next();
+                                let second = arguments.next();
+                                self.log(first, second);
+                            }
+                            Some(_) => debug!("Valid commands are: {}", COMMANDS),
+                            None => {}
+                        }
+                    }
+                    Err(_e) => debug!("Invalid command: {:?}", command),
+                }
+            }
+            command[0] = 0;
+        });
+        self.command_index.set(0);
+        self.cursor.set(0);
+        self.history_position.set(None);
+    }
+
+    // Run `f` on the process named `name`.
+    fn with_process<F: Fn(&dyn ProcessType)>(&self, name: Option<&str>, f: F) {
+        let name = match name {
+            Some(name) => name,
+            None => {
+                debug!("Missing process name");
+                return;
+            }
+        };
+        let found = Cell::new(false);
+        self.kernel
+            .process_each_capability(&self.capability, |_i, proc| {
+                if proc.get_process_name() == name {
+                    found.set(true);
+                    f(proc);
+                }
+            });
+        if !found.get() {
+            debug!("No process named {}", name);
+        }
+    }
+
+    // Print the share of the CPU time of each process in the sliding window of
+    // the CPU time statistics.
+    fn top(&self) {
+        let info: KernelInfo = KernelInfo::new(self.kernel);
+        let total = info.cpu_window_time(&self.capability);
+        let unit = info.cpu_time_unit(&self.capability);
+        debug!(
+            " PID    Name                  CPU     Userspace     Syscalls  ({})",
+            match unit {
+                CpuTimeUnit::Cycles => "cycles",
+                CpuTimeUnit::Microseconds => "us",
+            }
+        );
+        let processes_time = Cell::new(0);
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| {
+                let window_time = proc.debug_cpu_window_time();
+                processes_time.set(processes_time.get() + window_time);
+                let share = per_mille(window_time, total);
+                debug!(
+                    "  {:02}\t{:<20}{:3}.{}%{:13}{:13}",
+                    i,
+                    proc.get_process_name(),
+                    share / 10,
+                    share % 10,
+                    proc.debug_cpu_time_userspace(),
+                    proc.debug_cpu_time_syscall()
+                );
+            });
+        // Only a cycle counter measures the time the kernel uses.
+        if unit == CpuTimeUnit::Cycles {
+            let share = per_mille(total.saturating_sub(processes_time.get()), total);
+            debug!("  \t{:<20}{:3}.{}%", "kernel", share / 10, share % 10);
+        }
+    }
+
+    // Print the tasks queued for each process and how many callbacks were
+    // dropped because its queue was full.
+    fn queue(&self) {
+        let info: KernelInfo = KernelInfo::new(self.kernel);
+        debug!(
+            "Pending tasks: {}",
+            info.number_pending_tasks(&self.capability)
+        );
+        debug!(" PID    Name                Queued  Dropped Callbacks    State");
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| {
+                debug!(
+                    "  {:02}\t{:<20}{:6}{:19}  {:?}",
+                    i,
+                    proc.get_process_name(),
+                    proc.debug_queued_task_count(),
+                    proc.debug_dropped_callback_count(),
+                    proc.get_state()
+                );
+            });
+    }
+
+    // Print how much of its grant region each process uses, in total and for
+    // each grant.
+    fn grants(&self) {
+        let info: KernelInfo = KernelInfo::new(self.kernel);
+        let number_grants = info.number_grants(&self.capability);
+        debug!(" PID    Name                Region  Grants  Bytes per grant");
+        self.kernel
+            .process_each_capability(&self.capability, |i, proc| {
+                let region = proc.mem_end() as usize - proc.kernel_memory_break() as usize;
+                let used: usize = (0..number_grants)
+                    .map(|grant| proc.debug_grant_bytes(grant).unwrap_or(0))
+                    .sum();
+                debug!(
+                    "  {:02}\t{:<20}{:6}{:8} {}",
+                    i,
+                    proc.get_process_name(),
+                    region,
+                    used,
+                    GrantUsage(proc, number_grants)
+                );
+                if let Some(grant) = proc.debug_failed_grant_alloc() {
+                    debug!("      \tOut of memory allocating {}", grant);
+                }
+            });
+    }
+
+    // Print how many times each interrupt that fired was serviced.
+    fn interrupts(&self) {
+        let info: KernelInfo = KernelInfo::new(self.kernel);
+        let number_interrupts = info.number_interrupts(&self.capability);
+        if number_interrupts == 0 {
+            debug!("This board does not count interrupts");
+            return;
+        }
+        debug!(" IRQ        Count");
+        for interrupt in 0..number_interrupts {
+            match info.interrupt_count(interrupt, &self.capability) {
+                Some(count) if count > 0 => debug!("  {:3}{:13}", interrupt, count),
+                _ => {}
+            }
+        }
+    }
+
+    // Print the log levels, or set the default level or the level of a module.
+    fn log(&self, first: Option<&str>, second: Option<&str>) {
+        match (first, second) {
+            (None, _) => {
+                debug!(
+                    "Compiled-in maximum: {}, default: {}",
+                    debug::MAX_LEVEL.name(),
+                    debug::log_level().name()
+                );
+                debug::each_module_log_level(|module, level| {
+                    debug!("  {:<24}{}", module, level.name());
+                });
+            }
+            (Some(level), None) => match debug::Level::from_name(level) {
+                Some(level) => {
+                    debug::set_log_level(level);
+                    debug!("Default log level is {}", level.name());
+                }
+                None => debug!("Invalid log level {}", level),
+            },
+            (Some(module), Some("default")) => match debug::clear_module_log_level(module) {
+                ReturnCode::SUCCESS => debug!("{} uses the default log level", module),
+                _ => debug!("{} does not have its own log level", module),
+            },
+            (Some(module), Some(level)) => match debug::Level::from_name(level) {
+                Some(level) => match debug::set_module_log_level(module, level) {
+                    ReturnCode::SUCCESS => {
+                        debug!("Log level of {} is {}", module, level.name());
+                        if level > debug::MAX_LEVEL {
                             debug!(
-                                "Timeslice expirations: {}",
-                                info.timeslice_expirations(&self.capability)
+                                "Messages above {} are compiled out",
+                                debug::MAX_LEVEL.name()
                             );
-                        } else {
-                            debug!("Valid commands are: help status list stop start fault");
                         }
                     }
-                    Err(_e) => debug!("Invalid command: {:?}", command),
+                    ReturnCode::ESIZE => debug!("Module name too long"),
+                    _ => debug!("Too many modules with their own log level"),
+                },
+                None => debug!("Invalid log level {}", level),
+            },
+        }
+    }
+
+    // Print the memory ranges of a process, or hexdump `length` bytes of its
+    // memory starting at `address`.
+    fn dump(&self, name: Option<&str>, address: Option<&str>, length: Option<&str>) {
+        let address = match address.map(|address| parse_number(address, 16)) {
+            None => None,
+            Some(Some(address)) => Some(address),
+            Some(None) => {
+                debug!("Invalid address");
+                return;
+            }
+        };
+        let length = match length.map(|length| parse_number(length, 10)) {
+            None => DEFAULT_DUMP_LENGTH,
+            Some(Some(length)) => cmp::min(length, MAX_DUMP_LENGTH),
+            Some(None) => {
+                debug!("Invalid length");
+                return;
+            }
+        };
+        self.with_process(name, |proc| match address {
+            None => {
+                debug!(
+                    "RAM    {:#010x}-{:#010x}, grant region from {:#010x}",
+                    proc.mem_start() as usize,
+                    proc.mem_end() as usize,
+                    proc.kernel_memory_break() as usize
+                );
+                debug!(
+                    "Flash  {:#010x}-{:#010x}",
+                    proc.flash_start() as usize,
+                    proc.flash_end() as usize
+                );
+            }
+            Some(address) => {
+                let mut offset = 0;
+                while offset < length {
+                    let line_address = address.wrapping_add(offset);
+                    let mut line = [0; 16];
+                    let line_length = cmp::min(line.len(), length - offset);
+                    let line = &mut line[..line_length];
+                    if proc.debugger_read_memory(line_address, line) != ReturnCode::SUCCESS {
+                        debug!(
+                            "{:#010x} is not in the RAM or flash of process {}",
+                            line_address,
+                            proc.get_process_name()
+                        );
+                        break;
+                    }
+                    debug!("{:08x}  {}", line_address, HexLine(line));
+                    offset += line_length;
+                }
+            }
+        });
+    }
+
+    // Handle a byte typed on the terminal. Returns whether the command is
+    // complete.
+    fn handle_byte(&self, byte: u8) -> bool {
+        match self.escape.get() {
+            Escape::Start => {
+                if byte == b'[' || byte == b'O' {
+                    self.escape.set(Escape::Csi);
+                } else {
+                    self.escape.set(Escape::None);
+                }
+            }
+            Escape::Csi => {
+                self.escape.set(Escape::None);
+                match byte {
+                    b'A' => self.history_previous(),
+                    b'B' => self.history_next(),
+                    b'C' => self.move_cursor(self.cursor.get() + 1),
+                    b'D' => {
+                        if self.cursor.get() > 0 {
+                            self.move_cursor(self.cursor.get() - 1);
+                        }
+                    }
+                    b'H' => self.move_cursor(0),
+                    b'F' => self.move_cursor(self.command_index.get()),
+                    b'0'..=b'9' => self.escape.set(Escape::Parameter(byte)),
+                    _ => {}
+                }
+            }
+            Escape::Parameter(parameter) => {
+                self.escape.set(Escape::None);
+                if byte == b'~' {
+                    match parameter {
+                        b'1' | b'7' => self.move_cursor(0),
+                        b'4' | b'8' => self.move_cursor(self.command_index.get()),
+                        b'3' => self.delete(self.cursor.get()),
+                        _ => {}
+                    }
+                }
+            }
+            Escape::None => match byte {
+                b'\n' | b'\r' => {
+                    self.echo_newline();
+                    return true;
                 }
+                // Backspace, which terminals send as either BS or DEL.
+                0x08 | 0x7f => {
+                    if self.cursor.get() > 0 {
+                        self.delete(self.cursor.get() - 1);
+                    }
+                }
+                // Ctrl-a and ctrl-e move to the start and the end of the line.
+                0x01 => self.move_cursor(0),
+                0x05 => self.move_cursor(self.command_index.get()),
+                // Ctrl-u clears the line.
+                0x15 => {
+                    self.command_buffer.map(|command| command[0] = 0);
+                    self.command_index.set(0);
+                    self.cursor.set(0);
+                    self.redraw();
+                }
+                0x1b => self.escape.set(Escape::Start),
+                // For some reason, sometimes reads return > 127 but no error,
+                // which causes utf-8 decoding failure, so only store printable
+                // ASCII. -pal
+                0x20..=0x7e => self.insert(byte),
+                _ => {}
+            },
+        }
+        false
+    }
+
+    // Insert `byte` into the command at the cursor.
+    fn insert(&self, byte: u8) {
+        let length = self.command_index.get();
+        let cursor = self.cursor.get();
+        let inserted = self.command_buffer.map_or(false, |command| {
+            if length + 1 >= command.len() {
+                return false;
             }
+            for i in (cursor..length).rev() {
+                command[i + 1] = command[i];
+            }
+            command[cursor] = byte;
+            command[length + 1] = 0;
+            true
         });
+        if inserted {
+            self.command_index.set(length + 1);
+            self.cursor.set(cursor + 1);
+            if cursor == length {
+                self.echo(&[byte]);
+            } else {
+                self.redraw();
+            }
+        }
+    }
+
+    // Remove the byte at `position` from the command.
+    fn delete(&self, position: usize) {
+        let length = self.command_index.get();
+        let cursor = self.cursor.get();
+        if position >= length {
+            return;
+        }
         self.command_buffer.map(|command| {
-            command[0] = 0;
+            for i in position..length {
+                command[i] = command[i + 1];
+            }
         });
-        self.command_index.set(0);
+        self.command_index.set(length - 1);
+        if position < cursor {
+            self.cursor.set(cursor - 1);
+        }
+        if position + 1 == length && cursor == length {
+            // Backspace at the end of the line, erase it with '\b \b'.
+            self.echo(&[0x08, b' ', 0x08]);
+        } else {
+            self.redraw();
+        }
     }
 
-    fn write_byte(&self, byte: u8) -> ReturnCode {
-        if self.tx_in_progress.get() {
-            ReturnCode::EBUSY
+    fn move_cursor(&self, position: usize) {
+        let cursor = self.cursor.get();
+        if position > self.command_index.get() || position == cursor {
+            return;
+        }
+        self.cursor.set(position);
+        if position + 1 == cursor {
+            self.echo(b"\x1b[D");
+        } else if position == cursor + 1 {
+            self.echo(b"\x1b[C");
         } else {
-            self.tx_in_progress.set(true);
-            self.tx_buffer.take().map(|buffer| {
-                buffer[0] = byte;
-                self.uart.transmit_buffer(buffer, 1);
+            self.redraw();
+        }
+    }
+
+    // Save a command as the most recent one in the history, unless it repeats
+    // the most recent one.
+    fn save_history(&self, line: &[u8]) {
+        let slot_size = self.history_slot_size;
+        if line.is_empty() || line.len() >= slot_size {
+            return;
+        }
+        self.history_buffer.map(|history| {
+            let slots = history.len() / slot_size;
+            if slots == 0 {
+                return;
+            }
+            let newest = self.history_newest.get();
+            let count = self.history_count.get();
+            if count > 0 && history_line(history, slot_size, newest) == line {
+                return;
+            }
+            let slot = if count == 0 { 0 } else { (newest + 1) % slots };
+            let start = slot * slot_size;
+            history[start..start + line.len()].copy_from_slice(line);
+            history[start + line.len()] = 0;
+            self.history_newest.set(slot);
+            self.history_count.set(cmp::min(count + 1, slots));
+        });
+    }
+
+    // Replace the command with the command at `position` in the history, or
+    // with an empty command for `None`.
+    fn recall_history(&self, position: Option<usize>) {
+        let slot_size = self.history_slot_size;
+        let newest = self.history_newest.get();
+        let length = self.command_buffer.map_or(0, |command| {
+            let length = position.map_or(0, |position| {
+                self.history_buffer.map_or(0, |history| {
+                    let slots = history.len() / slot_size;
+                    let slot = (newest + slots - position) % slots;
+                    let line = history_line(history, slot_size, slot);
+                    command[..line.len()].copy_from_slice(line);
+                    line.len()
+                })
             });
-            ReturnCode::SUCCESS
+            command[length] = 0;
+            length
+        });
+        self.history_position.set(position);
+        self.command_index.set(length);
+        self.cursor.set(length);
+        self.redraw();
+    }
+
+    fn history_previous(&self) {
+        let position = self
+            .history_position
+            .get()
+            .map_or(0, |position| position + 1);
+        if position < self.history_count.get() {
+            self.recall_history(Some(position));
+        }
+    }
+
+    fn history_next(&self) {
+        match self.history_position.get() {
+            None => {}
+            Some(0) => self.recall_history(None),
+            Some(position) => self.recall_history(Some(position - 1)),
+        }
+    }
+
+    // Echo `bytes`, or redraw the command line later if the UART is busy.
+    fn echo(&self, bytes: &[u8]) {
+        if self.redraw_pending.get()
+            || self.newline_pending.get()
+            || self.write_bytes(bytes) != ReturnCode::SUCCESS
+        {
+            self.redraw_pending.set(true);
+        }
+    }
+
+    fn echo_newline(&self) {
+        // The line is done, so there is nothing left to redraw.
+        self.redraw_pending.set(false);
+        if self.write_bytes(b"\r\n") != ReturnCode::SUCCESS {
+            self.newline_pending.set(true);
         }
     }
 
+    // Rewrite the whole command line and put the cursor back in place.
+    fn redraw(&self) {
+        if self.tx_in_progress.get() || self.newline_pending.get() {
+            self.redraw_pending.set(true);
+            return;
+        }
+        self.redraw_pending.set(false);
+        let length = self.command_index.get();
+        let back = length - self.cursor.get();
+        self.command_buffer.map(|command| {
+            self.tx_buffer.take().map(|buffer| {
+                // Leave room for the escape sequences.
+                let length = cmp::min(length, buffer.len() - 12);
+                let mut index = 0;
+                index += copy_bytes(&mut buffer[index..], b"\r");
+                index += copy_bytes(&mut buffer[index..], &command[..length]);
+                index += copy_bytes(&mut buffer[index..], b"\x1b[K");
+                if back > 0 {
+                    index += copy_bytes(&mut buffer[index..], b"\x1b[");
+                    index += write_decimal(&mut buffer[index..], back);
+                    index += copy_bytes(&mut buffer[index..], b"D");
+                }
+                self.tx_in_progress.set(true);
+                self.uart.transmit_buffer(buffer, index);
+            });
+        });
+    }
+
     fn write_bytes(&self, bytes: &[u8]) -> ReturnCode {
         if self.tx_in_progress.get() {
             ReturnCode::EBUSY
@@ -299,10 +882,15 @@ impl<'a, C: ProcessManagementCapability> ProcessConsole<'a, C> {
 
 impl<'a, C: ProcessManagementCapability> uart::TransmitClient for ProcessConsole<'a, C> {
     fn transmitted_buffer(&self, buffer: &'static mut [u8], _tx_len: usize, _rcode: ReturnCode) {
-        // Either print more from the AppSlice or send a callback to the
-        // application.
         self.tx_buffer.replace(buffer);
         self.tx_in_progress.set(false);
+        // Catch up on echoes that were lost while the UART was busy.
+        if self.newline_pending.get() {
+            self.newline_pending.set(false);
+            self.write_bytes(b"\r\n");
+        } else if self.redraw_pending.get() {
+            self.redraw();
+        }
     }
 }
 impl<'a, C: ProcessManagementCapability> uart::ReceiveClient for ProcessConsole<'a, C> {
@@ -317,30 +905,7 @@ impl<'a, C: ProcessManagementCapability> uart::ReceiveClient for ProcessConsole<
         if error == uart::Error::None {
             match rx_len {
                 0 => debug!("ProcessConsole had read of 0 bytes"),
-                1 => {
-                    self.command_buffer.map(|command| {
-                        let index = self.command_index.get() as usize;
-                        if read_buf[0] == ('\n' as u8) || read_buf[0] == ('\r' as u8) {
-                            execute = true;
-                            self.write_bytes(&['\r' as u8, '\n' as u8]);
-                        } else if read_buf[0] == ('\x08' as u8) && index > 0 {
-                            // Backspace, echo and remove last byte
-                            // Note echo is '\b \b' to erase
-                            self.write_bytes(&['\x08' as u8, ' ' as u8, '\x08' as u8]);
-                            command[index - 1] = '\0' as u8;
-                            self.command_index.set(index - 1);
-                        } else if index < (command.len() - 1) && read_buf[0] < 128 {
-                            // For some reason, sometimes reads return > 127 but no error,
-                            // which causes utf-8 decoding failure, so check byte is < 128. -pal
-
-                            // Echo the byte and store it
-                            self.write_byte(read_buf[0]);
-                            command[index] = read_buf[0];
-                            self.command_index.set(index + 1);
-                            command[index + 1] = 0;
-                        }
-                    });
-                }
+                1 => execute = self.handle_byte(read_buf[0]),
                 _ => debug!(
                     "ProcessConsole issues reads of 1 byte, but receive_complete was length {}",
                     rx_len
@@ -354,3 +919,98 @@ impl<'a, C: ProcessManagementCapability> uart::ReceiveClient for ProcessConsole<
         }
     }
 }
+
+// Formats up to 16 bytes as a line of a hexdump: the bytes in hex and then as
+// ASCII characters.
+struct HexLine<'a>(&'a [u8]);
+
+impl fmt::Display for HexLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for i in 0..16 {
+            if i == 8 {
+                write!(f, " ")?;
+            }
+            match self.0.get(i) {
+                Some(byte) => write!(f, "{:02x} ", byte)?,
+                None => write!(f, "   ")?,
+            }
+        }
+        write!(f, " |")?;
+        for &byte in self.0 {
+            let c = if byte >= 0x20 && byte < 0x7f {
+                byte as char
+            } else {
+                '.'
+            };
+            write!(f, "{}", c)?;
+        }
+        write!(f, "|")
+    }
+}
+
+// Formats the bytes each grant a process uses takes in its grant region, as
+// `grant:bytes` pairs.
+struct GrantUsage<'a>(&'a dyn ProcessType, usize);
+
+impl fmt::Display for GrantUsage<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut separator = "";
+        for grant in 0..self.1 {
+            match self.0.debug_grant_bytes(grant) {
+                Some(bytes) if bytes > 0 => {
+                    write!(f, "{}{}:{}", separator, grant, bytes)?;
+                    separator = " ";
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+// Returns the command in slot `slot` of the history.
+fn history_line(history: &[u8], slot_size: usize, slot: usize) -> &[u8] {
+    let entry = &history[slot * slot_size..(slot + 1) * slot_size];
+    let length = entry
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(slot_size);
+    &entry[..length]
+}
+
+// Parses a number in the given radix, or in hex with a `0x` prefix.
+fn parse_number(text: &str, radix: u32) -> Option<usize> {
+    if text.starts_with("0x") {
+        usize::from_str_radix(&text[2..], 16).ok()
+    } else {
+        usize::from_str_radix(text, radix).ok()
+    }
+}
+
+// Copies `bytes` to the start of `buffer` and returns how many bytes it copied.
+fn copy_bytes(buffer: &mut [u8], bytes: &[u8]) -> usize {
+    buffer[..bytes.len()].copy_from_slice(bytes);
+    bytes.len()
+}
+
+// Writes `value` in decimal to the start of `buffer` and returns how many
+// digits it wrote.
+fn write_decimal(buffer: &mut [u8], value: usize) -> usize {
+    let mut digits = 1;
+    while value / 10usize.pow(digits as u32) > 0 {
+        digits += 1;
+    }
+    for i in 0..digits {
+        buffer[digits - 1 - i] = b'0' + (value / 10usize.pow(i as u32) % 10) as u8;
+    }
+    digits
+}
+
+// Returns `part` in thousandths of `total`.
+fn per_mille(part: u64, total: u64) -> u64 {
+    if total == 0 {
+        0
+    } else {
+        cmp::min(part * 1000 / total, 1000)
+    }
+}
@@ -0,0 +1,237 @@
+//! Provides a privileged userspace app with information about the processes
+//! on the board and kernel statistics.
+//!
+//! This exposes the same information as `kernel::introspection::KernelInfo`
+//! through the system call interface, so that a monitoring app can report
+//! the health of the system (for example over the network) instead of a
+//! developer having to read it from the UART console.
+//!
+//! Because this leaks information about every process on the board, access is
+//! gated twice. First, the board must pass a `ProcessManagementCapability` to
+//! create the driver. Second, only the apps whose package names are listed
+//! when the driver is created may use it. All other apps get `ENOSUPPORT`, as
+//! if the driver did not exist.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! struct ProcessInfoCapability;
+//! unsafe impl capabilities::ProcessManagementCapability for ProcessInfoCapability {}
+//!
+//! let process_info = static_init!(
+//!     capsules::process_info::ProcessInfo<ProcessInfoCapability>,
+//!     capsules::process_info::ProcessInfo::new(
+//!         board_kernel,
+//!         board_kernel.create_grant(&grant_cap),
+//!         &["monitor"],
+//!         ProcessInfoCapability,
+//!     )
+//! );
+//! ```
+//!
+//! Process Records
+//! ---------------
+//!
+//! Command 6 copies a record describing a single process into the buffer
+//! shared with allow 0. All fields are little-endian 32 bit words:
+//!
+//! ```text
+//! 0x00  state (see `state_to_number()`)
+//! 0x04  RAM start address
+//! 0x08  RAM end address
+//! 0x0C  kernel memory break (start of the grant region)
+//! 0x10  flash start address
+//! 0x14  flash end address
+//! 0x18  syscall count
+//! 0x1C  dropped callback count
+//! 0x20  restart count
+//! 0x24  timeslice expiration count
+//! 0x28  length of the package name
+//! 0x2C  package name, truncated to fit in the buffer
+//! ```
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::introspection::KernelInfo;
+use kernel::procs::State;
+use kernel::{AppId, AppSlice, Driver, Grant, Kernel, ReturnCode, Shared};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::ProcessInfo as usize;
+
+/// Size of the fixed part of a process record, before the package name.
+pub const RECORD_HEADER_LEN: usize = 44;
+
+#[derive(Default)]
+pub struct App {
+    buffer: Option<AppSlice<Shared, u8>>,
+}
+
+pub struct ProcessInfo<C: ProcessManagementCapability> {
+    kernel_info: KernelInfo,
+    apps: Grant<App>,
+    privileged_apps: &'static [&'static str],
+    capability: C,
+}
+
+impl<C: ProcessManagementCapability> ProcessInfo<C> {
+    pub fn new(
+        kernel: &'static Kernel,
+        grant: Grant<App>,
+        privileged_apps: &'static [&'static str],
+        capability: C,
+    ) -> ProcessInfo<C> {
+        ProcessInfo {
+            kernel_info: KernelInfo::new(kernel),
+            apps: grant,
+            privileged_apps: privileged_apps,
+            capability: capability,
+        }
+    }
+
+    /// Only apps the board explicitly listed may use this driver.
+    fn is_privileged(&self, appid: AppId) -> bool {
+        let name = self.kernel_info.process_name(appid, &self.capability);
+        self.privileged_apps.iter().any(|allowed| *allowed == name)
+    }
+
+    /// Copy the record for the process in slot `index` into `buffer`. Returns
+    /// the number of bytes written.
+    fn fill_record(&self, index: usize, buffer: &mut [u8]) -> ReturnCode {
+        if buffer.len() < RECORD_HEADER_LEN {
+            return ReturnCode::ESIZE;
+        }
+
+        let cap = &self.capability;
+        self.kernel_info
+            .process_appid(index, cap)
+            .map_or(ReturnCode::EINVAL, |app| {
+                let info = &self.kernel_info;
+                let name = info.process_name(app, cap).as_bytes();
+                let fields = [
+                    info.process_state(app, cap).map_or(0, state_to_number),
+                    info.process_mem_start(app, cap) as usize,
+                    info.process_mem_end(app, cap) as usize,
+                    info.process_kernel_memory_break(app, cap) as usize,
+                    info.process_flash_start(app, cap) as usize,
+                    info.process_flash_end(app, cap) as usize,
+                    info.number_app_syscalls(app, cap),
+                    info.number_app_dropped_callbacks(app, cap),
+                    info.number_app_restarts(app, cap),
+                    info.number_app_timeslice_expirations(app, cap),
+                    name.len(),
+                ];
+                for (i, field) in fields.iter().enumerate() {
+                    write_u32(&mut buffer[i * 4..i * 4 + 4], *field as u32);
+                }
+
+                let name_len = core::cmp::min(name.len(), buffer.len() - RECORD_HEADER_LEN);
+                buffer[RECORD_HEADER_LEN..RECORD_HEADER_LEN + name_len]
+                    .copy_from_slice(&name[..name_len]);
+
+                ReturnCode::SuccessWithValue {
+                    value: RECORD_HEADER_LEN + name_len,
+                }
+            })
+    }
+}
+
+/// Numbering of process states as seen by userspace.
+fn state_to_number(state: State) -> usize {
+    match state {
+        State::Running => 1,
+        State::Yielded => 2,
+        State::StoppedRunning => 3,
+        State::StoppedYielded => 4,
+        State::StoppedFaulted => 5,
+        State::Fault => 6,
+        State::Unstarted => 7,
+        State::Terminated => 8,
+    }
+}
+
+fn write_u32(buf: &mut [u8], value: u32) {
+    buf[0] = value as u8;
+    buf[1] = (value >> 8) as u8;
+    buf[2] = (value >> 16) as u8;
+    buf[3] = (value >> 24) as u8;
+}
+
+impl<C: ProcessManagementCapability> Driver for ProcessInfo<C> {
+    /// Setup shared buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Buffer that process records are copied into.
+    fn allow(
+        &self,
+        appid: AppId,
+        allow_num: usize,
+        slice: Option<AppSlice<Shared, u8>>,
+    ) -> ReturnCode {
+        if !self.is_privileged(appid) {
+            return ReturnCode::ENOSUPPORT;
+        }
+
+        match allow_num {
+            0 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Query process and kernel state.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check. Fails for apps that are not privileged.
+    /// - `1`: Number of process slots on the board.
+    /// - `2`: Number of loaded processes.
+    /// - `3`: Number of active (running or yielded) processes.
+    /// - `4`: Number of inactive processes.
+    /// - `5`: Total number of timeslice expirations across all processes.
+    /// - `6`: Copy the record for the process in slot `data` into the buffer
+    ///        shared with allow 0. Returns the number of bytes written,
+    ///        `EINVAL` if the slot is empty and `ESIZE` if the buffer cannot
+    ///        hold the fixed part of the record.
+    fn command(&self, command_num: usize, data: usize, _: usize, appid: AppId) -> ReturnCode {
+        if !self.is_privileged(appid) {
+            return ReturnCode::ENOSUPPORT;
+        }
+
+        let cap = &self.capability;
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => ReturnCode::SuccessWithValue {
+                value: self.kernel_info.number_process_slots(cap),
+            },
+            2 => ReturnCode::SuccessWithValue {
+                value: self.kernel_info.number_loaded_processes(cap),
+            },
+            3 => ReturnCode::SuccessWithValue {
+                value: self.kernel_info.number_active_processes(cap),
+            },
+            4 => ReturnCode::SuccessWithValue {
+                value: self.kernel_info.number_inactive_processes(cap),
+            },
+            5 => ReturnCode::SuccessWithValue {
+                value: self.kernel_info.timeslice_expirations(cap),
+            },
+            6 => self
+                .apps
+                .enter(appid, |app, _| {
+                    app.buffer.as_mut().map_or(ReturnCode::ERESERVE, |buffer| {
+                        self.fill_record(data, buffer.as_mut())
+                    })
+                })
+                .unwrap_or_else(|err| err.into()),
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
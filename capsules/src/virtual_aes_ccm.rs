@@ -0,0 +1,418 @@
+//! Virtualize an AES-CCM* engine.
+//!
+//! `MuxAES128CCM` provides shared access to a single `AES128CCM`
+//! implementation for multiple users, e.g. the 802.15.4 framer and MLE.
+//! `VirtualAES128CCM` keeps the key and nonce of one user, and queues its
+//! requests until the engine is free.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mux_aes_ccm = static_init!(
+//!     MuxAES128CCM<'static, AES128CCM<'static, sam4l::aes::Aes<'static>>>,
+//!     MuxAES128CCM::new(aes_ccm)
+//! );
+//! aes_ccm.set_client(mux_aes_ccm);
+//!
+//! let framer_aes_ccm = static_init!(
+//!     VirtualAES128CCM<'static, AES128CCM<'static, sam4l::aes::Aes<'static>>>,
+//!     VirtualAES128CCM::new(mux_aes_ccm)
+//! );
+//! ```
+
+use core::cell::Cell;
+use core::ptr;
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::{List, ListLink, ListNode};
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM, AES128_KEY_SIZE, CCM_NONCE_LENGTH};
+use kernel::ReturnCode;
+
+pub struct MuxAES128CCM<'a, A: AES128CCM<'a>> {
+    aes_ccm: &'a A,
+    users: List<'a, VirtualAES128CCM<'a, A>>,
+    enabled: Cell<usize>,
+    inflight: OptionalCell<&'a VirtualAES128CCM<'a, A>>,
+}
+
+impl<A: AES128CCM<'a>> MuxAES128CCM<'a, A> {
+    pub const fn new(aes_ccm: &'a A) -> MuxAES128CCM<'a, A> {
+        MuxAES128CCM {
+            aes_ccm,
+            users: List::new(),
+            enabled: Cell::new(0),
+            inflight: OptionalCell::empty(),
+        }
+    }
+
+    fn enable(&self) {
+        let enabled = self.enabled.get();
+        self.enabled.set(enabled + 1);
+        if enabled == 0 {
+            self.aes_ccm.enable();
+        }
+    }
+
+    fn disable(&self) {
+        let enabled = self.enabled.get();
+        self.enabled.set(enabled - 1);
+        if enabled == 1 {
+            self.aes_ccm.disable();
+        }
+    }
+
+    /// Starts the request of `user` with its key and nonce. Returns the
+    /// buffer if the engine did not accept it.
+    fn start(
+        &self,
+        user: &'a VirtualAES128CCM<'a, A>,
+        buf: &'static mut [u8],
+        op: Op,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.aes_ccm.set_key(&user.key.get()) != ReturnCode::SUCCESS
+            || self.aes_ccm.set_nonce(&user.nonce.get()) != ReturnCode::SUCCESS
+        {
+            return (ReturnCode::FAIL, Some(buf));
+        }
+        let (res, buf) = self.aes_ccm.crypt(
+            buf,
+            op.a_off,
+            op.m_off,
+            op.m_len,
+            op.mic_len,
+            op.confidential,
+            op.encrypting,
+        );
+        if res == ReturnCode::SUCCESS {
+            self.inflight.set(user);
+        }
+        (res, buf)
+    }
+
+    fn do_next_op(&self) {
+        while self.inflight.is_none() {
+            let user = match self.users.iter().find(|user| user.buffer.is_some()) {
+                Some(user) => user,
+                None => return,
+            };
+            if let Some(buf) = user.buffer.take() {
+                if let (res, Some(buf)) = self.start(user, buf, user.op.get()) {
+                    user.crypt_done(buf, res, false);
+                }
+            }
+        }
+    }
+}
+
+impl<A: AES128CCM<'a>> CCMClient for MuxAES128CCM<'a, A> {
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        if let Some(user) = self.inflight.take() {
+            user.crypt_done(buf, res, tag_is_valid);
+        }
+        self.do_next_op();
+    }
+}
+
+/// The arguments of a request to `AES128CCM::crypt`.
+#[derive(Copy, Clone)]
+struct Op {
+    a_off: usize,
+    m_off: usize,
+    m_len: usize,
+    mic_len: usize,
+    confidential: bool,
+    encrypting: bool,
+}
+
+pub struct VirtualAES128CCM<'a, A: AES128CCM<'a>> {
+    mux: &'a MuxAES128CCM<'a, A>,
+    enabled: Cell<bool>,
+    // Set while a request is queued or in progress
+    busy: Cell<bool>,
+    key: Cell<[u8; AES128_KEY_SIZE]>,
+    nonce: Cell<[u8; CCM_NONCE_LENGTH]>,
+    buffer: TakeCell<'static, [u8]>,
+    op: Cell<Op>,
+    next: ListLink<'a, VirtualAES128CCM<'a, A>>,
+    client: OptionalCell<&'a dyn CCMClient>,
+}
+
+impl<A: AES128CCM<'a>> VirtualAES128CCM<'a, A> {
+    pub const fn new(mux: &'a MuxAES128CCM<'a, A>) -> VirtualAES128CCM<'a, A> {
+        VirtualAES128CCM {
+            mux,
+            enabled: Cell::new(false),
+            busy: Cell::new(false),
+            key: Cell::new([0; AES128_KEY_SIZE]),
+            nonce: Cell::new([0; CCM_NONCE_LENGTH]),
+            buffer: TakeCell::empty(),
+            op: Cell::new(Op {
+                a_off: 0,
+                m_off: 0,
+                m_len: 0,
+                mic_len: 0,
+                confidential: false,
+                encrypting: false,
+            }),
+            next: ListLink::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn crypt_done(&self, buf: &'static mut [u8], res: ReturnCode, tag_is_valid: bool) {
+        self.busy.set(false);
+        self.client.map(move |client| {
+            client.crypt_done(buf, res, tag_is_valid);
+        });
+    }
+}
+
+impl<A: AES128CCM<'a>> ListNode<'a, VirtualAES128CCM<'a, A>> for VirtualAES128CCM<'a, A> {
+    fn next(&'a self) -> &'a ListLink<'a, VirtualAES128CCM<'a, A>> {
+        &self.next
+    }
+}
+
+impl<A: AES128CCM<'a>> AES128CCM<'a> for VirtualAES128CCM<'a, A> {
+    fn enable(&'a self) {
+        if !self.enabled.get() {
+            self.enabled.set(true);
+            self.mux.enable();
+        }
+    }
+
+    fn disable(&'a self) {
+        if self.enabled.get() {
+            self.enabled.set(false);
+            self.mux.disable();
+        }
+    }
+
+    fn set_client(&'a self, client: &'a dyn CCMClient) {
+        // The user is linked into the mux once, even if its client changes
+        if self.client.is_none() {
+            self.mux.users.push_head(self);
+        }
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> ReturnCode {
+        if key.len() != AES128_KEY_SIZE {
+            return ReturnCode::EINVAL;
+        }
+        let mut new_key = [0; AES128_KEY_SIZE];
+        new_key.copy_from_slice(key);
+        self.key.set(new_key);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_nonce(&self, nonce: &[u8]) -> ReturnCode {
+        if nonce.len() != CCM_NONCE_LENGTH {
+            return ReturnCode::EINVAL;
+        }
+        let mut new_nonce = [0; CCM_NONCE_LENGTH];
+        new_nonce.copy_from_slice(nonce);
+        self.nonce.set(new_nonce);
+        ReturnCode::SUCCESS
+    }
+
+    /// Starts the request right away if the engine is free, and queues it
+    /// otherwise. The key and nonce are those set when the request starts.
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        a_off: usize,
+        m_off: usize,
+        m_len: usize,
+        mic_len: usize,
+        confidential: bool,
+        encrypting: bool,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if self.busy.get() {
+            return (ReturnCode::EBUSY, Some(buf));
+        }
+        if !(a_off <= m_off && m_off + m_len + mic_len <= buf.len()) {
+            return (ReturnCode::EINVAL, Some(buf));
+        }
+
+        let op = Op {
+            a_off,
+            m_off,
+            m_len,
+            mic_len,
+            confidential,
+            encrypting,
+        };
+        // The mux keeps the users with the lifetime that the request in
+        // flight needs, from `set_client`
+        let user = match self.mux.users.iter().find(|user| ptr::eq(*user, self)) {
+            Some(user) => user,
+            None => return (ReturnCode::EOFF, Some(buf)),
+        };
+        if self.mux.inflight.is_none() {
+            let (res, buf) = self.mux.start(user, buf, op);
+            if res == ReturnCode::SUCCESS {
+                self.busy.set(true);
+            }
+            (res, buf)
+        } else {
+            self.buffer.replace(buf);
+            self.op.set(op);
+            self.busy.set(true);
+            (ReturnCode::SUCCESS, None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    // Keeps the buffer of the request in progress, and the first byte of the
+    // key of each request
+    struct Engine {
+        enabled: Cell<bool>,
+        key: Cell<u8>,
+        keys: Cell<Vec<u8>>,
+        buf: TakeCell<'static, [u8]>,
+    }
+
+    impl Engine {
+        fn new() -> Engine {
+            Engine {
+                enabled: Cell::new(false),
+                key: Cell::new(0),
+                keys: Cell::new(Vec::new()),
+                buf: TakeCell::empty(),
+            }
+        }
+    }
+
+    impl AES128CCM<'a> for Engine {
+        fn enable(&'a self) {
+            self.enabled.set(true);
+        }
+
+        fn disable(&'a self) {
+            self.enabled.set(false);
+        }
+
+        fn set_client(&'a self, _client: &'a dyn CCMClient) {}
+
+        fn set_key(&self, key: &[u8]) -> ReturnCode {
+            self.key.set(key[0]);
+            ReturnCode::SUCCESS
+        }
+
+        fn set_nonce(&self, _nonce: &[u8]) -> ReturnCode {
+            ReturnCode::SUCCESS
+        }
+
+        fn crypt(
+            &self,
+            buf: &'static mut [u8],
+            _a_off: usize,
+            _m_off: usize,
+            _m_len: usize,
+            _mic_len: usize,
+            _confidential: bool,
+            _encrypting: bool,
+        ) -> (ReturnCode, Option<&'static mut [u8]>) {
+            if self.buf.is_some() {
+                return (ReturnCode::EBUSY, Some(buf));
+            }
+            let mut keys = self.keys.take();
+            keys.push(self.key.get());
+            self.keys.set(keys);
+            self.buf.replace(buf);
+            (ReturnCode::SUCCESS, None)
+        }
+    }
+
+    #[derive(Default)]
+    struct Client {
+        done: Cell<usize>,
+    }
+
+    impl CCMClient for Client {
+        fn crypt_done(&self, _buf: &'static mut [u8], res: ReturnCode, _tag_is_valid: bool) {
+            assert_eq!(res, ReturnCode::SUCCESS);
+            self.done.set(self.done.get() + 1);
+        }
+    }
+
+    fn buf() -> &'static mut [u8] {
+        Box::leak(Box::new([0; 16]))
+    }
+
+    fn crypt(user: &VirtualAES128CCM<Engine>) -> ReturnCode {
+        user.crypt(buf(), 0, 4, 8, 4, true, true).0
+    }
+
+    #[test]
+    fn queues_requests_until_engine_is_free() {
+        let engine = Engine::new();
+        let mux = MuxAES128CCM::new(&engine);
+        let (a, b) = (VirtualAES128CCM::new(&mux), VirtualAES128CCM::new(&mux));
+        let (client_a, client_b) = (Client::default(), Client::default());
+        a.set_client(&client_a);
+        b.set_client(&client_b);
+        a.set_key(&[1; AES128_KEY_SIZE]);
+        b.set_key(&[2; AES128_KEY_SIZE]);
+
+        assert_eq!(crypt(&a), ReturnCode::SUCCESS);
+        assert_eq!(crypt(&b), ReturnCode::SUCCESS);
+        // Each user has one request at a time
+        assert_eq!(crypt(&b), ReturnCode::EBUSY);
+        assert_eq!(engine.keys.take(), [1]);
+
+        // The queued request starts with its own key once the first is done
+        mux.crypt_done(engine.buf.take().unwrap(), ReturnCode::SUCCESS, true);
+        assert_eq!(client_a.done.get(), 1);
+        assert_eq!(client_b.done.get(), 0);
+        assert_eq!(engine.keys.take(), [2]);
+
+        mux.crypt_done(engine.buf.take().unwrap(), ReturnCode::SUCCESS, true);
+        assert_eq!(client_b.done.get(), 1);
+        assert!(engine.buf.is_none());
+        assert_eq!(crypt(&b), ReturnCode::SUCCESS);
+    }
+
+    #[test]
+    fn engine_is_enabled_while_a_user_is() {
+        let engine = Engine::new();
+        let mux = MuxAES128CCM::new(&engine);
+        let (a, b) = (VirtualAES128CCM::new(&mux), VirtualAES128CCM::new(&mux));
+
+        a.enable();
+        a.enable();
+        b.enable();
+        assert!(engine.enabled.get());
+        a.disable();
+        assert!(engine.enabled.get());
+        b.disable();
+        assert!(!engine.enabled.get());
+    }
+
+    #[test]
+    fn users_join_once() {
+        let engine = Engine::new();
+        let mux = MuxAES128CCM::new(&engine);
+        let a = VirtualAES128CCM::new(&mux);
+        // Requests need a client to be handed back to
+        assert_eq!(crypt(&a), ReturnCode::EOFF);
+
+        let (first, second) = (Client::default(), Client::default());
+        a.set_client(&first);
+        a.set_client(&second);
+        assert_eq!(mux.users.iter().count(), 1);
+        assert_eq!(crypt(&a), ReturnCode::SUCCESS);
+        mux.crypt_done(engine.buf.take().unwrap(), ReturnCode::SUCCESS, true);
+        assert_eq!(first.done.get(), 0);
+        assert_eq!(second.done.get(), 1);
+    }
+}
@@ -0,0 +1,300 @@
+//! Virtualize a 32-bit entropy source.
+//!
+//! `MuxEntropy32` provides shared access to a single `Entropy32`
+//! implementation, such as a TRNG, for multiple users, e.g. the userspace RNG
+//! driver and MLE. Each `VirtualEntropy32` is an `Entropy32` of its own: the
+//! source runs while any user asks for entropy, and the entropy it produces
+//! is handed to the users that asked, in turn.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mux_entropy = static_init!(
+//!     MuxEntropy32<'static>,
+//!     MuxEntropy32::new(&sam4l::trng::TRNG)
+//! );
+//! kernel::hil::entropy::Entropy32::set_client(&sam4l::trng::TRNG, mux_entropy);
+//!
+//! let rng_entropy = static_init!(
+//!     VirtualEntropy32<'static>,
+//!     VirtualEntropy32::new(mux_entropy)
+//! );
+//! ```
+
+use core::cell::Cell;
+use kernel::common::cells::OptionalCell;
+use kernel::common::{List, ListLink, ListNode};
+use kernel::hil::entropy::{Client32, Continue, Entropy32};
+use kernel::ReturnCode;
+
+pub struct MuxEntropy32<'a> {
+    entropy: &'a dyn Entropy32<'a>,
+    users: List<'a, VirtualEntropy32<'a>>,
+    running: Cell<bool>,
+}
+
+impl MuxEntropy32<'a> {
+    pub const fn new(entropy: &'a dyn Entropy32<'a>) -> MuxEntropy32<'a> {
+        MuxEntropy32 {
+            entropy,
+            users: List::new(),
+            running: Cell::new(false),
+        }
+    }
+
+    /// Starts the source if a user asks for entropy, and stops it if none
+    /// does anymore.
+    fn update(&self) -> ReturnCode {
+        let wanted = self.users.iter().any(|user| user.requested.get());
+        if wanted && !self.running.get() {
+            let res = self.entropy.get();
+            self.running.set(res == ReturnCode::SUCCESS);
+            res
+        } else {
+            if !wanted && self.running.get() {
+                self.running.set(false);
+                self.entropy.cancel();
+            }
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl Client32 for MuxEntropy32<'a> {
+    fn entropy_available(
+        &self,
+        entropy: &mut dyn Iterator<Item = u32>,
+        error: ReturnCode,
+    ) -> Continue {
+        for user in self.users.iter().filter(|user| user.requested.get()) {
+            if user.entropy_available(entropy, error) == Continue::Done {
+                user.requested.set(false);
+            }
+        }
+        if self.users.iter().any(|user| user.requested.get()) {
+            Continue::More
+        } else {
+            self.running.set(false);
+            Continue::Done
+        }
+    }
+}
+
+pub struct VirtualEntropy32<'a> {
+    mux: &'a MuxEntropy32<'a>,
+    // Set from `get` until the client has enough entropy
+    requested: Cell<bool>,
+    next: ListLink<'a, VirtualEntropy32<'a>>,
+    client: OptionalCell<&'a dyn Client32>,
+}
+
+impl VirtualEntropy32<'a> {
+    pub const fn new(mux: &'a MuxEntropy32<'a>) -> VirtualEntropy32<'a> {
+        VirtualEntropy32 {
+            mux,
+            requested: Cell::new(false),
+            next: ListLink::empty(),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    fn entropy_available(
+        &self,
+        entropy: &mut dyn Iterator<Item = u32>,
+        error: ReturnCode,
+    ) -> Continue {
+        self.client.map_or(Continue::Done, |client| {
+            client.entropy_available(entropy, error)
+        })
+    }
+}
+
+impl ListNode<'a, VirtualEntropy32<'a>> for VirtualEntropy32<'a> {
+    fn next(&'a self) -> &'a ListLink<'a, VirtualEntropy32<'a>> {
+        &self.next
+    }
+}
+
+impl Entropy32<'a> for VirtualEntropy32<'a> {
+    fn get(&self) -> ReturnCode {
+        self.requested.set(true);
+        let res = self.mux.update();
+        if res != ReturnCode::SUCCESS {
+            self.requested.set(false);
+        }
+        res
+    }
+
+    fn cancel(&self) -> ReturnCode {
+        self.requested.set(false);
+        self.mux.update()
+    }
+
+    fn set_client(&'a self, client: &'a dyn Client32) {
+        // Only join the mux the first time, a second push would link the node
+        // to itself.
+        if self.client.is_none() {
+            self.mux.users.push_head(self);
+        }
+        self.client.set(client);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Default)]
+    struct Source {
+        gets: Cell<usize>,
+        cancels: Cell<usize>,
+    }
+
+    impl Entropy32<'a> for Source {
+        fn get(&self) -> ReturnCode {
+            self.gets.set(self.gets.get() + 1);
+            ReturnCode::SUCCESS
+        }
+
+        fn cancel(&self) -> ReturnCode {
+            self.cancels.set(self.cancels.get() + 1);
+            ReturnCode::SUCCESS
+        }
+
+        fn set_client(&'a self, _: &'a dyn Client32) {}
+    }
+
+    // Takes words until it has the number it wants
+    struct User {
+        wanted: Cell<usize>,
+        words: Cell<u32>,
+    }
+
+    impl User {
+        fn new(wanted: usize) -> User {
+            User {
+                wanted: Cell::new(wanted),
+                words: Cell::new(0),
+            }
+        }
+    }
+
+    impl Client32 for User {
+        fn entropy_available(
+            &self,
+            entropy: &mut dyn Iterator<Item = u32>,
+            _error: ReturnCode,
+        ) -> Continue {
+            while self.wanted.get() > 0 {
+                match entropy.next() {
+                    Some(_) => {
+                        self.wanted.set(self.wanted.get() - 1);
+                        self.words.set(self.words.get() + 1);
+                    }
+                    None => return Continue::More,
+                }
+            }
+            Continue::Done
+        }
+    }
+
+    #[test]
+    fn hands_entropy_to_requesting_users() {
+        let source = Source::default();
+        let mux = MuxEntropy32::new(&source);
+        let (a, b, c) = (
+            VirtualEntropy32::new(&mux),
+            VirtualEntropy32::new(&mux),
+            VirtualEntropy32::new(&mux),
+        );
+        let (user_a, user_b, user_c) = (User::new(2), User::new(1), User::new(1));
+        a.set_client(&user_a);
+        b.set_client(&user_b);
+        c.set_client(&user_c);
+
+        assert_eq!(a.get(), ReturnCode::SUCCESS);
+        assert_eq!(b.get(), ReturnCode::SUCCESS);
+        // The source is started once for both users
+        assert_eq!(source.gets.get(), 1);
+
+        let words = [1, 2, 3, 4];
+        let next = mux.entropy_available(&mut words.iter().cloned(), ReturnCode::SUCCESS);
+        assert_eq!(next, Continue::Done);
+        assert_eq!(user_a.words.get(), 2);
+        assert_eq!(user_b.words.get(), 1);
+        // A user that did not ask gets nothing
+        assert_eq!(user_c.words.get(), 0);
+
+        // The source stopped, so the next request starts it again
+        assert_eq!(c.get(), ReturnCode::SUCCESS);
+        assert_eq!(source.gets.get(), 2);
+    }
+
+    #[test]
+    fn keeps_running_until_users_are_done() {
+        let source = Source::default();
+        let mux = MuxEntropy32::new(&source);
+        let a = VirtualEntropy32::new(&mux);
+        let user_a = User::new(3);
+        a.set_client(&user_a);
+
+        a.get();
+        let words = [1, 2];
+        let next = mux.entropy_available(&mut words.iter().cloned(), ReturnCode::SUCCESS);
+        assert_eq!(next, Continue::More);
+        assert_eq!(user_a.words.get(), 2);
+
+        let next = mux.entropy_available(&mut words.iter().cloned(), ReturnCode::SUCCESS);
+        assert_eq!(next, Continue::Done);
+        assert_eq!(user_a.words.get(), 3);
+        assert_eq!(source.gets.get(), 1);
+        assert_eq!(source.cancels.get(), 0);
+    }
+
+    #[test]
+    fn cancel_stops_source_after_last_user() {
+        let source = Source::default();
+        let mux = MuxEntropy32::new(&source);
+        let (a, b) = (VirtualEntropy32::new(&mux), VirtualEntropy32::new(&mux));
+        let (user_a, user_b) = (User::new(1), User::new(1));
+        a.set_client(&user_a);
+        b.set_client(&user_b);
+
+        a.get();
+        b.get();
+        assert_eq!(a.cancel(), ReturnCode::SUCCESS);
+        // The other user still wants entropy
+        assert_eq!(source.cancels.get(), 0);
+
+        let words = [1, 2];
+        let next = mux.entropy_available(&mut words.iter().cloned(), ReturnCode::SUCCESS);
+        assert_eq!(next, Continue::Done);
+        assert_eq!(user_a.words.get(), 0);
+        assert_eq!(user_b.words.get(), 1);
+
+        a.get();
+        assert_eq!(a.cancel(), ReturnCode::SUCCESS);
+        assert_eq!(source.cancels.get(), 1);
+        // Cancelling while stopped leaves the source alone
+        assert_eq!(b.cancel(), ReturnCode::SUCCESS);
+        assert_eq!(source.cancels.get(), 1);
+    }
+
+    #[test]
+    fn set_client_joins_once() {
+        let source = Source::default();
+        let mux = MuxEntropy32::new(&source);
+        let a = VirtualEntropy32::new(&mux);
+        let (first, second) = (User::new(1), User::new(1));
+        a.set_client(&first);
+        a.set_client(&second);
+        assert_eq!(mux.users.iter().count(), 1);
+
+        a.get();
+        let words = [1];
+        mux.entropy_available(&mut words.iter().cloned(), ReturnCode::SUCCESS);
+        assert_eq!(first.words.get(), 0);
+        assert_eq!(second.words.get(), 1);
+    }
+}
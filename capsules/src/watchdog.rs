@@ -0,0 +1,238 @@
+//! Kernel watchdog service with optional supervision of apps.
+//!
+//! This capsule sits between the kernel loop and a hardware watchdog. The
+//! board returns it from `Platform::watchdog()`, and the kernel then tickles it
+//! on every iteration of its main loop. If an interrupt handler or a capsule
+//! gets stuck, or the kernel otherwise stops looping, the hardware watchdog is
+//! no longer serviced and resets the board.
+//!
+//! The hardware watchdog keeps running while the chip sleeps. Before the chip
+//! goes to sleep, the capsule arms its alarm to wake it up within half of the
+//! watchdog period, so that the kernel loops and tickles the watchdog even
+//! when nothing else happens. If the kernel is stuck, the wake up is never
+//! followed by a tickle, and the board resets.
+//!
+//! Apps can additionally register as "supervised" with a check-in period. If a
+//! supervised app does not check in within its period, the capsule stops
+//! passing tickles on to the hardware and the board resets once the hardware
+//! watchdog period elapses.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let watchdog_alarm = static_init!(
+//!     VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     VirtualMuxAlarm::new(mux_alarm)
+//! );
+//! let watchdog = static_init!(
+//!     capsules::watchdog::WatchdogService<
+//!         'static,
+//!         sam4l::wdt::Wdt,
+//!         VirtualMuxAlarm<'static, sam4l::ast::Ast>,
+//!     >,
+//!     capsules::watchdog::WatchdogService::new(
+//!         &sam4l::wdt::WDT,
+//!         watchdog_alarm,
+//!         1000, // Reset if the kernel does not loop for one second.
+//!         board_kernel.create_grant(&grant_cap)
+//!     )
+//! );
+//! watchdog_alarm.set_client(watchdog);
+//! ```
+//!
+//! and in the board's `Platform` implementation:
+//!
+//! ```rust
+//! fn watchdog(&self) -> &dyn kernel::watchdog::WatchDog {
+//!     self.watchdog
+//! }
+//! ```
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### `command_num`
+//!
+//! - `0`: Driver check.
+//! - `1`: Register the app as supervised. `data` is the check-in period in
+//!        milliseconds. The first period starts immediately.
+//! - `2`: Check in. Restarts the app's period.
+//! - `3`: Stop supervising the app.
+
+use core::cell::Cell;
+use kernel::debug;
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::hil::watchdog;
+use kernel::{AppId, Driver, Grant, ReturnCode};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Watchdog as usize;
+
+#[derive(Default)]
+pub struct App {
+    /// Check-in period in alarm ticks, if the app is supervised.
+    period: Option<u32>,
+    /// Alarm tick by which the app must check in next.
+    deadline: u32,
+}
+
+pub struct WatchdogService<'a, W: watchdog::Watchdog, A: Alarm<'a>> {
+    watchdog: &'a W,
+    alarm: &'a A,
+    /// Hardware watchdog period in milliseconds.
+    period_ms: usize,
+    apps: Grant<App>,
+    /// Set once a supervised app has missed its deadline. From then on the
+    /// hardware watchdog is left to expire.
+    starved: Cell<bool>,
+}
+
+impl<W: watchdog::Watchdog, A: Alarm<'a>> WatchdogService<'a, W, A> {
+    pub fn new(
+        watchdog: &'a W,
+        alarm: &'a A,
+        period_ms: usize,
+        grant: Grant<App>,
+    ) -> WatchdogService<'a, W, A> {
+        WatchdogService {
+            watchdog: watchdog,
+            alarm: alarm,
+            period_ms: period_ms,
+            apps: grant,
+            starved: Cell::new(false),
+        }
+    }
+
+    fn ms_to_ticks(ms: usize) -> u32 {
+        (ms as u64 * <A::Frequency>::frequency() as u64 / 1000) as u32
+    }
+
+    /// Check every supervised app against its deadline and arm the alarm for
+    /// the earliest remaining one. If `keep_alive` is set, the alarm also
+    /// fires within half of the hardware watchdog period, to wake the chip
+    /// up in time to tickle the watchdog.
+    fn check_deadlines(&self, keep_alive: bool) {
+        let now = self.alarm.now();
+        let mut next_dist = u32::max_value();
+        let mut next_deadline = None;
+        let mut missed = None;
+
+        for app in self.apps.iter() {
+            app.enter(|app, _| {
+                if app.period.is_some() {
+                    let dist = app.deadline.wrapping_sub(now);
+                    if dist > u32::max_value() / 2 {
+                        // The deadline is in the past.
+                        missed = Some(app.appid());
+                    } else if dist < next_dist {
+                        next_dist = dist;
+                        next_deadline = Some(app.deadline);
+                    }
+                }
+            });
+        }
+
+        if let Some(appid) = missed {
+            if !self.starved.get() {
+                debug!(
+                    "Watchdog: app {:?} missed its check-in, letting the watchdog expire",
+                    appid
+                );
+            }
+            self.starved.set(true);
+        }
+
+        if keep_alive {
+            let keep_alive_dist = Self::ms_to_ticks(self.period_ms / 2);
+            if keep_alive_dist < next_dist {
+                next_deadline = Some(now.wrapping_add(keep_alive_dist));
+            }
+        }
+
+        match next_deadline {
+            Some(deadline) => self.alarm.set_alarm(deadline),
+            None => self.alarm.disable(),
+        }
+    }
+}
+
+impl<W: watchdog::Watchdog, A: Alarm<'a>> kernel::watchdog::WatchDog for WatchdogService<'a, W, A> {
+    fn setup(&self) {
+        self.watchdog.start(self.period_ms);
+    }
+
+    fn tickle(&self) {
+        if !self.starved.get() {
+            self.watchdog.tickle();
+        }
+    }
+
+    fn suspend(&self) {
+        // The hardware watchdog keeps counting while the chip sleeps, so the
+        // chip must wake up before it expires.
+        self.check_deadlines(true);
+    }
+
+    fn resume(&self) {
+        self.tickle();
+    }
+}
+
+impl<W: watchdog::Watchdog, A: Alarm<'a>> time::AlarmClient for WatchdogService<'a, W, A> {
+    fn fired(&self) {
+        self.check_deadlines(false);
+    }
+}
+
+impl<W: watchdog::Watchdog, A: Alarm<'a>> Driver for WatchdogService<'a, W, A> {
+    /// Register, check in and unregister supervised apps.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Register as supervised with a period of `data` milliseconds.
+    /// - `2`: Check in.
+    /// - `3`: Stop supervision.
+    fn command(&self, command_num: usize, data: usize, _: usize, appid: AppId) -> ReturnCode {
+        let res = match command_num {
+            0 => return ReturnCode::SUCCESS,
+
+            1 => {
+                if data == 0 {
+                    return ReturnCode::EINVAL;
+                }
+                let ticks = Self::ms_to_ticks(data);
+                if ticks == 0 || ticks > u32::max_value() / 2 {
+                    return ReturnCode::EINVAL;
+                }
+                self.apps.enter(appid, |app, _| {
+                    app.period = Some(ticks);
+                    app.deadline = self.alarm.now().wrapping_add(ticks);
+                    ReturnCode::SUCCESS
+                })
+            }
+
+            2 => self.apps.enter(appid, |app, _| {
+                app.period.map_or(ReturnCode::ERESERVE, |period| {
+                    app.deadline = self.alarm.now().wrapping_add(period);
+                    ReturnCode::SUCCESS
+                })
+            }),
+
+            3 => self.apps.enter(appid, |app, _| {
+                app.period = None;
+                ReturnCode::SUCCESS
+            }),
+
+            _ => return ReturnCode::ENOSUPPORT,
+        };
+
+        let rcode = res.unwrap_or_else(|err| err.into());
+        if rcode == ReturnCode::SUCCESS {
+            self.check_deadlines(false);
+        }
+        rcode
+    }
+}
@@ -125,7 +125,7 @@ impl kernel::Chip for ArtyExx {
     // complete, and while it should disable the PMP, it seems to cause some negative
     // side effects (context switching does not work correctly) on the Arty-E21 platform.
     //
-    // TODO: implement the PMP driver and add it here `type MPU = rv32i::pmp::PMPConfig;`.
+    // TODO: use the PMP driver here: `type MPU = rv32i::pmp::PMP;`.
     //
     // See https://github.com/tock/tock/pull/1382 for (a little) more information.
     type MPU = ();
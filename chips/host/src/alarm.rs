@@ -0,0 +1,135 @@
+//! Alarm backed by the host's monotonic clock.
+//!
+//! The counter is the number of milliseconds since the alarm was created. A
+//! timer thread sleeps until the alarm expires and then raises the alarm
+//! interrupt.
+
+use core::cell::Cell;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use kernel::common::cells::OptionalCell;
+use kernel::hil::time::{self, Alarm, Freq1KHz, Time};
+
+use crate::interrupt;
+
+/// Expiry shared with the timer thread.
+struct Timer {
+    deadline: Mutex<Option<Instant>>,
+    changed: Condvar,
+}
+
+pub struct HostAlarm<'a> {
+    client: OptionalCell<&'a dyn time::AlarmClient>,
+    epoch: Instant,
+    alarm: Cell<u32>,
+    enabled: Cell<bool>,
+    timer: Arc<Timer>,
+}
+
+impl HostAlarm<'a> {
+    pub fn new() -> HostAlarm<'a> {
+        let timer = Arc::new(Timer {
+            deadline: Mutex::new(None),
+            changed: Condvar::new(),
+        });
+
+        let thread_timer = timer.clone();
+        thread::Builder::new()
+            .name("alarm".to_string())
+            .spawn(move || {
+                let mut deadline = thread_timer.deadline.lock().unwrap();
+                loop {
+                    match *deadline {
+                        None => deadline = thread_timer.changed.wait(deadline).unwrap(),
+                        Some(expiry) => {
+                            let now = Instant::now();
+                            if now >= expiry {
+                                *deadline = None;
+                                interrupt::trigger(interrupt::ALARM);
+                            } else {
+                                deadline = thread_timer
+                                    .changed
+                                    .wait_timeout(deadline, expiry - now)
+                                    .unwrap()
+                                    .0;
+                            }
+                        }
+                    }
+                }
+            })
+            .expect("failed to spawn alarm thread");
+
+        HostAlarm {
+            client: OptionalCell::empty(),
+            epoch: Instant::now(),
+            alarm: Cell::new(0),
+            enabled: Cell::new(false),
+            timer: timer,
+        }
+    }
+
+    fn set_deadline(&self, deadline: Option<Instant>) {
+        *self.timer.deadline.lock().unwrap() = deadline;
+        self.timer.changed.notify_one();
+    }
+
+    pub fn handle_interrupt(&self) {
+        // The interrupt may be stale if the alarm was disabled or moved since
+        // the timer thread raised it.
+        let late = self.now().wrapping_sub(self.alarm.get());
+        if self.enabled.get() && late <= u32::max_value() / 2 {
+            self.enabled.set(false);
+            self.client.map(|client| client.fired());
+        }
+    }
+}
+
+impl Time for HostAlarm<'a> {
+    type Frequency = Freq1KHz;
+
+    fn now(&self) -> u32 {
+        let elapsed = self.epoch.elapsed();
+        (elapsed.as_secs() * 1000 + elapsed.subsec_millis() as u64) as u32
+    }
+
+    fn max_tics(&self) -> u32 {
+        u32::max_value()
+    }
+}
+
+impl Alarm<'a> for HostAlarm<'a> {
+    fn set_client(&'a self, client: &'a dyn time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, tics: u32) {
+        self.alarm.set(tics);
+        self.enabled.set(true);
+
+        // Alarms in the past fire right away.
+        let remaining = tics.wrapping_sub(self.now());
+        let remaining = if remaining > u32::max_value() / 2 {
+            0
+        } else {
+            remaining
+        };
+        self.set_deadline(Some(
+            Instant::now() + Duration::from_millis(remaining as u64),
+        ));
+    }
+
+    fn get_alarm(&self) -> u32 {
+        self.alarm.get()
+    }
+
+    fn disable(&self) {
+        self.enabled.set(false);
+        self.set_deadline(None);
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled.get()
+    }
+}
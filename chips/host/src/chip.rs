@@ -0,0 +1,97 @@
+//! High-level setup and interrupt mapping for the host chip.
+
+use kernel;
+use kernel::debug;
+
+use crate::alarm::HostAlarm;
+use crate::gpio;
+use crate::interrupt;
+use crate::radio::Radio;
+use crate::syscall;
+use crate::systick::SysTick;
+use crate::uart::Uart;
+
+/// The emulated chip. Boards create its peripherals and hand references to
+/// them to the chip, so that it can dispatch their interrupts.
+pub struct Host {
+    userspace_kernel_boundary: syscall::SysCall,
+    uart: &'static Uart<'static>,
+    alarm: &'static HostAlarm<'static>,
+    gpio: &'static gpio::Port,
+    radio: Option<&'static Radio>,
+}
+
+impl Host {
+    /// Create the chip. Must be called on the thread that will run the kernel
+    /// loop.
+    pub fn new(
+        uart: &'static Uart<'static>,
+        alarm: &'static HostAlarm<'static>,
+        gpio: &'static gpio::Port,
+        radio: Option<&'static Radio>,
+    ) -> Host {
+        interrupt::init();
+        Host {
+            userspace_kernel_boundary: syscall::SysCall::new(),
+            uart: uart,
+            alarm: alarm,
+            gpio: gpio,
+            radio: radio,
+        }
+    }
+}
+
+impl kernel::Chip for Host {
+    type MPU = ();
+    type UserspaceKernelBoundary = syscall::SysCall;
+    type SysTick = SysTick;
+
+    fn mpu(&self) -> &Self::MPU {
+        &()
+    }
+
+    fn systick(&self) -> &Self::SysTick {
+        self.userspace_kernel_boundary.systick()
+    }
+
+    fn userspace_kernel_boundary(&self) -> &syscall::SysCall {
+        &self.userspace_kernel_boundary
+    }
+
+    fn service_pending_interrupts(&self) {
+        while let Some(irq) = interrupt::next_pending() {
+            match irq {
+                interrupt::UART0 => self.uart.handle_interrupt(),
+                interrupt::ALARM => self.alarm.handle_interrupt(),
+                interrupt::GPIO => self.gpio.handle_interrupt(),
+                interrupt::RADIO => self.radio.map_or((), |radio| radio.handle_interrupt()),
+                _ => debug!("Unexpected interrupt {}", irq),
+            }
+        }
+    }
+
+    fn has_pending_interrupts(&self) -> bool {
+        interrupt::has_pending()
+    }
+
+    fn sleep(&self) {
+        interrupt::wait(None);
+    }
+
+    unsafe fn atomic<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        // Interrupts are only ever handled on the kernel thread, so the
+        // kernel cannot be interrupted in the middle of `f`.
+        f()
+    }
+}
+
+/// The host has no clocks to manage, so the kernel's clock changes are
+/// ignored.
+impl kernel::hil::clock_pm::ChangeClock for Host {
+    fn change_clock(&self) {}
+
+    fn set_compute_mode(&self, _compute_mode: bool) {}
+}
@@ -0,0 +1,280 @@
+//! Simulated GPIO pins, optionally mirrored to files.
+//!
+//! Every pin has a single line level. Driving a pin as an output sets the
+//! level, and reading it returns the level. If the port is created with a
+//! directory, pin `n` is mirrored to the file `<dir>/<n>`, which holds `0` or
+//! `1`: outputs are written to the file, and a watcher thread picks up changes
+//! other programs make to it, raising the GPIO interrupt. This lets scripts
+//! press buttons and watch LEDs:
+//!
+//! ```shell
+//! $ echo 1 > /tmp/tock-gpio/2
+//! $ cat /tmp/tock-gpio/0
+//! ```
+
+use core::cell::Cell;
+use core::ops::Index;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use kernel::common::cells::OptionalCell;
+use kernel::hil;
+use kernel::hil::gpio::{Configuration, FloatingState, InterruptEdge};
+
+use crate::interrupt;
+
+/// How often the watcher thread checks the pin files.
+const POLL_INTERVAL_MS: u64 = 10;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Mode {
+    LowPower,
+    Input,
+    Output,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Edge {
+    Rising,
+    Falling,
+    Either,
+}
+
+pub struct GpioPin {
+    file: Option<PathBuf>,
+    mode: Cell<Mode>,
+    floating: Cell<FloatingState>,
+    /// The line level, shared with the watcher thread.
+    level: Arc<AtomicBool>,
+    /// The level when the interrupt handler last looked at the pin.
+    last_level: Cell<bool>,
+    edge: Cell<Option<Edge>>,
+    pending: Cell<bool>,
+    client: OptionalCell<&'static dyn hil::gpio::Client>,
+}
+
+impl GpioPin {
+    fn new(file: Option<PathBuf>) -> GpioPin {
+        GpioPin {
+            file: file,
+            mode: Cell::new(Mode::LowPower),
+            floating: Cell::new(FloatingState::PullNone),
+            level: Arc::new(AtomicBool::new(false)),
+            last_level: Cell::new(false),
+            edge: Cell::new(None),
+            pending: Cell::new(false),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Drive the line from outside the chip, for example from a test.
+    pub fn drive(&self, level: bool) {
+        self.level.store(level, Ordering::SeqCst);
+        interrupt::trigger(interrupt::GPIO);
+    }
+
+    fn write(&self, level: bool) {
+        self.level.store(level, Ordering::SeqCst);
+        self.last_level.set(level);
+        self.file.as_ref().map(|file| {
+            let _ = fs::write(file, if level { "1\n" } else { "0\n" });
+        });
+    }
+
+    fn handle_interrupt(&self) {
+        let level = self.level.load(Ordering::SeqCst);
+        let last_level = self.last_level.replace(level);
+        if level == last_level || self.mode.get() != Mode::Input {
+            return;
+        }
+
+        let fire = match self.edge.get() {
+            Some(Edge::Rising) => level,
+            Some(Edge::Falling) => !level,
+            Some(Edge::Either) => true,
+            None => false,
+        };
+        if fire {
+            self.pending.set(true);
+            self.client.map(|client| client.fired());
+            self.pending.set(false);
+        }
+    }
+}
+
+/// The simulated GPIO controller.
+pub struct Port {
+    pins: Vec<GpioPin>,
+}
+
+impl Port {
+    /// Create a port with `count` pins. If `dir` is given, it is created if
+    /// needed and the pins are mirrored to files in it.
+    pub fn new(count: usize, dir: Option<&Path>) -> Port {
+        let dir = dir.and_then(|dir| fs::create_dir_all(dir).ok().map(|_| dir));
+        let pins: Vec<GpioPin> = (0..count)
+            .map(|i| GpioPin::new(dir.map(|dir| dir.join(i.to_string()))))
+            .collect();
+
+        if dir.is_some() {
+            for pin in pins.iter() {
+                pin.write(false);
+            }
+
+            // Only changes to the file contents are applied to the pins, so
+            // that the watcher cannot undo a level the kernel just set.
+            let mut files: Vec<(PathBuf, Arc<AtomicBool>, bool)> = pins
+                .iter()
+                .filter_map(|pin| {
+                    pin.file
+                        .clone()
+                        .map(|file| (file, pin.level.clone(), false))
+                })
+                .collect();
+            thread::Builder::new()
+                .name("gpio".to_string())
+                .spawn(move || loop {
+                    for (file, level, last_seen) in files.iter_mut() {
+                        let contents = fs::read_to_string(&file).unwrap_or_default();
+                        let new_level = contents.trim() == "1";
+                        if new_level != *last_seen {
+                            *last_seen = new_level;
+                            level.store(new_level, Ordering::SeqCst);
+                            interrupt::trigger(interrupt::GPIO);
+                        }
+                    }
+                    thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+                })
+                .expect("failed to spawn gpio thread");
+        }
+
+        Port { pins: pins }
+    }
+
+    pub fn handle_interrupt(&self) {
+        for pin in self.pins.iter() {
+            pin.handle_interrupt();
+        }
+    }
+}
+
+impl Index<usize> for Port {
+    type Output = GpioPin;
+
+    fn index(&self, index: usize) -> &GpioPin {
+        &self.pins[index]
+    }
+}
+
+impl hil::gpio::Configure for GpioPin {
+    fn configuration(&self) -> Configuration {
+        match self.mode.get() {
+            Mode::LowPower => Configuration::LowPower,
+            Mode::Input => Configuration::Input,
+            Mode::Output => Configuration::Output,
+        }
+    }
+
+    fn make_output(&self) -> Configuration {
+        self.mode.set(Mode::Output);
+        self.configuration()
+    }
+
+    fn disable_output(&self) -> Configuration {
+        if self.mode.get() == Mode::Output {
+            self.mode.set(Mode::LowPower);
+        }
+        self.configuration()
+    }
+
+    fn make_input(&self) -> Configuration {
+        self.mode.set(Mode::Input);
+        self.last_level.set(self.level.load(Ordering::SeqCst));
+        self.configuration()
+    }
+
+    fn disable_input(&self) -> Configuration {
+        if self.mode.get() == Mode::Input {
+            self.mode.set(Mode::LowPower);
+        }
+        self.configuration()
+    }
+
+    fn deactivate_to_low_power(&self) {
+        self.mode.set(Mode::LowPower);
+    }
+
+    fn set_floating_state(&self, state: FloatingState) {
+        if self.mode.get() == Mode::Input && self.file.is_none() {
+            // With nothing else driving the line, the pull resistor decides.
+            match state {
+                FloatingState::PullUp => self.drive(true),
+                FloatingState::PullDown => self.drive(false),
+                FloatingState::PullNone => {}
+            }
+        }
+        self.floating.set(state);
+    }
+
+    fn floating_state(&self) -> FloatingState {
+        let state = self.floating.replace(FloatingState::PullNone);
+        let copy = match state {
+            FloatingState::PullUp => FloatingState::PullUp,
+            FloatingState::PullDown => FloatingState::PullDown,
+            FloatingState::PullNone => FloatingState::PullNone,
+        };
+        self.floating.set(state);
+        copy
+    }
+}
+
+impl hil::gpio::Output for GpioPin {
+    fn set(&self) {
+        self.write(true);
+    }
+
+    fn clear(&self) {
+        self.write(false);
+    }
+
+    fn toggle(&self) -> bool {
+        let level = !self.level.load(Ordering::SeqCst);
+        self.write(level);
+        level
+    }
+}
+
+impl hil::gpio::Input for GpioPin {
+    fn read(&self) -> bool {
+        self.level.load(Ordering::SeqCst)
+    }
+}
+
+impl hil::gpio::Interrupt for GpioPin {
+    fn set_client(&self, client: &'static dyn hil::gpio::Client) {
+        self.client.set(client);
+    }
+
+    fn enable_interrupts(&self, mode: InterruptEdge) {
+        self.edge.set(Some(match mode {
+            InterruptEdge::RisingEdge => Edge::Rising,
+            InterruptEdge::FallingEdge => Edge::Falling,
+            InterruptEdge::EitherEdge => Edge::Either,
+        }));
+    }
+
+    fn disable_interrupts(&self) {
+        self.edge.set(None);
+    }
+
+    fn is_pending(&self) -> bool {
+        self.pending.get()
+    }
+}
+
+impl hil::gpio::Pin for GpioPin {}
+impl hil::gpio::InterruptPin for GpioPin {}
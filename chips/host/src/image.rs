@@ -0,0 +1,126 @@
+//! Flash images for host processes.
+//!
+//! On hardware, processes are TBF binaries that tockloader writes to flash.
+//! Host processes are functions linked into the kernel binary instead. This
+//! module lays them out in a buffer the same way tockloader would lay out apps
+//! in flash, so that `kernel::procs::load_processes` finds them unmodified:
+//!
+//! ```text
+//! +------------+---------+---------------------+------------+-----+---
+//! | TBF header | padding | entry fn pointer    | TBF header | ... | 0
+//! +------------+---------+---------------------+------------+-----+---
+//!              \__ init_fn_offset __/
+//! ```
+//!
+//! Where a process's code would normally start, the image holds a pointer to
+//! its entry function. `SysCall::switch_to_process()` follows that pointer when
+//! the kernel starts the process.
+
+use std::mem;
+
+use crate::userspace::ProcessFn;
+
+/// A host process to include in the image.
+pub struct App {
+    /// The package name, used for IPC and by the process console.
+    pub name: &'static str,
+    /// The entry point, called like `_start` of a real Tock app.
+    pub main: ProcessFn,
+    /// Minimum RAM the kernel should allocate for the process.
+    pub minimum_ram_size: u32,
+}
+
+const TBF_VERSION: u16 = 2;
+const TBF_FLAG_ENABLED: u32 = 1;
+const TBF_TYPE_MAIN: u16 = 1;
+const TBF_TYPE_PACKAGE_NAME: u16 = 3;
+
+/// Size of the TBF base header.
+const BASE_LEN: usize = 16;
+/// Size of the main TLV, including its TL header.
+const MAIN_LEN: usize = 16;
+
+fn align(value: usize, alignment: usize) -> usize {
+    (value + alignment - 1) / alignment * alignment
+}
+
+fn put_u16(image: &mut [u8], offset: usize, value: u16) {
+    image[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(image: &mut [u8], offset: usize, value: u32) {
+    image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Write a TBF app with `header_len` bytes of header at the start of `image`.
+fn write_app(image: &mut [u8], app: &App, header_len: usize, total_len: usize) {
+    let entry_offset = align(header_len, mem::size_of::<usize>());
+
+    put_u16(image, 0, TBF_VERSION);
+    put_u16(image, 2, header_len as u16);
+    put_u32(image, 4, total_len as u32);
+    put_u32(image, 8, TBF_FLAG_ENABLED);
+
+    // Main TLV. The init function offset is relative to the end of the header.
+    put_u16(image, BASE_LEN, TBF_TYPE_MAIN);
+    put_u16(image, BASE_LEN + 2, 12);
+    put_u32(image, BASE_LEN + 4, (entry_offset - header_len) as u32);
+    put_u32(image, BASE_LEN + 8, 0);
+    put_u32(image, BASE_LEN + 12, app.minimum_ram_size);
+
+    // Package name TLV.
+    let name = app.name.as_bytes();
+    let name_offset = BASE_LEN + MAIN_LEN;
+    put_u16(image, name_offset, TBF_TYPE_PACKAGE_NAME);
+    put_u16(image, name_offset + 2, name.len() as u16);
+    image[name_offset + 4..name_offset + 4 + name.len()].copy_from_slice(name);
+
+    // The checksum is the XOR of all header words except itself.
+    let checksum = image[..header_len]
+        .chunks(4)
+        .enumerate()
+        .filter(|(i, _)| *i != 3)
+        .fold(0, |checksum, (_, word)| {
+            let mut bytes = [0; 4];
+            bytes[..word.len()].copy_from_slice(word);
+            checksum ^ u32::from_le_bytes(bytes)
+        });
+    put_u32(image, 12, checksum);
+
+    let entry = app.main as usize;
+    image[entry_offset..entry_offset + mem::size_of::<usize>()]
+        .copy_from_slice(&entry.to_le_bytes());
+}
+
+/// Build a flash image containing `apps`. The image lives for the rest of the
+/// program, as flash would.
+pub fn build(apps: &[App]) -> &'static [u8] {
+    let word = mem::size_of::<usize>();
+    let layout: Vec<(usize, usize)> = apps
+        .iter()
+        .map(|app| {
+            let header_len = BASE_LEN + MAIN_LEN + 4 + align(app.name.len(), 4);
+            let total_len = align(align(header_len, word) + word, word);
+            (header_len, total_len)
+        })
+        .collect();
+
+    // End the image with an empty word, which `load_processes` does not
+    // recognize as an app.
+    let image_len = layout.iter().map(|(_, total)| total).sum::<usize>() + word;
+
+    // Allocate as words so that process images are aligned like in flash.
+    let words = vec![0usize; image_len / word].into_boxed_slice();
+    let image: &'static mut [u8] = unsafe {
+        let words = Box::leak(words);
+        std::slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, image_len)
+    };
+
+    let mut offset = 0;
+    for (app, (header_len, total_len)) in apps.iter().zip(layout) {
+        write_app(&mut image[offset..], app, header_len, total_len);
+        offset += total_len;
+    }
+
+    image
+}
@@ -0,0 +1,85 @@
+//! Emulated interrupt controller.
+//!
+//! Simulated peripherals do their blocking work (reading the terminal, waiting
+//! for timers or sockets) on their own host threads. When one of them has
+//! something for the kernel it marks its interrupt line pending here, which
+//! also wakes the kernel thread if it is sleeping or waiting for a process.
+//! The kernel then handles the interrupt on its own thread, from
+//! `Chip::service_pending_interrupts()`, like it would on hardware.
+
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+use std::thread::{self, Thread};
+use std::time::Duration;
+
+/// Interrupt lines of the emulated peripherals.
+pub const UART0: u32 = 0;
+pub const ALARM: u32 = 1;
+pub const GPIO: u32 = 2;
+pub const RADIO: u32 = 3;
+
+/// Bitmask of pending interrupt lines.
+static PENDING: AtomicU32 = AtomicU32::new(0);
+
+/// The thread running the kernel loop, which interrupts wake up.
+static KERNEL_THREAD: AtomicPtr<Thread> = AtomicPtr::new(ptr::null_mut());
+
+/// Register the calling thread as the one running the kernel. Must be called
+/// before any peripheral is started.
+pub fn init() {
+    let thread = Box::into_raw(Box::new(thread::current()));
+    let old = KERNEL_THREAD.swap(thread, Ordering::SeqCst);
+    if !old.is_null() {
+        unsafe {
+            drop(Box::from_raw(old));
+        }
+    }
+}
+
+/// Mark interrupt line `irq` as pending and wake the kernel. Can be called from
+/// any thread.
+pub fn trigger(irq: u32) {
+    PENDING.fetch_or(1 << irq, Ordering::SeqCst);
+    wake();
+}
+
+/// Wake the kernel thread without raising an interrupt, for example because a
+/// process thread has made a system call.
+pub fn wake() {
+    let thread = KERNEL_THREAD.load(Ordering::SeqCst);
+    if !thread.is_null() {
+        unsafe {
+            (*thread).unpark();
+        }
+    }
+}
+
+pub fn has_pending() -> bool {
+    PENDING.load(Ordering::SeqCst) != 0
+}
+
+/// Return and clear the lowest pending interrupt line, if any.
+pub fn next_pending() -> Option<u32> {
+    let pending = PENDING.load(Ordering::SeqCst);
+    if pending == 0 {
+        return None;
+    }
+    let irq = pending.trailing_zeros();
+    PENDING.fetch_and(!(1 << irq), Ordering::SeqCst);
+    Some(irq)
+}
+
+/// Block the kernel thread until it is woken up, or until `timeout` elapses.
+/// Returns immediately if an interrupt is already pending.
+///
+/// Like a real `wfi`, this can return spuriously, so callers need to check
+/// why they were woken up.
+pub fn wait(timeout: Option<Duration>) {
+    if has_pending() {
+        return;
+    }
+    match timeout {
+        Some(timeout) => thread::park_timeout(timeout),
+        None => thread::park(),
+    }
+}
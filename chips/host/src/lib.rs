@@ -0,0 +1,33 @@
+//! Architecture and chip support for running Tock as a Linux host process.
+//!
+//! This crate lets the unmodified kernel, capsules and scheduler run on a
+//! development machine or in CI, without any hardware. It plays the role of
+//! both an arch crate and a chip crate:
+//!
+//! - Processes are host threads. Each process thread only runs while the kernel
+//!   is waiting for it in `switch_to_process()`, and hands system calls back to
+//!   the kernel over a channel. See `syscall` and `userspace`.
+//! - Peripherals are simulated: the UART is the terminal, the alarm is the host
+//!   clock, GPIO pins can be mirrored to files and the 802.15.4 radio sends
+//!   frames as UDP datagrams to the other host boards on the machine.
+//! - Peripheral threads raise interrupts through the emulated interrupt
+//!   controller in `interrupt`, which wakes the kernel thread.
+//!
+//! Since processes are Rust functions linked into the kernel binary rather
+//! than TBF binaries in flash, `image` wraps them in TBF headers so that
+//! `load_processes` finds them like any other app.
+
+#![crate_name = "host"]
+#![crate_type = "rlib"]
+#![feature(crate_visibility_modifier, in_band_lifetimes)]
+
+pub mod alarm;
+pub mod chip;
+pub mod gpio;
+pub mod image;
+pub mod interrupt;
+pub mod radio;
+pub mod syscall;
+pub mod systick;
+pub mod uart;
+pub mod userspace;
@@ -0,0 +1,282 @@
+//! 802.15.4 radio that sends frames to other host boards over UDP.
+//!
+//! Each host board on the machine is a node with a small integer id. Node `n`
+//! listens on UDP port `BASE_PORT + n` on the loopback interface, and a
+//! transmitted frame is sent as one datagram to every other node's port, as if
+//! all nodes were in range of each other. Frames are not acknowledged and the
+//! CRC is always valid. As on most real radios without hardware address
+//! filtering, the MAC layer above drops frames not addressed to this node.
+
+use core::cell::Cell;
+use std::collections::VecDeque;
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil::radio;
+use kernel::ReturnCode;
+
+use crate::interrupt;
+
+/// UDP port of node 0.
+pub const BASE_PORT: u16 = 15400;
+
+pub struct Radio {
+    node: u16,
+    nodes: u16,
+    socket: Option<UdpSocket>,
+    /// Frames received by the socket thread but not yet delivered.
+    incoming: Arc<Mutex<VecDeque<Vec<u8>>>>,
+
+    tx_client: OptionalCell<&'static dyn radio::TxClient>,
+    rx_client: OptionalCell<&'static dyn radio::RxClient>,
+    cfg_client: OptionalCell<&'static dyn radio::ConfigClient>,
+    power_client: OptionalCell<&'static dyn radio::PowerClient>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+
+    on: Cell<bool>,
+    /// Events waiting for the next interrupt.
+    power_changed: Cell<bool>,
+    config_committed: Cell<bool>,
+
+    addr: Cell<u16>,
+    addr_long: Cell<[u8; 8]>,
+    pan: Cell<u16>,
+    tx_power: Cell<i8>,
+    channel: Cell<u8>,
+}
+
+impl Radio {
+    /// Create the radio for node `node` of `nodes`. If the node's port is
+    /// taken the radio can still transmit, but receives nothing.
+    pub fn new(node: u16, nodes: u16) -> Radio {
+        let incoming = Arc::new(Mutex::new(VecDeque::new()));
+        let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, BASE_PORT + node)).ok();
+
+        socket
+            .as_ref()
+            .and_then(|socket| socket.try_clone().ok())
+            .map(|socket| {
+                let incoming = incoming.clone();
+                thread::Builder::new()
+                    .name("radio".to_string())
+                    .spawn(move || {
+                        let mut frame = [0; radio::MAX_FRAME_SIZE];
+                        while let Ok(len) = socket.recv(&mut frame) {
+                            incoming.lock().unwrap().push_back(frame[..len].to_vec());
+                            interrupt::trigger(interrupt::RADIO);
+                        }
+                    })
+                    .expect("failed to spawn radio thread");
+            });
+
+        Radio {
+            node: node,
+            nodes: nodes,
+            // Fall back to any free port so that transmitting still works.
+            socket: socket.or_else(|| UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).ok()),
+            incoming: incoming,
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            cfg_client: OptionalCell::empty(),
+            power_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            rx_buffer: TakeCell::empty(),
+            on: Cell::new(false),
+            power_changed: Cell::new(false),
+            config_committed: Cell::new(false),
+            addr: Cell::new(0),
+            addr_long: Cell::new([0; 8]),
+            pan: Cell::new(0),
+            tx_power: Cell::new(0),
+            channel: Cell::new(26),
+        }
+    }
+
+    pub fn handle_interrupt(&self) {
+        if self.power_changed.replace(false) {
+            let on = self.on.get();
+            self.power_client.map(|client| client.changed(on));
+        }
+
+        if self.config_committed.replace(false) {
+            self.cfg_client
+                .map(|client| client.config_done(ReturnCode::SUCCESS));
+        }
+
+        self.tx_buffer.take().map(|buf| {
+            self.tx_client
+                .map(move |client| client.send_done(buf, false, ReturnCode::SUCCESS));
+        });
+
+        // Deliver one frame per receive buffer. The client hands the buffer
+        // back with `set_receive_buffer()` when it is done with it.
+        while self.rx_buffer.is_some() {
+            let frame = match self.incoming.lock().unwrap().pop_front() {
+                Some(frame) => frame,
+                None => break,
+            };
+            if !self.on.get() {
+                continue;
+            }
+            self.rx_buffer.take().map(|buf| {
+                if radio::PSDU_OFFSET + frame.len() > buf.len() {
+                    self.rx_buffer.replace(buf);
+                    return;
+                }
+                buf[1] = (frame.len() + radio::MFR_SIZE) as u8;
+                buf[radio::PSDU_OFFSET..radio::PSDU_OFFSET + frame.len()].copy_from_slice(&frame);
+                let frame_len = frame.len();
+                self.rx_client
+                    .map(move |client| client.receive(buf, frame_len, true, ReturnCode::SUCCESS));
+            });
+        }
+    }
+}
+
+impl radio::Radio for Radio {}
+
+impl radio::RadioConfig for Radio {
+    fn initialize(
+        &self,
+        _spi_buf: &'static mut [u8],
+        _reg_write: &'static mut [u8],
+        _reg_read: &'static mut [u8],
+    ) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn reset(&self) -> ReturnCode {
+        ReturnCode::SUCCESS
+    }
+
+    fn start(&self) -> ReturnCode {
+        self.on.set(true);
+        self.power_changed.set(true);
+        interrupt::trigger(interrupt::RADIO);
+        ReturnCode::SUCCESS
+    }
+
+    fn stop(&self) -> ReturnCode {
+        self.on.set(false);
+        self.power_changed.set(true);
+        interrupt::trigger(interrupt::RADIO);
+        ReturnCode::SUCCESS
+    }
+
+    fn is_on(&self) -> bool {
+        self.on.get()
+    }
+
+    fn busy(&self) -> bool {
+        self.tx_buffer.is_some()
+    }
+
+    fn set_power_client(&self, client: &'static dyn radio::PowerClient) {
+        self.power_client.set(client);
+    }
+
+    fn config_commit(&self) {
+        self.config_committed.set(true);
+        interrupt::trigger(interrupt::RADIO);
+    }
+
+    fn set_config_client(&self, client: &'static dyn radio::ConfigClient) {
+        self.cfg_client.set(client);
+    }
+
+    fn get_address(&self) -> u16 {
+        self.addr.get()
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        self.addr_long.get()
+    }
+
+    fn get_pan(&self) -> u16 {
+        self.pan.get()
+    }
+
+    fn get_tx_power(&self) -> i8 {
+        self.tx_power.get()
+    }
+
+    fn get_channel(&self) -> u8 {
+        self.channel.get()
+    }
+
+    fn set_address(&self, addr: u16) {
+        self.addr.set(addr);
+    }
+
+    fn set_address_long(&self, addr: [u8; 8]) {
+        self.addr_long.set(addr);
+    }
+
+    fn set_pan(&self, id: u16) {
+        self.pan.set(id);
+    }
+
+    fn set_tx_power(&self, power: i8) -> ReturnCode {
+        self.tx_power.set(power);
+        ReturnCode::SUCCESS
+    }
+
+    fn set_channel(&self, chan: u8) -> ReturnCode {
+        if chan < 11 || chan > 26 {
+            return ReturnCode::EINVAL;
+        }
+        self.channel.set(chan);
+        ReturnCode::SUCCESS
+    }
+}
+
+impl radio::RadioData for Radio {
+    fn set_transmit_client(&self, client: &'static dyn radio::TxClient) {
+        self.tx_client.set(client);
+    }
+
+    fn set_receive_client(&self, client: &'static dyn radio::RxClient, buffer: &'static mut [u8]) {
+        self.rx_client.set(client);
+        self.set_receive_buffer(buffer);
+    }
+
+    fn set_receive_buffer(&self, buffer: &'static mut [u8]) {
+        self.rx_buffer.replace(buffer);
+        if !self.incoming.lock().unwrap().is_empty() {
+            interrupt::trigger(interrupt::RADIO);
+        }
+    }
+
+    fn transmit(
+        &self,
+        spi_buf: &'static mut [u8],
+        frame_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if !self.on.get() {
+            return (ReturnCode::EOFF, Some(spi_buf));
+        } else if self.tx_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(spi_buf));
+        } else if radio::PSDU_OFFSET + frame_len > spi_buf.len()
+            || frame_len + radio::MFR_SIZE > radio::MAX_FRAME_SIZE
+        {
+            return (ReturnCode::ESIZE, Some(spi_buf));
+        }
+
+        let frame = &spi_buf[radio::PSDU_OFFSET..radio::PSDU_OFFSET + frame_len];
+        self.socket.as_ref().map(|socket| {
+            for node in (0..self.nodes).filter(|node| *node != self.node) {
+                let _ = socket.send_to(
+                    frame,
+                    SocketAddrV4::new(Ipv4Addr::LOCALHOST, BASE_PORT + node),
+                );
+            }
+        });
+
+        self.tx_buffer.replace(spi_buf);
+        interrupt::trigger(interrupt::RADIO);
+        (ReturnCode::SUCCESS, None)
+    }
+}
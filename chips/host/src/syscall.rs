@@ -0,0 +1,242 @@
+//! Kernel-userland system call interface for processes running as host
+//! threads.
+
+use core::fmt::Write;
+use std::cell::RefCell;
+use std::ptr;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+
+use kernel;
+use kernel::procs::{FunctionCall, FunctionCallSource};
+use kernel::syscall::ContextSwitchReason;
+
+use crate::interrupt;
+use crate::systick::SysTick;
+use crate::userspace::{self, Preemption, Resume, Trap};
+
+/// This holds all of the state that the kernel must keep for the process when
+/// the process is not executing.
+#[derive(Copy, Clone, Default)]
+pub struct HostStoredState {
+    /// Index of the thread running this process plus one, or zero if the
+    /// process has not been started yet.
+    thread: usize,
+
+    /// Function the process should run when it is resumed.
+    function: Option<FunctionCall>,
+
+    /// Value to return from the system call the process is blocked in when it
+    /// is resumed.
+    return_value: Option<isize>,
+
+    /// Number of system calls the process thread has made.
+    syscall_count: usize,
+}
+
+/// The kernel's end of the channels to a process thread.
+struct ProcessThread {
+    resume: Sender<Resume>,
+    trap: Receiver<Trap>,
+    preemption: Preemption,
+}
+
+/// Implementation of the `UserspaceKernelBoundary` for host processes.
+pub struct SysCall {
+    systick: SysTick,
+    threads: RefCell<Vec<ProcessThread>>,
+    /// Message of the last process panic, for `fault_fmt()`.
+    last_fault: RefCell<Option<String>>,
+}
+
+impl SysCall {
+    pub fn new() -> SysCall {
+        SysCall {
+            systick: SysTick::new(),
+            threads: RefCell::new(Vec::new()),
+            last_fault: RefCell::new(None),
+        }
+    }
+
+    /// The timeslice timer. It lives here because `switch_to_process()` must
+    /// stop waiting for the process when the timeslice expires.
+    pub fn systick(&self) -> &SysTick {
+        &self.systick
+    }
+}
+
+impl kernel::syscall::UserspaceKernelBoundary for SysCall {
+    type StoredState = HostStoredState;
+
+    unsafe fn initialize_new_process(
+        &self,
+        stack_pointer: *const usize,
+        _stack_size: usize,
+        _state: &mut Self::StoredState,
+    ) -> Result<*const usize, ()> {
+        // Process threads use their own host stacks, so the stack pointer is
+        // just passed through. The thread itself is started the first time the
+        // process runs.
+        Ok(stack_pointer)
+    }
+
+    unsafe fn set_syscall_return_value(
+        &self,
+        _stack_pointer: *const usize,
+        state: &mut Self::StoredState,
+        return_value: isize,
+    ) {
+        state.return_value = Some(return_value);
+    }
+
+    unsafe fn set_process_function(
+        &self,
+        stack_pointer: *const usize,
+        _remaining_stack_memory: usize,
+        state: &mut Self::StoredState,
+        callback: FunctionCall,
+    ) -> Result<*mut usize, *mut usize> {
+        state.function = Some(callback);
+        Ok(stack_pointer as *mut usize)
+    }
+
+    unsafe fn switch_to_process(
+        &self,
+        stack_pointer: *const usize,
+        state: &mut Self::StoredState,
+    ) -> (*mut usize, ContextSwitchReason) {
+        let stack_pointer = stack_pointer as *mut usize;
+        let mut threads = self.threads.borrow_mut();
+
+        let resume = if let Some(function) = state.function.take() {
+            state.return_value = None;
+            let pc = match function.source {
+                FunctionCallSource::Kernel => {
+                    // The kernel is starting the process, either for the first
+                    // time or after a restart. Start a fresh thread; dropping
+                    // the channels to an old one makes it exit at its next
+                    // system call, and an old one that was preempted stays
+                    // parked, so that it never runs on the memory of the new
+                    // one.
+                    let index = if state.thread == 0 {
+                        threads.len()
+                    } else {
+                        state.thread - 1
+                    };
+                    let (resume, trap, preemption) = userspace::spawn(format!("process {}", index));
+                    let thread = ProcessThread {
+                        resume: resume,
+                        trap: trap,
+                        preemption,
+                    };
+                    if index == threads.len() {
+                        threads.push(thread);
+                    } else {
+                        threads[index] = thread;
+                    }
+                    state.thread = index + 1;
+                    state.syscall_count = 0;
+
+                    // The init function "code" in the process's flash image is
+                    // a pointer to its entry function. See `image`.
+                    ptr::read_unaligned(function.pc as *const usize)
+                }
+                FunctionCallSource::Driver(_) => function.pc,
+            };
+            Some(Resume::Call {
+                pc: pc,
+                arguments: [
+                    function.argument0,
+                    function.argument1,
+                    function.argument2,
+                    function.argument3,
+                ],
+            })
+        } else {
+            // If there is no return value either the process was preempted
+            // and is still running on its thread.
+            state.return_value.take().map(Resume::Return)
+        };
+
+        let thread = match state.thread.checked_sub(1).and_then(|i| threads.get(i)) {
+            Some(thread) => thread,
+            None => return (stack_pointer, ContextSwitchReason::Fault),
+        };
+        thread.preemption.unpark();
+        if let Some(resume) = resume {
+            if thread.resume.send(resume).is_err() {
+                return (stack_pointer, ContextSwitchReason::Fault);
+            }
+        }
+
+        loop {
+            match thread.trap.try_recv() {
+                Ok(Trap::Syscall { number, arguments }) => {
+                    state.syscall_count += 1;
+                    let syscall = kernel::syscall::arguments_to_syscall(
+                        number,
+                        arguments[0],
+                        arguments[1],
+                        arguments[2],
+                        arguments[3],
+                    );
+                    return match syscall {
+                        Some(syscall) => (
+                            stack_pointer,
+                            ContextSwitchReason::SyscallFired { syscall: syscall },
+                        ),
+                        None => (stack_pointer, ContextSwitchReason::Fault),
+                    };
+                }
+                Ok(Trap::Fault(message)) => {
+                    *self.last_fault.borrow_mut() = Some(message);
+                    return (stack_pointer, ContextSwitchReason::Fault);
+                }
+                Err(TryRecvError::Disconnected) => {
+                    return (stack_pointer, ContextSwitchReason::Fault);
+                }
+                Err(TryRecvError::Empty) => {}
+            }
+
+            // The process is still running. Park it before the kernel takes
+            // over, since the kernel reads and writes its memory.
+            if interrupt::has_pending() {
+                thread.preemption.park();
+                return (stack_pointer, ContextSwitchReason::Interrupted);
+            }
+            if self.systick.expired() {
+                thread.preemption.park();
+                return (stack_pointer, ContextSwitchReason::TimesliceExpired);
+            }
+
+            interrupt::wait(self.systick.remaining());
+        }
+    }
+
+    unsafe fn fault_fmt(&self, writer: &mut dyn Write) {
+        let _ = writer.write_fmt(format_args!(
+            "\r\n---| Host process fault |---\r\n{}\r\n",
+            self.last_fault
+                .borrow()
+                .as_ref()
+                .map_or("no panic message", |message| message.as_str())
+        ));
+    }
+
+    unsafe fn process_detail_fmt(
+        &self,
+        _stack_pointer: *const usize,
+        state: &HostStoredState,
+        writer: &mut dyn Write,
+    ) {
+        let _ = writer.write_fmt(format_args!(
+            "\
+             \r\n Host thread: {:>10}   Syscalls: {}\
+             \r\n Pending call: {:#x}   Pending return value: {:?}\
+             \r\n",
+            state.thread.checked_sub(1).map_or(-1, |i| i as isize),
+            state.syscall_count,
+            state.function.map_or(0, |function| function.pc),
+            state.return_value,
+        ));
+    }
+}
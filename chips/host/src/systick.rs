@@ -0,0 +1,85 @@
+//! SysTick timer backed by the host clock.
+
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use kernel;
+
+/// Measures process timeslices with the host's monotonic clock.
+///
+/// There is no timer interrupt. Instead the kernel thread waits for a process
+/// thread for at most `remaining()`, and preempts the process when it is
+/// `overflowed()`.
+pub struct SysTick {
+    deadline: Cell<Option<Instant>>,
+    interrupt_enabled: Cell<bool>,
+}
+
+impl SysTick {
+    pub fn new() -> SysTick {
+        SysTick {
+            deadline: Cell::new(None),
+            interrupt_enabled: Cell::new(false),
+        }
+    }
+
+    /// Time left until the timeslice expires, if the timer is set to interrupt
+    /// the running process.
+    pub fn remaining(&self) -> Option<Duration> {
+        if !self.interrupt_enabled.get() {
+            return None;
+        }
+        let now = Instant::now();
+        self.deadline.get().map(|deadline| {
+            if deadline > now {
+                deadline - now
+            } else {
+                Duration::from_secs(0)
+            }
+        })
+    }
+
+    /// Whether the timeslice of the running process has expired.
+    pub fn expired(&self) -> bool {
+        self.interrupt_enabled.get() && kernel::SysTick::overflowed(self)
+    }
+}
+
+impl kernel::SysTick for SysTick {
+    fn set_timer(&self, us: u32) {
+        self.deadline
+            .set(Some(Instant::now() + Duration::from_micros(us as u64)));
+    }
+
+    fn greater_than(&self, us: u32) -> bool {
+        self.deadline.get().map_or(false, |deadline| {
+            deadline > Instant::now() + Duration::from_micros(us as u64)
+        })
+    }
+
+    fn overflowed(&self) -> bool {
+        self.deadline
+            .get()
+            .map_or(false, |deadline| Instant::now() >= deadline)
+    }
+
+    fn reset(&self) {
+        self.deadline.set(None);
+        self.interrupt_enabled.set(false);
+    }
+
+    fn enable(&self, with_interrupt: bool) {
+        self.interrupt_enabled.set(with_interrupt);
+    }
+
+    fn remaining_us(&self) -> Option<u32> {
+        let now = Instant::now();
+        self.deadline.get().map(|deadline| {
+            if deadline > now {
+                (deadline - now).as_micros() as u32
+            } else {
+                0
+            }
+        })
+    }
+}
@@ -0,0 +1,228 @@
+//! UART backed by the terminal.
+//!
+//! Transmitted bytes are written to stdout. Bytes typed on stdin are collected
+//! by a reader thread and handed to the receive client from the UART
+//! interrupt.
+
+use core::cell::Cell;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::hil;
+use kernel::ReturnCode;
+
+use crate::interrupt;
+
+pub struct Uart<'a> {
+    tx_client: OptionalCell<&'a dyn hil::uart::TransmitClient>,
+    rx_client: OptionalCell<&'a dyn hil::uart::ReceiveClient>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    /// A word transmission is waiting for its completion callback.
+    tx_word: Cell<bool>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_index: Cell<usize>,
+    /// Bytes read from stdin that have not been received yet.
+    input: Arc<Mutex<VecDeque<u8>>>,
+}
+
+impl Uart<'a> {
+    pub fn new() -> Uart<'a> {
+        Uart {
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_word: Cell::new(false),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_index: Cell::new(0),
+            input: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Start reading from stdin.
+    pub fn initialize(&self) {
+        let input = self.input.clone();
+        thread::Builder::new()
+            .name("uart0 rx".to_string())
+            .spawn(move || {
+                let mut buf = [0; 64];
+                let stdin = io::stdin();
+                loop {
+                    match stdin.lock().read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(len) => {
+                            input.lock().unwrap().extend(&buf[..len]);
+                            interrupt::trigger(interrupt::UART0);
+                        }
+                    }
+                }
+            })
+            .expect("failed to spawn uart thread");
+    }
+
+    /// Write bytes synchronously, for panic messages.
+    pub fn transmit_sync(&self, bytes: &[u8]) {
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        let _ = stdout.write_all(bytes);
+        let _ = stdout.flush();
+    }
+
+    pub fn handle_interrupt(&self) {
+        if self.tx_word.get() {
+            self.tx_word.set(false);
+            self.tx_client
+                .map(|client| client.transmitted_word(ReturnCode::SUCCESS));
+        }
+        self.tx_buffer.take().map(|buffer| {
+            self.tx_client.map(move |client| {
+                client.transmitted_buffer(buffer, self.tx_len.get(), ReturnCode::SUCCESS)
+            });
+        });
+
+        if self.rx_buffer.is_some() {
+            let mut index = self.rx_index.get();
+            let len = self.rx_len.get();
+            self.rx_buffer.map(|buffer| {
+                let mut input = self.input.lock().unwrap();
+                while index < len {
+                    match input.pop_front() {
+                        Some(byte) => buffer[index] = byte,
+                        None => break,
+                    }
+                    index += 1;
+                }
+            });
+            self.rx_index.set(index);
+
+            if index == len {
+                self.rx_buffer.take().map(|buffer| {
+                    self.rx_client.map(move |client| {
+                        client.received_buffer(
+                            buffer,
+                            len,
+                            ReturnCode::SUCCESS,
+                            hil::uart::Error::None,
+                        )
+                    });
+                });
+            }
+        }
+    }
+}
+
+impl hil::uart::UartData<'a> for Uart<'a> {}
+impl hil::uart::Uart<'a> for Uart<'a> {}
+
+impl hil::uart::Configure for Uart<'a> {
+    fn configure(&self, params: hil::uart::Parameters) -> ReturnCode {
+        // The terminal does not care about the line settings.
+        if params.baud_rate == 0 {
+            ReturnCode::EINVAL
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl hil::uart::Transmit<'a> for Uart<'a> {
+    fn set_transmit_client(&self, client: &'a dyn hil::uart::TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        tx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if tx_len > tx_buffer.len() {
+            return (ReturnCode::ESIZE, Some(tx_buffer));
+        }
+        if self.tx_buffer.is_some() || self.tx_word.get() {
+            return (ReturnCode::EBUSY, Some(tx_buffer));
+        }
+
+        self.transmit_sync(&tx_buffer[..tx_len]);
+
+        // Complete the transmission from the interrupt handler, as the
+        // hardware would.
+        self.tx_len.set(tx_len);
+        self.tx_buffer.replace(tx_buffer);
+        interrupt::trigger(interrupt::UART0);
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn transmit_word(&self, word: u32) -> ReturnCode {
+        if self.tx_buffer.is_some() || self.tx_word.get() {
+            return ReturnCode::EBUSY;
+        }
+        self.transmit_sync(&[word as u8]);
+        self.tx_word.set(true);
+        interrupt::trigger(interrupt::UART0);
+        ReturnCode::SUCCESS
+    }
+
+    fn transmit_abort(&self) -> ReturnCode {
+        // Transmissions complete immediately, so there is never anything to
+        // abort. A pending callback will still be delivered.
+        if self.tx_buffer.is_some() || self.tx_word.get() {
+            ReturnCode::FAIL
+        } else {
+            ReturnCode::SUCCESS
+        }
+    }
+}
+
+impl hil::uart::Receive<'a> for Uart<'a> {
+    fn set_receive_client(&self, client: &'a dyn hil::uart::ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if rx_len > rx_buffer.len() {
+            return (ReturnCode::ESIZE, Some(rx_buffer));
+        }
+        if self.rx_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(rx_buffer));
+        }
+
+        self.rx_buffer.replace(rx_buffer);
+        self.rx_len.set(rx_len);
+        self.rx_index.set(0);
+
+        // Input may already be waiting.
+        if !self.input.lock().unwrap().is_empty() {
+            interrupt::trigger(interrupt::UART0);
+        }
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn receive_word(&self) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn receive_abort(&self) -> ReturnCode {
+        self.rx_buffer.take().map_or(ReturnCode::SUCCESS, |buffer| {
+            let index = self.rx_index.get();
+            self.rx_client.map(move |client| {
+                client.received_buffer(
+                    buffer,
+                    index,
+                    ReturnCode::ECANCEL,
+                    hil::uart::Error::Aborted,
+                )
+            });
+            ReturnCode::EBUSY
+        })
+    }
+}
@@ -0,0 +1,301 @@
+//! The userspace side of host processes.
+//!
+//! A host process is a Rust function with the signature of a Tock entry point,
+//! running on its own host thread. It talks to the kernel with the functions in
+//! this module instead of `svc`/`ecall` instructions:
+//!
+//! ```ignore
+//! extern "C" fn blink(_: usize, _mem_start: usize, _: usize, _: usize) {
+//!     loop {
+//!         host::userspace::command(LED_DRIVER_NUM, 3, 0, 0);
+//!         delay_ms(500);
+//!     }
+//! }
+//! ```
+//!
+//! Each system call hands its arguments to the kernel thread and blocks until
+//! the kernel resumes the process. Only one of the kernel and the processes is
+//! running at a time: when the kernel preempts a process, because its
+//! timeslice expired or an interrupt is pending, it parks the process thread
+//! with a signal wherever it is, like a timer interrupt on hardware. So, as on
+//! hardware, processes must not take locks that the kernel takes too, like the
+//! one of stdout: they print through the console driver.
+//!
+//! Buffers passed to `allow` must lie inside the process's memory, just like on
+//! hardware, and buffers passed to `allow_readonly` inside its memory or
+//! flash. The entry function is passed the start of that memory and the
+//! initial program break, and can grow it with `memop`.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::os::raw::c_int;
+use std::os::unix::thread::{JoinHandleExt, RawPthread};
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Once};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::interrupt;
+
+/// Signature of process entry points and callbacks.
+pub type ProcessFn = extern "C" fn(usize, usize, usize, usize);
+
+/// Sent by the kernel to resume a process.
+crate enum Resume {
+    /// Run a function: the entry point, or a callback from within `yield`.
+    Call { pc: usize, arguments: [usize; 4] },
+    /// Return from the system call the process is blocked in.
+    Return(isize),
+}
+
+/// Sent by a process to return control to the kernel.
+crate enum Trap {
+    Syscall {
+        number: u8,
+        arguments: [usize; 4],
+    },
+    /// The process thread panicked.
+    Fault(String),
+}
+
+struct Channels {
+    resume: Receiver<Resume>,
+    trap: Sender<Trap>,
+    parking: Arc<Parking>,
+}
+
+/// Shared by a process thread and the kernel to park the thread.
+#[derive(Default)]
+struct Parking {
+    /// Set by the thread once it is parked, and cleared by the kernel to let
+    /// it go on.
+    parked: AtomicBool,
+    /// Set by the thread when it is about to exit, after which it cannot be
+    /// parked.
+    exited: AtomicBool,
+}
+
+/// The signal that parks a process thread (`SIGUSR1` on Linux).
+const SIGPARK: c_int = 10;
+
+/// How often a parked thread checks whether the kernel let it go.
+const PARK_POLL: Duration = Duration::from_micros(200);
+
+extern "C" {
+    fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    fn pthread_kill(thread: RawPthread, signum: c_int) -> c_int;
+}
+
+/// Handler of `SIGPARK`, which sleeps until the kernel clears `parked`. It
+/// only reads the thread's channels, which are set before the thread can be
+/// parked, touches atomics and sleeps, all of which is safe in a signal
+/// handler.
+extern "C" fn park(_signum: c_int) {
+    let parking = CHANNELS.try_with(|channels| {
+        channels
+            .borrow()
+            .as_ref()
+            .map(|channels| channels.parking.clone())
+    });
+    if let Ok(Some(parking)) = parking {
+        parking.parked.store(true, Ordering::SeqCst);
+        while parking.parked.load(Ordering::SeqCst) {
+            thread::sleep(PARK_POLL);
+        }
+    }
+}
+
+/// The kernel's handle to a process thread, to stop it when the kernel takes
+/// over from the process and to let it go on when the process is resumed.
+crate struct Preemption {
+    thread: JoinHandle<()>,
+    parking: Arc<Parking>,
+}
+
+impl Preemption {
+    /// Park the thread, and wait until it is parked or has exited.
+    crate fn park(&self) {
+        let parking = &self.parking;
+        if parking.exited.load(Ordering::SeqCst) {
+            return;
+        }
+        // The thread is not joined until the kernel drops this handle, so
+        // its ID is valid even if it has just exited.
+        unsafe {
+            pthread_kill(self.thread.as_pthread_t(), SIGPARK);
+        }
+        while !parking.parked.load(Ordering::SeqCst) && !parking.exited.load(Ordering::SeqCst) {
+            thread::yield_now();
+        }
+    }
+
+    /// Let the thread go on, if it is parked.
+    crate fn unpark(&self) {
+        self.parking.parked.store(false, Ordering::SeqCst);
+    }
+}
+
+thread_local! {
+    static CHANNELS: RefCell<Option<Channels>> = RefCell::new(None);
+}
+
+/// Panic payload used to unwind a process thread after the kernel dropped the
+/// other end of its channels, because the process was restarted or the kernel
+/// exited.
+struct Exit;
+
+/// Start the thread for a new process. The thread waits for the kernel to
+/// send it its entry point.
+crate fn spawn(name: String) -> (Sender<Resume>, Receiver<Trap>, Preemption) {
+    static PARK_HANDLER: Once = Once::new();
+    PARK_HANDLER.call_once(|| unsafe {
+        signal(SIGPARK, park);
+    });
+
+    let (resume_tx, resume_rx) = mpsc::channel();
+    let (trap_tx, trap_rx) = mpsc::channel();
+    let (ready_tx, ready_rx) = mpsc::channel();
+    let fault = trap_tx.clone();
+    let parking = Arc::new(Parking::default());
+    let thread_parking = parking.clone();
+
+    let thread = thread::Builder::new()
+        .name(name)
+        .spawn(move || {
+            CHANNELS.with(|channels| {
+                *channels.borrow_mut() = Some(Channels {
+                    resume: resume_rx,
+                    trap: trap_tx,
+                    parking: thread_parking.clone(),
+                })
+            });
+            // The thread can only be parked once the handler finds `parking`.
+            let _ = ready_tx.send(());
+
+            let result = panic::catch_unwind(|| {
+                resume();
+                // Tock processes never return from their entry point. If this
+                // one does, it has nothing left to do but wait for callbacks.
+                loop {
+                    yieldk();
+                }
+            });
+
+            if let Err(payload) = result {
+                if !payload.is::<Exit>() {
+                    let _ = fault.send(Trap::Fault(panic_message(&*payload)));
+                    interrupt::wake();
+                }
+            }
+            thread_parking.exited.store(true, Ordering::SeqCst);
+        })
+        .expect("failed to spawn process thread");
+    let _ = ready_rx.recv();
+
+    (
+        resume_tx,
+        trap_rx,
+        Preemption {
+            thread,
+            parking,
+        },
+    )
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "process panicked".to_string()
+    }
+}
+
+/// Wait for the kernel to resume this process, and run the function it asks
+/// for, if any.
+fn resume() -> isize {
+    let resume = CHANNELS.with(|channels| {
+        channels
+            .borrow()
+            .as_ref()
+            .expect("system call from a thread that is not a process")
+            .resume
+            .recv()
+    });
+
+    match resume {
+        Ok(Resume::Return(value)) => value,
+        Ok(Resume::Call { pc, arguments }) => {
+            let function: ProcessFn = unsafe { std::mem::transmute(pc) };
+            function(arguments[0], arguments[1], arguments[2], arguments[3]);
+            0
+        }
+        Err(_) => panic::resume_unwind(Box::new(Exit)),
+    }
+}
+
+/// Issue a raw system call. `number` is the SVC number of the system call.
+pub fn syscall(number: u8, r0: usize, r1: usize, r2: usize, r3: usize) -> isize {
+    let sent = CHANNELS.with(|channels| {
+        channels
+            .borrow()
+            .as_ref()
+            .expect("system call from a thread that is not a process")
+            .trap
+            .send(Trap::Syscall {
+                number: number,
+                arguments: [r0, r1, r2, r3],
+            })
+    });
+    if sent.is_err() {
+        panic::resume_unwind(Box::new(Exit));
+    }
+    interrupt::wake();
+
+    resume()
+}
+
+/// Wait for and run a single callback.
+pub fn yieldk() {
+    syscall(0, 0, 0, 0, 0);
+}
+
+/// Yield until `condition` returns true.
+pub fn yieldk_for<F: Fn() -> bool>(condition: F) {
+    while !condition() {
+        yieldk();
+    }
+}
+
+pub fn subscribe(
+    driver: usize,
+    subscribe_num: usize,
+    callback: Option<ProcessFn>,
+    userdata: usize,
+) -> isize {
+    let callback = callback.map_or(0, |callback| callback as usize);
+    syscall(1, driver, subscribe_num, callback, userdata)
+}
+
+pub fn command(driver: usize, command_num: usize, arg1: usize, arg2: usize) -> isize {
+    syscall(2, driver, command_num, arg1, arg2)
+}
+
+/// Share `len` bytes at `address` with a driver. Pass a null address to
+/// revoke a previously shared buffer.
+pub fn allow(driver: usize, allow_num: usize, address: *mut u8, len: usize) -> isize {
+    syscall(3, driver, allow_num, address as usize, len)
+}
+
+/// Share `len` bytes at `address` with a driver that only reads them. The
+/// buffer may also lie in the process's flash.
+pub fn allow_readonly(driver: usize, allow_num: usize, address: *const u8, len: usize) -> isize {
+    syscall(5, driver, allow_num, address as usize, len)
+}
+
+pub fn memop(operand: usize, arg: usize) -> isize {
+    syscall(4, operand, arg, 0, 0)
+}
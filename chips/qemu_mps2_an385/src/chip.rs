@@ -0,0 +1,113 @@
+//! Chip trait setup.
+
+use cortexm3;
+use kernel::common::deferred_call;
+use kernel::Chip;
+
+use crate::deferred_call_tasks::Task;
+use crate::nvic;
+use crate::timer;
+use crate::uart;
+
+/// Frequency of the core clock, which also drives the SysTick.
+const SYSCLK_FREQUENCY: u32 = 25_000_000;
+
+pub struct QemuMps2An385 {
+    mpu: cortexm3::mpu::MPU,
+    userspace_kernel_boundary: cortexm3::syscall::SysCall,
+    systick: cortexm3::systick::SysTick,
+}
+
+impl QemuMps2An385 {
+    pub unsafe fn new() -> QemuMps2An385 {
+        // The calibration value QEMU reports does not match the core clock,
+        // so set the SysTick frequency explicitly.
+        cortexm3::systick::SysTick::set_hertz(SYSCLK_FREQUENCY);
+
+        QemuMps2An385 {
+            mpu: cortexm3::mpu::MPU::new(),
+            userspace_kernel_boundary: cortexm3::syscall::SysCall::new(),
+            systick: cortexm3::systick::SysTick::new(),
+        }
+    }
+
+    /// Enable the interrupts of the peripherals that have drivers.
+    pub unsafe fn enable_interrupts(&self) {
+        for interrupt in [nvic::UART0_RX, nvic::UART0_TX, nvic::TIMER1].iter() {
+            cortexm3::nvic::Nvic::new(*interrupt).enable();
+        }
+    }
+}
+
+impl Chip for QemuMps2An385 {
+    type MPU = cortexm3::mpu::MPU;
+    type UserspaceKernelBoundary = cortexm3::syscall::SysCall;
+    type SysTick = cortexm3::systick::SysTick;
+
+    fn service_pending_interrupts(&self) {
+        unsafe {
+            loop {
+                if let Some(task) = deferred_call::DeferredCall::next_pending() {
+                    match task {
+                        Task::Uart0 => uart::UART0.handle_interrupt(),
+                    }
+                } else if let Some(interrupt) = cortexm3::nvic::next_pending() {
+                    // QEMU interrupts are level triggered. Clear the pending
+                    // state before handling the interrupt, so that an event
+                    // during the handler pends it again.
+                    let n = cortexm3::nvic::Nvic::new(interrupt);
+                    n.clear_pending();
+
+                    match interrupt {
+                        nvic::UART0_RX | nvic::UART0_TX => uart::UART0.handle_interrupt(),
+                        nvic::TIMER1 => timer::ALARM.handle_interrupt(),
+                        _ => {
+                            panic!("unhandled interrupt {}", interrupt);
+                        }
+                    }
+
+                    n.enable();
+                } else {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn has_pending_interrupts(&self) -> bool {
+        unsafe { cortexm3::nvic::has_pending() || deferred_call::has_tasks() }
+    }
+
+    fn mpu(&self) -> &cortexm3::mpu::MPU {
+        &self.mpu
+    }
+
+    fn systick(&self) -> &cortexm3::systick::SysTick {
+        &self.systick
+    }
+
+    fn userspace_kernel_boundary(&self) -> &cortexm3::syscall::SysCall {
+        &self.userspace_kernel_boundary
+    }
+
+    fn sleep(&self) {
+        unsafe {
+            cortexm3::scb::unset_sleepdeep();
+            cortexm3::support::wfi();
+        }
+    }
+
+    unsafe fn atomic<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        cortexm3::support::atomic(f)
+    }
+}
+
+/// QEMU has no clocks to manage, so the kernel's clock changes are ignored.
+impl kernel::hil::clock_pm::ChangeClock for QemuMps2An385 {
+    fn change_clock(&self) {}
+
+    fn set_compute_mode(&self, _compute_mode: bool) {}
+}
@@ -0,0 +1,38 @@
+//! Exit QEMU through ARM semihosting.
+//!
+//! This lets a kernel end the emulation, for example when it panics, so that
+//! scripts running QEMU get an exit status instead of a hung emulator. QEMU
+//! must be started with `-semihosting`, otherwise the breakpoint used for the
+//! semihosting call is a fault.
+
+/// The `SYS_EXIT` semihosting operation.
+#[cfg_attr(not(target_os = "none"), allow(dead_code))]
+const SYS_EXIT: u32 = 0x18;
+
+// Reasons passed to `SYS_EXIT`. QEMU exits with status 0 for
+// `ApplicationExit`, and with status 1 for any other reason.
+const ADP_STOPPED_APPLICATION_EXIT: u32 = 0x20026;
+const ADP_STOPPED_RUN_TIME_ERROR_UNKNOWN: u32 = 0x20023;
+
+#[cfg(not(target_os = "none"))]
+fn sys_exit(_reason: u32) -> ! {
+    loop {}
+}
+
+#[cfg(target_os = "none")]
+fn sys_exit(reason: u32) -> ! {
+    unsafe {
+        asm!("bkpt #0xab" : : "{r0}"(SYS_EXIT), "{r1}"(reason) : "memory" : "volatile");
+    }
+    loop {}
+}
+
+/// Exit QEMU with status 0.
+pub fn success() -> ! {
+    sys_exit(ADP_STOPPED_APPLICATION_EXIT)
+}
+
+/// Exit QEMU with status 1.
+pub fn failure() -> ! {
+    sys_exit(ADP_STOPPED_RUN_TIME_ERROR_UNKNOWN)
+}
@@ -0,0 +1,90 @@
+//! Chip support for the QEMU `mps2-an385` machine.
+//!
+//! `qemu-system-arm -M mps2-an385` emulates the ARM MPS2 FPGA board running
+//! the AN385 image: a Cortex-M3 with an MPU, and the CMSDK APB peripherals.
+//! It is not a real chip, but it runs the whole Cortex-M path of Tock,
+//! including MPU protected processes and SysTick preemption, without hardware.
+
+#![crate_name = "qemu_mps2_an385"]
+#![crate_type = "rlib"]
+#![feature(asm, const_fn, in_band_lifetimes)]
+#![no_std]
+
+mod deferred_call_tasks;
+
+pub mod chip;
+pub mod exit;
+pub mod nvic;
+pub mod timer;
+pub mod uart;
+
+use cortexm3::{generic_isr, hard_fault_handler, svc_handler, systick_handler};
+
+unsafe extern "C" fn unhandled_interrupt() {
+    let mut interrupt_number: u32;
+
+    // IPSR[8:0] holds the currently active interrupt
+    asm!(
+    "mrs    r0, ipsr                    "
+    : "={r0}"(interrupt_number)
+    :
+    : "r0"
+    :
+    );
+
+    interrupt_number = interrupt_number & 0x1ff;
+
+    panic!("Unhandled Interrupt. ISR {} is active.", interrupt_number);
+}
+
+extern "C" {
+    // _estack is not really a function, but it makes the types work
+    // You should never actually invoke it!!
+    fn _estack();
+
+    // Defined by platform
+    fn reset_handler();
+}
+
+#[link_section = ".vectors"]
+// used Ensures that the symbol is kept until the final binary
+#[used]
+pub static BASE_VECTORS: [unsafe extern "C" fn(); 16] = [
+    _estack,
+    reset_handler,
+    unhandled_interrupt, // NMI
+    hard_fault_handler,  // Hard Fault
+    unhandled_interrupt, // MemManage
+    unhandled_interrupt, // BusFault
+    unhandled_interrupt, // UsageFault
+    unhandled_interrupt,
+    unhandled_interrupt,
+    unhandled_interrupt,
+    unhandled_interrupt,
+    svc_handler,         // SVC
+    unhandled_interrupt, // DebugMon
+    unhandled_interrupt,
+    unhandled_interrupt, // PendSV
+    systick_handler,     // SysTick
+];
+
+// QEMU wires 32 external interrupts to the NVIC of this machine.
+#[link_section = ".irqs"]
+#[used] // Ensures that the symbol is kept until the final binary
+pub static IRQS: [unsafe extern "C" fn(); 32] = [generic_isr; 32];
+
+extern "C" {
+    static mut _szero: u32;
+    static mut _ezero: u32;
+    static mut _etext: u32;
+    static mut _srelocate: u32;
+    static mut _erelocate: u32;
+}
+
+pub unsafe fn init() {
+    tock_rt0::init_data(&mut _etext, &mut _srelocate, &mut _erelocate);
+    tock_rt0::zero_bss(&mut _szero, &mut _ezero);
+
+    cortexm3::nvic::disable_all();
+    cortexm3::nvic::clear_all_pending();
+}
@@ -0,0 +1,17 @@
+//! Interrupt numbers of the `mps2-an385` machine.
+//!
+//! Each CMSDK UART has separate receive and transmit interrupts, see
+//! `hw/arm/mps2.c` in QEMU.
+
+#![allow(dead_code)]
+
+pub const UART0_RX: u32 = 0;
+pub const UART0_TX: u32 = 1;
+pub const UART1_RX: u32 = 2;
+pub const UART1_TX: u32 = 3;
+pub const UART2_RX: u32 = 4;
+pub const UART2_TX: u32 = 5;
+pub const TIMER0: u32 = 8;
+pub const TIMER1: u32 = 9;
+pub const DUALTIMER: u32 = 10;
+pub const UART_OVERFLOW: u32 = 12;
@@ -0,0 +1,136 @@
+//! Alarm built from the two CMSDK APB timers.
+//!
+//! A CMSDK timer is a 32 bit down counter that interrupts when it reaches zero
+//! and then restarts from its reload value. There is no compare register, so
+//! the alarm uses two of them: `TIMER0` counts down from `u32::MAX` forever
+//! and provides the time, and `TIMER1` is loaded with the number of tics until
+//! the alarm expires.
+
+use core::cell::Cell;
+
+use kernel::common::cells::OptionalCell;
+use kernel::common::registers::{register_bitfields, ReadWrite};
+use kernel::common::StaticRef;
+use kernel::hil;
+use kernel::hil::time::Freq25MHz;
+
+#[repr(C)]
+pub struct TimerRegisters {
+    ctrl: ReadWrite<u32, CTRL::Register>,
+    value: ReadWrite<u32>,
+    reload: ReadWrite<u32>,
+    /// Interrupt status (read) and clear (write 1)
+    intstatus: ReadWrite<u32, INTSTATUS::Register>,
+}
+
+register_bitfields![u32,
+    CTRL [
+        /// Enable
+        EN OFFSET(0) NUMBITS(1) [],
+        /// Use the external input as enable
+        EXTIN_EN OFFSET(1) NUMBITS(1) [],
+        /// Use the external input as clock
+        EXTIN_CLK OFFSET(2) NUMBITS(1) [],
+        /// Interrupt enable
+        INTEN OFFSET(3) NUMBITS(1) []
+    ],
+    INTSTATUS [
+        INT OFFSET(0) NUMBITS(1) []
+    ]
+];
+
+const TIMER0_BASE: StaticRef<TimerRegisters> =
+    unsafe { StaticRef::new(0x4000_0000 as *const TimerRegisters) };
+const TIMER1_BASE: StaticRef<TimerRegisters> =
+    unsafe { StaticRef::new(0x4000_1000 as *const TimerRegisters) };
+
+pub static mut ALARM: CmsdkAlarm = CmsdkAlarm::new(TIMER0_BASE, TIMER1_BASE);
+
+pub struct CmsdkAlarm<'a> {
+    counter: StaticRef<TimerRegisters>,
+    compare: StaticRef<TimerRegisters>,
+    alarm: Cell<u32>,
+    client: OptionalCell<&'a dyn hil::time::AlarmClient>,
+}
+
+impl CmsdkAlarm<'a> {
+    const fn new(
+        counter: StaticRef<TimerRegisters>,
+        compare: StaticRef<TimerRegisters>,
+    ) -> CmsdkAlarm<'a> {
+        CmsdkAlarm {
+            counter: counter,
+            compare: compare,
+            alarm: Cell::new(0),
+            client: OptionalCell::empty(),
+        }
+    }
+
+    /// Start the counter that provides the time. Interrupts of the compare
+    /// timer must be enabled in the NVIC.
+    pub fn start(&self) {
+        self.counter.ctrl.set(0);
+        self.counter.reload.set(core::u32::MAX);
+        self.counter.value.set(core::u32::MAX);
+        self.counter.ctrl.write(CTRL::EN::SET);
+
+        self.stop_compare();
+    }
+
+    fn stop_compare(&self) {
+        self.compare.ctrl.set(0);
+        self.compare.intstatus.write(INTSTATUS::INT::SET);
+    }
+
+    pub fn handle_interrupt(&self) {
+        self.stop_compare();
+
+        self.client.map(|client| {
+            client.fired();
+        });
+    }
+}
+
+impl hil::time::Time for CmsdkAlarm<'a> {
+    type Frequency = Freq25MHz;
+
+    fn now(&self) -> u32 {
+        // The counter counts down.
+        core::u32::MAX - self.counter.value.get()
+    }
+
+    fn max_tics(&self) -> u32 {
+        core::u32::MAX
+    }
+}
+
+impl hil::time::Alarm<'a> for CmsdkAlarm<'a> {
+    fn set_client(&self, client: &'a dyn hil::time::AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, tics: u32) {
+        self.stop_compare();
+        self.alarm.set(tics);
+
+        // The timer interrupts when it reaches zero, so it cannot be loaded
+        // with zero.
+        let remaining = tics.wrapping_sub(hil::time::Time::now(self));
+        let remaining = core::cmp::max(remaining, 1);
+        self.compare.reload.set(remaining);
+        self.compare.value.set(remaining);
+        self.compare.ctrl.write(CTRL::EN::SET + CTRL::INTEN::SET);
+    }
+
+    fn get_alarm(&self) -> u32 {
+        self.alarm.get()
+    }
+
+    fn disable(&self) {
+        self.stop_compare();
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.compare.ctrl.is_set(CTRL::EN)
+    }
+}
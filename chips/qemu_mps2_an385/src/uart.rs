@@ -0,0 +1,308 @@
+//! Driver for the CMSDK APB UART.
+//!
+//! The UART has a single byte buffer in each direction and no FIFO. It
+//! interrupts after each byte is sent and each byte is received, so the
+//! driver moves one byte per interrupt.
+
+use core::cell::Cell;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::deferred_call::DeferredCall;
+use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite};
+use kernel::common::StaticRef;
+use kernel::hil;
+use kernel::ReturnCode;
+
+use crate::deferred_call_tasks::Task;
+
+/// Frequency of the APB clock of the UARTs.
+const PCLK_FREQUENCY: u32 = 25_000_000;
+
+/// Used to deliver a byte that was received before the receive buffer was
+/// set.
+static DEFERRED_CALL: DeferredCall<Task> = unsafe { DeferredCall::new(Task::Uart0) };
+
+pub static mut UART0: Uart = Uart::new(UART0_BASE);
+
+const UART0_BASE: StaticRef<UartRegisters> =
+    unsafe { StaticRef::new(0x4000_4000 as *const UartRegisters) };
+
+#[repr(C)]
+pub struct UartRegisters {
+    data: ReadWrite<u32>,
+    state: ReadOnly<u32, STATE::Register>,
+    ctrl: ReadWrite<u32, CTRL::Register>,
+    /// Interrupt status (read) and clear (write 1)
+    intstatus: ReadWrite<u32, INT::Register>,
+    bauddiv: ReadWrite<u32>,
+}
+
+register_bitfields![u32,
+    STATE [
+        /// A byte is waiting to be sent
+        TXFULL OFFSET(0) NUMBITS(1) [],
+        /// A byte was received and not read yet
+        RXFULL OFFSET(1) NUMBITS(1) [],
+        TXOVERRUN OFFSET(2) NUMBITS(1) [],
+        RXOVERRUN OFFSET(3) NUMBITS(1) []
+    ],
+    CTRL [
+        TX_EN OFFSET(0) NUMBITS(1) [],
+        RX_EN OFFSET(1) NUMBITS(1) [],
+        TX_INTEN OFFSET(2) NUMBITS(1) [],
+        RX_INTEN OFFSET(3) NUMBITS(1) [],
+        TXO_INTEN OFFSET(4) NUMBITS(1) [],
+        RXO_INTEN OFFSET(5) NUMBITS(1) []
+    ],
+    INT [
+        TX OFFSET(0) NUMBITS(1) [],
+        RX OFFSET(1) NUMBITS(1) [],
+        TXO OFFSET(2) NUMBITS(1) [],
+        RXO OFFSET(3) NUMBITS(1) []
+    ]
+];
+
+pub struct Uart<'a> {
+    registers: StaticRef<UartRegisters>,
+    tx_client: OptionalCell<&'a dyn hil::uart::TransmitClient>,
+    rx_client: OptionalCell<&'a dyn hil::uart::ReceiveClient>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_index: Cell<usize>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_index: Cell<usize>,
+}
+
+impl Uart<'a> {
+    pub const fn new(base: StaticRef<UartRegisters>) -> Uart<'a> {
+        Uart {
+            registers: base,
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_index: Cell::new(0),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_index: Cell::new(0),
+        }
+    }
+
+    /// Handle both the transmit and the receive interrupt.
+    ///
+    /// This keeps going until neither direction can make progress, so the
+    /// interrupt line is low when it returns even if a client started a new
+    /// operation from its callback.
+    pub fn handle_interrupt(&self) {
+        let regs = self.registers;
+
+        loop {
+            // Acknowledge first. Both directions are checked below, so an
+            // event that happens after this is not missed.
+            regs.intstatus
+                .write(INT::TX::SET + INT::RX::SET + INT::TXO::SET + INT::RXO::SET);
+
+            let received = self.receive_byte();
+            let transmitted = self.transmit_byte();
+            if !received && !transmitted {
+                break;
+            }
+        }
+    }
+
+    /// Move a received byte to the receive buffer. Returns `false` if there
+    /// was nothing to do.
+    fn receive_byte(&self) -> bool {
+        let regs = self.registers;
+
+        // Leave the byte in the UART while there is no buffer for it. QEMU
+        // does not accept more input until it is read.
+        if self.rx_buffer.is_none() || !regs.state.is_set(STATE::RXFULL) {
+            return false;
+        }
+
+        let byte = regs.data.get() as u8;
+        let index = self.rx_index.get();
+        self.rx_buffer.map(|buffer| buffer[index] = byte);
+        self.rx_index.set(index + 1);
+
+        if index + 1 == self.rx_len.get() {
+            regs.ctrl.modify(CTRL::RX_INTEN::CLEAR);
+            self.rx_buffer.take().map(|buffer| {
+                self.rx_client.map(move |client| {
+                    client.received_buffer(
+                        buffer,
+                        index + 1,
+                        ReturnCode::SUCCESS,
+                        hil::uart::Error::None,
+                    )
+                });
+            });
+        }
+        true
+    }
+
+    /// Send the next byte of the transmit buffer, or finish the transmission.
+    /// Returns `false` if there was nothing to do.
+    fn transmit_byte(&self) -> bool {
+        let regs = self.registers;
+
+        if self.tx_buffer.is_none() || regs.state.is_set(STATE::TXFULL) {
+            return false;
+        }
+
+        let index = self.tx_index.get();
+        if index < self.tx_len.get() {
+            self.tx_buffer
+                .map(|buffer| regs.data.set(buffer[index] as u32));
+            self.tx_index.set(index + 1);
+        } else {
+            regs.ctrl.modify(CTRL::TX_INTEN::CLEAR);
+            self.tx_buffer.take().map(|buffer| {
+                self.tx_client.map(move |client| {
+                    client.transmitted_buffer(buffer, self.tx_len.get(), ReturnCode::SUCCESS)
+                });
+            });
+        }
+        true
+    }
+
+    pub fn transmit_sync(&self, bytes: &[u8]) {
+        let regs = self.registers;
+        // This may be used before the UART is configured, e.g. to report a
+        // panic.
+        if regs.bauddiv.get() < 16 {
+            regs.bauddiv.set(PCLK_FREQUENCY / 115200);
+        }
+        regs.ctrl.modify(CTRL::TX_EN::SET);
+        for b in bytes.iter() {
+            while regs.state.is_set(STATE::TXFULL) {}
+            regs.data.set(*b as u32);
+        }
+    }
+}
+
+impl hil::uart::UartData<'a> for Uart<'a> {}
+impl hil::uart::Uart<'a> for Uart<'a> {}
+
+impl hil::uart::Configure for Uart<'a> {
+    fn configure(&self, params: hil::uart::Parameters) -> ReturnCode {
+        // The UART only supports 8N1 without flow control.
+        if params.baud_rate == 0 {
+            return ReturnCode::EINVAL;
+        }
+        if params.width != hil::uart::Width::Eight
+            || params.parity != hil::uart::Parity::None
+            || params.stop_bits != hil::uart::StopBits::One
+            || params.hw_flow_control
+        {
+            return ReturnCode::ENOSUPPORT;
+        }
+
+        // QEMU does not send anything with a divisor below 16.
+        let divisor = PCLK_FREQUENCY / params.baud_rate;
+        if divisor < 16 {
+            return ReturnCode::EINVAL;
+        }
+
+        let regs = self.registers;
+        regs.ctrl.set(0);
+        regs.bauddiv.set(divisor);
+        regs.intstatus
+            .write(INT::TX::SET + INT::RX::SET + INT::TXO::SET + INT::RXO::SET);
+        regs.ctrl.write(CTRL::TX_EN::SET + CTRL::RX_EN::SET);
+
+        ReturnCode::SUCCESS
+    }
+}
+
+impl hil::uart::Transmit<'a> for Uart<'a> {
+    fn set_transmit_client(&self, client: &'a dyn hil::uart::TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_data: &'static mut [u8],
+        tx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if tx_len == 0 || tx_len > tx_data.len() {
+            return (ReturnCode::ESIZE, Some(tx_data));
+        }
+        if self.tx_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(tx_data));
+        }
+
+        self.tx_buffer.replace(tx_data);
+        self.tx_len.set(tx_len);
+        self.tx_index.set(0);
+
+        // Enable the interrupt before writing, so the first byte being sent
+        // raises it. The rest of the buffer is sent from the interrupt.
+        self.registers.ctrl.modify(CTRL::TX_INTEN::SET);
+        self.transmit_byte();
+
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn transmit_abort(&self) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn transmit_word(&self, _word: u32) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+}
+
+impl hil::uart::Receive<'a> for Uart<'a> {
+    fn set_receive_client(&self, client: &'a dyn hil::uart::ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if rx_len == 0 || rx_len > rx_buffer.len() {
+            return (ReturnCode::ESIZE, Some(rx_buffer));
+        }
+        if self.rx_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(rx_buffer));
+        }
+
+        self.rx_buffer.replace(rx_buffer);
+        self.rx_len.set(rx_len);
+        self.rx_index.set(0);
+        self.registers.ctrl.modify(CTRL::RX_INTEN::SET);
+
+        // A byte that arrived while there was no buffer did not raise an
+        // interrupt, so pick it up from a deferred call.
+        if self.registers.state.is_set(STATE::RXFULL) {
+            DEFERRED_CALL.set();
+        }
+
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn receive_abort(&self) -> ReturnCode {
+        self.registers.ctrl.modify(CTRL::RX_INTEN::CLEAR);
+        self.rx_buffer.take().map_or(ReturnCode::SUCCESS, |buffer| {
+            let index = self.rx_index.get();
+            self.rx_client.map(move |client| {
+                client.received_buffer(
+                    buffer,
+                    index,
+                    ReturnCode::ECANCEL,
+                    hil::uart::Error::Aborted,
+                )
+            });
+            ReturnCode::EBUSY
+        })
+    }
+
+    fn receive_word(&self) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+}
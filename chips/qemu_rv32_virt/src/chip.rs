@@ -0,0 +1,166 @@
+//! High-level setup and interrupt mapping for the chip.
+
+use kernel;
+use kernel::debug;
+use kernel::hil::time::Alarm;
+use rv32i;
+use rv32i::csr;
+use rv32i::csr::mcause;
+
+use crate::interrupts;
+use crate::plic;
+use crate::timer;
+use crate::uart;
+
+/// Number of PMP entries QEMU implements.
+const PMP_ENTRIES: usize = 16;
+
+pub struct QemuRv32Virt {
+    userspace_kernel_boundary: rv32i::syscall::SysCall,
+    pmp: rv32i::pmp::PMP,
+}
+
+impl QemuRv32Virt {
+    pub unsafe fn new() -> QemuRv32Virt {
+        QemuRv32Virt {
+            userspace_kernel_boundary: rv32i::syscall::SysCall::new(),
+            pmp: rv32i::pmp::PMP::new(PMP_ENTRIES),
+        }
+    }
+
+    /// Route the UART and timer interrupts to the hart.
+    ///
+    /// Interrupts still have to be enabled globally in `mstatus` afterwards.
+    pub unsafe fn enable_interrupts(&self) {
+        // The timer interrupt is pending until the first alarm is set.
+        timer::MACHINETIMER.disable();
+
+        plic::disable_all();
+        plic::enable_all();
+
+        csr::CSR
+            .mie
+            .modify(csr::mie::mie::mext::SET + csr::mie::mie::mtimer::SET);
+    }
+}
+
+impl kernel::Chip for QemuRv32Virt {
+    type MPU = rv32i::pmp::PMP;
+    type UserspaceKernelBoundary = rv32i::syscall::SysCall;
+    type SysTick = ();
+
+    fn mpu(&self) -> &Self::MPU {
+        &self.pmp
+    }
+
+    fn systick(&self) -> &Self::SysTick {
+        &()
+    }
+
+    fn userspace_kernel_boundary(&self) -> &rv32i::syscall::SysCall {
+        &self.userspace_kernel_boundary
+    }
+
+    fn service_pending_interrupts(&self) {
+        unsafe {
+            if csr::CSR.mip.is_set(csr::mip::mip::mtimer) {
+                timer::MACHINETIMER.handle_interrupt();
+            }
+
+            while let Some(interrupt) = plic::next_pending() {
+                match interrupt {
+                    interrupts::UART0 => uart::UART0.handle_interrupt(),
+                    _ => debug!("Pidx {}", interrupt),
+                }
+
+                // Mark that we are done with this interrupt and the hardware
+                // can clear it.
+                plic::complete(interrupt);
+            }
+
+            // The trap handler disabled the interrupts that brought us here.
+            // Now that they are handled, enable them again.
+            csr::CSR
+                .mie
+                .modify(csr::mie::mie::mext::SET + csr::mie::mie::mtimer::SET);
+        }
+    }
+
+    fn has_pending_interrupts(&self) -> bool {
+        // `mip` shows pending interrupts even while they are disabled in
+        // `mie`.
+        csr::CSR.mip.is_set(csr::mip::mip::mext) || csr::CSR.mip.is_set(csr::mip::mip::mtimer)
+    }
+
+    fn sleep(&self) {
+        unsafe {
+            rv32i::support::wfi();
+        }
+    }
+
+    unsafe fn atomic<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        rv32i::support::atomic(f)
+    }
+}
+
+/// QEMU has no clocks to manage, so the kernel's clock changes are ignored.
+impl kernel::hil::clock_pm::ChangeClock for QemuRv32Virt {
+    fn change_clock(&self) {}
+
+    fn set_compute_mode(&self, _compute_mode: bool) {}
+}
+
+/// Disable the interrupt that caused the current trap, so that it does not
+/// fire again before the kernel loop handles it.
+fn disable_interrupt_cause(interrupt: mcause::Interrupt) {
+    match interrupt {
+        mcause::Interrupt::MachineSoft => {
+            csr::CSR.mie.modify(csr::mie::mie::msoft::CLEAR);
+        }
+        mcause::Interrupt::MachineTimer => {
+            csr::CSR.mie.modify(csr::mie::mie::mtimer::CLEAR);
+        }
+        mcause::Interrupt::MachineExternal => {
+            csr::CSR.mie.modify(csr::mie::mie::mext::CLEAR);
+        }
+        // Supervisor and user mode interrupts are never enabled.
+        _ => {
+            debug!("unexpected interrupt {:?}", interrupt);
+        }
+    }
+}
+
+/// Trap handler for board/chip specific code.
+///
+/// This gets called when a trap occurs while the chip is in kernel mode. For
+/// interrupts, all we need to do is disable the interrupt until the kernel
+/// loop services it. Any exception is a bug in the kernel.
+#[export_name = "_start_trap_rust"]
+pub unsafe extern "C" fn start_trap_rust() {
+    let cause = csr::CSR.mcause.extract();
+    match mcause::McauseHelpers::cause(&cause) {
+        mcause::Trap::Interrupt(interrupt) => disable_interrupt_cause(interrupt),
+        mcause::Trap::Exception(exception) => {
+            panic!(
+                "kernel exception {:?} at {:#x}, mtval {:#x}",
+                exception,
+                csr::CSR.mepc.get(),
+                csr::CSR.mtval.get()
+            );
+        }
+    }
+}
+
+/// Function that gets called if an interrupt occurs while an app was running.
+/// mcause is passed in, and this function should correctly handle disabling the
+/// interrupt that fired so that it does not trigger again.
+#[export_name = "_disable_interrupt_trap_handler"]
+pub extern "C" fn disable_interrupt_trap_handler(_mcause: u32) {
+    let cause = csr::CSR.mcause.extract();
+    if let mcause::Trap::Interrupt(interrupt) = mcause::McauseHelpers::cause(&cause) {
+        disable_interrupt_cause(interrupt);
+    }
+}
@@ -0,0 +1,25 @@
+//! Exit QEMU through the SiFive test device of the `virt` machine.
+//!
+//! This lets a kernel end the emulation, for example when it panics, so that
+//! scripts running QEMU get an exit status instead of a hung emulator.
+
+use kernel::common::registers::WriteOnly;
+use kernel::common::StaticRef;
+
+const FINISHER_PASS: u32 = 0x5555;
+const FINISHER_FAIL: u32 = 0x3333;
+
+const TEST_BASE: StaticRef<WriteOnly<u32>> =
+    unsafe { StaticRef::new(0x0010_0000 as *const WriteOnly<u32>) };
+
+/// Exit QEMU with status 0.
+pub fn success() -> ! {
+    TEST_BASE.set(FINISHER_PASS);
+    loop {}
+}
+
+/// Exit QEMU with status `code`, which should be non-zero.
+pub fn failure(code: u16) -> ! {
+    TEST_BASE.set(((code as u32) << 16) | FINISHER_FAIL);
+    loop {}
+}
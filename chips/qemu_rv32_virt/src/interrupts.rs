@@ -0,0 +1,14 @@
+//! Named PLIC interrupts for the `virt` machine.
+
+#![allow(dead_code)]
+
+pub const VIRTIO0: u32 = 1;
+pub const VIRTIO1: u32 = 2;
+pub const VIRTIO2: u32 = 3;
+pub const VIRTIO3: u32 = 4;
+pub const VIRTIO4: u32 = 5;
+pub const VIRTIO5: u32 = 6;
+pub const VIRTIO6: u32 = 7;
+pub const VIRTIO7: u32 = 8;
+pub const UART0: u32 = 10;
+pub const RTC: u32 = 11;
@@ -0,0 +1,19 @@
+//! Chip support for the QEMU RISC-V `virt` machine.
+//!
+//! `qemu-system-riscv32 -M virt` emulates a generic RV32IMAC board with a
+//! CLINT, a PLIC, a 16550 UART and physical memory protection. It is not a
+//! real chip, but it runs the whole RISC-V path of Tock, including user mode
+//! processes, without hardware.
+
+#![feature(asm, const_fn, in_band_lifetimes)]
+#![no_std]
+#![crate_name = "qemu_rv32_virt"]
+#![crate_type = "rlib"]
+
+mod interrupts;
+
+pub mod chip;
+pub mod exit;
+pub mod plic;
+pub mod timer;
+pub mod uart;
@@ -0,0 +1,88 @@
+//! Platform Level Interrupt Control peripheral driver.
+//!
+//! The `virt` machine's PLIC has a context for the machine mode and one for
+//! the supervisor mode of each hart. Tock only uses the machine mode context
+//! of hart 0.
+
+use kernel::common::registers::{register_bitfields, ReadOnly, ReadWrite};
+use kernel::common::StaticRef;
+
+#[repr(C)]
+struct PlicRegisters {
+    /// Interrupt Priority Register
+    _reserved0: u32,
+    priority: [ReadWrite<u32, priority::Register>; 63],
+    _reserved1: [u8; 3840],
+    /// Interrupt Pending Register
+    pending: [ReadOnly<u32>; 2],
+    _reserved2: [u8; 4088],
+    /// Interrupt Enable Register for hart 0 machine mode
+    enable: [ReadWrite<u32>; 2],
+    _reserved3: [u8; 2088952],
+    /// Priority Threshold Register for hart 0 machine mode
+    threshold: ReadWrite<u32, priority::Register>,
+    /// Claim/Complete Register for hart 0 machine mode
+    claim: ReadWrite<u32>,
+}
+
+register_bitfields![u32,
+    priority [
+        Priority OFFSET(0) NUMBITS(3) []
+    ]
+];
+
+const PLIC_BASE: StaticRef<PlicRegisters> =
+    unsafe { StaticRef::new(0x0c00_0000 as *const PlicRegisters) };
+
+/// Enable all interrupts.
+pub unsafe fn enable_all() {
+    let plic: &PlicRegisters = &*PLIC_BASE;
+    for enable in plic.enable.iter() {
+        enable.set(0xFFFF_FFFF);
+    }
+
+    // Interrupts with priority 0 never fire, so give them all the same
+    // non-zero priority.
+    for priority in plic.priority.iter() {
+        priority.write(priority::Priority.val(1));
+    }
+
+    // Accept all interrupts.
+    plic.threshold.write(priority::Priority.val(0));
+}
+
+/// Disable all interrupts.
+pub unsafe fn disable_all() {
+    let plic: &PlicRegisters = &*PLIC_BASE;
+    for enable in plic.enable.iter() {
+        enable.set(0);
+    }
+}
+
+/// Claim the highest priority pending interrupt, or return `None` if none is
+/// pending. The interrupt is not signaled again until it is completed.
+pub unsafe fn next_pending() -> Option<u32> {
+    let plic: &PlicRegisters = &*PLIC_BASE;
+
+    let claim = plic.claim.get();
+    if claim == 0 {
+        None
+    } else {
+        Some(claim)
+    }
+}
+
+/// Signal that an interrupt is finished being handled. In Tock, this should be
+/// called from the normal main loop (not the interrupt handler).
+pub unsafe fn complete(index: u32) {
+    let plic: &PlicRegisters = &*PLIC_BASE;
+    plic.claim.set(index);
+}
+
+/// Return `true` if there are any pending interrupts in the PLIC, `false`
+/// otherwise.
+pub unsafe fn has_pending() -> bool {
+    let plic: &PlicRegisters = &*PLIC_BASE;
+
+    plic.pending.iter().fold(0, |i, pending| pending.get() | i) != 0
+}
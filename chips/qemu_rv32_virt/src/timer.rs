@@ -0,0 +1,11 @@
+//! Machine Timer instantiation.
+
+use kernel::common::StaticRef;
+use kernel::hil::time::Freq10MHz;
+use rv32i::machine_timer::{MachineTimer, MachineTimerRegisters};
+
+/// The CLINT timer, which QEMU runs at 10 MHz.
+pub static mut MACHINETIMER: MachineTimer<Freq10MHz> = MachineTimer::new(CLINT_BASE);
+
+const CLINT_BASE: StaticRef<MachineTimerRegisters> =
+    unsafe { StaticRef::new(0x0200_0000 as *const MachineTimerRegisters) };
@@ -0,0 +1,355 @@
+//! Driver for the NS16550A compatible UART of the `virt` machine.
+//!
+//! Several registers share an address: what is accessed depends on whether
+//! the register is read or written, and on the divisor latch access bit (DLAB)
+//! in the line control register.
+
+use core::cell::Cell;
+
+use kernel::common::cells::{OptionalCell, TakeCell};
+use kernel::common::registers::{register_bitfields, LocalRegisterCopy, ReadOnly, ReadWrite};
+use kernel::common::StaticRef;
+use kernel::hil;
+use kernel::ReturnCode;
+
+/// Depth of the transmit FIFO.
+const TX_FIFO_SIZE: usize = 16;
+
+pub static mut UART0: Uart = Uart::new(UART0_BASE, 3_686_400);
+
+const UART0_BASE: StaticRef<UartRegisters> =
+    unsafe { StaticRef::new(0x1000_0000 as *const UartRegisters) };
+
+#[repr(C)]
+pub struct UartRegisters {
+    /// Receive Buffer (read), Transmit Holding (write) and Divisor Latch LSB
+    /// (DLAB set) Register
+    rbr_thr: ReadWrite<u8>,
+    /// Interrupt Enable and Divisor Latch MSB (DLAB set) Register
+    ier: ReadWrite<u8, ier::Register>,
+    /// Interrupt Identification (read) and FIFO Control (write) Register
+    iir_fcr: ReadWrite<u8>,
+    /// Line Control Register
+    lcr: ReadWrite<u8, lcr::Register>,
+    /// Modem Control Register
+    _mcr: ReadWrite<u8>,
+    /// Line Status Register
+    lsr: ReadOnly<u8, lsr::Register>,
+    /// Modem Status Register
+    _msr: ReadOnly<u8>,
+    /// Scratch Register
+    _scr: ReadWrite<u8>,
+}
+
+register_bitfields![u8,
+    ier [
+        /// Received data available
+        erbfi OFFSET(0) NUMBITS(1) [],
+        /// Transmit holding register empty
+        etbei OFFSET(1) NUMBITS(1) [],
+        /// Receiver line status
+        elsi OFFSET(2) NUMBITS(1) []
+    ],
+    iir [
+        /// Cleared when an interrupt is pending
+        no_interrupt OFFSET(0) NUMBITS(1) [],
+        id OFFSET(1) NUMBITS(3) []
+    ],
+    fcr [
+        enable OFFSET(0) NUMBITS(1) [],
+        rx_reset OFFSET(1) NUMBITS(1) [],
+        tx_reset OFFSET(2) NUMBITS(1) []
+    ],
+    lcr [
+        wls OFFSET(0) NUMBITS(2) [
+            Bits6 = 1,
+            Bits7 = 2,
+            Bits8 = 3
+        ],
+        stb OFFSET(2) NUMBITS(1) [
+            OneStopBit = 0,
+            TwoStopBits = 1
+        ],
+        pen OFFSET(3) NUMBITS(1) [],
+        eps OFFSET(4) NUMBITS(1) [
+            Odd = 0,
+            Even = 1
+        ],
+        dlab OFFSET(7) NUMBITS(1) []
+    ],
+    lsr [
+        /// Data ready
+        dr OFFSET(0) NUMBITS(1) [],
+        /// Overrun error
+        oe OFFSET(1) NUMBITS(1) [],
+        /// Parity error
+        pe OFFSET(2) NUMBITS(1) [],
+        /// Framing error
+        fe OFFSET(3) NUMBITS(1) [],
+        /// Transmit holding register (and FIFO) empty
+        thre OFFSET(5) NUMBITS(1) []
+    ]
+];
+
+pub struct Uart<'a> {
+    registers: StaticRef<UartRegisters>,
+    clock_frequency: u32,
+    tx_client: OptionalCell<&'a dyn hil::uart::TransmitClient>,
+    rx_client: OptionalCell<&'a dyn hil::uart::ReceiveClient>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_index: Cell<usize>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    rx_len: Cell<usize>,
+    rx_index: Cell<usize>,
+}
+
+impl Uart<'a> {
+    pub const fn new(base: StaticRef<UartRegisters>, clock_frequency: u32) -> Uart<'a> {
+        Uart {
+            registers: base,
+            clock_frequency: clock_frequency,
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_index: Cell::new(0),
+            rx_buffer: TakeCell::empty(),
+            rx_len: Cell::new(0),
+            rx_index: Cell::new(0),
+        }
+    }
+
+    fn set_baud_rate(&self, baud_rate: u32) {
+        let regs = self.registers;
+
+        //              f_clk
+        // f_baud = -------------
+        //           16 * divisor
+        let divisor = self.clock_frequency / (16 * baud_rate);
+
+        regs.lcr.modify(lcr::dlab::SET);
+        regs.rbr_thr.set(divisor as u8);
+        regs.ier.set((divisor >> 8) as u8);
+        regs.lcr.modify(lcr::dlab::CLEAR);
+    }
+
+    /// Fill the transmit FIFO from the transmit buffer. Returns `true` once
+    /// the whole buffer has been written.
+    fn fill_tx_fifo(&self) -> bool {
+        let regs = self.registers;
+        if !regs.lsr.is_set(lsr::thre) {
+            return false;
+        }
+
+        let len = self.tx_len.get();
+        let mut index = self.tx_index.get();
+        self.tx_buffer.map(|buffer| {
+            let end = core::cmp::min(index + TX_FIFO_SIZE, len);
+            for byte in &buffer[index..end] {
+                regs.rbr_thr.set(*byte);
+            }
+            index = end;
+        });
+        self.tx_index.set(index);
+        index == len
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs = self.registers;
+
+        // Reading the IIR acknowledges a transmit holding register empty
+        // interrupt. Pending received data and line status interrupts are
+        // acknowledged by reading the data and the LSR below.
+        let iir: LocalRegisterCopy<u8, iir::Register> = LocalRegisterCopy::new(regs.iir_fcr.get());
+        if iir.is_set(iir::no_interrupt) {
+            return;
+        }
+
+        // Receive. Data is left in the FIFO while there is no buffer for it.
+        let mut error = hil::uart::Error::None;
+        while self.rx_buffer.is_some() {
+            let status = regs.lsr.extract();
+            if status.is_set(lsr::oe) {
+                error = hil::uart::Error::OverrunError;
+            } else if status.is_set(lsr::pe) {
+                error = hil::uart::Error::ParityError;
+            } else if status.is_set(lsr::fe) {
+                error = hil::uart::Error::FramingError;
+            }
+            if !status.is_set(lsr::dr) {
+                break;
+            }
+
+            let byte = regs.rbr_thr.get();
+            let index = self.rx_index.get();
+            self.rx_buffer.map(|buffer| buffer[index] = byte);
+            self.rx_index.set(index + 1);
+            if index + 1 == self.rx_len.get() {
+                break;
+            }
+        }
+
+        if self.rx_buffer.is_some()
+            && (self.rx_index.get() == self.rx_len.get() || error != hil::uart::Error::None)
+        {
+            regs.ier.modify(ier::erbfi::CLEAR + ier::elsi::CLEAR);
+            let (rval, len) = if error == hil::uart::Error::None {
+                (ReturnCode::SUCCESS, self.rx_len.get())
+            } else {
+                (ReturnCode::FAIL, self.rx_index.get())
+            };
+            self.rx_buffer.take().map(|buffer| {
+                self.rx_client
+                    .map(move |client| client.received_buffer(buffer, len, rval, error));
+            });
+        }
+
+        // Transmit.
+        if self.tx_buffer.is_some() && self.fill_tx_fifo() {
+            regs.ier.modify(ier::etbei::CLEAR);
+            self.tx_buffer.take().map(|buffer| {
+                self.tx_client.map(move |client| {
+                    client.transmitted_buffer(buffer, self.tx_len.get(), ReturnCode::SUCCESS)
+                });
+            });
+        }
+    }
+
+    pub fn transmit_sync(&self, bytes: &[u8]) {
+        let regs = self.registers;
+        for b in bytes.iter() {
+            while !regs.lsr.is_set(lsr::thre) {}
+            regs.rbr_thr.set(*b);
+        }
+    }
+}
+
+impl hil::uart::UartData<'a> for Uart<'a> {}
+impl hil::uart::Uart<'a> for Uart<'a> {}
+
+impl hil::uart::Configure for Uart<'a> {
+    fn configure(&self, params: hil::uart::Parameters) -> ReturnCode {
+        let regs = self.registers;
+
+        if params.baud_rate == 0 {
+            return ReturnCode::EINVAL;
+        }
+        // This UART does not support these features.
+        if params.hw_flow_control != false {
+            return ReturnCode::ENOSUPPORT;
+        }
+
+        self.set_baud_rate(params.baud_rate);
+
+        let width = match params.width {
+            hil::uart::Width::Six => lcr::wls::Bits6,
+            hil::uart::Width::Seven => lcr::wls::Bits7,
+            hil::uart::Width::Eight => lcr::wls::Bits8,
+        };
+        let stop_bits = match params.stop_bits {
+            hil::uart::StopBits::One => lcr::stb::OneStopBit,
+            hil::uart::StopBits::Two => lcr::stb::TwoStopBits,
+        };
+        let parity = match params.parity {
+            hil::uart::Parity::None => lcr::pen::CLEAR,
+            hil::uart::Parity::Odd => lcr::pen::SET + lcr::eps::Odd,
+            hil::uart::Parity::Even => lcr::pen::SET + lcr::eps::Even,
+        };
+        regs.lcr.write(width + stop_bits + parity);
+
+        // Enable and clear the FIFOs. Interrupts are enabled as needed.
+        regs.iir_fcr
+            .set((fcr::enable::SET + fcr::rx_reset::SET + fcr::tx_reset::SET).value);
+        regs.ier.set(0);
+
+        ReturnCode::SUCCESS
+    }
+}
+
+impl hil::uart::Transmit<'a> for Uart<'a> {
+    fn set_transmit_client(&self, client: &'a dyn hil::uart::TransmitClient) {
+        self.tx_client.set(client);
+    }
+
+    fn transmit_buffer(
+        &self,
+        tx_data: &'static mut [u8],
+        tx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if tx_len == 0 || tx_len > tx_data.len() {
+            return (ReturnCode::ESIZE, Some(tx_data));
+        }
+        if self.tx_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(tx_data));
+        }
+
+        self.tx_buffer.replace(tx_data);
+        self.tx_len.set(tx_len);
+        self.tx_index.set(0);
+        self.fill_tx_fifo();
+
+        // The UART interrupts when the FIFO is empty, and again whenever it
+        // becomes empty after being written to. Continue, or signal that we
+        // are done, from the interrupt.
+        self.registers.ier.modify(ier::etbei::SET);
+
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn transmit_abort(&self) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+
+    fn transmit_word(&self, _word: u32) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+}
+
+impl hil::uart::Receive<'a> for Uart<'a> {
+    fn set_receive_client(&self, client: &'a dyn hil::uart::ReceiveClient) {
+        self.rx_client.set(client);
+    }
+
+    fn receive_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+    ) -> (ReturnCode, Option<&'static mut [u8]>) {
+        if rx_len == 0 || rx_len > rx_buffer.len() {
+            return (ReturnCode::ESIZE, Some(rx_buffer));
+        }
+        if self.rx_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(rx_buffer));
+        }
+
+        self.rx_buffer.replace(rx_buffer);
+        self.rx_len.set(rx_len);
+        self.rx_index.set(0);
+        self.registers.ier.modify(ier::erbfi::SET + ier::elsi::SET);
+
+        (ReturnCode::SUCCESS, None)
+    }
+
+    fn receive_abort(&self) -> ReturnCode {
+        self.registers
+            .ier
+            .modify(ier::erbfi::CLEAR + ier::elsi::CLEAR);
+        self.rx_buffer.take().map_or(ReturnCode::SUCCESS, |buffer| {
+            let index = self.rx_index.get();
+            self.rx_client.map(move |client| {
+                client.received_buffer(
+                    buffer,
+                    index,
+                    ReturnCode::ECANCEL,
+                    hil::uart::Error::Aborted,
+                )
+            });
+            ReturnCode::EBUSY
+        })
+    }
+
+    fn receive_word(&self) -> ReturnCode {
+        ReturnCode::FAIL
+    }
+}
@@ -2,6 +2,10 @@
 
 use kernel::common::registers::{register_bitfields, ReadWrite, WriteOnly};
 use kernel::common::StaticRef;
+use kernel::hil;
+
+/// The watchdog counts the always-on low frequency clock.
+const WATCHDOG_CLOCK_HZ: u64 = 32768;
 
 #[repr(C)]
 pub struct WatchdogRegisters {
@@ -74,3 +78,39 @@ impl Watchdog {
         self.feed();
     }
 }
+
+impl hil::watchdog::Watchdog for Watchdog {
+    fn start(&self, period: usize) {
+        let regs = &*self.registers;
+
+        // The reset fires when the scaled counter (the counter shifted right
+        // by `scale`) reaches the 16 bit compare value, so pick the smallest
+        // scale for which the period fits.
+        let ticks = period as u64 * WATCHDOG_CLOCK_HZ / 1000;
+        let mut scale = 0;
+        while (ticks >> scale) > 0xFFFF && scale < 15 {
+            scale += 1;
+        }
+
+        // Every write to a watchdog register must be preceded by the key.
+        self.unlock();
+        regs.wdogcmp.set((ticks >> scale) as u32);
+        self.unlock();
+        regs.wdogcfg.write(
+            cfg::scale.val(scale as u32)
+                + cfg::rsten::SET
+                + cfg::zerocmp::CLEAR
+                + cfg::enalways::SET
+                + cfg::encoreawake::CLEAR,
+        );
+        self.feed();
+    }
+
+    fn stop(&self) {
+        self.disable();
+    }
+
+    fn tickle(&self) {
+        self.feed();
+    }
+}
@@ -61,6 +61,42 @@ pub struct CallbackId {
     pub subscribe_num: usize,
 }
 
+/// What to do when a callback is scheduled but the callback queue of the
+/// process is full.
+///
+/// Processes choose this in their TBF header, for all their callbacks and for
+/// single subscriptions, so that callbacks which fire often cannot push out
+/// callbacks that must not be lost.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CallbackOverflow {
+    /// Drop the callback that is being scheduled. This is the default.
+    DropNewest,
+    /// Drop the oldest queued call of the same callback, if there is one, to
+    /// make room. Otherwise drop the callback that is being scheduled.
+    DropOldest,
+    /// Keep at most one queued call of the callback. A new call replaces the
+    /// queued one, even if the queue is not full.
+    Coalesce,
+}
+
+impl CallbackOverflow {
+    /// Get the policy from its number in the TBF header.
+    crate fn from_u32(overflow: u32) -> Option<CallbackOverflow> {
+        match overflow {
+            0 => Some(CallbackOverflow::DropNewest),
+            1 => Some(CallbackOverflow::DropOldest),
+            2 => Some(CallbackOverflow::Coalesce),
+            _ => None,
+        }
+    }
+}
+
+impl Default for CallbackOverflow {
+    fn default() -> CallbackOverflow {
+        CallbackOverflow::DropNewest
+    }
+}
+
 /// Type for calling a callback in a process.
 ///
 /// This is essentially a wrapper around a function pointer.
@@ -70,6 +106,7 @@ pub struct Callback {
     callback_id: CallbackId,
     appdata: usize,
     fn_ptr: NonNull<*mut ()>,
+    overflow: CallbackOverflow,
 }
 
 impl Callback {
@@ -78,12 +115,14 @@ impl Callback {
         callback_id: CallbackId,
         appdata: usize,
         fn_ptr: NonNull<*mut ()>,
+        overflow: CallbackOverflow,
     ) -> Callback {
         Callback {
             app_id,
             callback_id,
             appdata,
             fn_ptr,
+            overflow,
         }
     }
 
@@ -91,7 +130,8 @@ impl Callback {
     ///
     /// This will queue the `Callback` for the associated process. It returns
     /// `false` if the queue for the process is full and the callback could not
-    /// be scheduled.
+    /// be scheduled. What happens when the queue is full depends on the
+    /// `CallbackOverflow` policy the process chose when it subscribed.
     ///
     /// The arguments (`r0-r2`) are the values passed back to the process and
     /// are specific to the individual `Driver` interfaces.
@@ -99,14 +139,17 @@ impl Callback {
         self.app_id
             .kernel
             .process_map_or(false, self.app_id.idx(), |process| {
-                process.enqueue_task(process::Task::FunctionCall(process::FunctionCall {
-                    source: process::FunctionCallSource::Driver(self.callback_id),
-                    argument0: r0,
-                    argument1: r1,
-                    argument2: r2,
-                    argument3: self.appdata,
-                    pc: self.fn_ptr.as_ptr() as usize,
-                }))
+                process.enqueue_callback(
+                    process::FunctionCall {
+                        source: process::FunctionCallSource::Driver(self.callback_id),
+                        argument0: r0,
+                        argument1: r1,
+                        argument2: r2,
+                        argument3: self.appdata,
+                        pc: self.fn_ptr.as_ptr() as usize,
+                    },
+                    self.overflow,
+                )
             })
     }
 }
@@ -23,4 +23,10 @@ pub trait Queue<T> {
     fn retain<F>(&mut self, f: F)
     where
         F: FnMut(&T) -> bool;
+
+    /// Remove the first element, counting from the front of the queue, that
+    /// satisfies the predicate, and return it.
+    fn remove_first_matching<F>(&mut self, f: F) -> Option<T>
+    where
+        F: Fn(&T) -> bool;
 }
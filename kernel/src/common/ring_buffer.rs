@@ -122,6 +122,31 @@ impl<T: Copy> queue::Queue<T> for RingBuffer<'a, T> {
 
         self.tail = dst;
     }
+
+    fn remove_first_matching<F>(&mut self, f: F) -> Option<T>
+    where
+        F: Fn(&T) -> bool,
+    {
+        let len = self.ring.len();
+        let mut slot = self.head;
+        while slot != self.tail {
+            if f(&self.ring[slot]) {
+                // Found the element, shift the elements after it forward by
+                // one to close the gap.
+                let val = self.ring[slot];
+                let mut next = (slot + 1) % len;
+                while next != self.tail {
+                    self.ring[slot] = self.ring[next];
+                    slot = next;
+                    next = (next + 1) % len;
+                }
+                self.tail = slot;
+                return Some(val);
+            }
+            slot = (slot + 1) % len;
+        }
+        None
+    }
 }
 
 #[cfg(test)]
@@ -237,4 +262,30 @@ mod test {
         assert_eq!(buf.dequeue(), Some(9));
         assert_eq!(buf.dequeue(), None);
     }
+
+    #[test]
+    fn test_remove_first_matching() {
+        const LEN: usize = 10;
+        let mut ring = [0; LEN];
+        let mut buf = RingBuffer::new(&mut ring);
+
+        move_head(&mut buf, LEN - 2);
+        enqueue_iota(&mut buf, LEN);
+
+        assert_eq!(buf.remove_first_matching(|x| *x == 3), Some(3));
+        assert_eq!(buf.remove_first_matching(|x| *x == 3), None);
+        assert_eq!(buf.remove_first_matching(|x| x % 4 == 0), Some(4));
+        assert_eq!(buf.len(), LEN - 3);
+        assert!(buf.enqueue(10));
+
+        assert_eq!(buf.dequeue(), Some(1));
+        assert_eq!(buf.dequeue(), Some(2));
+        assert_eq!(buf.dequeue(), Some(5));
+        assert_eq!(buf.dequeue(), Some(6));
+        assert_eq!(buf.dequeue(), Some(7));
+        assert_eq!(buf.dequeue(), Some(8));
+        assert_eq!(buf.dequeue(), Some(9));
+        assert_eq!(buf.dequeue(), Some(10));
+        assert_eq!(buf.dequeue(), None);
+    }
 }
@@ -36,9 +36,68 @@
 //! Yes the code gets here with value 42
 //! TOCK_DEBUG(0): /tock/capsules/src/sensys.rs:24: got here
 //! ```
+//!
+//! Log levels
+//! ----------
+//!
+//! The `error!`, `warn!`, `info!` and `trace!` macros print messages with a
+//! severity, the module they come from and, if the board set a clock with
+//! `set_log_clock()`, a timestamp in seconds:
+//!
+//! ```no_run
+//! # use kernel::{trace, warn};
+//! # fn main() {
+//! # let tag = 7;
+//! warn!("dropped fragment of datagram {}", tag);
+//! trace!("sent preamble {}", 3);
+//! # }
+//! ```
+//!
+//! ```text
+//! [12.345] WARN sixlowpan_state: dropped fragment of datagram 7
+//! ```
+//!
+//! Messages above `MAX_LEVEL` compile to nothing. It is `Info` unless a board
+//! enables one of the `log_level_trace`, `log_level_warn`, `log_level_error`
+//! or `log_level_off` features of the kernel crate, so capsules can keep
+//! `trace!` calls for detailed tracing at no cost. At runtime, messages are
+//! also filtered by the level of their module, set with
+//! `set_module_log_level()`, or else the default level set with
+//! `set_log_level()`, which is `Info` at boot.
+//!
+//! Binary logging
+//! --------------
+//!
+//! `debug_binary!()` takes the same format strings as `debug!()`, but does
+//! not format the message on the board. The format string is interned in the
+//! `tock_log` section of the kernel ELF file, which is not loaded, and only
+//! its offset in the section and the arguments, each tagged with its type,
+//! go through the debug writer to the UART or to `segger_rtt`. This saves the
+//! flash of the format strings and of the formatting code, and the cycles to
+//! format the message. `tools/decode_binary_log.py` formats the messages from
+//! the kernel ELF file and passes other output through:
+//!
+//! ```no_run
+//! # use kernel::debug_binary;
+//! # fn main() {
+//! # let (length, address) = (42, 0x2000_0000u32);
+//! debug_binary!("received {} bytes at {:#x}", length, address);
+//! # }
+//! ```
+//!
+//! ```text
+//! $ tools/decode_binary_log.py kernel.elf /dev/ttyACM0
+//! received 42 bytes at 0x20000000
+//! ```
+//!
+//! The arguments are integers, `bool`, `char`, `&str`, `&[u8]`, references to
+//! them, and types that implement `BinaryArgument`. Messages longer than
+//! `MAX_BINARY_MESSAGE` bytes, or that do not fit in the debug buffer, are
+//! dropped, and the decoder prints how many.
 
 use core::cell::Cell;
 use core::fmt::{write, Arguments, Result, Write};
+use core::iter::Peekable;
 use core::panic::PanicInfo;
 use core::ptr;
 use core::str;
@@ -48,6 +107,7 @@ use crate::common::cells::{MapCell, TakeCell};
 use crate::common::queue::Queue;
 use crate::common::ring_buffer::RingBuffer;
 use crate::hil;
+use crate::hil::time::Frequency;
 use crate::process::ProcessType;
 use crate::ReturnCode;
 
@@ -213,6 +273,9 @@ pub struct DebugWriter {
     internal_buffer: TakeCell<'static, RingBuffer<'static, u8>>,
     // Number of debug!() calls.
     count: Cell<usize>,
+    // Number of debug_binary!() messages dropped since the last one that was
+    // queued.
+    binary_dropped: Cell<usize>,
 }
 
 /// Static variable that holds the kernel's reference to the debug tool. This is
@@ -253,6 +316,7 @@ impl DebugWriter {
             output_buffer: TakeCell::new(out_buffer),
             internal_buffer: TakeCell::new(internal_buffer),
             count: Cell::new(0), // how many debug! calls
+            binary_dropped: Cell::new(0),
         }
     }
 
@@ -297,6 +361,33 @@ impl DebugWriter {
     fn extract(&self) -> Option<&mut RingBuffer<'static, u8>> {
         self.internal_buffer.take()
     }
+
+    /// Queue an encoded binary log message, preceded by how many messages
+    /// were dropped before it if any. The decoder cannot resynchronize after
+    /// part of a message, so messages that do not fit are dropped whole.
+    fn write_binary(&self, message: &[u8]) {
+        self.internal_buffer.map(|ring_buffer| {
+            let mut report = [0; 1 + MAX_VARINT];
+            let mut report_len = 0;
+            let dropped = self.binary_dropped.get();
+            if dropped > 0 {
+                report[0] = BINARY_DROPPED;
+                report_len = 1 + encode_varint(&mut report[1..], dropped as u64);
+            }
+            if ring_buffer.available_len() >= report_len + message.len() {
+                for &b in report[..report_len].iter().chain(message) {
+                    ring_buffer.enqueue(b);
+                }
+                self.binary_dropped.set(0);
+            } else {
+                self.binary_dropped.increment();
+            }
+        });
+    }
+
+    fn drop_binary(&self) {
+        self.binary_dropped.increment();
+    }
 }
 
 impl hil::uart::TransmitClient for DebugWriter {
@@ -333,6 +424,18 @@ impl DebugWriterWrapper {
     fn extract(&self) -> Option<&mut RingBuffer<'static, u8>> {
         self.dw.map_or(None, |dw| dw.extract())
     }
+
+    fn write_binary(&self, message: &[u8]) {
+        self.dw.map(|dw| {
+            dw.write_binary(message);
+        });
+    }
+
+    fn drop_binary(&self) {
+        self.dw.map(|dw| {
+            dw.drop_binary();
+        });
+    }
 }
 
 impl Write for DebugWriterWrapper {
@@ -428,6 +531,534 @@ macro_rules! debug_verbose {
     });
 }
 
+///////////////////////////////////////////////////////////////////
+// error!, warn!, info! and trace! support
+
+/// Severity of a log message, from the most to the least severe. `Off` only
+/// makes sense as a filter, to disable all messages.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Off = 0,
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Trace = 4,
+}
+
+impl Level {
+    /// Returns the level with the given lowercase name, as printed by
+    /// `name()`.
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "off" => Some(Level::Off),
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Off => "off",
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Trace => "trace",
+        }
+    }
+
+    // The label of messages with this level.
+    fn label(self) -> &'static str {
+        match self {
+            Level::Off => "",
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+/// The most verbose level of log messages that are compiled in. When several
+/// of the level features are enabled, the most verbose one wins.
+#[cfg(feature = "log_level_trace")]
+pub const MAX_LEVEL: Level = Level::Trace;
+#[cfg(not(any(
+    feature = "log_level_trace",
+    feature = "log_level_warn",
+    feature = "log_level_error",
+    feature = "log_level_off"
+)))]
+pub const MAX_LEVEL: Level = Level::Info;
+#[cfg(all(feature = "log_level_warn", not(feature = "log_level_trace")))]
+pub const MAX_LEVEL: Level = Level::Warn;
+#[cfg(all(
+    feature = "log_level_error",
+    not(any(feature = "log_level_trace", feature = "log_level_warn"))
+))]
+pub const MAX_LEVEL: Level = Level::Error;
+#[cfg(all(
+    feature = "log_level_off",
+    not(any(
+        feature = "log_level_trace",
+        feature = "log_level_warn",
+        feature = "log_level_error"
+    ))
+))]
+pub const MAX_LEVEL: Level = Level::Off;
+
+/// How many modules can have their own log level.
+pub const MAX_MODULE_FILTERS: usize = 8;
+/// How long the name of a module with its own log level can be.
+pub const MAX_MODULE_NAME: usize = 48;
+
+#[derive(Copy, Clone)]
+struct ModuleFilter {
+    module: [u8; MAX_MODULE_NAME],
+    length: usize,
+    level: Level,
+}
+
+impl ModuleFilter {
+    fn module(&self) -> &str {
+        // Only ever set from a whole `&str`, as `set_module_log_level` rejects
+        // names longer than `MAX_MODULE_NAME`.
+        str::from_utf8(&self.module[..self.length]).unwrap_or("")
+    }
+
+    // How specifically the filter applies to messages from `module`, or
+    // `None` if it does not: the filter names the module or a module it is
+    // part of, or the last part of its path, like `xmac` for
+    // `capsules::ieee802154::xmac`, which is as specific as the full path.
+    fn specificity(&self, module: &str) -> Option<usize> {
+        let name = self.module();
+        if module.starts_with(name) {
+            let rest = &module[name.len()..];
+            if rest.is_empty() || rest.starts_with("::") {
+                return Some(name.len());
+            }
+        }
+        if module.ends_with(name) && module[..module.len() - name.len()].ends_with("::") {
+            return Some(module.len());
+        }
+        None
+    }
+}
+
+/// Source of the timestamps of log messages.
+pub trait LogClock {
+    /// Returns the current time in milliseconds. The time can wrap around.
+    fn now_ms(&self) -> u64;
+}
+
+impl<T: hil::time::Time> LogClock for T {
+    fn now_ms(&self) -> u64 {
+        self.now() as u64 * 1000 / T::Frequency::frequency() as u64
+    }
+}
+
+static mut LOG_LEVEL: Level = Level::Info;
+static mut MODULE_FILTERS: [Option<ModuleFilter>; MAX_MODULE_FILTERS] = [None; MAX_MODULE_FILTERS];
+static mut LOG_CLOCK: Option<&'static dyn LogClock> = None;
+
+/// Timestamp log messages with `clock`, for example an alarm.
+pub fn set_log_clock(clock: &'static dyn LogClock) {
+    unsafe {
+        LOG_CLOCK = Some(clock);
+    }
+}
+
+/// Set the level of messages printed from modules without their own level.
+pub fn set_log_level(level: Level) {
+    unsafe {
+        LOG_LEVEL = level;
+    }
+}
+
+/// Returns the level of messages printed from modules without their own
+/// level.
+pub fn log_level() -> Level {
+    unsafe { LOG_LEVEL }
+}
+
+/// Set the level of messages printed from `module`, which overrides the
+/// default level. `module` is a module path, like `capsules::net`, or the last
+/// part of one, like `xmac`. The most specific level applies to a message.
+///
+/// Returns `ESIZE` if the name is longer than `MAX_MODULE_NAME` and `ENOMEM`
+/// if `MAX_MODULE_FILTERS` modules already have their own level.
+pub fn set_module_log_level(module: &str, level: Level) -> ReturnCode {
+    if module.len() > MAX_MODULE_NAME {
+        return ReturnCode::ESIZE;
+    }
+    let filters = unsafe { &mut MODULE_FILTERS };
+    let index = filters
+        .iter()
+        .position(|filter| filter.map_or(false, |filter| filter.module() == module))
+        .or_else(|| filters.iter().position(|filter| filter.is_none()));
+    match index {
+        Some(index) => {
+            let mut filter = ModuleFilter {
+                module: [0; MAX_MODULE_NAME],
+                length: module.len(),
+                level,
+            };
+            filter.module[..module.len()].copy_from_slice(module.as_bytes());
+            filters[index] = Some(filter);
+            ReturnCode::SUCCESS
+        }
+        None => ReturnCode::ENOMEM,
+    }
+}
+
+/// Remove the level of `module`, so the default level applies to it again.
+/// Returns `EINVAL` if the module does not have its own level.
+pub fn clear_module_log_level(module: &str) -> ReturnCode {
+    let filters = unsafe { &mut MODULE_FILTERS };
+    for filter in filters.iter_mut() {
+        if filter.map_or(false, |filter| filter.module() == module) {
+            *filter = None;
+            return ReturnCode::SUCCESS;
+        }
+    }
+    ReturnCode::EINVAL
+}
+
+/// Call `f` with each module that has its own log level and its level.
+pub fn each_module_log_level<F: FnMut(&str, Level)>(mut f: F) {
+    let filters = unsafe { &MODULE_FILTERS };
+    for filter in filters.iter() {
+        if let Some(filter) = filter {
+            f(filter.module(), filter.level);
+        }
+    }
+}
+
+/// Whether messages of `level` from `module` are printed at runtime. Used by
+/// the log macros after they checked `MAX_LEVEL`.
+pub fn log_enabled(level: Level, module: &str) -> bool {
+    let filters = unsafe { &MODULE_FILTERS };
+    let mut threshold = log_level();
+    let mut most_specific = 0;
+    for filter in filters.iter() {
+        if let Some(filter) = filter {
+            match filter.specificity(module) {
+                Some(specificity) if specificity > most_specific => {
+                    threshold = filter.level;
+                    most_specific = specificity;
+                }
+                _ => {}
+            }
+        }
+    }
+    level != Level::Off && level <= threshold
+}
+
+pub fn begin_log_fmt(args: Arguments, level: Level, module: &str) {
+    unsafe {
+        // Unlike `debug!`, messages are logged from paths that run before the
+        // board sets the writer, or in unit tests that have none, so they are
+        // dropped instead.
+        let writer = match ptr::read(&DEBUG_WRITER) {
+            Some(writer) => writer,
+            None => return,
+        };
+        if let Some(clock) = LOG_CLOCK {
+            let ms = clock.now_ms();
+            let _ = writer.write_fmt(format_args!("[{}.{:03}] ", ms / 1000, ms % 1000));
+        }
+        // The last part of the module path is enough to tell where a message
+        // comes from, and saves space in the debug buffer.
+        let name = module.rsplit("::").next().unwrap_or(module);
+        let _ = writer.write_fmt(format_args!("{} {}: ", level.label(), name));
+        let _ = write(writer, args);
+        let _ = writer.write_str("\r\n");
+        writer.publish_str();
+    }
+}
+
+/// Print a log message of the given `Level` if both `MAX_LEVEL` and the
+/// runtime level of the module allow it. Messages above `MAX_LEVEL` compile
+/// to nothing.
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => ({
+        let level: $crate::debug::Level = $level;
+        if level as u8 <= $crate::debug::MAX_LEVEL as u8
+            && $crate::debug::log_enabled(level, module_path!())
+        {
+            $crate::debug::begin_log_fmt(format_args!($($arg)+), level, module_path!())
+        }
+    });
+}
+
+/// Log an error: something failed and the kernel or a capsule cannot recover.
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => ($crate::log!($crate::debug::Level::Error, $($arg)+));
+}
+
+/// Log a warning: something failed, like a dropped packet, but the kernel or
+/// the capsule recovers.
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => ($crate::log!($crate::debug::Level::Warn, $($arg)+));
+}
+
+/// Log an event worth knowing about in normal operation.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => ($crate::log!($crate::debug::Level::Info, $($arg)+));
+}
+
+/// Log detailed tracing of what a module does, like protocol state changes.
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => ($crate::log!($crate::debug::Level::Trace, $($arg)+));
+}
+
+///////////////////////////////////////////////////////////////////
+// debug_binary! support
+
+/// Longest encoded `debug_binary!()` message. Longer messages are dropped.
+pub const MAX_BINARY_MESSAGE: usize = 64;
+
+// Longest LEB128 encoding of a u64.
+const MAX_VARINT: usize = 10;
+
+// Bytes that start binary log records. They never appear in UTF-8 text, so
+// the decoder can tell the records apart from the output of debug!().
+const BINARY_MESSAGE: u8 = 0xff;
+const BINARY_TIMESTAMPED_MESSAGE: u8 = 0xfe;
+const BINARY_DROPPED: u8 = 0xfd;
+
+// Type tags that precede each argument of a binary log message.
+const TAG_UNSIGNED: u8 = 0;
+const TAG_SIGNED: u8 = 1;
+const TAG_FALSE: u8 = 2;
+const TAG_TRUE: u8 = 3;
+const TAG_CHAR: u8 = 4;
+const TAG_STR: u8 = 5;
+const TAG_BYTES: u8 = 6;
+
+extern "C" {
+    // Start of the section of interned format strings, defined by the linker
+    // script, or by the linker when the section is not in a linker script.
+    static __start_tock_log: u8;
+}
+
+// Encode `value` as LEB128 into `buffer` and return the number of bytes
+// written. `buffer` must be at least `MAX_VARINT` bytes long.
+fn encode_varint(buffer: &mut [u8], mut value: u64) -> usize {
+    let mut length = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buffer[length] = byte;
+            return length + 1;
+        }
+        buffer[length] = byte | 0x80;
+        length += 1;
+    }
+}
+
+/// A `debug_binary!()` message being encoded: a record marker, the timestamp
+/// if the board set a log clock, the index of the format string and the
+/// arguments, each preceded by its type.
+pub struct BinaryMessage {
+    buffer: [u8; MAX_BINARY_MESSAGE],
+    length: usize,
+    overflow: bool,
+}
+
+impl BinaryMessage {
+    /// Start a message with the format string interned at `string` by
+    /// `debug_binary!()`. `string` is never read: it points into a section
+    /// that is not loaded, and only its offset in the section is sent.
+    pub fn new(string: *const u8) -> BinaryMessage {
+        let mut message = BinaryMessage {
+            buffer: [0; MAX_BINARY_MESSAGE],
+            length: 0,
+            overflow: false,
+        };
+        let index = string as usize - unsafe { &__start_tock_log as *const u8 as usize };
+        match unsafe { LOG_CLOCK } {
+            Some(clock) => {
+                message.push(BINARY_TIMESTAMPED_MESSAGE);
+                message.push_varint(clock.now_ms());
+            }
+            None => message.push(BINARY_MESSAGE),
+        }
+        message.push_varint(index as u64);
+        message
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.length < MAX_BINARY_MESSAGE {
+            self.buffer[self.length] = byte;
+            self.length += 1;
+        } else {
+            self.overflow = true;
+        }
+    }
+
+    fn push_varint(&mut self, value: u64) {
+        let mut buffer = [0; MAX_VARINT];
+        let length = encode_varint(&mut buffer, value);
+        for &b in &buffer[..length] {
+            self.push(b);
+        }
+    }
+
+    fn push_slice(&mut self, tag: u8, bytes: &[u8]) {
+        self.push(tag);
+        self.push_varint(bytes.len() as u64);
+        for &b in bytes {
+            self.push(b);
+        }
+    }
+
+    pub fn push_unsigned(&mut self, value: u64) {
+        self.push(TAG_UNSIGNED);
+        self.push_varint(value);
+    }
+
+    /// Zigzag encoded, so that small negative values stay short.
+    pub fn push_signed(&mut self, value: i64) {
+        self.push(TAG_SIGNED);
+        self.push_varint(((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    pub fn push_bool(&mut self, value: bool) {
+        self.push(if value { TAG_TRUE } else { TAG_FALSE });
+    }
+
+    pub fn push_char(&mut self, value: char) {
+        self.push(TAG_CHAR);
+        self.push_varint(value as u64);
+    }
+
+    pub fn push_str(&mut self, value: &str) {
+        self.push_slice(TAG_STR, value.as_bytes());
+    }
+
+    /// Formatted by the decoder like a `[u8]` with `{:?}` or `{:x?}`.
+    pub fn push_bytes(&mut self, value: &[u8]) {
+        self.push_slice(TAG_BYTES, value);
+    }
+}
+
+/// Arguments of `debug_binary!()`. Implement it for other types by pushing
+/// their fields to the message.
+pub trait BinaryArgument {
+    fn encode(&self, message: &mut BinaryMessage);
+}
+
+macro_rules! binary_argument {
+    ($push:ident, $as:ty, $($t:ty)*) => {
+        $(
+            impl BinaryArgument for $t {
+                fn encode(&self, message: &mut BinaryMessage) {
+                    message.$push(*self as $as);
+                }
+            }
+        )*
+    };
+}
+
+binary_argument!(push_unsigned, u64, u8 u16 u32 u64 usize);
+binary_argument!(push_signed, i64, i8 i16 i32 i64 isize);
+
+impl BinaryArgument for bool {
+    fn encode(&self, message: &mut BinaryMessage) {
+        message.push_bool(*self);
+    }
+}
+
+impl BinaryArgument for char {
+    fn encode(&self, message: &mut BinaryMessage) {
+        message.push_char(*self);
+    }
+}
+
+impl BinaryArgument for str {
+    fn encode(&self, message: &mut BinaryMessage) {
+        message.push_str(self);
+    }
+}
+
+impl BinaryArgument for [u8] {
+    fn encode(&self, message: &mut BinaryMessage) {
+        message.push_bytes(self);
+    }
+}
+
+impl<T: BinaryArgument + ?Sized> BinaryArgument for &T {
+    fn encode(&self, message: &mut BinaryMessage) {
+        (**self).encode(message);
+    }
+}
+
+pub fn begin_debug_binary(message: &BinaryMessage) {
+    unsafe {
+        let writer = get_debug_writer();
+        if message.overflow {
+            writer.drop_binary();
+        } else {
+            writer.write_binary(&message.buffer[..message.length]);
+            writer.publish_str();
+        }
+    }
+}
+
+/// Intern a format string of `debug_binary!()` in the `tock_log` section and
+/// return its address.
+#[doc(hidden)]
+#[macro_export]
+#[allow_internal_unsafe]
+macro_rules! debug_binary_string {
+    ($fmt:expr) => {{
+        const STRING: &str = concat!($fmt, "\0");
+        const LENGTH: usize = STRING.len();
+        // Only an array, rather than a reference, puts the bytes of the
+        // string in the section.
+        union Cast {
+            pointer: *const u8,
+            array: &'static [u8; LENGTH],
+        }
+        #[link_section = "tock_log"]
+        static INTERNED: [u8; LENGTH] = unsafe {
+            *Cast {
+                pointer: STRING.as_ptr(),
+            }
+            .array
+        };
+        &INTERNED as *const [u8; LENGTH] as *const u8
+    }};
+}
+
+/// In-kernel `println()` debugging that sends the index of the format string
+/// and the arguments in binary, for `tools/decode_binary_log.py` to format.
+#[macro_export]
+macro_rules! debug_binary {
+    ($fmt:expr $(, $arg:expr)* $(,)?) => ({
+        // Check the format string and the arguments like debug!() does,
+        // without the code to format them.
+        if false {
+            let _ = format_args!($fmt $(, $arg)*);
+        }
+        #[allow(unused_mut)]
+        let mut message = $crate::debug::BinaryMessage::new($crate::debug_binary_string!($fmt));
+        $(
+            $crate::debug::BinaryArgument::encode(&$arg, &mut message);
+        )*
+        $crate::debug::begin_debug_binary(&message);
+    });
+}
+
 pub trait Debug {
     fn write(&self, buf: &'static mut [u8], len: usize);
 }
@@ -442,6 +1073,14 @@ impl Default for Debug {
     }
 }
 
+/// Write what is left in the debug buffer to `writer`, for panic handlers.
+/// `debug_binary!()` records are skipped: `writer` only takes text, and
+/// only `tools/decode_binary_log.py` with the kernel ELF file can format them.
+///
+/// # Safety
+///
+/// Takes the buffer of the debug writer, so the kernel must not write debug
+/// output any more, as when it panics.
 pub unsafe fn flush<W: Write>(writer: &mut W) {
     let debug_writer = get_debug_writer();
 
@@ -452,14 +1091,206 @@ pub unsafe fn flush<W: Write>(writer: &mut W) {
             );
 
             let (left, right) = ring_buffer.as_slices();
-            if let Some(slice) = left {
-                let s = str::from_utf8_unchecked(slice);
-                let _ = writer.write_str(s);
+            let bytes = left
+                .unwrap_or(&[])
+                .iter()
+                .chain(right.unwrap_or(&[]).iter())
+                .cloned();
+            write_text(bytes, writer);
+        }
+    }
+}
+
+// Write the text in `bytes` to `writer`, skipping the records of
+// `debug_binary!()` and replacing bytes that are not UTF-8.
+fn write_text<W: Write>(bytes: impl Iterator<Item = u8>, writer: &mut W) {
+    let mut bytes = bytes.peekable();
+    let mut text = [0; 32];
+    let mut length = 0;
+    while let Some(byte) = bytes.next() {
+        match byte {
+            BINARY_MESSAGE | BINARY_TIMESTAMPED_MESSAGE | BINARY_DROPPED => {
+                length = write_utf8(&mut text, length, true, writer);
+                skip_binary_record(byte, &mut bytes);
             }
-            if let Some(slice) = right {
-                let s = str::from_utf8_unchecked(slice);
-                let _ = writer.write_str(s);
+            _ => {
+                text[length] = byte;
+                length += 1;
+                if length == text.len() {
+                    length = write_utf8(&mut text, length, false, writer);
+                }
             }
         }
     }
+    write_utf8(&mut text, length, true, writer);
+}
+
+// Write the first `length` bytes of `text` to `writer`. A character cut at
+// the end is moved to the start of `text`, and its length returned, unless
+// this is the `last` of the text.
+fn write_utf8<W: Write>(text: &mut [u8], length: usize, last: bool, writer: &mut W) -> usize {
+    let mut start = 0;
+    loop {
+        match str::from_utf8(&text[start..length]) {
+            Ok(valid) => {
+                let _ = writer.write_str(valid);
+                return 0;
+            }
+            Err(error) => {
+                let end = start + error.valid_up_to();
+                let _ = writer.write_str(str::from_utf8(&text[start..end]).unwrap_or(""));
+                match error.error_len() {
+                    None if !last => {
+                        text.copy_within(end..length, 0);
+                        return length - end;
+                    }
+                    invalid => {
+                        let _ = writer.write_char(core::char::REPLACEMENT_CHARACTER);
+                        start = end + invalid.unwrap_or(length - end);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Read a LEB128 number, as written by `encode_varint()`.
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> u64 {
+    let mut value = 0;
+    let mut shift = 0;
+    for byte in bytes {
+        if shift < 64 {
+            value |= ((byte & 0x7f) as u64) << shift;
+        }
+        shift += 7;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    value
+}
+
+// Skip the rest of the binary log record that starts with `marker`. How many
+// arguments a message has is only in its format string, so they end at the
+// first byte that is not a type tag. Text does not start with those.
+fn skip_binary_record<I: Iterator<Item = u8>>(marker: u8, bytes: &mut Peekable<I>) {
+    if marker == BINARY_DROPPED {
+        read_varint(bytes);
+        return;
+    }
+    if marker == BINARY_TIMESTAMPED_MESSAGE {
+        read_varint(bytes);
+    }
+    read_varint(bytes);
+    while bytes.peek().map_or(false, |&tag| tag <= TAG_BYTES) {
+        match bytes.next() {
+            Some(TAG_STR) | Some(TAG_BYTES) => {
+                let length = read_varint(bytes);
+                for _ in 0..length {
+                    bytes.next();
+                }
+            }
+            Some(TAG_FALSE) | Some(TAG_TRUE) => {}
+            _ => {
+                read_varint(bytes);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::{encode_varint, read_varint, write_text, Level, ModuleFilter, MAX_MODULE_NAME};
+    use super::{BinaryArgument, BinaryMessage, MAX_VARINT};
+    use super::{BINARY_MESSAGE, TAG_BYTES, TAG_SIGNED, TAG_STR, TAG_TRUE, TAG_UNSIGNED};
+    use std::string::String;
+
+    fn filter(module: &str) -> ModuleFilter {
+        let mut filter = ModuleFilter {
+            module: [0; MAX_MODULE_NAME],
+            length: module.len(),
+            level: Level::Trace,
+        };
+        filter.module[..module.len()].copy_from_slice(module.as_bytes());
+        filter
+    }
+
+    #[test]
+    fn module_filter_specificity() {
+        let module = "capsules::ieee802154::xmac";
+        assert_eq!(filter("capsules").specificity(module), Some(8));
+        assert_eq!(filter(module).specificity(module), Some(module.len()));
+        assert_eq!(filter("xmac").specificity(module), Some(module.len()));
+        assert_eq!(filter("caps").specificity(module), None);
+        assert_eq!(filter("mac").specificity(module), None);
+        assert_eq!(filter("ieee802154").specificity(module), None);
+    }
+
+    #[test]
+    fn varint_encoding() {
+        let mut buffer = [0; MAX_VARINT];
+        assert_eq!(encode_varint(&mut buffer, 0), 1);
+        assert_eq!(buffer[0], 0);
+        assert_eq!(encode_varint(&mut buffer, 127), 1);
+        assert_eq!(buffer[0], 0x7f);
+        assert_eq!(encode_varint(&mut buffer, 300), 2);
+        assert_eq!(buffer[..2], [0xac, 0x02]);
+        assert_eq!(encode_varint(&mut buffer, u64::max_value()), MAX_VARINT);
+        assert_eq!(buffer[MAX_VARINT - 1], 0x01);
+    }
+
+    #[test]
+    fn binary_message_round_trip() {
+        let string = crate::debug_binary_string!("{} {} {} {} {:?}");
+        let mut message = BinaryMessage::new(string);
+        300u32.encode(&mut message);
+        (-2i8).encode(&mut message);
+        true.encode(&mut message);
+        "ab".encode(&mut message);
+        (&[0xffu8, 0xfe][..]).encode(&mut message);
+        assert!(!message.overflow);
+
+        let mut bytes = message.buffer[..message.length].iter().cloned();
+        assert_eq!(bytes.next(), Some(BINARY_MESSAGE));
+        let start = unsafe { &super::__start_tock_log as *const u8 as usize };
+        assert_eq!(read_varint(&mut bytes), (string as usize - start) as u64);
+        assert_eq!(bytes.next(), Some(TAG_UNSIGNED));
+        assert_eq!(read_varint(&mut bytes), 300);
+        assert_eq!(bytes.next(), Some(TAG_SIGNED));
+        assert_eq!(read_varint(&mut bytes), 3);
+        assert_eq!(bytes.next(), Some(TAG_TRUE));
+        assert_eq!(bytes.next(), Some(TAG_STR));
+        assert_eq!(read_varint(&mut bytes), 2);
+        assert_eq!((bytes.next(), bytes.next()), (Some(b'a'), Some(b'b')));
+        assert_eq!(bytes.next(), Some(TAG_BYTES));
+        assert_eq!(read_varint(&mut bytes), 2);
+        assert_eq!((bytes.next(), bytes.next()), (Some(0xff), Some(0xfe)));
+        assert_eq!(bytes.next(), None);
+    }
+
+    #[test]
+    fn flush_skips_binary_records() {
+        let mut message = BinaryMessage::new(crate::debug_binary_string!("{}"));
+        "text".encode(&mut message);
+        let mut buffer = std::vec::Vec::new();
+        buffer.extend_from_slice("before é\n".as_bytes());
+        buffer.extend_from_slice(&message.buffer[..message.length]);
+        // Dropped messages, then a message without arguments.
+        buffer.extend_from_slice(&[0xfd, 0x81, 0x01, 0xfe, 0x10, 0x05]);
+        // Long enough to cut the last character between two writes.
+        buffer.extend_from_slice("after ".repeat(5).as_bytes());
+        buffer.push(0x80);
+        buffer.extend_from_slice("é".as_bytes());
+        // A character cut by the end of the buffer.
+        buffer.push(0xc3);
+
+        let mut text = String::new();
+        write_text(buffer.into_iter(), &mut text);
+        assert_eq!(
+            text,
+            "before é\nafter after after after after \u{fffd}é\u{fffd}"
+        );
+    }
 }
@@ -4,8 +4,8 @@
 //!
 //! # System-call Overview
 //!
-//! Tock supports four system calls. The `yield` system call is handled entirely
-//! by the scheduler, while three others are passed along to drivers:
+//! Tock supports five system calls. The `yield` system call is handled entirely
+//! by the scheduler, while four others are passed along to drivers:
 //!
 //!   * `subscribe` lets an application pass a callback to the driver to be
 //!   called later, when an event has occurred or data of interest is available.
@@ -14,9 +14,12 @@
 //!
 //!   * `allow` provides the driver access to an application buffer.
 //!
+//!   * `allow_readonly` provides the driver read access to an application
+//!   buffer, which may be in flash.
+//!
 //! ## Mapping system-calls to drivers
 //!
-//! Each of these three system calls takes at least two parameters. The first is
+//! Each of these four system calls takes at least two parameters. The first is
 //! a _driver major number_ and tells the scheduler which driver to forward the
 //! system call to. The second parameters is a _driver minor number_ and is used
 //! by the driver to differentiate system calls with different driver-specific
@@ -39,7 +42,7 @@
 //! understand its function and how it interacts with `subscribe`.
 
 use crate::callback::{AppId, Callback};
-use crate::mem::{AppSlice, Shared};
+use crate::mem::{AppSlice, ReadOnly, Shared};
 use crate::returncode::ReturnCode;
 
 /// `Driver`s implement the three driver-specific system calls: `subscribe`,
@@ -111,4 +114,20 @@ pub trait Driver {
     ) -> ReturnCode {
         ReturnCode::ENOSUPPORT
     }
+
+    /// `allow_readonly` lets an application give the driver read access to a
+    /// buffer in the application's memory or flash. This returns `ENOSUPPORT`
+    /// if not used.
+    ///
+    /// Drivers that only read a buffer should accept it here, so applications
+    /// can pass constant data without copying it to RAM first.
+    #[allow(unused_variables)]
+    fn allow_readonly(
+        &self,
+        app: AppId,
+        minor_num: usize,
+        slice: Option<AppSlice<ReadOnly, u8>>,
+    ) -> ReturnCode {
+        ReturnCode::ENOSUPPORT
+    }
 }
@@ -1,9 +1,11 @@
 //! Data structure to store a list of userspace applications.
 
+use core::any::type_name;
 use core::marker::PhantomData;
 use core::mem::{align_of, size_of};
 use core::ops::{Deref, DerefMut};
 use core::ptr::{write, write_volatile, Unique};
+use core::slice;
 
 use crate::callback::AppId;
 use crate::process::Error;
@@ -18,6 +20,7 @@ pub struct Grant<T: Default> {
 
 pub struct AppliedGrant<T> {
     appid: AppId,
+    grant_num: usize,
     grant: *mut T,
     _phantom: PhantomData<T>,
 }
@@ -28,7 +31,11 @@ impl<T> AppliedGrant<T> {
         F: FnOnce(&mut Owned<T>, &mut Allocator) -> R,
         R: Copy,
     {
-        let mut allocator = Allocator { appid: self.appid };
+        let mut allocator = Allocator {
+            appid: self.appid,
+            grant_num: self.grant_num,
+            grant_name: type_name::<T>(),
+        };
         let mut root = unsafe { Owned::new(self.grant, self.appid) };
         fun(&mut root, &mut allocator)
     }
@@ -36,6 +43,8 @@ impl<T> AppliedGrant<T> {
 
 pub struct Allocator {
     appid: AppId,
+    grant_num: usize,
+    grant_name: &'static str,
 }
 
 pub struct Owned<T: ?Sized> {
@@ -88,16 +97,48 @@ impl Allocator {
             self.appid
                 .kernel
                 .process_map_or(Err(Error::NoSuchApp), self.appid.idx(), |process| {
-                    process.alloc(size_of::<T>(), align_of::<T>()).map_or(
-                        Err(Error::OutOfMemory),
-                        |arr| {
+                    process
+                        .grant_alloc(
+                            self.grant_num,
+                            self.grant_name,
+                            size_of::<T>(),
+                            align_of::<T>(),
+                        )
+                        .map_or(Err(Error::OutOfMemory), |arr| {
                             let ptr = arr.as_mut_ptr() as *mut T;
                             // We use `ptr::write` to avoid `Drop`ping the uninitialized memory in
                             // case `T` implements the `Drop` trait.
                             write(ptr, data);
                             Ok(Owned::new(ptr, self.appid))
-                        },
-                    )
+                        })
+                })
+        }
+    }
+
+    /// Allocate a slice of `len` default values, for tables whose size is
+    /// only known at runtime, e.g. one entry per process.
+    pub fn alloc_default_slice<T: Default>(&mut self, len: usize) -> Result<Owned<[T]>, Error> {
+        unsafe {
+            self.appid
+                .kernel
+                .process_map_or(Err(Error::NoSuchApp), self.appid.idx(), |process| {
+                    process
+                        .grant_alloc(
+                            self.grant_num,
+                            self.grant_name,
+                            size_of::<T>() * len,
+                            align_of::<T>(),
+                        )
+                        .map_or(Err(Error::OutOfMemory), |arr| {
+                            let ptr = arr.as_mut_ptr() as *mut T;
+                            for i in 0..len {
+                                write(ptr.add(i), T::default());
+                            }
+                            Ok(Owned::new(
+                                slice::from_raw_parts_mut(ptr, len) as *mut [T],
+                                self.appid,
+                            ))
+                        })
                 })
         }
     }
@@ -152,6 +193,7 @@ impl<T: Default> Grant<T> {
                 } else {
                     Some(AppliedGrant {
                         appid: appid,
+                        grant_num: self.grant_num,
                         grant: cntr,
                         _phantom: PhantomData,
                     })
@@ -204,7 +246,12 @@ impl<T: Default> Grant<T> {
                     // memory needs to be allocated.
                     let new_grant = if (*ctr_ptr).is_null() {
                         process
-                            .alloc(size_of::<T>(), align_of::<T>())
+                            .grant_alloc(
+                                self.grant_num,
+                                type_name::<T>(),
+                                size_of::<T>(),
+                                align_of::<T>(),
+                            )
                             .map(|root_arr| {
                                 let root_ptr = root_arr.as_mut_ptr() as *mut T;
                                 // Initialize the grant contents using ptr::write, to
@@ -225,7 +272,11 @@ impl<T: Default> Grant<T> {
                     new_grant.map_or(Err(Error::OutOfMemory), move |root_ptr| {
                         let root_ptr = root_ptr as *mut T;
                         let mut root = Borrowed::new(&mut *root_ptr, appid);
-                        let mut allocator = Allocator { appid: appid };
+                        let mut allocator = Allocator {
+                            appid: appid,
+                            grant_num: self.grant_num,
+                            grant_name: type_name::<T>(),
+                        };
                         let res = fun(&mut root, &mut allocator);
                         Ok(res)
                     })
@@ -9,13 +9,25 @@ pub trait NonvolatileStorage<'a> {
 
     /// Read `length` bytes starting at address `address` in to the provided
     /// buffer. The buffer must be at least `length` bytes long. The address
-    /// must be in the address space of the physical storage.
-    fn read(&self, buffer: &'a mut [u8], address: usize, length: usize) -> ReturnCode;
+    /// must be in the address space of the physical storage. If the read
+    /// does not start, the buffer is returned with the error.
+    fn read(
+        &self,
+        buffer: &'a mut [u8],
+        address: usize,
+        length: usize,
+    ) -> (ReturnCode, Option<&'a mut [u8]>);
 
     /// Write `length` bytes starting at address `address` from the provided
     /// buffer. The buffer must be at least `length` bytes long. This address
-    /// must be in the address space of the physical storage.
-    fn write(&self, buffer: &'a mut [u8], address: usize, length: usize) -> ReturnCode;
+    /// must be in the address space of the physical storage. If the write
+    /// does not start, the buffer is returned with the error.
+    fn write(
+        &self,
+        buffer: &'a mut [u8],
+        address: usize,
+        length: usize,
+    ) -> (ReturnCode, Option<&'a mut [u8]>);
 }
 
 /// Client interface for nonvolatile storage.
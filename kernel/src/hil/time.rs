@@ -30,6 +30,15 @@ pub trait Frequency {
     fn frequency() -> u32;
 }
 
+/// 25MHz `Frequency`
+#[derive(Debug)]
+pub struct Freq25MHz;
+impl Frequency for Freq25MHz {
+    fn frequency() -> u32 {
+        25000000
+    }
+}
+
 /// 16MHz `Frequency`
 #[derive(Debug)]
 pub struct Freq16MHz;
@@ -39,6 +48,15 @@ impl Frequency for Freq16MHz {
     }
 }
 
+/// 10MHz `Frequency`
+#[derive(Debug)]
+pub struct Freq10MHz;
+impl Frequency for Freq10MHz {
+    fn frequency() -> u32 {
+        10000000
+    }
+}
+
 /// 32KHz `Frequency`
 #[derive(Debug)]
 pub struct Freq32KHz;
@@ -11,6 +11,7 @@
 //! correct capabilities to can use it.
 
 use core::cell::Cell;
+use core::ptr;
 
 use crate::callback::AppId;
 use crate::capabilities::ProcessManagementCapability;
@@ -18,6 +19,16 @@ use crate::common::cells::NumericCellExt;
 use crate::process;
 use crate::sched::Kernel;
 
+/// The unit of the CPU time statistics.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CpuTimeUnit {
+    /// CPU cycles, measured with the cycle counter of the board.
+    Cycles,
+    /// Microseconds, measured with the SysTick. The kernel can only measure
+    /// the time processes use this way, not its own.
+    Microseconds,
+}
+
 /// This struct provides the inspection functions.
 pub struct KernelInfo {
     kernel: &'static Kernel,
@@ -71,6 +82,91 @@ impl KernelInfo {
         count.get()
     }
 
+    /// Returns how many process slots this board supports. Processes are
+    /// identified by their slot index, so this is the upper bound on the
+    /// indices that can be passed to `process_appid()`.
+    pub fn number_process_slots(&self, _capability: &dyn ProcessManagementCapability) -> usize {
+        self.kernel.number_of_process_slots()
+    }
+
+    /// Returns the `AppId` of the process loaded in the given slot, or `None`
+    /// if there is no process in that slot.
+    pub fn process_appid(
+        &self,
+        index: usize,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Option<AppId> {
+        self.kernel
+            .process_map_or(None, index, |process| Some(process.appid()))
+    }
+
+    /// Returns the current state of the process, or `None` if the process no
+    /// longer exists.
+    pub fn process_state(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Option<process::State> {
+        self.kernel
+            .process_map_or(None, app.idx(), |process| Some(process.get_state()))
+    }
+
+    /// Returns the start address of the RAM allocated to the process.
+    pub fn process_mem_start(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> *const u8 {
+        self.kernel
+            .process_map_or(ptr::null(), app.idx(), |process| process.mem_start())
+    }
+
+    /// Returns the first address after the end of the RAM allocated to the
+    /// process.
+    pub fn process_mem_end(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> *const u8 {
+        self.kernel
+            .process_map_or(ptr::null(), app.idx(), |process| process.mem_end())
+    }
+
+    /// Returns the lowest address of the grant region of the process. Memory
+    /// between this address and `process_mem_end()` is used by the kernel on
+    /// behalf of the process.
+    pub fn process_kernel_memory_break(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> *const u8 {
+        self.kernel
+            .process_map_or(ptr::null(), app.idx(), |process| {
+                process.kernel_memory_break()
+            })
+    }
+
+    /// Returns the start address of the flash region of the process.
+    pub fn process_flash_start(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> *const u8 {
+        self.kernel
+            .process_map_or(ptr::null(), app.idx(), |process| process.flash_start())
+    }
+
+    /// Returns the first address after the end of the flash region of the
+    /// process.
+    pub fn process_flash_end(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> *const u8 {
+        self.kernel
+            .process_map_or(ptr::null(), app.idx(), |process| process.flash_end())
+    }
+
     /// Get the name of the process.
     pub fn process_name(
         &self,
@@ -125,6 +221,102 @@ impl KernelInfo {
         })
     }
 
+    /// Returns how many tasks are queued for the app and not yet handled.
+    pub fn process_queued_tasks(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> usize {
+        self.kernel
+            .process_map_or(0, app.idx(), |process| process.debug_queued_task_count())
+    }
+
+    /// Returns how many tasks are queued for all processes together, plus one
+    /// for each process that is running. The kernel only sleeps when this is
+    /// zero.
+    pub fn number_pending_tasks(&self, _capability: &dyn ProcessManagementCapability) -> usize {
+        self.kernel.work_count()
+    }
+
+    /// Returns how many grants the board has created. Grants are numbered from
+    /// zero in the order they were created.
+    pub fn number_grants(&self, _capability: &dyn ProcessManagementCapability) -> usize {
+        self.kernel.number_of_grants()
+    }
+
+    /// Returns how many bytes of the grant region of the app the kernel uses
+    /// for the given grant, including padding for alignment. Returns 0 if the
+    /// app has not used the grant.
+    pub fn process_grant_bytes(
+        &self,
+        app: AppId,
+        grant_num: usize,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> usize {
+        self.kernel.process_map_or(0, app.idx(), |process| {
+            process.debug_grant_bytes(grant_num).unwrap_or(0)
+        })
+    }
+
+    /// Returns the name of the type of the grant that the kernel most
+    /// recently failed to allocate because the app ran out of memory. The
+    /// name identifies the capsule that owns the grant.
+    pub fn process_failed_grant_alloc(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Option<&'static str> {
+        self.kernel.process_map_or(None, app.idx(), |process| {
+            process.debug_failed_grant_alloc()
+        })
+    }
+
+    /// Returns the unit of the CPU time statistics.
+    pub fn cpu_time_unit(&self, _capability: &dyn ProcessManagementCapability) -> CpuTimeUnit {
+        self.kernel.cpu_time_unit()
+    }
+
+    /// Returns how much CPU time the kernel measured in the sliding window of
+    /// the CPU time statistics, which covers the last one to two windows. With
+    /// a cycle counter this includes the time the kernel used, otherwise only
+    /// the time of processes.
+    pub fn cpu_window_time(&self, _capability: &dyn ProcessManagementCapability) -> u64 {
+        self.kernel.cpu_window_time()
+    }
+
+    /// Returns how much CPU time the app used in the sliding window of the CPU
+    /// time statistics, in userspace and in system calls.
+    pub fn process_cpu_window_time(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> u64 {
+        self.kernel
+            .process_map_or(0, app.idx(), |process| process.debug_cpu_window_time())
+    }
+
+    /// Returns how much CPU time the app used in userspace since it was
+    /// loaded.
+    pub fn process_cpu_time_userspace(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> u64 {
+        self.kernel
+            .process_map_or(0, app.idx(), |process| process.debug_cpu_time_userspace())
+    }
+
+    /// Returns how much CPU time the kernel used handling system calls of the
+    /// app since it was loaded.
+    pub fn process_cpu_time_syscall(
+        &self,
+        app: AppId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> u64 {
+        self.kernel
+            .process_map_or(0, app.idx(), |process| process.debug_cpu_time_syscall())
+    }
+
     /// Returns the total number of times all processes have exceeded
     /// their timeslices.
     pub fn timeslice_expirations(&self, _capability: &dyn ProcessManagementCapability) -> usize {
@@ -134,4 +326,24 @@ impl KernelInfo {
         });
         count.get()
     }
+
+    /// Returns how many interrupts the interrupt counter of the board keeps
+    /// counts for, or 0 if the board did not set one.
+    pub fn number_interrupts(&self, _capability: &dyn ProcessManagementCapability) -> usize {
+        self.kernel
+            .interrupt_counter()
+            .map_or(0, |counter| counter.number_of_interrupts())
+    }
+
+    /// Returns how many times the chip serviced the interrupt, or `None` if
+    /// the board does not count it.
+    pub fn interrupt_count(
+        &self,
+        interrupt: usize,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> Option<u32> {
+        self.kernel
+            .interrupt_counter()
+            .and_then(|counter| counter.interrupt_count(interrupt))
+    }
 }
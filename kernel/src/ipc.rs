@@ -2,11 +2,22 @@
 //!
 //! This is a special syscall driver that allows userspace applications to
 //! share memory.
+//!
+//! Besides sharing buffers, processes can send messages to services. The
+//! kernel copies a message from the buffer of the client to the buffer of the
+//! service and the reply back, so neither has to share memory. A client has at
+//! most one message in flight and waits for the reply, which arrives in its
+//! reply callback. A service handles one message at a time. Messages sent to
+//! a busy service wait in the kernel until it replies to the current one, or
+//! until the client of the current one stops or is terminated.
+
+use core::cell::Cell;
+use core::cmp;
 
 use crate::callback::{AppId, Callback};
 use crate::capabilities::MemoryAllocationCapability;
 use crate::driver::Driver;
-use crate::grant::Grant;
+use crate::grant::{Allocator, Grant, Owned};
 use crate::mem::{AppSlice, Shared};
 use crate::process;
 use crate::returncode::ReturnCode;
@@ -15,55 +26,170 @@ use crate::sched::Kernel;
 /// Syscall number
 pub const DRIVER_NUM: usize = 0x10000;
 
+/// Passed as the second argument of a service callback, instead of the length
+/// of a shared buffer, when the client was restarted. The first argument is
+/// the ID of the client as usual, and anything the client shared with the
+/// service before the restart is no longer accessible.
+pub const CLIENT_RESTARTED: usize = core::usize::MAX;
+
+/// Subscribe number of the callback a service receives messages with.
+pub const MESSAGE_CALLBACK: usize = core::usize::MAX;
+
+/// Subscribe number of the callback a client receives replies with.
+pub const REPLY_CALLBACK: usize = core::usize::MAX - 1;
+
+/// Allow number of the buffer that messages are sent from and received in.
+pub const MESSAGE_BUFFER: usize = core::usize::MAX;
+
+/// Time source for the timeouts of messages.
+///
+/// The kernel loop checks whether messages timed out, so `wake_at()` only has
+/// to make sure that the chip does not sleep past that time. See
+/// `capsules::ipc_timer` for an implementation based on an alarm.
+pub trait Timer {
+    /// The current time in tics. This wraps around.
+    fn now(&self) -> u32;
+
+    /// Convert a duration in milliseconds to tics.
+    fn ms_to_tics(&self, ms: u32) -> u32;
+
+    /// Wake up the chip at `tics`. Replaces an earlier request.
+    fn wake_at(&self, tics: u32);
+}
+
+/// A message a client sent and is waiting for a reply to.
+#[derive(Clone, Copy)]
+struct Call {
+    service: AppId,
+    len: usize,
+    /// Whether the message was copied to the service.
+    delivered: bool,
+    /// When the message was sent and how long the client waits for the reply,
+    /// in tics. A timeout of 0 means that the client waits forever.
+    start: u32,
+    timeout: u32,
+}
+
+/// What a process knows about one other process.
+#[derive(Default)]
+struct Peer {
+    /// Buffer this process shared with the other process.
+    shared_memory: Option<AppSlice<Shared, u8>>,
+    /// Callback for notifications from the other process, if it is a service.
+    client_callback: Option<Callback>,
+    /// Whether the other process notified this process as a service.
+    is_client: bool,
+    /// How long to wait for replies from the other process, in milliseconds.
+    /// 0 means forever.
+    call_timeout: u32,
+}
+
+#[derive(Default)]
 struct IPCData {
-    shared_memory: [Option<AppSlice<Shared, u8>>; 8],
-    client_callbacks: [Option<Callback>; 8],
+    /// Callback for notifications from clients, if this process is a service.
     callback: Option<Callback>,
+    /// One entry per process slot of the board. Allocated in the grant region
+    /// the first time it is needed.
+    peers: Option<Owned<[Peer]>>,
+    /// Buffer that messages are sent from and received in, with the replies.
+    message_buffer: Option<AppSlice<Shared, u8>>,
+    /// Callback for messages, if this process is a service.
+    message_callback: Option<Callback>,
+    /// Callback for replies to messages this process sent.
+    reply_callback: Option<Callback>,
+    /// The message this process sent, if it waits for a reply.
+    call: Option<Call>,
+    /// The client whose message this process is handling, if it is a service.
+    caller: Option<AppId>,
 }
 
-impl Default for IPCData {
-    fn default() -> IPCData {
-        IPCData {
-            shared_memory: [None, None, None, None, None, None, None, None],
-            client_callbacks: [None, None, None, None, None, None, None, None],
-            callback: None,
+impl IPCData {
+    /// The entry for the process with index `idx`, allocating the table if
+    /// needed.
+    fn peer(
+        &mut self,
+        allocator: &mut Allocator,
+        num_processes: usize,
+        idx: usize,
+    ) -> Result<&mut Peer, ReturnCode> {
+        if self.peers.is_none() {
+            self.peers = Some(
+                allocator
+                    .alloc_default_slice(num_processes)
+                    .map_err(|_| ReturnCode::ENOMEM)?,
+            );
         }
+        self.peers
+            .as_mut()
+            .and_then(|peers| peers.get_mut(idx))
+            .ok_or(ReturnCode::EINVAL)
+    }
+
+    /// The entry for the process with index `idx`, if the table exists.
+    fn existing_peer(&mut self, idx: usize) -> Option<&mut Peer> {
+        self.peers.as_mut().and_then(|peers| peers.get_mut(idx))
     }
 }
 
 pub struct IPC {
     data: Grant<IPCData>,
+    /// Messages never time out without a timer.
+    timer: Cell<Option<&'static dyn Timer>>,
+    /// Number of messages waiting for a reply that can time out. This may be
+    /// too large, until `check_timeouts()` counts them again.
+    timed_calls: Cell<usize>,
 }
 
 impl IPC {
     pub fn new(kernel: &'static Kernel, capability: &dyn MemoryAllocationCapability) -> IPC {
         IPC {
             data: kernel.create_grant(capability),
+            timer: Cell::new(None),
+            timed_calls: Cell::new(0),
         }
     }
 
+    /// Set the time source for the timeouts of messages.
+    pub fn set_timer(&self, timer: &'static dyn Timer) {
+        self.timer.set(Some(timer));
+    }
+
     pub unsafe fn schedule_callback(
         &self,
         appid: AppId,
         otherapp: AppId,
         cb_type: process::IPCType,
     ) {
+        let num_processes = self.data.kernel.number_of_process_slots();
         self.data
-            .enter(appid, |mydata, _| {
+            .enter(appid, |mydata, allocator| {
                 let callback = match cb_type {
-                    process::IPCType::Service => mydata.callback,
-                    process::IPCType::Client => {
-                        *mydata.client_callbacks.get(otherapp.idx()).unwrap_or(&None)
+                    process::IPCType::Service => {
+                        // The service now knows the client, so it is told
+                        // if the client restarts.
+                        if let Ok(peer) = mydata.peer(allocator, num_processes, otherapp.idx()) {
+                            peer.is_client = true;
+                        }
+                        mydata.callback
+                    }
+                    process::IPCType::Client => mydata
+                        .existing_peer(otherapp.idx())
+                        .and_then(|peer| peer.client_callback),
+                    process::IPCType::ClientRestarted => {
+                        mydata.callback.map(|mut callback| {
+                            callback.schedule(otherapp.idx() + 1, CLIENT_RESTARTED, 0)
+                        });
+                        return;
                     }
                 };
                 callback.map_or((), |mut callback| {
                     self.data
                         .enter(otherapp, |otherdata, _| {
-                            if appid.idx() >= otherdata.shared_memory.len() {
-                                return;
-                            }
-                            match otherdata.shared_memory[appid.idx()] {
-                                Some(ref slice) => {
+                            match otherdata
+                                .existing_peer(appid.idx())
+                                .and_then(|peer| peer.shared_memory.as_ref())
+                            {
+                                Some(slice) => {
                                     slice.expose_to(appid);
                                     callback.schedule(
                                         otherapp.idx() + 1,
@@ -81,6 +207,378 @@ impl IPC {
             })
             .unwrap_or(());
     }
+
+    /// Called when a process starts running from its init function, which
+    /// also happens after it is restarted. Services that the process was a
+    /// client of get a `CLIENT_RESTARTED` callback, and messages sent to the
+    /// process fail with ECANCEL.
+    crate fn process_started(&self, appid: AppId) {
+        for idx in 0..self.data.kernel.number_of_process_slots() {
+            let other = match self.process_appid(idx) {
+                Some(other) if other.idx() != appid.idx() => other,
+                _ => continue,
+            };
+            // Do not allocate a grant in processes that never used IPC.
+            let (was_client, was_caller, call) =
+                self.data
+                    .grant(other)
+                    .map_or((false, false, None), |grant| {
+                        grant.enter(|data, _| {
+                            let was_client =
+                                data.existing_peer(appid.idx()).map_or(false, |peer| {
+                                    let was_client = peer.is_client;
+                                    peer.is_client = false;
+                                    was_client
+                                });
+                            let was_caller = data
+                                .caller
+                                .map_or(false, |caller| caller.idx() == appid.idx());
+                            if was_caller {
+                                data.caller = None;
+                            }
+                            let call = match data.call {
+                                Some(call) if call.service.idx() == appid.idx() => data.call.take(),
+                                _ => None,
+                            };
+                            (was_client, was_caller, call)
+                        })
+                    });
+
+            if was_client || was_caller {
+                self.data.kernel.process_map_or((), idx, |service| {
+                    service.enqueue_task(process::Task::IPC((
+                        appid,
+                        process::IPCType::ClientRestarted,
+                    )));
+                });
+            }
+            if was_caller {
+                self.deliver_next(other);
+            }
+            call.map(|mut call| {
+                // The restarted service lost the message, so there is nothing
+                // to release.
+                call.delivered = false;
+                self.complete(other, call, 0, ReturnCode::ECANCEL);
+            });
+        }
+    }
+
+    /// Fail messages that waited too long for a reply with ENOACK, and ask the
+    /// timer to wake up the chip when the next one times out. The kernel loop
+    /// calls this while messages that can time out are waiting.
+    crate fn check_timeouts(&self) {
+        if self.timed_calls.get() == 0 {
+            return;
+        }
+        let timer = match self.timer.get() {
+            Some(timer) => timer,
+            None => return,
+        };
+        let now = timer.now();
+
+        let mut timed_calls = 0;
+        let mut next_timeout: Option<u32> = None;
+        for idx in 0..self.data.kernel.number_of_process_slots() {
+            let client = match self.process_appid(idx) {
+                Some(client) => client,
+                None => continue,
+            };
+            let expired = self.data.grant(client).and_then(|grant| {
+                grant.enter(|data, _| match data.call {
+                    Some(call) if call.timeout > 0 => {
+                        let elapsed = now.wrapping_sub(call.start);
+                        if elapsed >= call.timeout {
+                            data.call.take()
+                        } else {
+                            timed_calls += 1;
+                            let remaining = call.timeout - elapsed;
+                            next_timeout =
+                                Some(next_timeout.map_or(remaining, |t| cmp::min(t, remaining)));
+                            None
+                        }
+                    }
+                    _ => None,
+                })
+            });
+            expired.map(|call| self.complete(client, call, 0, ReturnCode::ENOACK));
+        }
+
+        self.timed_calls.set(timed_calls);
+        next_timeout.map(|remaining| timer.wake_at(now.wrapping_add(remaining)));
+    }
+
+    /// Release services whose caller was stopped or terminated, and fail the
+    /// message of the caller with ECANCEL. Processes stop outside of IPC, so
+    /// the kernel loop calls this.
+    crate fn release_stopped_callers(&self) {
+        for idx in 0..self.data.kernel.number_of_process_slots() {
+            let service = match self.process_appid(idx) {
+                Some(service) => service,
+                None => continue,
+            };
+            let caller = self
+                .data
+                .grant(service)
+                .and_then(|grant| grant.enter(|data, _| data.caller));
+            let caller = match caller {
+                Some(caller) if self.is_stopped(caller) => caller,
+                _ => continue,
+            };
+            let call = self.data.grant(caller).and_then(|grant| {
+                grant.enter(|data, _| match data.call {
+                    Some(call) if call.service.idx() == service.idx() => data.call.take(),
+                    _ => None,
+                })
+            });
+            match call {
+                Some(call) => self.complete(caller, call, 0, ReturnCode::ECANCEL),
+                None => self.release(service, caller),
+            }
+        }
+    }
+
+    fn is_stopped(&self, appid: AppId) -> bool {
+        self.data
+            .kernel
+            .process_map_or(true, appid.idx(), |process| match process.get_state() {
+                process::State::StoppedRunning
+                | process::State::StoppedYielded
+                | process::State::StoppedFaulted
+                | process::State::Terminated => true,
+                _ => false,
+            })
+    }
+
+    fn process_appid(&self, idx: usize) -> Option<AppId> {
+        self.data
+            .kernel
+            .process_map_or(None, idx, |process| Some(process.appid()))
+    }
+
+    /// Send the message in the buffer of `client` to `service`.
+    fn call(&self, client: AppId, service: AppId, len: usize) -> ReturnCode {
+        if client.idx() == service.idx() {
+            return ReturnCode::EINVAL;
+        }
+
+        // Check what can be checked now, the message is copied once the
+        // service is ready for it.
+        let service_buffer_len = self.data.grant(service).and_then(|grant| {
+            grant.enter(|data, _| match data.message_callback {
+                Some(_) => data.message_buffer.as_ref().map(|buffer| buffer.len()),
+                None => None,
+            })
+        });
+        match service_buffer_len {
+            None => return ReturnCode::ENODEVICE, /* Not a message service */
+            Some(service_buffer_len) if len > service_buffer_len => return ReturnCode::ESIZE,
+            Some(_) => {}
+        }
+
+        let timer = self.timer.get();
+        let ret = self
+            .data
+            .enter(client, |data, _| {
+                if data.call.is_some() {
+                    return ReturnCode::EBUSY;
+                }
+                if data
+                    .message_buffer
+                    .as_ref()
+                    .map_or(true, |buffer| len > buffer.len())
+                {
+                    return ReturnCode::ESIZE;
+                }
+                let timeout_ms = data
+                    .existing_peer(service.idx())
+                    .map_or(0, |peer| peer.call_timeout);
+                data.call = Some(Call {
+                    service: service,
+                    len: len,
+                    delivered: false,
+                    start: timer.map_or(0, |timer| timer.now()),
+                    timeout: match timer {
+                        Some(timer) if timeout_ms > 0 => cmp::max(timer.ms_to_tics(timeout_ms), 1),
+                        _ => 0,
+                    },
+                });
+                ReturnCode::SUCCESS
+            })
+            .unwrap_or(ReturnCode::EBUSY);
+
+        if ret == ReturnCode::SUCCESS {
+            self.deliver_next(service);
+            if timer.is_some() {
+                self.timed_calls.set(self.timed_calls.get() + 1);
+                self.check_timeouts();
+            }
+        }
+        ret
+    }
+
+    /// Copy the reply in the buffer of `service` to `client`, which sent the
+    /// message the service is handling.
+    fn reply(&self, service: AppId, client: AppId, len: usize) -> ReturnCode {
+        let reply = self
+            .data
+            .grant(service)
+            .map_or(Err(ReturnCode::EINVAL), |grant| {
+                grant.enter(|data, _| {
+                    if data
+                        .caller
+                        .map_or(true, |caller| caller.idx() != client.idx())
+                    {
+                        return Err(ReturnCode::EINVAL); /* Not the caller */
+                    }
+                    match data.message_buffer {
+                        Some(ref buffer) if len <= buffer.len() => Ok(buffer.ptr()),
+                        _ => Err(ReturnCode::ESIZE),
+                    }
+                })
+            });
+        let reply = match reply {
+            Ok(reply) => reply,
+            Err(err) => return err,
+        };
+
+        let call = self.data.grant(client).and_then(|grant| {
+            grant.enter(|data, _| match data.call {
+                Some(call) if call.service.idx() == service.idx() && call.delivered => {
+                    data.call.take()
+                }
+                _ => None,
+            })
+        });
+        let call = match call {
+            Some(call) => call,
+            None => {
+                // The client is gone, so the service moves on to the next
+                // message.
+                self.release(service, client);
+                return ReturnCode::EINVAL;
+            }
+        };
+
+        let ret = self.data.grant(client).map_or(ReturnCode::EINVAL, |grant| {
+            grant.enter(|data, _| {
+                match data.message_buffer {
+                    Some(ref mut buffer) if len <= buffer.len() => {
+                        let reply = unsafe { core::slice::from_raw_parts(reply, len) };
+                        buffer.as_mut()[..len].copy_from_slice(reply);
+                    }
+                    Some(_) => return ReturnCode::ESIZE,
+                    None => return ReturnCode::EINVAL, /* No buffer for the reply */
+                }
+                data.reply_callback.map(|mut callback| {
+                    callback.schedule(service.idx() + 1, len, 0);
+                });
+                ReturnCode::SUCCESS
+            })
+        });
+
+        if ret == ReturnCode::SUCCESS {
+            self.release(service, client);
+        } else {
+            // The client gets the error instead of the reply.
+            self.complete(client, call, 0, ret);
+        }
+        ret
+    }
+
+    /// Finish a message of `client` that did not get a reply with the error
+    /// `result`.
+    fn complete(&self, client: AppId, call: Call, len: usize, result: ReturnCode) {
+        self.data.grant(client).map(|grant| {
+            grant.enter(|data, _| {
+                data.reply_callback.map(|mut callback| {
+                    callback.schedule(call.service.idx() + 1, len, isize::from(result) as usize);
+                });
+            })
+        });
+        if call.delivered {
+            self.release(call.service, client);
+        }
+    }
+
+    /// Let `service` handle the next message after the one from `client`.
+    fn release(&self, service: AppId, client: AppId) {
+        let released = self.data.grant(service).map_or(false, |grant| {
+            grant.enter(|data, _| match data.caller {
+                Some(caller) if caller.idx() == client.idx() => {
+                    data.caller = None;
+                    true
+                }
+                _ => false,
+            })
+        });
+        if released {
+            self.deliver_next(service);
+        }
+    }
+
+    /// Copy the next waiting message to `service`, unless it is handling one
+    /// already. Waiting messages are delivered in the order of the processes.
+    fn deliver_next(&self, service: AppId) {
+        let ready = self.data.grant(service).and_then(|grant| {
+            grant.enter(|data, _| match (data.caller, data.message_callback) {
+                (None, Some(callback)) => data
+                    .message_buffer
+                    .as_mut()
+                    .map(|buffer| (buffer.as_mut().as_mut_ptr(), buffer.len(), callback)),
+                _ => None,
+            })
+        });
+        let (buffer, buffer_len, mut callback) = match ready {
+            Some(ready) => ready,
+            None => return,
+        };
+
+        for idx in 0..self.data.kernel.number_of_process_slots() {
+            let client = match self.process_appid(idx) {
+                Some(client) => client,
+                None => continue,
+            };
+            let delivered = self.data.grant(client).and_then(|grant| {
+                grant.enter(|data, _| {
+                    let mut call = match data.call {
+                        Some(call) if call.service.idx() == service.idx() && !call.delivered => {
+                            call
+                        }
+                        _ => return None,
+                    };
+                    match data.message_buffer {
+                        Some(ref message)
+                            if call.len <= message.len() && call.len <= buffer_len =>
+                        {
+                            let buffer =
+                                unsafe { core::slice::from_raw_parts_mut(buffer, call.len) };
+                            buffer.copy_from_slice(&message.as_ref()[..call.len]);
+                            call.delivered = true;
+                            data.call = Some(call);
+                            Some(Ok(call.len))
+                        }
+                        // The buffer changed since the message was sent.
+                        _ => {
+                            data.call = None;
+                            Some(Err(call))
+                        }
+                    }
+                })
+            });
+            match delivered {
+                Some(Ok(len)) => {
+                    self.data
+                        .grant(service)
+                        .map(|grant| grant.enter(|data, _| data.caller = Some(client)));
+                    callback.schedule(client.idx() + 1, len, 0);
+                    return;
+                }
+                Some(Err(call)) => self.complete(client, call, 0, ReturnCode::ESIZE),
+                None => {}
+            }
+        }
+    }
 }
 
 impl Driver for IPC {
@@ -109,6 +607,36 @@ impl Driver for IPC {
                 })
                 .unwrap_or(ReturnCode::EBUSY),
 
+            // subscribe(MESSAGE_CALLBACK)
+            //
+            // The callback is called with the ID of the client and the
+            // length of the message, once the message was copied to the
+            // message buffer. The service replies with command().
+            MESSAGE_CALLBACK => {
+                let ret = self
+                    .data
+                    .enter(app_id, |data, _| {
+                        data.message_callback = callback;
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or(ReturnCode::EBUSY);
+                self.deliver_next(app_id);
+                ret
+            }
+
+            // subscribe(REPLY_CALLBACK)
+            //
+            // The callback is called with the ID of the service, the length
+            // of the reply and a return code, once the reply was copied to the
+            // message buffer or the message failed.
+            REPLY_CALLBACK => self
+                .data
+                .enter(app_id, |data, _| {
+                    data.reply_callback = callback;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or(ReturnCode::EBUSY),
+
             // subscribe(>=1)
             //
             // Subscribe with subscribe_num >= 1 is how a client registers
@@ -117,43 +645,72 @@ impl Driver for IPC {
             // Once subscribed, the client will receive callbacks when the
             // service process calls notify_client().
             svc_id => {
-                if svc_id - 1 >= 8 {
-                    ReturnCode::EINVAL /* Maximum of 8 IPC's exceeded */
-                } else {
-                    self.data
-                        .enter(app_id, |data, _| {
-                            data.client_callbacks[svc_id - 1] = callback;
-                            ReturnCode::SUCCESS
-                        })
-                        .unwrap_or(ReturnCode::EBUSY)
-                }
+                let num_processes = self.data.kernel.number_of_process_slots();
+                self.data
+                    .enter(app_id, |data, allocator| {
+                        match data.peer(allocator, num_processes, svc_id - 1) {
+                            Ok(peer) => {
+                                peer.client_callback = callback;
+                                ReturnCode::SUCCESS
+                            }
+                            Err(err) => err, /* No process with this ID */
+                        }
+                    })
+                    .unwrap_or(ReturnCode::EBUSY)
             }
         }
     }
 
-    /// command is how notify() is implemented.
-    /// Notifying an IPC service is done by setting client_or_svc to 0,
-    /// and notifying an IPC client is done by setting client_or_svc to 1.
-    /// In either case, the target_id is the same number as provided in a notify
-    /// callback or as returned by allow.
+    /// command is how notify() and messages are implemented. The target_id
+    /// is the same number as provided in a callback or as returned by allow.
+    ///
+    /// - `0`: Notify the IPC service target_id.
+    /// - `1`: Notify the IPC client target_id.
+    /// - `2`: Send the first `arg` bytes of the message buffer to the service
+    ///   target_id. Returns ENODEVICE if it does not handle messages, ESIZE
+    ///   if a buffer is too small and EBUSY if a message is in flight.
+    /// - `3`: Reply to the message of the client target_id with the first
+    ///   `arg` bytes of the message buffer. If the buffer of the client is
+    ///   missing or too small, its message fails with the same error.
+    /// - `4`: Fail messages to the service target_id with ENOACK if it does
+    ///   not reply within `arg` milliseconds. 0 disables the timeout.
     ///
     /// Returns EINVAL if the other process doesn't exist.
-    fn command(
-        &self,
-        target_id: usize,
-        client_or_svc: usize,
-        _: usize,
-        appid: AppId,
-    ) -> ReturnCode {
-        let cb_type = if client_or_svc == 0 {
-            process::IPCType::Service
-        } else {
-            process::IPCType::Client
+    fn command(&self, target_id: usize, operation: usize, arg: usize, appid: AppId) -> ReturnCode {
+        let target = match target_id
+            .checked_sub(1)
+            .and_then(|idx| self.process_appid(idx))
+        {
+            Some(target) => target,
+            None => return ReturnCode::EINVAL,
+        };
+
+        let cb_type = match operation {
+            0 => process::IPCType::Service,
+            1 => process::IPCType::Client,
+            2 => return self.call(appid, target, arg),
+            3 => return self.reply(appid, target, arg),
+            4 => {
+                let num_processes = self.data.kernel.number_of_process_slots();
+                return self
+                    .data
+                    .enter(appid, |data, allocator| {
+                        match data.peer(allocator, num_processes, target.idx()) {
+                            Ok(peer) => {
+                                peer.call_timeout = arg as u32;
+                                ReturnCode::SUCCESS
+                            }
+                            Err(err) => err,
+                        }
+                    })
+                    .unwrap_or(ReturnCode::EBUSY);
+            }
+            _ => return ReturnCode::ENOSUPPORT,
         };
 
         self.data
             .kernel
-            .process_map_or(ReturnCode::EINVAL, target_id - 1, |target| {
+            .process_map_or(ReturnCode::EINVAL, target.idx(), |target| {
                 let ret = target.enqueue_task(process::Task::IPC((appid, cb_type)));
                 match ret {
                     true => ReturnCode::SUCCESS,
@@ -166,15 +723,19 @@ impl Driver for IPC {
     /// share buffers with existing services.
     ///
     /// If allow is called with target_id == 0, it is an IPC service discover
-    /// call. The contents of the slice should be the string name of the IPC
-    /// service. If this mechanism can find that service, allow will return
-    /// an ID that can be used to notify that service. Otherwise an error will
-    /// be returned.
+    /// call. The contents of the slice should be the package name of the IPC
+    /// service, as stored in its TBF header. If a process with that name
+    /// exists, allow will return an ID that can be used to notify that
+    /// service. Otherwise ENODEVICE is returned.
     ///
     /// If allow is called with target_id >= 1, it is a share command where the
     /// application is explicitly sharing a slice with an IPC service (as
     /// specified by the target_id). allow() simply allows both processes to
     /// access the buffer, it does not signal the service.
+    ///
+    /// If allow is called with target_id == MESSAGE_BUFFER, the slice becomes
+    /// the buffer that messages are sent from and that messages and replies
+    /// are copied to.
     fn allow(
         &self,
         appid: AppId,
@@ -182,7 +743,7 @@ impl Driver for IPC {
         slice: Option<AppSlice<Shared, u8>>,
     ) -> ReturnCode {
         if target_id == 0 {
-            match slice {
+            return match slice {
                 Some(slice_data) => {
                     let ret = self.data.kernel.process_until(|p| {
                         let s = p.get_process_name().as_bytes();
@@ -198,24 +759,249 @@ impl Driver for IPC {
                         }
                     });
                     if ret != ReturnCode::FAIL {
-                        return ret;
+                        ret
+                    } else {
+                        ReturnCode::ENODEVICE /* No service with this name */
                     }
                 }
-                None => {}
-            }
-
-            return ReturnCode::EINVAL; /* AppSlice must have non-zero length */
+                None => ReturnCode::EINVAL, /* AppSlice must have non-zero length */
+            };
         }
+        if target_id == MESSAGE_BUFFER {
+            let ret = self
+                .data
+                .enter(appid, |data, _| {
+                    data.message_buffer = slice;
+                    ReturnCode::SUCCESS
+                })
+                .unwrap_or(ReturnCode::EBUSY);
+            self.deliver_next(appid);
+            return ret;
+        }
+        let num_processes = self.data.kernel.number_of_process_slots();
         self.data
-            .enter(appid, |data, _| {
-                data.shared_memory.get_mut(target_id - 1).map_or(
-                    ReturnCode::EINVAL, /* Target process does not exist */
-                    |smem| {
-                        *smem = slice;
+            .enter(appid, |data, allocator| {
+                match data.peer(allocator, num_processes, target_id - 1) {
+                    Ok(peer) => {
+                        peer.shared_memory = slice;
                         ReturnCode::SUCCESS
-                    },
-                )
+                    }
+                    Err(err) => err, /* Target process does not exist */
+                }
             })
             .unwrap_or(ReturnCode::EBUSY)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use core::ptr::NonNull;
+
+    use super::*;
+    use crate::callback::{CallbackId, CallbackOverflow};
+    use crate::process::{FaultResponse, ProcessType, Task};
+    use crate::test_support::{TestCapability, TestKernel};
+
+    // Where the message buffers start in the memory of the processes
+    const BUFFER_OFFSET: usize = 2048;
+
+    /// Creates a process in `index` and runs its init function.
+    fn start(test: &TestKernel, index: usize) -> &'static dyn ProcessType {
+        let process = test.create_process(index, FaultResponse::Stop);
+        match process.dequeue_task() {
+            Some(Task::FunctionCall(init)) => unsafe { process.set_process_function(init) },
+            _ => panic!("no init function"),
+        }
+        process
+    }
+
+    /// Subscribes to `subscribe_num`, with the subscribe number as the
+    /// argument of the callback.
+    fn subscribe(ipc: &IPC, process: &dyn ProcessType, subscribe_num: usize) {
+        let callback = Callback::new(
+            process.appid(),
+            CallbackId {
+                driver_num: DRIVER_NUM,
+                subscribe_num,
+            },
+            subscribe_num,
+            NonNull::dangling(),
+            CallbackOverflow::DropNewest,
+        );
+        let ret = ipc.subscribe(subscribe_num, Some(callback), process.appid());
+        assert_eq!(ret, ReturnCode::SUCCESS);
+    }
+
+    /// Allows `len` bytes of the memory of `process` as its message buffer,
+    /// and returns them.
+    fn allow_buffer(ipc: &IPC, process: &dyn ProcessType, len: usize) -> &'static mut [u8] {
+        let start = unsafe { process.mem_start().add(BUFFER_OFFSET) };
+        let slice = process.allow(start, len).expect("buffer not allowed");
+        assert_eq!(
+            ipc.allow(process.appid(), MESSAGE_BUFFER, slice),
+            ReturnCode::SUCCESS
+        );
+        unsafe { core::slice::from_raw_parts_mut(start as *mut u8, len) }
+    }
+
+    /// The subscribe number and arguments of the next callback of `process`.
+    fn callback(process: &dyn ProcessType) -> Option<(usize, usize, usize, usize)> {
+        match process.dequeue_task() {
+            Some(Task::FunctionCall(call)) => Some((
+                call.argument3,
+                call.argument0,
+                call.argument1,
+                call.argument2,
+            )),
+            _ => None,
+        }
+    }
+
+    fn error(ret: ReturnCode) -> usize {
+        isize::from(ret) as usize
+    }
+
+    // A service in slot 1 with a 4 byte buffer, and clients in slots 0 and 2
+    // with 8 byte buffers.
+    struct Apps {
+        client: &'static dyn ProcessType,
+        service: &'static dyn ProcessType,
+        other: &'static dyn ProcessType,
+        client_buffer: &'static mut [u8],
+        service_buffer: &'static mut [u8],
+    }
+
+    fn apps(test: &TestKernel, ipc: &IPC) -> Apps {
+        let client = start(test, 0);
+        let service = start(test, 1);
+        let other = start(test, 2);
+        subscribe(ipc, service, MESSAGE_CALLBACK);
+        let service_buffer = allow_buffer(ipc, service, 4);
+        subscribe(ipc, client, REPLY_CALLBACK);
+        let client_buffer = allow_buffer(ipc, client, 8);
+        subscribe(ipc, other, REPLY_CALLBACK);
+        allow_buffer(ipc, other, 8);
+        Apps {
+            client,
+            service,
+            other,
+            client_buffer,
+            service_buffer,
+        }
+    }
+
+    /// Sends a message from `client` to the service in slot 1.
+    fn call(ipc: &IPC, client: &dyn ProcessType, len: usize) -> ReturnCode {
+        ipc.command(2, 2, len, client.appid())
+    }
+
+    fn reply(
+        ipc: &IPC,
+        service: &dyn ProcessType,
+        client: &dyn ProcessType,
+        len: usize,
+    ) -> ReturnCode {
+        ipc.command(client.appid().idx() + 1, 3, len, service.appid())
+    }
+
+    #[test]
+    fn replies_reach_client() {
+        let test = TestKernel::new(3);
+        let ipc = IPC::new(test.kernel, &TestCapability);
+        let apps = apps(&test, &ipc);
+
+        apps.client_buffer[..3].copy_from_slice(b"abc");
+        assert_eq!(call(&ipc, apps.client, 3), ReturnCode::SUCCESS);
+        assert_eq!(callback(apps.service), Some((MESSAGE_CALLBACK, 1, 3, 0)));
+        assert_eq!(&apps.service_buffer[..3], b"abc");
+        // The other client waits until the service replied
+        assert_eq!(call(&ipc, apps.other, 1), ReturnCode::SUCCESS);
+        assert_eq!(callback(apps.service), None);
+        // Only the caller gets a reply
+        assert_eq!(reply(&ipc, apps.service, apps.other, 1), ReturnCode::EINVAL);
+
+        apps.service_buffer.copy_from_slice(b"wxyz");
+        assert_eq!(
+            reply(&ipc, apps.service, apps.client, 4),
+            ReturnCode::SUCCESS
+        );
+        assert_eq!(callback(apps.client), Some((REPLY_CALLBACK, 2, 4, 0)));
+        assert_eq!(&apps.client_buffer[..4], b"wxyz");
+        assert_eq!(callback(apps.service), Some((MESSAGE_CALLBACK, 3, 1, 0)));
+    }
+
+    #[test]
+    fn failed_reply_releases_service() {
+        let test = TestKernel::new(3);
+        let ipc = IPC::new(test.kernel, &TestCapability);
+        let apps = apps(&test, &ipc);
+
+        assert_eq!(call(&ipc, apps.client, 4), ReturnCode::SUCCESS);
+        assert_eq!(call(&ipc, apps.other, 4), ReturnCode::SUCCESS);
+        callback(apps.service);
+        // The client shrinks its buffer below the length of the reply
+        allow_buffer(&ipc, apps.client, 2);
+        assert_eq!(reply(&ipc, apps.service, apps.client, 4), ReturnCode::ESIZE);
+        assert_eq!(
+            callback(apps.client),
+            Some((REPLY_CALLBACK, 2, 0, error(ReturnCode::ESIZE)))
+        );
+        // And the service moves on to the next message
+        assert_eq!(callback(apps.service), Some((MESSAGE_CALLBACK, 3, 4, 0)));
+
+        // A client without a buffer cannot take the reply either
+        ipc.allow(apps.other.appid(), MESSAGE_BUFFER, None);
+        assert_eq!(reply(&ipc, apps.service, apps.other, 4), ReturnCode::EINVAL);
+        assert_eq!(
+            callback(apps.other),
+            Some((REPLY_CALLBACK, 2, 0, error(ReturnCode::EINVAL)))
+        );
+        allow_buffer(&ipc, apps.client, 8);
+        assert_eq!(call(&ipc, apps.client, 4), ReturnCode::SUCCESS);
+        assert_eq!(callback(apps.service), Some((MESSAGE_CALLBACK, 1, 4, 0)));
+    }
+
+    #[test]
+    fn stopped_callers_release_service() {
+        let test = TestKernel::new(3);
+        let ipc = IPC::new(test.kernel, &TestCapability);
+        let apps = apps(&test, &ipc);
+
+        assert_eq!(call(&ipc, apps.client, 4), ReturnCode::SUCCESS);
+        assert_eq!(call(&ipc, apps.other, 4), ReturnCode::SUCCESS);
+        callback(apps.service);
+        ipc.release_stopped_callers();
+        assert_eq!(callback(apps.service), None);
+
+        apps.client.stop();
+        ipc.release_stopped_callers();
+        assert_eq!(
+            callback(apps.client),
+            Some((REPLY_CALLBACK, 2, 0, error(ReturnCode::ECANCEL)))
+        );
+        assert_eq!(callback(apps.service), Some((MESSAGE_CALLBACK, 3, 4, 0)));
+
+        apps.other.terminate();
+        ipc.release_stopped_callers();
+        apps.client.resume();
+        assert_eq!(call(&ipc, apps.client, 4), ReturnCode::SUCCESS);
+        assert_eq!(callback(apps.service), Some((MESSAGE_CALLBACK, 1, 4, 0)));
+    }
+
+    #[test]
+    fn reply_to_terminated_client_releases_service() {
+        let test = TestKernel::new(3);
+        let ipc = IPC::new(test.kernel, &TestCapability);
+        let apps = apps(&test, &ipc);
+
+        assert_eq!(call(&ipc, apps.client, 4), ReturnCode::SUCCESS);
+        assert_eq!(call(&ipc, apps.other, 4), ReturnCode::SUCCESS);
+        assert_eq!(callback(apps.service), Some((MESSAGE_CALLBACK, 1, 4, 0)));
+        apps.client.terminate();
+        assert_eq!(
+            reply(&ipc, apps.service, apps.client, 4),
+            ReturnCode::EINVAL
+        );
+        assert_eq!(callback(apps.service), Some((MESSAGE_CALLBACK, 3, 4, 0)));
+    }
+}
@@ -9,7 +9,7 @@
 #![feature(core_intrinsics, ptr_internals, const_fn)]
 #![feature(panic_info_message)]
 #![feature(in_band_lifetimes, crate_visibility_modifier)]
-#![feature(associated_type_defaults)]
+#![feature(associated_type_defaults, allow_internal_unsafe)]
 #![warn(unreachable_pub)]
 #![no_std]
 
@@ -32,13 +32,17 @@ mod process;
 mod returncode;
 mod sched;
 mod tbfheader;
+#[cfg(test)]
+mod test_support;
 
-pub use crate::callback::{AppId, Callback};
+pub use crate::callback::{AppId, Callback, CallbackOverflow};
 pub use crate::driver::Driver;
 pub use crate::grant::Grant;
-pub use crate::mem::{AppPtr, AppSlice, Private, Shared};
+pub use crate::mem::{AppPtr, AppSlice, Private, ReadOnly, Shared};
+pub use crate::platform::cycle_counter::CycleCounter;
+pub use crate::platform::interrupt_counter::InterruptCounter;
 pub use crate::platform::systick::SysTick;
-pub use crate::platform::{mpu, Chip, Platform};
+pub use crate::platform::{mpu, watchdog, Chip, Platform};
 pub use crate::platform::{ClockInterface, NoClockControl, NO_CLOCK_CONTROL};
 pub use crate::returncode::ReturnCode;
 pub use crate::sched::Kernel;
@@ -49,5 +53,8 @@ pub use crate::sched::Kernel;
 // processes.
 /// Publicly available process-related objects.
 pub mod procs {
-    pub use crate::process::{load_processes, FaultResponse, FunctionCall, Process, ProcessType};
+    pub use crate::process::{
+        load_processes, FaultResponse, FunctionCall, FunctionCallSource, Process, ProcessDebugger,
+        ProcessType, State, MAX_CALLBACK_QUEUE_DEPTH,
+    };
 }
@@ -15,6 +15,11 @@ pub struct Private;
 #[derive(Debug)]
 pub struct Shared;
 
+/// Type for specifying an AppSlice is shared with the kernel, which may only
+/// read it. The memory may be in flash.
+#[derive(Debug)]
+pub struct ReadOnly;
+
 /// Base type for an AppSlice that holds the raw pointer to the memory region
 /// the app shared with the kernel.
 pub struct AppPtr<L, T> {
@@ -107,26 +112,38 @@ impl<L, T> AppSlice<L, T> {
         self.as_ref().iter()
     }
 
-    pub fn iter_mut(&mut self) -> slice::IterMut<T> {
-        self.as_mut().iter_mut()
-    }
-
     pub fn chunks(&self, size: usize) -> slice::Chunks<T> {
         self.as_ref().chunks(size)
     }
+}
+
+impl<T> AppSlice<Shared, T> {
+    pub fn iter_mut(&mut self) -> slice::IterMut<T> {
+        self.as_mut().iter_mut()
+    }
 
     pub fn chunks_mut(&mut self, size: usize) -> slice::ChunksMut<T> {
         self.as_mut().chunks_mut(size)
     }
 }
 
+/// A buffer the kernel may write to can be used where the kernel only reads,
+/// so drivers can accept a buffer from both kinds of allow.
+impl<T> From<AppSlice<Shared, T>> for AppSlice<ReadOnly, T> {
+    fn from(slice: AppSlice<Shared, T>) -> AppSlice<ReadOnly, T> {
+        // Do not drop the old `AppPtr`, the new one takes it over.
+        let slice = core::mem::ManuallyDrop::new(slice);
+        AppSlice::new(slice.ptr.ptr.as_ptr(), slice.len, slice.ptr.process)
+    }
+}
+
 impl<L, T> AsRef<[T]> for AppSlice<L, T> {
     fn as_ref(&self) -> &[T] {
         unsafe { slice::from_raw_parts(self.ptr.ptr.as_ref(), self.len) }
     }
 }
 
-impl<L, T> AsMut<[T]> for AppSlice<L, T> {
+impl<T> AsMut<[T]> for AppSlice<Shared, T> {
     fn as_mut(&mut self) -> &mut [T] {
         unsafe { slice::from_raw_parts_mut(self.ptr.ptr.as_mut(), self.len) }
     }
@@ -36,6 +36,8 @@ use crate::returncode::ReturnCode;
 ///   where the app has put the start of its heap. This is not strictly
 ///   necessary for correct operation, but allows for better debugging if the
 ///   app crashes.
+/// - `13`: Get how many bytes of the app's grant region the kernel uses for
+///   the grant indexed from 0 by r1. Returns EINVAL if there is no such grant.
 crate fn memop(process: &dyn ProcessType, op_type: usize, r1: usize) -> ReturnCode {
     match op_type {
         // Op Type 0: BRK
@@ -108,6 +110,12 @@ crate fn memop(process: &dyn ProcessType, op_type: usize, r1: usize) -> ReturnCo
             ReturnCode::SUCCESS
         }
 
+        // Op Type 13: Grant memory used by the grant indexed by r1.
+        13 => match process.debug_grant_bytes(r1) {
+            Some(bytes) => ReturnCode::SuccessWithValue { value: bytes },
+            None => ReturnCode::EINVAL,
+        },
+
         _ => ReturnCode::ENOSUPPORT,
     }
 }
@@ -0,0 +1,17 @@
+//! Interface for counting the CPU cycles the kernel and processes use.
+
+/// A free running counter of CPU cycles.
+///
+/// The kernel reads the counter when it switches to and from processes to
+/// account how much CPU time each process uses. Boards pass the counter to the
+/// kernel with `Kernel::set_cycle_counter()`. Without a counter, the kernel
+/// measures CPU time with the `SysTick` in microseconds, if the `SysTick` can
+/// tell how much time is left.
+///
+/// Most cores with a cycle counter stop it while the core sleeps, so the
+/// counter measures active time rather than wall clock time.
+pub trait CycleCounter {
+    /// Returns the current value of the counter. The counter wraps around, so
+    /// callers must only use the difference of two values.
+    fn cycles(&self) -> u32;
+}
@@ -0,0 +1,18 @@
+//! Interface for counting how often the chip services each interrupt.
+
+/// Per-interrupt counts of how many times the chip serviced each hardware
+/// interrupt.
+///
+/// The kernel does not use the counts itself. Boards pass the counter to the
+/// kernel with `Kernel::set_interrupt_counter()` so that capsules can read it
+/// through `KernelInfo`, for example to find an interrupt that fires far more
+/// often than expected.
+pub trait InterruptCounter {
+    /// Returns how many interrupts the counter keeps counts for. Interrupts are
+    /// numbered as the chip numbers them, from zero.
+    fn number_of_interrupts(&self) -> usize;
+
+    /// Returns how many times the chip serviced interrupt `interrupt`, or
+    /// `None` if there is no count for it. The count wraps around.
+    fn interrupt_count(&self, interrupt: usize) -> Option<u32>;
+}
@@ -3,8 +3,11 @@
 use crate::driver::Driver;
 use crate::syscall;
 
+crate mod cycle_counter;
+crate mod interrupt_counter;
 pub mod mpu;
 crate mod systick;
+pub mod watchdog;
 
 /// Interface for individual boards.
 ///
@@ -42,6 +45,12 @@ pub trait Platform {
     fn with_driver<F, R>(&self, driver_num: usize, f: F) -> R
     where
         F: FnOnce(Option<&dyn Driver>) -> R;
+
+    /// The watchdog the kernel loop services. Boards that do not use a
+    /// watchdog can rely on the default, which does nothing.
+    fn watchdog(&self) -> &dyn watchdog::WatchDog {
+        &()
+    }
 }
 
 /// Interface for individual MCUs.
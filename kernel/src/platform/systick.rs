@@ -36,6 +36,15 @@ pub trait SysTick {
     ///
     ///   * `with_interrupt` - if set, an expiring timer will fire an interrupt.
     fn enable(&self, with_interrupt: bool);
+
+    /// Returns how many microseconds are left before the timer expires, or
+    /// `None` if the implementation cannot tell.
+    ///
+    /// The kernel uses this to measure the CPU time of processes when the
+    /// board has no `CycleCounter`.
+    fn remaining_us(&self) -> Option<u32> {
+        None
+    }
 }
 
 /// A dummy `SysTick` implementation in which the timer never expires.
@@ -0,0 +1,38 @@
+//! Interface for the watchdog serviced by the kernel loop.
+
+/// A watchdog that the kernel services as it runs.
+///
+/// The kernel calls `setup()` once before entering the main loop and then
+/// calls `tickle()` on every iteration of the loop. If the kernel stops making
+/// progress, for example because an interrupt handler or a capsule is stuck in
+/// an infinite loop, the watchdog is no longer tickled and should reset the
+/// board.
+///
+/// Since the kernel does not run while the chip sleeps, `suspend()` is called
+/// right before the chip goes to sleep and `resume()` right after it wakes up.
+/// Implementations that use a hardware watchdog which keeps counting in sleep
+/// should keep it running, so that a kernel that never wakes up again is
+/// caught too, and make sure in `suspend()` that the chip wakes up in time to
+/// tickle it, e.g. with an alarm.
+///
+/// Implementations are free to decide not to pass a `tickle()` on to the
+/// hardware, for example if some other part of the system they supervise has
+/// stopped making progress.
+pub trait WatchDog {
+    /// Start the watchdog. Called once before the kernel loop starts.
+    fn setup(&self) {}
+
+    /// Service the watchdog. Called once per iteration of the kernel loop.
+    fn tickle(&self) {}
+
+    /// Called before the chip goes to sleep.
+    fn suspend(&self) {}
+
+    /// Called when the chip wakes up from sleep.
+    fn resume(&self) {
+        self.tickle();
+    }
+}
+
+/// Implement default WatchDog trait for unit, for boards that do not use one.
+impl WatchDog for () {}
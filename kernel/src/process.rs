@@ -5,11 +5,11 @@ use core::fmt::Write;
 use core::ptr::write_volatile;
 use core::{mem, ptr, slice, str};
 
-use crate::callback::{AppId, CallbackId};
+use crate::callback::{AppId, CallbackId, CallbackOverflow};
 use crate::capabilities::ProcessManagementCapability;
 use crate::common::cells::MapCell;
 use crate::common::{Queue, RingBuffer};
-use crate::mem::{AppSlice, Shared};
+use crate::mem::{AppSlice, ReadOnly, Shared};
 use crate::platform::mpu::{self, MPU};
 use crate::platform::Chip;
 use crate::returncode::ReturnCode;
@@ -18,6 +18,12 @@ use crate::syscall::{self, Syscall, UserspaceKernelBoundary};
 use crate::tbfheader;
 use core::cmp::max;
 
+/// Deepest callback queue a process can have, whether its TBF header or the
+/// board sets the depth. Processes asking for more are not loaded. This keeps
+/// a broken or hostile header from making the kernel allocate the whole
+/// memory of the process, or more, for its callbacks.
+pub const MAX_CALLBACK_QUEUE_DEPTH: usize = 256;
+
 /// Helper function to load processes from flash into an array of active
 /// processes. This is the default template for loading processes, but a board
 /// is able to create its own `load_processes()` function and use that instead.
@@ -91,6 +97,12 @@ pub trait ProcessType {
     /// this is passed to the capsule that tried to schedule the `Task`.
     fn enqueue_task(&self, task: Task) -> bool;
 
+    /// Queue a call to a callback the process subscribed to. If the queue is
+    /// full, `overflow` decides which call is dropped.
+    ///
+    /// This function returns `false` if the call was dropped.
+    fn enqueue_callback(&self, call: FunctionCall, overflow: CallbackOverflow) -> bool;
+
     /// Remove the scheduled operation from the front of the queue and return it
     /// to be handled by the scheduler.
     ///
@@ -119,6 +131,15 @@ pub trait ProcessType {
     /// `FaultResponse` for this process to occur.
     fn set_fault_state(&self);
 
+    /// Reset this process and start it again from its entry point, whatever
+    /// state it is in, as if it faulted with `FaultResponse::Restart`.
+    fn restart(&self);
+
+    /// Remove the work queued for this process and its grant regions and move
+    /// it into the terminated state. The kernel does not run it again unless
+    /// it is restarted.
+    fn terminate(&self);
+
     /// Get the name of the process. Used for IPC.
     fn get_process_name(&self) -> &'static str;
 
@@ -165,6 +186,10 @@ pub trait ProcessType {
     /// Also optional.
     fn update_heap_start_pointer(&self, heap_pointer: *const u8);
 
+    /// Get what happens when the callback queue is full for the callbacks of
+    /// a subscription. The process sets this in its TBF header.
+    fn get_callback_overflow(&self, callback_id: CallbackId) -> CallbackOverflow;
+
     // additional memop like functions
 
     /// Creates an `AppSlice` from the given offset and size in process memory.
@@ -180,6 +205,14 @@ pub trait ProcessType {
         size: usize,
     ) -> Result<Option<AppSlice<Shared, u8>>, ReturnCode>;
 
+    /// Like `allow()`, but the buffer may also be in the flash of the process,
+    /// and the kernel only reads it.
+    fn allow_readonly(
+        &self,
+        buf_start_addr: *const u8,
+        size: usize,
+    ) -> Result<Option<AppSlice<ReadOnly, u8>>, ReturnCode>;
+
     /// Get the first address of process's flash that isn't protected by the
     /// kernel. The protected range of flash contains the TBF header and
     /// potentially other state the kernel is storing on behalf of the process,
@@ -208,6 +241,17 @@ pub trait ProcessType {
 
     unsafe fn free(&self, _: *mut u8);
 
+    /// Allocate memory from the grant region for grant number `grant_num`,
+    /// and account for it. `grant_name` identifies the grant if the process
+    /// runs out of memory.
+    unsafe fn grant_alloc(
+        &self,
+        grant_num: usize,
+        grant_name: &'static str,
+        size: usize,
+        align: usize,
+    ) -> Option<&mut [u8]>;
+
     /// Get a pointer to the grant pointer for this grant number.
     unsafe fn grant_ptr(&self, grant_num: usize) -> *mut *mut u8;
 
@@ -226,6 +270,41 @@ pub trait ProcessType {
     unsafe fn fault_fmt(&self, writer: &mut dyn Write);
     unsafe fn process_detail_fmt(&self, writer: &mut dyn Write);
 
+    // debugger
+
+    /// GDB target description of the registers of the process, or an empty
+    /// string if the architecture does not support debugging processes.
+    fn debugger_target_description(&self) -> &'static str;
+
+    /// Read a register of the process, numbered as in the target description.
+    fn debugger_read_register(&self, register: usize) -> Option<usize>;
+
+    /// Write a register of the process, numbered as in the target
+    /// description.
+    fn debugger_write_register(&self, register: usize, value: usize) -> ReturnCode;
+
+    /// The instruction that traps into the kernel when the process executes
+    /// it, for the given GDB breakpoint kind.
+    fn debugger_breakpoint_instruction(&self, kind: usize) -> Option<&'static [u8]>;
+
+    /// Whether the last fault of the process was caused by a breakpoint
+    /// instruction.
+    fn debugger_breakpoint_hit(&self) -> bool;
+
+    /// Addresses the process may execute next after the instruction it
+    /// resumes at: the following instruction and, for a branch, its target.
+    /// Each comes with the breakpoint kind to trap there.
+    fn debugger_step_addresses(&self) -> [Option<(usize, usize)>; 2];
+
+    /// Copy memory of the process at `address` into `buffer`. The memory must
+    /// be in the RAM or the flash of the process.
+    fn debugger_read_memory(&self, address: usize, buffer: &mut [u8]) -> ReturnCode;
+
+    /// Copy `data` into the RAM of the process at `address`. The memory must
+    /// be accessible to the process, so the kernel data in the grant region
+    /// cannot be written.
+    fn debugger_write_memory(&self, address: usize, data: &[u8]) -> ReturnCode;
+
     // debug
 
     /// Returns how many syscalls this app has called.
@@ -234,6 +313,9 @@ pub trait ProcessType {
     /// Returns how many callbacks for this process have been dropped.
     fn debug_dropped_callback_count(&self) -> usize;
 
+    /// Returns how many tasks are queued for this process.
+    fn debug_queued_task_count(&self) -> usize;
+
     /// Returns how many times this process has been restarted.
     fn debug_restart_count(&self) -> usize;
 
@@ -241,6 +323,33 @@ pub trait ProcessType {
     fn debug_timeslice_expiration_count(&self) -> usize;
 
     fn debug_timeslice_expired(&self);
+
+    /// Returns how many bytes of process memory the kernel has allocated for
+    /// grant number `grant_num`, including alignment padding, or `None` if
+    /// there is no such grant.
+    fn debug_grant_bytes(&self, grant_num: usize) -> Option<usize>;
+
+    /// Returns the name of the grant type whose allocation most recently
+    /// failed because the process ran out of memory, if any.
+    fn debug_failed_grant_alloc(&self) -> Option<&'static str>;
+
+    /// Account CPU time the process used in userspace and in the kernel
+    /// handling its system calls. The unit is the one the kernel measures CPU
+    /// time in.
+    fn debug_add_cpu_time(&self, userspace: u32, syscall: u32);
+
+    /// Returns how much CPU time the process used in userspace since it was
+    /// loaded, including before restarts.
+    fn debug_cpu_time_userspace(&self) -> u64;
+
+    /// Returns how much CPU time the kernel used handling system calls of the
+    /// process since it was loaded, including before restarts.
+    fn debug_cpu_time_syscall(&self) -> u64;
+
+    /// Returns how much CPU time the process used, in userspace and in system
+    /// calls, in the current and the previous window of the CPU time
+    /// statistics.
+    fn debug_cpu_window_time(&self) -> u64;
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -293,6 +402,10 @@ pub enum State {
     /// and continue executing other things.
     StoppedFaulted,
 
+    /// The process was terminated on request, for example from the process
+    /// console. It stays in this state until it is restarted.
+    Terminated,
+
     /// The process has caused a fault.
     Fault,
 
@@ -325,10 +438,22 @@ pub enum FaultResponse {
     Stop,
 }
 
+/// A debugger that processes trap into when they execute a breakpoint
+/// instruction. A board registers it with `Kernel::set_debugger()`.
+pub trait ProcessDebugger {
+    /// Called when the process `appid` faulted because it executed a
+    /// breakpoint instruction. If the debugger handles the breakpoint and
+    /// returns `true`, the kernel stops the process instead of handling the
+    /// fault.
+    fn breakpoint_hit(&self, appid: AppId) -> bool;
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum IPCType {
     Service,
     Client,
+    /// A client of the service was restarted, and what it shared is gone.
+    ClientRestarted,
 }
 
 #[derive(Copy, Clone)]
@@ -341,7 +466,7 @@ pub enum Task {
 /// kernel or from a callback subscribed through a driver.
 ///
 /// An example of kernel function is the application entry point.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum FunctionCallSource {
     Kernel, // For functions coming directly from the kernel, such as `init_fn`.
     Driver(CallbackId),
@@ -399,6 +524,33 @@ struct ProcessDebug {
     /// How many times this process has been paused because it exceeded its
     /// timeslice.
     timeslice_expiration_count: usize,
+
+    /// Which grant the kernel could not allocate memory for last.
+    failed_grant_alloc: Option<&'static str>,
+
+    /// CPU time used in userspace.
+    cpu_time_userspace: u64,
+
+    /// CPU time the kernel used handling system calls.
+    cpu_time_syscall: u64,
+
+    /// CPU time used in the current and the previous window of the CPU time
+    /// statistics.
+    cpu_window_time: (u32, u32),
+
+    /// The window `cpu_window_time` was last updated in.
+    cpu_window_epoch: usize,
+}
+
+/// Move CPU window times from window `from` forward to window `to`.
+fn cpu_window_at(window_time: (u32, u32), from: usize, to: usize) -> (u32, u32) {
+    if from == to {
+        window_time
+    } else if from.wrapping_add(1) == to {
+        (0, window_time.0)
+    } else {
+        (0, 0)
+    }
 }
 
 pub struct Process<'a, C: 'static + Chip> {
@@ -493,6 +645,9 @@ pub struct Process<'a, C: 'static + Chip> {
     /// process.
     tasks: MapCell<RingBuffer<'a, Task>>,
 
+    /// How many bytes of the grant region each grant uses.
+    grant_usage: &'a [Cell<usize>],
+
     /// Name of the app.
     process_name: &'static str,
 
@@ -514,19 +669,19 @@ impl<C: Chip> ProcessType for Process<'a, C> {
     }
 
     fn enqueue_task(&self, task: Task) -> bool {
-        // If this app is in the `Fault` state then we shouldn't schedule
-        // any work for it.
-        if self.state.get() == State::Fault {
+        // If this app is in the `Fault` or `Terminated` state then we
+        // shouldn't schedule any work for it.
+        if self.state.get() == State::Fault || self.state.get() == State::Terminated {
             return false;
         }
 
-        self.kernel.increment_work();
-
         let ret = self.tasks.map_or(false, |tasks| tasks.enqueue(task));
 
         // Make a note that we lost this callback if the enqueue function
         // fails.
-        if ret == false {
+        if ret {
+            self.kernel.increment_work();
+        } else {
             self.debug.map(|debug| {
                 debug.dropped_callback_count += 1;
             });
@@ -535,6 +690,43 @@ impl<C: Chip> ProcessType for Process<'a, C> {
         ret
     }
 
+    fn enqueue_callback(&self, call: FunctionCall, overflow: CallbackOverflow) -> bool {
+        if self.state.get() == State::Fault || self.state.get() == State::Terminated {
+            return false;
+        }
+
+        let same_callback = |task: &Task| match task {
+            Task::FunctionCall(queued) => queued.source == call.source,
+            _ => false,
+        };
+
+        // Make room for the new call by removing a queued call of the same
+        // callback, if the policy asks for it. The new call takes over the
+        // work item of the removed call.
+        let replaced = self.tasks.map_or(false, |tasks| {
+            let remove = match overflow {
+                CallbackOverflow::DropNewest => false,
+                CallbackOverflow::DropOldest => tasks.is_full(),
+                CallbackOverflow::Coalesce => true,
+            };
+            remove && tasks.remove_first_matching(same_callback).is_some()
+        });
+
+        if !replaced {
+            return self.enqueue_task(Task::FunctionCall(call));
+        }
+
+        // Coalescing is what the process asked for, only count calls that
+        // were dropped because the queue was full.
+        if overflow == CallbackOverflow::DropOldest {
+            self.debug.map(|debug| {
+                debug.dropped_callback_count += 1;
+            });
+        }
+        self.tasks.map(|tasks| tasks.enqueue(Task::FunctionCall(call)));
+        true
+    }
+
     fn remove_pending_callbacks(&self, callback_id: CallbackId) {
         self.tasks.map(|tasks| {
             tasks.retain(|task| match task {
@@ -577,6 +769,7 @@ impl<C: Chip> ProcessType for Process<'a, C> {
     }
 
     fn set_fault_state(&self) {
+        let previous_state = self.state.get();
         self.state.set(State::Fault);
 
         match self.fault_response {
@@ -585,66 +778,7 @@ impl<C: Chip> ProcessType for Process<'a, C> {
                 panic!("Process {} had a fault", self.process_name);
             }
             FaultResponse::Restart => {
-                // Remove the tasks that were scheduled for the app from the
-                // amount of work queue.
-                let tasks_len = self.tasks.map_or(0, |tasks| tasks.len());
-                for _ in 0..tasks_len {
-                    self.kernel.decrement_work();
-                }
-
-                // And remove those tasks
-                self.tasks.map(|tasks| {
-                    tasks.empty();
-                });
-
-                // Update debug information
-                self.debug.map(|debug| {
-                    // Mark that we restarted this process.
-                    debug.restart_count += 1;
-
-                    // Reset some state for the process.
-                    debug.syscall_count = 0;
-                    debug.last_syscall = None;
-                    debug.dropped_callback_count = 0;
-                });
-
-                // We are going to start this process over again, so need
-                // the init_fn location.
-                let app_flash_address = self.flash_start();
-                let init_fn = unsafe {
-                    app_flash_address.offset(self.header.get_init_function_offset() as isize)
-                        as usize
-                };
-                self.state.set(State::Unstarted);
-
-                // Need to reset the grant region.
-                unsafe {
-                    self.grant_ptrs_reset();
-                }
-                self.kernel_memory_break
-                    .set(self.original_kernel_memory_break);
-
-                // Reset other memory pointers.
-                self.app_break.set(self.original_app_break);
-                self.current_stack_pointer.set(self.original_stack_pointer);
-
-                // And queue up this app to be restarted.
-                let flash_protected_size = self.header.get_protected_size() as usize;
-                let flash_app_start = app_flash_address as usize + flash_protected_size;
-
-                self.tasks.map(|tasks| {
-                    tasks.empty();
-                    tasks.enqueue(Task::FunctionCall(FunctionCall {
-                        source: FunctionCallSource::Kernel,
-                        pc: init_fn,
-                        argument0: flash_app_start,
-                        argument1: self.memory.as_ptr() as usize,
-                        argument2: self.memory.len() as usize,
-                        argument3: self.app_break.get() as usize,
-                    }));
-                });
-
-                self.kernel.increment_work();
+                self.restart_process(previous_state);
             }
             FaultResponse::Stop => {
                 // This looks a lot like restart, except we just leave the app
@@ -652,18 +786,7 @@ impl<C: Chip> ProcessType for Process<'a, C> {
                 // all of the app's todo work it will not be scheduled, and
                 // clearing all of the grant regions will cause capsules to drop
                 // this app as well.
-
-                // Remove the tasks that were scheduled for the app from the
-                // amount of work queue.
-                let tasks_len = self.tasks.map_or(0, |tasks| tasks.len());
-                for _ in 0..tasks_len {
-                    self.kernel.decrement_work();
-                }
-
-                // And remove those tasks
-                self.tasks.map(|tasks| {
-                    tasks.empty();
-                });
+                self.remove_process_work(previous_state);
 
                 // Clear any grant regions this app has setup with any capsules.
                 unsafe {
@@ -676,6 +799,22 @@ impl<C: Chip> ProcessType for Process<'a, C> {
         }
     }
 
+    fn restart(&self) {
+        let previous_state = self.state.get();
+        self.restart_process(previous_state);
+    }
+
+    fn terminate(&self) {
+        let previous_state = self.state.get();
+        self.remove_process_work(previous_state);
+
+        // Capsules drop the app once its grant regions are gone.
+        unsafe {
+            self.grant_ptrs_reset();
+        }
+        self.state.set(State::Terminated);
+    }
+
     fn dequeue_task(&self) -> Option<Task> {
         self.tasks.map_or(None, |tasks| {
             tasks.dequeue().map(|cb| {
@@ -729,6 +868,14 @@ impl<C: Chip> ProcessType for Process<'a, C> {
         }
     }
 
+    fn get_callback_overflow(&self, callback_id: CallbackId) -> CallbackOverflow {
+        // The policies were checked when the process was loaded.
+        self.header
+            .get_callback_overflow(callback_id.driver_num, callback_id.subscribe_num)
+            .and_then(CallbackOverflow::from_u32)
+            .unwrap_or_default()
+    }
+
     fn update_heap_start_pointer(&self, heap_pointer: *const u8) {
         if heap_pointer >= self.mem_start() && heap_pointer < self.mem_end() {
             self.debug.map(|debug| {
@@ -826,6 +973,35 @@ impl<C: Chip> ProcessType for Process<'a, C> {
         }
     }
 
+    fn allow_readonly(
+        &self,
+        buf_start_addr: *const u8,
+        size: usize,
+    ) -> Result<Option<AppSlice<ReadOnly, u8>>, ReturnCode> {
+        if buf_start_addr == ptr::null() {
+            Ok(None)
+        } else if self.in_app_owned_memory(buf_start_addr, size) {
+            // Like `allow()`, the process must not move its break below the
+            // buffer.
+            let buf_end_addr = buf_start_addr.wrapping_add(size);
+            let new_water_mark = max(self.allow_high_water_mark.get(), buf_end_addr);
+            self.allow_high_water_mark.set(new_water_mark);
+            Ok(Some(AppSlice::new(
+                buf_start_addr as *mut u8,
+                size,
+                self.appid(),
+            )))
+        } else if self.in_app_flash(buf_start_addr, size) {
+            Ok(Some(AppSlice::new(
+                buf_start_addr as *mut u8,
+                size,
+                self.appid(),
+            )))
+        } else {
+            Err(ReturnCode::EINVAL)
+        }
+    }
+
     unsafe fn alloc(&self, size: usize, align: usize) -> Option<&mut [u8]> {
         self.mpu_config.and_then(|mut config| {
             let new_break_unaligned = self.kernel_memory_break.get().offset(-(size as isize));
@@ -851,6 +1027,28 @@ impl<C: Chip> ProcessType for Process<'a, C> {
 
     unsafe fn free(&self, _: *mut u8) {}
 
+    unsafe fn grant_alloc(
+        &self,
+        grant_num: usize,
+        grant_name: &'static str,
+        size: usize,
+        align: usize,
+    ) -> Option<&mut [u8]> {
+        let old_break = self.kernel_memory_break.get();
+        let res = self.alloc(size, align);
+        if res.is_some() {
+            let used = old_break as usize - self.kernel_memory_break.get() as usize;
+            self.grant_usage
+                .get(grant_num)
+                .map(|usage| usage.set(usage.get() + used));
+        } else {
+            self.debug.map(|debug| {
+                debug.failed_grant_alloc = Some(grant_name);
+            });
+        }
+        res
+    }
+
     #[allow(clippy::cast_ptr_alignment)]
     unsafe fn grant_ptr(&self, grant_num: usize) -> *mut *mut u8 {
         let grant_num = grant_num as isize;
@@ -948,6 +1146,12 @@ impl<C: Chip> ProcessType for Process<'a, C> {
             if switch_reason == syscall::ContextSwitchReason::TimesliceExpired {
                 debug.timeslice_expiration_count += 1;
             }
+
+            // Keep track of syscalls so that introspection can report them.
+            if let syscall::ContextSwitchReason::SyscallFired { syscall } = switch_reason {
+                debug.syscall_count += 1;
+                debug.last_syscall = Some(syscall);
+            }
         });
 
         Some(switch_reason)
@@ -961,6 +1165,10 @@ impl<C: Chip> ProcessType for Process<'a, C> {
         self.debug.map_or(0, |debug| debug.dropped_callback_count)
     }
 
+    fn debug_queued_task_count(&self) -> usize {
+        self.tasks.map_or(0, |tasks| tasks.len())
+    }
+
     fn debug_restart_count(&self) -> usize {
         self.debug.map_or(0, |debug| debug.restart_count)
     }
@@ -975,6 +1183,134 @@ impl<C: Chip> ProcessType for Process<'a, C> {
             .map(|debug| debug.timeslice_expiration_count += 1);
     }
 
+    fn debug_grant_bytes(&self, grant_num: usize) -> Option<usize> {
+        self.grant_usage.get(grant_num).map(|usage| usage.get())
+    }
+
+    fn debug_failed_grant_alloc(&self) -> Option<&'static str> {
+        self.debug.map_or(None, |debug| debug.failed_grant_alloc)
+    }
+
+    fn debug_add_cpu_time(&self, userspace: u32, syscall: u32) {
+        let epoch = self.kernel.cpu_window_epoch();
+        self.debug.map(|debug| {
+            debug.cpu_time_userspace += userspace as u64;
+            debug.cpu_time_syscall += syscall as u64;
+            let (current, previous) =
+                cpu_window_at(debug.cpu_window_time, debug.cpu_window_epoch, epoch);
+            let current = current.saturating_add(userspace).saturating_add(syscall);
+            debug.cpu_window_time = (current, previous);
+            debug.cpu_window_epoch = epoch;
+        });
+    }
+
+    fn debug_cpu_time_userspace(&self) -> u64 {
+        self.debug.map_or(0, |debug| debug.cpu_time_userspace)
+    }
+
+    fn debug_cpu_time_syscall(&self) -> u64 {
+        self.debug.map_or(0, |debug| debug.cpu_time_syscall)
+    }
+
+    fn debug_cpu_window_time(&self) -> u64 {
+        let epoch = self.kernel.cpu_window_epoch();
+        self.debug.map_or(0, |debug| {
+            let (current, previous) =
+                cpu_window_at(debug.cpu_window_time, debug.cpu_window_epoch, epoch);
+            current as u64 + previous as u64
+        })
+    }
+
+    fn debugger_target_description(&self) -> &'static str {
+        self.chip
+            .userspace_kernel_boundary()
+            .debug_target_description()
+    }
+
+    fn debugger_read_register(&self, register: usize) -> Option<usize> {
+        let stored_state = self.stored_state.get();
+        unsafe {
+            self.chip.userspace_kernel_boundary().debug_read_register(
+                self.sp(),
+                &stored_state,
+                register,
+            )
+        }
+    }
+
+    fn debugger_write_register(&self, register: usize, value: usize) -> ReturnCode {
+        let mut stored_state = self.stored_state.get();
+        let res = unsafe {
+            self.chip.userspace_kernel_boundary().debug_write_register(
+                self.sp(),
+                &mut stored_state,
+                register,
+                value,
+            )
+        };
+        self.stored_state.set(stored_state);
+        match res {
+            Ok(()) => ReturnCode::SUCCESS,
+            Err(()) => ReturnCode::EINVAL,
+        }
+    }
+
+    fn debugger_breakpoint_instruction(&self, kind: usize) -> Option<&'static [u8]> {
+        self.chip
+            .userspace_kernel_boundary()
+            .debug_breakpoint_instruction(kind)
+    }
+
+    fn debugger_breakpoint_hit(&self) -> bool {
+        let stored_state = self.stored_state.get();
+        unsafe {
+            self.chip
+                .userspace_kernel_boundary()
+                .debug_breakpoint_hit(self.sp(), &stored_state)
+        }
+    }
+
+    fn debugger_step_addresses(&self) -> [Option<(usize, usize)>; 2] {
+        let stored_state = self.stored_state.get();
+        let read_halfword = |address: usize| {
+            let mut halfword = [0; 2];
+            match self.debugger_read_memory(address, &mut halfword) {
+                ReturnCode::SUCCESS => Some(u16::from_le_bytes(halfword)),
+                _ => None,
+            }
+        };
+        unsafe {
+            self.chip.userspace_kernel_boundary().debug_step_addresses(
+                self.sp(),
+                &stored_state,
+                &read_halfword,
+            )
+        }
+    }
+
+    fn debugger_read_memory(&self, address: usize, buffer: &mut [u8]) -> ReturnCode {
+        let buf_start_addr = address as *const u8;
+        if !self.in_app_memory(buf_start_addr, buffer.len())
+            && !self.in_app_flash(buf_start_addr, buffer.len())
+        {
+            return ReturnCode::EINVAL;
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(address as *const u8, buffer.as_mut_ptr(), buffer.len());
+        }
+        ReturnCode::SUCCESS
+    }
+
+    fn debugger_write_memory(&self, address: usize, data: &[u8]) -> ReturnCode {
+        if !self.in_app_owned_memory(address as *const u8, data.len()) {
+            return ReturnCode::EINVAL;
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), address as *mut u8, data.len());
+        }
+        ReturnCode::SUCCESS
+    }
+
     unsafe fn fault_fmt(&self, writer: &mut dyn Write) {
         self.chip.userspace_kernel_boundary().fault_fmt(writer);
     }
@@ -1032,6 +1368,19 @@ impl<C: Chip> ProcessType for Process<'a, C> {
             None => writer.write_str(" Last Syscall: None"),
         };
 
+        let _ = writer.write_str("\r\n Grant Usage (bytes):");
+        for (grant_num, usage) in self.grant_usage.iter().enumerate() {
+            if usage.get() > 0 {
+                let _ = writer.write_fmt(format_args!(" {}: {}", grant_num, usage.get()));
+            }
+        }
+        if let Some(grant_name) = self.debug.map_or(None, |debug| debug.failed_grant_alloc) {
+            let _ = writer.write_fmt(format_args!(
+                "\r\n Out of memory allocating grant: {}",
+                grant_name
+            ));
+        }
+
         let _ = writer.write_fmt(format_args!(
             "\
              \r\n\
@@ -1215,26 +1564,61 @@ impl<C: 'static + Chip> Process<'a, C> {
             let grant_ptrs_num = kernel.get_grant_count_and_finalize();
             let grant_ptrs_offset = grant_ptrs_num * grant_ptr_size;
 
-            // Allocate memory for callback ring buffer.
+            // Make room to count how much memory each grant uses.
+            let grant_usage_offset = grant_ptrs_num * mem::size_of::<Cell<usize>>();
+
+            // Allocate memory for callback ring buffer. The ring buffer keeps
+            // one slot empty, so it needs one slot more than the queue depth.
             let callback_size = mem::size_of::<Task>();
-            let callback_len = 10;
-            let callbacks_offset = callback_len * callback_size;
+            let callback_depth = tbf_header
+                .get_callback_queue_depth()
+                .map_or(kernel.get_default_callback_queue_depth(), |depth| {
+                    depth as usize
+                });
+            if callback_depth > MAX_CALLBACK_QUEUE_DEPTH {
+                return (None, app_flash_size, 0);
+            }
+            let callback_len = max(callback_depth, 1) + 1;
+            let callbacks_offset = match callback_len.checked_mul(callback_size) {
+                Some(offset) => offset,
+                None => return (None, app_flash_size, 0),
+            };
+
+            // Do not load a process that asks for an overflow policy the
+            // kernel does not know.
+            if tbf_header
+                .callback_overflows()
+                .any(|overflow| CallbackOverflow::from_u32(overflow).is_none())
+            {
+                return (None, app_flash_size, 0);
+            }
 
             // Make room to store this process's metadata.
             let process_struct_offset = mem::size_of::<Process<C>>();
 
             // Initial sizes of the app-owned and kernel-owned parts of process memory.
             // Provide the app with plenty of initial process accessible memory.
-            let initial_kernel_memory_size =
-                grant_ptrs_offset + callbacks_offset + process_struct_offset;
+            let initial_kernel_memory_size = match grant_ptrs_offset
+                .checked_add(grant_usage_offset)
+                .and_then(|size| size.checked_add(callbacks_offset))
+                .and_then(|size| size.checked_add(process_struct_offset))
+            {
+                Some(size) => size,
+                None => return (None, app_flash_size, 0),
+            };
             let initial_app_memory_size = 3 * 1024;
 
             if min_app_ram_size < initial_app_memory_size {
                 min_app_ram_size = initial_app_memory_size;
             }
 
-            // Minimum memory size for the process.
-            let min_total_memory_size = min_app_ram_size + initial_kernel_memory_size;
+            // Minimum memory size for the process. The RAM size comes from the
+            // header, so do not let it wrap around.
+            let min_total_memory_size =
+                match min_app_ram_size.checked_add(initial_kernel_memory_size) {
+                    Some(size) => size,
+                    None => return (None, app_flash_size, 0),
+                };
 
             // Determine where process memory will go and allocate MPU region for app-owned memory.
             let (memory_start, memory_size) = match chip.mpu().allocate_app_memory_region(
@@ -1277,6 +1661,14 @@ impl<C: 'static + Chip> Process<'a, C> {
                 *opt = ptr::null()
             }
 
+            // Then the grant usage counters, which all start at zero.
+            kernel_memory_break = kernel_memory_break.offset(-(grant_usage_offset as isize));
+            let grant_usage_ptr = kernel_memory_break as *mut Cell<usize>;
+            for i in 0..grant_ptrs_num {
+                ptr::write(grant_usage_ptr.add(i), Cell::new(0));
+            }
+            let grant_usage = slice::from_raw_parts(grant_usage_ptr, grant_ptrs_num);
+
             // Now that we know we have the space we can setup the memory
             // for the callbacks.
             kernel_memory_break = kernel_memory_break.offset(-(callbacks_offset as isize));
@@ -1330,6 +1722,7 @@ impl<C: 'static + Chip> Process<'a, C> {
                 Cell::new(None),
             ];
             process.tasks = MapCell::new(tasks);
+            process.grant_usage = grant_usage;
             process.process_name = process_name;
 
             process.debug = MapCell::new(ProcessDebug {
@@ -1341,6 +1734,11 @@ impl<C: 'static + Chip> Process<'a, C> {
                 dropped_callback_count: 0,
                 restart_count: 0,
                 timeslice_expiration_count: 0,
+                failed_grant_alloc: None,
+                cpu_time_userspace: 0,
+                cpu_time_syscall: 0,
+                cpu_window_time: (0, 0),
+                cpu_window_epoch: kernel.cpu_window_epoch(),
             });
 
             let flash_protected_size = process.header.get_protected_size() as usize;
@@ -1406,7 +1804,102 @@ impl<C: 'static + Chip> Process<'a, C> {
             && buf_end_addr <= self.app_break.get()
     }
 
-    /// Reset all `grant_ptr`s to NULL.
+    /// Checks if the buffer represented by the passed in base pointer and size
+    /// is within the RAM allocated for the process, including the grant
+    /// region.
+    fn in_app_memory(&self, buf_start_addr: *const u8, size: usize) -> bool {
+        let buf_end_addr = buf_start_addr.wrapping_add(size);
+
+        buf_end_addr >= buf_start_addr
+            && buf_start_addr >= self.mem_start()
+            && buf_end_addr <= self.mem_end()
+    }
+
+    /// Checks if the buffer represented by the passed in base pointer and size
+    /// is within the flash of the process, including its TBF header.
+    fn in_app_flash(&self, buf_start_addr: *const u8, size: usize) -> bool {
+        let buf_end_addr = buf_start_addr.wrapping_add(size);
+
+        buf_end_addr >= buf_start_addr
+            && buf_start_addr >= self.flash_start()
+            && buf_end_addr <= self.flash_end()
+    }
+
+    /// Remove the tasks that were scheduled for the app, and the work the
+    /// kernel counts for them and for the app running, given the state the
+    /// app was in.
+    fn remove_process_work(&self, previous_state: State) {
+        let tasks_len = self.tasks.map_or(0, |tasks| tasks.len());
+        for _ in 0..tasks_len {
+            self.kernel.decrement_work();
+        }
+        match previous_state {
+            State::Running | State::StoppedRunning => self.kernel.decrement_work(),
+            _ => {}
+        }
+
+        // And remove those tasks
+        self.tasks.map(|tasks| {
+            tasks.empty();
+        });
+    }
+
+    /// Reset the app to how it was loaded and queue it to start executing
+    /// from its entry point again.
+    fn restart_process(&self, previous_state: State) {
+        self.remove_process_work(previous_state);
+
+        // Update debug information
+        self.debug.map(|debug| {
+            // Mark that we restarted this process.
+            debug.restart_count += 1;
+
+            // Reset some state for the process.
+            debug.syscall_count = 0;
+            debug.last_syscall = None;
+            debug.dropped_callback_count = 0;
+            debug.failed_grant_alloc = None;
+        });
+
+        // We are going to start this process over again, so need
+        // the init_fn location.
+        let app_flash_address = self.flash_start();
+        let init_fn = unsafe {
+            app_flash_address.offset(self.header.get_init_function_offset() as isize) as usize
+        };
+        self.state.set(State::Unstarted);
+
+        // Need to reset the grant region.
+        unsafe {
+            self.grant_ptrs_reset();
+        }
+        self.kernel_memory_break
+            .set(self.original_kernel_memory_break);
+
+        // Reset other memory pointers.
+        self.app_break.set(self.original_app_break);
+        self.current_stack_pointer.set(self.original_stack_pointer);
+
+        // And queue up this app to be restarted.
+        let flash_protected_size = self.header.get_protected_size() as usize;
+        let flash_app_start = app_flash_address as usize + flash_protected_size;
+
+        self.tasks.map(|tasks| {
+            tasks.enqueue(Task::FunctionCall(FunctionCall {
+                source: FunctionCallSource::Kernel,
+                pc: init_fn,
+                argument0: flash_app_start,
+                argument1: self.memory.as_ptr() as usize,
+                argument2: self.memory.len() as usize,
+                argument3: self.app_break.get() as usize,
+            }));
+        });
+
+        self.kernel.increment_work();
+    }
+
+    /// Reset all `grant_ptr`s to NULL, and the memory used by each grant to
+    /// zero.
     #[allow(clippy::cast_ptr_alignment)]
     unsafe fn grant_ptrs_reset(&self) {
         let grant_ptrs_num = self.kernel.get_grant_count_and_finalize();
@@ -1415,6 +1908,9 @@ impl<C: 'static + Chip> Process<'a, C> {
             let ctr_ptr = (self.mem_end() as *mut *mut usize).offset(-(grant_num + 1));
             write_volatile(ctr_ptr, ptr::null_mut());
         }
+        for usage in self.grant_usage.iter() {
+            usage.set(0);
+        }
     }
 
     fn debug_set_max_stack_depth(&self) {
@@ -1425,3 +1921,66 @@ impl<C: 'static + Chip> Process<'a, C> {
         });
     }
 }
+
+#[cfg(test)]
+mod test {
+    use core::any::type_name;
+
+    use super::FaultResponse;
+    use crate::test_support::{TestCapability, TestKernel, APP_MEMORY_SIZE};
+
+    #[test]
+    fn test_grant_accounting_and_reset() {
+        let test = TestKernel::new(1);
+        let small = test.kernel.create_grant::<u64>(&TestCapability);
+        let large = test.kernel.create_grant::<[u8; 24]>(&TestCapability);
+
+        let process = test.create_process(0, FaultResponse::Restart);
+        let appid = process.appid();
+        let initial_break = process.kernel_memory_break();
+        assert_eq!(process.debug_grant_bytes(0), Some(0));
+        assert_eq!(process.debug_grant_bytes(1), Some(0));
+        assert_eq!(process.debug_grant_bytes(2), None);
+
+        // Entering a grant allocates it, and allocations inside the grant
+        // count towards it.
+        assert_eq!(small.enter(appid, |_, _| ()), Ok(()));
+        assert_eq!(
+            large.enter(appid, |_, allocator| allocator.alloc(0u64).is_ok()),
+            Ok(true)
+        );
+        assert_eq!(process.debug_grant_bytes(0), Some(8));
+        assert_eq!(process.debug_grant_bytes(1), Some(32));
+        assert_eq!(
+            initial_break as usize - process.kernel_memory_break() as usize,
+            40
+        );
+
+        // Running out of memory names the grant.
+        assert_eq!(process.debug_failed_grant_alloc(), None);
+        assert_eq!(
+            large.enter(appid, |_, allocator| allocator
+                .alloc_default_slice::<u8>(APP_MEMORY_SIZE)
+                .is_err()),
+            Ok(true)
+        );
+        assert_eq!(
+            process.debug_failed_grant_alloc(),
+            Some(type_name::<[u8; 24]>())
+        );
+        assert_eq!(process.debug_grant_bytes(1), Some(32));
+
+        // Restarting the process returns all grant memory.
+        process.set_fault_state();
+        assert_eq!(process.kernel_memory_break(), initial_break);
+        assert_eq!(process.debug_grant_bytes(0), Some(0));
+        assert_eq!(process.debug_grant_bytes(1), Some(0));
+        assert_eq!(process.debug_failed_grant_alloc(), None);
+        assert!(small.grant(appid).is_none());
+        assert!(large.grant(appid).is_none());
+
+        // And the grants can be allocated again.
+        assert_eq!(small.enter(appid, |_, _| ()), Ok(()));
+        assert_eq!(process.debug_grant_bytes(0), Some(8));
+    }
+}
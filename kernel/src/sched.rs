@@ -8,8 +8,11 @@ use crate::capabilities;
 use crate::common::cells::NumericCellExt;
 use crate::common::dynamic_deferred_call::DynamicDeferredCall;
 use crate::grant::Grant;
+use crate::introspection::CpuTimeUnit;
 use crate::ipc;
 use crate::memop;
+use crate::platform::cycle_counter::CycleCounter;
+use crate::platform::interrupt_counter::InterruptCounter;
 use crate::platform::mpu::MPU;
 use crate::platform::systick::SysTick;
 use crate::platform::{Chip, Platform};
@@ -22,6 +25,9 @@ use crate::hil::clock_pm::ChangeClock;
 const KERNEL_TICK_DURATION_US: u32 = 10000;
 /// Skip re-scheduling a process if its quanta is nearly exhausted
 const MIN_QUANTA_THRESHOLD_US: u32 = 500;
+/// How much CPU time, in the unit the kernel measures it in, makes up one
+/// window of the CPU time statistics, unless the board sets it.
+const DEFAULT_CPU_WINDOW_LENGTH: u32 = 1 << 24;
 
 /// Main object for the kernel. Each board will need to create one.
 pub struct Kernel {
@@ -39,6 +45,28 @@ pub struct Kernel {
     /// created and the data structures for grants have already been
     /// established.
     grants_finalized: Cell<bool>,
+    /// How many callbacks can be queued for processes whose TBF header does
+    /// not set the depth of their callback queue.
+    default_callback_queue_depth: Cell<usize>,
+    /// Counts the CPU cycles the kernel and processes use, if the board has a
+    /// counter. Otherwise the kernel measures the CPU time of processes with
+    /// the SysTick.
+    cycle_counter: Cell<Option<&'static dyn CycleCounter>>,
+    /// Value of the cycle counter when the kernel loop last accounted for the
+    /// cycles it used.
+    last_cycles: Cell<u32>,
+    /// How much CPU time makes up one window of the CPU time statistics.
+    cpu_window_length: Cell<u32>,
+    /// Number of the current window of the CPU time statistics.
+    cpu_window_epoch: Cell<usize>,
+    /// CPU time measured in the current and in the previous window.
+    cpu_window_time: Cell<(u32, u32)>,
+    /// Handles processes that execute a breakpoint instruction, if the board
+    /// set a debugger.
+    debugger: Cell<Option<&'static dyn process::ProcessDebugger>>,
+    /// Counts how often the chip services each interrupt, if the board has a
+    /// counter. Only read through `KernelInfo`.
+    interrupt_counter: Cell<Option<&'static dyn InterruptCounter>>,
 }
 
 impl Kernel {
@@ -48,9 +76,137 @@ impl Kernel {
             processes: processes,
             grant_counter: Cell::new(0),
             grants_finalized: Cell::new(false),
+            default_callback_queue_depth: Cell::new(10),
+            cycle_counter: Cell::new(None),
+            last_cycles: Cell::new(0),
+            cpu_window_length: Cell::new(DEFAULT_CPU_WINDOW_LENGTH),
+            cpu_window_epoch: Cell::new(0),
+            cpu_window_time: Cell::new((0, 0)),
+            debugger: Cell::new(None),
+            interrupt_counter: Cell::new(None),
         }
     }
 
+    /// Use `counter` to measure the CPU time of the kernel and of processes.
+    pub fn set_cycle_counter(&self, counter: &'static dyn CycleCounter) {
+        self.last_cycles.set(counter.cycles());
+        self.cycle_counter.set(Some(counter));
+    }
+
+    /// Set how much CPU time makes up one window of the CPU time statistics,
+    /// in cycles if the board set a cycle counter and in microseconds
+    /// otherwise. The statistics cover the last one to two windows.
+    pub fn set_cpu_time_window(&self, length: u32) {
+        self.cpu_window_length.set(length);
+    }
+
+    /// Let `debugger` handle processes that execute a breakpoint instruction.
+    /// Without a debugger, breakpoints are faults.
+    pub fn set_debugger(
+        &self,
+        debugger: &'static dyn process::ProcessDebugger,
+        _capability: &dyn capabilities::ProcessManagementCapability,
+    ) {
+        self.debugger.set(Some(debugger));
+    }
+
+    /// Make the interrupt counts of `counter` available through `KernelInfo`.
+    pub fn set_interrupt_counter(&self, counter: &'static dyn InterruptCounter) {
+        self.interrupt_counter.set(Some(counter));
+    }
+
+    /// Returns the interrupt counter of the board, if it set one.
+    crate fn interrupt_counter(&self) -> Option<&'static dyn InterruptCounter> {
+        self.interrupt_counter.get()
+    }
+
+    /// Returns the unit the kernel measures CPU time in.
+    crate fn cpu_time_unit(&self) -> CpuTimeUnit {
+        match self.cycle_counter.get() {
+            Some(_) => CpuTimeUnit::Cycles,
+            None => CpuTimeUnit::Microseconds,
+        }
+    }
+
+    /// Returns the number of the current window of the CPU time statistics.
+    crate fn cpu_window_epoch(&self) -> usize {
+        self.cpu_window_epoch.get()
+    }
+
+    /// Returns how much CPU time the kernel measured in the current and the
+    /// previous window.
+    crate fn cpu_window_time(&self) -> u64 {
+        let (current, previous) = self.cpu_window_time.get();
+        current as u64 + previous as u64
+    }
+
+    /// Add CPU time to the current window, and start the next window if the
+    /// current one is full.
+    fn add_cpu_window_time(&self, time: u32) {
+        let (current, previous) = self.cpu_window_time.get();
+        let current = current.saturating_add(time);
+        if current >= self.cpu_window_length.get() {
+            self.cpu_window_epoch.increment();
+            self.cpu_window_time.set((0, current));
+        } else {
+            self.cpu_window_time.set((current, previous));
+        }
+    }
+
+    /// Returns a timestamp for measuring the CPU time a process uses. Without
+    /// a cycle counter, this is the time since the timeslice of the process
+    /// started, as long as the SysTick can tell.
+    fn cpu_time<C: Chip>(&self, chip: &C) -> Option<u32> {
+        match self.cycle_counter.get() {
+            Some(counter) => Some(counter.cycles()),
+            None => {
+                let systick = chip.systick();
+                if systick.overflowed() {
+                    Some(KERNEL_TICK_DURATION_US)
+                } else {
+                    systick
+                        .remaining_us()
+                        .map(|remaining| KERNEL_TICK_DURATION_US.saturating_sub(remaining))
+                }
+            }
+        }
+    }
+
+    /// Account CPU time a process used in userspace and in the kernel
+    /// handling its system calls.
+    fn account_cpu_time(&self, process: &dyn process::ProcessType, userspace: u32, syscall: u32) {
+        process.debug_add_cpu_time(userspace, syscall);
+        // With a cycle counter, the kernel loop accounts all cycles to the
+        // window, including those of processes.
+        if self.cycle_counter.get().is_none() {
+            self.add_cpu_window_time(userspace.saturating_add(syscall));
+        }
+    }
+
+    /// Account the cycles used since the kernel loop last did so.
+    fn account_kernel_cycles(&self) {
+        self.cycle_counter.get().map(|counter| {
+            let now = counter.cycles();
+            self.add_cpu_window_time(now.wrapping_sub(self.last_cycles.get()));
+            self.last_cycles.set(now);
+        });
+    }
+
+    /// Set how many callbacks can be queued for processes whose TBF header
+    /// does not set the depth of their callback queue. The default is 10.
+    /// Processes are not loaded if this is more than
+    /// `procs::MAX_CALLBACK_QUEUE_DEPTH`.
+    ///
+    /// This must be called before the processes are loaded.
+    pub fn set_default_callback_queue_depth(&self, depth: usize) {
+        self.default_callback_queue_depth.set(depth);
+    }
+
+    /// Get the depth of the callback queue for processes that do not set one.
+    crate fn get_default_callback_queue_depth(&self) -> usize {
+        self.default_callback_queue_depth.get()
+    }
+
     /// Something was scheduled for a process, so there is more work to do.
     crate fn increment_work(&self) {
         self.work.increment();
@@ -62,6 +218,12 @@ impl Kernel {
         self.work.decrement();
     }
 
+    /// Returns how many to-do items, queued tasks and running processes, there
+    /// are.
+    crate fn work_count(&self) -> usize {
+        self.work.get()
+    }
+
     /// Helper function for determining if we should service processes or go to
     /// sleep.
     fn processes_blocked(&self) -> bool {
@@ -76,7 +238,7 @@ impl Kernel {
     where
         F: FnOnce(&dyn process::ProcessType) -> R,
     {
-        if process_index > self.processes.len() {
+        if process_index >= self.processes.len() {
             return default;
         }
         self.processes[process_index].map_or(default, |process| closure(process))
@@ -183,6 +345,12 @@ impl Kernel {
         self.grant_counter.get()
     }
 
+    /// Returns the number of grants that have been setup in the system,
+    /// without finalizing them.
+    crate fn number_of_grants(&self) -> usize {
+        self.grant_counter.get()
+    }
+
     /// Cause all apps to fault.
     ///
     /// This will call `set_fault_state()` on each app, causing the app to enter
@@ -210,10 +378,18 @@ impl Kernel {
         _capability: &dyn capabilities::MainLoopCapability,
         clock_driver: &'static dyn ChangeClock,
     ) {
+        platform.watchdog().setup();
+
         loop {
             unsafe {
+                platform.watchdog().tickle();
+                self.account_kernel_cycles();
                 chip.service_pending_interrupts();
                 DynamicDeferredCall::call_global_instance_while(|| !chip.has_pending_interrupts());
+                if let Some(ipc) = ipc {
+                    ipc.check_timeouts();
+                    ipc.release_stopped_callers();
+                }
 
                 for p in self.processes.iter() {
                     p.map(|process| {
@@ -238,7 +414,9 @@ impl Kernel {
                         && !DynamicDeferredCall::global_instance_calls_pending().unwrap_or(false)
                         && self.processes_blocked()
                     {
+                        platform.watchdog().suspend();
                         chip.sleep();
+                        platform.watchdog().resume();
                     }
                 });
             };
@@ -259,7 +437,17 @@ impl Kernel {
         systick.set_timer(KERNEL_TICK_DURATION_US);
         systick.enable(false);
 
+        // When the process called a system call, the time the kernel started
+        // to handle it.
+        let mut syscall_start = None;
+
         loop {
+            if let Some(start) = syscall_start.take() {
+                if let Some(now) = self.cpu_time(chip) {
+                    self.account_cpu_time(process, 0, now.wrapping_sub(start));
+                }
+            }
+
             if chip.has_pending_interrupts() {
                 break;
             }
@@ -277,16 +465,36 @@ impl Kernel {
                     process.setup_mpu();
                     chip.mpu().enable_mpu();
                     systick.enable(true);
+                    let switch_start = self.cpu_time(chip);
                     let context_switch_reason = process.switch_to();
+                    let switch_end = self.cpu_time(chip);
                     systick.enable(false);
                     chip.mpu().disable_mpu();
 
+                    if let (Some(start), Some(end)) = (switch_start, switch_end) {
+                        self.account_cpu_time(process, end.wrapping_sub(start), 0);
+                    }
+                    if let Some(ContextSwitchReason::SyscallFired { .. }) = context_switch_reason {
+                        syscall_start = switch_end;
+                    }
+
                     // Now the process has returned back to the kernel. Check
                     // why and handle the process as appropriate.
                     match context_switch_reason {
                         Some(ContextSwitchReason::Fault) => {
-                            // Let process deal with it as appropriate.
-                            process.set_fault_state();
+                            // A breakpoint the debugger handles stops the
+                            // process. Otherwise, let process deal with it as
+                            // appropriate.
+                            if process.debugger_breakpoint_hit()
+                                && self
+                                    .debugger
+                                    .get()
+                                    .map_or(false, |debugger| debugger.breakpoint_hit(appid))
+                            {
+                                process.stop();
+                            } else {
+                                process.set_fault_state();
+                            }
                         }
                         Some(ContextSwitchReason::SyscallFired { syscall }) => {
                             // Handle each of the syscalls.
@@ -315,7 +523,13 @@ impl Kernel {
 
                                     let callback_ptr = NonNull::new(callback_ptr);
                                     let callback = callback_ptr.map(|ptr| {
-                                        Callback::new(appid, callback_id, appdata, ptr.cast())
+                                        Callback::new(
+                                            appid,
+                                            callback_id,
+                                            appdata,
+                                            ptr.cast(),
+                                            process.get_callback_overflow(callback_id),
+                                        )
                                     });
 
                                     let res =
@@ -369,6 +583,29 @@ impl Kernel {
                                     });
                                     process.set_syscall_return_value(res.into());
                                 }
+                                Syscall::ALLOW_READONLY {
+                                    driver_number,
+                                    subdriver_number,
+                                    allow_address,
+                                    allow_size,
+                                } => {
+                                    let res = platform.with_driver(driver_number, |driver| {
+                                        match driver {
+                                            Some(d) => match process
+                                                .allow_readonly(allow_address, allow_size)
+                                            {
+                                                Ok(oslice) => d.allow_readonly(
+                                                    appid,
+                                                    subdriver_number,
+                                                    oslice,
+                                                ),
+                                                Err(err) => err, /* memory not valid */
+                                            },
+                                            None => ReturnCode::ENODEVICE,
+                                        }
+                                    });
+                                    process.set_syscall_return_value(res.into());
+                                }
                             }
                         }
                         Some(ContextSwitchReason::TimesliceExpired) => {
@@ -405,6 +642,11 @@ impl Kernel {
                     }, 
                     Some(cb) => match cb {
                         Task::FunctionCall(ccb) => {
+                            // The kernel calls the init function of a process
+                            // when it starts, including after a restart.
+                            if let process::FunctionCallSource::Kernel = ccb.source {
+                                ipc.map(|ipc| ipc.process_started(appid));
+                            }
                             process.set_process_function(ccb);
                         }
                         Task::IPC((otherapp, ipc_type)) => {
@@ -438,6 +680,10 @@ impl Kernel {
                     break;
                     // Do nothing
                 }
+                process::State::Terminated => {
+                    break;
+                    // Do nothing
+                }
             }
         }
         systick.reset();
@@ -47,6 +47,18 @@ pub enum Syscall {
     ///
     /// SVC_NUM = 4
     MEMOP { operand: usize, arg0: usize },
+
+    /// Share a memory buffer with the kernel that the kernel only reads. The
+    /// buffer can be in the flash of the process.
+    ///
+    /// SVC_NUM = 5
+    #[allow(non_camel_case_types)]
+    ALLOW_READONLY {
+        driver_number: usize,
+        subdriver_number: usize,
+        allow_address: *const u8,
+        allow_size: usize,
+    },
 }
 
 /// Why the process stopped executing and execution returned to the kernel.
@@ -153,6 +165,73 @@ pub trait UserspaceKernelBoundary {
         state: &Self::StoredState,
         writer: &mut dyn Write,
     );
+
+    /// GDB target description (an XML document) of the registers a debugger
+    /// can access with `debug_read_register()` and
+    /// `debug_write_register()`, in order. Architectures that do not support
+    /// debugging processes return an empty string.
+    fn debug_target_description(&self) -> &'static str {
+        ""
+    }
+
+    /// Read register number `register`, as numbered by the target
+    /// description, of a process that is not executing. Returns `None` if there
+    /// is no such register.
+    unsafe fn debug_read_register(
+        &self,
+        _stack_pointer: *const usize,
+        _state: &Self::StoredState,
+        _register: usize,
+    ) -> Option<usize> {
+        None
+    }
+
+    /// Write register number `register`, as numbered by the target
+    /// description, of a process that is not executing. Returns `Err` if there
+    /// is no such register or the register cannot be changed.
+    unsafe fn debug_write_register(
+        &self,
+        _stack_pointer: *const usize,
+        _state: &mut Self::StoredState,
+        _register: usize,
+        _value: usize,
+    ) -> Result<(), ()> {
+        Err(())
+    }
+
+    /// The instruction that traps into the kernel when a process executes it,
+    /// for a breakpoint of the given GDB breakpoint kind (usually the length
+    /// of the instruction it replaces). Returns `None` if the kind is not
+    /// supported.
+    fn debug_breakpoint_instruction(&self, _kind: usize) -> Option<&'static [u8]> {
+        None
+    }
+
+    /// Addresses where a process that is not executing may execute its next
+    /// instruction after the one the program counter points at: the following
+    /// instruction, and the target if it is a branch. Each comes with the
+    /// breakpoint kind of the instruction there, and trapping at both
+    /// single-steps the process. `read_halfword` reads 16 bits of the memory
+    /// of the process, and returns `None` outside of it.
+    unsafe fn debug_step_addresses(
+        &self,
+        _stack_pointer: *const usize,
+        _state: &Self::StoredState,
+        _read_halfword: &dyn Fn(usize) -> Option<u16>,
+    ) -> [Option<(usize, usize)>; 2] {
+        [None, None]
+    }
+
+    /// Whether the last fault of a process was caused by executing a
+    /// breakpoint instruction. The register state is that of the process when
+    /// it executed the instruction, with the program counter pointing at it.
+    unsafe fn debug_breakpoint_hit(
+        &self,
+        _stack_pointer: *const usize,
+        _state: &Self::StoredState,
+    ) -> bool {
+        false
+    }
 }
 
 /// Helper function for converting raw values passed back from an application
@@ -198,6 +277,12 @@ pub fn arguments_to_syscall(
             operand: r0,
             arg0: r1,
         }),
+        5 => Some(Syscall::ALLOW_READONLY {
+            driver_number: r0,
+            subdriver_number: r1,
+            allow_address: r2 as *const u8,
+            allow_size: r3,
+        }),
         _ => None,
     }
 }
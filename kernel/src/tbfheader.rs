@@ -28,7 +28,8 @@ crate enum TbfHeaderTypes {
     TbfHeaderMain = 1,
     TbfHeaderWriteableFlashRegions = 2,
     TbfHeaderPackageName = 3,
-    Unused = 5,
+    TbfHeaderCallbackQueue = 5,
+    Unused = 6,
 }
 
 /// The TLV header (T and L).
@@ -62,6 +63,28 @@ crate struct TbfHeaderV2WriteableFlashRegion {
     writeable_flash_region_size: u32,
 }
 
+/// Number of callbacks the kernel queues for the app, and what it does with
+/// callbacks that do not fit.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+crate struct TbfHeaderV2CallbackQueue {
+    depth: u32,
+    overflow: u32,
+}
+
+/// Overflow policy for the callbacks of one subscription, overriding the one
+/// of the callback queue.
+///
+/// There can be multiple (or zero) of these after the callback queue, so this
+/// is its own struct.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+crate struct TbfHeaderV2CallbackOverflow {
+    driver_num: u32,
+    subscribe_num: u32,
+    overflow: u32,
+}
+
 /// Single header that can contain all parts of a v2 header.
 #[derive(Clone, Copy, Debug)]
 crate struct TbfHeaderV2 {
@@ -69,6 +92,8 @@ crate struct TbfHeaderV2 {
     main: Option<&'static TbfHeaderV2Main>,
     package_name: Option<&'static str>,
     writeable_regions: Option<&'static [TbfHeaderV2WriteableFlashRegion]>,
+    callback_queue: Option<&'static TbfHeaderV2CallbackQueue>,
+    callback_overflows: Option<&'static [TbfHeaderV2CallbackOverflow]>,
 }
 
 /// Type that represents the fields of the Tock Binary Format header.
@@ -176,6 +201,49 @@ impl TbfHeader {
             _ => (0, 0),
         }
     }
+
+    /// Get how many callbacks the app wants the kernel to queue for it, if
+    /// the app specified this in its header.
+    crate fn get_callback_queue_depth(&self) -> Option<u32> {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd.callback_queue.map(|cq| cq.depth),
+            _ => None,
+        }
+    }
+
+    /// Get the overflow policy the app set in its header for the callbacks
+    /// of a subscription, or for all of them if it did not set one for that
+    /// subscription. The policy is not checked, so it may be unknown.
+    crate fn get_callback_overflow(&self, driver_num: usize, subscribe_num: usize) -> Option<u32> {
+        match *self {
+            TbfHeader::TbfHeaderV2(hd) => hd
+                .callback_overflows
+                .and_then(|overflows| {
+                    overflows
+                        .iter()
+                        .find(|o| {
+                            o.driver_num as usize == driver_num
+                                && o.subscribe_num as usize == subscribe_num
+                        })
+                        .map(|o| o.overflow)
+                })
+                .or_else(|| hd.callback_queue.map(|cq| cq.overflow)),
+            _ => None,
+        }
+    }
+
+    /// Get all the overflow policies the app set in its header, so that they
+    /// can be checked before the app runs.
+    crate fn callback_overflows(&self) -> impl Iterator<Item = u32> {
+        let (queue, overflows) = match *self {
+            TbfHeader::TbfHeaderV2(hd) => (hd.callback_queue, hd.callback_overflows),
+            _ => (None, None),
+        };
+        queue
+            .map(|cq| cq.overflow)
+            .into_iter()
+            .chain(overflows.unwrap_or(&[]).iter().map(|o| o.overflow))
+    }
 }
 
 /// Converts a pointer to memory to a TbfHeader struct
@@ -245,6 +313,9 @@ crate unsafe fn parse_and_validate_tbf_header(address: *const u8) -> Option<TbfH
                 let mut main_pointer: Option<&TbfHeaderV2Main> = None;
                 let mut wfr_pointer: Option<&'static [TbfHeaderV2WriteableFlashRegion]> = None;
                 let mut app_name_str = "";
+                let mut callback_queue_pointer: Option<&TbfHeaderV2CallbackQueue> = None;
+                let mut callback_overflows_pointer: Option<&'static [TbfHeaderV2CallbackOverflow]> =
+                    None;
 
                 // Loop through the header looking for known options.
                 while remaining_length > mem::size_of::<TbfHeaderTlv>() {
@@ -303,6 +374,30 @@ crate unsafe fn parse_and_validate_tbf_header(address: *const u8) -> Option<TbfH
                                         });
                                 }
                             }
+                            TbfHeaderTypes::TbfHeaderCallbackQueue =>
+                            /* Callback Queue */
+                            {
+                                // The queue may be followed by overflow
+                                // policies for single subscriptions.
+                                let length = tbf_tlv_header.length as usize;
+                                let queue_size = mem::size_of::<TbfHeaderV2CallbackQueue>();
+                                let overflow_size = mem::size_of::<TbfHeaderV2CallbackOverflow>();
+                                if remaining_length >= length
+                                    && length >= queue_size
+                                    && (length - queue_size) % overflow_size == 0
+                                {
+                                    let callback_queue = &*(address.offset(offset)
+                                        as *const TbfHeaderV2CallbackQueue);
+                                    callback_queue_pointer = Some(callback_queue);
+                                    let overflows_start = &*(address
+                                        .offset(offset + queue_size as isize)
+                                        as *const TbfHeaderV2CallbackOverflow);
+                                    callback_overflows_pointer = Some(slice::from_raw_parts(
+                                        overflows_start,
+                                        (length - queue_size) / overflow_size,
+                                    ));
+                                }
+                            }
                             TbfHeaderTypes::Unused => {}
                         }
                     }
@@ -318,6 +413,8 @@ crate unsafe fn parse_and_validate_tbf_header(address: *const u8) -> Option<TbfH
                     main: main_pointer,
                     package_name: Some(app_name_str),
                     writeable_regions: wfr_pointer,
+                    callback_queue: callback_queue_pointer,
+                    callback_overflows: callback_overflows_pointer,
                 };
 
                 Some(TbfHeader::TbfHeaderV2(tbf_header))
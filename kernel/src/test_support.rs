@@ -0,0 +1,179 @@
+//! A kernel with processes that never run, for the unit tests of the kernel.
+//!
+//! The processes are loaded from a minimal TBF header into memory of their
+//! own. Switching to a process faults, so tests drive the kernel through the
+//! functions that the scheduler calls instead.
+
+extern crate std;
+
+use core::fmt::Write;
+use std::boxed::Box;
+use std::vec;
+
+use crate::capabilities::MemoryAllocationCapability;
+use crate::platform::Chip;
+use crate::process::{FaultResponse, FunctionCall, Process, ProcessType};
+use crate::sched::Kernel;
+use crate::syscall::{ContextSwitchReason, UserspaceKernelBoundary};
+
+pub(crate) struct TestBoundary;
+
+impl UserspaceKernelBoundary for TestBoundary {
+    type StoredState = ();
+
+    unsafe fn initialize_new_process(
+        &self,
+        stack_pointer: *const usize,
+        _stack_size: usize,
+        _state: &mut (),
+    ) -> Result<*const usize, ()> {
+        Ok(stack_pointer)
+    }
+
+    unsafe fn set_syscall_return_value(
+        &self,
+        _stack_pointer: *const usize,
+        _state: &mut (),
+        _return_value: isize,
+    ) {
+    }
+
+    unsafe fn set_process_function(
+        &self,
+        stack_pointer: *const usize,
+        _remaining_stack_memory: usize,
+        _state: &mut (),
+        _callback: FunctionCall,
+    ) -> Result<*mut usize, *mut usize> {
+        Ok(stack_pointer as *mut usize)
+    }
+
+    unsafe fn switch_to_process(
+        &self,
+        stack_pointer: *const usize,
+        _state: &mut (),
+    ) -> (*mut usize, ContextSwitchReason) {
+        (stack_pointer as *mut usize, ContextSwitchReason::Fault)
+    }
+
+    unsafe fn fault_fmt(&self, _writer: &mut dyn Write) {}
+
+    unsafe fn process_detail_fmt(
+        &self,
+        _stack_pointer: *const usize,
+        _state: &(),
+        _writer: &mut dyn Write,
+    ) {
+    }
+}
+
+pub(crate) struct TestChip {
+    mpu: (),
+    systick: (),
+    boundary: TestBoundary,
+}
+
+impl Chip for TestChip {
+    type MPU = ();
+    type UserspaceKernelBoundary = TestBoundary;
+    type SysTick = ();
+
+    fn service_pending_interrupts(&self) {}
+
+    fn has_pending_interrupts(&self) -> bool {
+        false
+    }
+
+    fn mpu(&self) -> &() {
+        &self.mpu
+    }
+
+    fn systick(&self) -> &() {
+        &self.systick
+    }
+
+    fn userspace_kernel_boundary(&self) -> &TestBoundary {
+        &self.boundary
+    }
+
+    fn sleep(&self) {}
+
+    unsafe fn atomic<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        f()
+    }
+}
+
+pub(crate) struct TestCapability;
+unsafe impl MemoryAllocationCapability for TestCapability {}
+
+/// The memory each process gets.
+pub(crate) const APP_MEMORY_SIZE: usize = 8192;
+
+#[repr(C, align(8))]
+struct AppMemory([u8; APP_MEMORY_SIZE]);
+
+// A TBF header with only a main section, followed by padding up to the
+// total size of the app. The app asks for 1 kB more RAM than the kernel
+// gives it initially, which leaves room for grants.
+const fn tbf_header() -> [u32; 16] {
+    let mut header = [0; 16];
+    header[0] = 2 | (32 << 16); // Version 2, 32 byte header.
+    header[1] = 64; // Total size.
+    header[2] = 1; // Enabled.
+    header[4] = 1 | (12 << 16); // Main TLV.
+    header[7] = 4096; // Minimum RAM size.
+    header[3] = header[0] ^ header[1] ^ header[2] ^ header[4] ^ header[7];
+    header
+}
+static APP_FLASH: [u32; 16] = tbf_header();
+
+/// A kernel with a fixed number of process slots, which the test fills with
+/// `create_process`. Grants must be created before the processes.
+pub(crate) struct TestKernel {
+    pub(crate) kernel: &'static Kernel,
+    chip: &'static TestChip,
+    processes: *mut [Option<&'static dyn ProcessType>],
+}
+
+impl TestKernel {
+    pub(crate) fn new(slots: usize) -> TestKernel {
+        let processes: &'static mut [Option<&'static dyn ProcessType>] =
+            Box::leak(vec![None; slots].into_boxed_slice());
+        let processes = processes as *mut [Option<&'static dyn ProcessType>];
+        TestKernel {
+            kernel: Box::leak(Box::new(Kernel::new(unsafe { &*processes }))),
+            chip: Box::leak(Box::new(TestChip {
+                mpu: (),
+                systick: (),
+                boundary: TestBoundary,
+            })),
+            processes,
+        }
+    }
+
+    /// Loads a process into slot `index`. The process is unstarted, with its
+    /// init function queued.
+    pub(crate) fn create_process(
+        &self,
+        index: usize,
+        fault_response: FaultResponse,
+    ) -> &'static dyn ProcessType {
+        let memory = Box::leak(Box::new(AppMemory([0; APP_MEMORY_SIZE])));
+        unsafe {
+            let (process, _, _) = Process::create(
+                self.kernel,
+                self.chip,
+                APP_FLASH.as_ptr() as *const u8,
+                memory.0.as_mut_ptr(),
+                memory.0.len(),
+                fault_response,
+                index,
+            );
+            (*self.processes)[index] = process;
+            process.expect("process not created")
+        }
+    }
+}
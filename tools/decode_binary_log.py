@@ -0,0 +1,317 @@
+#!/usr/bin/env python3
+
+'''
+Decode the output of the kernel's `debug_binary!()` macro.
+
+`debug_binary!()` interns its format strings in the `tock_log` section of the
+kernel ELF file, which is not loaded on the board, and only sends the offset
+of the format string in that section and the arguments. This script reads the
+format strings from the ELF file and formats the messages in the debug output
+of the board, while passing the text output of `debug!()` and the process
+console through.
+
+Read from a serial port:
+
+    stty -F /dev/ttyACM0 115200 raw
+    tools/decode_binary_log.py boards/hail/target/thumbv7em-none-eabihf/release/hail.elf /dev/ttyACM0
+
+or from the output of a board, like the host board or a `segger_rtt` client:
+
+    target/debug/tock-host | tools/decode_binary_log.py target/debug/tock-host
+
+Each binary record starts with one of the bytes 0xfd-0xff, which never
+appear in UTF-8 text:
+
+    0xff index arguments...            a message
+    0xfe milliseconds index arguments  a message with a timestamp
+    0xfd count                         `count` messages were dropped
+
+The index, the timestamp and the count are unsigned LEB128 numbers. Each
+argument is a type tag followed by its value:
+
+    0 LEB128           unsigned integer
+    1 zigzag LEB128    signed integer
+    2, 3               false, true
+    4 LEB128           char
+    5 length bytes     UTF-8 string
+    6 length bytes     byte slice
+'''
+
+import argparse
+import os
+import re
+import struct
+import sys
+
+SECTION = 'tock_log'
+
+MESSAGE = 0xff
+TIMESTAMPED_MESSAGE = 0xfe
+DROPPED = 0xfd
+
+TAG_UNSIGNED = 0
+TAG_SIGNED = 1
+TAG_FALSE = 2
+TAG_TRUE = 3
+TAG_CHAR = 4
+TAG_STR = 5
+TAG_BYTES = 6
+
+# A `{...}` placeholder of a Rust format string, or an escaped brace.
+PLACEHOLDER = re.compile(r'\{\{|\}\}|\{([^{}]*)\}')
+
+# The format spec of a placeholder, after the `:`.
+SPEC = re.compile(r'^(?:(.)?([<^>]))?([+-])?(#)?(0)?(\d+)?(?:\.(\d+))?([a-zA-Z]?\??)$')
+
+
+class EndOfInput(Exception):
+    pass
+
+
+class Char(str):
+    '''
+    A `char` argument, which `{:?}` quotes differently from a string.
+    '''
+    pass
+
+
+def read_section(elf_path, name):
+    '''
+    Return the contents of the section `name` of an ELF file.
+    '''
+    with open(elf_path, 'rb') as f:
+        elf = f.read()
+
+    if elf[:4] != b'\x7fELF':
+        sys.exit('{} is not an ELF file'.format(elf_path))
+    is_64 = elf[4] == 2
+    endian = '<' if elf[5] == 1 else '>'
+
+    if is_64:
+        shoff, = struct.unpack_from(endian + 'Q', elf, 0x28)
+        shentsize, shnum, shstrndx = struct.unpack_from(endian + 'HHH', elf, 0x3a)
+        section_format = endian + 'IIQQQQ'
+    else:
+        shoff, = struct.unpack_from(endian + 'I', elf, 0x20)
+        shentsize, shnum, shstrndx = struct.unpack_from(endian + 'HHH', elf, 0x2e)
+        section_format = endian + 'IIIIII'
+
+    # Each section header starts with the name, type, flags, address, offset
+    # and size of the section.
+    sections = [struct.unpack_from(section_format, elf, shoff + i * shentsize)
+                for i in range(shnum)]
+    names_offset = sections[shstrndx][4]
+    for (name_offset, _, _, _, offset, size) in sections:
+        start = names_offset + name_offset
+        if elf[start:elf.index(b'\0', start)].decode() == name:
+            return elf[offset:offset + size]
+
+    sys.exit('{} has no {} section: does the kernel use debug_binary!()?'.format(
+        elf_path, name))
+
+
+def read_bytes(f):
+    '''
+    Yield the bytes of `f` as soon as they arrive.
+    '''
+    fd = f.fileno()
+    while True:
+        data = os.read(fd, 4096)
+        if not data:
+            return
+        for byte in data:
+            yield byte
+
+
+def next_byte(stream):
+    try:
+        return next(stream)
+    except StopIteration:
+        raise EndOfInput()
+
+
+def read_varint(stream):
+    value = 0
+    shift = 0
+    while True:
+        byte = next_byte(stream)
+        value |= (byte & 0x7f) << shift
+        shift += 7
+        if byte & 0x80 == 0:
+            return value
+
+
+def read_argument(stream):
+    tag = next_byte(stream)
+    if tag == TAG_UNSIGNED:
+        return read_varint(stream)
+    elif tag == TAG_SIGNED:
+        value = read_varint(stream)
+        return (value >> 1) ^ -(value & 1)
+    elif tag == TAG_FALSE:
+        return False
+    elif tag == TAG_TRUE:
+        return True
+    elif tag == TAG_CHAR:
+        return Char(chr(read_varint(stream)))
+    elif tag in (TAG_STR, TAG_BYTES):
+        data = bytes(next_byte(stream) for _ in range(read_varint(stream)))
+        return data.decode(errors='replace') if tag == TAG_STR else data
+    else:
+        raise ValueError('unknown argument type {}'.format(tag))
+
+
+def debug_str(value, quote):
+    '''
+    Escape a string or a char like Rust's `{:?}`.
+    '''
+    escapes = {'\t': '\\t', '\r': '\\r', '\n': '\\n', '\\': '\\\\', quote: '\\' + quote}
+    escaped = ''.join(escapes.get(c, c) if c.isprintable() or c in escapes
+                      else '\\u{{{:x}}}'.format(ord(c)) for c in value)
+    return quote + escaped + quote
+
+
+def format_argument(value, spec):
+    '''
+    Format `value` like Rust formats it with the format spec `spec`.
+    '''
+    match = SPEC.match(spec)
+    if match is None:
+        return '{{:{}}}'.format(spec)
+    fill, align, sign, alternate, zero, width, precision, kind = match.groups()
+    debug = kind.endswith('?')
+    kind = kind.rstrip('?')
+
+    if isinstance(value, bool):
+        value = 'true' if value else 'false'
+    elif isinstance(value, bytes):
+        integer_kind = kind if kind in ('x', 'X') else 'd'
+        value = '[' + ', '.join(format(b, integer_kind) for b in value) + ']'
+    elif isinstance(value, int):
+        if kind not in ('x', 'X', 'o', 'b'):
+            kind = 'd'
+        # Python prints `0X` for `#X`, where Rust prints `0x`.
+        if alternate and kind == 'X':
+            value = '0x' + format(value, 'X')
+        else:
+            python_spec = (sign or '') + (alternate or '') + kind
+            value = format(value, python_spec)
+        precision = None
+        if zero and not align:
+            sign_length = 1 if value[0] in '+-' else 0
+            prefix_length = sign_length + (2 if alternate and kind != 'd' else 0)
+            digits = value[prefix_length:]
+            width = int(width or 0) - prefix_length
+            return value[:prefix_length] + digits.rjust(width, '0')
+        align = align or '>'
+    elif isinstance(value, str) and debug:
+        value = debug_str(value, "'" if isinstance(value, Char) else '"')
+
+    if precision is not None and isinstance(value, str):
+        value = value[:int(precision)]
+    if width:
+        python_spec = (fill or ' ') + (align or '<') + width
+        value = format(value, python_spec)
+    return value
+
+
+def format_message(string, arguments):
+    '''
+    Substitute `arguments` into the Rust format string `string`.
+    '''
+    next_argument = [0]
+
+    def substitute(match):
+        if match.group(0) == '{{':
+            return '{'
+        if match.group(0) == '}}':
+            return '}'
+        position, _, spec = match.group(1).partition(':')
+        if position:
+            index = int(position)
+        else:
+            index = next_argument[0]
+            next_argument[0] += 1
+        if index >= len(arguments):
+            return '<missing argument>'
+        return format_argument(arguments[index], spec)
+
+    return PLACEHOLDER.sub(substitute, string)
+
+
+def decode_record(marker, stream, strings):
+    '''
+    Decode the binary record that starts with `marker` and return its text.
+    '''
+    if marker == DROPPED:
+        return '*** {} BINARY LOG MESSAGES DROPPED ***'.format(read_varint(stream))
+
+    prefix = ''
+    if marker == TIMESTAMPED_MESSAGE:
+        milliseconds = read_varint(stream)
+        prefix = '[{}.{:03}] '.format(milliseconds // 1000, milliseconds % 1000)
+
+    index = read_varint(stream)
+    end = strings.find(b'\0', index)
+    if end < 0:
+        return prefix + '<no format string at offset {}: is this the right ELF file?>'.format(
+            index)
+    string = strings[index:end].decode(errors='replace')
+
+    # The format string tells how many arguments follow.
+    implicit = 0
+    count = 0
+    for match in PLACEHOLDER.finditer(string):
+        if match.group(1) is None:
+            continue
+        position = match.group(1).partition(':')[0]
+        if position:
+            count = max(count, int(position) + 1)
+        else:
+            implicit += 1
+    count = max(count, implicit)
+
+    arguments = [read_argument(stream) for _ in range(count)]
+    return prefix + format_message(string, arguments)
+
+
+def decode(stream, strings, output):
+    text = bytearray()
+    try:
+        for byte in stream:
+            if byte in (MESSAGE, TIMESTAMPED_MESSAGE, DROPPED):
+                output.write(bytes(text))
+                text.clear()
+                message = decode_record(byte, stream, strings)
+                output.write((message + '\n').encode())
+            else:
+                text.append(byte)
+                if byte != ord('\n'):
+                    continue
+                output.write(bytes(text))
+                text.clear()
+            output.flush()
+    except EndOfInput:
+        pass
+    output.write(bytes(text))
+    output.flush()
+
+
+def main():
+    parser = argparse.ArgumentParser(
+        description='Decode the debug_binary!() messages in the output of a Tock board.')
+    parser.add_argument('elf', help='the ELF file of the kernel running on the board')
+    parser.add_argument('input', nargs='?', type=argparse.FileType('rb'),
+                        default=sys.stdin.buffer,
+                        help='the serial port or file to read, standard input by default')
+    args = parser.parse_args()
+
+    strings = read_section(args.elf, SECTION)
+    try:
+        decode(read_bytes(args.input), strings, sys.stdout.buffer)
+    except KeyboardInterrupt:
+        pass
+
+
+if __name__ == '__main__':
+    main()